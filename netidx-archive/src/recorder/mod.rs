@@ -377,16 +377,20 @@ impl TryFrom<file::Config> for Config {
         let desired_auth = f.desired_auth.unwrap_or_else(|| netidx_config.default_auth());
         let publish =
             f.publish.map(|f| PublishConfig::from_file(&netidx_config, f)).transpose()?;
+        let record = f
+            .record
+            .map(|r| RecordConfig::from_file(r))
+            .transpose()?
+            .unwrap_or(HashMap::default());
+        if record.is_empty() && publish.is_none() {
+            bail!("at least one of record or publish must be specified")
+        }
         Ok(Self {
             archive_directory: f.archive_directory,
             archive_cmds: f.archive_cmds,
             netidx_config,
             desired_auth,
-            record: f
-                .record
-                .map(|r| RecordConfig::from_file(r))
-                .transpose()?
-                .unwrap_or(HashMap::default()),
+            record,
             publish,
         })
     }
@@ -677,3 +681,32 @@ pub fn put_file(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(record: Option<file::RecordConfig>) -> file::Config {
+        file::Config {
+            archive_directory: PathBuf::from("/tmp/netidx-archive-test"),
+            archive_cmds: None,
+            netidx_config: Some(PathBuf::from("../cfg/simple-client.json")),
+            desired_auth: Some(DesiredAuth::Anonymous),
+            record,
+            publish: None,
+        }
+    }
+
+    #[test]
+    fn config_must_record_or_publish() {
+        let e = Config::try_from(config(None)).unwrap_err();
+        assert_eq!(e.to_string(), "at least one of record or publish must be specified");
+        let mut empty = file::RecordConfig::example();
+        empty.shards.clear();
+        let e = Config::try_from(config(Some(empty))).unwrap_err();
+        assert_eq!(e.to_string(), "at least one of record or publish must be specified");
+        let cfg = Config::try_from(config(Some(file::RecordConfig::example()))).unwrap();
+        assert_eq!(cfg.record.len(), 1);
+        assert!(cfg.publish.is_none());
+    }
+}
@@ -461,7 +461,8 @@ impl SessionShard {
                 for BatchItem(id, ev) in batch.1.drain(..) {
                     let v = match ev {
                         Event::Unsubscribed => Value::Null,
-                        Event::Update(v) => v,
+                        Event::Update(v) | Event::Unpublished(v) => v,
+                        Event::Stale => continue,
                     };
                     match self.published.get(&id) {
                         Some(val) => {
@@ -634,8 +635,8 @@ impl SessionShard {
             let index = self.pathindex.index();
             for (id, path) in index.iter_pathmap() {
                 let v = match idx.remove(id) {
-                    None | Some(Event::Unsubscribed) => Value::Null,
-                    Some(Event::Update(v)) => v,
+                    None | Some(Event::Unsubscribed | Event::Stale) => Value::Null,
+                    Some(Event::Update(v)) | Some(Event::Unpublished(v)) => v,
                 };
                 match self.published.get(&id) {
                     Some(val) => val.update(pbatch, v),
@@ -758,7 +759,9 @@ impl SessionShard {
                     publisher::Event::Unsubscribe(id, _) => if self.published_ids.contains(&id) {
                         used -= 1;
                     },
-                    publisher::Event::Destroyed(_) => (),
+                    publisher::Event::Destroyed(_)
+                    | publisher::Event::SlowSubscriber(_)
+                    | publisher::Event::SubscriberDropped(_) => (),
                 },
                 _ = idle_check.tick().fuse() => {
                     let has_clients = used > 0;
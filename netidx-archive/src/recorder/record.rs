@@ -17,7 +17,10 @@ use log::{error, info, warn};
 use netidx::{
     path::Path,
     pool::Pooled,
-    protocol::glob::{Glob, GlobSet},
+    protocol::{
+        glob::{Glob, GlobSet},
+        value::Value,
+    },
     resolver_client::{ChangeTracker, ResolverRead},
     subscriber::{Dval, Event, SubId, Subscriber, UpdatesFlags},
     utils::{self, Batched},
@@ -178,6 +181,18 @@ fn write_pathmap(
     Ok(())
 }
 
+/// Translate an event into one the archive format can hold, or None
+/// if it shouldn't be recorded. The archive predates unpublished and
+/// stale events, so an unpublished value is recorded as null, and
+/// stale isn't recorded at all.
+fn archived(ev: Event) -> Option<Event> {
+    match ev {
+        Event::Update(_) | Event::Unsubscribed => Some(ev),
+        Event::Unpublished(_) => Some(Event::Update(Value::Null)),
+        Event::Stale => None,
+    }
+}
+
 fn write_image(
     archive: &mut ArchiveWriter,
     by_subid: &FxHashMap<SubId, Id>,
@@ -345,6 +360,10 @@ pub(super) async fn run(
                     task::block_in_place(|| -> Result<()> {
                         for mut batch in queued.drain(..) {
                             for (subid, ev) in batch.drain(..) {
+                                let ev = match archived(ev) {
+                                    Some(ev) => ev,
+                                    None => continue,
+                                };
                                 if record_config.image_frequency.is_some() {
                                     image.insert(subid, ev.clone());
                                 }
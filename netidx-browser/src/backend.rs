@@ -1,5 +1,5 @@
-use super::{default_view, FromGui, RawBatch, ToGui, ViewLoc, WidgetPath};
-use crate::util::OneShot;
+use super::{default_view, FromGui, RawBatch, ToGui, ViewLoc, WVal, WidgetPath};
+use crate::util::{csv_line, OneShot};
 use anyhow::{anyhow, Error, Result};
 use futures::{
     channel::{mpsc, oneshot},
@@ -17,11 +17,13 @@ use netidx::{
     protocol::resolver,
     resolver_client::{ChangeTracker, DesiredAuth, ResolverRead},
     subscriber::{Dval, Event, SubId, Subscriber, UpdatesFlags, Value},
+    utils::Either,
 };
 use netidx_bscript::vm::{RpcCallId, TimerId};
 use netidx_protocols::{rpc::client as rpc, view};
+use parking_lot::RwLock;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     fs, mem,
     path::PathBuf,
     result,
@@ -47,15 +49,51 @@ macro_rules! break_err {
     };
 }
 
+/// A cell of a table being exported
+#[derive(Debug)]
+pub(crate) enum ExportCell {
+    /// Written as is
+    Literal(Chars),
+    /// Subscribed, and it's current value is written
+    Path(Path),
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct Ctx {
     pub(crate) subscriber: Subscriber,
     pub(crate) to_gui: glib::Sender<ToGui>,
     pub(crate) from_gui: mpsc::UnboundedSender<FromGui>,
     pub(crate) updates: mpsc::Sender<RawBatch>,
+    playback: Arc<RwLock<Option<Path>>>,
 }
 
 impl Ctx {
+    /// Subscribe to a data path. When playing back an archive the
+    /// path is redirected to the data published by the playback
+    /// session, otherwise it is subscribed live.
+    pub(crate) fn subscribe(&self, path: Path) -> Dval {
+        match &*self.playback.read() {
+            None => self.subscriber.subscribe(path),
+            Some(session) => {
+                self.subscriber.subscribe(session.append("data").append(&path))
+            }
+        }
+    }
+
+    /// The base path of the current playback session, if any
+    pub(crate) fn playback_session(&self) -> Option<Path> {
+        self.playback.read().clone()
+    }
+
+    pub(crate) fn start_playback(&self, archive: Path) {
+        let _: result::Result<_, _> =
+            self.from_gui.unbounded_send(FromGui::StartPlayback(archive));
+    }
+
+    pub(crate) fn stop_playback(&self) {
+        let _: result::Result<_, _> = self.from_gui.unbounded_send(FromGui::StopPlayback);
+    }
+
     pub(crate) fn navigate(&self, loc: ViewLoc) {
         let _: result::Result<_, _> =
             self.from_gui.unbounded_send(FromGui::Navigate(loc));
@@ -72,6 +110,13 @@ impl Ctx {
         let _: result::Result<_, _> = self.from_gui.unbounded_send(FromGui::Terminate);
     }
 
+    /// Limit updates to the gui to at most `rate` per second, or no
+    /// limit if `None`
+    pub(crate) fn set_refresh_rate(&self, rate: Option<u32>) {
+        let _: result::Result<_, _> =
+            self.from_gui.unbounded_send(FromGui::SetRefreshRate(rate));
+    }
+
     pub(crate) fn updated(&self) {
         let _: result::Result<_, _> = self.from_gui.unbounded_send(FromGui::Updated);
     }
@@ -89,11 +134,25 @@ impl Ctx {
         let _: result::Result<_, _> = self.from_gui.unbounded_send(FromGui::Poll(path));
     }
 
+    /// Write the rows to `dest` as csv
+    pub(crate) fn export_table(&self, dest: PathBuf, rows: Vec<Vec<ExportCell>>) {
+        let _: result::Result<_, _> =
+            self.from_gui.unbounded_send(FromGui::ExportTable(dest, rows));
+    }
+
     pub(crate) fn resolve_table(&self, path: Path) {
         let _: result::Result<_, _> =
             self.from_gui.unbounded_send(FromGui::ResolveTable(path));
     }
 
+    /// List the children of `path` in the resolver
+    pub(crate) async fn list(&self, path: Path) -> Result<Vec<Path>> {
+        let (tx, rx) = oneshot::channel();
+        let _: result::Result<_, _> =
+            self.from_gui.unbounded_send(FromGui::List(path, tx));
+        Ok(rx.await??)
+    }
+
     pub(crate) fn call_rpc(&self, name: Path, args: Vec<(Chars, Value)>, id: RpcCallId) {
         let _: result::Result<_, _> =
             self.from_gui.unbounded_send(FromGui::CallRpc(name, args, id));
@@ -119,7 +178,12 @@ struct CtxInner {
         HashMap<Path, (Instant, mpsc::UnboundedSender<(Vec<(Chars, Value)>, RpcCallId)>)>,
     polls: HashMap<Path, (Instant, mpsc::UnboundedSender<()>)>,
     changed: Pooled<Vec<(SubId, Value)>>,
+    changed_idx: HashMap<SubId, usize>,
     refreshing: bool,
+    refresh_interval: Option<Duration>,
+    last_refresh: Instant,
+    playback: Arc<RwLock<Option<Path>>>,
+    from_gui_tx: mpsc::UnboundedSender<FromGui>,
 }
 
 impl CtxInner {
@@ -130,6 +194,7 @@ impl CtxInner {
     ) -> Ctx {
         let (tx_updates, rx_updates) = mpsc::channel(2);
         let (tx_from_gui, rx_from_gui) = mpsc::unbounded();
+        let playback = Arc::new(RwLock::new(None));
         let inner = CtxInner {
             subscriber: subscriber.clone(),
             resolver: subscriber.resolver(),
@@ -143,10 +208,15 @@ impl CtxInner {
             rpcs: HashMap::new(),
             polls: HashMap::new(),
             changed: UPDATES.take(),
+            changed_idx: HashMap::new(),
             refreshing: false,
+            refresh_interval: None,
+            last_refresh: Instant::now(),
+            playback: playback.clone(),
+            from_gui_tx: tx_from_gui.clone(),
         };
         task::spawn(inner.run());
-        Ctx { subscriber, to_gui, from_gui: tx_from_gui, updates: tx_updates }
+        Ctx { subscriber, to_gui, from_gui: tx_from_gui, updates: tx_updates, playback }
     }
 
     async fn navigate_path(&mut self, base_path: Path) -> Result<()> {
@@ -213,6 +283,67 @@ impl CtxInner {
         });
     }
 
+    fn list(&self, path: Path, fin: oneshot::Sender<Result<Vec<Path>>>) {
+        let resolver = self.resolver.clone();
+        task::spawn(async move {
+            let res = resolver.list(path).await.map(|mut l| l.drain(..).collect());
+            let _: result::Result<_, _> = fin.send(res);
+        });
+    }
+
+    fn export_table(&self, dest: PathBuf, rows: Vec<Vec<ExportCell>>) {
+        static TIMEOUT: Duration = Duration::from_secs(30);
+        let subscriber = self.subscriber.clone();
+        let playback = self.playback.read().clone();
+        let to_gui = self.to_gui.clone();
+        task::spawn(async move {
+            let rows = rows
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|cell| match cell {
+                            ExportCell::Literal(s) => Either::Left(s),
+                            ExportCell::Path(path) => Either::Right(match &playback {
+                                None => subscriber.subscribe(path),
+                                Some(session) => subscriber
+                                    .subscribe(session.append("data").append(&path)),
+                            }),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let wait = async {
+                for cell in rows.iter().flat_map(|r| r.iter()) {
+                    if let Either::Right(dv) = cell {
+                        let _: Result<()> = dv.wait_subscribed().await;
+                    }
+                }
+            };
+            if time::timeout(TIMEOUT, wait).await.is_err() {
+                warn!("timed out subscribing to table cells for export to {:?}", dest);
+            }
+            let mut buf = String::new();
+            for row in rows.iter() {
+                csv_line(
+                    &mut buf,
+                    row.iter().map(|cell| match cell {
+                        Either::Left(s) => s.to_string(),
+                        Either::Right(dv) => match dv.last() {
+                            Event::Update(v) | Event::Unpublished(v) => {
+                                format!("{}", WVal(&v))
+                            }
+                            Event::Unsubscribed | Event::Stale => String::new(),
+                        },
+                    }),
+                )
+            }
+            if let Err(e) = task::block_in_place(|| fs::write(&dest, buf)) {
+                let m = format!("failed to export table to {:?}, {}", dest, e);
+                let _: result::Result<_, _> = to_gui.send(ToGui::ShowError(m));
+            }
+        });
+    }
+
     fn save_view_netidx(
         &self,
         path: Path,
@@ -315,18 +446,46 @@ impl CtxInner {
         Ok(())
     }
 
+    /// Queue a change for the gui. When the refresh rate is limited
+    /// only the latest value of each subscription is kept.
+    fn push_changed(&mut self, id: SubId, v: Value) {
+        if self.refresh_interval.is_none() {
+            self.changed.push((id, v))
+        } else {
+            match self.changed_idx.entry(id) {
+                Entry::Occupied(e) => self.changed[*e.get()].1 = v,
+                Entry::Vacant(e) => {
+                    e.insert(self.changed.len());
+                    self.changed.push((id, v))
+                }
+            }
+        }
+    }
+
     fn process_updates(&mut self, mut batch: RawBatch) -> Result<()> {
         for (id, ev) in batch.drain(..) {
             match ev {
-                Event::Update(v) => self.changed.push((id, v)),
+                Event::Update(v) | Event::Unpublished(v) => self.push_changed(id, v),
                 Event::Unsubscribed => {
-                    self.changed.push((id, Value::Error(Chars::from("#LOST"))))
+                    self.push_changed(id, Value::Error(Chars::from("#LOST")))
                 }
+                Event::Stale => (),
             }
         }
         self.refresh()
     }
 
+    fn set_refresh_rate(&mut self, rate: Option<u32>) -> Result<()> {
+        self.refresh_interval =
+            rate.filter(|r| *r > 0).map(|r| Duration::from_secs(1) / r);
+        self.changed_idx.clear();
+        if self.refresh_interval.is_some() {
+            let idx = self.changed.iter().enumerate().map(|(i, (id, _))| (*id, i));
+            self.changed_idx.extend(idx);
+        }
+        self.refresh()
+    }
+
     fn get_rpc_proc(
         &mut self,
         name: &Path,
@@ -396,6 +555,36 @@ impl CtxInner {
         Ok(())
     }
 
+    fn start_playback(&self, archive: Path) {
+        async fn new_session(subscriber: &Subscriber, archive: &Path) -> Result<Path> {
+            let proc = rpc::Proc::new(subscriber, archive.append("session"))?;
+            match proc.call(Vec::<(Chars, Value)>::new()).await? {
+                Value::String(id) => Ok(archive.append(&*id)),
+                Value::Error(e) => Err(anyhow!(String::from(&*e))),
+                v => Err(anyhow!("unexpected session id {}", v)),
+            }
+        }
+        let subscriber = self.subscriber.clone();
+        let from_gui = self.from_gui_tx.clone();
+        let to_gui = self.to_gui.clone();
+        task::spawn(async move {
+            match new_session(&subscriber, &archive).await {
+                Ok(session) => {
+                    let _ = from_gui.unbounded_send(FromGui::PlaybackStarted(session));
+                }
+                Err(e) => {
+                    let m = format!("failed to start playback of {}, {}", archive, e);
+                    let _ = to_gui.send(ToGui::ShowError(m));
+                }
+            }
+        });
+    }
+
+    fn set_playback(&mut self, session: Option<Path>) -> Result<()> {
+        *self.playback.write() = session.clone();
+        Ok(self.to_gui.send(ToGui::Playback(session))?)
+    }
+
     fn poll(&mut self, path: Path) {
         async fn poll_task(
             to_gui: glib::Sender<ToGui>,
@@ -450,8 +639,27 @@ impl CtxInner {
         self.polls.retain(|_, (last, _)| now - *last < MAX_AGE);
     }
 
+    /// When the next refresh may be sent, if one is being held back
+    /// by the refresh rate
+    fn refresh_deadline(&self) -> Option<Instant> {
+        match self.refresh_interval {
+            Some(i) if !self.refreshing && !self.changed.is_empty() => {
+                Some(self.last_refresh + i)
+            }
+            None | Some(_) => None,
+        }
+    }
+
     fn refresh(&mut self) -> Result<()> {
         if !self.refreshing && !self.changed.is_empty() {
+            if let Some(deadline) = self.refresh_deadline() {
+                let now = Instant::now();
+                if now < deadline {
+                    return Ok(());
+                }
+                self.last_refresh = now;
+                self.changed_idx.clear();
+            }
             self.refreshing = true;
             self.to_gui
                 .send(ToGui::Update(mem::replace(&mut self.changed, UPDATES.take())))?
@@ -468,6 +676,12 @@ impl CtxInner {
                 Some(rx_view) => rx_view.next().await,
             }
         }
+        async fn wait_refresh(deadline: Option<Instant>) {
+            match deadline {
+                None => pending().await,
+                Some(deadline) => time::sleep_until(deadline.into()).await,
+            }
+        }
         async fn read_updates(
             updates: &mut mpsc::Receiver<RawBatch>,
             nchanged: usize,
@@ -481,6 +695,7 @@ impl CtxInner {
         }
         let mut gc = time::interval(Duration::from_secs(60));
         loop {
+            let deadline = self.refresh_deadline();
             select_biased! {
                 m = self.from_gui.next() => match m {
                     None => break,
@@ -494,6 +709,7 @@ impl CtxInner {
                     },
                     Some(FromGui::ResolveTable(path)) =>
                         self.resolve_table(path),
+                    Some(FromGui::List(path, fin)) => self.list(path, fin),
                     Some(FromGui::Save(ViewLoc::Netidx(path), view, fin)) =>
                         self.save_view_netidx(path, view, fin),
                     Some(FromGui::Save(ViewLoc::File(file), view, fin)) => {
@@ -506,7 +722,18 @@ impl CtxInner {
                     Some(FromGui::CallRpc(path, args, id)) =>
                         break_err!(self.call_rpc(path, args, id)),
                     Some(FromGui::Poll(path)) => self.poll(path),
+                    Some(FromGui::ExportTable(dest, rows)) =>
+                        self.export_table(dest, rows),
                     Some(FromGui::SetTimer(id, timeout)) => self.set_timer(id, timeout),
+                    Some(FromGui::StartPlayback(archive)) => self.start_playback(archive),
+                    Some(FromGui::PlaybackStarted(session)) =>
+                        break_err!(self.set_playback(Some(session))),
+                    Some(FromGui::StopPlayback) => break_err!(self.set_playback(None)),
+                    Some(FromGui::SetRefreshRate(rate)) =>
+                        break_err!(self.set_refresh_rate(rate)),
+                },
+                _ = wait_refresh(deadline).fuse() => {
+                    break_err!(self.refresh())
                 },
                 b = read_updates(
                     &mut self.updates,
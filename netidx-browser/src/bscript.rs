@@ -1,15 +1,22 @@
 use super::{util::ask_modal, ToGui, ViewLoc, WidgetCtx};
 use glib::thread_guard::ThreadGuard;
+use gtk::{self, prelude::*};
 use netidx::{chars::Chars, path::Path, resolver_client, subscriber::Value};
 use netidx_bscript::vm::{self, Apply, Ctx, ExecCtx, InitFn, Node, Register};
 use parking_lot::Mutex;
-use std::{cell::RefCell, mem, rc::Rc, result::Result, sync::Arc};
+use std::{cell::RefCell, mem, path::PathBuf, rc::Rc, result::Result, sync::Arc};
 
 #[derive(Clone, Debug)]
 pub(crate) enum LocalEvent {
     Event(Value),
     TableResolved(Path, Rc<resolver_client::Table>),
     Poll(Path),
+    TableExport(Path, PathBuf),
+    /// Widgets with a refresh rate should deliver the updates they
+    /// are holding back if it is time
+    Flush,
+    /// The runtime state of a named widget changed, see `widget`
+    WidgetState(String, Value),
 }
 
 pub(crate) struct Event {
@@ -49,7 +56,10 @@ impl Apply<WidgetCtx, LocalEvent> for Event {
             | vm::Event::Rpc(_, _)
             | vm::Event::Timer(_)
             | vm::Event::User(LocalEvent::TableResolved(_, _))
-            | vm::Event::User(LocalEvent::Poll(_)) => None,
+            | vm::Event::User(LocalEvent::TableExport(_, _))
+            | vm::Event::User(LocalEvent::Poll(_))
+            | vm::Event::User(LocalEvent::WidgetState(_, _))
+            | vm::Event::User(LocalEvent::Flush) => None,
             vm::Event::User(LocalEvent::Event(value)) => {
                 self.cur = Some(value.clone());
                 self.current(ctx)
@@ -313,6 +323,192 @@ impl Navigate {
     }
 }
 
+pub(crate) struct Dialog {
+    invalid: bool,
+}
+
+impl Register<WidgetCtx, LocalEvent> for Dialog {
+    fn register(ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) {
+        let f: InitFn<WidgetCtx, LocalEvent> =
+            Arc::new(|_, from, _, _| Box::new(Dialog { invalid: from.is_empty() }));
+        ctx.functions.insert("dialog".into(), f);
+        ctx.user.register_fn("dialog".into(), Path::root());
+    }
+}
+
+impl Apply<WidgetCtx, LocalEvent> for Dialog {
+    fn current(&self, _ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) -> Option<Value> {
+        if self.invalid {
+            Dialog::usage()
+        } else {
+            None
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<WidgetCtx, LocalEvent>,
+        from: &mut [Node<WidgetCtx, LocalEvent>],
+        event: &vm::Event<LocalEvent>,
+    ) -> Option<Value> {
+        match from {
+            [] => None,
+            [title, fields @ ..] => {
+                for field in fields.iter_mut() {
+                    field.update(ctx, event);
+                }
+                title.update(ctx, event).and_then(|title| {
+                    let fields = fields.iter().filter_map(|f| f.current(ctx)).collect();
+                    Dialog::run(ctx, title, fields)
+                })
+            }
+        }
+    }
+}
+
+impl Dialog {
+    fn usage() -> Option<Value> {
+        Some(Value::Error(Chars::from(
+            "dialog(title, field, ..): expected at least 1 argument, where each field is a name or [name, default]",
+        )))
+    }
+
+    /// Ask the user to fill in the fields, return an array of
+    /// [name, value] pairs if they accept, or nothing if they cancel.
+    fn run(
+        ctx: &ExecCtx<WidgetCtx, LocalEvent>,
+        title: Value,
+        fields: Vec<Value>,
+    ) -> Option<Value> {
+        let title = match title.cast_to::<Chars>() {
+            Ok(title) => title,
+            Err(_) => return Dialog::usage(),
+        };
+        let d = gtk::Dialog::with_buttons(
+            Some(&*title),
+            Some(&ctx.user.window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR,
+            &[("Cancel", gtk::ResponseType::Cancel), ("Ok", gtk::ResponseType::Accept)],
+        );
+        d.set_default_response(gtk::ResponseType::Accept);
+        let grid = gtk::Grid::new();
+        grid.set_row_spacing(5);
+        grid.set_column_spacing(5);
+        let mut entries = vec![];
+        for (i, field) in fields.into_iter().enumerate() {
+            let (name, default) = match field {
+                Value::Array(a) if a.len() == 2 => (a[0].clone(), Some(a[1].clone())),
+                v => (v, None),
+            };
+            let name = match name.cast_to::<Chars>() {
+                Ok(name) => name,
+                Err(_) => return Dialog::usage(),
+            };
+            let label = gtk::Label::new(Some(&*name));
+            label.set_halign(gtk::Align::Start);
+            let entry = gtk::Entry::new();
+            entry.set_activates_default(true);
+            if let Some(v) = default {
+                entry.set_text(&format!("{}", v));
+            }
+            grid.attach(&label, 0, i as i32, 1, 1);
+            grid.attach(&entry, 1, i as i32, 1, 1);
+            entries.push((name, entry));
+        }
+        d.content_area().add(&grid);
+        d.content_area().show_all();
+        let res = match d.run() {
+            gtk::ResponseType::Accept => Some(Value::from(
+                entries
+                    .iter()
+                    .map(|(name, entry)| {
+                        let txt = entry.text();
+                        let v = txt
+                            .parse::<Value>()
+                            .unwrap_or_else(|_| Value::from(String::from(&*txt)));
+                        Value::from(vec![Value::from(name.clone()), v])
+                    })
+                    .collect::<Vec<_>>(),
+            )),
+            gtk::ResponseType::Cancel | _ => None,
+        };
+        unsafe {
+            d.destroy();
+        }
+        res
+    }
+}
+
+pub(crate) struct SetTitle {
+    invalid: bool,
+}
+
+impl Register<WidgetCtx, LocalEvent> for SetTitle {
+    fn register(ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) {
+        let f: InitFn<WidgetCtx, LocalEvent> = Arc::new(|ctx, from, _, _| match from {
+            [title] => {
+                let t = SetTitle { invalid: false };
+                let title = title.current(ctx);
+                t.set(ctx, title);
+                Box::new(t)
+            }
+            _ => Box::new(SetTitle { invalid: true }),
+        });
+        ctx.functions.insert("set_title".into(), f);
+        ctx.user.register_fn("set_title".into(), Path::root());
+    }
+}
+
+impl Apply<WidgetCtx, LocalEvent> for SetTitle {
+    fn current(&self, _ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) -> Option<Value> {
+        if self.invalid {
+            Some(Value::Error(Chars::from("set_title(title): expected 1 argument")))
+        } else {
+            None
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<WidgetCtx, LocalEvent>,
+        from: &mut [Node<WidgetCtx, LocalEvent>],
+        event: &vm::Event<LocalEvent>,
+    ) -> Option<Value> {
+        match from {
+            [title] => {
+                let title = title.update(ctx, event);
+                self.set(ctx, title);
+                None
+            }
+            exprs => {
+                let mut up = false;
+                for expr in exprs {
+                    up |= expr.update(ctx, event).is_some()
+                }
+                if up {
+                    self.current(ctx)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl SetTitle {
+    fn set(&self, ctx: &ExecCtx<WidgetCtx, LocalEvent>, title: Option<Value>) {
+        if let Some(title) = title {
+            let title = match title.cast_to::<Chars>() {
+                Ok(s) => String::from(&*s),
+                Err(_) => format!("{}", title),
+            };
+            // sent via the gui loop so it takes effect after the view
+            // is built, which would otherwise reset the title
+            let _: Result<_, _> = ctx.user.backend.to_gui.send(ToGui::SetTitle(title));
+        }
+    }
+}
+
 pub(crate) struct Poll {
     path: Option<Path>,
     invalid: bool,
@@ -369,6 +565,9 @@ impl Apply<WidgetCtx, LocalEvent> for Poll {
                     vm::Event::User(LocalEvent::Poll(_))
                     | vm::Event::User(LocalEvent::Event(_))
                     | vm::Event::User(LocalEvent::TableResolved(_, _))
+                    | vm::Event::User(LocalEvent::TableExport(_, _))
+                    | vm::Event::User(LocalEvent::WidgetState(_, _))
+                    | vm::Event::User(LocalEvent::Flush)
                     | vm::Event::Variable(_, _, _)
                     | vm::Event::Netidx(_, _)
                     | vm::Event::Rpc(_, _)
@@ -399,12 +598,226 @@ impl Poll {
     }
 }
 
+pub(crate) struct TableExport {
+    path: Option<Path>,
+    invalid: bool,
+}
+
+impl Register<WidgetCtx, LocalEvent> for TableExport {
+    fn register(ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) {
+        let f: InitFn<WidgetCtx, LocalEvent> = Arc::new(|ctx, from, _, _| match from {
+            [path, dest] => {
+                let mut t = TableExport {
+                    path: path.current(ctx).and_then(|p| p.cast_to::<Path>().ok()),
+                    invalid: false,
+                };
+                let dest = dest.current(ctx);
+                t.export(ctx, dest);
+                Box::new(t)
+            }
+            _ => Box::new(TableExport { path: None, invalid: true }),
+        });
+        ctx.functions.insert("table_export".into(), f);
+        ctx.user.register_fn("table_export".into(), Path::root());
+    }
+}
+
+impl Apply<WidgetCtx, LocalEvent> for TableExport {
+    fn current(&self, _ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) -> Option<Value> {
+        if self.invalid {
+            Some(Value::from(
+                "table_export(path, dest): expected 2 arguments, where path is the path of a table in the view, and dest is a file",
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<WidgetCtx, LocalEvent>,
+        from: &mut [Node<WidgetCtx, LocalEvent>],
+        event: &vm::Event<LocalEvent>,
+    ) -> Option<Value> {
+        match from {
+            [path, dest] => {
+                if let Some(path) = path.update(ctx, event) {
+                    self.path = path.cast_to::<Path>().ok();
+                }
+                let dest = dest.update(ctx, event);
+                self.export(ctx, dest);
+                None
+            }
+            exprs => {
+                let mut up = false;
+                self.invalid = true;
+                for expr in exprs {
+                    up |= expr.update(ctx, event).is_some()
+                }
+                if up {
+                    self.current(ctx)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl TableExport {
+    fn export(&self, ctx: &ExecCtx<WidgetCtx, LocalEvent>, dest: Option<Value>) {
+        let dest = dest.and_then(|d| d.cast_to::<Chars>().ok());
+        if let (Some(path), Some(dest)) = (&self.path, dest) {
+            let m = ToGui::TableExport(path.clone(), PathBuf::from(&*dest));
+            let _: Result<_, _> = ctx.user.backend.to_gui.send(m);
+        }
+    }
+}
+
+pub(crate) struct Tr {
+    cur: Option<Value>,
+    invalid: bool,
+}
+
+impl Register<WidgetCtx, LocalEvent> for Tr {
+    fn register(ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) {
+        let f: InitFn<WidgetCtx, LocalEvent> = Arc::new(|ctx, from, _, _| match from {
+            [key] => {
+                let cur = key.current(ctx).map(|k| Tr::translate(ctx, k));
+                Box::new(Tr { cur, invalid: false })
+            }
+            _ => Box::new(Tr { cur: None, invalid: true }),
+        });
+        ctx.functions.insert("tr".into(), f);
+        ctx.user.register_fn("tr".into(), Path::root());
+    }
+}
+
+impl Apply<WidgetCtx, LocalEvent> for Tr {
+    fn current(&self, _ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) -> Option<Value> {
+        if self.invalid {
+            Some(Value::from("tr(key): expected 1 argument"))
+        } else {
+            self.cur.clone()
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<WidgetCtx, LocalEvent>,
+        from: &mut [Node<WidgetCtx, LocalEvent>],
+        event: &vm::Event<LocalEvent>,
+    ) -> Option<Value> {
+        match from {
+            [key] => key.update(ctx, event).map(|k| {
+                let v = Tr::translate(ctx, k);
+                self.cur = Some(v.clone());
+                v
+            }),
+            exprs => {
+                let mut up = false;
+                for expr in exprs {
+                    up |= expr.update(ctx, event).is_some()
+                }
+                if up {
+                    self.current(ctx)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl Tr {
+    fn translate(ctx: &ExecCtx<WidgetCtx, LocalEvent>, key: Value) -> Value {
+        match key.cast_to::<Chars>() {
+            Err(_) => Value::from("tr(key): expected a string"),
+            Ok(k) => match ctx.user.translations.get(&*k) {
+                Some(t) => Value::from(Chars::from(t.clone())),
+                None => Value::from(k),
+            },
+        }
+    }
+}
+
+pub(crate) struct Widget {
+    key: Option<Chars>,
+    invalid: bool,
+}
+
+impl Register<WidgetCtx, LocalEvent> for Widget {
+    fn register(ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) {
+        let f: InitFn<WidgetCtx, LocalEvent> = Arc::new(|ctx, from, _, _| match from {
+            [key] => Box::new(Widget {
+                key: key.current(ctx).and_then(|k| k.cast_to::<Chars>().ok()),
+                invalid: false,
+            }),
+            _ => Box::new(Widget { key: None, invalid: true }),
+        });
+        ctx.functions.insert("widget".into(), f);
+        ctx.user.register_fn("widget".into(), Path::root());
+    }
+}
+
+impl Apply<WidgetCtx, LocalEvent> for Widget {
+    fn current(&self, ctx: &mut ExecCtx<WidgetCtx, LocalEvent>) -> Option<Value> {
+        if self.invalid {
+            Some(Value::from(
+                "widget(key): expected 1 argument, where key is name/field, e.g. orders/selection",
+            ))
+        } else {
+            self.key.as_ref().and_then(|k| ctx.user.widget_state.get(&**k).cloned())
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<WidgetCtx, LocalEvent>,
+        from: &mut [Node<WidgetCtx, LocalEvent>],
+        event: &vm::Event<LocalEvent>,
+    ) -> Option<Value> {
+        match from {
+            [key] => match key.update(ctx, event) {
+                Some(k) => {
+                    self.key = k.cast_to::<Chars>().ok();
+                    self.current(ctx)
+                }
+                None => match event {
+                    vm::Event::User(LocalEvent::WidgetState(k, v))
+                        if self.key.as_ref().map(|s| &**s == k).unwrap_or(false) =>
+                    {
+                        Some(v.clone())
+                    }
+                    _ => None,
+                },
+            },
+            exprs => {
+                let mut up = false;
+                for expr in exprs {
+                    up |= expr.update(ctx, event).is_some()
+                }
+                if up {
+                    self.current(ctx)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
 pub(crate) fn create_ctx(ctx: WidgetCtx) -> ExecCtx<WidgetCtx, LocalEvent> {
     let mut t = ExecCtx::new(ctx);
     Event::register(&mut t);
     CurrentPath::register(&mut t);
     Confirm::register(&mut t);
     Navigate::register(&mut t);
+    Dialog::register(&mut t);
+    SetTitle::register(&mut t);
     Poll::register(&mut t);
+    TableExport::register(&mut t);
+    Tr::register(&mut t);
+    Widget::register(&mut t);
     t
 }
@@ -0,0 +1,209 @@
+use crate::{bscript::LocalEvent, BSCtx, BSCtxRef, BSNode};
+use gdk::keys::constants as k;
+use glib::clone;
+use gtk::{self, prelude::*, Adjustment};
+use netidx::{path::Path, subscriber::Value};
+use netidx_bscript::{expr::Expr, vm};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+    rc::Rc,
+};
+
+const MAX_HISTORY: usize = 1000;
+const WIDTH: usize = 80;
+
+/// Format `v` for the console, arrays that don't fit on one line
+/// are broken up with one element per line.
+fn pretty(buf: &mut String, indent: usize, v: &Value) {
+    let flat = format!("{}", v);
+    match v {
+        Value::Array(elts) if indent + flat.len() > WIDTH && elts.len() > 0 => {
+            buf.push_str("[\n");
+            for (i, v) in elts.iter().enumerate() {
+                for _ in 0..indent + 2 {
+                    buf.push(' ');
+                }
+                pretty(buf, indent + 2, v);
+                if i < elts.len() - 1 {
+                    buf.push(',');
+                }
+                buf.push('\n');
+            }
+            for _ in 0..indent {
+                buf.push(' ');
+            }
+            buf.push(']');
+        }
+        _ => buf.push_str(&flat),
+    }
+}
+
+/// Previously entered expressions, most recent last
+struct History {
+    entries: Vec<String>,
+    pos: Cell<usize>,
+}
+
+impl History {
+    fn push(&mut self, s: &str) {
+        if self.entries.last().map(|l| l.as_str()) != Some(s) {
+            self.entries.push(String::from(s));
+            if self.entries.len() > MAX_HISTORY {
+                self.entries.remove(0);
+            }
+        }
+        self.pos.set(self.entries.len());
+    }
+
+    fn prev(&self) -> Option<&str> {
+        let pos = self.pos.get();
+        if pos == 0 {
+            None
+        } else {
+            self.pos.set(pos - 1);
+            Some(&self.entries[pos - 1])
+        }
+    }
+
+    fn next(&self) -> Option<&str> {
+        let pos = self.pos.get();
+        if pos >= self.entries.len() {
+            None
+        } else {
+            self.pos.set(pos + 1);
+            Some(self.entries.get(pos + 1).map(|s| s.as_str()).unwrap_or(""))
+        }
+    }
+}
+
+/// The expression entered most recently, its results are printed
+/// as they arrive until another expression is entered.
+struct Live {
+    n: usize,
+    node: BSNode,
+}
+
+/// A window for evaluating bscript expressions in the scope of the
+/// root of the current view, so they can see the view's variables
+/// and share its subscriptions.
+pub(crate) struct Console {
+    window: gtk::Window,
+    view: gtk::TextView,
+    live: Rc<RefCell<Option<Live>>>,
+}
+
+fn print(view: &gtk::TextView, s: &str) {
+    if let Some(buf) = view.buffer() {
+        let mut end = buf.end_iter();
+        buf.insert(&mut end, s);
+        buf.insert(&mut end, "\n");
+        let mark = buf.create_mark(None, &buf.end_iter(), false);
+        view.scroll_mark_onscreen(&mark);
+        buf.delete_mark(&mark);
+    }
+}
+
+fn print_result(view: &gtk::TextView, n: usize, v: &Value) {
+    let mut buf = String::new();
+    write!(buf, "[{}] ", n).unwrap();
+    let indent = buf.len();
+    pretty(&mut buf, indent, v);
+    print(view, &buf)
+}
+
+impl Console {
+    pub(crate) fn new(ctx: &BSCtx) -> Console {
+        let window = gtk::Window::builder()
+            .title("Bscript Console")
+            .default_width(600)
+            .default_height(400)
+            .type_(gtk::WindowType::Toplevel)
+            .build();
+        let root = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        root.set_margin(2);
+        window.add(&root);
+        let scroll = gtk::ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+        let view = gtk::TextView::new();
+        view.set_monospace(true);
+        view.set_editable(false);
+        view.set_cursor_visible(false);
+        view.set_wrap_mode(gtk::WrapMode::WordChar);
+        scroll.add(&view);
+        root.pack_start(&scroll, true, true, 0);
+        let entry = gtk::Entry::new();
+        entry.set_placeholder_text(Some("bscript expression"));
+        root.pack_start(&entry, false, false, 0);
+        let live: Rc<RefCell<Option<Live>>> = Rc::new(RefCell::new(None));
+        let history =
+            Rc::new(RefCell::new(History { entries: vec![], pos: Cell::new(0) }));
+        let count = Rc::new(Cell::new(0));
+        entry.connect_activate(clone!(
+            @weak ctx, @weak view, @strong live, @strong history, @strong count =>
+            move |e| {
+                let text = String::from(e.text());
+                if text.trim().is_empty() {
+                    return;
+                }
+                e.set_text("");
+                history.borrow_mut().push(&text);
+                let n = count.get() + 1;
+                count.set(n);
+                print(&view, &format!("[{}]> {}", n, text));
+                live.borrow_mut().take();
+                match text.parse::<Expr>() {
+                    Err(e) => print(&view, &format!("[{}] parse error: {}", n, e)),
+                    Ok(expr) => {
+                        let mut ctx = ctx.borrow_mut();
+                        let node = BSNode::compile(&mut ctx, Path::root(), expr);
+                        if let Some(v) = node.current(&mut ctx) {
+                            print_result(&view, n, &v)
+                        }
+                        *live.borrow_mut() = Some(Live { n, node });
+                    }
+                }
+            }
+        ));
+        entry.connect_key_press_event(clone!(
+            @strong history => move |e, key| {
+                let kv = key.keyval();
+                let h = history.borrow();
+                let s = if kv == k::Up {
+                    h.prev()
+                } else if kv == k::Down {
+                    h.next()
+                } else {
+                    return Inhibit(false);
+                };
+                if let Some(s) = s {
+                    e.set_text(s);
+                    e.set_position(-1);
+                }
+                Inhibit(true)
+            }
+        ));
+        window.show_all();
+        entry.grab_focus();
+        Console { window, view, live }
+    }
+
+    pub(crate) fn window(&self) -> &gtk::Window {
+        &self.window
+    }
+
+    /// Print the result of the live expression if `event` changes it
+    pub(crate) fn update(&self, ctx: BSCtxRef, event: &vm::Event<LocalEvent>) {
+        if let Some(live) = &mut *self.live.borrow_mut() {
+            if let Some(v) = live.node.update(ctx, event) {
+                print_result(&self.view, live.n, &v)
+            }
+        }
+    }
+
+    /// Drop the live expression, it belongs to a view that is gone
+    pub(crate) fn reset(&self) {
+        if self.live.borrow_mut().take().is_some() {
+            print(&self.view, "-- view changed --")
+        }
+    }
+}
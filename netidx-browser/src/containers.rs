@@ -1,12 +1,19 @@
-use super::{util, BSCtx, BSCtxRef, BSNode, BWidget, Widget, WidgetPath, DEFAULT_PROPS};
+use super::{
+    css_name, util, BSCtx, BSCtxRef, BSNode, BWidget, Widget, WidgetPath, WidgetState,
+    DEFAULT_PROPS,
+};
 use crate::{bscript::LocalEvent, view};
 use futures::channel::oneshot;
 use gdk::{self, prelude::*};
 use glib::idle_add_local_once;
 use gtk::{self, prelude::*, Orientation};
-use netidx::{chars::Chars, path::Path};
+use netidx::{chars::Chars, path::Path, subscriber::Value};
 use netidx_bscript::vm;
-use std::{cell::RefCell, cmp::max, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::max,
+    rc::Rc,
+};
 
 pub(crate) fn dir_to_gtk(d: &view::Direction) -> gtk::Orientation {
     match d {
@@ -15,6 +22,21 @@ pub(crate) fn dir_to_gtk(d: &view::Direction) -> gtk::Orientation {
     }
 }
 
+// name a container and then each of it's children, see
+// BWidget::set_css_names
+fn set_css_names<'a>(
+    root: &impl IsA<gtk::Widget>,
+    path: &mut Vec<WidgetPath>,
+    children: impl IntoIterator<Item = (WidgetPath, &'a Widget)>,
+) {
+    root.set_widget_name(&css_name(path));
+    for (p, c) in children {
+        path.push(p);
+        c.set_css_names(path);
+        path.pop();
+    }
+}
+
 pub(super) struct Paned {
     root: gtk::Paned,
     first_child: Option<Widget>,
@@ -73,6 +95,17 @@ impl BWidget for Paned {
         Some(self.root.upcast_ref())
     }
 
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        set_css_names(
+            &self.root,
+            path,
+            self.first_child
+                .iter()
+                .map(|c| (WidgetPath::Box(0), c))
+                .chain(self.second_child.iter().map(|c| (WidgetPath::Box(1), c))),
+        )
+    }
+
     fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
         match path.next() {
             Some(WidgetPath::Leaf) => util::set_highlight(&self.root, h),
@@ -144,6 +177,14 @@ impl BWidget for Frame {
         Some(self.root.upcast_ref())
     }
 
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        set_css_names(
+            &self.root,
+            path,
+            self.child.iter().map(|c| (WidgetPath::Box(0), c)),
+        )
+    }
+
     fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
         match path.next() {
             Some(WidgetPath::Leaf) => util::set_highlight(&self.root, h),
@@ -191,7 +232,10 @@ impl Notebook {
         });
         root.set_enable_popup(spec.tabs_popup);
         let mut children = Vec::new();
-        for s in spec.children.iter() {
+        for (i, s) in spec.children.iter().enumerate() {
+            // each page has it's own scope so pages can't see each
+            // other's local variables
+            let scope = scope.append(&i.to_string());
             match &s.kind {
                 view::WidgetKind::NotebookPage(view::NotebookPage {
                     label,
@@ -212,8 +256,7 @@ impl Notebook {
                     children.push(w);
                 }
                 _ => {
-                    let w =
-                        Widget::new(ctx, s.clone(), scope.clone(), selected_path.clone());
+                    let w = Widget::new(ctx, s.clone(), scope, selected_path.clone());
                     if let Some(r) = w.root() {
                         root.append_page(r, None::<&gtk::Label>);
                     }
@@ -255,6 +298,139 @@ impl BWidget for Notebook {
         Some(self.root.upcast_ref())
     }
 
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        set_css_names(
+            &self.root,
+            path,
+            self.children.iter().enumerate().map(|(i, c)| (WidgetPath::Box(i), c)),
+        )
+    }
+
+    fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
+        match path.next() {
+            Some(WidgetPath::Leaf) => util::set_highlight(&self.root, h),
+            Some(WidgetPath::Box(i)) => {
+                if let Some(c) = self.children.get(*i) {
+                    c.set_highlight(path, h)
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn export_state(&self, state: WidgetState) {
+        let page = self.root.current_page().map(Value::from).unwrap_or(Value::Null);
+        state.set("page", page);
+        self.root.connect_switch_page(move |_, _, page| state.set("page", page.into()));
+    }
+}
+
+pub(super) struct Responsive {
+    root: gtk::Stack,
+    window: gtk::ApplicationWindow,
+    on_resize: Option<glib::SignalHandlerId>,
+    children: Vec<Widget>,
+}
+
+impl Responsive {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        spec: view::Responsive,
+        scope: Path,
+        selected_path: gtk::Label,
+    ) -> Self {
+        let scope = scope.append("r");
+        let root = gtk::Stack::new();
+        root.set_no_show_all(true);
+        root.set_homogeneous(false);
+        let mut bounds = Vec::new();
+        let mut children = Vec::new();
+        for (i, s) in spec.children.into_iter().enumerate() {
+            // like notebook pages each layout has it's own scope
+            let scope = scope.append(&i.to_string());
+            let (b, spec) = match s.kind {
+                view::WidgetKind::Breakpoint(view::Breakpoint {
+                    min_width,
+                    max_width,
+                    min_height,
+                    max_height,
+                    widget,
+                }) => {
+                    let b = view::Breakpoint {
+                        min_width,
+                        max_width,
+                        min_height,
+                        max_height,
+                        widget: Default::default(),
+                    };
+                    (b, *widget)
+                }
+                _ => (view::Breakpoint::default(), s),
+            };
+            let w = Widget::new(ctx, spec, scope, selected_path.clone());
+            if let Some(r) = w.root() {
+                root.add_named(r, &i.to_string());
+            }
+            bounds.push(b);
+            children.push(w);
+        }
+        let window = ctx.borrow().user.window.clone();
+        let current = Rc::new(Cell::new(Responsive::select(&bounds, window.size())));
+        root.set_visible_child_name(&current.get().to_string());
+        // changing the layout resizes the window's contents, so it
+        // can't be done while the window is being allocated
+        let on_resize = window.connect_size_allocate(clone!(
+            @weak root, @strong current => move |window, _| {
+                let i = Responsive::select(&bounds, window.size());
+                if i != current.get() {
+                    current.set(i);
+                    idle_add_local_once(clone!(@weak root => move || {
+                        root.set_visible_child_name(&i.to_string());
+                    }));
+                }
+        }));
+        Responsive { root, window, on_resize: Some(on_resize), children }
+    }
+
+    // the index of the first matching layout, or 0 if none match
+    fn select(bounds: &[view::Breakpoint], (width, height): (i32, i32)) -> usize {
+        let (width, height) = (max(width, 0) as u32, max(height, 0) as u32);
+        bounds.iter().position(|b| b.matches(width, height)).unwrap_or(0)
+    }
+}
+
+impl Drop for Responsive {
+    fn drop(&mut self) {
+        if let Some(id) = self.on_resize.take() {
+            self.window.disconnect(id);
+        }
+    }
+}
+
+impl BWidget for Responsive {
+    fn update(
+        &mut self,
+        ctx: BSCtxRef,
+        waits: &mut Vec<oneshot::Receiver<()>>,
+        event: &vm::Event<LocalEvent>,
+    ) {
+        for c in &mut self.children {
+            c.update(ctx, waits, event);
+        }
+    }
+
+    fn root(&self) -> Option<&gtk::Widget> {
+        Some(self.root.upcast_ref())
+    }
+
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        set_css_names(
+            &self.root,
+            path,
+            self.children.iter().enumerate().map(|(i, c)| (WidgetPath::Box(i), c)),
+        )
+    }
+
     fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
         match path.next() {
             Some(WidgetPath::Leaf) => util::set_highlight(&self.root, h),
@@ -266,6 +442,18 @@ impl BWidget for Notebook {
             _ => (),
         }
     }
+
+    fn export_state(&self, state: WidgetState) {
+        let layout = |s: &gtk::Stack| {
+            s.visible_child_name()
+                .and_then(|n| n.parse::<u32>().ok())
+                .map(Value::from)
+                .unwrap_or(Value::Null)
+        };
+        state.set("layout", layout(&self.root));
+        self.root
+            .connect_visible_child_name_notify(move |s| state.set("layout", layout(s)));
+    }
 }
 
 pub(super) struct Box {
@@ -352,6 +540,14 @@ impl BWidget for Box {
         Some(self.root.upcast_ref())
     }
 
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        set_css_names(
+            &self.root,
+            path,
+            self.children.iter().enumerate().map(|(i, c)| (WidgetPath::Box(i), c)),
+        )
+    }
+
     fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
         match path.next() {
             Some(WidgetPath::Leaf) => util::set_highlight(&self.root, h),
@@ -380,7 +576,8 @@ impl Grid {
         let scope = scope.append("g");
         let root = gtk::Grid::new();
         root.set_no_show_all(true);
-        let attach_child = |spec: view::GridChild,
+        let attach_child = |scope: &Path,
+                            spec: view::GridChild,
                             max_height: &mut i32,
                             i: &mut i32,
                             j: i32|
@@ -400,7 +597,7 @@ impl Grid {
             *max_height = max(*max_height, height);
             w
         };
-        let attach_normal = |spec: view::Widget, i: &mut i32, j: i32| -> Widget {
+        let attach_normal = |scope: &Path, spec: view::Widget, i: &mut i32, j: i32| {
             let w = Widget::new(ctx, spec.clone(), scope.clone(), selected_path.clone());
             if let Some(r) = w.root() {
                 root.attach(r, *i, j, 1, 1);
@@ -417,22 +614,26 @@ impl Grid {
         let children = spec
             .rows
             .into_iter()
-            .map(|spec| {
+            .enumerate()
+            .map(|(k, spec)| {
+                // each row has it's own scope so rows can't see each
+                // other's local variables
+                let scope = scope.append(&k.to_string());
                 let mut max_height = 1;
                 let row = match spec.kind {
                     view::WidgetKind::GridChild(c) => {
-                        vec![attach_child(c, &mut max_height, &mut i, j)]
+                        vec![attach_child(&scope, c, &mut max_height, &mut i, j)]
                     }
                     view::WidgetKind::GridRow(view::GridRow { columns }) => columns
                         .into_iter()
                         .map(|spec| match spec.kind {
                             view::WidgetKind::GridChild(c) => {
-                                attach_child(c, &mut max_height, &mut i, j)
+                                attach_child(&scope, c, &mut max_height, &mut i, j)
                             }
-                            _ => attach_normal(spec, &mut i, j),
+                            _ => attach_normal(&scope, spec, &mut i, j),
                         })
                         .collect(),
-                    _ => vec![attach_normal(spec, &mut i, j)],
+                    _ => vec![attach_normal(&scope, spec, &mut i, j)],
                 };
                 j += max_height;
                 i = 0;
@@ -461,6 +662,16 @@ impl BWidget for Grid {
         Some(self.root.upcast_ref())
     }
 
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        set_css_names(
+            &self.root,
+            path,
+            self.children.iter().enumerate().flat_map(|(i, row)| {
+                row.iter().enumerate().map(move |(j, c)| (WidgetPath::GridItem(i, j), c))
+            }),
+        )
+    }
+
     fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
         match path.next() {
             Some(WidgetPath::Leaf) => util::set_highlight(&self.root, h),
@@ -1,12 +1,161 @@
 use super::Scope;
 use glib::{prelude::*, subclass::prelude::*};
-use netidx::path::Path;
+use gtk::prelude::*;
+use log::warn;
+use netidx::{
+    path::Path,
+    utils::{escape, unescape},
+};
 use radix_trie::TrieCommon;
 use sourceview4::{
     prelude::*, subclass::prelude::*, CompletionActivation, CompletionContext,
-    CompletionItem, CompletionProvider,
+    CompletionItem, CompletionProposal, CompletionProvider,
 };
-use std::{default::Default, rc::Rc};
+use std::{cell::Cell, default::Default, rc::Rc};
+
+/// The characters that must be escaped in bscript string constants
+static ESC: [char; 4] = ['"', '\\', '[', ']'];
+
+/// Argument hints for the builtin functions
+static HINTS: &[(&str, &str)] = &[
+    ("abs", "v"),
+    ("after", "delay, v"),
+    ("after_idle", "timeout, v"),
+    ("all", "v0, v1, .."),
+    ("and", "v0, v1, .."),
+    ("any", "v0, v1, .."),
+    ("array", "v0, v1, .."),
+    ("array_len", "array"),
+    ("array_map", "array, fname"),
+    ("basename", "path"),
+    ("call", "trigger, rpc, name0, arg0, .."),
+    ("cast", "typ, v"),
+    ("ceil", "v"),
+    ("changed", "v"),
+    ("clamp", "lo, hi, v"),
+    ("cmp", "op, v0, v1"),
+    ("confirm", "[msg], v"),
+    ("contains", "part, s"),
+    ("count", "v"),
+    ("current_path", ""),
+    ("dialog", "title, field0, field1, .."),
+    ("dirname", "path"),
+    ("divide", "v0, v1, .."),
+    ("do", "e0, e1, .."),
+    ("ends_with", "part, s"),
+    ("eval", "src"),
+    ("event", ""),
+    ("filter", "predicate, v"),
+    ("filter_err", "v"),
+    ("floor", "v"),
+    ("get", "name"),
+    ("guard", "condition, action"),
+    ("if", "predicate, case_if, [case_else]"),
+    ("index", "array, index"),
+    ("is_error", "v"),
+    ("is_subscribed", "path"),
+    ("isa", "typ, v"),
+    ("let", "name, v"),
+    ("load", "path"),
+    ("map_get", "map, key, [default]"),
+    ("match", "v, pat0, out0, .., [default]"),
+    ("max", "v0, v1, .."),
+    ("mean", "v"),
+    ("min", "v0, v1, .."),
+    ("navigate", "[new_window], to"),
+    ("not", "v"),
+    ("now", "[trigger]"),
+    ("once", "v"),
+    ("or", "v0, v1, .."),
+    ("path_basename", "path"),
+    ("path_concat", "base, p0, p1, .."),
+    ("path_dirname", "path"),
+    ("path_escape", "s"),
+    ("poll", "path, trigger"),
+    ("product", "v0, v1, .."),
+    ("replace", "pat, rep, s"),
+    ("round", "digits, v"),
+    ("sample", "trigger, v"),
+    ("sample_on", "trigger, v"),
+    ("set", "name, v"),
+    ("set_title", "title"),
+    ("starts_with", "part, s"),
+    ("store", "path, v"),
+    ("string_concat", "s0, s1, .."),
+    ("string_join", "sep, s0, s1, .."),
+    ("strip_prefix", "part, s"),
+    ("strip_suffix", "part, s"),
+    ("sum", "v0, v1, .."),
+    ("table_export", "path, dest"),
+    ("time_diff", "a, b"),
+    ("time_format", "fmt, v"),
+    ("timer", "timeout, repeat"),
+    ("tr", "key"),
+    ("trim", "s"),
+    ("trim_end", "s"),
+    ("trim_start", "s"),
+    ("uniq", "v"),
+    ("widget", "key"),
+];
+
+/// If `text` ends inside a string constant that is the path
+/// argument of `load` or `store`, return the length in chars of the
+/// constant so far, and the part of the path it contains.
+fn path_prefix(text: &str) -> Option<(usize, String)> {
+    enum St {
+        Code,
+        Str(usize),
+    }
+    let mut stack = vec![St::Code];
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match stack.last() {
+            None | Some(St::Code) => match c {
+                '"' => stack.push(St::Str(i)),
+                '[' => stack.push(St::Code),
+                ']' if stack.len() > 1 => {
+                    stack.pop();
+                }
+                _ => (),
+            },
+            Some(St::Str(_)) if escaped => escaped = false,
+            Some(St::Str(_)) => match c {
+                '\\' => escaped = true,
+                '"' => {
+                    stack.pop();
+                }
+                '[' => stack.push(St::Code),
+                _ => (),
+            },
+        }
+    }
+    let start = match stack.last() {
+        Some(St::Str(start)) => *start,
+        None | Some(St::Code) => return None,
+    };
+    let path = &text[start + 1..];
+    // interpolated strings aren't completed
+    if path.contains('[') || !path.starts_with('/') {
+        return None;
+    }
+    let func = text[..start].trim_end().strip_suffix('(')?.trim_end();
+    let name_start = func
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    match &func[name_start..] {
+        "load" | "store" => {
+            Some((path.chars().count(), unescape(path, '\\').into_owned()))
+        }
+        _ => None,
+    }
+}
+
+/// The text of the buffer up to `iter`
+fn text_before(iter: &gtk::TextIter) -> Option<glib::GString> {
+    let buf = iter.buffer()?;
+    buf.text(&buf.start_iter(), iter, false)
+}
 
 glib::wrapper! {
     pub(crate) struct BScriptCompletionProvider(ObjectSubclass<imp::BScriptCompletionProvider>)
@@ -22,7 +171,7 @@ impl BScriptCompletionProvider {
 pub(crate) mod imp {
     use std::cell::RefCell;
 
-    use crate::BSCtx;
+    use crate::{backend, BSCtx};
 
     use super::*;
 
@@ -39,6 +188,55 @@ pub(crate) mod imp {
         pub(crate) fn init(&self, ctx: BSCtx, scope: Scope) {
             *self.0.borrow_mut() = Some(BScriptCompletionProviderInner { ctx, scope });
         }
+
+        /// Complete a path by asking the resolver for the children of
+        /// its parent. The proposals are added when the resolver
+        /// answers, unless the completion was cancelled first.
+        fn populate_paths(
+            &self,
+            backend: &backend::Ctx,
+            context: &CompletionContext,
+            prefix: String,
+        ) {
+            let obj = self.obj();
+            let provider = obj.dynamic_cast_ref::<CompletionProvider>().unwrap().clone();
+            context.add_proposals(&provider, &[], false);
+            let (parent, base) = match prefix.rfind('/') {
+                Some(0) | None => (Path::root(), &prefix[1..]),
+                Some(i) => (Path::from(String::from(&prefix[..i])), &prefix[i + 1..]),
+            };
+            let base = String::from(base);
+            let cancelled = Rc::new(Cell::new(false));
+            context.connect_cancelled({
+                let cancelled = cancelled.clone();
+                move |_| cancelled.set(true)
+            });
+            let backend = backend.clone();
+            let context = context.clone();
+            glib::MainContext::default().spawn_local(async move {
+                let mut children = match backend.list(parent).await {
+                    Ok(children) => children,
+                    Err(e) => {
+                        warn!("completion failed to list paths {}", e);
+                        vec![]
+                    }
+                };
+                if cancelled.get() {
+                    return;
+                }
+                children.sort();
+                let candidates = children
+                    .into_iter()
+                    .filter(|p| Path::basename(p).unwrap_or("").starts_with(&*base))
+                    .map(|p| {
+                        let l = Path::basename(&p).unwrap_or("");
+                        let t = escape(&*p, '\\', &ESC);
+                        CompletionItem::builder().text(&*t).label(l).build().upcast()
+                    })
+                    .collect::<Vec<_>>();
+                context.add_proposals(&provider, &*candidates, true);
+            });
+        }
     }
 
     impl Default for BScriptCompletionProvider {
@@ -62,7 +260,7 @@ pub(crate) mod imp {
 
     impl CompletionProviderImpl for BScriptCompletionProvider {
         fn activation(&self) -> CompletionActivation {
-            CompletionActivation::USER_REQUESTED
+            CompletionActivation::USER_REQUESTED | CompletionActivation::INTERACTIVE
         }
 
         fn interactive_delay(&self) -> i32 {
@@ -85,6 +283,16 @@ pub(crate) mod imp {
             let inner = self.0.borrow();
             let inner = get!(&*inner);
             let ctx = inner.ctx.borrow();
+            let text = get!(text_before(&get!(context.iter())));
+            if let Some((_, prefix)) = path_prefix(&text) {
+                return self.populate_paths(&ctx.user.backend, context, prefix);
+            }
+            // only paths are completed as you type
+            if context.activation() == CompletionActivation::INTERACTIVE {
+                let obj = self.obj();
+                let provider = obj.dynamic_cast_ref::<CompletionProvider>().unwrap();
+                return context.add_proposals(provider, &[], true);
+            }
             let word = {
                 let mut iter = get!(context.iter());
                 let fin = iter.clone();
@@ -119,7 +327,10 @@ pub(crate) mod imp {
                 .map(|st| st.iter())
                 .flatten()
                 .map(|(c, ())| {
-                    let l = format!("fn {}(..)", c);
+                    let l = match HINTS.iter().find(|(f, _)| f == c) {
+                        Some((_, args)) => format!("fn {}({})", c, args),
+                        None => format!("fn {}(..)", c),
+                    };
                     CompletionItem::builder().text(c).label(&l).build().upcast()
                 });
             let scope = inner.scope.borrow();
@@ -152,5 +363,32 @@ pub(crate) mod imp {
             let provider = obj.dynamic_cast_ref::<CompletionProvider>().unwrap();
             context.add_proposals(provider, &*candidates, true);
         }
+
+        fn activate_proposal(
+            &self,
+            proposal: &CompletionProposal,
+            iter: &gtk::TextIter,
+        ) -> bool {
+            macro_rules! get {
+                ($e:expr) => {
+                    match $e {
+                        None => return false,
+                        Some(e) => e,
+                    }
+                };
+            }
+            // paths replace the whole string constant, other proposals
+            // are inserted by the default handler
+            let text = get!(text_before(iter));
+            let (len, _) = get!(path_prefix(&text));
+            let new = get!(proposal.text());
+            let buf = get!(iter.buffer());
+            let mut start = iter.clone();
+            let mut end = iter.clone();
+            start.backward_chars(len as i32);
+            buf.delete(&mut start, &mut end);
+            buf.insert(&mut start, &new);
+            true
+        }
     }
 }
@@ -1,12 +1,12 @@
 use super::super::{bscript::LocalEvent, util::ask_modal, BSCtx};
-use super::{completion::BScriptCompletionProvider, Scope};
+use super::{completion::BScriptCompletionProvider, util::text_columns, Scope};
 use chrono::prelude::*;
 use fxhash::FxHashMap;
 use gdk::keys;
 use glib::idle_add_local_once;
 use glib::{clone, prelude::*, subclass::prelude::*, thread_guard::ThreadGuard};
 use gtk::{self, prelude::*};
-use netidx::subscriber::Value;
+use netidx::{path::Path, subscriber::Value};
 use netidx_bscript::{expr, vm};
 use parking_lot::Mutex;
 use sourceview4::{self as sv, prelude::*, traits::ViewExt};
@@ -21,6 +21,61 @@ use std::{
 #[boxed_type(name = "NetidxExprInspectorWrap")]
 struct ExprWrap(Arc<dyn Fn(&DateTime<Local>, &Option<vm::Event<LocalEvent>>, &Value)>);
 
+#[derive(Clone, Boxed)]
+#[boxed_type(name = "NetidxExprInspectorId")]
+struct IdWrap(expr::ExprId);
+
+/// Describe the target of the event that caused an expression to update
+fn trigger(e: &Option<vm::Event<LocalEvent>>) -> String {
+    match e {
+        None => String::from("initialization"),
+        Some(vm::Event::Variable(scope, name, _)) => {
+            format!("variable {} in {}", name, scope)
+        }
+        Some(vm::Event::Netidx(id, _)) => format!("netidx {:?}", id),
+        Some(vm::Event::Rpc(id, _)) => format!("rpc {:?}", id),
+        Some(vm::Event::Timer(id)) => format!("timer {:?}", id),
+        Some(vm::Event::User(LocalEvent::Event(_))) => String::from("event"),
+        Some(vm::Event::User(LocalEvent::TableResolved(path, _))) => {
+            format!("table resolved {}", path)
+        }
+        Some(vm::Event::User(LocalEvent::Poll(path))) => format!("poll {}", path),
+        Some(vm::Event::User(LocalEvent::TableExport(path, _))) => {
+            format!("table export {}", path)
+        }
+        Some(vm::Event::User(LocalEvent::Flush)) => String::from("flush"),
+    }
+}
+
+fn set_updated(
+    store: &gtk::TreeStore,
+    iter: &gtk::TreeIter,
+    ts: &DateTime<Local>,
+    e: &Option<vm::Event<LocalEvent>>,
+    v: &Value,
+) {
+    store.set_value(iter, 1, &format!("{}", v).to_value());
+    store.set_value(iter, 2, &format!("{}", ts).to_value());
+    store.set_value(iter, 3, &trigger(e).to_value());
+}
+
+fn log_history_val(
+    history: &gtk::ListStore,
+    ts: &DateTime<Local>,
+    e: &Option<vm::Event<LocalEvent>>,
+    v: &Value,
+) {
+    let i = history.append();
+    history.set_value(&i, 0, &format!("{}", ts).to_value());
+    history.set_value(&i, 1, &trigger(e).to_value());
+    history.set_value(&i, 2, &format!("{}", v).to_value());
+    if history.iter_n_children(None) as usize > vm::DbgCtx::<LocalEvent>::HISTORY {
+        if let Some(iter) = history.iter_first() {
+            history.remove(&iter);
+        }
+    }
+}
+
 fn log_expr_val(
     log: &gtk::ListStore,
     expr: &expr::Expr,
@@ -32,10 +87,7 @@ fn log_expr_val(
     let i = log.append();
     log.set_value(&i, 0, &format!("{}", ts).to_value());
     log.set_value(&i, 1, &format!("{}", expr).to_value());
-    match e {
-        Some(e) => log.set_value(&i, 2, &format!("{:?}", e).to_value()),
-        None => log.set_value(&i, 2, &"initialization".to_value()),
-    }
+    log.set_value(&i, 2, &trigger(e).to_value());
     log.set_value(&i, 3, &format!("{}", v).to_value());
     if log.iter_n_children(None) as usize > MAX {
         if let Some(iter) = log.iter_first() {
@@ -49,6 +101,8 @@ fn add_watch(
     store: &gtk::TreeStore,
     iter: &gtk::TreeIter,
     log: &gtk::ListStore,
+    history: &gtk::ListStore,
+    selected: &Rc<Cell<Option<expr::ExprId>>>,
     expr: expr::Expr,
 ) {
     let id = expr.id;
@@ -59,29 +113,38 @@ fn add_watch(
             store: gtk::TreeStore,
             iter: gtk::TreeIter,
             log: gtk::ListStore,
+            history: gtk::ListStore,
+            selected: Rc<Cell<Option<expr::ExprId>>>,
         }
         struct Ctx(Mutex<ThreadGuard<CtxInner>>);
         let ctx = Ctx(Mutex::new(ThreadGuard::new(CtxInner {
             store: store.clone(),
             iter: iter.clone(),
             log: log.clone(),
+            history: history.clone(),
+            selected: selected.clone(),
         })));
         Arc::new(
             move |ts: &DateTime<Local>, e: &Option<vm::Event<LocalEvent>>, v: &Value| {
                 let inner = ctx.0.lock();
                 let inner = inner.get_ref();
-                inner.store.set_value(&inner.iter, 1, &format!("{}", v).to_value());
+                set_updated(&inner.store, &inner.iter, ts, e, v);
+                if inner.selected.get() == Some(id) {
+                    log_history_val(&inner.history, ts, e, v)
+                }
                 log_expr_val(&inner.log, &expr, ts, e, v)
             },
         )
     };
     ctx.borrow_mut().dbg_ctx.add_watch(id, &watch);
-    store.set_value(&iter, 2, &ExprWrap(watch).to_value());
+    store.set_value(&iter, 4, &ExprWrap(watch).to_value());
 }
 
 struct DataFlow {
-    call_root: gtk::ScrolledWindow,
+    call_root: gtk::Paned,
     call_store: gtk::TreeStore,
+    history_store: gtk::ListStore,
+    selected: Rc<Cell<Option<expr::ExprId>>>,
     event_root: gtk::ScrolledWindow,
     event_store: gtk::ListStore,
     ctx: BSCtx,
@@ -89,18 +152,33 @@ struct DataFlow {
 
 impl DataFlow {
     fn new(ctx: BSCtx) -> Self {
-        let call_root =
+        let call_root = gtk::Paned::new(gtk::Orientation::Horizontal);
+        let call_win =
             gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
-        call_root.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
-        call_root.set_expand(false);
+        call_win.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+        call_win.set_expand(false);
+        let history_win =
+            gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        history_win.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+        history_win.set_expand(false);
+        call_root.pack1(&call_win, true, false);
+        call_root.pack2(&history_win, true, true);
         let event_root =
             gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
         event_root.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
         event_root.set_expand(false);
         let call_store = gtk::TreeStore::new(&[
+            String::static_type(),
+            String::static_type(),
             String::static_type(),
             String::static_type(),
             ExprWrap::static_type(),
+            IdWrap::static_type(),
+        ]);
+        let history_store = gtk::ListStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
         ]);
         let event_store = gtk::ListStore::new(&[
             String::static_type(),
@@ -109,41 +187,51 @@ impl DataFlow {
             String::static_type(),
         ]);
         let call_view = gtk::TreeView::new();
+        let history_view = gtk::TreeView::new();
         let event_view = gtk::TreeView::new();
-        call_root.add(&call_view);
+        call_win.add(&call_view);
+        history_win.add(&history_view);
         event_root.add(&event_view);
-        for (i, name) in ["kind", "current"].iter().enumerate() {
-            call_view.append_column(&{
-                let column = gtk::TreeViewColumn::new();
-                let cell = gtk::CellRendererText::new();
-                CellLayoutExt::pack_start(&column, &cell, true);
-                column.set_resizable(true);
-                column.set_title(name);
-                CellLayoutExt::add_attribute(&column, &cell, "text", i as i32);
-                column
-            });
-        }
-        for (i, name) in ["timestamp", "expr", "event", "result"].iter().enumerate() {
-            event_view.append_column(&{
-                let column = gtk::TreeViewColumn::new();
-                let cell = gtk::CellRendererText::new();
-                CellLayoutExt::pack_start(&column, &cell, true);
-                column.set_resizable(true);
-                column.set_title(name);
-                CellLayoutExt::add_attribute(&column, &cell, "text", i as i32);
-                column
-            });
-        }
+        text_columns(&call_view, &["kind", "current", "updated", "trigger"]);
+        text_columns(&history_view, &["timestamp", "trigger", "value"]);
+        text_columns(&event_view, &["timestamp", "expr", "event", "result"]);
         call_view.set_model(Some(&call_store));
         call_view.set_reorderable(false);
         call_view.set_enable_tree_lines(true);
+        history_view.set_model(Some(&history_store));
+        history_view.set_reorderable(false);
         event_view.set_model(Some(&event_store));
         event_view.set_reorderable(false);
-        DataFlow { call_root, call_store, event_root, event_store, ctx }
+        let selected = Rc::new(Cell::new(None));
+        call_view.selection().connect_changed(clone!(
+            @strong ctx, @strong history_store, @strong selected => move |s| {
+                history_store.clear();
+                selected.set(None);
+                if let Some((m, i)) = s.selected() {
+                    if let Ok(IdWrap(id)) = m.value(&i, 5).get::<&IdWrap>() {
+                        selected.set(Some(*id));
+                        for (ts, e, v) in ctx.borrow().dbg_ctx.iter_history(id) {
+                            log_history_val(&history_store, ts, e, v);
+                        }
+                    }
+                }
+            }
+        ));
+        DataFlow {
+            call_root,
+            call_store,
+            history_store,
+            selected,
+            event_root,
+            event_store,
+            ctx,
+        }
     }
 
     fn clear(&self) {
+        self.selected.set(None);
         self.call_store.clear();
+        self.history_store.clear();
         self.event_store.clear();
     }
 
@@ -163,14 +251,19 @@ impl DataFlow {
             expr::Expr { kind: expr::ExprKind::Apply { args, function }, id } => {
                 exprs.insert(*id, s.clone());
                 self.call_store.set_value(&iter, 0, &function.to_value());
-                if let Some((_, v)) = self.ctx.borrow().dbg_ctx.get_current(&id) {
-                    self.call_store.set_value(&iter, 1, &format!("{}", v).to_value());
+                self.call_store.set_value(&iter, 5, &IdWrap(*id).to_value());
+                if let Some((ts, e, v)) =
+                    self.ctx.borrow().dbg_ctx.iter_history(id).last()
+                {
+                    set_updated(&self.call_store, &iter, ts, e, v);
                 }
                 add_watch(
                     &self.ctx,
                     &self.call_store,
                     &iter,
                     &self.event_store,
+                    &self.history_store,
+                    &self.selected,
                     s.clone(),
                 );
                 for s in args {
@@ -226,27 +319,82 @@ impl ErrorDisplay {
     }
 }
 
+/// The variables visible from the scope of the expression, innermost
+/// scope first, so a variable shadows any later one with the same name.
+struct Variables {
+    root: gtk::ScrolledWindow,
+    store: gtk::ListStore,
+    ctx: BSCtx,
+    scope: Scope,
+}
+
+impl Variables {
+    fn new(ctx: BSCtx, scope: Scope) -> Self {
+        let root =
+            gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        root.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+        let store = gtk::ListStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+        ]);
+        let view = gtk::TreeView::new();
+        root.add(&view);
+        text_columns(&view, &["name", "scope", "value"]);
+        view.set_model(Some(&store));
+        Variables { root, store, ctx, scope }
+    }
+
+    fn display(&self) {
+        self.store.clear();
+        let ctx = self.ctx.borrow();
+        let scope = self.scope.borrow();
+        for s in Path::dirnames(&**scope).rev() {
+            if let Some(vars) = ctx.variables.get(s) {
+                let mut vars = vars.iter().collect::<Vec<_>>();
+                vars.sort_by(|(n0, _), (n1, _)| n0.cmp(n1));
+                for (name, value) in vars {
+                    let iter = self.store.append();
+                    self.store.set_value(&iter, 0, &name.to_string().to_value());
+                    self.store.set_value(&iter, 1, &s.to_value());
+                    self.store.set_value(&iter, 2, &format!("{}", value).to_value());
+                }
+            }
+        }
+    }
+}
+
 struct Tools {
     root: gtk::Notebook,
     data_flow: DataFlow,
+    variables: Rc<Variables>,
     error: ErrorDisplay,
 }
 
 impl Tools {
-    fn new(ctx: BSCtx) -> Self {
+    fn new(ctx: BSCtx, scope: Scope) -> Self {
         let root = gtk::Notebook::new();
         let data_flow = DataFlow::new(ctx.clone());
         let call_tree_lbl = gtk::Label::new(Some("Call Tree"));
         let event_log_lbl = gtk::Label::new(Some("Event Log"));
+        let variables_lbl = gtk::Label::new(Some("Variables"));
         root.append_page(&data_flow.call_root, Some(&call_tree_lbl));
         root.append_page(&data_flow.event_root, Some(&event_log_lbl));
+        let variables = Rc::new(Variables::new(ctx, scope));
+        let variables_page = root.append_page(&variables.root, Some(&variables_lbl));
+        root.connect_switch_page(clone!(@weak variables => move |_, _, page| {
+            if page == variables_page {
+                variables.display()
+            }
+        }));
         let error = ErrorDisplay::new();
         root.append_page(&error.root, Some(&error.error_lbl));
-        Tools { root, data_flow, error }
+        Tools { root, data_flow, variables, error }
     }
 
     fn display(&self, e: &expr::Expr) {
         self.data_flow.display(e);
+        self.variables.display();
         self.error.clear()
     }
 
@@ -351,7 +499,7 @@ impl ExprInspector {
         headerbar.set_show_close_button(true);
         window.set_titlebar(Some(&headerbar));
         let root = gtk::Paned::new(gtk::Orientation::Vertical);
-        let tools = Rc::new(Tools::new(ctx.clone()));
+        let tools = Rc::new(Tools::new(ctx.clone(), scope.clone()));
         let editor = ExprEditor::new(
             tools.clone(),
             save_button.clone(),
@@ -0,0 +1,380 @@
+use super::{
+    super::BSCtx,
+    util::{expander_touch_enable, text_columns},
+    Widget,
+};
+use fxhash::{FxHashMap, FxHashSet};
+use glib::clone;
+use gtk::{self, prelude::*};
+use netidx::{path::Path, subscriber::Value};
+use netidx_bscript::expr::{Expr, ExprKind};
+use netidx_protocols::view;
+use std::{cell::RefCell, rc::Rc};
+
+/// A problem found in the view, and the path in the editor tree of
+/// the widget it was found in.
+struct Problem {
+    path: Vec<i32>,
+    widget: String,
+    message: String,
+}
+
+/// The expressions of a widget, not including it's children, by
+/// field name, and whether the widget casts the field to a bool.
+fn exprs(w: &view::Widget) -> Vec<(&'static str, &Expr, bool)> {
+    let mut res = Vec::new();
+    if let Some(p) = &w.props {
+        res.push(("sensitive", &p.sensitive, true));
+        res.push(("visible", &p.visible, true));
+        res.push(("interlock", &p.interlock, true));
+        for k in &p.keybinds {
+            res.push(("keybind", &k.expr, false));
+        }
+    }
+    match &w.kind {
+        view::WidgetKind::BScript(e) => res.push(("expression", e, false)),
+        view::WidgetKind::Table(t) => res.extend([
+            ("path", &t.path, false),
+            ("sort_mode", &t.sort_mode, false),
+            ("column_filter", &t.column_filter, false),
+            ("row_filter", &t.row_filter, false),
+            ("column_editable", &t.column_editable, false),
+            ("column_widths", &t.column_widths, false),
+            ("columns_resizable", &t.columns_resizable, true),
+            ("column_types", &t.column_types, false),
+            ("footer", &t.footer, false),
+            ("selection_mode", &t.selection_mode, false),
+            ("selection", &t.selection, false),
+            ("show_row_name", &t.show_row_name, true),
+            ("refresh", &t.refresh, false),
+            ("on_select", &t.on_select, false),
+            ("on_activate", &t.on_activate, false),
+            ("on_edit", &t.on_edit, false),
+            ("on_header_click", &t.on_header_click, false),
+        ]),
+        view::WidgetKind::Label(l) => res.extend([
+            ("ellipsize", &l.ellipsize, false),
+            ("text", &l.text, false),
+            ("width", &l.width, false),
+            ("single_line", &l.single_line, true),
+            ("selectable", &l.selectable, true),
+        ]),
+        view::WidgetKind::Button(b) => res.extend([
+            ("label", &b.label, false),
+            ("image", &b.image, false),
+            ("on_click", &b.on_click, false),
+        ]),
+        view::WidgetKind::LinkButton(b) => res.extend([
+            ("uri", &b.uri, false),
+            ("label", &b.label, false),
+            ("on_activate_link", &b.on_activate_link, false),
+        ]),
+        view::WidgetKind::Switch(s) => {
+            res.extend([("value", &s.value, true), ("on_change", &s.on_change, false)])
+        }
+        view::WidgetKind::ToggleButton(t) | view::WidgetKind::CheckButton(t) => res
+            .extend([
+                ("value", &t.toggle.value, true),
+                ("on_change", &t.toggle.on_change, false),
+                ("label", &t.label, false),
+                ("image", &t.image, false),
+            ]),
+        view::WidgetKind::RadioButton(r) => res.extend([
+            ("label", &r.label, false),
+            ("image", &r.image, false),
+            ("group", &r.group, false),
+            ("value", &r.value, true),
+            ("on_toggled", &r.on_toggled, false),
+        ]),
+        view::WidgetKind::ComboBox(c) => res.extend([
+            ("choices", &c.choices, false),
+            ("selected", &c.selected, false),
+            ("on_change", &c.on_change, false),
+        ]),
+        view::WidgetKind::Entry(e) => res.extend([
+            ("text", &e.text, false),
+            ("on_change", &e.on_change, false),
+            ("on_activate", &e.on_activate, false),
+        ]),
+        view::WidgetKind::SearchEntry(e) => res.extend([
+            ("text", &e.text, false),
+            ("on_search_changed", &e.on_search_changed, false),
+            ("on_activate", &e.on_activate, false),
+        ]),
+        view::WidgetKind::ProgressBar(p) => res.extend([
+            ("ellipsize", &p.ellipsize, false),
+            ("fraction", &p.fraction, false),
+            ("pulse", &p.pulse, false),
+            ("text", &p.text, false),
+            ("show_text", &p.show_text, true),
+        ]),
+        view::WidgetKind::Scale(s) => res.extend([
+            ("draw_value", &s.draw_value, true),
+            ("marks", &s.marks, false),
+            ("has_origin", &s.has_origin, true),
+            ("value", &s.value, false),
+            ("min", &s.min, false),
+            ("max", &s.max, false),
+            ("step", &s.step, false),
+            ("on_change", &s.on_change, false),
+        ]),
+        view::WidgetKind::SpinButton(s) => res.extend([
+            ("value", &s.value, false),
+            ("min", &s.min, false),
+            ("max", &s.max, false),
+            ("step", &s.step, false),
+            ("digits", &s.digits, false),
+            ("on_change", &s.on_change, false),
+        ]),
+        view::WidgetKind::Calendar(c) => {
+            res.extend([("value", &c.value, false), ("on_change", &c.on_change, false)])
+        }
+        view::WidgetKind::RadioGroup(r) => res.extend([
+            ("choices", &r.choices, false),
+            ("selected", &r.selected, false),
+            ("on_change", &r.on_change, false),
+        ]),
+        view::WidgetKind::Image(i) => {
+            res.extend([("spec", &i.spec, false), ("on_click", &i.on_click, false)])
+        }
+        view::WidgetKind::Frame(f) => res.push(("label", &f.label, false)),
+        view::WidgetKind::Notebook(n) => res.extend([
+            ("page", &n.page, false),
+            ("on_switch_page", &n.on_switch_page, false),
+        ]),
+        view::WidgetKind::LinePlot(p) => {
+            res.extend([
+                ("x_min", &p.x_min, false),
+                ("x_max", &p.x_max, false),
+                ("y_min", &p.y_min, false),
+                ("y_max", &p.y_max, false),
+                ("keep_points", &p.keep_points, false),
+                ("on_cursor", &p.on_cursor, false),
+            ]);
+            for s in &p.series {
+                res.extend([("series x", &s.x, false), ("series y", &s.y, false)]);
+            }
+        }
+        view::WidgetKind::Box(_)
+        | view::WidgetKind::BoxChild(_)
+        | view::WidgetKind::Grid(_)
+        | view::WidgetKind::GridChild(_)
+        | view::WidgetKind::GridRow(_)
+        | view::WidgetKind::Paned(_)
+        | view::WidgetKind::NotebookPage(_)
+        | view::WidgetKind::Responsive(_)
+        | view::WidgetKind::Breakpoint(_) => (),
+    }
+    res
+}
+
+fn walk<'a>(e: &'a Expr, f: &mut impl FnMut(&'a Expr)) {
+    f(e);
+    if let ExprKind::Apply { args, .. } = &e.kind {
+        for e in args {
+            walk(e, f)
+        }
+    }
+}
+
+fn constant_name(e: &Expr) -> Option<String> {
+    match &e.kind {
+        ExprKind::Constant(v) => v.clone().cast_to::<String>().ok(),
+        ExprKind::Apply { .. } => None,
+    }
+}
+
+#[derive(Default)]
+struct Lint {
+    problems: Vec<Problem>,
+    defined: FxHashSet<String>,
+    // function names, and where they are called
+    called: Vec<(String, usize)>,
+    // variable names, and where they are first set
+    set: FxHashMap<String, usize>,
+    read: FxHashSet<String>,
+    // true if a variable may be read by a name that is only known
+    // at run time, in which case unused variables can't be found.
+    dynamic_read: bool,
+}
+
+impl Lint {
+    fn problem(&mut self, path: &[i32], widget: &str, message: String) {
+        let path = path.to_vec();
+        self.problems.push(Problem { path, widget: widget.into(), message })
+    }
+
+    fn expr(&mut self, node: usize, path: &[i32], widget: &str, e: &Expr) {
+        walk(e, &mut |e| match &e.kind {
+            ExprKind::Constant(_) => (),
+            ExprKind::Apply { args, function } => {
+                self.called.push((function.clone(), node));
+                match function.as_str() {
+                    "get" => match args.get(0).and_then(constant_name) {
+                        Some(name) => {
+                            self.read.insert(name);
+                        }
+                        None => self.dynamic_read = true,
+                    },
+                    "eval" => self.dynamic_read = true,
+                    "set" | "let" => {
+                        if let Some(name) = args.get(0).and_then(constant_name) {
+                            self.set.entry(name).or_insert(node);
+                        }
+                    }
+                    "store" => match args.get(0).map(|e| &e.kind) {
+                        Some(ExprKind::Constant(v)) => {
+                            match v.clone().cast_to::<String>() {
+                                Ok(p) if Path::is_absolute(&p) => (),
+                                Ok(_) | Err(_) => {
+                                    let m = format!(
+                                        "store target {} is not an absolute path, \
+                                         it will never resolve",
+                                        v
+                                    );
+                                    self.problem(path, widget, m)
+                                }
+                            }
+                        }
+                        Some(ExprKind::Apply { .. }) | None => (),
+                    },
+                    _ => (),
+                }
+            }
+        })
+    }
+}
+
+/// Check the view in the editor tree for unknown functions, store
+/// targets that can never resolve, constants that can't be cast to
+/// the type a widget expects, and variables that are set but never
+/// read. Store targets computed at run time are not checked, and
+/// neither is whether anything is published at a target.
+fn lint(ctx: &BSCtx, store: &gtk::TreeStore) -> Vec<Problem> {
+    let mut lint = Lint::default();
+    let mut nodes: Vec<(Vec<i32>, String)> = Vec::new();
+    store.foreach(|_, path, iter| {
+        let node = nodes.len();
+        let path = path.indices();
+        let widget = store.value(iter, 0).get::<String>().unwrap_or_default();
+        if let Ok(w) = store.value(iter, 1).get::<&Widget>() {
+            let spec = w.spec();
+            if let Some(p) = &spec.props {
+                for f in &p.functions {
+                    lint.defined.insert(f.name.clone());
+                    lint.expr(node, &path, &widget, &f.body);
+                }
+            }
+            for (field, e, is_bool) in exprs(&spec) {
+                if let ExprKind::Constant(v) = &e.kind {
+                    if is_bool
+                        && v != &Value::Null
+                        && v.clone().cast_to::<bool>().is_err()
+                    {
+                        let m = format!("{} should be a bool, but {} is not", field, v);
+                        lint.problem(&path, &widget, m)
+                    }
+                }
+                lint.expr(node, &path, &widget, e);
+            }
+        }
+        nodes.push((path, widget));
+        false
+    });
+    let ctx = ctx.borrow();
+    let mut reported = FxHashSet::default();
+    for (function, node) in lint.called.drain(..) {
+        let known =
+            ctx.functions.contains_key(&function) || lint.defined.contains(&function);
+        if !known && reported.insert((function.clone(), node)) {
+            let (path, widget) = &nodes[node];
+            let m = format!("unknown function {}", function);
+            lint.problems.push(Problem {
+                path: path.clone(),
+                widget: widget.clone(),
+                message: m,
+            })
+        }
+    }
+    if !lint.dynamic_read {
+        let mut unused = lint
+            .set
+            .iter()
+            .filter(|(name, _)| !lint.read.contains(*name))
+            .map(|(name, node)| (*node, name.clone()))
+            .collect::<Vec<_>>();
+        unused.sort();
+        for (node, name) in unused {
+            let (path, widget) = &nodes[node];
+            let m = format!("variable {} is set but never read", name);
+            lint.problems.push(Problem {
+                path: path.clone(),
+                widget: widget.clone(),
+                message: m,
+            })
+        }
+    }
+    lint.problems.sort_by(|p0, p1| p0.path.cmp(&p1.path));
+    lint.problems
+}
+
+/// The problems found in the view, selecting a problem selects the
+/// widget it was found in.
+pub(super) struct Problems {
+    root: gtk::Expander,
+    list: gtk::ListStore,
+    found: Rc<RefCell<Vec<Problem>>>,
+}
+
+impl Problems {
+    pub(super) fn new(tree: &gtk::TreeView) -> Self {
+        let root = gtk::Expander::new(Some("Problems"));
+        expander_touch_enable(&root);
+        let win =
+            gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        win.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+        win.set_min_content_height(100);
+        root.add(&win);
+        let list = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
+        let view = gtk::TreeView::new();
+        win.add(&view);
+        text_columns(&view, &["widget", "problem"]);
+        view.set_model(Some(&list));
+        let found: Rc<RefCell<Vec<Problem>>> = Rc::new(RefCell::new(Vec::new()));
+        view.connect_row_activated(clone!(
+            @strong found, @weak tree => move |_, path, _| {
+                let found = found.borrow();
+                let problem = path.indices().first().and_then(|i| found.get(*i as usize));
+                if let Some(problem) = problem {
+                    let path = gtk::TreePath::from_indicesv(&problem.path);
+                    tree.expand_to_path(&path);
+                    tree.selection().select_path(&path);
+                    tree.scroll_to_cell(
+                        Some(&path),
+                        None::<&gtk::TreeViewColumn>,
+                        true,
+                        0.5,
+                        0.,
+                    );
+                }
+        }));
+        Problems { root, list, found }
+    }
+
+    pub(super) fn root(&self) -> &gtk::Widget {
+        self.root.upcast_ref()
+    }
+
+    /// Lint the view in `store`, and display the problems found.
+    pub(super) fn update(&self, ctx: &BSCtx, store: &gtk::TreeStore) {
+        let problems = lint(ctx, store);
+        self.list.clear();
+        for p in &problems {
+            let iter = self.list.append();
+            self.list.set_value(&iter, 0, &p.widget.to_value());
+            self.list.set_value(&iter, 1, &p.message.to_value());
+        }
+        self.root.set_label(Some(&format!("Problems ({})", problems.len())));
+        *self.found.borrow_mut() = problems;
+    }
+}
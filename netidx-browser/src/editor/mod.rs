@@ -1,10 +1,13 @@
 mod completion;
 mod expr_inspector;
+mod lint;
 mod util;
 mod widgets;
 use super::{default_view, BSCtx, WidgetPath, DEFAULT_PROPS};
 use glib::{clone, idle_add_local, prelude::*, GString};
 use gtk::{self, prelude::*};
+use indexmap::IndexMap;
+use log::warn;
 use netidx::{chars::Chars, path::Path, subscriber::Value};
 use netidx_bscript::expr;
 use netidx_protocols::view;
@@ -35,11 +38,42 @@ fn label_with_txt(text: &'static str) -> view::Widget {
     }
 }
 
+#[derive(Clone)]
+struct Keybind {
+    _dbg_expr: widgets::DbgExpr,
+    spec: Rc<RefCell<view::Keybind>>,
+}
+
+static ALIGNS: [&'static str; 5] = ["Fill", "Start", "End", "Center", "Baseline"];
+
+fn align_to_str(a: view::Align) -> &'static str {
+    match a {
+        view::Align::Fill => "Fill",
+        view::Align::Start => "Start",
+        view::Align::End => "End",
+        view::Align::Center => "Center",
+        view::Align::Baseline => "Baseline",
+    }
+}
+
+fn align_from_str(a: GString) -> view::Align {
+    match &*a {
+        "Fill" => view::Align::Fill,
+        "Start" => view::Align::Start,
+        "End" => view::Align::End,
+        "Center" => view::Align::Center,
+        "Baseline" => view::Align::Baseline,
+        x => unreachable!("{}", x),
+    }
+}
+
 #[derive(Clone)]
 struct WidgetProps {
     root: gtk::Expander,
     _dbg_sensitive: widgets::DbgExpr,
     _dbg_visible: widgets::DbgExpr,
+    _dbg_interlock: widgets::DbgExpr,
+    _keybinds: Rc<RefCell<IndexMap<usize, Keybind>>>,
     spec: Rc<RefCell<Option<view::WidgetProps>>>,
 }
 
@@ -52,7 +86,7 @@ impl WidgetProps {
     ) -> Self {
         let spec = Rc::new(RefCell::new(spec));
         let root = gtk::Expander::new(Some("Common Properties"));
-        let on_change = Rc::new({
+        let on_change: OnChange = Rc::new({
             let spec = spec.clone();
             move || {
                 let default = spec.borrow().as_ref() == Some(&DEFAULT_PROPS);
@@ -65,33 +99,13 @@ impl WidgetProps {
         util::expander_touch_enable(&root);
         let mut grid = TwoColGrid::new();
         root.add(grid.root());
-        let aligns = ["Fill", "Start", "End", "Center", "Baseline"];
-        fn align_to_str(a: view::Align) -> &'static str {
-            match a {
-                view::Align::Fill => "Fill",
-                view::Align::Start => "Start",
-                view::Align::End => "End",
-                view::Align::Center => "Center",
-                view::Align::Baseline => "Baseline",
-            }
-        }
-        fn align_from_str(a: GString) -> view::Align {
-            match &*a {
-                "Fill" => view::Align::Fill,
-                "Start" => view::Align::Start,
-                "End" => view::Align::End,
-                "Center" => view::Align::Center,
-                "Baseline" => view::Align::Baseline,
-                x => unreachable!("{}", x),
-            }
-        }
         let halign_lbl = gtk::Label::new(Some("Horizontal Alignment:"));
         let halign = gtk::ComboBoxText::new();
         let valign_lbl = gtk::Label::new(Some("Vertical Alignment:"));
         let valign = gtk::ComboBoxText::new();
         grid.add((halign_lbl.clone(), halign.clone()));
         grid.add((valign_lbl.clone(), valign.clone()));
-        for a in &aligns {
+        for a in &ALIGNS {
             halign.append(Some(a), a);
             valign.append(Some(a), a);
         }
@@ -139,6 +153,18 @@ impl WidgetProps {
             }
             on_change()
         }));
+        grid.add(parse_entry(
+            "Name:",
+            &spec.borrow().as_ref().unwrap_or(&DEFAULT_PROPS).name,
+            clone!(@strong spec, @strong on_change => move |s: String| {
+                {
+                    let mut spec = spec.borrow_mut();
+                    let spec = spec.get_or_insert(DEFAULT_PROPS.clone());
+                    spec.name = s;
+                }
+                on_change()
+            }),
+        ));
         grid.add(parse_entry(
             "Top Margin:",
             &spec.borrow().as_ref().unwrap_or(&DEFAULT_PROPS).margin_top,
@@ -217,7 +243,246 @@ impl WidgetProps {
             }),
         );
         grid.add((l, e));
-        WidgetProps { root, spec, _dbg_sensitive, _dbg_visible }
+        let (l, e, _dbg_interlock) = widgets::expr(
+            ctx,
+            "Interlock:",
+            scope.clone(),
+            &spec.borrow().as_ref().unwrap_or(&DEFAULT_PROPS).interlock,
+            clone!(@strong spec, @strong on_change => move |e| {
+                {
+                    let mut spec = spec.borrow_mut();
+                    let spec = spec.get_or_insert(DEFAULT_PROPS.clone());
+                    spec.interlock = e;
+                }
+                on_change()
+            }),
+        );
+        grid.add((l, e));
+        grid.add(parse_entry(
+            "Refresh Rate (Hz, 0 unlimited):",
+            &spec.borrow().as_ref().unwrap_or(&DEFAULT_PROPS).refresh_rate.unwrap_or(0),
+            clone!(@strong spec, @strong on_change => move |s: u32| {
+                {
+                    let mut spec = spec.borrow_mut();
+                    let spec = spec.get_or_insert(DEFAULT_PROPS.clone());
+                    spec.refresh_rate = if s == 0 { None } else { Some(s) };
+                }
+                on_change()
+            }),
+        ));
+        let _keybinds = WidgetProps::build_keybinds_editor(
+            ctx,
+            &mut grid,
+            &on_change,
+            scope.clone(),
+            &spec,
+        );
+        WidgetProps::build_functions_editor(&mut grid, &on_change, &spec);
+        WidgetProps::build_css_editor(&mut grid, &on_change, &spec);
+        WidgetProps {
+            root,
+            spec,
+            _dbg_sensitive,
+            _dbg_visible,
+            _dbg_interlock,
+            _keybinds,
+        }
+    }
+
+    fn build_css_editor(
+        grid: &mut TwoColGrid,
+        on_change: &OnChange,
+        spec: &Rc<RefCell<Option<view::WidgetProps>>>,
+    ) {
+        let css_exp = gtk::Expander::new(Some("CSS"));
+        util::expander_touch_enable(&css_exp);
+        let view = gtk::TextView::new();
+        view.set_monospace(true);
+        view.set_size_request(-1, 100);
+        css_exp.add(&view);
+        grid.attach(&css_exp, 0, 2, 1);
+        if let Some(buf) = view.buffer() {
+            buf.set_text(&spec.borrow().as_ref().unwrap_or(&DEFAULT_PROPS).css);
+            buf.connect_changed(clone!(
+                @strong spec, @strong on_change => move |buf: &gtk::TextBuffer| {
+                if let Some(text) = buf.slice(&buf.start_iter(), &buf.end_iter(), false) {
+                    {
+                        let mut spec = spec.borrow_mut();
+                        let spec = spec.get_or_insert(DEFAULT_PROPS.clone());
+                        spec.css = text.to_string();
+                    }
+                    on_change()
+                }
+            }));
+        }
+    }
+
+    fn build_functions_editor(
+        grid: &mut TwoColGrid,
+        on_change: &OnChange,
+        spec: &Rc<RefCell<Option<view::WidgetProps>>>,
+    ) {
+        let functions_exp = gtk::Expander::new(Some("Functions"));
+        util::expander_touch_enable(&functions_exp);
+        let functionsbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        let addbtn = gtk::Button::with_label("+");
+        functions_exp.add(&functionsbox);
+        grid.attach(&functions_exp, 0, 2, 1);
+        let function_id = Rc::new(Cell::new(0));
+        let functions: Rc<RefCell<IndexMap<usize, expr::FnDef>>> =
+            Rc::new(RefCell::new(IndexMap::new()));
+        let on_change = Rc::new(clone!(
+        @strong functions, @strong on_change, @strong spec => move || {
+            {
+                let mut spec = spec.borrow_mut();
+                let spec = spec.get_or_insert(DEFAULT_PROPS.clone());
+                spec.functions.clear();
+                spec.functions.extend(functions.borrow().values().cloned());
+            }
+            on_change()
+        }));
+        functionsbox.pack_start(&addbtn, false, false, 0);
+        let build_function = Rc::new(clone!(
+            @weak functionsbox,
+            @strong on_change,
+            @strong functions => move |def: expr::FnDef| {
+                let i = function_id.get();
+                function_id.set(i + 1);
+                let mut grid = TwoColGrid::new();
+                functionsbox.pack_start(grid.root(), false, false, 0);
+                let sep = gtk::Separator::new(gtk::Orientation::Vertical);
+                grid.attach(&sep, 0, 2, 1);
+                grid.add(parse_entry(
+                    "Definition:",
+                    &def,
+                    clone!(@strong functions, @strong on_change => move |def| {
+                        functions.borrow_mut().insert(i, def);
+                        on_change()
+                    })
+                ));
+                let remove = gtk::Button::with_label("-");
+                grid.attach(&remove, 0, 2, 1);
+                functions.borrow_mut().insert(i, def);
+                functionsbox.show_all();
+                let grid_root = grid.root();
+                remove.connect_clicked(clone!(
+                    @strong functions,
+                    @weak grid_root,
+                    @weak functionsbox,
+                    @strong on_change => move |_| {
+                        grid_root.hide();
+                        for c in functionsbox.children() {
+                            if c == grid_root {
+                                functionsbox.remove(&c);
+                            }
+                        }
+                        functions.borrow_mut().remove(&i);
+                        on_change()
+                    }));
+        }));
+        addbtn.connect_clicked(clone!(@strong build_function => move |_| {
+            build_function(expr::FnDef {
+                name: String::from("f"),
+                args: vec![String::from("x")],
+                body: ce(Value::Null),
+            })
+        }));
+        let init = spec.borrow().as_ref().map(|s| s.functions.clone());
+        for def in init.unwrap_or_default() {
+            build_function(def)
+        }
+    }
+
+    fn build_keybinds_editor(
+        ctx: &BSCtx,
+        grid: &mut TwoColGrid,
+        on_change: &OnChange,
+        scope: Scope,
+        spec: &Rc<RefCell<Option<view::WidgetProps>>>,
+    ) -> Rc<RefCell<IndexMap<usize, Keybind>>> {
+        let keybinds_exp = gtk::Expander::new(Some("Keybinds"));
+        util::expander_touch_enable(&keybinds_exp);
+        let keybindsbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        let addbtn = gtk::Button::with_label("+");
+        keybinds_exp.add(&keybindsbox);
+        grid.attach(&keybinds_exp, 0, 2, 1);
+        let keybind_id = Rc::new(Cell::new(0));
+        let keybinds: Rc<RefCell<IndexMap<usize, Keybind>>> =
+            Rc::new(RefCell::new(IndexMap::new()));
+        let on_change = Rc::new(clone!(
+        @strong keybinds, @strong on_change, @strong spec => move || {
+            {
+                let mut spec = spec.borrow_mut();
+                let spec = spec.get_or_insert(DEFAULT_PROPS.clone());
+                spec.keybinds.clear();
+                let kbs = keybinds.borrow();
+                spec.keybinds.extend(kbs.values().map(|k| k.spec.borrow().clone()));
+            }
+            on_change()
+        }));
+        keybindsbox.pack_start(&addbtn, false, false, 0);
+        let build_keybind = Rc::new(clone!(
+            @weak keybindsbox,
+            @strong ctx,
+            @strong on_change,
+            @strong keybinds => move |spec: view::Keybind| {
+                let spec = Rc::new(RefCell::new(spec));
+                let mut grid = TwoColGrid::new();
+                keybindsbox.pack_start(grid.root(), false, false, 0);
+                let sep = gtk::Separator::new(gtk::Orientation::Vertical);
+                grid.attach(&sep, 0, 2, 1);
+                grid.add(parse_entry(
+                    "Key:",
+                    &spec.borrow().key,
+                    clone!(@strong spec, @strong on_change => move |s| {
+                        spec.borrow_mut().key = s;
+                        on_change()
+                    })
+                ));
+                let (l, e, _dbg_expr) = widgets::expr(
+                    &ctx,
+                    "Action:",
+                    scope.clone(),
+                    &spec.borrow().expr,
+                    clone!(@strong spec, @strong on_change => move |e| {
+                        spec.borrow_mut().expr = e;
+                        on_change()
+                    })
+                );
+                grid.add((l, e));
+                let remove = gtk::Button::with_label("-");
+                grid.attach(&remove, 0, 2, 1);
+                let i = keybind_id.get();
+                keybind_id.set(i + 1);
+                keybinds.borrow_mut().insert(i, Keybind { _dbg_expr, spec });
+                keybindsbox.show_all();
+                let grid_root = grid.root();
+                remove.connect_clicked(clone!(
+                    @strong keybinds,
+                    @weak grid_root,
+                    @weak keybindsbox,
+                    @strong on_change => move |_| {
+                        grid_root.hide();
+                        for c in keybindsbox.children() {
+                            if c == grid_root {
+                                keybindsbox.remove(&c);
+                            }
+                        }
+                        keybinds.borrow_mut().remove(&i);
+                        on_change()
+                    }));
+        }));
+        addbtn.connect_clicked(clone!(@strong build_keybind => move |_| {
+            build_keybind(view::Keybind {
+                key: String::from("ctrl+k"),
+                expr: ce(Value::Null),
+            })
+        }));
+        let init = spec.borrow().as_ref().map(|s| s.keybinds.clone());
+        for k in init.unwrap_or_default() {
+            build_keybind(k)
+        }
+        keybinds
     }
 
     fn root(&self) -> &gtk::Widget {
@@ -240,6 +505,9 @@ enum WidgetKind {
     ToggleButton(widgets::ToggleButton),
     CheckButton(widgets::ToggleButton),
     Scale(widgets::Scale),
+    SpinButton(widgets::SpinButton),
+    Calendar(widgets::Calendar),
+    RadioGroup(widgets::RadioGroup),
     ProgressBar(widgets::ProgressBar),
     Switch(widgets::Switch),
     ComboBox(widgets::ComboBox),
@@ -255,6 +523,8 @@ enum WidgetKind {
     Paned(widgets::Paned),
     Notebook(widgets::Notebook),
     NotebookPage(widgets::NotebookPage),
+    Responsive,
+    Breakpoint(widgets::Breakpoint),
     GridRow,
 }
 
@@ -274,6 +544,9 @@ impl WidgetKind {
             WidgetKind::ComboBox(w) => Some(w.root()),
             WidgetKind::RadioButton(w) => Some(w.root()),
             WidgetKind::Scale(w) => Some(w.root()),
+            WidgetKind::SpinButton(w) => Some(w.root()),
+            WidgetKind::Calendar(w) => Some(w.root()),
+            WidgetKind::RadioGroup(w) => Some(w.root()),
             WidgetKind::Entry(w) => Some(w.root()),
             WidgetKind::SearchEntry(w) => Some(w.root()),
             WidgetKind::LinePlot(w) => Some(w.root()),
@@ -285,7 +558,8 @@ impl WidgetKind {
             WidgetKind::Paned(w) => Some(w.root()),
             WidgetKind::Notebook(w) => Some(w.root()),
             WidgetKind::NotebookPage(w) => Some(w.root()),
-            WidgetKind::GridRow => None,
+            WidgetKind::Breakpoint(w) => Some(w.root()),
+            WidgetKind::Responsive | WidgetKind::GridRow => None,
         }
     }
 }
@@ -432,6 +706,36 @@ impl Widget {
                 )),
                 Some(WidgetProps::new(ctx, scope.clone(), on_change, props)),
             ),
+            view::Widget { props, kind: view::WidgetKind::SpinButton(s) } => (
+                "SpinButton",
+                WidgetKind::SpinButton(widgets::SpinButton::new(
+                    ctx,
+                    on_change.clone(),
+                    scope.clone(),
+                    s,
+                )),
+                Some(WidgetProps::new(ctx, scope.clone(), on_change, props)),
+            ),
+            view::Widget { props, kind: view::WidgetKind::Calendar(s) } => (
+                "Calendar",
+                WidgetKind::Calendar(widgets::Calendar::new(
+                    ctx,
+                    on_change.clone(),
+                    scope.clone(),
+                    s,
+                )),
+                Some(WidgetProps::new(ctx, scope.clone(), on_change, props)),
+            ),
+            view::Widget { props, kind: view::WidgetKind::RadioGroup(s) } => (
+                "RadioGroup",
+                WidgetKind::RadioGroup(widgets::RadioGroup::new(
+                    ctx,
+                    on_change.clone(),
+                    scope.clone(),
+                    s,
+                )),
+                Some(WidgetProps::new(ctx, scope.clone(), on_change, props)),
+            ),
             view::Widget { props, kind: view::WidgetKind::ProgressBar(s) } => (
                 "ProgressBar",
                 WidgetKind::ProgressBar(widgets::ProgressBar::new(
@@ -531,6 +835,20 @@ impl Widget {
                 )),
                 None,
             ),
+            view::Widget { props, kind: view::WidgetKind::Responsive(_) } => (
+                "Responsive",
+                WidgetKind::Responsive,
+                Some(WidgetProps::new(ctx, scope.clone(), on_change, props)),
+            ),
+            view::Widget { props: _, kind: view::WidgetKind::Breakpoint(s) } => (
+                "Breakpoint",
+                WidgetKind::Breakpoint(widgets::Breakpoint::new(
+                    on_change.clone(),
+                    scope.clone(),
+                    s,
+                )),
+                None,
+            ),
             view::Widget { props, kind: view::WidgetKind::LinePlot(s) } => (
                 "LinePlot",
                 WidgetKind::LinePlot(widgets::LinePlot::new(
@@ -576,6 +894,9 @@ impl Widget {
             WidgetKind::Switch(w) => view::WidgetKind::Switch(w.spec()),
             WidgetKind::ComboBox(w) => view::WidgetKind::ComboBox(w.spec()),
             WidgetKind::Scale(w) => view::WidgetKind::Scale(w.spec()),
+            WidgetKind::SpinButton(w) => view::WidgetKind::SpinButton(w.spec()),
+            WidgetKind::Calendar(w) => view::WidgetKind::Calendar(w.spec()),
+            WidgetKind::RadioGroup(w) => view::WidgetKind::RadioGroup(w.spec()),
             WidgetKind::ProgressBar(w) => view::WidgetKind::ProgressBar(w.spec()),
             WidgetKind::Entry(w) => view::WidgetKind::Entry(w.spec()),
             WidgetKind::SearchEntry(w) => view::WidgetKind::SearchEntry(w.spec()),
@@ -588,6 +909,10 @@ impl Widget {
             WidgetKind::Paned(w) => view::WidgetKind::Paned(w.spec()),
             WidgetKind::Notebook(w) => view::WidgetKind::Notebook(w.spec()),
             WidgetKind::NotebookPage(w) => view::WidgetKind::NotebookPage(w.spec()),
+            WidgetKind::Responsive => {
+                view::WidgetKind::Responsive(view::Responsive { children: vec![] })
+            }
+            WidgetKind::Breakpoint(w) => view::WidgetKind::Breakpoint(w.spec()),
             WidgetKind::GridRow => {
                 view::WidgetKind::GridRow(view::GridRow { columns: vec![] })
             }
@@ -723,8 +1048,34 @@ impl Widget {
                 value: ce((0f64).into()),
                 min: ce((0f64).into()),
                 max: ce((1f64).into()),
+                step: ce(Value::Null),
                 on_change: ce(Value::Null),
             })),
+            Some("SpinButton") => {
+                widget(view::WidgetKind::SpinButton(view::SpinButton {
+                    value: ce((0f64).into()),
+                    min: ce((0f64).into()),
+                    max: ce((100f64).into()),
+                    step: ce((1f64).into()),
+                    digits: ce(Value::U32(0)),
+                    on_change: ce(Value::Null),
+                }))
+            }
+            Some("Calendar") => widget(view::WidgetKind::Calendar(view::Calendar {
+                value: ce(Value::Null),
+                on_change: ce(Value::Null),
+            })),
+            Some("RadioGroup") => {
+                widget(view::WidgetKind::RadioGroup(view::RadioGroup {
+                    direction: view::Direction::Vertical,
+                    choices: ce(Value::from(vec![
+                        Value::from(vec![Value::from("a"), Value::from("Choice A")]),
+                        Value::from(vec![Value::from("b"), Value::from("Choice B")]),
+                    ])),
+                    selected: ce(Value::from("a")),
+                    on_change: ce(Value::Null),
+                }))
+            }
             Some("ProgressBar") => {
                 widget(view::WidgetKind::ProgressBar(view::ProgressBar {
                     ellipsize: ce("none".into()),
@@ -815,6 +1166,9 @@ impl Widget {
                     y_max: ce(Value::Null),
                     keep_points: ce(Value::U64(256)),
                     series: Vec::new(),
+                    zoom: true,
+                    cursor: true,
+                    on_cursor: ce(Value::Null),
                 });
                 view::Widget { kind, props }
             }
@@ -872,6 +1226,20 @@ impl Widget {
                 page: ce(Value::Null),
                 on_switch_page: ce(Value::Null),
             })),
+            Some("Breakpoint") => {
+                widget(view::WidgetKind::Breakpoint(view::Breakpoint {
+                    min_width: None,
+                    max_width: Some(1000),
+                    min_height: None,
+                    max_height: None,
+                    widget: boxed::Box::new(label_with_txt("empty layout")),
+                }))
+            }
+            Some("Responsive") => {
+                widget(view::WidgetKind::Responsive(view::Responsive {
+                    children: vec![],
+                }))
+            }
             _ => unreachable!(),
         }
     }
@@ -894,6 +1262,9 @@ impl Widget {
             | WidgetKind::Switch(_)
             | WidgetKind::ComboBox(_)
             | WidgetKind::Scale(_)
+            | WidgetKind::SpinButton(_)
+            | WidgetKind::Calendar(_)
+            | WidgetKind::RadioGroup(_)
             | WidgetKind::ProgressBar(_)
             | WidgetKind::Entry(_)
             | WidgetKind::SearchEntry(_)
@@ -906,16 +1277,158 @@ impl Widget {
             | WidgetKind::Paned(_)
             | WidgetKind::Notebook(_)
             | WidgetKind::NotebookPage(_)
+            | WidgetKind::Responsive
+            | WidgetKind::Breakpoint(_)
             | WidgetKind::GridRow => (),
         }
     }
 }
 
-static KINDS: [&'static str; 25] = [
+/// Edits the common properties of several selected widgets at
+/// once. Each control starts with the value the widgets share, or
+/// blank if they differ, and a change is applied to every selected
+/// widget that has common properties, leaving the others alone.
+struct BulkProps {
+    root: gtk::Box,
+}
+
+impl BulkProps {
+    fn new(
+        ctx: &BSCtx,
+        on_change: OnChange,
+        store: &gtk::TreeStore,
+        iters: Vec<gtk::TreeIter>,
+    ) -> Self {
+        let specs = iters
+            .iter()
+            .filter_map(|iter| {
+                let v = store.value(iter, 1);
+                let w = v.get::<&Widget>().ok()?;
+                let props = w.props.as_ref()?;
+                Some(props.spec().unwrap_or_else(|| DEFAULT_PROPS.clone()))
+            })
+            .collect::<Vec<_>>();
+        // the value of a property if all the widgets agree on it
+        fn shared<T: PartialEq>(
+            specs: &[view::WidgetProps],
+            f: impl Fn(&view::WidgetProps) -> T,
+        ) -> Option<T> {
+            let mut vals = specs.iter().map(f);
+            let first = vals.next()?;
+            vals.all(|v| v == first).then(|| first)
+        }
+        let apply = Rc::new({
+            let ctx = ctx.clone();
+            let store = store.clone();
+            move |f: &dyn Fn(&mut view::WidgetProps)| {
+                BulkProps::apply(&ctx, &on_change, &store, &iters, f)
+            }
+        });
+        let root = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        let lbl = gtk::Label::new(Some(&format!(
+            "Editing the common properties of {} widgets",
+            specs.len()
+        )));
+        root.pack_start(&lbl, false, false, 0);
+        let mut grid = TwoColGrid::new();
+        root.pack_start(grid.root(), false, false, 0);
+        let halign = gtk::ComboBoxText::new();
+        let valign = gtk::ComboBoxText::new();
+        for a in &ALIGNS {
+            halign.append(Some(a), a);
+            valign.append(Some(a), a);
+        }
+        halign.set_active_id(shared(&specs, |p| p.halign).map(align_to_str));
+        valign.set_active_id(shared(&specs, |p| p.valign).map(align_to_str));
+        halign.connect_changed(clone!(@strong apply => move |c| {
+            if let Some(a) = c.active_id().map(align_from_str) {
+                apply(&|p| p.halign = a)
+            }
+        }));
+        valign.connect_changed(clone!(@strong apply => move |c| {
+            if let Some(a) = c.active_id().map(align_from_str) {
+                apply(&|p| p.valign = a)
+            }
+        }));
+        grid.add((gtk::Label::new(Some("Horizontal Alignment:")), halign));
+        grid.add((gtk::Label::new(Some("Vertical Alignment:")), valign));
+        type Get = fn(&view::WidgetProps) -> u32;
+        type Set = fn(&mut view::WidgetProps, u32);
+        let margins: [(&str, Get, Set); 4] = [
+            ("Top Margin:", |p| p.margin_top, |p, m| p.margin_top = m),
+            ("Bottom Margin:", |p| p.margin_bottom, |p, m| p.margin_bottom = m),
+            ("Start Margin:", |p| p.margin_start, |p, m| p.margin_start = m),
+            ("End Margin:", |p| p.margin_end, |p, m| p.margin_end = m),
+        ];
+        for (name, get, set) in margins {
+            let cur = shared(&specs, get);
+            let (l, e) = parse_entry(
+                name,
+                &cur.unwrap_or(0),
+                clone!(@strong apply => move |m: u32| apply(&|p| set(p, m))),
+            );
+            if cur.is_none() {
+                BulkProps::mixed(&e);
+            }
+            grid.add((l, e));
+        }
+        let cur = shared(&specs, |p| p.visible.clone());
+        let (l, e) = parse_entry(
+            "Visible:",
+            cur.as_ref().unwrap_or(&DEFAULT_PROPS.visible),
+            clone!(@strong apply => move |e: expr::Expr| {
+                apply(&|p| p.visible = e.clone())
+            }),
+        );
+        if cur.is_none() {
+            BulkProps::mixed(&e);
+        }
+        grid.add((l, e));
+        BulkProps { root }
+    }
+
+    /// blank an entry because the widgets have different values
+    fn mixed(e: &gtk::Entry) {
+        e.set_text("");
+        e.set_placeholder_text(Some("(mixed)"));
+        e.set_icon_from_icon_name(gtk::EntryIconPosition::Secondary, None);
+    }
+
+    fn apply(
+        ctx: &BSCtx,
+        on_change: &OnChange,
+        store: &gtk::TreeStore,
+        iters: &[gtk::TreeIter],
+        f: &dyn Fn(&mut view::WidgetProps),
+    ) {
+        for iter in iters {
+            let v = store.value(iter, 1);
+            let (scope, mut spec) = match v.get::<&Widget>() {
+                Ok(w) if w.props.is_some() => (w.scope.borrow().clone(), w.spec()),
+                Ok(_) | Err(_) => continue,
+            };
+            let props = spec.props.get_or_insert_with(|| DEFAULT_PROPS.clone());
+            f(props);
+            if spec.props.as_ref() == Some(&*DEFAULT_PROPS) {
+                spec.props = None;
+            }
+            Widget::insert(ctx, on_change.clone(), store, iter, scope, spec);
+        }
+        on_change()
+    }
+
+    fn root(&self) -> &gtk::Widget {
+        self.root.upcast_ref()
+    }
+}
+
+static KINDS: [&'static str; 28] = [
     "Box",
     "BoxChild",
+    "Breakpoint",
     "BScript",
     "Button",
+    "Calendar",
     "CheckButton",
     "ComboBox",
     "Entry",
@@ -931,14 +1444,65 @@ static KINDS: [&'static str; 25] = [
     "NotebookPage",
     "Paned",
     "ProgressBar",
+    "Responsive",
     "RadioButton",
+    "RadioGroup",
     "Scale",
     "SearchEntry",
+    "SpinButton",
     "Switch",
     "Table",
     "ToggleButton",
 ];
 
+/// The drag and drop target used to drag widget kinds from the
+/// palette into the widget tree
+static PALETTE_TARGET: &'static str = "netidx-browser/widget-kind";
+
+fn kind_icon(kind: &str) -> &'static str {
+    match kind {
+        "Box" => "format-justify-fill-symbolic",
+        "BoxChild" | "GridChild" => "format-indent-more-symbolic",
+        "Breakpoint" => "zoom-fit-best-symbolic",
+        "BScript" => "utilities-terminal-symbolic",
+        "Button" => "input-mouse-symbolic",
+        "Calendar" => "x-office-calendar-symbolic",
+        "CheckButton" => "checkbox-checked-symbolic",
+        "ComboBox" => "pan-down-symbolic",
+        "Entry" => "insert-text-symbolic",
+        "Frame" => "window-maximize-symbolic",
+        "Grid" => "view-grid-symbolic",
+        "GridRow" => "view-list-symbolic",
+        "Image" => "image-x-generic-symbolic",
+        "Label" => "font-x-generic-symbolic",
+        "LinePlot" => "utilities-system-monitor-symbolic",
+        "LinkButton" => "insert-link-symbolic",
+        "Notebook" => "tab-new-symbolic",
+        "NotebookPage" => "document-new-symbolic",
+        "Paned" => "view-dual-symbolic",
+        "ProgressBar" => "content-loading-symbolic",
+        "RadioButton" => "radio-checked-symbolic",
+        "RadioGroup" => "view-list-bullet-symbolic",
+        "Responsive" => "view-restore-symbolic",
+        "Scale" => "multimedia-volume-control-symbolic",
+        "SearchEntry" => "edit-find-symbolic",
+        "SpinButton" => "value-increase-symbolic",
+        "Switch" => "emblem-ok-symbolic",
+        "Table" => "x-office-spreadsheet-symbolic",
+        "ToggleButton" => "view-pin-symbolic",
+        _ => "image-missing",
+    }
+}
+
+/// Which match to select when searching the widget tree
+#[derive(Clone, Copy)]
+enum SearchDir {
+    /// the selected widget if it matches, otherwise the next match
+    Here,
+    Forward,
+    Backward,
+}
+
 pub(super) struct Editor {
     root: gtk::Paned,
 }
@@ -989,37 +1553,80 @@ impl Editor {
             gtk::IconSize::SmallToolbar,
         );
         let undobtn = gtk::ToolButton::new(Some(&undobtnicon), None);
+        let palbtnicon = gtk::Image::from_icon_name(
+            Some("view-app-grid-symbolic"),
+            gtk::IconSize::SmallToolbar,
+        );
+        let palbtn = gtk::ToggleToolButton::new();
+        palbtn.set_icon_widget(Some(&palbtnicon));
+        palbtn.set_tooltip_text(Some("show the widget palette"));
+        palbtn.set_active(true);
         treebtns.pack_start(&addbtn, false, false, 5);
         treebtns.pack_start(&addchbtn, false, false, 5);
         treebtns.pack_start(&delbtn, false, false, 5);
         treebtns.pack_start(&dupbtn, false, false, 5);
         treebtns.pack_start(&undobtn, false, false, 5);
+        treebtns.pack_start(&palbtn, false, false, 5);
+        let search = gtk::SearchEntry::new();
+        search.set_placeholder_text(Some("find kind, expression, or path"));
+        search.set_tooltip_text(Some(
+            "matching widgets are shown in bold, enter or ctrl-g jumps to the next match",
+        ));
+        treebtns.pack_end(&search, true, true, 5);
         let treewin =
             gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
         treewin.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
-        root_upper.pack_start(&treewin, true, true, 5);
+        let treepane = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        root_upper.pack_start(&treepane, true, true, 5);
+        let reveal_palette = gtk::Revealer::new();
+        reveal_palette.set_transition_type(gtk::RevealerTransitionType::SlideRight);
+        reveal_palette.set_reveal_child(true);
+        palbtn.connect_toggled(clone!(@weak reveal_palette => move |b| {
+            reveal_palette.set_reveal_child(b.is_active())
+        }));
+        let palettewin =
+            gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        palettewin.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        reveal_palette.add(&palettewin);
+        let palette = gtk::FlowBox::new();
+        palette.set_selection_mode(gtk::SelectionMode::None);
+        palette.set_homogeneous(true);
+        palette.set_min_children_per_line(2);
+        palette.set_max_children_per_line(2);
+        palette.set_valign(gtk::Align::Start);
+        palettewin.add(&palette);
+        treepane.pack_start(&reveal_palette, false, false, 0);
+        treepane.pack_start(&treewin, true, true, 0);
         let view = gtk::TreeView::new();
         treewin.add(&view);
+        let problems = Rc::new(lint::Problems::new(&view));
+        root_upper.pack_start(problems.root(), false, false, 0);
         view.append_column(&{
             let column = gtk::TreeViewColumn::new();
             let cell = gtk::CellRendererText::new();
+            cell.set_weight(700);
             CellLayoutExt::pack_start(&column, &cell, true);
             column.set_title("widget name");
             CellLayoutExt::add_attribute(&column, &cell, "text", 0);
+            CellLayoutExt::add_attribute(&column, &cell, "weight-set", 3);
             column
         });
         view.append_column(&{
             let column = gtk::TreeViewColumn::new();
             let cell = gtk::CellRendererText::new();
+            cell.set_weight(700);
             CellLayoutExt::pack_start(&column, &cell, true);
             column.set_title("desc");
             CellLayoutExt::add_attribute(&column, &cell, "text", 2);
+            CellLayoutExt::add_attribute(&column, &cell, "weight-set", 3);
             column
         });
+        // name, widget, description, matches the search
         let store = gtk::TreeStore::new(&[
             String::static_type(),
             Widget::static_type(),
             String::static_type(),
+            bool::static_type(),
         ]);
         view.set_model(Some(&store));
         view.set_reorderable(true);
@@ -1036,6 +1643,7 @@ impl Editor {
             let scheduled = Rc::new(Cell::new(false));
             let undo_stack = undo_stack.clone();
             let undoing = undoing.clone();
+            let problems = problems.clone();
             move || {
                 if !scheduled.get() {
                     scheduled.set(true);
@@ -1046,7 +1654,8 @@ impl Editor {
                         @strong store,
                         @strong scheduled,
                         @strong undo_stack,
-                        @strong undoing => move || {
+                        @strong undoing,
+                        @strong problems => move || {
                             if let Some(root) = store.iter_first() {
                                 if undoing.get() {
                                     undoing.set(false)
@@ -1057,6 +1666,7 @@ impl Editor {
                                 *spec.borrow_mut() =
                                     Editor::build_spec(&store, &root);
                                 ctx.borrow().user.backend.render(spec.borrow().clone());
+                                problems.update(&ctx, &store);
                             }
                             scheduled.set(false);
                             glib::Continue(false)
@@ -1072,6 +1682,7 @@ impl Editor {
             None,
             &*spec.borrow(),
         );
+        problems.update(&ctx, &store);
         let selected: Rc<RefCell<Option<gtk::TreeIter>>> = Rc::new(RefCell::new(None));
         let reveal_properties = gtk::Revealer::new();
         root_lower.pack_start(&reveal_properties, true, true, 5);
@@ -1128,10 +1739,13 @@ impl Editor {
             false,
             0,
         );
+        // ctrl or shift click selects several widgets, their common
+        // properties can then be edited together
         let selection = view.selection();
-        selection.set_mode(gtk::SelectionMode::Single);
+        selection.set_mode(gtk::SelectionMode::Multiple);
         selection.connect_changed(clone!(
             @strong ctx,
+            @strong on_change,
             @strong selected,
             @weak store,
             @weak kind,
@@ -1146,13 +1760,26 @@ impl Editor {
                         properties.remove(&children[2]);
                     }
                 }
-                match s.selected() {
+                let (rows, _) = s.selected_rows();
+                let mut iters =
+                    rows.iter().filter_map(|p| store.iter(p)).collect::<Vec<_>>();
+                match iters.pop() {
                     None => {
                         *selected.borrow_mut() = None;
                         ctx.borrow().user.backend.highlight(vec![]);
                         reveal_properties.set_reveal_child(false);
                     }
-                    Some((_, iter)) => {
+                    Some(iter) if !iters.is_empty() => {
+                        *selected.borrow_mut() = None;
+                        ctx.borrow().user.backend.highlight(vec![]);
+                        iters.push(iter);
+                        let bulk = BulkProps::new(&ctx, on_change.clone(), &store, iters);
+                        properties.pack_start(bulk.root(), true, true, 5);
+                        properties.show_all();
+                        kind.hide();
+                        reveal_properties.set_reveal_child(true);
+                    }
+                    Some(iter) => {
                         *selected.borrow_mut() = Some(iter.clone());
                         let mut path = Vec::new();
                         Editor::build_widget_path(&store, &iter, 0, 0, &mut path);
@@ -1179,11 +1806,19 @@ impl Editor {
         let new_sib = gtk::MenuItem::with_label("New Sibling");
         let new_child = gtk::MenuItem::with_label("New Child");
         let delete = gtk::MenuItem::with_label("Delete");
+        let cut = gtk::MenuItem::with_label("Cut");
+        let copy = gtk::MenuItem::with_label("Copy");
+        let paste = gtk::MenuItem::with_label("Paste");
+        let paste_child = gtk::MenuItem::with_label("Paste as Child");
         let undo = gtk::MenuItem::with_label("Undo");
         menu.append(&duplicate);
         menu.append(&new_sib);
         menu.append(&new_child);
         menu.append(&delete);
+        menu.append(&cut);
+        menu.append(&copy);
+        menu.append(&paste);
+        menu.append(&paste_child);
         menu.append(&undo);
         let dup = Rc::new(clone!(
             @strong scope,
@@ -1240,6 +1875,117 @@ impl Editor {
         }));
         new_child.connect_activate(clone!(@strong newch => move |_| newch()));
         addchbtn.connect_clicked(clone!(@strong newch => move |_| newch()));
+        let add_kind = Rc::new(clone!(
+            @strong scope,
+            @strong on_change,
+            @weak store,
+            @weak view,
+            @strong ctx => move |iter: gtk::TreeIter, kind: &str| {
+                let spec = Widget::default_spec(Some(kind));
+                Widget::insert(
+                    &ctx,
+                    on_change.clone(),
+                    &store,
+                    &iter,
+                    scope.clone(),
+                    spec
+                );
+                if let Some(path) = store.path(&iter) {
+                    view.expand_to_path(&path);
+                    view.selection().unselect_all();
+                    view.selection().select_path(&path);
+                }
+                on_change();
+        }));
+        // palette items can be dragged to a specific place in the
+        // tree, or clicked to add a sibling after the selection
+        let palette_target =
+            gtk::TargetEntry::new(PALETTE_TARGET, gtk::TargetFlags::SAME_APP, 0);
+        for k in &KINDS {
+            let k: &'static str = k;
+            let item = gtk::Box::new(gtk::Orientation::Vertical, 2);
+            let icon = gtk::Image::from_icon_name(
+                Some(kind_icon(k)),
+                gtk::IconSize::LargeToolbar,
+            );
+            item.pack_start(&icon, false, false, 0);
+            item.pack_start(&gtk::Label::new(Some(k)), false, false, 0);
+            let btn = gtk::Button::new();
+            btn.set_relief(gtk::ReliefStyle::None);
+            btn.set_tooltip_text(Some(&format!(
+                "drag into the tree to add a {}, or click to add it after the selection",
+                k
+            )));
+            btn.add(&item);
+            btn.drag_source_set(
+                gdk::ModifierType::BUTTON1_MASK,
+                &[palette_target.clone()],
+                gdk::DragAction::COPY,
+            );
+            btn.drag_source_set_icon_name(kind_icon(k));
+            btn.connect_drag_data_get(move |_, _, sel, _, _| {
+                sel.set(&sel.target(), 8, k.as_bytes());
+            });
+            btn.connect_clicked(clone!(
+                @weak store,
+                @strong selected,
+                @strong add_kind => move |_| {
+                    let iter = store.insert_after(None, selected.borrow().as_ref());
+                    add_kind(iter, k)
+            }));
+            palette.add(&btn);
+        }
+        // set_reorderable only accepts rows from the tree itself, add
+        // the palette target alongside it
+        view.enable_model_drag_dest(
+            &[
+                gtk::TargetEntry::new(
+                    "GTK_TREE_MODEL_ROW",
+                    gtk::TargetFlags::SAME_WIDGET,
+                    0,
+                ),
+                palette_target,
+            ],
+            gdk::DragAction::MOVE | gdk::DragAction::COPY,
+        );
+        view.connect_drag_data_received(clone!(
+            @weak store,
+            @strong add_kind => move |view, dctx, x, y, sel, _, time| {
+                if sel.target().name() != PALETTE_TARGET {
+                    return; // a row being reordered, the default handler moves it
+                }
+                view.stop_signal_emission_by_name("drag-data-received");
+                let data = sel.data();
+                let kind = KINDS.iter().find(|k| k.as_bytes() == &data[..]);
+                let kind = match kind {
+                    Some(k) => *k,
+                    None => {
+                        dctx.drag_finish(false, false, time);
+                        return;
+                    }
+                };
+                // dropping into a row adds a child, dropping before or
+                // after it adds a sibling, and dropping below the last
+                // row adds a sibling of the root
+                let target = view
+                    .dest_row_at_pos(x, y)
+                    .and_then(|(path, pos)| Some((store.iter(&path?)?, pos)));
+                let iter = match target {
+                    None => store.insert_before(None, None),
+                    Some((t, gtk::TreeViewDropPosition::Before)) => {
+                        store.insert_before(None, Some(&t))
+                    }
+                    Some((t, gtk::TreeViewDropPosition::After)) => {
+                        store.insert_after(None, Some(&t))
+                    }
+                    Some((t, gtk::TreeViewDropPosition::IntoOrBefore)) => {
+                        store.prepend(Some(&t))
+                    }
+                    Some((t, _)) => store.append(Some(&t)),
+                };
+                add_kind(iter, kind);
+                dctx.drag_finish(true, false, time);
+        }));
         let del = Rc::new(clone!(
             @weak selection,
             @strong on_change,
@@ -1254,6 +2000,83 @@ impl Editor {
         }));
         delete.connect_activate(clone!(@strong del => move |_| del()));
         delbtn.connect_clicked(clone!(@strong del => move |_| del()));
+        // the clipboard holds the spec of the copied subtree as json,
+        // so it can be pasted into another view, or another browser
+        let cpy = Rc::new(clone!(@weak store, @strong selected => move || {
+            if let Some(iter) = &*selected.borrow() {
+                let spec = Editor::build_spec(&store, iter);
+                match serde_json::to_string_pretty(&spec) {
+                    Err(e) => warn!("failed to serialize widget {}", e),
+                    Ok(s) => gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&s),
+                }
+            }
+        }));
+        copy.connect_activate(clone!(@strong cpy => move |_| cpy()));
+        let ct = Rc::new(clone!(@strong cpy, @strong del => move || {
+            cpy();
+            del()
+        }));
+        cut.connect_activate(clone!(@strong ct => move |_| ct()));
+        let pst = Rc::new(clone!(
+            @strong scope,
+            @strong on_change,
+            @weak store,
+            @strong selected,
+            @strong ctx => move |as_child: bool| {
+                let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                let spec = match clipboard.wait_for_text() {
+                    None => return,
+                    Some(s) => match serde_json::from_str::<view::Widget>(&s) {
+                        Ok(spec) => spec,
+                        Err(e) => {
+                            warn!("clipboard does not contain a widget {}", e);
+                            return;
+                        }
+                    },
+                };
+                let selected = selected.borrow().clone();
+                let parent = match &selected {
+                    Some(iter) if as_child => Some(iter.clone()),
+                    Some(iter) => store.iter_parent(iter),
+                    None => None,
+                };
+                Editor::build_tree(
+                    &ctx,
+                    &on_change,
+                    &store,
+                    scope.clone(), // overwritten by on_change
+                    parent.as_ref(),
+                    &spec
+                );
+                // build_tree appends, move the pasted widget after the selection
+                if let (false, Some(sel)) = (as_child, &selected) {
+                    let n = store.iter_n_children(parent.as_ref());
+                    if let Some(iter) = store.iter_nth_child(parent.as_ref(), n - 1) {
+                        store.move_after(&iter, Some(sel));
+                    }
+                }
+                on_change()
+        }));
+        paste.connect_activate(clone!(@strong pst => move |_| pst(false)));
+        paste_child.connect_activate(clone!(@strong pst => move |_| pst(true)));
+        view.connect_key_press_event(move |_, k| {
+            if !k.state().contains(gdk::ModifierType::CONTROL_MASK) {
+                return Inhibit(false);
+            }
+            let kv = k.keyval();
+            if kv == gdk::keys::constants::c {
+                cpy();
+                Inhibit(true)
+            } else if kv == gdk::keys::constants::x {
+                ct();
+                Inhibit(true)
+            } else if kv == gdk::keys::constants::v {
+                pst(false);
+                Inhibit(true)
+            } else {
+                Inhibit(false)
+            }
+        });
         let und = Rc::new(clone!(
             @strong ctx,
             @weak store,
@@ -1296,6 +2119,54 @@ impl Editor {
                 Inhibit(false)
             }
         });
+        let find = Rc::new(clone!(
+            @weak store,
+            @weak view,
+            @weak search => move |dir: SearchDir| {
+                let query = search.text().to_lowercase();
+                let matches = Editor::mark_matches(&store, &query);
+                let (rows, _) = view.selection().selected_rows();
+                let current = rows.first().map(|p| p.indices());
+                let target = match (dir, current) {
+                    (SearchDir::Here | SearchDir::Forward, None) => matches.first(),
+                    (SearchDir::Backward, None) => matches.last(),
+                    (SearchDir::Here, Some(cur)) => {
+                        matches.iter().find(|m| **m >= cur).or(matches.first())
+                    }
+                    (SearchDir::Forward, Some(cur)) => {
+                        matches.iter().find(|m| **m > cur).or(matches.first())
+                    }
+                    (SearchDir::Backward, Some(cur)) => {
+                        matches.iter().rev().find(|m| **m < cur).or(matches.last())
+                    }
+                };
+                if let Some(target) = target {
+                    let path = gtk::TreePath::from_indicesv(target);
+                    view.expand_to_path(&path);
+                    view.selection().unselect_all();
+                    view.selection().select_path(&path);
+                    view.scroll_to_cell(
+                        Some(&path),
+                        None::<&gtk::TreeViewColumn>,
+                        true,
+                        0.5,
+                        0.,
+                    );
+                }
+        }));
+        search.connect_search_changed(clone!(@strong find => move |_| {
+            find(SearchDir::Here)
+        }));
+        search.connect_activate(clone!(@strong find => move |_| {
+            find(SearchDir::Forward)
+        }));
+        search.connect_next_match(clone!(@strong find => move |_| {
+            find(SearchDir::Forward)
+        }));
+        search.connect_previous_match(clone!(@strong find => move |_| {
+            find(SearchDir::Backward)
+        }));
+        search.connect_stop_search(|s| s.set_text(""));
         store.connect_row_deleted(clone!(@strong on_change => move |_, _| {
             on_change();
         }));
@@ -1322,9 +2193,11 @@ impl Editor {
                 WidgetKind::Box(_) => scope.append("b"),
                 WidgetKind::Grid(_) => scope.append("g"),
                 WidgetKind::Paned(_) => scope.append("p"),
+                WidgetKind::Responsive => scope.append("r"),
                 WidgetKind::Frame(_)
                 | WidgetKind::GridRow
                 | WidgetKind::NotebookPage(_)
+                | WidgetKind::Breakpoint(_)
                 | WidgetKind::BoxChild(_)
                 | WidgetKind::GridChild(_)
                 | WidgetKind::BScript(_)
@@ -1339,14 +2212,31 @@ impl Editor {
                 | WidgetKind::Switch(_)
                 | WidgetKind::ComboBox(_)
                 | WidgetKind::Scale(_)
+                | WidgetKind::SpinButton(_)
+                | WidgetKind::Calendar(_)
+                | WidgetKind::RadioGroup(_)
                 | WidgetKind::ProgressBar(_)
                 | WidgetKind::Entry(_)
                 | WidgetKind::SearchEntry(_)
                 | WidgetKind::LinePlot(_) => scope.clone(),
             };
+            // notebook pages, layouts and grid rows each get their own scope
+            let per_child = match &w.kind {
+                WidgetKind::Notebook(_)
+                | WidgetKind::Responsive
+                | WidgetKind::Grid(_) => true,
+                _ => false,
+            };
             if let Some(iter) = store.iter_children(Some(root)) {
+                let mut i = 0usize;
                 loop {
-                    Editor::update_scope(store, scope.clone(), &iter);
+                    let scope = if per_child {
+                        scope.append(&i.to_string())
+                    } else {
+                        scope.clone()
+                    };
+                    Editor::update_scope(store, scope, &iter);
+                    i += 1;
                     if !store.iter_next(&iter) {
                         break;
                     }
@@ -1374,6 +2264,22 @@ impl Editor {
             view::WidgetKind::NotebookPage(p) => {
                 Editor::build_tree(ctx, on_change, store, scope, Some(&iter), &*p.widget);
             }
+            view::WidgetKind::Breakpoint(b) => {
+                Editor::build_tree(ctx, on_change, store, scope, Some(&iter), &*b.widget);
+            }
+            view::WidgetKind::Responsive(r) => {
+                let scope = scope.append("r");
+                for w in r.children.iter() {
+                    Editor::build_tree(
+                        ctx,
+                        on_change,
+                        store,
+                        scope.clone(),
+                        Some(&iter),
+                        w,
+                    );
+                }
+            }
             view::WidgetKind::Notebook(n) => {
                 let scope = scope.append("n");
                 for w in n.children.iter() {
@@ -1459,6 +2365,9 @@ impl Editor {
             | view::WidgetKind::Switch(_)
             | view::WidgetKind::ComboBox(_)
             | view::WidgetKind::Scale(_)
+            | view::WidgetKind::SpinButton(_)
+            | view::WidgetKind::Calendar(_)
+            | view::WidgetKind::RadioGroup(_)
             | view::WidgetKind::ProgressBar(_)
             | view::WidgetKind::Entry(_)
             | view::WidgetKind::SearchEntry(_)
@@ -1509,6 +2418,22 @@ impl Editor {
                             p.widget = boxed::Box::new(Editor::build_spec(store, &iter));
                         }
                     }
+                    view::WidgetKind::Responsive(ref mut r) => {
+                        r.children.clear();
+                        if let Some(iter) = store.iter_children(Some(root)) {
+                            loop {
+                                r.children.push(Editor::build_spec(store, &iter));
+                                if !store.iter_next(&iter) {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    view::WidgetKind::Breakpoint(ref mut b) => {
+                        if let Some(iter) = store.iter_children(Some(root)) {
+                            b.widget = boxed::Box::new(Editor::build_spec(store, &iter));
+                        }
+                    }
                     view::WidgetKind::Box(ref mut b) => {
                         b.children.clear();
                         if let Some(iter) = store.iter_children(Some(root)) {
@@ -1564,6 +2489,9 @@ impl Editor {
                     | view::WidgetKind::Switch(_)
                     | view::WidgetKind::ComboBox(_)
                     | view::WidgetKind::Scale(_)
+                    | view::WidgetKind::SpinButton(_)
+                    | view::WidgetKind::Calendar(_)
+                    | view::WidgetKind::RadioGroup(_)
                     | view::WidgetKind::ProgressBar(_)
                     | view::WidgetKind::Entry(_)
                     | view::WidgetKind::SearchEntry(_)
@@ -1574,6 +2502,60 @@ impl Editor {
         }
     }
 
+    /// The text the search matches for the widget at `iter`, it's
+    /// kind, and every string in it's spec, which covers the bscript
+    /// expressions and the paths they reference. Children are matched
+    /// on their own rows.
+    fn search_text(store: &gtk::TreeStore, iter: &gtk::TreeIter) -> String {
+        fn strings(v: &serde_json::Value, buf: &mut String) {
+            match v {
+                serde_json::Value::String(s) => {
+                    buf.push_str(s);
+                    buf.push('\n');
+                }
+                serde_json::Value::Array(a) => a.iter().for_each(|v| strings(v, buf)),
+                serde_json::Value::Object(o) => {
+                    for (k, v) in o {
+                        match k.as_str() {
+                            "child" | "first_child" | "second_child" | "children"
+                            | "widget" | "rows" | "columns" => (),
+                            _ => strings(v, buf),
+                        }
+                    }
+                }
+                serde_json::Value::Null
+                | serde_json::Value::Bool(_)
+                | serde_json::Value::Number(_) => (),
+            }
+        }
+        let mut buf = String::new();
+        if let Ok(kind) = store.value(iter, 0).get::<&str>() {
+            buf.push_str(kind);
+            buf.push('\n');
+        }
+        if let Ok(w) = store.value(iter, 1).get::<&Widget>() {
+            if let Ok(v) = serde_json::to_value(&w.spec()) {
+                strings(&v, &mut buf)
+            }
+        }
+        buf.to_lowercase()
+    }
+
+    /// Mark the widgets matching `query`, and return their paths in
+    /// tree order. An empty query matches nothing.
+    fn mark_matches(store: &gtk::TreeStore, query: &str) -> Vec<Vec<i32>> {
+        let mut matches = Vec::new();
+        store.foreach(|_, path, iter| {
+            let m = !query.is_empty() && Editor::search_text(store, iter).contains(query);
+            store.set_value(iter, 3, &m.to_value());
+            if m {
+                matches.push(path.indices());
+            }
+            false
+        });
+        matches
+    }
+
     fn build_widget_path(
         store: &gtk::TreeStore,
         start: &gtk::TreeIter,
@@ -1597,6 +2579,9 @@ impl Editor {
                 | WidgetKind::Switch(_)
                 | WidgetKind::ComboBox(_)
                 | WidgetKind::Scale(_)
+                | WidgetKind::SpinButton(_)
+                | WidgetKind::Calendar(_)
+                | WidgetKind::RadioGroup(_)
                 | WidgetKind::ProgressBar(_)
                 | WidgetKind::Entry(_)
                 | WidgetKind::SearchEntry(_)
@@ -1607,6 +2592,7 @@ impl Editor {
                 WidgetKind::Frame(_)
                 | WidgetKind::Box(_)
                 | WidgetKind::Notebook(_)
+                | WidgetKind::Responsive
                 | WidgetKind::Paned(_) => {
                     if path.len() == 0 {
                         path.insert(0, WidgetPath::Leaf);
@@ -1615,7 +2601,9 @@ impl Editor {
                     }
                     false
                 }
-                WidgetKind::NotebookPage(_) | WidgetKind::BoxChild(_) => {
+                WidgetKind::NotebookPage(_)
+                | WidgetKind::Breakpoint(_)
+                | WidgetKind::BoxChild(_) => {
                     if path.len() == 0 {
                         path.insert(0, WidgetPath::Leaf);
                     }
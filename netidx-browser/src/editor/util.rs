@@ -93,3 +93,19 @@ pub(super) fn expander_touch_enable(root: &gtk::Expander) {
         }),
     );
 }
+
+/// Append a resizable text column to `view` for each name, showing
+/// the model column with the same index.
+pub(super) fn text_columns(view: &gtk::TreeView, names: &[&str]) {
+    for (i, name) in names.iter().enumerate() {
+        view.append_column(&{
+            let column = gtk::TreeViewColumn::new();
+            let cell = gtk::CellRendererText::new();
+            CellLayoutExt::pack_start(&column, &cell, true);
+            column.set_resizable(true);
+            column.set_title(name);
+            CellLayoutExt::add_attribute(&column, &cell, "text", i as i32);
+            column
+        });
+    }
+}
@@ -7,6 +7,7 @@ use super::{
 use glib::{clone, prelude::*};
 use gtk::{self, prelude::*};
 use indexmap::IndexMap;
+use log::warn;
 use netidx::subscriber::Value;
 use netidx_bscript::expr;
 use netidx_protocols::view;
@@ -125,6 +126,7 @@ pub(super) struct Table {
     _dbg_column_widths: DbgExpr,
     _dbg_columns_resizable: DbgExpr,
     _dbg_column_types: DbgExpr,
+    _dbg_footer: DbgExpr,
     _dbg_selection_mode: DbgExpr,
     _dbg_selection: DbgExpr,
     _dbg_show_row_name: DbgExpr,
@@ -186,6 +188,11 @@ impl Table {
         col_config.add((l, e));
         let (l, e, _dbg_column_types) =
             expr!(ctx, "Column Types:", scope, spec, on_change, column_types);
+        l.set_tooltip_text(Some(
+            "[[name, type, props], ...] type is one of text, toggle, image, combo, spin, progress, sparkline, or hidden",
+        ));
+        col_config.add((l, e));
+        let (l, e, _dbg_footer) = expr!(ctx, "Footer:", scope, spec, on_change, footer);
         col_config.add((l, e));
         let (l, e, _dbg_selection_mode) =
             expr!(ctx, "Selection Mode:", scope, spec, on_change, selection_mode);
@@ -220,6 +227,7 @@ impl Table {
             _dbg_column_widths,
             _dbg_columns_resizable,
             _dbg_column_types,
+            _dbg_footer,
             _dbg_selection_mode,
             _dbg_selection,
             _dbg_show_row_name,
@@ -711,6 +719,7 @@ pub(super) struct LinePlot {
     _y_min: DbgExpr,
     _y_max: DbgExpr,
     _keep_points: DbgExpr,
+    _on_cursor: DbgExpr,
     _series: Rc<RefCell<IndexMap<usize, Series>>>,
 }
 
@@ -733,8 +742,63 @@ impl LinePlot {
                 scope.clone(),
                 &spec,
             );
+        let _on_cursor = LinePlot::build_interaction_editor(
+            ctx,
+            &root,
+            &on_change,
+            scope.clone(),
+            &spec,
+        );
         let _series = LinePlot::build_series_editor(ctx, &root, &on_change, scope, &spec);
-        LinePlot { root, spec, _x_min, _x_max, _y_min, _y_max, _keep_points, _series }
+        LinePlot {
+            root,
+            spec,
+            _x_min,
+            _x_max,
+            _y_min,
+            _y_max,
+            _keep_points,
+            _on_cursor,
+            _series,
+        }
+    }
+
+    fn build_interaction_editor(
+        ctx: &BSCtx,
+        root: &gtk::Box,
+        on_change: &OnChange,
+        scope: Scope,
+        spec: &Rc<RefCell<view::LinePlot>>,
+    ) -> DbgExpr {
+        let interaction_exp = gtk::Expander::new(Some("Interaction"));
+        util::expander_touch_enable(&interaction_exp);
+        let mut interaction = TwoColGrid::new();
+        root.pack_start(&interaction_exp, false, false, 0);
+        root.pack_start(
+            &gtk::Separator::new(gtk::Orientation::Horizontal),
+            false,
+            false,
+            0,
+        );
+        interaction_exp.add(interaction.root());
+        let zoom = gtk::CheckButton::with_label("Zoom & Pan");
+        zoom.set_active(spec.borrow().zoom);
+        zoom.connect_toggled(clone!(@strong on_change, @strong spec => move |b| {
+            spec.borrow_mut().zoom = b.is_active();
+            on_change()
+        }));
+        interaction.attach(&zoom, 0, 2, 1);
+        let cursor = gtk::CheckButton::with_label("Cursor");
+        cursor.set_active(spec.borrow().cursor);
+        cursor.connect_toggled(clone!(@strong on_change, @strong spec => move |b| {
+            spec.borrow_mut().cursor = b.is_active();
+            on_change()
+        }));
+        interaction.attach(&cursor, 0, 2, 1);
+        let (l, e, on_cursor) =
+            expr!(ctx, "On Cursor:", scope, spec, on_change, on_cursor);
+        interaction.add((l, e));
+        on_cursor
     }
 
     fn build_axis_style_editor(
@@ -1283,6 +1347,60 @@ impl NotebookPage {
     }
 }
 
+#[derive(Clone)]
+pub(super) struct Breakpoint {
+    root: TwoColGrid,
+    spec: Rc<RefCell<view::Breakpoint>>,
+}
+
+impl Breakpoint {
+    pub(super) fn new(
+        on_change: OnChange,
+        _scope: Scope,
+        spec: view::Breakpoint,
+    ) -> Self {
+        let mut root = TwoColGrid::new();
+        let spec = Rc::new(RefCell::new(spec));
+        // leave a bound empty to not check it
+        let bound = |label: &str, get: fn(&mut view::Breakpoint) -> &mut Option<u32>| {
+            let cur = *get(&mut *spec.borrow_mut());
+            let cur = cur.map(|v| v.to_string()).unwrap_or_default();
+            parse_entry(
+                label,
+                &cur,
+                clone!(@strong on_change, @strong spec => move |s: String| {
+                    let s = s.trim();
+                    if s.is_empty() {
+                        *get(&mut *spec.borrow_mut()) = None;
+                        on_change()
+                    } else {
+                        match s.parse::<u32>() {
+                            Err(e) => warn!("invalid bound {}, {}", s, e),
+                            Ok(v) => {
+                                *get(&mut *spec.borrow_mut()) = Some(v);
+                                on_change()
+                            }
+                        }
+                    }
+                }),
+            )
+        };
+        root.add(bound("Min Width:", |b| &mut b.min_width));
+        root.add(bound("Max Width:", |b| &mut b.max_width));
+        root.add(bound("Min Height:", |b| &mut b.min_height));
+        root.add(bound("Max Height:", |b| &mut b.max_height));
+        Breakpoint { root, spec }
+    }
+
+    pub(super) fn spec(&self) -> view::Breakpoint {
+        self.spec.borrow().clone()
+    }
+
+    pub(super) fn root(&self) -> &gtk::Widget {
+        self.root.root().upcast_ref()
+    }
+}
+
 #[derive(Clone)]
 pub(super) struct Notebook {
     root: TwoColGrid,
@@ -1470,6 +1588,7 @@ pub(super) struct Scale {
     _dbg_value: DbgExpr,
     _dbg_min: DbgExpr,
     _dbg_max: DbgExpr,
+    _dbg_step: DbgExpr,
     _dbg_on_change: DbgExpr,
 }
 
@@ -1505,6 +1624,8 @@ impl Scale {
         root.add((l, e));
         let (l, e, _dbg_max) = expr!(ctx, "Max:", scope, spec, on_change, max);
         root.add((l, e));
+        let (l, e, _dbg_step) = expr!(ctx, "Step:", scope, spec, on_change, step);
+        root.add((l, e));
         let (l, e, _dbg_on_change) =
             expr!(ctx, "On Change:", scope, spec, on_change, on_change);
         root.add((l, e));
@@ -1515,6 +1636,7 @@ impl Scale {
             _dbg_marks,
             _dbg_min,
             _dbg_max,
+            _dbg_step,
             _dbg_has_origin,
             _dbg_value,
             _dbg_on_change,
@@ -1530,6 +1652,143 @@ impl Scale {
     }
 }
 
+#[derive(Clone)]
+pub(super) struct SpinButton {
+    root: TwoColGrid,
+    spec: Rc<RefCell<view::SpinButton>>,
+    _dbg_value: DbgExpr,
+    _dbg_min: DbgExpr,
+    _dbg_max: DbgExpr,
+    _dbg_step: DbgExpr,
+    _dbg_digits: DbgExpr,
+    _dbg_on_change: DbgExpr,
+}
+
+impl SpinButton {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        on_change: OnChange,
+        scope: Scope,
+        spec: view::SpinButton,
+    ) -> Self {
+        let mut root = TwoColGrid::new();
+        let spec = Rc::new(RefCell::new(spec));
+        let (l, e, _dbg_value) = expr!(ctx, "Value:", scope, spec, on_change, value);
+        root.add((l, e));
+        let (l, e, _dbg_min) = expr!(ctx, "Min:", scope, spec, on_change, min);
+        root.add((l, e));
+        let (l, e, _dbg_max) = expr!(ctx, "Max:", scope, spec, on_change, max);
+        root.add((l, e));
+        let (l, e, _dbg_step) = expr!(ctx, "Step:", scope, spec, on_change, step);
+        root.add((l, e));
+        let (l, e, _dbg_digits) = expr!(ctx, "Digits:", scope, spec, on_change, digits);
+        root.add((l, e));
+        let (l, e, _dbg_on_change) =
+            expr!(ctx, "On Change:", scope, spec, on_change, on_change);
+        root.add((l, e));
+        Self {
+            root,
+            spec,
+            _dbg_value,
+            _dbg_min,
+            _dbg_max,
+            _dbg_step,
+            _dbg_digits,
+            _dbg_on_change,
+        }
+    }
+
+    pub(super) fn root(&self) -> &gtk::Widget {
+        self.root.root().upcast_ref()
+    }
+
+    pub(super) fn spec(&self) -> view::SpinButton {
+        self.spec.borrow().clone()
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct Calendar {
+    root: TwoColGrid,
+    spec: Rc<RefCell<view::Calendar>>,
+    _dbg_value: DbgExpr,
+    _dbg_on_change: DbgExpr,
+}
+
+impl Calendar {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        on_change: OnChange,
+        scope: Scope,
+        spec: view::Calendar,
+    ) -> Self {
+        let mut root = TwoColGrid::new();
+        let spec = Rc::new(RefCell::new(spec));
+        let (l, e, _dbg_value) = expr!(ctx, "Value:", scope, spec, on_change, value);
+        root.add((l, e));
+        let (l, e, _dbg_on_change) =
+            expr!(ctx, "On Change:", scope, spec, on_change, on_change);
+        root.add((l, e));
+        Self { root, spec, _dbg_value, _dbg_on_change }
+    }
+
+    pub(super) fn root(&self) -> &gtk::Widget {
+        self.root.root().upcast_ref()
+    }
+
+    pub(super) fn spec(&self) -> view::Calendar {
+        self.spec.borrow().clone()
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct RadioGroup {
+    root: TwoColGrid,
+    spec: Rc<RefCell<view::RadioGroup>>,
+    _dbg_choices: DbgExpr,
+    _dbg_selected: DbgExpr,
+    _dbg_on_change: DbgExpr,
+}
+
+impl RadioGroup {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        on_change: OnChange,
+        scope: Scope,
+        spec: view::RadioGroup,
+    ) -> Self {
+        let mut root = TwoColGrid::new();
+        let spec = Rc::new(RefCell::new(spec));
+        let dirlb = gtk::Label::new(Some("Direction:"));
+        let dircb = dirselect(
+            spec.borrow().direction,
+            clone!(@strong on_change, @strong spec => move |d| {
+                spec.borrow_mut().direction = d;
+                on_change()
+            }),
+        );
+        root.add((dirlb, dircb));
+        let (l, e, _dbg_choices) =
+            expr!(ctx, "Choices:", scope, spec, on_change, choices);
+        root.add((l, e));
+        let (l, e, _dbg_selected) =
+            expr!(ctx, "Selected:", scope, spec, on_change, selected);
+        root.add((l, e));
+        let (l, e, _dbg_on_change) =
+            expr!(ctx, "On Change:", scope, spec, on_change, on_change);
+        root.add((l, e));
+        Self { root, spec, _dbg_choices, _dbg_selected, _dbg_on_change }
+    }
+
+    pub(super) fn root(&self) -> &gtk::Widget {
+        self.root.root().upcast_ref()
+    }
+
+    pub(super) fn spec(&self) -> view::RadioGroup {
+        self.spec.borrow().clone()
+    }
+}
+
 #[derive(Clone)]
 pub(super) struct ProgressBar {
     root: TwoColGrid,
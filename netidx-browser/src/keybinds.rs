@@ -0,0 +1,156 @@
+use super::{BSCtx, BSCtxRef, BSNode};
+use crate::{bscript::LocalEvent, view};
+use anyhow::{bail, Result};
+use gdk::{self, keys::Key, ModifierType};
+use glib::clone;
+use gtk::{self, prelude::*};
+use log::warn;
+use netidx::{path::Path, subscriber::Value};
+use netidx_bscript::vm;
+use std::{cell::RefCell, rc::Rc};
+
+/// A parsed key chord, e.g. ctrl+shift+a or alt_l+F1
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Chord {
+    key: Key,
+    mods: ModifierType,
+    sided: Vec<Key>,
+}
+
+impl Chord {
+    pub(super) fn parse(s: &str) -> Result<Chord> {
+        use gdk::keys::constants as k;
+        let mut parts = s.split('+').map(|p| p.trim()).collect::<Vec<_>>();
+        let key = match parts.pop() {
+            Some(key) if !key.is_empty() => key,
+            Some(_) | None => bail!("missing key in {}", s),
+        };
+        let key = Key::from_name(key);
+        if key == k::VoidSymbol {
+            bail!("unknown key name in {}", s)
+        }
+        let mut mods = ModifierType::empty();
+        let mut sided = vec![];
+        for m in parts {
+            let (m, side) = match m.to_lowercase().as_str() {
+                "ctrl" => (ModifierType::CONTROL_MASK, None),
+                "ctrl_l" => (ModifierType::CONTROL_MASK, Some(k::Control_L)),
+                "ctrl_r" => (ModifierType::CONTROL_MASK, Some(k::Control_R)),
+                "alt" => (ModifierType::MOD1_MASK, None),
+                "alt_l" => (ModifierType::MOD1_MASK, Some(k::Alt_L)),
+                "alt_r" => (ModifierType::MOD1_MASK, Some(k::Alt_R)),
+                "shift" => (ModifierType::SHIFT_MASK, None),
+                "shift_l" => (ModifierType::SHIFT_MASK, Some(k::Shift_L)),
+                "shift_r" => (ModifierType::SHIFT_MASK, Some(k::Shift_R)),
+                "super" => (ModifierType::SUPER_MASK, None),
+                "super_l" => (ModifierType::SUPER_MASK, Some(k::Super_L)),
+                "super_r" => (ModifierType::SUPER_MASK, Some(k::Super_R)),
+                _ => bail!("unknown modifier {} in {}", m, s),
+            };
+            mods.insert(m);
+            sided.extend(side);
+        }
+        Ok(Chord { key: key.to_lower(), mods, sided })
+    }
+
+    fn matches(&self, ev: &gdk::EventKey, pressed: &[Key]) -> bool {
+        let mods = ModifierType::CONTROL_MASK
+            | ModifierType::MOD1_MASK
+            | ModifierType::SHIFT_MASK
+            | ModifierType::SUPER_MASK;
+        ev.keyval().to_lower() == self.key
+            && ev.state() & mods == self.mods
+            && self.sided.iter().all(|k| pressed.contains(k))
+    }
+}
+
+fn is_sided_modifier(key: &Key) -> bool {
+    use gdk::keys::constants as k;
+    [
+        k::Control_L,
+        k::Control_R,
+        k::Alt_L,
+        k::Alt_R,
+        k::Shift_L,
+        k::Shift_R,
+        k::Super_L,
+        k::Super_R,
+    ]
+    .contains(key)
+}
+
+/// The keybinds of a widget. Key presses that reach the widget,
+/// either because it has focus or because one of it's children
+/// does, are matched against each chord, and the expression of the
+/// first one that matches receives an event.
+pub(super) struct Keybinds {
+    binds: Rc<Vec<(Chord, RefCell<BSNode>)>>,
+}
+
+impl Keybinds {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        scope: Path,
+        spec: &[view::Keybind],
+        root: Option<&gtk::Widget>,
+    ) -> Self {
+        let binds = spec
+            .iter()
+            .filter_map(|kb| match Chord::parse(&kb.key) {
+                Ok(chord) => {
+                    let node = BSNode::compile(
+                        &mut ctx.borrow_mut(),
+                        scope.clone(),
+                        kb.expr.clone(),
+                    );
+                    Some((chord, RefCell::new(node)))
+                }
+                Err(e) => {
+                    warn!("invalid keybind {}", e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let binds = Rc::new(binds);
+        if let Some(root) = root {
+            if !binds.is_empty() {
+                root.add_events(
+                    gdk::EventMask::KEY_PRESS_MASK | gdk::EventMask::KEY_RELEASE_MASK,
+                );
+                let pressed = Rc::new(RefCell::new(Vec::<Key>::new()));
+                root.connect_key_press_event(clone!(
+                    @strong ctx, @strong binds, @strong pressed => move |_, ev| {
+                        let key = ev.keyval();
+                        if is_sided_modifier(&key) {
+                            pressed.borrow_mut().push(key);
+                            return Inhibit(false);
+                        }
+                        let pressed = pressed.borrow();
+                        match binds.iter().find(|(c, _)| c.matches(ev, &pressed)) {
+                            None => Inhibit(false),
+                            Some((_, node)) => {
+                                node.borrow_mut().update(
+                                    &mut ctx.borrow_mut(),
+                                    &vm::Event::User(LocalEvent::Event(Value::Null)),
+                                );
+                                Inhibit(true)
+                            }
+                        }
+                    }
+                ));
+                root.connect_key_release_event(move |_, ev| {
+                    let key = ev.keyval();
+                    pressed.borrow_mut().retain(|k| k != &key);
+                    Inhibit(false)
+                });
+            }
+        }
+        Keybinds { binds }
+    }
+
+    pub(super) fn update(&self, ctx: BSCtxRef, event: &vm::Event<LocalEvent>) {
+        for (_, node) in self.binds.iter() {
+            node.borrow_mut().update(ctx, event);
+        }
+    }
+}
@@ -55,6 +55,154 @@ impl<'a, I: Iterator<Item = &'a Value>> Iterator for ValidTypIter<'a, I> {
     }
 }
 
+fn timestamp(d: &DateTime<Utc>) -> f64 {
+    d.timestamp() as f64 + d.timestamp_subsec_nanos() as f64 / 1e9
+}
+
+fn to_f64(v: &Value) -> Option<f64> {
+    match v {
+        Value::DateTime(d) => Some(timestamp(d)),
+        v => v.clone().cast_to::<f64>().ok(),
+    }
+}
+
+/// convert `f` back to a value of the same type as `like`
+fn from_f64(like: &Value, f: f64) -> Value {
+    match like {
+        Value::DateTime(_) => {
+            let secs = f.floor();
+            match Utc.timestamp_opt(secs as i64, ((f - secs) * 1e9) as u32).single() {
+                Some(d) => Value::DateTime(d),
+                None => like.clone(),
+            }
+        }
+        _ => Value::F64(f),
+    }
+}
+
+/// The pixel and data extents of the plotting area as of the last
+/// draw
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    px: (f64, f64),
+    py: (f64, f64),
+    x: (f64, f64),
+    y: (f64, f64),
+}
+
+impl Area {
+    fn contains(&self, (x, y): (f64, f64)) -> bool {
+        x >= self.px.0 && x <= self.px.1 && y >= self.py.0 && y <= self.py.1
+    }
+
+    fn to_data(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let fx = (x - self.px.0) / (self.px.1 - self.px.0);
+        let fy = (self.py.1 - y) / (self.py.1 - self.py.0);
+        (self.x.0 + fx * (self.x.1 - self.x.0), self.y.0 + fy * (self.y.1 - self.y.0))
+    }
+
+    fn to_pixel(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let fx = (x - self.x.0) / (self.x.1 - self.x.0);
+        let fy = (y - self.y.0) / (self.y.1 - self.y.0);
+        (
+            self.px.0 + fx * (self.px.1 - self.px.0),
+            self.py.1 - fy * (self.py.1 - self.py.0),
+        )
+    }
+}
+
+/// Zoom, pan, and cursor state
+#[derive(Debug, Default)]
+struct Interaction {
+    area: Option<Area>,
+    /// the range selected by the user, overrides x/y min/max
+    zoom: Option<((f64, f64), (f64, f64))>,
+    /// the last pointer position while dragging
+    drag: Option<(f64, f64)>,
+    /// the point nearest the pointer
+    cursor: Option<(Value, Value)>,
+}
+
+impl Interaction {
+    fn set_range(&mut self, x: (f64, f64), y: (f64, f64)) {
+        self.zoom = Some((x, y));
+        if let Some(a) = self.area.as_mut() {
+            a.x = x;
+            a.y = y;
+        }
+    }
+
+    /// scale the range by `factor` keeping the point under `pos` fixed
+    fn zoom_at(&mut self, pos: (f64, f64), factor: f64) -> bool {
+        match self.area {
+            Some(a) if a.contains(pos) => {
+                let (x, y) = a.to_data(pos);
+                let x = (x - (x - a.x.0) * factor, x + (a.x.1 - x) * factor);
+                let y = (y - (y - a.y.0) * factor, y + (a.y.1 - y) * factor);
+                self.set_range(x, y);
+                true
+            }
+            Some(_) | None => false,
+        }
+    }
+
+    fn pan(&mut self, pos: (f64, f64)) -> bool {
+        match (self.area, self.drag.replace(pos)) {
+            (Some(a), Some(last)) => {
+                let (x, y) = a.to_data(pos);
+                let (lx, ly) = a.to_data(last);
+                let (dx, dy) = (lx - x, ly - y);
+                self.set_range((a.x.0 + dx, a.x.1 + dx), (a.y.0 + dy, a.y.1 + dy));
+                true
+            }
+            (_, _) => false,
+        }
+    }
+
+    fn nearest(&self, series: &[Series], pos: (f64, f64)) -> Option<(Value, Value)> {
+        let area = self.area.filter(|a| a.contains(pos))?;
+        let mut best: Option<(f64, &Value, &Value)> = None;
+        for s in series {
+            for (x, y) in s.x_data.iter().zip(s.y_data.iter()) {
+                if let (Some(xf), Some(yf)) = (to_f64(x), to_f64(y)) {
+                    let (px, py) = area.to_pixel((xf, yf));
+                    let d = (px - pos.0).powi(2) + (py - pos.1).powi(2);
+                    if best.map(|(b, _, _)| d < b).unwrap_or(true) {
+                        best = Some((d, x, y))
+                    }
+                }
+            }
+        }
+        best.map(|(_, x, y)| (x.clone(), y.clone()))
+    }
+
+    fn draw_cursor(&self, context: &cairo::Context) -> Result<()> {
+        let (area, x, y) = match (self.area, &self.cursor) {
+            (Some(area), Some((x, y))) => (area, x, y),
+            (_, _) => return Ok(()),
+        };
+        let (px, py) = match (to_f64(x), to_f64(y)) {
+            (Some(xf), Some(yf)) => area.to_pixel((xf, yf)),
+            (_, _) => return Ok(()),
+        };
+        if area.contains((px, py)) {
+            context.save()?;
+            context.set_source_rgb(0.5, 0.5, 0.5);
+            context.set_line_width(1.);
+            context.move_to(area.px.0, py);
+            context.line_to(area.px.1, py);
+            context.move_to(px, area.py.0);
+            context.line_to(px, area.py.1);
+            context.stroke()?;
+            context.set_source_rgb(0., 0., 0.);
+            context.move_to(px + 5., py - 5.);
+            context.show_text(&format!("{}, {}", x, y))?;
+            context.restore()?;
+        }
+        Ok(())
+    }
+}
+
 struct Series {
     line_color: view::RGB,
     x: BSNode,
@@ -71,6 +219,7 @@ pub(super) struct LinePlot {
     y_min: Rc<RefCell<BSNode>>,
     y_max: Rc<RefCell<BSNode>>,
     keep_points: Rc<RefCell<BSNode>>,
+    on_cursor: Rc<RefCell<BSNode>>,
     series: Rc<RefCell<Vec<Series>>>,
 }
 
@@ -111,6 +260,11 @@ impl LinePlot {
             scope.clone(),
             spec.keep_points.clone(),
         )));
+        let on_cursor = Rc::new(RefCell::new(BSNode::compile(
+            &mut ctx.borrow_mut(),
+            scope.clone(),
+            spec.on_cursor.clone(),
+        )));
         let series = Rc::new(RefCell::new(
             spec.series
                 .iter()
@@ -137,6 +291,8 @@ impl LinePlot {
         ));
         let allocated_width = Rc::new(Cell::new(0));
         let allocated_height = Rc::new(Cell::new(0));
+        let state = Rc::new(RefCell::new(Interaction::default()));
+        LinePlot::connect_interaction(ctx, &spec, &canvas, &state, &on_cursor, &series);
         canvas.connect_draw(clone!(
             @strong ctx,
             @strong allocated_width,
@@ -145,6 +301,7 @@ impl LinePlot {
             @strong x_max,
             @strong y_min,
             @strong y_max,
+            @strong state,
             @strong series => move |_, context| {
                 // CR estokes: there is a bug in plotters that causes
                 // it to somtimes panic in draw it probably isn't
@@ -159,6 +316,7 @@ impl LinePlot {
                 &x_max,
                 &y_min,
                 &y_max,
+                &state,
                 &series,
                 context
             )));
@@ -175,7 +333,112 @@ impl LinePlot {
             allocated_width.set(i32::abs(a.width()) as u32);
             allocated_height.set(i32::abs(a.height()) as u32);
         }));
-        LinePlot { root, canvas, x_min, x_max, y_min, y_max, keep_points, series }
+        LinePlot {
+            root,
+            canvas,
+            x_min,
+            x_max,
+            y_min,
+            y_max,
+            keep_points,
+            on_cursor,
+            series,
+        }
+    }
+
+    fn connect_interaction(
+        ctx: &BSCtx,
+        spec: &view::LinePlot,
+        canvas: &gtk::DrawingArea,
+        state: &Rc<RefCell<Interaction>>,
+        on_cursor: &Rc<RefCell<BSNode>>,
+        series: &Rc<RefCell<Vec<Series>>>,
+    ) {
+        if !spec.zoom && !spec.cursor {
+            return;
+        }
+        canvas.add_events(
+            gdk::EventMask::SCROLL_MASK
+                | gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON_RELEASE_MASK
+                | gdk::EventMask::POINTER_MOTION_MASK
+                | gdk::EventMask::LEAVE_NOTIFY_MASK,
+        );
+        if spec.zoom {
+            canvas.connect_scroll_event(clone!(@strong state => move |canvas, ev| {
+                let factor = match ev.direction() {
+                    gdk::ScrollDirection::Up => 0.9,
+                    gdk::ScrollDirection::Down => 1. / 0.9,
+                    _ => return Inhibit(false),
+                };
+                if state.borrow_mut().zoom_at(ev.position(), factor) {
+                    canvas.queue_draw();
+                }
+                Inhibit(true)
+            }));
+            canvas.connect_button_press_event(
+                clone!(@strong state => move |canvas, ev| {
+                    if ev.button() != 1 {
+                        return Inhibit(false);
+                    }
+                    let mut state = state.borrow_mut();
+                    if ev.event_type() == gdk::EventType::DoubleButtonPress {
+                        state.zoom = None;
+                        canvas.queue_draw();
+                    } else {
+                        state.drag = Some(ev.position());
+                    }
+                    Inhibit(true)
+                }),
+            );
+            canvas.connect_button_release_event(clone!(@strong state => move |_, ev| {
+                if ev.button() == 1 {
+                    state.borrow_mut().drag = None;
+                }
+                Inhibit(false)
+            }));
+        }
+        let zoom = spec.zoom;
+        let cursor = spec.cursor;
+        canvas.connect_motion_notify_event(clone!(
+            @strong ctx, @strong state, @strong on_cursor, @strong series =>
+            move |canvas, ev| {
+                let pos = ev.position();
+                let mut queue_draw = false;
+                let mut fire = None;
+                {
+                    let mut state = state.borrow_mut();
+                    if zoom && state.drag.is_some() {
+                        queue_draw |= state.pan(pos);
+                    }
+                    if cursor {
+                        let nearest = state.nearest(&series.borrow(), pos);
+                        if nearest.is_some() && nearest != state.cursor {
+                            state.cursor = nearest.clone();
+                            fire = nearest;
+                            queue_draw = true;
+                        }
+                    }
+                }
+                if let Some((x, y)) = fire {
+                    on_cursor.borrow_mut().update(
+                        &mut ctx.borrow_mut(),
+                        &vm::Event::User(LocalEvent::Event(Value::from(vec![x, y]))),
+                    );
+                }
+                if queue_draw {
+                    canvas.queue_draw();
+                }
+                Inhibit(false)
+            }
+        ));
+        if cursor {
+            canvas.connect_leave_notify_event(clone!(@strong state => move |canvas, _| {
+                state.borrow_mut().cursor = None;
+                canvas.queue_draw();
+                Inhibit(false)
+            }));
+        }
     }
 
     fn draw(
@@ -187,6 +450,7 @@ impl LinePlot {
         x_max: &Rc<RefCell<BSNode>>,
         y_min: &Rc<RefCell<BSNode>>,
         y_max: &Rc<RefCell<BSNode>>,
+        state: &Rc<RefCell<Interaction>>,
         series: &Rc<RefCell<Vec<Series>>>,
         context: &cairo::Context,
     ) -> Result<()> {
@@ -228,6 +492,21 @@ impl LinePlot {
             }
             mesh.draw().map_err(|e| anyhow!("{}", e))
         }
+        fn plot_area<'a, DB, X, Y>(
+            chart: &ChartContext<'a, DB, Cartesian2d<X, Y>>,
+            x: (f64, f64),
+            y: (f64, f64),
+        ) -> Area
+        where
+            DB: DrawingBackend,
+            X: Ranged,
+            Y: Ranged,
+        {
+            let (px, py) = chart.plotting_area().get_pixel_range();
+            let px = (px.start as f64, px.end as f64);
+            let py = (py.start as f64, py.end as f64);
+            Area { px, py, x, y }
+        }
         if width.get() > 0 && height.get() > 0 {
             let x_min = x_min.borrow().current(&mut ctx.borrow_mut());
             let x_max = x_max.borrow().current(&mut ctx.borrow_mut());
@@ -269,6 +548,15 @@ impl LinePlot {
             let x_max = get_min_max(x_max, computed_x_max);
             let y_min = get_min_max(y_min, computed_y_min);
             let y_max = get_min_max(y_max, computed_y_max);
+            let (x_min, x_max, y_min, y_max) = match state.borrow().zoom {
+                None => (x_min, x_max, y_min, y_max),
+                Some(((x0, x1), (y0, y1))) => (
+                    from_f64(&x_min, x0),
+                    from_f64(&x_max, x1),
+                    from_f64(&y_min, y0),
+                    from_f64(&y_max, y1),
+                ),
+            };
             let back = CairoBackend::new(context, (width.get(), height.get()))?
                 .into_drawing_area();
             match spec.fill {
@@ -301,6 +589,11 @@ impl LinePlot {
                         y_max.cast_to::<DateTime<Utc>>().unwrap(),
                     );
                     let mut chart = chart.build_cartesian_2d(xmin..xmax, ymin..ymax)?;
+                    state.borrow_mut().area = Some(plot_area(
+                        &chart,
+                        (timestamp(&xmin), timestamp(&xmax)),
+                        (timestamp(&ymin), timestamp(&ymax)),
+                    ));
                     draw_mesh(spec, &mut chart)?;
                     for s in series.borrow().iter() {
                         let data =
@@ -328,6 +621,11 @@ impl LinePlot {
                     let ymin = y_min.cast_to::<f64>().unwrap();
                     let ymax = f64::max(ymin + 1., y_max.cast_to::<f64>().unwrap());
                     let mut chart = chart.build_cartesian_2d(xmin..xmax, ymin..ymax)?;
+                    state.borrow_mut().area = Some(plot_area(
+                        &chart,
+                        (timestamp(&xmin), timestamp(&xmax)),
+                        (ymin, ymax),
+                    ));
                     draw_mesh(spec, &mut chart)?;
                     for s in series.borrow().iter() {
                         let data = s
@@ -356,6 +654,11 @@ impl LinePlot {
                         y_max.cast_to::<DateTime<Utc>>().unwrap(),
                     );
                     let mut chart = chart.build_cartesian_2d(xmin..xmax, ymin..ymax)?;
+                    state.borrow_mut().area = Some(plot_area(
+                        &chart,
+                        (xmin, xmax),
+                        (timestamp(&ymin), timestamp(&ymax)),
+                    ));
                     draw_mesh(spec, &mut chart)?;
                     for s in series.borrow().iter() {
                         let data =
@@ -381,6 +684,8 @@ impl LinePlot {
                     let ymin = y_min.cast_to::<f64>().unwrap();
                     let ymax = f64::max(ymin + 1., y_max.cast_to::<f64>().unwrap());
                     let mut chart = chart.build_cartesian_2d(xmin..xmax, ymin..ymax)?;
+                    state.borrow_mut().area =
+                        Some(plot_area(&chart, (xmin, xmax), (ymin, ymax)));
                     draw_mesh(spec, &mut chart)?;
                     for s in series.borrow().iter() {
                         let data = s
@@ -400,6 +705,9 @@ impl LinePlot {
                 }
                 (_, _) => (),
             }
+            if spec.cursor {
+                state.borrow().draw_cursor(context)?;
+            }
         }
         Ok(())
     }
@@ -428,6 +736,7 @@ impl BWidget for LinePlot {
         if self.keep_points.borrow_mut().update(ctx, event).is_some() {
             queue_draw = true;
         }
+        self.on_cursor.borrow_mut().update(ctx, event);
         for s in self.series.borrow_mut().iter_mut() {
             if let Some(v) = s.x.update(ctx, event) {
                 s.x_data.push_back(v);
@@ -7,12 +7,17 @@ extern crate lazy_static;
 mod backend;
 mod bscript;
 mod cairo_backend;
+mod console;
 mod containers;
 mod editor;
+mod keybinds;
 mod lineplot;
+mod playback;
+mod session;
 mod table;
 mod util;
 mod widgets;
+mod wizard;
 
 use anyhow::{anyhow, bail, Result};
 use arcstr::ArcStr;
@@ -24,7 +29,8 @@ use fxhash::{FxBuildHasher, FxHashMap};
 use gdk::{self, prelude::*};
 use glib::{clone, idle_add_local, idle_add_local_once, source::PRIORITY_LOW};
 use gtk::{self, prelude::*, Adjustment, Application, ApplicationWindow};
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
+use log::warn;
 use netidx::{
     chars::Chars,
     config::Config,
@@ -35,14 +41,15 @@ use netidx::{
     subscriber::{DesiredAuth, Dval, Event, SubId, UpdatesFlags, Value},
 };
 use netidx_bscript::{
-    expr::{ExprId, ExprKind},
+    expr::{Expr, ExprId, ExprKind, FnDef},
     vm::{self, ExecCtx, Node, RpcCallId, TimerId},
 };
 use netidx_protocols::view;
 use radix_trie::Trie;
+use serde_derive::{Deserialize, Serialize};
 use std::{
     cell::{Cell, RefCell},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt, mem,
     path::PathBuf,
     rc::Rc,
@@ -51,7 +58,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use util::{ask_modal, err_modal};
 
@@ -82,6 +89,7 @@ fn default_view(path: Path) -> view::Widget {
             column_widths: ExprKind::Constant(Value::Null).to_expr(),
             columns_resizable: ExprKind::Constant(Value::True).to_expr(),
             column_types: ExprKind::Constant(Value::Null).to_expr(),
+            footer: ExprKind::Constant(Value::Null).to_expr(),
             selection_mode: ExprKind::Constant(Value::from("single")).to_expr(),
             selection: ExprKind::Constant(Value::Null).to_expr(),
             show_row_name: ExprKind::Constant(Value::True).to_expr(),
@@ -108,7 +116,22 @@ enum WidgetPath {
     GridRow(usize),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// The css name of the widget at `path`, e.g. netidx-b1-g2-0 for
+/// the grid item at row 2 column 0 of the second child of the root.
+fn css_name(path: &[WidgetPath]) -> String {
+    let mut name = String::from("netidx");
+    for p in path {
+        match p {
+            WidgetPath::Leaf => (),
+            WidgetPath::Box(i) => name.push_str(&format!("-b{}", i)),
+            WidgetPath::GridItem(i, j) => name.push_str(&format!("-g{}-{}", i, j)),
+            WidgetPath::GridRow(i) => name.push_str(&format!("-r{}", i)),
+        }
+    }
+    name
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 enum ViewLoc {
     File(PathBuf),
     Netidx(Path),
@@ -152,7 +175,12 @@ enum ToGui {
     UpdateRpc(RpcCallId, Value),
     UpdateTimer(TimerId),
     UpdatePoll(Path),
+    Flush,
+    TableExport(Path, PathBuf),
     TableResolved(Path, resolver_client::Table),
+    Playback(Option<Path>),
+    SetTitle(String),
+    WidgetState(String, Value),
     ShowError(String),
     SaveError(String),
     Terminate,
@@ -163,10 +191,16 @@ enum FromGui {
     Navigate(ViewLoc),
     Render(view::Widget),
     ResolveTable(Path),
+    List(Path, oneshot::Sender<Result<Vec<Path>>>),
     Save(ViewLoc, view::Widget, oneshot::Sender<Result<()>>),
     CallRpc(Path, Vec<(Chars, Value)>, RpcCallId),
     SetTimer(TimerId, Duration),
     Poll(Path),
+    ExportTable(PathBuf, Vec<Vec<backend::ExportCell>>),
+    StartPlayback(Path),
+    PlaybackStarted(Path),
+    StopPlayback,
+    SetRefreshRate(Option<u32>),
     Updated,
     Terminate,
 }
@@ -180,6 +214,10 @@ struct WidgetCtx {
     view_saved: Cell<bool>,
     fns: Trie<String, ()>,
     vars: Trie<String, Trie<String, ()>>,
+    translations: FxHashMap<String, String>,
+    view_css: String,
+    css: gtk::CssProvider,
+    widget_state: FxHashMap<String, Value>,
     radio_groups:
         FxHashMap<String, (Rc<Cell<bool>>, IndexSet<gtk::RadioButton, FxBuildHasher>)>,
 }
@@ -193,7 +231,7 @@ impl vm::Ctx for WidgetCtx {
         path: Path,
         _ref_id: ExprId,
     ) -> Dval {
-        let dv = self.backend.subscriber.subscribe(path);
+        let dv = self.backend.subscribe(path);
         dv.updates(flags, self.backend.updates.clone());
         dv
     }
@@ -250,33 +288,110 @@ impl vm::Ctx for WidgetCtx {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScaleMode {
+    Fit,
+    Fill,
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
 #[derive(Debug)]
 enum ImageSpec {
-    Icon { name: Chars, size: gtk::IconSize },
-    PixBuf { bytes: Bytes, width: Option<u32>, height: Option<u32>, keep_aspect: bool },
+    Icon {
+        name: Chars,
+        size: gtk::IconSize,
+        pixel_size: i32,
+    },
+    PixBuf {
+        bytes: Bytes,
+        width: Option<u32>,
+        height: Option<u32>,
+        scale: ScaleMode,
+        region: Option<Region>,
+    },
 }
 
 impl ImageSpec {
     fn get_pixbuf(&self) -> Option<gdk_pixbuf::Pixbuf> {
         match self {
             Self::Icon { .. } => None,
-            Self::PixBuf { bytes, width, height, keep_aspect } => {
-                let width = width.map(|i| i as i32).unwrap_or(-1);
-                let height = height.map(|i| i as i32).unwrap_or(-1);
+            Self::PixBuf { bytes, width, height, scale, region } => {
                 let bytes = glib::Bytes::from_owned(bytes.clone());
                 let stream = gio::MemoryInputStream::from_bytes(&bytes);
-                gdk_pixbuf::Pixbuf::from_stream_at_scale(
-                    &stream,
-                    width,
-                    height,
-                    *keep_aspect,
-                    gio::Cancellable::NONE,
-                )
-                .ok()
+                let pb = gdk_pixbuf::Pixbuf::from_stream(&stream, gio::Cancellable::NONE)
+                    .ok()?;
+                let pb = match region {
+                    None => pb,
+                    Some(r) => {
+                        if r.x < 0
+                            || r.y < 0
+                            || r.width <= 0
+                            || r.height <= 0
+                            || r.x + r.width > pb.width()
+                            || r.y + r.height > pb.height()
+                        {
+                            return None;
+                        }
+                        pb.new_subpixbuf(r.x, r.y, r.width, r.height)
+                    }
+                };
+                Self::scale_pixbuf(pb, *width, *height, *scale)
             }
         }
     }
 
+    fn scale_pixbuf(
+        pb: gdk_pixbuf::Pixbuf,
+        width: Option<u32>,
+        height: Option<u32>,
+        scale: ScaleMode,
+    ) -> Option<gdk_pixbuf::Pixbuf> {
+        use gdk_pixbuf::InterpType;
+        let (w, h) = (pb.width() as f64, pb.height() as f64);
+        let (dw, dh) = match (width, height) {
+            (None, None) => return Some(pb),
+            (Some(dw), None) if scale != ScaleMode::Stretch => {
+                (dw as f64, h * dw as f64 / w)
+            }
+            (None, Some(dh)) if scale != ScaleMode::Stretch => {
+                (w * dh as f64 / h, dh as f64)
+            }
+            (dw, dh) => {
+                (dw.map(|v| v as f64).unwrap_or(w), dh.map(|v| v as f64).unwrap_or(h))
+            }
+        };
+        let (sw, sh) = match scale {
+            ScaleMode::Stretch => (dw, dh),
+            ScaleMode::Fit => {
+                let r = f64::min(dw / w, dh / h);
+                (w * r, h * r)
+            }
+            ScaleMode::Fill => {
+                let r = f64::max(dw / w, dh / h);
+                (w * r, h * r)
+            }
+        };
+        let sw = (sw.round() as i32).max(1);
+        let sh = (sh.round() as i32).max(1);
+        let pb = pb.scale_simple(sw, sh, InterpType::Bilinear)?;
+        if scale != ScaleMode::Fill {
+            Some(pb)
+        } else {
+            // crop the excess, keeping the center of the image
+            let cw = (dw.round() as i32).max(1).min(sw);
+            let ch = (dh.round() as i32).max(1).min(sh);
+            Some(pb.new_subpixbuf((sw - cw) / 2, (sh - ch) / 2, cw, ch))
+        }
+    }
+
     fn get(&self) -> gtk::Image {
         let image = gtk::Image::new();
         self.apply(&image);
@@ -286,8 +401,14 @@ impl ImageSpec {
     // apply the spec to an existing image
     fn apply(&self, image: &gtk::Image) {
         match self {
-            Self::Icon { name, size } => image.set_from_icon_name(Some(&**name), *size),
-            Self::PixBuf { .. } => image.set_from_pixbuf(self.get_pixbuf().as_ref()),
+            Self::Icon { name, size, pixel_size } => {
+                image.set_from_icon_name(Some(&**name), *size);
+                image.set_pixel_size(*pixel_size);
+            }
+            Self::PixBuf { .. } => {
+                image.set_pixel_size(-1);
+                image.set_from_pixbuf(self.get_pixbuf().as_ref())
+            }
         }
     }
 }
@@ -296,11 +417,15 @@ impl FromValue for ImageSpec {
     fn from_value(v: Value) -> Result<Self> {
         match v {
             Value::String(name) => {
-                Ok(Self::Icon { name, size: gtk::IconSize::SmallToolbar })
-            }
-            Value::Bytes(bytes) => {
-                Ok(Self::PixBuf { bytes, width: None, height: None, keep_aspect: true })
+                Ok(Self::Icon { name, size: gtk::IconSize::SmallToolbar, pixel_size: -1 })
             }
+            Value::Bytes(bytes) => Ok(Self::PixBuf {
+                bytes,
+                width: None,
+                height: None,
+                scale: ScaleMode::Fit,
+                region: None,
+            }),
             Value::Array(elts) => match &*elts {
                 [Value::String(name), Value::String(size)] => {
                     let size = match &**size {
@@ -311,7 +436,12 @@ impl FromValue for ImageSpec {
                         "dialog" => gtk::IconSize::Dialog,
                         _ => bail!("invalid size"),
                     };
-                    Ok(Self::Icon { name: name.clone(), size })
+                    Ok(Self::Icon { name: name.clone(), size, pixel_size: -1 })
+                }
+                [Value::String(name), size] if size.clone().cast_to::<u32>().is_ok() => {
+                    let pixel_size = size.clone().cast_to::<u32>()? as i32;
+                    let size = gtk::IconSize::Dialog;
+                    Ok(Self::Icon { name: name.clone(), size, pixel_size })
                 }
                 _ => {
                     let mut alist =
@@ -328,7 +458,29 @@ impl FromValue for ImageSpec {
                         .remove("keep-aspect")
                         .and_then(|v| v.cast_to::<bool>().ok())
                         .unwrap_or(true);
-                    Ok(Self::PixBuf { bytes, width, height, keep_aspect })
+                    let scale = match alist.remove("scale") {
+                        None if keep_aspect => ScaleMode::Fit,
+                        None => ScaleMode::Stretch,
+                        Some(v) => match &*v.cast_to::<Chars>()? {
+                            "fit" => ScaleMode::Fit,
+                            "fill" => ScaleMode::Fill,
+                            "stretch" => ScaleMode::Stretch,
+                            _ => bail!("invalid scale mode"),
+                        },
+                    };
+                    let region = match alist.remove("region") {
+                        None => None,
+                        Some(v) => match &*v.cast_to::<Vec<u32>>()? {
+                            [x, y, width, height] => Some(Region {
+                                x: *x as i32,
+                                y: *y as i32,
+                                width: *width as i32,
+                                height: *height as i32,
+                            }),
+                            _ => bail!("region must be [x, y, width, height]"),
+                        },
+                    };
+                    Ok(Self::PixBuf { bytes, width, height, scale, region })
                 }
             },
             _ => bail!("expected bytes or array"),
@@ -368,6 +520,95 @@ fn set_common_props<T: IsA<gtk::Widget> + 'static>(props: &view::WidgetProps, t:
     t.set_margin_end(props.margin_end as i32);
 }
 
+// wrap every event handler and keybind of w, and of all it's
+// children, in guard(cond, handler)
+fn interlock(w: &mut view::Widget, cond: &Expr) {
+    fn guard(e: &mut Expr, cond: &Expr) {
+        let args = vec![cond.clone(), mem::take(e)];
+        *e = ExprKind::Apply { function: "guard".into(), args }.to_expr();
+    }
+    if let Some(props) = &mut w.props {
+        for k in &mut props.keybinds {
+            guard(&mut k.expr, cond);
+        }
+    }
+    match &mut w.kind {
+        view::WidgetKind::BScript(_)
+        | view::WidgetKind::Label(_)
+        | view::WidgetKind::ProgressBar(_) => (),
+        view::WidgetKind::Table(t) => {
+            guard(&mut t.on_select, cond);
+            guard(&mut t.on_activate, cond);
+            guard(&mut t.on_edit, cond);
+            guard(&mut t.on_header_click, cond);
+        }
+        view::WidgetKind::Image(i) => guard(&mut i.on_click, cond),
+        view::WidgetKind::Button(b) => guard(&mut b.on_click, cond),
+        view::WidgetKind::LinkButton(b) => guard(&mut b.on_activate_link, cond),
+        view::WidgetKind::Switch(s) => guard(&mut s.on_change, cond),
+        view::WidgetKind::ToggleButton(t) | view::WidgetKind::CheckButton(t) => {
+            guard(&mut t.toggle.on_change, cond)
+        }
+        view::WidgetKind::RadioButton(r) => guard(&mut r.on_toggled, cond),
+        view::WidgetKind::ComboBox(c) => guard(&mut c.on_change, cond),
+        view::WidgetKind::Entry(e) => {
+            guard(&mut e.on_change, cond);
+            guard(&mut e.on_activate, cond);
+        }
+        view::WidgetKind::SearchEntry(e) => {
+            guard(&mut e.on_search_changed, cond);
+            guard(&mut e.on_activate, cond);
+        }
+        view::WidgetKind::Scale(s) => guard(&mut s.on_change, cond),
+        view::WidgetKind::SpinButton(s) => guard(&mut s.on_change, cond),
+        view::WidgetKind::Calendar(c) => guard(&mut c.on_change, cond),
+        view::WidgetKind::RadioGroup(r) => guard(&mut r.on_change, cond),
+        view::WidgetKind::LinePlot(p) => guard(&mut p.on_cursor, cond),
+        view::WidgetKind::Frame(f) => {
+            if let Some(c) = &mut f.child {
+                interlock(c, cond)
+            }
+        }
+        view::WidgetKind::Box(b) => {
+            for c in &mut b.children {
+                interlock(c, cond)
+            }
+        }
+        view::WidgetKind::BoxChild(view::BoxChild { widget, .. })
+        | view::WidgetKind::GridChild(view::GridChild { widget, .. })
+        | view::WidgetKind::NotebookPage(view::NotebookPage { widget, .. })
+        | view::WidgetKind::Breakpoint(view::Breakpoint { widget, .. }) => {
+            interlock(widget, cond)
+        }
+        view::WidgetKind::Grid(g) => {
+            for c in &mut g.rows {
+                interlock(c, cond)
+            }
+        }
+        view::WidgetKind::GridRow(r) => {
+            for c in &mut r.columns {
+                interlock(c, cond)
+            }
+        }
+        view::WidgetKind::Paned(p) => {
+            for c in [&mut p.first_child, &mut p.second_child].into_iter().flatten() {
+                interlock(c, cond)
+            }
+        }
+        view::WidgetKind::Notebook(n) => {
+            guard(&mut n.on_switch_page, cond);
+            for c in &mut n.children {
+                interlock(c, cond)
+            }
+        }
+        view::WidgetKind::Responsive(r) => {
+            for c in &mut r.children {
+                interlock(c, cond)
+            }
+        }
+    }
+}
+
 trait BWidget {
     fn update(
         &mut self,
@@ -398,21 +639,124 @@ trait BWidget {
             util::set_highlight(w, h);
         }
     }
+
+    /// name the widget, and it's children, after their position in
+    /// the view, so they can be selected by the view's css.
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        if let Some(w) = self.root() {
+            w.set_widget_name(&css_name(path));
+        }
+    }
+
+    /// publish the runtime state of the widget, the current page,
+    /// the selection, etc, so it can be read with widget("name/field")
+    fn export_state(&self, _state: WidgetState) {}
+}
+
+/// The runtime state of a named widget. Changes are sent via the
+/// gui loop, since they usually happen inside signal handlers.
+#[derive(Clone)]
+struct WidgetState {
+    name: String,
+    to_gui: glib::Sender<ToGui>,
+}
+
+impl WidgetState {
+    fn new(ctx: &BSCtx, name: &str) -> Self {
+        let to_gui = ctx.borrow().user.backend.to_gui.clone();
+        WidgetState { name: name.into(), to_gui }
+    }
+
+    fn set(&self, field: &str, value: Value) {
+        let key = format!("{}/{}", self.name, field);
+        let _: result::Result<_, _> = self.to_gui.send(ToGui::WidgetState(key, value));
+    }
+}
+
+/// Holds back the netidx updates for a widget with a refresh rate,
+/// see `view::WidgetProps::refresh_rate`
+struct Throttle {
+    interval: Duration,
+    last: Instant,
+    scheduled: bool,
+    pending: IndexMap<SubId, Value, FxBuildHasher>,
+}
+
+impl Throttle {
+    fn new(rate: Option<u32>) -> Option<Self> {
+        rate.filter(|r| *r > 0).map(|r| {
+            let interval = Duration::from_secs(1) / r;
+            let now = Instant::now();
+            Throttle {
+                interval,
+                last: now.checked_sub(interval).unwrap_or(now),
+                scheduled: false,
+                pending: IndexMap::default(),
+            }
+        })
+    }
+
+    /// Ask for a flush when the widget may next be redrawn
+    fn schedule(&mut self, ctx: BSCtxRef) {
+        if !self.scheduled {
+            self.scheduled = true;
+            let now = Instant::now();
+            let wait = (self.last + self.interval).saturating_duration_since(now);
+            let to_gui = ctx.user.backend.to_gui.clone();
+            glib::timeout_add_local_once(wait, move || {
+                let _: Result<_, _> = to_gui.send(ToGui::Flush);
+            });
+        }
+    }
+
+    /// Take the pending updates if it is time to deliver them
+    fn flush(&mut self, ctx: BSCtxRef) -> Option<IndexMap<SubId, Value, FxBuildHasher>> {
+        self.scheduled = false;
+        if self.pending.is_empty() {
+            None
+        } else {
+            let now = Instant::now();
+            if now < self.last + self.interval {
+                self.schedule(ctx);
+                None
+            } else {
+                self.last = now;
+                Some(mem::take(&mut self.pending))
+            }
+        }
+    }
 }
 
 struct Widget {
     sensitive: BSNode,
     visible: BSNode,
+    keybinds: keybinds::Keybinds,
+    throttle: Option<Throttle>,
     widget: Box<dyn BWidget>,
 }
 
 impl Widget {
     fn new(
         ctx: &BSCtx,
-        spec: view::Widget,
+        mut spec: view::Widget,
         scope: Path,
         selected_path: gtk::Label,
     ) -> Self {
+        let cond = spec.props.as_ref().map(|p| p.interlock.clone());
+        if let Some(cond) = cond {
+            if cond.kind != ExprKind::Constant(Value::Null) {
+                interlock(&mut spec, &cond)
+            }
+        }
+        if let Some(props) = &spec.props {
+            Widget::load_translations(ctx, &props.translations);
+            Widget::load_functions(ctx, &props.functions);
+            if !props.css.is_empty() {
+                let css = &mut ctx.borrow_mut().user.view_css;
+                css.push_str(&props.css);
+                css.push('\n');
+            }
+        }
         let widget: Box<dyn BWidget> = match spec.kind {
             view::WidgetKind::BScript(spec) => {
                 Box::new(widgets::BScript::new(ctx, scope.clone(), spec))
@@ -464,6 +808,21 @@ impl Widget {
             view::WidgetKind::Scale(spec) => {
                 Box::new(widgets::Scale::new(ctx, spec, scope.clone(), selected_path))
             }
+            view::WidgetKind::SpinButton(spec) => Box::new(widgets::SpinButton::new(
+                ctx,
+                spec,
+                scope.clone(),
+                selected_path,
+            )),
+            view::WidgetKind::Calendar(spec) => {
+                Box::new(widgets::Calendar::new(ctx, spec, scope.clone(), selected_path))
+            }
+            view::WidgetKind::RadioGroup(spec) => Box::new(widgets::RadioGroup::new(
+                ctx,
+                spec,
+                scope.clone(),
+                selected_path,
+            )),
             view::WidgetKind::ComboBox(spec) => {
                 Box::new(widgets::ComboBox::new(ctx, spec, scope.clone(), selected_path))
             }
@@ -523,11 +882,23 @@ impl Widget {
             view::WidgetKind::LinePlot(spec) => {
                 Box::new(lineplot::LinePlot::new(ctx, spec, scope.clone(), selected_path))
             }
+            view::WidgetKind::Responsive(spec) => Box::new(containers::Responsive::new(
+                ctx,
+                spec,
+                scope.clone(),
+                selected_path,
+            )),
+            view::WidgetKind::Breakpoint(view::Breakpoint { widget: w, .. }) => {
+                Box::new(Widget::new(ctx, (&*w).clone(), scope.clone(), selected_path))
+            }
         };
         let props = spec.props.as_ref().unwrap_or(&DEFAULT_PROPS);
         if let Some(r) = widget.root() {
             set_common_props(props, r);
         }
+        if !props.name.is_empty() {
+            widget.export_state(WidgetState::new(ctx, &props.name));
+        }
         let sensitive = BSNode::compile(
             &mut ctx.borrow_mut(),
             scope.clone(),
@@ -535,6 +906,8 @@ impl Widget {
         );
         let visible =
             BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), props.visible.clone());
+        let keybinds =
+            keybinds::Keybinds::new(ctx, scope.clone(), &props.keybinds, widget.root());
         if let Some(b) = sensitive
             .current(&mut ctx.borrow_mut())
             .and_then(|v| v.cast_to::<bool>().ok())
@@ -546,7 +919,39 @@ impl Widget {
         {
             widget.set_visible(b);
         }
-        Self { sensitive, visible, widget }
+        let throttle = Throttle::new(props.refresh_rate);
+        Self { sensitive, visible, keybinds, throttle, widget }
+    }
+
+    // load the translations for the best matching locale, the
+    // locales glib returns are ordered from most to least specific.
+    fn load_translations(
+        ctx: &BSCtx,
+        translations: &BTreeMap<String, BTreeMap<String, String>>,
+    ) {
+        if !translations.is_empty() {
+            let table = glib::language_names()
+                .into_iter()
+                .find_map(|locale| translations.get(locale.as_str()));
+            if let Some(table) = table {
+                let mut ctx = ctx.borrow_mut();
+                for (k, v) in table {
+                    ctx.user.translations.insert(k.clone(), v.clone());
+                }
+            }
+        }
+    }
+
+    // functions must be defined before any expression that calls them
+    // is compiled, so this happens before the widget is built.
+    fn load_functions(ctx: &BSCtx, functions: &[FnDef]) {
+        let mut ctx = ctx.borrow_mut();
+        for def in functions {
+            let name = def.name.clone();
+            if let Err(e) = ctx.define_fn(def.clone()) {
+                warn!("failed to define function {}, {}", name, e)
+            }
+        }
     }
 }
 
@@ -557,17 +962,22 @@ impl BWidget for Widget {
         waits: &mut Vec<oneshot::Receiver<()>>,
         event: &vm::Event<LocalEvent>,
     ) {
-        if let Some(b) =
-            self.sensitive.update(ctx, event).and_then(|v| v.cast_to::<bool>().ok())
-        {
-            self.set_sensitive(b);
-        }
-        if let Some(b) =
-            self.visible.update(ctx, event).and_then(|v| v.cast_to::<bool>().ok())
-        {
-            self.set_visible(b);
+        match (&mut self.throttle, event) {
+            (Some(t), vm::Event::Netidx(id, v)) => {
+                t.pending.insert(*id, v.clone());
+                t.schedule(ctx);
+                return;
+            }
+            (Some(t), vm::Event::User(LocalEvent::Flush)) => {
+                if let Some(pending) = t.flush(ctx) {
+                    for (id, v) in pending {
+                        self.update_now(ctx, waits, &vm::Event::Netidx(id, v))
+                    }
+                }
+            }
+            (_, _) => (),
         }
-        self.widget.update(ctx, waits, event)
+        self.update_now(ctx, waits, event)
     }
 
     fn root(&self) -> Option<&gtk::Widget> {
@@ -585,6 +995,36 @@ impl BWidget for Widget {
     fn set_highlight(&self, path: std::slice::Iter<WidgetPath>, h: bool) {
         self.widget.set_highlight(path, h)
     }
+
+    fn set_css_names(&self, path: &mut Vec<WidgetPath>) {
+        self.widget.set_css_names(path)
+    }
+
+    fn export_state(&self, state: WidgetState) {
+        self.widget.export_state(state)
+    }
+}
+
+impl Widget {
+    fn update_now(
+        &mut self,
+        ctx: BSCtxRef,
+        waits: &mut Vec<oneshot::Receiver<()>>,
+        event: &vm::Event<LocalEvent>,
+    ) {
+        if let Some(b) =
+            self.sensitive.update(ctx, event).and_then(|v| v.cast_to::<bool>().ok())
+        {
+            self.set_sensitive(b);
+        }
+        if let Some(b) =
+            self.visible.update(ctx, event).and_then(|v| v.cast_to::<bool>().ok())
+        {
+            self.set_visible(b);
+        }
+        self.keybinds.update(ctx, event);
+        self.widget.update(ctx, waits, event)
+    }
 }
 
 fn make_crumbs(ctx: &BSCtx, loc: &ViewLoc) -> gtk::ScrolledWindow {
@@ -673,10 +1113,19 @@ fn make_crumbs(ctx: &BSCtx, loc: &ViewLoc) -> gtk::ScrolledWindow {
 struct View {
     root: gtk::Box,
     widget: Widget,
+    console: Rc<RefCell<Option<console::Console>>>,
 }
 
 impl View {
-    fn new(ctx: &BSCtx, path: &ViewLoc, spec: view::Widget) -> View {
+    fn new(
+        ctx: &BSCtx,
+        path: &ViewLoc,
+        spec: view::Widget,
+        console: &Rc<RefCell<Option<console::Console>>>,
+    ) -> View {
+        if let Some(console) = &*console.borrow() {
+            console.reset();
+        }
         let selected_path = gtk::Label::new(None);
         selected_path.set_halign(gtk::Align::Start);
         selected_path.set_margin_start(0);
@@ -687,7 +1136,23 @@ impl View {
         selected_path_window
             .set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
         selected_path_window.add(&selected_path);
-        let widget = Widget::new(ctx, spec.clone(), Path::root(), selected_path.clone());
+        ctx.borrow_mut().user.translations.clear();
+        ctx.borrow_mut().user.view_css.clear();
+        ctx.borrow_mut().user.widget_state.clear();
+        let mut widget =
+            Widget::new(ctx, spec.clone(), Path::root(), selected_path.clone());
+        {
+            let ctx = ctx.borrow();
+            if let Err(e) = ctx.user.css.load_from_data(ctx.user.view_css.as_bytes()) {
+                warn!("failed to load the view css, {}", e)
+            }
+        }
+        widget.set_css_names(&mut vec![]);
+        // the refresh rate of the root widget applies to the whole
+        // view, and is enforced by the backend
+        let rate = spec.props.as_ref().and_then(|p| p.refresh_rate);
+        ctx.borrow().user.backend.set_refresh_rate(rate);
+        widget.throttle = None;
         let root = gtk::Box::new(gtk::Orientation::Vertical, 5);
         root.set_margin(2);
         root.add(&make_crumbs(ctx, path));
@@ -699,7 +1164,7 @@ impl View {
         root.add(&gtk::Separator::new(gtk::Orientation::Horizontal));
         root.add(&selected_path_window);
         root.set_child_packing(&selected_path, false, false, 1, gtk::PackType::End);
-        View { root, widget }
+        View { root, widget, console: console.clone() }
     }
 
     fn root(&self) -> &gtk::Widget {
@@ -713,6 +1178,9 @@ impl View {
         event: &vm::Event<LocalEvent>,
     ) {
         self.widget.update(ctx, waits, event);
+        if let Some(console) = &*self.console.borrow() {
+            console.update(ctx, event);
+        }
     }
 }
 
@@ -731,6 +1199,24 @@ fn setup_css(screen: &gdk::Screen) {
     gtk::StyleContext::add_provider_for_screen(screen, &style, 800);
 }
 
+// apply the theme preference and the site style sheet given on the
+// command line. The view's style sheet is applied on top of the site
+// style sheet.
+fn setup_theme(css: Option<&PathBuf>, dark: bool) {
+    if dark {
+        if let Some(settings) = gtk::Settings::default() {
+            settings.set_gtk_application_prefer_dark_theme(true);
+        }
+    }
+    if let (Some(css), Some(screen)) = (css, gdk::Screen::default()) {
+        let style = gtk::CssProvider::new();
+        match style.load_from_path(&css.to_string_lossy()) {
+            Err(e) => warn!("failed to load css {}, {}", css.display(), e),
+            Ok(()) => gtk::StyleContext::add_provider_for_screen(&screen, &style, 600),
+        }
+    }
+}
+
 fn choose_location(parent: &gtk::ApplicationWindow, save: bool) -> Option<ViewLoc> {
     enum W {
         File(gtk::FileChooserWidget),
@@ -800,6 +1286,35 @@ fn choose_location(parent: &gtk::ApplicationWindow, save: bool) -> Option<ViewLo
     res
 }
 
+fn choose_archive(parent: &gtk::ApplicationWindow) -> Option<Path> {
+    let d = gtk::Dialog::with_buttons(
+        Some("Playback Archive"),
+        Some(parent),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR,
+        &[("Cancel", gtk::ResponseType::Cancel), ("Play", gtk::ResponseType::Accept)],
+    );
+    let root = d.content_area();
+    let b = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    let l = gtk::Label::new(Some("Recorder Base Path:"));
+    let e = gtk::Entry::new();
+    e.set_activates_default(true);
+    b.pack_start(&l, true, true, 5);
+    b.pack_start(&e, true, true, 5);
+    root.add(&b);
+    root.show_all();
+    d.set_default_response(gtk::ResponseType::Accept);
+    let res = match d.run() {
+        gtk::ResponseType::Accept if Path::is_absolute(&*e.text()) => {
+            Some(Path::from(String::from(e.text())))
+        }
+        _ => None,
+    };
+    unsafe {
+        d.destroy();
+    }
+    res
+}
+
 fn save_view(
     ctx: &BSCtx,
     save_loc: &Rc<RefCell<Option<ViewLoc>>>,
@@ -863,8 +1378,14 @@ lazy_static! {
         margin_start: 0,
         margin_end: 0,
         keybinds: vec![],
+        translations: BTreeMap::new(),
+        functions: vec![],
+        css: String::new(),
+        name: String::new(),
         sensitive: ExprKind::Constant(Value::True).to_expr(),
         visible: ExprKind::Constant(Value::True).to_expr(),
+        interlock: ExprKind::Constant(Value::Null).to_expr(),
+        refresh_rate: None,
     };
 }
 
@@ -880,7 +1401,13 @@ fn update_single(
     }
 }
 
-fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
+fn run_gui(
+    ctx: BSCtx,
+    app: Application,
+    session: Rc<RefCell<session::Session>>,
+    swin: Rc<session::Window>,
+    to_gui: glib::Receiver<ToGui>,
+) {
     let group = gtk::WindowGroup::new();
     group.add_window(&ctx.borrow().user.window);
     let headerbar = gtk::HeaderBar::new();
@@ -899,10 +1426,14 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
         gtk::Image::from_icon_name(Some("open-menu"), gtk::IconSize::SmallToolbar);
     prefs_button.set_image(Some(&menu_img));
     let main_menu = gio::Menu::new();
+    main_menu.append(Some("Back"), Some("win.back"));
     main_menu.append(Some("Go"), Some("win.go"));
     main_menu.append(Some("Save View As"), Some("win.save_as"));
+    main_menu.append(Some("New View Wizard"), Some("win.wizard"));
     main_menu.append(Some("Raw View"), Some("win.raw_view"));
+    main_menu.append(Some("Playback Archive"), Some("win.playback"));
     main_menu.append(Some("Bscript Tracing"), Some("win.bscript_tracing"));
+    main_menu.append(Some("Bscript Console"), Some("win.console"));
     main_menu.append(Some("New Window"), Some("win.new_window"));
     prefs_button.set_use_popover(true);
     prefs_button.set_menu_model(Some(&main_menu));
@@ -920,6 +1451,11 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
         w.show_all();
         if let Some(screen) = WidgetExt::screen(w) {
             setup_css(&screen);
+            let css = &ctx.borrow().user.css;
+            gtk::StyleContext::add_provider_for_screen(&screen, css, 700);
+            w.connect_destroy(clone!(@strong css => move |_| {
+                gtk::StyleContext::remove_provider_for_screen(&screen, &css)
+            }));
         }
     }
     let save_loc: Rc<RefCell<Option<ViewLoc>>> = Rc::new(RefCell::new(None));
@@ -930,10 +1466,14 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
     let editor: Rc<RefCell<Option<Editor>>> = Rc::new(RefCell::new(None));
     let editor_window: Rc<RefCell<Option<gtk::Window>>> = Rc::new(RefCell::new(None));
     let highlight: Rc<RefCell<Vec<WidgetPath>>> = Rc::new(RefCell::new(vec![]));
+    let playback: Rc<RefCell<Option<playback::Playback>>> = Rc::new(RefCell::new(None));
+    let console: Rc<RefCell<Option<console::Console>>> = Rc::new(RefCell::new(None));
+    let back_to: Rc<RefCell<Option<ViewLoc>>> = Rc::new(RefCell::new(None));
     ctx.borrow().user.window.connect_delete_event(clone!(
-        @weak ctx => @default-return Inhibit(false), move |w, _| {
+        @weak ctx, @strong session => @default-return Inhibit(false), move |w, _| {
             let saved = ctx.borrow().user.view_saved.get();
             if saved || ask_modal(w, "Unsaved view will be lost.") {
+                session.borrow_mut().close(w);
                 ctx.borrow().user.backend.terminate();
                 Inhibit(false)
             } else {
@@ -985,6 +1525,25 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             save_view(&ctx, &save_loc, &current_spec, b, false)
         }
     ));
+    let back_act = gio::SimpleAction::new("back", None);
+    back_act.set_enabled(swin.has_history());
+    ctx.borrow().user.window.add_action(&back_act);
+    app.set_accels_for_action("win.back", &["<Alt>Left"]);
+    back_act.connect_activate(clone!(
+        @weak ctx, @strong swin, @strong back_to => move |_, _| {
+        let (saved, window) = {
+            let ctx = ctx.borrow();
+            let saved = ctx.user.view_saved.get();
+            let window = ctx.user.window.clone();
+            (saved, window)
+        };
+        if saved || ask_modal(&window, "Unsaved view will be lost.") {
+            if let Some(loc) = swin.pop_history() {
+                *back_to.borrow_mut() = Some(loc.clone());
+                ctx.borrow().user.backend.navigate(loc)
+            }
+        }
+    }));
     let go_act = gio::SimpleAction::new("go", None);
     ctx.borrow().user.window.add_action(&go_act);
     go_act.connect_activate(clone!(@weak ctx => move |_, _| {
@@ -1010,6 +1569,21 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             save_view(&ctx, &save_loc, &current_spec, &save_button, true)
         }
     ));
+    let wizard_act = gio::SimpleAction::new("wizard", None);
+    ctx.borrow().user.window.add_action(&wizard_act);
+    wizard_act.connect_activate(clone!(@weak ctx => move |_, _| {
+        let (saved, window) = {
+            let ctx = ctx.borrow();
+            let saved = ctx.user.view_saved.get();
+            let window = ctx.user.window.clone();
+            (saved, window)
+        };
+        if saved || ask_modal(&window, "Unsaved view will be lost.") {
+            if let Some(spec) = wizard::run(&window) {
+                ctx.borrow().user.backend.render(spec);
+            }
+        }
+    }));
     let raw_view_act =
         gio::SimpleAction::new_stateful("raw_view", None, false.to_variant());
     ctx.borrow().user.window.add_action(&raw_view_act);
@@ -1031,6 +1605,14 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             }
         }
     }));
+    let playback_act = gio::SimpleAction::new("playback", None);
+    ctx.borrow().user.window.add_action(&playback_act);
+    playback_act.connect_activate(clone!(@weak ctx => move |_, _| {
+        let window = ctx.borrow().user.window.clone();
+        if let Some(archive) = choose_archive(&window) {
+            ctx.borrow().user.backend.start_playback(archive);
+        }
+    }));
     let bscript_tracing_act =
         gio::SimpleAction::new_stateful("bscript_tracing", None, true.to_variant());
     ctx.borrow().user.window.add_action(&bscript_tracing_act);
@@ -1042,6 +1624,23 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             a.change_state(&new_v.to_variant());
         }
     }));
+    let console_act = gio::SimpleAction::new("console", None);
+    ctx.borrow().user.window.add_action(&console_act);
+    console_act.connect_activate(clone!(
+        @weak ctx, @strong console, @strong group => move |_, _| {
+        if let Some(c) = &*console.borrow() {
+            c.window().present();
+            return;
+        }
+        let c = console::Console::new(&ctx);
+        c.window().connect_destroy(clone!(@strong console => move |_| {
+            console.borrow_mut().take();
+        }));
+        let w = c.window().clone();
+        ctx.borrow().user.window.connect_destroy(clone!(@weak w => move |_| w.close()));
+        group.add_window(c.window());
+        *console.borrow_mut() = Some(c);
+    }));
     let new_window_act = gio::SimpleAction::new("new_window", None);
     ctx.borrow().user.window.add_action(&new_window_act);
     new_window_act.connect_activate(clone!(@weak app => move |_, _| app.activate()));
@@ -1094,6 +1693,26 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             }
             Continue(true)
         }
+        ToGui::Flush => {
+            if let Some(root) = &mut *current.borrow_mut() {
+                // nothing is waiting for flushed updates to be processed
+                let mut waits = Vec::new();
+                root.update(
+                    &mut ctx.borrow_mut(),
+                    &mut waits,
+                    &vm::Event::User(LocalEvent::Flush),
+                );
+            }
+            Continue(true)
+        }
+        ToGui::TableExport(path, dest) => {
+            update_single(
+                &current,
+                &mut ctx.borrow_mut(),
+                &vm::Event::User(LocalEvent::TableExport(path, dest)),
+            );
+            Continue(true)
+        }
         ToGui::TableResolved(path, table) => {
             let e = vm::Event::User(LocalEvent::TableResolved(path, Rc::new(table)));
             update_single(&current, &mut ctx.borrow_mut(), &e);
@@ -1133,7 +1752,14 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
                     } else {
                         *save_loc.borrow_mut() = None;
                     }
+                    let prev = current_loc.borrow().clone();
+                    let back = back_to.borrow_mut().take();
+                    if prev != loc && back.as_ref() != Some(&loc) {
+                        swin.push_history(prev);
+                    }
+                    back_act.set_enabled(swin.has_history());
                     *current_loc.borrow_mut() = loc;
+                    session.borrow().save();
                     if design_mode.is_active() {
                         design_mode.set_active(false);
                     }
@@ -1145,7 +1771,7 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             ctx.borrow_mut().user.radio_groups.clear();
             ctx.borrow_mut().clear();
             *current_spec.borrow_mut() = spec.clone();
-            let cur = View::new(&ctx, &*current_loc.borrow(), spec);
+            let cur = View::new(&ctx, &*current_loc.borrow(), spec, &console);
             let window = ctx.borrow().user.window.clone();
             window.set_title(&format!("Netidx Browser {}", &*current_loc.borrow()));
             window.add(cur.root());
@@ -1164,6 +1790,39 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             }
             Continue(true)
         }
+        ToGui::Playback(session) => {
+            if let Some(pb) = playback.borrow_mut().take() {
+                headerbar.remove(pb.root());
+            }
+            if let Some(session) = session {
+                let pb = playback::Playback::new(&ctx.borrow().user.backend, &session);
+                headerbar.pack_start(pb.root());
+                *playback.borrow_mut() = Some(pb);
+            }
+            // rebuild the current view so it subscribes to the new data source
+            if let Some(cur) = current.borrow_mut().take() {
+                ctx.borrow().user.window.remove(cur.root());
+            }
+            ctx.borrow_mut().user.radio_groups.clear();
+            ctx.borrow_mut().clear();
+            let spec = current_spec.borrow().clone();
+            let cur = View::new(&ctx, &*current_loc.borrow(), spec, &console);
+            let window = ctx.borrow().user.window.clone();
+            window.add(cur.root());
+            window.show_all();
+            *current.borrow_mut() = Some(cur);
+            Continue(true)
+        }
+        ToGui::SetTitle(title) => {
+            ctx.borrow().user.window.set_title(&title);
+            Continue(true)
+        }
+        ToGui::WidgetState(key, value) => {
+            ctx.borrow_mut().user.widget_state.insert(key.clone(), value.clone());
+            let e = vm::Event::User(LocalEvent::WidgetState(key, value));
+            update_single(&current, &mut ctx.borrow_mut(), &e);
+            Continue(true)
+        }
         ToGui::ShowError(s) => {
             err_modal(&ctx.borrow().user.window, &s);
             Continue(true)
@@ -1231,6 +1890,30 @@ fn add_local_options(application: &gtk::Application) {
         "load the specified view file on load",
         Some("file"),
     );
+    application.add_main_option(
+        "css",
+        glib::Char::from(b's'),
+        glib::OptionFlags::empty(),
+        glib::OptionArg::String,
+        "load the specified gtk css style sheet, e.g. to brand views",
+        Some("file"),
+    );
+    application.add_main_option(
+        "dark",
+        glib::Char::from(b'd'),
+        glib::OptionFlags::empty(),
+        glib::OptionArg::None,
+        "prefer the dark variant of the gtk theme",
+        None,
+    );
+    application.add_main_option(
+        "no-restore",
+        glib::Char::from(b'\0'),
+        glib::OptionFlags::empty(),
+        glib::OptionArg::None,
+        "don't reopen the windows from the last session",
+        None,
+    );
 }
 
 fn parse_auth(cfg: &Config, opts: &glib::VariantDict) -> DesiredAuth {
@@ -1270,6 +1953,13 @@ fn main() {
             Some(path) => Config::load(path.get::<String>().unwrap()).unwrap(),
         };
         let auth = parse_auth(&cfg, opts);
+        let restore = !(opts.contains("path")
+            || opts.contains("file")
+            || opts.contains("no-restore"));
+        let session = Rc::new(RefCell::new(session::Session::new(
+            session::Session::default_path(),
+            restore,
+        )));
         let default_loc = match opts.lookup_value("path", Some(&glib::VariantTy::STRING))
         {
             Some(path) => ViewLoc::Netidx(Path::from(path.get::<String>().unwrap())),
@@ -1278,6 +1968,11 @@ fn main() {
                 None => ViewLoc::Netidx(Path::from("/")),
             },
         };
+        let css = opts
+            .lookup_value("css", Some(&glib::VariantTy::STRING))
+            .map(|file| PathBuf::from(file.get::<String>().unwrap()));
+        let dark = opts.contains("dark");
+        application.connect_startup(move |_| setup_theme(css.as_ref(), dark));
         let (jh, backend) = backend::Backend::new(cfg, auth);
         let new_window_loc = Rc::new(RefCell::new(default_loc.clone()));
         application.connect_activate({
@@ -1287,23 +1982,46 @@ fn main() {
                 let (tx_to_gui, rx_to_gui) = glib::MainContext::channel(PRIORITY_LOW);
                 let raw_view = Arc::new(AtomicBool::new(false));
                 let backend = backend.create_ctx(tx_to_gui, raw_view.clone()).unwrap();
-                let _ = backend.from_gui.unbounded_send(FromGui::Navigate(mem::replace(
-                    &mut *new_window_loc.borrow_mut(),
-                    default_loc.clone(),
-                )));
+                let restored = session.borrow_mut().next_restore();
+                let loc = match &restored {
+                    Some(w) => w.loc.clone(),
+                    None => mem::replace(
+                        &mut *new_window_loc.borrow_mut(),
+                        default_loc.clone(),
+                    ),
+                };
+                let _ = backend.from_gui.unbounded_send(FromGui::Navigate(loc.clone()));
                 let window = ApplicationWindow::new(&app);
+                let current_loc = Rc::new(RefCell::new(loc));
+                let history = restored.as_ref().map(|w| w.history.clone());
+                let swin = Rc::new(session::Window::new(
+                    window.clone(),
+                    current_loc.clone(),
+                    history.unwrap_or_default(),
+                ));
                 let ctx = Rc::new(RefCell::new(bscript::create_ctx(WidgetCtx {
                     backend,
                     raw_view,
                     window: window.clone(),
                     new_window_loc: new_window_loc.clone(),
-                    current_loc: Rc::new(RefCell::new(default_loc.clone())),
+                    current_loc,
                     view_saved: Cell::new(true),
                     fns: Trie::new(),
                     vars: Trie::new(),
+                    translations: HashMap::default(),
+                    view_css: String::new(),
+                    css: gtk::CssProvider::new(),
+                    widget_state: HashMap::default(),
                     radio_groups: HashMap::default(),
                 })));
-                run_gui(ctx, app, rx_to_gui);
+                run_gui(ctx, app.clone(), session.clone(), swin.clone(), rx_to_gui);
+                if let Some(w) = restored {
+                    window.resize(w.width, w.height);
+                }
+                session.borrow_mut().add(swin);
+                if session.borrow().restore_pending() {
+                    idle_add_local_once(move || app.activate());
+                }
             }
         });
         let jh = RefCell::new(Some(jh));
@@ -0,0 +1,163 @@
+use crate::{backend, WVal};
+use futures::{channel::mpsc, StreamExt};
+use glib::clone;
+use gtk::{self, prelude::*};
+use netidx::{
+    chars::Chars,
+    path::Path,
+    subscriber::{Dval, Event, UpdatesFlags, Value},
+};
+use std::{cell::Cell, rc::Rc};
+
+static SPEEDS: [&'static str; 7] = ["0.1", "0.5", "1", "2", "10", "100", "unlimited"];
+
+fn icon_button(icon: &str, tip: &str) -> gtk::Button {
+    let b = gtk::Button::new();
+    let img = gtk::Image::from_icon_name(Some(icon), gtk::IconSize::SmallToolbar);
+    b.set_image(Some(&img));
+    b.set_tooltip_text(Some(tip));
+    b
+}
+
+/// Feed the current value of a session control to `f` on the gui
+/// thread.
+fn watch<F: Fn(Value) + 'static>(dv: &Dval, f: F) {
+    let (tx, mut rx) = mpsc::channel(3);
+    dv.updates(UpdatesFlags::BEGIN_WITH_LAST, tx);
+    glib::MainContext::default().spawn_local(async move {
+        while let Some(mut batch) = rx.next().await {
+            for (_, ev) in batch.drain(..) {
+                match ev {
+                    Event::Update(v) => f(v),
+                    Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => (),
+                }
+            }
+        }
+    });
+}
+
+/// Toolbar controls for an archive playback session. The controls
+/// read and write the control paths published by the recorder for
+/// the session.
+pub(crate) struct Playback {
+    root: gtk::Box,
+    _state_ctl: Dval,
+    _pos_ctl: Dval,
+    _speed_ctl: Dval,
+}
+
+impl Playback {
+    pub(crate) fn new(ctx: &backend::Ctx, session: &Path) -> Playback {
+        let root = gtk::Box::new(gtk::Orientation::Horizontal, 2);
+        let control = session.append("control");
+        let state_ctl = ctx.subscriber.subscribe(control.append("state/current"));
+        let pos_ctl = ctx.subscriber.subscribe(control.append("pos/current"));
+        let speed_ctl = ctx.subscriber.subscribe(control.append("speed/current"));
+        let back = icon_button("media-skip-backward", "Step back one batch");
+        let play = gtk::ToggleButton::new();
+        let play_img = gtk::Image::from_icon_name(
+            Some("media-playback-start"),
+            gtk::IconSize::SmallToolbar,
+        );
+        play.set_image(Some(&play_img));
+        play.set_tooltip_text(Some("Play/Pause"));
+        let forward = icon_button("media-skip-forward", "Step forward one batch");
+        let tail = icon_button("go-last", "Seek to the end and follow new data");
+        let pos = gtk::Entry::new();
+        pos.set_width_chars(26);
+        pos.set_tooltip_text(Some(
+            "Playback position. Enter a timestamp, beginning, end, \
+             or a relative offset such as -1h or +10 to seek",
+        ));
+        let speed = gtk::ComboBoxText::with_entry();
+        for s in SPEEDS {
+            speed.append(Some(s), s);
+        }
+        speed.set_tooltip_text(Some("Playback speed"));
+        let stop = icon_button("window-close", "Stop playback and return to live data");
+        root.pack_start(&back, false, false, 0);
+        root.pack_start(&play, false, false, 0);
+        root.pack_start(&forward, false, false, 0);
+        root.pack_start(&tail, false, false, 0);
+        root.pack_start(&pos, false, false, 0);
+        root.pack_start(&speed, false, false, 0);
+        root.pack_start(&stop, false, false, 0);
+        // true while we are updating a control from the session, so
+        // that reflecting the current state doesn't write it back.
+        let updating = Rc::new(Cell::new(false));
+        back.connect_clicked(clone!(@strong pos_ctl => move |_| {
+            pos_ctl.write(Value::from("-1"));
+        }));
+        forward.connect_clicked(clone!(@strong pos_ctl => move |_| {
+            pos_ctl.write(Value::from("+1"));
+        }));
+        tail.connect_clicked(clone!(@strong state_ctl => move |_| {
+            state_ctl.write(Value::from("tail"));
+        }));
+        play.connect_toggled(clone!(@strong state_ctl, @strong updating => move |b| {
+            if !updating.get() {
+                let st = if b.is_active() { "play" } else { "pause" };
+                state_ctl.write(Value::from(st));
+            }
+        }));
+        pos.connect_activate(clone!(@strong pos_ctl => move |e| {
+            let v = Value::String(Chars::from(String::from(e.text())));
+            pos_ctl.write(v);
+        }));
+        speed.connect_changed(clone!(@strong speed_ctl, @strong updating => move |c| {
+            if !updating.get() {
+                if let Some(s) = c.active_text() {
+                    let v = match s.parse::<f64>() {
+                        Ok(f) => Value::F64(f),
+                        Err(_) => Value::String(Chars::from(String::from(s))),
+                    };
+                    speed_ctl.write(v);
+                }
+            }
+        }));
+        let ctx = ctx.clone();
+        stop.connect_clicked(move |_| ctx.stop_playback());
+        watch(
+            &state_ctl,
+            clone!(@weak play, @strong updating => move |v| {
+                updating.set(true);
+                play.set_active(match v {
+                    Value::String(s) => &*s == "play" || &*s == "tail",
+                    _ => false,
+                });
+                updating.set(false);
+            }),
+        );
+        watch(
+            &pos_ctl,
+            clone!(@weak pos => move |v| {
+                if !pos.has_focus() {
+                    pos.set_text(&match v {
+                        Value::Null => String::new(),
+                        v => format!("{}", WVal(&v)),
+                    })
+                }
+            }),
+        );
+        watch(
+            &speed_ctl,
+            clone!(@weak speed, @strong updating => move |v| {
+                let entry = speed.child().and_then(|c| c.downcast::<gtk::Entry>().ok());
+                if let Some(e) = entry {
+                    updating.set(true);
+                    e.set_text(&match v {
+                        Value::Null => String::from("unlimited"),
+                        v => format!("{}", WVal(&v)),
+                    });
+                    updating.set(false);
+                }
+            }),
+        );
+        root.show_all();
+        Playback { root, _state_ctl: state_ctl, _pos_ctl: pos_ctl, _speed_ctl: speed_ctl }
+    }
+
+    pub(crate) fn root(&self) -> &gtk::Widget {
+        self.root.upcast_ref()
+    }
+}
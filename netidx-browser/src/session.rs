@@ -0,0 +1,153 @@
+use super::ViewLoc;
+use anyhow::Result;
+use gtk::{self, prelude::*};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// The most locations remembered in the history of a window
+const MAX_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct WindowState {
+    pub(super) loc: ViewLoc,
+    pub(super) width: i32,
+    pub(super) height: i32,
+    /// previously visited locations, the most recent last
+    pub(super) history: Vec<ViewLoc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SessionFile {
+    windows: Vec<WindowState>,
+}
+
+/// A browser window that is part of the session
+pub(super) struct Window {
+    window: gtk::ApplicationWindow,
+    loc: Rc<RefCell<ViewLoc>>,
+    history: RefCell<Vec<ViewLoc>>,
+}
+
+impl Window {
+    pub(super) fn new(
+        window: gtk::ApplicationWindow,
+        loc: Rc<RefCell<ViewLoc>>,
+        history: Vec<ViewLoc>,
+    ) -> Self {
+        Window { window, loc, history: RefCell::new(history) }
+    }
+
+    pub(super) fn push_history(&self, loc: ViewLoc) {
+        let mut history = self.history.borrow_mut();
+        history.push(loc);
+        if history.len() > MAX_HISTORY {
+            history.remove(0);
+        }
+    }
+
+    pub(super) fn pop_history(&self) -> Option<ViewLoc> {
+        self.history.borrow_mut().pop()
+    }
+
+    pub(super) fn has_history(&self) -> bool {
+        !self.history.borrow().is_empty()
+    }
+
+    fn state(&self) -> WindowState {
+        let (width, height) = self.window.size();
+        WindowState {
+            loc: self.loc.borrow().clone(),
+            width,
+            height,
+            history: self.history.borrow().clone(),
+        }
+    }
+}
+
+/// The set of open windows, which is saved to the session file
+/// whenever it changes, and restored by the next browser.
+pub(super) struct Session {
+    file: Option<PathBuf>,
+    windows: Vec<Rc<Window>>,
+    /// windows from the last session that haven't been reopened yet
+    restore: VecDeque<WindowState>,
+}
+
+impl Session {
+    pub(super) fn default_path() -> Option<PathBuf> {
+        dirs::data_local_dir().map(|mut p| {
+            p.push("netidx");
+            p.push("browser-session.json");
+            p
+        })
+    }
+
+    /// Create a new session saved in `file`. If `restore` is true
+    /// the windows saved in `file` by the last session will be
+    /// reopened.
+    pub(super) fn new(file: Option<PathBuf>, restore: bool) -> Self {
+        let restore = match &file {
+            Some(file) if restore && file.exists() => match Self::load(file) {
+                Ok(s) => VecDeque::from(s.windows),
+                Err(e) => {
+                    warn!("failed to load session {}, {}", file.display(), e);
+                    VecDeque::new()
+                }
+            },
+            Some(_) | None => VecDeque::new(),
+        };
+        Session { file, windows: vec![], restore }
+    }
+
+    fn load(file: &Path) -> Result<SessionFile> {
+        Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+    }
+
+    /// The next window from the last session to reopen
+    pub(super) fn next_restore(&mut self) -> Option<WindowState> {
+        self.restore.pop_front()
+    }
+
+    pub(super) fn restore_pending(&self) -> bool {
+        !self.restore.is_empty()
+    }
+
+    pub(super) fn add(&mut self, window: Rc<Window>) {
+        self.windows.push(window);
+        self.save()
+    }
+
+    /// Remove a window the user closed. The last window stays in the
+    /// session, since closing it exits the browser.
+    pub(super) fn close(&mut self, window: &gtk::ApplicationWindow) {
+        if self.windows.len() > 1 {
+            self.windows.retain(|w| &w.window != window);
+        }
+        self.save()
+    }
+
+    pub(super) fn save(&self) {
+        if let Some(file) = &self.file {
+            let s =
+                SessionFile { windows: self.windows.iter().map(|w| w.state()).collect() };
+            let res = serde_json::to_string_pretty(&s)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| {
+                    if let Some(dir) = file.parent() {
+                        fs::create_dir_all(dir)?
+                    }
+                    Ok(fs::write(file, s)?)
+                });
+            if let Err(e) = res {
+                warn!("failed to save session {}, {}", file.display(), e)
+            }
+        }
+    }
+}
@@ -1,12 +1,12 @@
 mod raeified;
 mod shared;
 
-use super::{BSCtx, BSCtxRef, BSNode, BWidget};
+use super::{BSCtx, BSCtxRef, BSNode, BWidget, WidgetState};
 use crate::bscript::LocalEvent;
 use futures::channel::oneshot;
 use gio::prelude::*;
 use glib::{self, clone, idle_add_local, source::Continue};
-use gtk::{prelude::*, Adjustment, Label, ScrolledWindow};
+use gtk::{prelude::*, Adjustment, Label, Orientation, PolicyType, ScrolledWindow};
 use netidx::{path::Path, subscriber::Value};
 use netidx_bscript::vm;
 use netidx_protocols::view;
@@ -29,6 +29,7 @@ pub(super) struct Table {
     columns_resizable: BSNode,
     column_types: BSNode,
     column_widths: BSNode,
+    footer: BSNode,
     selection_mode: BSNode,
     selection: BSNode,
     path: BSNode,
@@ -36,6 +37,7 @@ pub(super) struct Table {
     row_filter: BSNode,
     show_row_name: BSNode,
     sort_mode: BSNode,
+    root: gtk::Box,
     shared: Rc<SharedState>,
     state: Rc<RefCell<TableState>>,
     visible: Cell<bool>,
@@ -72,6 +74,7 @@ impl Table {
             BSNode::compile(&mut *ctx.borrow_mut(), scope.clone(), spec.column_types);
         let column_widths =
             BSNode::compile(&mut *ctx.borrow_mut(), scope.clone(), spec.column_widths);
+        let footer = BSNode::compile(&mut *ctx.borrow_mut(), scope.clone(), spec.footer);
         let refresh =
             BSNode::compile(&mut *ctx.borrow_mut(), scope.clone(), spec.refresh);
         let on_select =
@@ -82,11 +85,23 @@ impl Table {
             BSNode::compile(&mut *ctx.borrow_mut(), scope.clone(), spec.on_edit);
         let on_header_click =
             BSNode::compile(&mut *ctx.borrow_mut(), scope, spec.on_header_click);
-        let root = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+        let window = ScrolledWindow::new(None::<&Adjustment>, None::<&Adjustment>);
+        // the footer scrolls horizontally with the table, but is
+        // always visible at the bottom
+        let footer_window =
+            ScrolledWindow::new(Some(&window.hadjustment()), None::<&Adjustment>);
+        footer_window.set_policy(PolicyType::External, PolicyType::Never);
+        let footer_row = gtk::Box::new(Orientation::Horizontal, 0);
+        footer_row.set_no_show_all(true);
+        footer_window.add(&footer_row);
+        let root = gtk::Box::new(Orientation::Vertical, 0);
+        root.pack_start(&window, true, true, 0);
+        root.pack_end(&footer_window, false, false, 0);
         let shared = Rc::new(SharedState::new(
             ctx.clone(),
             selected_path,
-            root,
+            window,
+            footer_row,
             on_activate,
             on_edit,
             on_header_click,
@@ -103,6 +118,7 @@ impl Table {
         shared.set_columns_resizable(columns_resizable.current(&mut ctx.borrow_mut()));
         shared.set_column_types(column_types.current(&mut ctx.borrow_mut()));
         shared.set_column_widths(column_widths.current(&mut ctx.borrow_mut()));
+        shared.set_footer(footer.current(&mut ctx.borrow_mut()));
         let state = Rc::new(RefCell::new({
             let path = &*shared.path.borrow();
             shared.ctx.borrow().user.backend.resolve_table(path.clone());
@@ -124,6 +140,7 @@ impl Table {
             columns_resizable,
             column_types,
             column_widths,
+            footer,
             selection_mode,
             selection,
             path,
@@ -131,6 +148,7 @@ impl Table {
             row_filter,
             show_row_name,
             sort_mode,
+            root,
             state,
             visible: Cell::new(true),
         }
@@ -198,6 +216,7 @@ impl BWidget for Table {
             self.shared.set_columns_resizable(self.columns_resizable.update(ctx, event));
         re |= self.shared.set_column_types(self.column_types.update(ctx, event));
         re |= self.shared.set_column_widths(self.column_widths.update(ctx, event));
+        re |= self.shared.set_footer(self.footer.update(ctx, event));
         let force_refresh = self.refresh.update(ctx, event).is_some();
         self.shared.on_activate.borrow_mut().update(ctx, event);
         self.shared.on_select.borrow_mut().update(ctx, event);
@@ -215,7 +234,10 @@ impl BWidget for Table {
                 | vm::Event::Timer(_)
                 | vm::Event::Variable(_, _, _)
                 | vm::Event::User(LocalEvent::Event(_))
-                | vm::Event::User(LocalEvent::Poll(_)) => (),
+                | vm::Event::User(LocalEvent::TableExport(_, _))
+                | vm::Event::User(LocalEvent::Poll(_))
+                | vm::Event::User(LocalEvent::WidgetState(_, _))
+                | vm::Event::User(LocalEvent::Flush) => (),
                 vm::Event::User(LocalEvent::TableResolved(path, descriptor)) => {
                     if path == rpath {
                         match self.selection.current(ctx) {
@@ -236,12 +258,12 @@ impl BWidget for Table {
     }
 
     fn root(&self) -> Option<&gtk::Widget> {
-        Some(self.shared.root.upcast_ref())
+        Some(self.root.upcast_ref())
     }
 
     fn set_visible(&self, v: bool) {
         self.visible.set(v);
-        self.shared.root.set_visible(v);
+        self.root.set_visible(v);
         match &mut *self.state.borrow_mut() {
             TableState::Raeified(t) => {
                 if v {
@@ -257,4 +279,14 @@ impl BWidget for Table {
             TableState::Refresh(_) | TableState::Resolving(_) => (),
         }
     }
+
+    fn export_state(&self, state: WidgetState) {
+        state.set("selection", Value::from(Vec::<Value>::new()));
+        let vadj = self.shared.root.vadjustment();
+        state.set("scroll", vadj.value().into());
+        vadj.connect_value_changed(clone!(@strong state => move |a| {
+            state.set("scroll", a.value().into())
+        }));
+        *self.shared.state.borrow_mut() = Some(state);
+    }
 }
@@ -1,23 +1,25 @@
 use super::super::{
-    util::{err_modal, toplevel},
+    backend::ExportCell,
+    util::{csv_line, err_modal, toplevel},
     BSCtxRef, ImageSpec, WVal,
 };
 use super::shared::{
-    BVal, CTCommonResolved, Color, ColumnSpec, ColumnType, ColumnTypeCombo,
-    ColumnTypeCommon, ColumnTypeProgress, ColumnTypeSpin, ColumnTypeText,
-    ColumnTypeToggle, IndexDescriptor, OrLoad, SelectionMode, SharedState, SortDir,
-    SortSpec, NAME_COL,
+    Aggregate, BVal, CTCommonResolved, Color, ColumnSpec, ColumnType, ColumnTypeCombo,
+    ColumnTypeCommon, ColumnTypeProgress, ColumnTypeSparkline, ColumnTypeSpin,
+    ColumnTypeText, ColumnTypeToggle, IndexDescriptor, OrLoad, SelectionMode,
+    SharedState, SortDir, SortSpec, NAME_COL,
 };
 use crate::bscript::LocalEvent;
 use arcstr::ArcStr;
 use futures::channel::oneshot;
 use fxhash::{FxBuildHasher, FxHashMap, FxHashSet};
-use gdk::{keys, EventButton, EventKey, RGBA};
+use gdk::{cairo, keys, EventButton, EventKey, RGBA};
+use gdk_pixbuf::Pixbuf;
 use gio::prelude::*;
 use glib::{self, clone, idle_add_local, signal::Inhibit, source::Continue};
 use gtk::{
     prelude::*, CellRenderer, CellRendererCombo, CellRendererPixbuf,
-    CellRendererProgress, CellRendererSpin, CellRendererText, CellRendererToggle,
+    CellRendererProgress, CellRendererSpin, CellRendererText, CellRendererToggle, Label,
     ListStore, SortColumn, SortType, StateFlags, StyleContext, TreeIter, TreeModel,
     TreePath, TreeView, TreeViewColumn, TreeViewColumnSizing,
 };
@@ -33,11 +35,12 @@ use netidx_bscript::vm;
 use std::{
     cell::{Cell, RefCell},
     cmp::Ordering,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Write,
     ops::Deref,
     rc::{Rc, Weak},
     result,
+    time::Duration,
 };
 
 struct Subscription {
@@ -50,6 +53,77 @@ lazy_static! {
     static ref FORMATTED: Pool<String> = Pool::new(50_000, 1024);
 }
 
+const SPARKLINE_HEIGHT: i32 = 16;
+
+/// How long an edited cell may wait for the publisher to confirm the
+/// write before it is reverted.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A cell the user edited, waiting for the publisher to confirm the
+/// new value.
+struct PendingWrite {
+    row: TreeIter,
+    col: u32,
+    /// the contents of the cell before the edit, restored if the
+    /// write fails
+    prev: glib::Value,
+    /// distinguishes this edit from later edits of the same cell
+    serial: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteState {
+    None,
+    Pending,
+    Failed,
+}
+
+/// values accumulated for the cells of a column that is the source of
+/// a sparkline, by row name, and the number of values to keep.
+type History = FxHashMap<u32, (usize, FxHashMap<String, VecDeque<f64>>)>;
+
+fn draw_sparkline(
+    points: &[f64],
+    width: i32,
+    min: Option<f64>,
+    max: Option<f64>,
+    color: &RGBA,
+) -> Option<Pixbuf> {
+    if points.is_empty() || width <= 1 {
+        return None;
+    }
+    let surface =
+        cairo::ImageSurface::create(cairo::Format::ARgb32, width, SPARKLINE_HEIGHT)
+            .ok()?;
+    {
+        let cr = cairo::Context::new(&surface).ok()?;
+        let lo =
+            min.unwrap_or_else(|| points.iter().copied().fold(f64::INFINITY, f64::min));
+        let hi = max
+            .unwrap_or_else(|| points.iter().copied().fold(f64::NEG_INFINITY, f64::max));
+        let (w, h) = ((width - 1) as f64, (SPARKLINE_HEIGHT - 1) as f64);
+        let step = if points.len() > 1 { w / (points.len() - 1) as f64 } else { w };
+        let y = |p: f64| {
+            if hi > lo {
+                0.5 + h - ((p.max(lo).min(hi) - lo) / (hi - lo)) * h
+            } else {
+                0.5 + h / 2.
+            }
+        };
+        cr.set_source_rgba(color.red(), color.green(), color.blue(), color.alpha());
+        cr.set_line_width(1.);
+        cr.move_to(0.5, y(points[0]));
+        for (i, p) in points.iter().enumerate().skip(1) {
+            cr.line_to(0.5 + i as f64 * step, y(*p));
+        }
+        if points.len() == 1 {
+            cr.line_to(0.5 + w, y(points[0]));
+        }
+        cr.stroke().ok()?;
+    }
+    gdk::pixbuf_get_from_surface(&surface, 0, 0, width, SPARKLINE_HEIGHT)
+}
+
 fn get_sort_column(store: &ListStore) -> Option<u32> {
     match store.sort_column_id() {
         None | Some((SortColumn::Default, _)) => None,
@@ -99,6 +173,7 @@ pub(super) struct RaeifiedTableInner {
     columns_autosizing: Rc<Cell<bool>>,
     descriptor: IndexDescriptor,
     destroyed: Cell<bool>,
+    footer: RefCell<Vec<(u32, Aggregate, Label)>>,
     name_column: RefCell<Option<TreeViewColumn>>,
     sort_column: Cell<Option<u32>>,
     sort_temp_disabled: Cell<bool>,
@@ -110,6 +185,11 @@ pub(super) struct RaeifiedTableInner {
     view: TreeView,
     combo_models: RefCell<IndexMap<Value, ListStore, FxBuildHasher>>,
     combo_models_used: Cell<usize>,
+    history: RefCell<History>,
+    column_sources: RefCell<Vec<(TreeViewColumn, CTCommonResolved)>>,
+    pending_writes: RefCell<FxHashMap<Path, PendingWrite>>,
+    failed_writes: RefCell<FxHashMap<Path, String>>,
+    write_serial: Cell<u64>,
 }
 
 #[derive(Clone)]
@@ -135,6 +215,9 @@ impl RaeifiedTable {
     ) {
         let t = self;
         column.set_title(&**name);
+        if let Some(common) = common.as_ref() {
+            t.column_sources.borrow_mut().push((column.clone(), common.clone()));
+        }
         if let Some(CTCommonResolved { source: id, .. }) = common.as_ref() {
             let id = *id;
             column.connect_clicked(clone!(@weak t, @strong name => move |_| {
@@ -181,7 +264,9 @@ impl RaeifiedTable {
         cell.connect_edited(
             clone!(@weak t, @strong common, @strong editable => move |_, p, v| {
                 if let Some(path) = t.path_from_treepath(&p, &*common.source_column) {
-                    let v = vec![Value::from(path), Value::from(String::from(v))];
+                    let v = Value::from(String::from(v));
+                    t.begin_write(&p, &common, v.clone());
+                    let v = vec![Value::from(path), v];
                     t.shared.on_edit.borrow_mut().update(
                         &mut t.shared.ctx.borrow_mut(),
                         &vm::Event::User(LocalEvent::Event(v.into()))
@@ -298,6 +383,7 @@ impl RaeifiedTable {
                         .and_then(|v| v.get::<&BVal>().ok()
                                        .and_then(|v| v.value.clone().cast_to::<bool>().ok()))
                         .unwrap_or(false);
+                    t.begin_write(&p, &common, Value::from(!val));
                     let val = vec![Value::from(path), Value::from(!val)];
                     t.shared.on_edit.borrow_mut().update(
                         &mut t.shared.ctx.borrow_mut(),
@@ -442,6 +528,53 @@ impl RaeifiedTable {
         t.view().append_column(&column);
     }
 
+    fn add_sparkline_column(
+        &self,
+        sorting_disabled: bool,
+        name: &Chars,
+        spec: &ColumnTypeSparkline,
+    ) {
+        let t = self;
+        let column = TreeViewColumn::new();
+        let cell = CellRendererPixbuf::new();
+        CellLayoutExt::pack_start(&column, &cell, true);
+        let common = spec.common.resolve(false, name, &self.descriptor);
+        if let Some(common) = common.as_ref() {
+            {
+                let mut history = t.history.borrow_mut();
+                let (n, _) = history
+                    .entry(common.source as u32)
+                    .or_insert_with(|| (0, HashMap::default()));
+                *n = (*n).max(spec.history);
+            }
+            let n = spec.history;
+            let color = spec.color.as_ref().and_then(|v| v.resolve(&t.descriptor));
+            let min = spec.min.as_ref().and_then(|v| v.resolve(&t.descriptor));
+            let max = spec.max.as_ref().and_then(|v| v.resolve(&t.descriptor));
+            let f =
+                Box::new(clone!(@weak t, @strong cell, @strong name, @strong common =>
+                move |c: &TreeViewColumn,
+                _: &CellRenderer,
+                _: &TreeModel,
+                i: &TreeIter| {
+                    t.render_sparkline_cell(
+                        &common,
+                        &*name,
+                        n,
+                        &color,
+                        &min,
+                        &max,
+                        c,
+                        &cell,
+                        i
+                    )
+                }));
+            TreeViewColumnExt::set_cell_data_func(&column, &cell, Some(f));
+        }
+        t.set_column_properties(&column, name, &common, sorting_disabled);
+        t.view().append_column(&column);
+    }
+
     fn add_columns(
         &self,
         vector_mode: bool,
@@ -506,6 +639,9 @@ impl RaeifiedTable {
                     ColumnType::Progress(cs) => {
                         t.add_progress_column(sorting_disabled, &name, cs)
                     }
+                    ColumnType::Sparkline(cs) => {
+                        t.add_sparkline_column(sorting_disabled, &name, cs)
+                    }
                     ColumnType::Hidden => (),
                 }
             }
@@ -552,6 +688,7 @@ impl RaeifiedTable {
             by_id: RefCell::new(HashMap::default()),
             columns_autosizing: Rc::new(Cell::new(false)),
             destroyed: Cell::new(false),
+            footer: RefCell::new(vec![]),
             name_column: RefCell::new(None),
             sort_column: Cell::new(None),
             sort_temp_disabled: Cell::new(false),
@@ -559,6 +696,11 @@ impl RaeifiedTable {
             update: RefCell::new(IndexMap::default()),
             combo_models: RefCell::new(IndexMap::default()),
             combo_models_used: Cell::new(0),
+            history: RefCell::new(HashMap::default()),
+            column_sources: RefCell::new(vec![]),
+            pending_writes: RefCell::new(HashMap::default()),
+            failed_writes: RefCell::new(HashMap::default()),
+            write_serial: Cell::new(0),
         }));
         if t.shared.column_widths.borrow().len() > 1000 {
             let cols =
@@ -573,6 +715,7 @@ impl RaeifiedTable {
             SortSpec::Column(_, _) | SortSpec::None => false,
         };
         t.add_columns(vector_mode, column_spec, sorting_disabled);
+        t.build_footer();
         t.view().set_model(Some(t.store()));
         t.view().connect_destroy(clone!(@weak t => move |_| t.destroyed.set(true)));
         t.store().connect_sort_column_changed(
@@ -587,6 +730,11 @@ impl RaeifiedTable {
             clone!(@weak t => move |_, p, _| t.handle_row_activated(p)),
         );
         t.view().connect_cursor_changed(clone!(@weak t => move |_| t.cursor_changed()));
+        t.view().set_has_tooltip(true);
+        t.view().connect_query_tooltip(clone!(
+        @weak t => @default-return false, move |_, x, y, kbd, tip| {
+            t.write_tooltip(x, y, kbd, tip)
+        }));
         match &*t.shared.sort_mode.borrow() {
             SortSpec::None => (),
             SortSpec::Disabled => (),
@@ -629,6 +777,77 @@ impl RaeifiedTable {
         t
     }
 
+    fn build_footer(&self) {
+        let row = &self.shared.footer_row;
+        for c in row.children() {
+            row.remove(&c);
+        }
+        let spec = match &*self.shared.footer.borrow() {
+            None => {
+                row.hide();
+                return;
+            }
+            Some(spec) => spec
+                .iter()
+                .map(|(col, agg)| (String::from(&**col), *agg))
+                .collect::<FxHashMap<_, _>>(),
+        };
+        let mut footer = self.footer.borrow_mut();
+        for column in self.view().columns() {
+            let label = Label::new(None);
+            label.set_xalign(0.);
+            label.set_ellipsize(pango::EllipsizeMode::End);
+            label.set_size_request(column.width(), -1);
+            column.connect_width_notify(clone!(@weak label => move |c| {
+                label.set_size_request(c.width(), -1);
+            }));
+            let title = column.title();
+            let agg = title.as_ref().and_then(|t| spec.get(t.as_str()));
+            let id = title.as_ref().and_then(|t| {
+                if self.vector_mode {
+                    if t.as_str() == "value" {
+                        Some(1)
+                    } else {
+                        None
+                    }
+                } else {
+                    self.descriptor.cols.get_index_of(t.as_str()).map(|i| i + 1)
+                }
+            });
+            if let (Some(agg), Some(id)) = (agg, id) {
+                footer.push((id as u32, *agg, label.clone()));
+            }
+            row.pack_start(&label, false, false, 0);
+            label.show();
+        }
+        drop(footer);
+        self.update_footer();
+        row.show();
+    }
+
+    fn update_footer(&self) {
+        for (id, agg, label) in self.footer.borrow().iter() {
+            let mut values = vec![];
+            if let Some(row) = self.store().iter_first() {
+                loop {
+                    let v = self.store().value(&row, *id as i32);
+                    if let Ok(bv) = v.get::<&BVal>() {
+                        match &bv.value {
+                            Value::Null => (),
+                            Value::String(s) if s.is_empty() => (),
+                            v => values.push(v.clone()),
+                        }
+                    }
+                    if !self.store().iter_next(&row) {
+                        break;
+                    }
+                }
+            }
+            let v = agg.compute(values.into_iter());
+            label.set_text(&format!("{}", WVal(&v)));
+        }
+    }
+
     fn handle_row_activated(&self, p: &TreePath) {
         if let Some(iter) = self.store().iter(&p) {
             if let Ok(row_name) = self.store().value(&iter, 0).get::<&str>() {
@@ -669,6 +888,8 @@ impl RaeifiedTable {
         i: &TreeIter,
         name: &str,
     ) -> bool {
+        let state = self.write_state(common, i);
+        cr.set_sensitive(state != WriteState::Pending);
         let sel = self.shared.selected.borrow();
         match self.row_of(Either::Right(i)).as_ref().map(|r| r.get::<&str>().unwrap()) {
             Some(r) if sel.get(r).map(|t| t.contains(name)).unwrap_or(false) => {
@@ -683,17 +904,125 @@ impl RaeifiedTable {
                 true
             }
             Some(_) | None => {
-                let bg = common
-                    .background
-                    .as_ref()
-                    .and_then(|s| s.load(i, self.store()))
-                    .map(|c| c.0);
+                let bg = if state == WriteState::Failed {
+                    Some(RGBA::new(1., 0., 0., 0.25))
+                } else {
+                    common
+                        .background
+                        .as_ref()
+                        .and_then(|s| s.load(i, self.store()))
+                        .map(|c| c.0)
+                };
                 cr.set_cell_background_rgba(bg.as_ref());
                 false
             }
         }
     }
 
+    fn write_state(&self, common: &CTCommonResolved, i: &TreeIter) -> WriteState {
+        let pending = self.pending_writes.borrow();
+        let failed = self.failed_writes.borrow();
+        if pending.is_empty() && failed.is_empty() {
+            return WriteState::None;
+        }
+        match self.cell_path(i, &*common.source_column) {
+            Some(p) if pending.contains_key(&p) => WriteState::Pending,
+            Some(p) if failed.contains_key(&p) => WriteState::Failed,
+            Some(_) | None => WriteState::None,
+        }
+    }
+
+    /// Show the value `v` the user wrote to the cell at `p` as
+    /// pending until the publisher confirms it, or the write fails.
+    fn begin_write(
+        &self,
+        p: &TreePath,
+        common: &CTCommonResolved,
+        v: Value,
+    ) -> Option<(Path, u64)> {
+        let path = self.path_from_treepath(p, &*common.source_column)?;
+        let row = self.store().iter(p)?;
+        let col = common.source as u32;
+        let serial = self.write_serial.get() + 1;
+        self.write_serial.set(serial);
+        self.failed_writes.borrow_mut().remove(&path);
+        let prev = match self.pending_writes.borrow_mut().remove(&path) {
+            Some(pw) => pw.prev,
+            None => self.store().value(&row, common.source),
+        };
+        self.pending_writes
+            .borrow_mut()
+            .insert(path.clone(), PendingWrite { row: row.clone(), col, prev, serial });
+        let mut formatted = FORMATTED.take();
+        write!(&mut *formatted, "{}", WVal(&v)).unwrap();
+        self.store().set_value(&row, col, &BVal { value: v, formatted }.to_value());
+        let t = Rc::downgrade(&self.0);
+        let timeout_path = path.clone();
+        glib::timeout_add_local_once(WRITE_TIMEOUT, move || {
+            if let Some(t) = t.upgrade() {
+                let msg = "the publisher did not confirm the write";
+                RaeifiedTable(t).fail_write(&timeout_path, serial, msg)
+            }
+        });
+        Some((path, serial))
+    }
+
+    /// The publisher accepted the write, keep showing the new value
+    fn confirm_write(&self, path: &Path, serial: u64) {
+        let mut pending = self.pending_writes.borrow_mut();
+        if pending.get(path).map(|pw| pw.serial == serial).unwrap_or(false) {
+            pending.remove(path);
+            drop(pending);
+            self.view().queue_draw();
+        }
+    }
+
+    /// The write was rejected or lost, revert the cell and remember
+    /// why so it can be shown in the tooltip.
+    fn fail_write(&self, path: &Path, serial: u64, msg: &str) {
+        let pw = {
+            let mut pending = self.pending_writes.borrow_mut();
+            match pending.get(path) {
+                Some(pw) if pw.serial == serial => pending.remove(path),
+                Some(_) | None => None,
+            }
+        };
+        if let Some(pw) = pw {
+            self.failed_writes.borrow_mut().insert(path.clone(), String::from(msg));
+            self.store().set_value(&pw.row, pw.col, &pw.prev);
+        }
+    }
+
+    fn write_tooltip(&self, x: i32, y: i32, kbd: bool, tip: &gtk::Tooltip) -> bool {
+        if kbd || self.failed_writes.borrow().is_empty() {
+            return false;
+        }
+        let (x, y) = self.view().convert_widget_to_bin_window_coords(x, y);
+        let (p, column) = match self.view().path_at_pos(x, y) {
+            Some((Some(p), Some(column), _, _)) => (p, column),
+            Some(_) | None => return false,
+        };
+        let sources = self.column_sources.borrow();
+        let source = match sources.iter().find(|(c, _)| c == &column) {
+            Some((_, common)) => &common.source_column,
+            None => return false,
+        };
+        let failed = self.failed_writes.borrow();
+        match self.path_from_treepath(&p, &**source).and_then(|p| failed.get(&p)) {
+            None => false,
+            Some(msg) => {
+                tip.set_text(Some(&format!("write failed: {}", msg)));
+                self.view().set_tooltip_cell(
+                    tip,
+                    Some(&p),
+                    Some(&column),
+                    None::<&CellRenderer>,
+                );
+                true
+            }
+        }
+    }
+
     fn render_text_cell(
         &self,
         common: &CTCommonResolved,
@@ -825,6 +1154,84 @@ impl RaeifiedTable {
         self.render_cell_selected(common, cr, i, name);
     }
 
+    fn render_sparkline_cell(
+        &self,
+        common: &CTCommonResolved,
+        name: &str,
+        n: usize,
+        color: &Option<OrLoad<Color>>,
+        min: &Option<OrLoad<f64>>,
+        max: &Option<OrLoad<f64>>,
+        column: &TreeViewColumn,
+        cr: &CellRendererPixbuf,
+        i: &TreeIter,
+    ) {
+        let bv = self.store().value(i, common.source);
+        // an array is history sent by the publisher, otherwise draw
+        // what we have accumulated
+        let points = match bv.get::<&BVal>().map(|bv| &bv.value) {
+            Ok(Value::Array(a)) => {
+                let a = &a[a.len().saturating_sub(n)..];
+                a.iter().filter_map(|v| v.clone().cast_to::<f64>().ok()).collect()
+            }
+            Ok(_) | Err(_) => {
+                let row = self.store().value(i, 0);
+                let history = self.history.borrow();
+                match (row.get::<&str>(), history.get(&(common.source as u32))) {
+                    (Ok(row), Some((_, h))) => match h.get(row) {
+                        Some(h) => {
+                            h.iter().skip(h.len().saturating_sub(n)).copied().collect()
+                        }
+                        None => vec![],
+                    },
+                    (_, _) => vec![],
+                }
+            }
+        };
+        let color = if self.render_cell_selected(common, cr, i, name) {
+            self.style.color(StateFlags::SELECTED)
+        } else {
+            color
+                .as_ref()
+                .and_then(|s| s.load(i, self.store()))
+                .map(|c| c.0)
+                .unwrap_or_else(|| self.style.color(StateFlags::NORMAL))
+        };
+        let min = min.as_ref().and_then(|v| v.load(i, self.store()));
+        let max = max.as_ref().and_then(|v| v.load(i, self.store()));
+        let (xpad, _) = cr.padding();
+        let width = column.width() - 2 * xpad;
+        cr.set_pixbuf(draw_sparkline(&points, width, min, max, &color).as_ref());
+    }
+
+    fn record_history(&self, id: SubId, v: &Value) {
+        let by_id = self.by_id.borrow();
+        let sub = match by_id.get(&id) {
+            None => return,
+            Some(sub) => sub,
+        };
+        let mut history = self.history.borrow_mut();
+        let (n, h) = match history.get_mut(&sub.col) {
+            None => return,
+            Some((n, h)) => (*n, h),
+        };
+        let v = match v.clone().cast_to::<f64>() {
+            Err(_) => return,
+            Ok(v) => v,
+        };
+        let row = self.store().value(&sub.row, 0);
+        if let Ok(row) = row.get::<&str>() {
+            if !h.contains_key(row) {
+                h.insert(row.into(), VecDeque::new());
+            }
+            let h = h.get_mut(row).unwrap();
+            h.push_back(v);
+            while h.len() > n {
+                h.pop_front();
+            }
+        }
+    }
+
     fn render_toggle_cell(
         &self,
         common: &CTCommonResolved,
@@ -939,6 +1346,11 @@ impl RaeifiedTable {
         {
             self.write_dialog()
         }
+        if kv == keys::constants::c
+            && key.state().contains(gdk::ModifierType::CONTROL_MASK)
+        {
+            self.copy_selection()
+        }
         Inhibit(false)
     }
 
@@ -954,11 +1366,155 @@ impl RaeifiedTable {
                     self.shared.selected.borrow_mut().clear();
                 }
             }
+            (_, gdk::EventType::ButtonPress) if n == 3 => {
+                self.context_menu(ev);
+                return Inhibit(true);
+            }
             (None, _) | (Some((_, _, _, _)), _) => (),
         }
         Inhibit(false)
     }
 
+    fn context_menu(&self, ev: &EventButton) {
+        let t = self;
+        let menu = gtk::Menu::new();
+        let copy = gtk::MenuItem::with_label("Copy Selection");
+        copy.set_sensitive(!self.shared.selected.borrow().is_empty());
+        copy.connect_activate(clone!(@weak t => move |_| t.copy_selection()));
+        menu.append(&copy);
+        let export = gtk::MenuItem::with_label("Export to CSV...");
+        export.connect_activate(clone!(@weak t => move |_| t.export_dialog()));
+        menu.append(&export);
+        menu.set_attach_widget(Some(self.view()));
+        menu.show_all();
+        menu.popup_at_pointer(Some(&**ev));
+    }
+
+    /// The titles of the displayed columns in display order
+    fn column_titles(&self) -> Vec<String> {
+        self.view()
+            .columns()
+            .iter()
+            .filter(|c| c.is_visible())
+            .filter_map(|c| c.title().map(|t| t.to_string()))
+            .collect()
+    }
+
+    /// The displayed rows in display order
+    fn rows(&self) -> Vec<(String, TreeIter)> {
+        let mut rows = vec![];
+        if let Some(iter) = self.store().iter_first() {
+            loop {
+                if let Ok(row) = self.store().value(&iter, 0).get::<String>() {
+                    rows.push((row, iter.clone()));
+                }
+                if !self.store().iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        rows
+    }
+
+    fn cell_text(&self, row: &str, iter: &TreeIter, title: &str) -> String {
+        if self.shared.show_name_column.get() && title == NAME_COL {
+            return String::from(row);
+        }
+        let col = if self.vector_mode {
+            Some(1)
+        } else {
+            self.descriptor.cols.iter().position(|(c, _)| &**c == title).map(|i| i + 1)
+        };
+        match col {
+            None => String::new(),
+            Some(col) => match self.store().value(iter, col as i32).get::<&BVal>() {
+                Ok(v) => v.formatted.to_string(),
+                Err(_) => String::new(),
+            },
+        }
+    }
+
+    /// Copy the selected cells to the clipboard as csv. The columns
+    /// are headed by their titles unless only one cell is selected.
+    fn copy_selection(&self) {
+        let selected = self.shared.selected.borrow();
+        let columns = self
+            .column_titles()
+            .into_iter()
+            .filter(|c| selected.values().any(|cols| cols.contains(c)))
+            .collect::<Vec<_>>();
+        let mut buf = String::new();
+        if selected.values().map(|cols| cols.len()).sum::<usize>() > 1 {
+            csv_line(&mut buf, &columns);
+        }
+        for (row, iter) in self.rows() {
+            if let Some(cols) = selected.get(&row) {
+                csv_line(
+                    &mut buf,
+                    columns.iter().map(|c| {
+                        if cols.contains(c) {
+                            self.cell_text(&row, &iter, c)
+                        } else {
+                            String::new()
+                        }
+                    }),
+                );
+            }
+        }
+        gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&buf);
+    }
+
+    /// The currently displayed view of the table, after filtering
+    /// and sorting, headed by the column titles.
+    fn export_cells(&self) -> Vec<Vec<ExportCell>> {
+        let columns = self.column_titles();
+        let mut cells = vec![columns
+            .iter()
+            .map(|c| ExportCell::Literal(Chars::from(c.clone())))
+            .collect::<Vec<_>>()];
+        for (row, _) in self.rows() {
+            cells.push(
+                columns
+                    .iter()
+                    .map(|c| {
+                        if self.shared.show_name_column.get() && c == NAME_COL {
+                            ExportCell::Literal(Chars::from(row.clone()))
+                        } else {
+                            ExportCell::Path(self.path_from_selected(&row, c))
+                        }
+                    })
+                    .collect(),
+            );
+        }
+        cells
+    }
+
+    fn export_dialog(&self) {
+        let window = toplevel(self.view());
+        let d = gtk::FileChooserDialog::with_buttons(
+            Some("Export to CSV"),
+            Some(&window),
+            gtk::FileChooserAction::Save,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Export", gtk::ResponseType::Accept),
+            ],
+        );
+        d.set_do_overwrite_confirmation(true);
+        let name = Path::basename(&self.path).unwrap_or("table");
+        d.set_current_name(&format!("{}.csv", name));
+        let dest = match d.run() {
+            gtk::ResponseType::Accept => d.filename(),
+            gtk::ResponseType::Cancel | _ => None,
+        };
+        unsafe {
+            d.destroy();
+        }
+        if let Some(dest) = dest {
+            self.shared.ctx.borrow().user.backend.export_table(dest, self.export_cells())
+        }
+    }
+
     fn write_dialog(&self) {
         let window = toplevel(self.view());
         let selected = self.shared.selected_path.text();
@@ -966,11 +1522,20 @@ impl RaeifiedTable {
             err_modal(&window, "Select a cell before write");
         } else {
             let path = Path::from(ArcStr::from(&*selected));
+            let cell = match self.view().cursor() {
+                (Some(p), Some(column)) => self
+                    .column_sources
+                    .borrow()
+                    .iter()
+                    .find(|(c, _)| c == &column)
+                    .map(|(_, common)| (p, common.clone())),
+                (_, _) => None,
+            };
             // we should already be subscribed, so we're just looking up the dval by path.
-            let dv = self.shared.ctx.borrow_mut().user.backend.subscriber.subscribe(path);
+            let dv = self.shared.ctx.borrow_mut().user.backend.subscribe(path);
             let val = Rc::new(RefCell::new(match dv.last() {
-                Event::Unsubscribed => Some(Value::Null),
-                Event::Update(v) => Some(v),
+                Event::Unsubscribed | Event::Stale => Some(Value::Null),
+                Event::Update(v) | Event::Unpublished(v) => Some(v),
             }));
             let d = gtk::Dialog::with_buttons(
                 Some("Write Cell"),
@@ -1035,7 +1600,29 @@ impl RaeifiedTable {
                         err_modal(&window, "Can't parse value, not written");
                     }
                     Some(v) => {
-                        dv.write(v.clone());
+                        let reply = dv.write_with_recipt(v.clone());
+                        let pending = cell.and_then(|(p, common)| {
+                            self.begin_write(&p, &common, v.clone())
+                        });
+                        if let Some((path, serial)) = pending {
+                            let t = Rc::downgrade(&self.0);
+                            glib::MainContext::default().spawn_local(async move {
+                                let reply = reply.await;
+                                if let Some(t) = t.upgrade().map(RaeifiedTable) {
+                                    match reply {
+                                        Err(_) => t.fail_write(
+                                            &path,
+                                            serial,
+                                            "the write was dropped",
+                                        ),
+                                        Ok(Value::Error(e)) => {
+                                            t.fail_write(&path, serial, &*e)
+                                        }
+                                        Ok(_) => t.confirm_write(&path, serial),
+                                    }
+                                }
+                            });
+                        }
                     }
                 },
                 gtk::ResponseType::Cancel | _ => (),
@@ -1066,6 +1653,13 @@ impl RaeifiedTable {
         }
     }
 
+    fn cell_path(&self, row: &TreeIter, column: &str) -> Option<Path> {
+        let row = self.row_of(Either::Right(row));
+        row.as_ref()
+            .and_then(|r| r.get::<&str>().ok())
+            .map(|r| self.path_from_selected(r, column))
+    }
+
     fn path_from_treepath(&self, row: &gtk::TreePath, column: &str) -> Option<Path> {
         let row = self.row_of(Either::Left(row));
         row.as_ref()
@@ -1110,6 +1704,9 @@ impl RaeifiedTable {
                 .map(|p| Value::from(Chars::from(String::from(&*p))))
                 .collect::<Vec<_>>(),
         );
+        if let Some(state) = &*self.shared.state.borrow() {
+            state.set("selection", v.clone());
+        }
         let ev = vm::Event::User(LocalEvent::Event(v));
         let mut on_select = self.shared.on_select.borrow_mut();
         on_select.update(&mut self.shared.ctx.borrow_mut(), &ev);
@@ -1143,8 +1740,9 @@ impl RaeifiedTable {
         self.by_id.borrow_mut().retain(|_, v| match self.store().path(&v.row) {
             None => false,
             Some(p) => {
-                let visible =
-                    (p >= start && p <= end) || (Some(v.col) == self.sort_column.get());
+                let visible = (p >= start && p <= end)
+                    || (Some(v.col) == self.sort_column.get())
+                    || self.footer.borrow().iter().any(|(id, _, _)| *id == v.col);
                 if !visible {
                     let row_name_v = self.store().value(&v.row, 0);
                     if let Ok(row_name) = row_name_v.get::<&str>() {
@@ -1182,7 +1780,7 @@ impl RaeifiedTable {
                 let s = {
                     let (s, u) = {
                         let r = &self.shared.ctx.borrow().user;
-                        let s = r.backend.subscriber.subscribe(p);
+                        let s = r.backend.subscribe(p);
                         let u = r.backend.updates.clone();
                         (s, u)
                     };
@@ -1212,8 +1810,14 @@ impl RaeifiedTable {
             }
             start.next();
         }
-        // subscribe to all rows in the sort column
-        if let Some(id) = self.sort_column.get() {
+        // subscribe to all rows in the sort column and the footer columns
+        let all_rows = self
+            .sort_column
+            .get()
+            .into_iter()
+            .chain(self.footer.borrow().iter().map(|(id, _, _)| *id))
+            .collect::<FxHashSet<_>>();
+        for id in all_rows {
             if let Some(row) = self.store().iter_first() {
                 loop {
                     let row_name_v = self.store().value(&row, 0);
@@ -1267,6 +1871,7 @@ impl RaeifiedTable {
                     match t.0.update.borrow_mut().pop() {
                         None => break,
                         Some((id, v)) => if let Some(sub) = t.0.by_id.borrow().get(&id) {
+                            t.value_arrived(sub);
                             let mut formatted = FORMATTED.take();
                             write!(&mut *formatted, "{}", WVal(&v)).unwrap();
                             let bval = BVal {
@@ -1285,6 +1890,7 @@ impl RaeifiedTable {
                     }
                     t.enable_sort(sctx);
                     t.visible_changed();
+                    t.update_footer();
                     // this should not be necessary, however under
                     // some themes (e.g. breeze gtk) it seems it is,
                     // otherwise updated values might not be drawn
@@ -1296,6 +1902,25 @@ impl RaeifiedTable {
         }));
     }
 
+    /// A new value from the publisher settles any write to the cell,
+    /// whether it was pending or failed.
+    fn value_arrived(&self, sub: &Subscription) {
+        if self.pending_writes.borrow().is_empty()
+            && self.failed_writes.borrow().is_empty()
+        {
+            return;
+        }
+        let column = if self.vector_mode {
+            None
+        } else {
+            self.descriptor.cols.get_index((sub.col - 1) as usize).map(|(c, _)| c)
+        };
+        if let Some(path) = self.cell_path(&sub.row, column.map(|c| &**c).unwrap_or("")) {
+            self.pending_writes.borrow_mut().remove(&path);
+            self.failed_writes.borrow_mut().remove(&path);
+        }
+    }
+
     fn visible_changed(&self) {
         if self.view().is_visible() {
             let (mut start, end) = match self.view().visible_range() {
@@ -1313,12 +1938,18 @@ impl RaeifiedTable {
 
     pub(super) fn update(
         &self,
-        _ctx: BSCtxRef,
+        ctx: BSCtxRef,
         waits: &mut Vec<oneshot::Receiver<()>>,
         event: &vm::Event<LocalEvent>,
     ) {
         match event {
+            vm::Event::User(LocalEvent::TableExport(path, dest))
+                if path == &self.path =>
+            {
+                ctx.user.backend.export_table(dest.clone(), self.export_cells())
+            }
             vm::Event::Netidx(id, value) => {
+                self.record_history(*id, value);
                 self.update.borrow_mut().insert(*id, value.clone());
                 if self.update.borrow().len() == 1 {
                     let (tx, rx) = oneshot::channel();
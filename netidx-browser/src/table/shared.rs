@@ -1,4 +1,4 @@
-use super::super::{BSCtx, BSNode};
+use super::super::{BSCtx, BSNode, WidgetState};
 use anyhow::{anyhow, bail};
 use arcstr::ArcStr;
 use fxhash::{FxBuildHasher, FxHashMap, FxHashSet};
@@ -248,6 +248,57 @@ impl FromValue for SelectionMode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Aggregate {
+    Sum,
+    Avg,
+    Count,
+    Min,
+    Max,
+}
+
+impl FromValue for Aggregate {
+    fn from_value(v: Value) -> anyhow::Result<Self> {
+        match v.cast_to::<Chars>()? {
+            c if &*c == "sum" => Ok(Aggregate::Sum),
+            c if &*c == "avg" => Ok(Aggregate::Avg),
+            c if &*c == "count" => Ok(Aggregate::Count),
+            c if &*c == "min" => Ok(Aggregate::Min),
+            c if &*c == "max" => Ok(Aggregate::Max),
+            _ => bail!("invalid aggregate"),
+        }
+    }
+}
+
+impl Aggregate {
+    pub(super) fn compute(&self, values: impl Iterator<Item = Value>) -> Value {
+        let mut n = 0u64;
+        let mut acc: Option<f64> = None;
+        for v in values {
+            match self {
+                Aggregate::Count => n += 1,
+                Aggregate::Sum | Aggregate::Avg | Aggregate::Min | Aggregate::Max => {
+                    if let Ok(v) = v.cast_to::<f64>() {
+                        n += 1;
+                        acc = Some(match (self, acc) {
+                            (_, None) => v,
+                            (Aggregate::Min, Some(acc)) => acc.min(v),
+                            (Aggregate::Max, Some(acc)) => acc.max(v),
+                            (_, Some(acc)) => acc + v,
+                        });
+                    }
+                }
+            }
+        }
+        match (self, acc) {
+            (Aggregate::Count, _) => Value::U64(n),
+            (_, None) => Value::Null,
+            (Aggregate::Avg, Some(acc)) => Value::F64(acc / n as f64),
+            (_, Some(acc)) => Value::F64(acc),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(super) enum OrLoad<T> {
     Static(T),
@@ -521,6 +572,35 @@ impl FromValue for ColumnTypeProgress {
     }
 }
 
+pub(super) const DEFAULT_SPARKLINE_HISTORY: usize = 60;
+
+#[derive(Clone, PartialEq)]
+pub(super) struct ColumnTypeSparkline {
+    pub(super) common: ColumnTypeCommon,
+    pub(super) history: usize,
+    pub(super) color: Option<OrLoadCol<Color>>,
+    pub(super) min: Option<OrLoadCol<f64>>,
+    pub(super) max: Option<OrLoadCol<f64>>,
+}
+
+impl FromValue for ColumnTypeSparkline {
+    fn from_value(v: Value) -> anyhow::Result<Self> {
+        let mut props = v.cast_to::<FxHashMap<Chars, Value>>()?;
+        let history = match prop!(props, "history", u64) {
+            None => DEFAULT_SPARKLINE_HISTORY,
+            Some(0) => bail!("history must be at least 1"),
+            Some(n) => n as usize,
+        };
+        Ok(Self {
+            common: ColumnTypeCommon::from_props(&mut props)?,
+            history,
+            color: or_load_prop!(props, "color", "color-column", Color),
+            min: or_load_prop!(props, "min", "min-column", f64),
+            max: or_load_prop!(props, "max", "max-column", f64),
+        })
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub(super) enum ColumnType {
     Text(ColumnTypeText),
@@ -529,6 +609,7 @@ pub(super) enum ColumnType {
     Combo(ColumnTypeCombo),
     Spin(ColumnTypeSpin),
     Progress(ColumnTypeProgress),
+    Sparkline(ColumnTypeSparkline),
     Hidden,
 }
 
@@ -548,6 +629,7 @@ impl FromValue for ColumnSpec {
             "combo" => ColumnType::Combo(props.cast_to::<ColumnTypeCombo>()?),
             "spin" => ColumnType::Spin(props.cast_to::<ColumnTypeSpin>()?),
             "progress" => ColumnType::Progress(props.cast_to::<ColumnTypeProgress>()?),
+            "sparkline" => ColumnType::Sparkline(props.cast_to::<ColumnTypeSparkline>()?),
             "hidden" => ColumnType::Hidden,
             _ => bail!("invalid column type"),
         };
@@ -629,6 +711,8 @@ pub(super) struct SharedState {
     pub(super) column_types: RefCell<Option<Vec<ColumnSpec>>>,
     pub(super) column_widths: RefCell<FxHashMap<String, i32>>,
     pub(super) ctx: BSCtx,
+    pub(super) footer: RefCell<Option<Vec<(Chars, Aggregate)>>>,
+    pub(super) footer_row: gtk::Box,
     pub(super) on_activate: RefCell<BSNode>,
     pub(super) on_edit: RefCell<BSNode>,
     pub(super) on_header_click: RefCell<BSNode>,
@@ -642,6 +726,7 @@ pub(super) struct SharedState {
     pub(super) selection_mode: Cell<SelectionMode>,
     pub(super) show_name_column: Cell<bool>,
     pub(super) sort_mode: RefCell<SortSpec>,
+    pub(super) state: RefCell<Option<WidgetState>>,
 }
 
 impl SharedState {
@@ -649,6 +734,7 @@ impl SharedState {
         ctx: BSCtx,
         selected_path: Label,
         root: ScrolledWindow,
+        footer_row: gtk::Box,
         on_activate: BSNode,
         on_edit: BSNode,
         on_header_click: BSNode,
@@ -661,6 +747,8 @@ impl SharedState {
             column_types: RefCell::new(None),
             column_widths: RefCell::new(HashMap::default()),
             ctx,
+            footer: RefCell::new(None),
+            footer_row,
             selection_mode: Cell::new(SelectionMode::None),
             on_activate: RefCell::new(on_activate),
             on_edit: RefCell::new(on_edit),
@@ -677,6 +765,7 @@ impl SharedState {
             selected: RefCell::new(HashMap::default()),
             show_name_column: Cell::new(true),
             sort_mode: RefCell::new(SortSpec::None),
+            state: RefCell::new(None),
         }
     }
 
@@ -692,6 +781,10 @@ impl SharedState {
         set_field!(self, v, Option<Vec<ColumnSpec>>, column_types, Some)
     }
 
+    pub(super) fn set_footer(&self, v: Option<Value>) -> bool {
+        set_field!(self, v, Option<Vec<(Chars, Aggregate)>>, footer, Some)
+    }
+
     pub(super) fn set_column_filter(&self, v: Option<Value>) -> bool {
         set_field!(self, v, Filter, column_filter, Some)
     }
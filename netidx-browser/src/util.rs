@@ -41,6 +41,28 @@ pub(super) fn err_modal<W: WidgetExt>(w: &W, msg: &str) {
     unsafe { d.destroy() };
 }
 
+/// Append one line of csv to `buf`, fields containing a separator,
+/// a quote, or a line break are quoted.
+pub(super) fn csv_line<S: AsRef<str>, I: IntoIterator<Item = S>>(
+    buf: &mut String,
+    fields: I,
+) {
+    for (i, f) in fields.into_iter().enumerate() {
+        let f = f.as_ref();
+        if i > 0 {
+            buf.push(',');
+        }
+        if f.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+            buf.push('"');
+            buf.push_str(&f.replace('"', "\"\""));
+            buf.push('"');
+        } else {
+            buf.push_str(f);
+        }
+    }
+    buf.push('\n');
+}
+
 use parking_lot::{Condvar, Mutex};
 use std::sync::Arc;
 
@@ -1,8 +1,10 @@
 use super::{
     util, val_to_bool, BSCtx, BSCtxRef, BSNode, BWidget, ImageSpec, WVal, WidgetPath,
+    WidgetState,
 };
 use crate::{bscript::LocalEvent, containers, view};
 use anyhow::{bail, Result};
+use chrono::prelude::*;
 use futures::channel::oneshot;
 use gdk::{self, prelude::*};
 use glib::{clone, idle_add_local, idle_add_local_once};
@@ -951,6 +953,13 @@ impl BWidget for Entry {
     fn root(&self) -> Option<&gtk::Widget> {
         Some(self.entry.upcast_ref())
     }
+
+    fn export_state(&self, state: WidgetState) {
+        state.set("text", Value::from(String::from(self.entry.text())));
+        self.entry.connect_changed(move |e| {
+            state.set("text", Value::from(String::from(e.text())))
+        });
+    }
 }
 
 pub(super) struct SearchEntry {
@@ -1031,6 +1040,13 @@ impl BWidget for SearchEntry {
     fn root(&self) -> Option<&gtk::Widget> {
         Some(&self.entry.upcast_ref())
     }
+
+    fn export_state(&self, state: WidgetState) {
+        state.set("text", Value::from(String::from(self.entry.text())));
+        self.entry.connect_search_changed(move |e| {
+            state.set("text", Value::from(String::from(e.text())))
+        });
+    }
 }
 
 pub(super) struct Image {
@@ -1075,7 +1091,7 @@ impl Image {
             }
             Inhibit(true)
         }));
-        hover_path(&image, &selected_path, "on_click", &spec.spec);
+        hover_path(&root, &selected_path, "on_click", &spec.on_click);
         Image { image_spec, on_click, image, root }
     }
 
@@ -1152,6 +1168,7 @@ pub(super) struct Scale {
     value: BSNode,
     min: BSNode,
     max: BSNode,
+    step: BSNode,
     on_change: Rc<RefCell<BSNode>>,
     we_set: Rc<Cell<bool>>,
 }
@@ -1184,6 +1201,8 @@ impl Scale {
             BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.value.clone());
         let min = BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.min.clone());
         let max = BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.max.clone());
+        let step =
+            BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.step.clone());
         let on_change = Rc::new(RefCell::new(BSNode::compile(
             &mut ctx.borrow_mut(),
             scope.clone(),
@@ -1192,6 +1211,7 @@ impl Scale {
         let we_set = Rc::new(Cell::new(false));
         Self::set_min(&scale, min.current(&mut ctx.borrow_mut()));
         Self::set_max(&scale, max.current(&mut ctx.borrow_mut()));
+        Self::set_step(&scale, step.current(&mut ctx.borrow_mut()));
         Self::set_value(&scale, value.current(&mut ctx.borrow_mut()));
         Self::set_draw_value(&scale, draw_value.current(&mut ctx.borrow_mut()));
         Self::set_marks(&scale, marks.current(&mut ctx.borrow_mut()));
@@ -1207,7 +1227,18 @@ impl Scale {
             }),
         );
         hover_path(&scale, &selected_path, "on_change", &spec.on_change);
-        Self { scale, draw_value, marks, has_origin, value, min, max, on_change, we_set }
+        Self {
+            scale,
+            draw_value,
+            marks,
+            has_origin,
+            value,
+            min,
+            max,
+            step,
+            on_change,
+            we_set,
+        }
     }
 
     fn set_min(scale: &gtk::Scale, v: Option<Value>) {
@@ -1222,6 +1253,12 @@ impl Scale {
         }
     }
 
+    fn set_step(scale: &gtk::Scale, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<f64>().ok()) {
+            scale.adjustment().set_step_increment(v);
+        }
+    }
+
     fn set_value(scale: &gtk::Scale, v: Option<Value>) {
         if let Some(v) = v.and_then(|v| v.cast_to::<f64>().ok()) {
             scale.set_value(v);
@@ -1280,6 +1317,7 @@ impl BWidget for Scale {
         self.we_set.set(false);
         Self::set_min(&self.scale, self.min.update(ctx, event));
         Self::set_max(&self.scale, self.max.update(ctx, event));
+        Self::set_step(&self.scale, self.step.update(ctx, event));
         self.on_change.borrow_mut().update(ctx, event);
     }
 
@@ -1288,6 +1326,325 @@ impl BWidget for Scale {
     }
 }
 
+pub(super) struct SpinButton {
+    spin: gtk::SpinButton,
+    value: BSNode,
+    min: BSNode,
+    max: BSNode,
+    step: BSNode,
+    digits: BSNode,
+    on_change: Rc<RefCell<BSNode>>,
+    we_set: Rc<Cell<bool>>,
+}
+
+impl SpinButton {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        spec: view::SpinButton,
+        scope: Path,
+        selected_path: gtk::Label,
+    ) -> Self {
+        let spin = gtk::SpinButton::new(gtk::Adjustment::NONE, 1., 0);
+        spin.set_no_show_all(true);
+        let value =
+            BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.value.clone());
+        let min = BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.min.clone());
+        let max = BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.max.clone());
+        let step =
+            BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.step.clone());
+        let digits =
+            BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.digits.clone());
+        let on_change = Rc::new(RefCell::new(BSNode::compile(
+            &mut ctx.borrow_mut(),
+            scope.clone(),
+            spec.on_change.clone(),
+        )));
+        let we_set = Rc::new(Cell::new(false));
+        Self::set_min(&spin, min.current(&mut ctx.borrow_mut()));
+        Self::set_max(&spin, max.current(&mut ctx.borrow_mut()));
+        Self::set_step(&spin, step.current(&mut ctx.borrow_mut()));
+        Self::set_digits(&spin, digits.current(&mut ctx.borrow_mut()));
+        Self::we_set_value(&we_set, &spin, value.current(&mut ctx.borrow_mut()));
+        spin.connect_value_changed(
+            clone!(@strong on_change, @strong ctx, @strong we_set => move |spin| {
+                if !we_set.get() {
+                    on_change.borrow_mut().update(
+                        &mut ctx.borrow_mut(),
+                        &vm::Event::User(LocalEvent::Event(spin.value().into()))
+                    );
+                }
+            }),
+        );
+        hover_path(&spin, &selected_path, "on_change", &spec.on_change);
+        Self { spin, value, min, max, step, digits, on_change, we_set }
+    }
+
+    fn set_min(spin: &gtk::SpinButton, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<f64>().ok()) {
+            spin.adjustment().set_lower(v);
+        }
+    }
+
+    fn set_max(spin: &gtk::SpinButton, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<f64>().ok()) {
+            spin.adjustment().set_upper(v);
+        }
+    }
+
+    fn set_step(spin: &gtk::SpinButton, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<f64>().ok()) {
+            spin.set_increments(v, v * 10.);
+        }
+    }
+
+    fn set_digits(spin: &gtk::SpinButton, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<u32>().ok()) {
+            spin.set_digits(v);
+        }
+    }
+
+    fn we_set_value(we_set: &Cell<bool>, spin: &gtk::SpinButton, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<f64>().ok()) {
+            we_set.set(true);
+            spin.set_value(v);
+            we_set.set(false);
+        }
+    }
+}
+
+impl BWidget for SpinButton {
+    fn update(
+        &mut self,
+        ctx: BSCtxRef,
+        _waits: &mut Vec<oneshot::Receiver<()>>,
+        event: &vm::Event<LocalEvent>,
+    ) {
+        Self::set_min(&self.spin, self.min.update(ctx, event));
+        Self::set_max(&self.spin, self.max.update(ctx, event));
+        Self::set_step(&self.spin, self.step.update(ctx, event));
+        Self::set_digits(&self.spin, self.digits.update(ctx, event));
+        Self::we_set_value(&self.we_set, &self.spin, self.value.update(ctx, event));
+        self.on_change.borrow_mut().update(ctx, event);
+    }
+
+    fn root(&self) -> Option<&gtk::Widget> {
+        Some(self.spin.upcast_ref())
+    }
+}
+
+pub(super) struct Calendar {
+    calendar: gtk::Calendar,
+    value: BSNode,
+    on_change: Rc<RefCell<BSNode>>,
+    we_set: Rc<Cell<bool>>,
+}
+
+impl Calendar {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        spec: view::Calendar,
+        scope: Path,
+        selected_path: gtk::Label,
+    ) -> Self {
+        let calendar = gtk::Calendar::new();
+        calendar.set_no_show_all(true);
+        let value =
+            BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.value.clone());
+        let on_change = Rc::new(RefCell::new(BSNode::compile(
+            &mut ctx.borrow_mut(),
+            scope.clone(),
+            spec.on_change.clone(),
+        )));
+        let we_set = Rc::new(Cell::new(false));
+        Self::we_set_value(&we_set, &calendar, value.current(&mut ctx.borrow_mut()));
+        calendar.connect_day_selected(
+            clone!(@strong on_change, @strong ctx, @strong we_set => move |calendar| {
+                if !we_set.get() {
+                    let (year, month, day) = calendar.date();
+                    let date = NaiveDate::from_ymd_opt(year as i32, month + 1, day)
+                        .and_then(|d| d.and_hms_opt(0, 0, 0));
+                    if let Some(date) = date {
+                        let v = Value::DateTime(Utc.from_utc_datetime(&date));
+                        on_change.borrow_mut().update(
+                            &mut ctx.borrow_mut(),
+                            &vm::Event::User(LocalEvent::Event(v))
+                        );
+                    }
+                }
+            }),
+        );
+        hover_path(&calendar, &selected_path, "on_change", &spec.on_change);
+        Self { calendar, value, on_change, we_set }
+    }
+
+    fn we_set_value(we_set: &Cell<bool>, calendar: &gtk::Calendar, v: Option<Value>) {
+        if let Some(v) = v.and_then(|v| v.cast_to::<DateTime<Utc>>().ok()) {
+            we_set.set(true);
+            calendar.select_month(v.month0(), v.year() as u32);
+            calendar.select_day(v.day());
+            we_set.set(false);
+        }
+    }
+}
+
+impl BWidget for Calendar {
+    fn update(
+        &mut self,
+        ctx: BSCtxRef,
+        _waits: &mut Vec<oneshot::Receiver<()>>,
+        event: &vm::Event<LocalEvent>,
+    ) {
+        Self::we_set_value(&self.we_set, &self.calendar, self.value.update(ctx, event));
+        self.on_change.borrow_mut().update(ctx, event);
+    }
+
+    fn root(&self) -> Option<&gtk::Widget> {
+        Some(self.calendar.upcast_ref())
+    }
+}
+
+pub(super) struct RadioGroup {
+    ctx: BSCtx,
+    root: gtk::Box,
+    buttons: Rc<RefCell<Vec<(Chars, gtk::RadioButton)>>>,
+    choices: BSNode,
+    selected: Rc<RefCell<BSNode>>,
+    on_change: Rc<RefCell<BSNode>>,
+    we_set: Rc<Cell<bool>>,
+    selected_path: gtk::Label,
+    on_change_expr: Expr,
+}
+
+impl RadioGroup {
+    pub(super) fn new(
+        ctx: &BSCtx,
+        spec: view::RadioGroup,
+        scope: Path,
+        selected_path: gtk::Label,
+    ) -> Self {
+        let root = gtk::Box::new(containers::dir_to_gtk(&spec.direction), 5);
+        root.set_no_show_all(true);
+        let choices =
+            BSNode::compile(&mut ctx.borrow_mut(), scope.clone(), spec.choices.clone());
+        let selected = Rc::new(RefCell::new(BSNode::compile(
+            &mut ctx.borrow_mut(),
+            scope.clone(),
+            spec.selected.clone(),
+        )));
+        let on_change = Rc::new(RefCell::new(BSNode::compile(
+            &mut ctx.borrow_mut(),
+            scope,
+            spec.on_change.clone(),
+        )));
+        let t = Self {
+            ctx: ctx.clone(),
+            root,
+            buttons: Rc::new(RefCell::new(Vec::new())),
+            choices,
+            selected,
+            on_change,
+            we_set: Rc::new(Cell::new(false)),
+            selected_path,
+            on_change_expr: spec.on_change,
+        };
+        t.set_choices(t.choices.current(&mut ctx.borrow_mut()));
+        let v = t.selected.borrow().current(&mut ctx.borrow_mut());
+        Self::we_set_selected(&t.we_set, &t.buttons.borrow(), v);
+        t
+    }
+
+    fn we_set_selected(
+        we_set: &Cell<bool>,
+        buttons: &[(Chars, gtk::RadioButton)],
+        v: Option<Value>,
+    ) {
+        if let Some(id) = v.and_then(|v| v.cast_to::<Chars>().ok()) {
+            we_set.set(true);
+            for (bid, button) in buttons {
+                if bid == &id {
+                    button.set_active(true);
+                }
+            }
+            we_set.set(false);
+        }
+    }
+
+    fn set_choices(&self, v: Option<Value>) {
+        let choices = match v.and_then(|v| v.cast_to::<Vec<(Chars, Chars)>>().ok()) {
+            None => return,
+            Some(choices) => choices,
+        };
+        let mut buttons = self.buttons.borrow_mut();
+        for (_, button) in buttons.drain(..) {
+            self.root.remove(&button);
+        }
+        let mut first: Option<gtk::RadioButton> = None;
+        for (id, label) in choices {
+            let button = match &first {
+                None => gtk::RadioButton::with_label(&*label),
+                Some(first) => gtk::RadioButton::with_label_from_widget(first, &*label),
+            };
+            hover_path(&button, &self.selected_path, "on_change", &self.on_change_expr);
+            button.connect_toggled(clone!(
+                @strong self.we_set as we_set,
+                @strong self.on_change as on_change,
+                @strong self.selected as selected,
+                @strong self.buttons as buttons,
+                @strong self.ctx as ctx,
+                @strong id => move |button| {
+                if !we_set.get() && button.is_active() {
+                    on_change.borrow_mut().update(
+                        &mut ctx.borrow_mut(),
+                        &vm::Event::User(LocalEvent::Event(Value::String(id.clone())))
+                    );
+                    idle_add_local_once(clone!(
+                        @strong ctx,
+                        @strong selected,
+                        @strong buttons,
+                        @strong we_set => move || {
+                            let v = selected.borrow().current(&mut ctx.borrow_mut());
+                            Self::we_set_selected(&we_set, &buttons.borrow(), v);
+                        })
+                    );
+                }
+            }));
+            self.root.pack_start(&button, false, false, 0);
+            button.show();
+            if first.is_none() {
+                first = Some(button.clone());
+            }
+            buttons.push((id, button));
+        }
+    }
+}
+
+impl BWidget for RadioGroup {
+    fn update(
+        &mut self,
+        ctx: BSCtxRef,
+        _waits: &mut Vec<oneshot::Receiver<()>>,
+        event: &vm::Event<LocalEvent>,
+    ) {
+        self.on_change.borrow_mut().update(ctx, event);
+        let v = self.choices.update(ctx, event);
+        self.set_choices(v);
+        let v = self.selected.borrow_mut().update(ctx, event);
+        Self::we_set_selected(&self.we_set, &self.buttons.borrow(), v);
+    }
+
+    fn root(&self) -> Option<&gtk::Widget> {
+        Some(self.root.upcast_ref())
+    }
+
+    fn set_highlight(&self, mut path: std::slice::Iter<WidgetPath>, h: bool) {
+        if let Some(WidgetPath::Leaf) = path.next() {
+            for (_, button) in self.buttons.borrow().iter() {
+                util::set_highlight(button, h);
+            }
+        }
+    }
+}
+
 pub(super) struct ProgressBar {
     progress: gtk::ProgressBar,
     ellipsize: BSNode,
@@ -0,0 +1,308 @@
+use super::{util::err_modal, DEFAULT_PROPS};
+use gtk::{self, prelude::*};
+use netidx::{chars::Chars, path::Path, subscriber::Value};
+use netidx_bscript::expr::{Expr, ExprKind};
+use netidx_protocols::view;
+use std::boxed;
+
+/// The variable the table/detail/plot pattern stores the selected
+/// row in.
+static SELECTED: &'static str = "wizard_selected_row";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    TableDetailPlot,
+    FormStatus,
+}
+
+#[derive(Debug, Clone)]
+struct Answers {
+    pattern: Pattern,
+    source: Path,
+    key_columns: Vec<String>,
+    writable: Vec<String>,
+}
+
+fn ce(v: Value) -> Expr {
+    ExprKind::Constant(v).to_expr()
+}
+
+fn apply(function: &str, args: Vec<Expr>) -> Expr {
+    ExprKind::Apply { function: function.into(), args }.to_expr()
+}
+
+fn event() -> Expr {
+    apply("event", vec![])
+}
+
+fn string(s: &str) -> Value {
+    Value::String(Chars::from(String::from(s)))
+}
+
+fn widget(kind: view::WidgetKind) -> view::Widget {
+    view::Widget { kind, props: None }
+}
+
+fn expanding(kind: view::WidgetKind) -> view::Widget {
+    let props =
+        Some(view::WidgetProps { vexpand: true, hexpand: true, ..DEFAULT_PROPS.clone() });
+    view::Widget { kind, props }
+}
+
+fn label(text: Expr) -> view::Widget {
+    widget(view::WidgetKind::Label(view::Label {
+        text,
+        width: ce(Value::Null),
+        ellipsize: ce(Value::Null),
+        selectable: ce(Value::True),
+        single_line: ce(Value::True),
+    }))
+}
+
+/// `["include", [col, ...]]`, or `default` if there are no columns
+fn include(cols: &[String], default: Value) -> Expr {
+    if cols.is_empty() {
+        ce(default)
+    } else {
+        let cols = cols.iter().map(|c| string(c)).collect::<Vec<_>>();
+        ce(Value::from(vec![string("include"), Value::from(cols)]))
+    }
+}
+
+fn table(path: Expr, cols: &[String], writable: &[String]) -> view::Table {
+    view::Table {
+        path,
+        column_filter: include(cols, Value::Null),
+        column_editable: include(writable, Value::False),
+        columns_resizable: ce(Value::True),
+        on_edit: apply(
+            "store",
+            vec![
+                apply("index", vec![event(), ce(Value::U64(0))]),
+                apply("index", vec![event(), ce(Value::U64(1))]),
+            ],
+        ),
+        ..view::Table::default()
+    }
+}
+
+fn paned(
+    direction: view::Direction,
+    first: view::Widget,
+    second: view::Widget,
+) -> view::Widget {
+    expanding(view::WidgetKind::Paned(view::Paned {
+        direction,
+        wide_handle: false,
+        first_child: Some(boxed::Box::new(first)),
+        second_child: Some(boxed::Box::new(second)),
+    }))
+}
+
+/// A table of the source, a detail table of the selected row, and a
+/// plot of the key columns of the selected row.
+fn table_detail_plot(a: &Answers) -> view::Widget {
+    let selected = || apply("get", vec![ce(string(SELECTED))]);
+    let main = table(ce(string(&*a.source)), &a.key_columns, &a.writable);
+    let main = view::Table {
+        on_select: apply(
+            "set",
+            vec![
+                ce(string(SELECTED)),
+                apply("dirname", vec![apply("index", vec![event(), ce(Value::U64(0))])]),
+            ],
+        ),
+        ..main
+    };
+    let detail = table(selected(), &[], &a.writable);
+    let series = a
+        .key_columns
+        .iter()
+        .enumerate()
+        .map(|(i, col)| {
+            let path = apply(
+                "string_concat",
+                vec![selected(), ce(string(&format!("/{}", col)))],
+            );
+            let y = apply("load", vec![path]);
+            let hue = i as f64 / a.key_columns.len() as f64;
+            view::Series {
+                title: col.clone(),
+                line_color: view::RGB { r: 1. - hue, g: 0., b: hue },
+                x: apply("count", vec![y.clone()]),
+                y,
+            }
+        })
+        .collect::<Vec<_>>();
+    let plot = view::LinePlot {
+        title: String::from("Selected Row"),
+        x_label: String::from("updates"),
+        y_label: String::from("value"),
+        x_labels: 4,
+        y_labels: 4,
+        x_grid: true,
+        y_grid: true,
+        fill: Some(view::RGB { r: 1., g: 1., b: 1. }),
+        margin: 3,
+        label_area: 50,
+        x_min: ce(Value::Null),
+        x_max: ce(Value::Null),
+        y_min: ce(Value::Null),
+        y_max: ce(Value::Null),
+        keep_points: ce(Value::U64(256)),
+        series,
+        zoom: true,
+        cursor: true,
+        on_cursor: ce(Value::Null),
+    };
+    let bottom = paned(
+        view::Direction::Horizontal,
+        expanding(view::WidgetKind::Table(detail)),
+        expanding(view::WidgetKind::LinePlot(plot)),
+    );
+    paned(view::Direction::Vertical, expanding(view::WidgetKind::Table(main)), bottom)
+}
+
+/// A grid of `name: widget` rows, one per field
+fn field_grid<F: Fn(Path) -> view::Widget>(
+    source: &Path,
+    fields: &[String],
+    f: F,
+) -> view::Widget {
+    let rows = fields
+        .iter()
+        .map(|field| {
+            let name = label(ce(string(&format!("{}:", field))));
+            let columns = vec![name, f(source.append(field))];
+            widget(view::WidgetKind::GridRow(view::GridRow { columns }))
+        })
+        .collect();
+    widget(view::WidgetKind::Grid(view::Grid {
+        homogeneous_columns: false,
+        homogeneous_rows: false,
+        column_spacing: 5,
+        row_spacing: 5,
+        rows,
+    }))
+}
+
+/// Entries for each writable field, and a status panel showing the
+/// key columns.
+fn form_status(a: &Answers) -> view::Widget {
+    let form = field_grid(&a.source, &a.writable, |path| {
+        let path = ce(string(&*path));
+        widget(view::WidgetKind::Entry(view::Entry {
+            text: apply("load", vec![path.clone()]),
+            on_change: apply("sample", vec![event(), ce(Value::True)]),
+            on_activate: apply("store", vec![path, event()]),
+        }))
+    });
+    let status = field_grid(&a.source, &a.key_columns, |path| {
+        label(apply("load", vec![ce(string(&*path))]))
+    });
+    let frame = |title: &str, child: view::Widget| {
+        widget(view::WidgetKind::Frame(view::Frame {
+            label: ce(string(title)),
+            label_align_horizontal: 0.,
+            label_align_vertical: 0.5,
+            child: Some(boxed::Box::new(child)),
+        }))
+    };
+    let child = |w: view::Widget| {
+        widget(view::WidgetKind::BoxChild(view::BoxChild {
+            pack: view::Pack::Start,
+            padding: 5,
+            widget: boxed::Box::new(w),
+        }))
+    };
+    widget(view::WidgetKind::Box(view::Box {
+        direction: view::Direction::Vertical,
+        homogeneous: false,
+        spacing: 5,
+        children: vec![child(frame("Settings", form)), child(frame("Status", status))],
+    }))
+}
+
+fn generate(a: &Answers) -> view::Widget {
+    match a.pattern {
+        Pattern::TableDetailPlot => table_detail_plot(a),
+        Pattern::FormStatus => form_status(a),
+    }
+}
+
+fn split_columns(s: &str) -> Vec<String> {
+    s.split(',').map(|c| c.trim()).filter(|c| !c.is_empty()).map(String::from).collect()
+}
+
+/// Ask the user a few questions about the data they want to display,
+/// and generate a starter view for it. The generated spec is an
+/// ordinary view that can be further edited in design mode.
+pub(crate) fn run(parent: &gtk::ApplicationWindow) -> Option<view::Widget> {
+    let d = gtk::Dialog::with_buttons(
+        Some("New View Wizard"),
+        Some(parent),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR,
+        &[("Cancel", gtk::ResponseType::Cancel), ("Create", gtk::ResponseType::Accept)],
+    );
+    let root = d.content_area();
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(5);
+    grid.set_column_spacing(10);
+    let row = |i: i32, name: &str, w: &gtk::Widget, tip: &str| {
+        let l = gtk::Label::new(Some(name));
+        l.set_halign(gtk::Align::Start);
+        w.set_tooltip_text(Some(tip));
+        w.set_hexpand(true);
+        grid.attach(&l, 0, i, 1, 1);
+        grid.attach(w, 1, i, 1, 1);
+    };
+    let pattern = gtk::ComboBoxText::new();
+    pattern.append(Some("table"), "Table + Detail + Plot");
+    pattern.append(Some("form"), "Form + Status");
+    pattern.set_active_id(Some("table"));
+    let source = gtk::Entry::new();
+    let keys = gtk::Entry::new();
+    let writable = gtk::Entry::new();
+    row(0, "Pattern:", pattern.upcast_ref(), "The layout of the generated view");
+    row(1, "Source Subtree:", source.upcast_ref(), "The netidx path to display");
+    row(
+        2,
+        "Key Columns:",
+        keys.upcast_ref(),
+        "Comma separated columns to show, plot, or display as status",
+    );
+    row(
+        3,
+        "Writable Fields:",
+        writable.upcast_ref(),
+        "Comma separated columns or fields the user may edit",
+    );
+    root.add(&grid);
+    root.show_all();
+    let res = loop {
+        match d.run() {
+            gtk::ResponseType::Accept => {
+                let src = String::from(source.text());
+                if !Path::is_absolute(&src) {
+                    err_modal(&d, "The source subtree must be an absolute path");
+                    continue;
+                }
+                let answers = Answers {
+                    pattern: match pattern.active_id().as_ref().map(|s| &**s) {
+                        Some("form") => Pattern::FormStatus,
+                        _ => Pattern::TableDetailPlot,
+                    },
+                    source: Path::from(src),
+                    key_columns: split_columns(&*keys.text()),
+                    writable: split_columns(&*writable.text()),
+                };
+                break Some(generate(&answers));
+            }
+            _ => break None,
+        }
+    };
+    unsafe {
+        d.destroy();
+    }
+    res
+}
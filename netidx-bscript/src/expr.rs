@@ -302,6 +302,56 @@ impl FromStr for Expr {
     }
 }
 
+/// A named function, `def name(a, b) = body`. When called the
+/// arguments are bound to local variables named after the parameters
+/// in a fresh scope, and the body is evaluated in that scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnDef {
+    pub name: String,
+    pub args: Vec<String>,
+    pub body: Expr,
+}
+
+impl fmt::Display for FnDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "def {}(", self.name)?;
+        for (i, a) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?
+            }
+            write!(f, "{}", a)?
+        }
+        write!(f, ") = {}", self.body)
+    }
+}
+
+impl FromStr for FnDef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        parser::parse_fndef(s)
+    }
+}
+
+impl Serialize for FnDef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for FnDef {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(de)?;
+        FnDef::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -389,9 +439,11 @@ mod tests {
             Just(String::from("filter")),
             Just(String::from("cast")),
             Just(String::from("isa")),
+            Just(String::from("match")),
             Just(String::from("eval")),
             Just(String::from("count")),
             Just(String::from("sample")),
+            Just(String::from("guard")),
             Just(String::from("string_join")),
             Just(String::from("string_concat")),
             Just(String::from("navigate")),
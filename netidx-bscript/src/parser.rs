@@ -1,17 +1,17 @@
-use crate::expr::{Expr, ExprId, ExprKind};
+use crate::expr::{Expr, ExprId, ExprKind, FnDef};
 use combine::{
-    attempt, between, choice, many,
+    attempt, between, choice, many, none_of, not_followed_by,
     parser::{
-        char::{spaces, string},
+        char::{space, spaces, string},
         combinator::recognize,
         range::{take_while, take_while1},
     },
-    sep_by,
+    sep_by, sep_by1, skip_many1,
     stream::{position, Range},
     token, unexpected_any, value, EasyParser, ParseError, Parser, RangeStream,
 };
 use netidx::{chars::Chars, publisher::Value};
-use netidx_netproto::value_parser::{close_expr, escaped_string, value as netidx_value};
+use netidx_netproto::value_parser::{escaped_string, value as netidx_value};
 
 pub static BSCRIPT_ESC: [char; 4] = ['"', '\\', '[', ']'];
 
@@ -36,6 +36,17 @@ where
     })
 }
 
+/// like `close_expr`, but a variable may also be the key in a map
+/// literal
+fn close_var<I>() -> impl Parser<I, Output = ()>
+where
+    I: RangeStream<Token = char>,
+    I::Error: ParseError<I::Token, I::Range, I::Position>,
+    I::Range: Range,
+{
+    not_followed_by(none_of([' ', '\n', '\t', ';', ')', ',', ']', '}', '"', ':']))
+}
+
 fn interpolated_<I>() -> impl Parser<I, Output = Expr>
 where
     I: RangeStream<Token = char>,
@@ -126,6 +137,20 @@ where
     I::Range: Range,
 {
     spaces().with(choice((
+        attempt(
+            between(
+                spaces().with(token('{')),
+                spaces().with(token('}')),
+                spaces().with(sep_by1(
+                    (expr(), spaces().with(token(':')), expr()).map(|(k, _, v)| {
+                        ExprKind::Apply { function: "array".into(), args: vec![k, v] }
+                            .to_expr()
+                    }),
+                    attempt(spaces().with(token(','))),
+                )),
+            )
+            .map(|args| ExprKind::Apply { function: "array".into(), args }.to_expr()),
+        ),
         attempt(
             between(
                 spaces().with(token('{')),
@@ -178,7 +203,7 @@ where
         })),
         attempt(interpolated()),
         attempt(netidx_value(&BSCRIPT_ESC).map(|v| ExprKind::Constant(v).to_expr())),
-        fname().skip(close_expr()).map(|var| {
+        fname().skip(close_var()).map(|var| {
             ExprKind::Apply {
                 function: "get".into(),
                 args: vec![ExprKind::Constant(Value::String(Chars::from(var))).to_expr()],
@@ -203,6 +228,31 @@ pub fn parse_expr(s: &str) -> anyhow::Result<Expr> {
         .map_err(|e| anyhow::anyhow!(format!("{}", e)))
 }
 
+fn fndef<I>() -> impl Parser<I, Output = FnDef>
+where
+    I: RangeStream<Token = char>,
+    I::Error: ParseError<I::Token, I::Range, I::Position>,
+    I::Range: Range,
+{
+    (
+        spaces().with(string("def")).with(skip_many1(space())).with(fname()),
+        between(
+            spaces().with(token('(')),
+            spaces().with(token(')')),
+            sep_by(spaces().with(fname()), attempt(spaces().with(token(',')))),
+        ),
+        spaces().with(token('=')).with(expr()),
+    )
+        .map(|(name, args, body)| FnDef { name, args, body })
+}
+
+pub fn parse_fndef(s: &str) -> anyhow::Result<FnDef> {
+    fndef()
+        .easy_parse(position::Stream::new(s))
+        .map(|(r, _)| r)
+        .map_err(|e| anyhow::anyhow!(format!("{}", e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -355,4 +405,29 @@ mod tests {
             r#"sum(f32:1., load("/foo/bar"), max(f32:675.6, load("/foo/baz")), rand())"#;
         assert_eq!(src, parse_expr(chs).unwrap());
     }
+
+    #[test]
+    fn map_parse() {
+        let m = parse_expr(r#"{"a": 1, b : [2, 3], "c": {x; y}}"#).unwrap();
+        let a = parse_expr(r#"[["a", 1], [b, [2, 3]], ["c", do(x, y)]]"#).unwrap();
+        assert_eq!(m, a);
+        assert_eq!(parse_expr("{x; y}").unwrap(), parse_expr("do(x, y)").unwrap());
+        assert_eq!(parse_expr("{}").unwrap(), parse_expr("do()").unwrap());
+        assert!(parse_expr(r#"{"a": 1; "b": 2}"#).is_err());
+    }
+
+    #[test]
+    fn fndef_parse() {
+        let s = r#"def scale(x, factor) = product(x, factor)"#;
+        let def = parse_fndef(s).unwrap();
+        assert_eq!(def.name, "scale");
+        assert_eq!(def.args, vec![String::from("x"), String::from("factor")]);
+        assert_eq!(def.body, parse_expr("product(x, factor)").unwrap());
+        assert_eq!(def, parse_fndef(&def.to_string()).unwrap());
+        let def = parse_fndef("def zero() = 0").unwrap();
+        assert!(def.args.is_empty());
+        assert_eq!(def, parse_fndef(&def.to_string()).unwrap());
+        assert!(parse_fndef("defzero() = 0").is_err());
+        assert!(parse_fndef("def Zero() = 0").is_err());
+    }
 }
@@ -1,7 +1,8 @@
 use crate::{
-    expr::{Expr, ExprId, VNAME},
+    expr::{Expr, ExprId, ExprKind, FnDef, VNAME},
     vm::{Apply, Ctx, Event, ExecCtx, InitFn, Node, Register},
 };
+use chrono::Utc;
 use fxhash::{FxBuildHasher, FxHashSet};
 use netidx::{
     chars::Chars,
@@ -9,7 +10,15 @@ use netidx::{
     subscriber::{self, Dval, Typ, UpdatesFlags, Value},
 };
 use netidx_core::utils::Either;
-use std::{collections::HashSet, iter, marker::PhantomData, sync::Arc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::{
+    borrow::Cow,
+    collections::{HashSet, VecDeque},
+    iter,
+    marker::PhantomData,
+    sync::Arc,
+    time::Duration,
+};
 
 pub struct CachedVals(pub Vec<Option<Value>>);
 
@@ -81,6 +90,9 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Any {
     }
 }
 
+/// once(source): produce the first value of source, and ignore
+/// every later update. If source already has a value when the
+/// expression is compiled then that is the only value produced.
 pub struct Once {
     val: Option<Value>,
     invalid: bool,
@@ -393,6 +405,218 @@ impl CachedCurEval for MaxEv {
 
 pub type Max = CachedCur<MaxEv>;
 
+pub struct ClampEv;
+
+impl CachedCurEval for ClampEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [Some(lo), Some(hi), Some(src)] => {
+                let typ = Typ::get(src);
+                match (lo.clone().cast(typ), hi.clone().cast(typ)) {
+                    (Some(lo), Some(hi)) if lo > hi => Some(Value::Error(Chars::from(
+                        "clamp(lo, hi, src): lo must not be greater than hi",
+                    ))),
+                    (Some(lo), Some(_)) if src < &lo => Some(lo),
+                    (Some(_), Some(hi)) if src > &hi => Some(hi),
+                    (Some(_), Some(_)) => Some(src.clone()),
+                    (_, _) => Some(Value::Error(Chars::from(format!(
+                        "clamp(lo, hi, src): can't cast lo and hi to {}",
+                        typ.name()
+                    )))),
+                }
+            }
+            [_, _, _] => None,
+            _ => Some(Value::Error(Chars::from(
+                "clamp(lo, hi, src): expected 3 arguments",
+            ))),
+        }
+    }
+
+    fn name() -> &'static str {
+        "clamp"
+    }
+}
+
+pub type Clamp = CachedCur<ClampEv>;
+
+/// evaluate a function of one number that returns a number of the
+/// same type, integers are passed through `int`, floats and decimals
+/// through `float` and `dec`.
+fn map_number(
+    from: &CachedVals,
+    name: &'static str,
+    int: impl Fn(Value) -> Value,
+    float: impl Fn(f64) -> f64,
+    dec: impl Fn(Decimal) -> Decimal,
+) -> Option<Value> {
+    match &*from.0 {
+        [None] => None,
+        [Some(v)] => match v {
+            Value::U32(_)
+            | Value::V32(_)
+            | Value::I32(_)
+            | Value::Z32(_)
+            | Value::U64(_)
+            | Value::V64(_)
+            | Value::I64(_)
+            | Value::Z64(_) => Some(int(v.clone())),
+            Value::F32(f) => Some(Value::F32(float(*f as f64) as f32)),
+            Value::F64(f) => Some(Value::F64(float(*f))),
+            Value::Decimal(d) => Some(Value::Decimal(dec(*d))),
+            _ => Some(Value::Error(Chars::from(format!("{}: expected a number", name)))),
+        },
+        _ => Some(Value::Error(Chars::from(format!("{}: expected 1 argument", name)))),
+    }
+}
+
+pub struct AbsEv;
+
+impl CachedCurEval for AbsEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        map_number(
+            from,
+            "abs(src)",
+            |v| match v {
+                Value::I32(i) => Value::I32(i.saturating_abs()),
+                Value::Z32(i) => Value::Z32(i.saturating_abs()),
+                Value::I64(i) => Value::I64(i.saturating_abs()),
+                Value::Z64(i) => Value::Z64(i.saturating_abs()),
+                v => v,
+            },
+            f64::abs,
+            |d| d.abs(),
+        )
+    }
+
+    fn name() -> &'static str {
+        "abs"
+    }
+}
+
+pub type Abs = CachedCur<AbsEv>;
+
+pub struct FloorEv;
+
+impl CachedCurEval for FloorEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        map_number(from, "floor(src)", |v| v, f64::floor, |d| d.floor())
+    }
+
+    fn name() -> &'static str {
+        "floor"
+    }
+}
+
+pub type Floor = CachedCur<FloorEv>;
+
+pub struct CeilEv;
+
+impl CachedCurEval for CeilEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        map_number(from, "ceil(src)", |v| v, f64::ceil, |d| d.ceil())
+    }
+
+    fn name() -> &'static str {
+        "ceil"
+    }
+}
+
+pub type Ceil = CachedCur<CeilEv>;
+
+// decimals round half to even by default, but everything else rounds
+// half away from zero
+const HALF_AWAY: RoundingStrategy = RoundingStrategy::MidpointAwayFromZero;
+
+/// round `i` half away from zero to a multiple of `10^places`
+fn round_int(i: i128, places: u32) -> Option<i128> {
+    let m = 10i128.checked_pow(places)?;
+    Some(i.signum() * ((i.abs() + m / 2) / m * m))
+}
+
+fn round_value(digits: i64, v: Value) -> Value {
+    macro_rules! int {
+        ($typ:ident, $i:expr) => {
+            if digits >= 0 {
+                Value::$typ($i)
+            } else {
+                let places = u32::try_from(-digits).unwrap_or(u32::MAX);
+                match round_int($i as i128, places).map(|i| i.try_into()) {
+                    Some(Ok(i)) => Value::$typ(i),
+                    None => Value::$typ(0),
+                    Some(Err(_)) => {
+                        Value::Error(Chars::from("round(digits, src): overflow"))
+                    }
+                }
+            }
+        };
+    }
+    let float = |f: f64| {
+        let m = 10f64.powi(digits.unsigned_abs().min(i32::MAX as u64) as i32);
+        let r = if digits >= 0 { (f * m).round() / m } else { (f / m).round() * m };
+        if r.is_finite() {
+            r
+        } else {
+            f
+        }
+    };
+    match v {
+        Value::U32(i) => int!(U32, i),
+        Value::V32(i) => int!(V32, i),
+        Value::I32(i) => int!(I32, i),
+        Value::Z32(i) => int!(Z32, i),
+        Value::U64(i) => int!(U64, i),
+        Value::V64(i) => int!(V64, i),
+        Value::I64(i) => int!(I64, i),
+        Value::Z64(i) => int!(Z64, i),
+        Value::F32(f) => Value::F32(float(f as f64) as f32),
+        Value::F64(f) => Value::F64(float(f)),
+        Value::Decimal(d) if digits >= 0 => {
+            let places = u32::try_from(digits).unwrap_or(u32::MAX);
+            Value::Decimal(d.round_dp_with_strategy(places, HALF_AWAY))
+        }
+        Value::Decimal(d) => {
+            let places = u32::try_from(-digits).unwrap_or(u32::MAX);
+            let m = 10i128
+                .checked_pow(places)
+                .and_then(|m| Decimal::try_from_i128_with_scale(m, 0).ok());
+            let r = m.and_then(|m| {
+                let q = (d / m).round_dp_with_strategy(0, HALF_AWAY);
+                q.checked_mul(m)
+            });
+            match r {
+                Some(d) => Value::Decimal(d),
+                None => Value::Error(Chars::from("round(digits, src): overflow")),
+            }
+        }
+        _ => Value::Error(Chars::from("round(digits, src): expected a number")),
+    }
+}
+
+pub struct RoundEv;
+
+impl CachedCurEval for RoundEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [Some(digits), Some(src)] => match digits.clone().cast_to::<i64>() {
+                Ok(digits) => Some(round_value(digits, src.clone())),
+                Err(_) => Some(Value::Error(Chars::from(
+                    "round(digits, src): expected digits to be an integer",
+                ))),
+            },
+            [_, _] => None,
+            _ => Some(Value::Error(Chars::from(
+                "round(digits, src): expected 2 arguments",
+            ))),
+        }
+    }
+
+    fn name() -> &'static str {
+        "round"
+    }
+}
+
+pub type Round = CachedCur<RoundEv>;
+
 pub struct AndEv;
 
 impl CachedCurEval for AndEv {
@@ -530,6 +754,65 @@ impl CachedCurEval for IndexEv {
 
 pub type Index = CachedCur<IndexEv>;
 
+pub struct ArrayLenEv;
+
+impl CachedCurEval for ArrayLenEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [Some(Value::Array(elts))] => Some(Value::I64(elts.len() as i64)),
+            [None] => None,
+            _ => Some(Value::Error(Chars::from("array_len(array): expected an array"))),
+        }
+    }
+
+    fn name() -> &'static str {
+        "array_len"
+    }
+}
+
+pub type ArrayLen = CachedCur<ArrayLenEv>;
+
+/// map_get(map, key[, default]), a map is an array of [key, value]
+/// pairs, as written by the `{key: value, ...}` literal syntax.
+pub struct MapGetEv;
+
+impl CachedCurEval for MapGetEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [None, _] | [_, None] | [None, _, _] | [_, None, _] => None,
+            [Some(Value::Array(pairs)), Some(key), default @ ..] if default.len() < 2 => {
+                for pair in pairs.iter() {
+                    match pair {
+                        Value::Array(kv) if kv.len() == 2 => {
+                            if &kv[0] == key {
+                                return Some(kv[1].clone());
+                            }
+                        }
+                        _ => {
+                            return Some(Value::Error(Chars::from(
+                                "map_get: expected an array of [key, value] pairs",
+                            )))
+                        }
+                    }
+                }
+                match default {
+                    [default] => default.clone(),
+                    _ => Some(Value::Error(Chars::from("map_get: key not found"))),
+                }
+            }
+            _ => Some(Value::Error(Chars::from(
+                "map_get(map, key[, default]): expected a map and a key",
+            ))),
+        }
+    }
+
+    fn name() -> &'static str {
+        "map_get"
+    }
+}
+
+pub type MapGet = CachedCur<MapGetEv>;
+
 // CR estokes: document
 pub struct EndsWithEv;
 
@@ -744,6 +1027,127 @@ impl CachedCurEval for BasenameEv {
 
 pub type Basename = CachedCur<BasenameEv>;
 
+pub struct PathDirnameEv;
+
+impl CachedCurEval for PathDirnameEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        DirnameEv::eval(from)
+    }
+
+    fn name() -> &'static str {
+        "path_dirname"
+    }
+}
+
+pub type PathDirname = CachedCur<PathDirnameEv>;
+
+pub struct PathBasenameEv;
+
+impl CachedCurEval for PathBasenameEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        BasenameEv::eval(from)
+    }
+
+    fn name() -> &'static str {
+        "path_basename"
+    }
+}
+
+pub type PathBasename = CachedCur<PathBasenameEv>;
+
+pub struct PathConcatEv;
+
+impl CachedCurEval for PathConcatEv {
+    fn name() -> &'static str {
+        "path_concat"
+    }
+
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &from.0[..] {
+            [] => Some(Value::Error(Chars::from(
+                "path_concat(base, p0, p1, ...): expected at least 1 argument",
+            ))),
+            parts => {
+                let mut res = Vec::with_capacity(parts.len());
+                for p in parts {
+                    match p {
+                        None => return None,
+                        Some(Value::String(c)) => res.push(c.clone()),
+                        Some(v) => match v.clone().cast_to::<Chars>().ok() {
+                            Some(c) => res.push(c),
+                            None => {
+                                return Some(Value::Error(Chars::from(
+                                    "path_concat: arguments must be strings",
+                                )))
+                            }
+                        },
+                    }
+                }
+                let path = Path::from(res[0].clone()).join(&res[1..]);
+                Some(Value::String(Chars::from(String::from(&*path))))
+            }
+        }
+    }
+}
+
+pub type PathConcat = CachedCur<PathConcatEv>;
+
+pub struct PathEscapeEv;
+
+impl CachedCurEval for PathEscapeEv {
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [Some(Value::String(s))] => match Path::escape(s) {
+                Cow::Borrowed(_) => Some(Value::String(s.clone())),
+                Cow::Owned(s) => Some(Value::String(Chars::from(s))),
+            },
+            [None] => None,
+            _ => Some(Value::Error(Chars::from("path_escape expected 1 argument"))),
+        }
+    }
+
+    fn name() -> &'static str {
+        "path_escape"
+    }
+}
+
+pub type PathEscape = CachedCur<PathEscapeEv>;
+
+/// Compare `v0` with `v1`. If only one side is a datetime or a
+/// duration then the other side is cast to the same type first, so
+/// e.g. a duration can be compared with a number of seconds.
+fn eval_cmp(op: &str, v0: &Value, v1: &Value) -> Value {
+    fn coerce(v: &Value, other: &Value) -> Option<Value> {
+        match (v, other) {
+            (Value::DateTime(_) | Value::Duration(_), _) => None,
+            (_, Value::DateTime(_)) => v.clone().cast(Typ::DateTime),
+            (_, Value::Duration(_)) => v.clone().cast(Typ::Duration),
+            (_, _) => None,
+        }
+    }
+    let (c0, c1) = (coerce(v0, v1), coerce(v1, v0));
+    let v0 = c0.as_ref().unwrap_or(v0);
+    let v1 = c1.as_ref().unwrap_or(v1);
+    let res = match op {
+        "eq" => v0 == v1,
+        "lt" => v0 < v1,
+        "gt" => v0 > v1,
+        "lte" => v0 <= v1,
+        "gte" => v0 >= v1,
+        op => {
+            return Value::Error(Chars::from(format!(
+                "invalid op {}, expected eq, lt, gt, lte, or gte",
+                op
+            )))
+        }
+    };
+    if res {
+        Value::True
+    } else {
+        Value::False
+    }
+}
+
 pub struct CmpEv;
 
 impl CachedCurEval for CmpEv {
@@ -757,47 +1161,7 @@ impl CachedCurEval for CmpEv {
                 None => None,
                 Some(Value::String(op)) => match (v0, v1) {
                     (_, None) | (None, _) => None,
-                    (Some(v0), Some(v1)) => Some(match &**op {
-                        "eq" => {
-                            if v0 == v1 {
-                                Value::True
-                            } else {
-                                Value::False
-                            }
-                        }
-                        "lt" => {
-                            if v0 < v1 {
-                                Value::True
-                            } else {
-                                Value::False
-                            }
-                        }
-                        "gt" => {
-                            if v0 > v1 {
-                                Value::True
-                            } else {
-                                Value::False
-                            }
-                        }
-                        "lte" => {
-                            if v0 <= v1 {
-                                Value::True
-                            } else {
-                                Value::False
-                            }
-                        }
-                        "gte" => {
-                            if v0 >= v1 {
-                                Value::True
-                            } else {
-                                Value::False
-                            }
-                        }
-                        op => Value::Error(Chars::from(format!(
-                            "invalid op {}, expected eq, lt, gt, lte, or gte",
-                            op
-                        ))),
-                    }),
+                    (Some(v0), Some(v1)) => Some(eval_cmp(op, v0, v1)),
                 },
                 Some(_) => Some(Value::Error(Chars::from(
                     "cmp(op, v0, v1): expected op to be a string",
@@ -924,8 +1288,16 @@ impl CachedCurEval for CastEv {
     }
 
     fn eval(from: &CachedVals) -> Option<Value> {
-        with_typ_prefix(from, "cast(typ, src)", |typ, v| {
-            v.as_ref().and_then(|v| v.clone().cast(typ))
+        with_typ_prefix(from, "cast(typ, src)", |typ, v| match v {
+            None => None,
+            Some(v) => match v.clone().cast(typ) {
+                Some(v) => Some(v),
+                None => Some(Value::Error(Chars::from(format!(
+                    "cast(typ, src): can't cast {} to {}",
+                    v,
+                    typ.name()
+                )))),
+            },
         })
     }
 }
@@ -934,60 +1306,110 @@ pub type Cast = CachedCur<CastEv>;
 
 pub struct IsaEv;
 
+fn isa(typ: Typ, v: &Value) -> bool {
+    match (typ, v) {
+        (Typ::U32, Value::U32(_))
+        | (Typ::V32, Value::V32(_))
+        | (Typ::I32, Value::I32(_))
+        | (Typ::Z32, Value::Z32(_))
+        | (Typ::U64, Value::U64(_))
+        | (Typ::V64, Value::V64(_))
+        | (Typ::I64, Value::I64(_))
+        | (Typ::Z64, Value::Z64(_))
+        | (Typ::F32, Value::F32(_))
+        | (Typ::F64, Value::F64(_))
+        | (Typ::Decimal, Value::Decimal(_))
+        | (Typ::Bool, Value::True)
+        | (Typ::Bool, Value::False)
+        | (Typ::String, Value::String(_))
+        | (Typ::Bytes, Value::Bytes(_))
+        | (Typ::Result, Value::Ok)
+        | (Typ::Result, Value::Error(_))
+        | (Typ::Array, Value::Array(_))
+        | (Typ::DateTime, Value::DateTime(_))
+        | (Typ::Duration, Value::Duration(_))
+        | (Typ::Null, Value::Null) => true,
+        (_, _) => false,
+    }
+}
+
 impl CachedCurEval for IsaEv {
     fn name() -> &'static str {
         "isa"
     }
 
     fn eval(from: &CachedVals) -> Option<Value> {
-        with_typ_prefix(from, "isa(typ, src)", |typ, v| match (typ, v) {
-            (_, None) => None,
-            (Typ::U32, Some(Value::U32(_))) => Some(Value::True),
-            (Typ::U32, Some(_)) => Some(Value::False),
-            (Typ::V32, Some(Value::V32(_))) => Some(Value::True),
-            (Typ::V32, Some(_)) => Some(Value::False),
-            (Typ::I32, Some(Value::I32(_))) => Some(Value::True),
-            (Typ::I32, Some(_)) => Some(Value::False),
-            (Typ::Z32, Some(Value::Z32(_))) => Some(Value::True),
-            (Typ::Z32, Some(_)) => Some(Value::False),
-            (Typ::U64, Some(Value::U64(_))) => Some(Value::True),
-            (Typ::U64, Some(_)) => Some(Value::False),
-            (Typ::V64, Some(Value::V64(_))) => Some(Value::True),
-            (Typ::V64, Some(_)) => Some(Value::False),
-            (Typ::I64, Some(Value::I64(_))) => Some(Value::True),
-            (Typ::I64, Some(_)) => Some(Value::False),
-            (Typ::Z64, Some(Value::Z64(_))) => Some(Value::True),
-            (Typ::Z64, Some(_)) => Some(Value::False),
-            (Typ::F32, Some(Value::F32(_))) => Some(Value::True),
-            (Typ::F32, Some(_)) => Some(Value::False),
-            (Typ::F64, Some(Value::F64(_))) => Some(Value::True),
-            (Typ::F64, Some(_)) => Some(Value::False),
-            (Typ::Decimal, Some(Value::Decimal(_))) => Some(Value::True),
-            (Typ::Decimal, Some(_)) => Some(Value::False),
-            (Typ::Bool, Some(Value::True)) => Some(Value::True),
-            (Typ::Bool, Some(Value::False)) => Some(Value::True),
-            (Typ::Bool, Some(_)) => Some(Value::False),
-            (Typ::String, Some(Value::String(_))) => Some(Value::True),
-            (Typ::String, Some(_)) => Some(Value::False),
-            (Typ::Bytes, Some(Value::Bytes(_))) => Some(Value::True),
-            (Typ::Bytes, Some(_)) => Some(Value::False),
-            (Typ::Result, Some(Value::Ok)) => Some(Value::True),
-            (Typ::Result, Some(Value::Error(_))) => Some(Value::True),
-            (Typ::Result, Some(_)) => Some(Value::False),
-            (Typ::Array, Some(Value::Array(_))) => Some(Value::True),
-            (Typ::Array, Some(_)) => Some(Value::False),
-            (Typ::DateTime, Some(Value::DateTime(_))) => Some(Value::True),
-            (Typ::DateTime, Some(_)) => Some(Value::False),
-            (Typ::Duration, Some(Value::Duration(_))) => Some(Value::True),
-            (Typ::Duration, Some(_)) => Some(Value::False),
-            (Typ::Null, Some(Value::Null)) => Some(Value::True),
-            (Typ::Null, Some(_)) => Some(Value::False),
+        with_typ_prefix(from, "isa(typ, src)", |typ, v| {
+            v.as_ref().map(|v| if isa(typ, v) { Value::True } else { Value::False })
         })
     }
 }
 
 pub type Isa = CachedCur<IsaEv>;
 
+pub struct MatchEv;
+
+/// Match `v` against a pattern, which is either
+/// - ["type", <typ>], matching any value of type typ
+/// - ["range", <lo>, <hi>], matching lo <= v <= hi, null means unbounded
+/// - any other value, matching only an equal value
+fn match_pattern(pat: &Value, v: &Value) -> Result<bool, Chars> {
+    match pat {
+        Value::Array(elts) => match &**elts {
+            [Value::String(k), Value::String(typ)] if &**k == "type" => {
+                match typ.parse::<Typ>() {
+                    Ok(typ) => Ok(isa(typ, v)),
+                    Err(e) => Err(Chars::from(format!("invalid type {}, {}", typ, e))),
+                }
+            }
+            [Value::String(k), lo, hi] if &**k == "range" => {
+                let above = match lo {
+                    Value::Null => true,
+                    lo => v >= lo,
+                };
+                let below = match hi {
+                    Value::Null => true,
+                    hi => v <= hi,
+                };
+                Ok(above && below)
+            }
+            _ => Ok(pat == v),
+        },
+        pat => Ok(pat == v),
+    }
+}
+
+impl CachedCurEval for MatchEv {
+    fn name() -> &'static str {
+        "match"
+    }
+
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [] | [_] => Some(Value::Error(Chars::from(
+                "match(src, pat0, out0, ..., [default]): expected at least 2 arguments",
+            ))),
+            [src, cases @ ..] => {
+                let src = src.as_ref()?;
+                for case in cases.chunks(2) {
+                    match case {
+                        [default] => return default.clone(),
+                        [pat, out] => match match_pattern(pat.as_ref()?, src) {
+                            Err(e) => return Some(Value::Error(e)),
+                            Ok(true) => return out.clone(),
+                            Ok(false) => (),
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+pub type Match = CachedCur<MatchEv>;
+
 pub struct StringJoinEv;
 
 impl CachedCurEval for StringJoinEv {
@@ -1160,28 +1582,191 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Eval<C, E> {
     }
 }
 
-pub struct Count {
-    from: CachedVals,
-    count: u64,
+/// array_map(array, fname) applies the function named by `fname`, a
+/// builtin or a function defined with `def`, to each element of
+/// `array`. The result is updated whenever any of the applications
+/// update, and the applications are rebuilt whenever the array
+/// changes.
+pub struct ArrayMap<C: Ctx, E> {
+    cached: CachedVals,
+    current: Result<Vec<Node<C, E>>, Value>,
+    scope: Path,
 }
 
-impl<C: Ctx, E: Clone> Register<C, E> for Count {
-    fn register(ctx: &mut ExecCtx<C, E>) {
-        let f: InitFn<C, E> = Arc::new(|ctx, from, _, _| {
-            Box::new(Count { from: CachedVals::new(from, ctx), count: 0 })
-        });
-        ctx.functions.insert("count".into(), f);
-        ctx.user.register_fn("count".into(), Path::root());
+impl<C: Ctx, E: Clone> ArrayMap<C, E> {
+    fn compile(&mut self, ctx: &mut ExecCtx<C, E>) {
+        self.current = match &*self.cached.0 {
+            [None, _] | [_, None] => Err(Value::Null),
+            [Some(Value::Array(elts)), Some(Value::String(f))] => Ok(elts
+                .iter()
+                .map(|v| {
+                    let spec = ExprKind::Apply {
+                        function: f.to_string(),
+                        args: vec![ExprKind::Constant(v.clone()).to_expr()],
+                    }
+                    .to_expr();
+                    Node::compile(ctx, self.scope.clone(), spec)
+                })
+                .collect()),
+            _ => Err(Value::Error(Chars::from(
+                "array_map(array, fname): expected an array and a function name",
+            ))),
+        }
+    }
+
+    fn collect(ctx: &mut ExecCtx<C, E>, elts: &[Node<C, E>]) -> Option<Value> {
+        let elts = elts.iter().map(|n| n.current(ctx)).collect::<Option<Vec<_>>>()?;
+        Some(Value::Array(Arc::from(elts)))
     }
 }
 
-impl<C: Ctx, E: Clone> Apply<C, E> for Count {
-    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
-        match &*self.from.0 {
-            [] => Some(Value::Error(Chars::from("count(s): requires 1 argument"))),
-            [_] => Some(Value::U64(self.count)),
-            _ => Some(Value::Error(Chars::from("count(s): requires 1 argument"))),
-        }
+impl<C: Ctx, E: Clone> Register<C, E> for ArrayMap<C, E> {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|ctx, from, scope, _| {
+            let mut t = ArrayMap {
+                cached: CachedVals::new(from, ctx),
+                current: Err(Value::Null),
+                scope,
+            };
+            t.compile(ctx);
+            Box::new(t)
+        });
+        ctx.functions.insert("array_map".into(), f);
+        ctx.user.register_fn("array_map".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for ArrayMap<C, E> {
+    fn current(&self, ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        match &self.current {
+            Ok(elts) => Self::collect(ctx, elts),
+            Err(Value::Null) => None,
+            Err(v) => Some(v.clone()),
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        if self.cached.update(ctx, from, event) {
+            self.compile(ctx);
+            return self.current(ctx);
+        }
+        match &mut self.current {
+            Ok(elts) => {
+                let mut up = false;
+                for n in elts.iter_mut() {
+                    up |= n.update(ctx, event).is_some();
+                }
+                if up {
+                    Self::collect(ctx, elts)
+                } else {
+                    None
+                }
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// A call to a function defined with `ExecCtx::define_fn`
+pub struct UserFn<C: Ctx, E> {
+    def: Arc<FnDef>,
+    scope: Path,
+    body: Node<C, E>,
+}
+
+impl<C: Ctx, E: Clone> UserFn<C, E> {
+    pub(crate) fn init(def: FnDef) -> InitFn<C, E> {
+        let def = Arc::new(def);
+        Arc::new(move |ctx, from, scope, top_id| {
+            // each call gets it's own scope for it's arguments
+            let scope = scope.append(&format!("fn{:?}", ExprId::new()));
+            let body = if from.len() != def.args.len() {
+                let e = format!(
+                    "{}: expected {} arguments, got {}",
+                    def.name,
+                    def.args.len(),
+                    from.len()
+                );
+                Node::Error(def.body.clone(), Value::Error(Chars::from(e)))
+            } else if ctx.fn_depth >= ExecCtx::<C, E>::MAX_FN_DEPTH {
+                let e = format!("{}: maximum call depth exceeded", def.name);
+                Node::Error(def.body.clone(), Value::Error(Chars::from(e)))
+            } else {
+                for (name, arg) in def.args.iter().zip(from.iter()) {
+                    if let Some(v) = arg.current(ctx) {
+                        UserFn::bind(ctx, &scope, name, v)
+                    }
+                }
+                ctx.fn_depth += 1;
+                let body =
+                    Node::compile_int(ctx, def.body.clone(), scope.clone(), top_id);
+                ctx.fn_depth -= 1;
+                body
+            };
+            Box::new(UserFn { def: Arc::clone(&def), scope, body })
+        })
+    }
+
+    fn bind(ctx: &mut ExecCtx<C, E>, scope: &Path, name: &str, v: Value) {
+        let name = Chars::from(String::from(name));
+        ctx.user.set_var(&mut ctx.variables, true, scope.clone(), name, v)
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for UserFn<C, E> {
+    fn current(&self, ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        self.body.current(ctx)
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        let mut up = false;
+        for (i, arg) in from.iter_mut().enumerate() {
+            if let Some(v) = arg.update(ctx, event) {
+                up = true;
+                if let Some(name) = self.def.args.get(i) {
+                    UserFn::bind(ctx, &self.scope, name, v)
+                }
+            }
+        }
+        match &mut self.body {
+            Node::Error(_, e) if up => Some(e.clone()),
+            body => body.update(ctx, event),
+        }
+    }
+}
+
+pub struct Count {
+    from: CachedVals,
+    count: u64,
+}
+
+impl<C: Ctx, E: Clone> Register<C, E> for Count {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|ctx, from, _, _| {
+            Box::new(Count { from: CachedVals::new(from, ctx), count: 0 })
+        });
+        ctx.functions.insert("count".into(), f);
+        ctx.user.register_fn("count".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for Count {
+    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        match &*self.from.0 {
+            [] => Some(Value::Error(Chars::from("count(s): requires 1 argument"))),
+            [_] => Some(Value::U64(self.count)),
+            _ => Some(Value::Error(Chars::from("count(s): requires 1 argument"))),
+        }
     }
 
     fn update(
@@ -1255,16 +1840,172 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Sample {
     }
 }
 
+/// sample_on(trigger, source): produce the latest value of source
+/// each time trigger updates. Unlike sample, the value trigger has
+/// when the expression is compiled is not a trigger, and nothing is
+/// produced until source has a value. When trigger and source update
+/// on the same event the new value of source is sampled.
+pub struct SampleOn {
+    source: Option<Value>,
+    current: Option<Value>,
+    invalid: bool,
+}
+
+impl<C: Ctx, E: Clone> Register<C, E> for SampleOn {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|ctx, from, _, _| {
+            let mut t = SampleOn { source: None, current: None, invalid: false };
+            match from {
+                [_, source] => t.source = source.current(ctx),
+                _ => t.invalid = true,
+            }
+            Box::new(t)
+        });
+        ctx.functions.insert("sample_on".into(), f);
+        ctx.user.register_fn("sample_on".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for SampleOn {
+    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        if self.invalid {
+            SampleOn::usage()
+        } else {
+            self.current.clone()
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        match from {
+            [trigger, source] => {
+                self.invalid = false;
+                if let Some(v) = source.update(ctx, event) {
+                    self.source = Some(v);
+                }
+                trigger.update(ctx, event).and_then(|_| {
+                    self.current = self.source.clone();
+                    self.current.clone()
+                })
+            }
+            exprs => {
+                self.invalid = true;
+                let mut up = false;
+                for e in exprs {
+                    up = e.update(ctx, event).is_some() || up;
+                }
+                if up {
+                    SampleOn::usage()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl SampleOn {
+    fn usage() -> Option<Value> {
+        Some(Value::Error(Chars::from(
+            "sample_on(trigger, source): expected 2 arguments",
+        )))
+    }
+}
+
+/// guard(condition, action): pass events to action only while
+/// condition is true. Unlike filter the action is not evaluated at
+/// all while the guard is closed, so any store or set inside it
+/// can't happen, and the condition becoming true doesn't trigger
+/// it. Anything other than true, including an error, closes the
+/// guard.
+pub struct Guard {
+    open: bool,
+    invalid: bool,
+}
+
+impl<C: Ctx, E: Clone> Register<C, E> for Guard {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|ctx, from, _, _| match from {
+            [cond, _] => {
+                let open = cond.current(ctx) == Some(Value::True);
+                Box::new(Guard { open, invalid: false })
+            }
+            _ => Box::new(Guard { open: false, invalid: true }),
+        });
+        ctx.functions.insert("guard".into(), f);
+        ctx.user.register_fn("guard".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for Guard {
+    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        self.usage()
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        match from {
+            [cond, action] => {
+                if let Some(c) = cond.update(ctx, event) {
+                    self.open = c == Value::True;
+                }
+                if self.open {
+                    action.update(ctx, event)
+                } else {
+                    None
+                }
+            }
+            exprs => {
+                let mut up = false;
+                for e in exprs {
+                    up |= e.update(ctx, event).is_some();
+                }
+                if up {
+                    self.usage()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl Guard {
+    fn usage(&self) -> Option<Value> {
+        if self.invalid {
+            let e = Chars::from("guard(condition, action): expected 2 arguments");
+            Some(Value::Error(e))
+        } else {
+            None
+        }
+    }
+}
+
 pub struct Mean {
     from: CachedVals,
     total: f64,
+    // the exact total, kept as long as every sample is a Decimal
+    exact: Option<Value>,
     samples: usize,
 }
 
 impl<C: Ctx, E: Clone> Register<C, E> for Mean {
     fn register(ctx: &mut ExecCtx<C, E>) {
         let f: InitFn<C, E> = Arc::new(|ctx, from, _, _| {
-            Box::new(Mean { from: CachedVals::new(from, ctx), total: 0., samples: 0 })
+            Box::new(Mean {
+                from: CachedVals::new(from, ctx),
+                total: 0.,
+                exact: None,
+                samples: 0,
+            })
         });
         ctx.functions.insert("mean".into(), f);
         ctx.user.register_fn("mean".into(), Path::root());
@@ -1277,7 +2018,10 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Mean {
             [] => Some(Value::Error(Chars::from("mean(s): requires 1 argument"))),
             [_] => {
                 if self.samples > 0 {
-                    Some(Value::F64(self.total / (self.samples as f64)))
+                    match &self.exact {
+                        Some(t) => Some(t.clone() / Value::U64(self.samples as u64)),
+                        None => Some(Value::F64(self.total / (self.samples as f64))),
+                    }
                 } else {
                     None
                 }
@@ -1295,8 +2039,13 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Mean {
         if self.from.update(ctx, from, event) {
             for v in self.from.flat_iter() {
                 if let Some(v) = v {
-                    if let Ok(v) = v.clone().cast_to::<f64>() {
-                        self.total += v;
+                    if let Ok(f) = v.clone().cast_to::<f64>() {
+                        self.exact = match (self.exact.take(), v) {
+                            (None, v @ Value::Decimal(_)) if self.samples == 0 => Some(v),
+                            (Some(t), v @ Value::Decimal(_)) => Some(t + v),
+                            (_, _) => None,
+                        };
+                        self.total += f;
                         self.samples += 1;
                     }
                 }
@@ -1308,24 +2057,30 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Mean {
     }
 }
 
-pub(crate) struct Uniq(Option<Value>);
+/// changed(source): suppress updates of source that are equal to
+/// the previous value of source. The value source has when the
+/// expression is compiled counts as the previous value. `uniq` is
+/// an alias.
+pub(crate) struct Changed(Option<Value>);
 
-impl<C: Ctx, E: Clone> Register<C, E> for Uniq {
+impl<C: Ctx, E: Clone> Register<C, E> for Changed {
     fn register(ctx: &mut ExecCtx<C, E>) {
         let f: InitFn<C, E> = Arc::new(|ctx, from, _, _| {
-            let mut t = Uniq(None);
+            let mut t = Changed(None);
             match from {
                 [e] => t.0 = e.current(ctx),
-                _ => t.0 = Uniq::usage(),
+                _ => t.0 = Changed::usage(),
             }
             Box::new(t)
         });
-        ctx.functions.insert("uniq".into(), f);
-        ctx.user.register_fn("uniq".into(), Path::root());
+        for name in ["changed", "uniq"] {
+            ctx.functions.insert(name.into(), f.clone());
+            ctx.user.register_fn(name.into(), Path::root());
+        }
     }
 }
 
-impl<C: Ctx, E: Clone> Apply<C, E> for Uniq {
+impl<C: Ctx, E: Clone> Apply<C, E> for Changed {
     fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
         self.0.as_ref().cloned()
     }
@@ -1350,7 +2105,7 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Uniq {
                 for e in exprs {
                     up = e.update(ctx, event).is_some() || up;
                 }
-                self.0 = Uniq::usage();
+                self.0 = Changed::usage();
                 if up {
                     Apply::<C, E>::current(self, ctx)
                 } else {
@@ -1361,9 +2116,9 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Uniq {
     }
 }
 
-impl Uniq {
+impl Changed {
     fn usage() -> Option<Value> {
-        Some(Value::Error(Chars::from("uniq(e): expected 1 argument")))
+        Some(Value::Error(Chars::from("changed(e): expected 1 argument")))
     }
 }
 
@@ -1541,22 +2296,39 @@ pub struct Set {
     queued: Vec<Value>,
     local: bool,
     scope: Path,
+    target: Option<Path>,
     name: Option<Chars>,
     invalid: bool,
+    invalid_target: bool,
 }
 
 impl<C: Ctx, E: Clone> Register<C, E> for Set {
     fn register(ctx: &mut ExecCtx<C, E>) {
         let f = |local| -> InitFn<C, E> {
             Arc::new(move |ctx, from, scope, _| {
-                let mut t =
-                    Set { queued: Vec::new(), local, scope, name: None, invalid: false };
+                let mut t = Set {
+                    queued: Vec::new(),
+                    local,
+                    target: Some(scope.clone()),
+                    scope,
+                    name: None,
+                    invalid: false,
+                    invalid_target: false,
+                };
                 match from {
                     [name, value] => {
                         let name = name.current(ctx);
                         let value = value.current(ctx);
                         t.set(ctx, name, value)
                     }
+                    [target, name, value] if local => {
+                        t.target = None;
+                        let target = target.current(ctx);
+                        t.set_target(target);
+                        let name = name.current(ctx);
+                        let value = value.current(ctx);
+                        t.set(ctx, name, value)
+                    }
                     _ => t.invalid = true,
                 }
                 Box::new(t)
@@ -1575,6 +2347,10 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Set {
             Some(Value::Error(Chars::from(
                 "set/let(name: string [a-z][a-z0-9_]+, value): expected 2 arguments",
             )))
+        } else if self.invalid_target {
+            Some(Value::Error(Chars::from(
+                "let(scope: [\"local\", \"container\", \"global\"], name, value)",
+            )))
         } else {
             None
         }
@@ -1603,6 +2379,24 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Set {
                     None
                 }
             }
+            [target, name, val] if self.local => {
+                let target = target.update(ctx, event);
+                let name = name.update(ctx, event);
+                let value = val.update(ctx, event);
+                let moved = target.is_some() && self.set_target(target);
+                let value = if moved || (name.is_some() && !self.same_name(&name)) {
+                    value.or_else(|| val.current(ctx))
+                } else {
+                    value
+                };
+                let up = value.is_some();
+                self.set(ctx, name, value);
+                if up {
+                    Apply::<C, E>::current(self, ctx)
+                } else {
+                    None
+                }
+            }
             exprs => {
                 let mut up = false;
                 for expr in exprs {
@@ -1624,6 +2418,44 @@ impl Set {
         self.queued.push(v)
     }
 
+    /// Resolve the scope argument of a three argument let. Returns
+    /// true if the target scope changed.
+    ///
+    /// - "local" is the scope the let appears in, the same as the two
+    ///   argument form
+    /// - "container" is the scope of the nearest enclosing container,
+    ///   e.g. the notebook page or grid row, skipping any do blocks
+    /// - "global" is the root scope, visible everywhere
+    fn set_target(&mut self, target: Option<Value>) -> bool {
+        let target = match target {
+            None => return false,
+            Some(Value::String(s)) => match &*s {
+                "local" => self.scope.clone(),
+                "container" => {
+                    let is_do = |s: &str| Path::basename(s).map(|b| b.starts_with("do"));
+                    let mut scope: &str = &self.scope;
+                    while is_do(scope).unwrap_or(false) {
+                        scope = Path::dirname(scope).unwrap_or("/");
+                    }
+                    Path::from(String::from(scope))
+                }
+                "global" => Path::root(),
+                _ => {
+                    self.invalid_target = true;
+                    return false;
+                }
+            },
+            Some(_) => {
+                self.invalid_target = true;
+                return false;
+            }
+        };
+        self.invalid_target = false;
+        let moved = self.target.as_ref() != Some(&target);
+        self.target = Some(target);
+        moved
+    }
+
     fn set<C: Ctx, E>(
         &mut self,
         ctx: &mut ExecCtx<C, E>,
@@ -1631,28 +2463,21 @@ impl Set {
         value: Option<Value>,
     ) {
         if let Some(name) = varname(&mut self.invalid, name) {
+            self.name = Some(name);
+        }
+        if let Some(value) = value {
+            self.queue_set(value)
+        }
+        if let (Some(name), Some(target)) = (self.name.as_ref(), self.target.as_ref()) {
             for v in self.queued.drain(..) {
                 ctx.user.set_var(
                     &mut ctx.variables,
                     self.local,
-                    self.scope.clone(),
+                    target.clone(),
                     name.clone(),
                     v,
                 )
             }
-            self.name = Some(name);
-        }
-        if let Some(value) = value {
-            match self.name.as_ref() {
-                None => self.queue_set(value),
-                Some(name) => ctx.user.set_var(
-                    &mut ctx.variables,
-                    self.local,
-                    self.scope.clone(),
-                    name.clone(),
-                    value,
-                ),
-            }
         }
     }
 
@@ -1698,7 +2523,10 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Load {
                 subscriber::Event::Unsubscribed => {
                     Some(Value::Error(Chars::from("#LOST")))
                 }
-                subscriber::Event::Update(v) => Some(v),
+                subscriber::Event::Update(v) | subscriber::Event::Unpublished(v) => {
+                    Some(v)
+                }
+                subscriber::Event::Stale => None,
             })
         }
     }
@@ -1775,6 +2603,125 @@ impl Load {
     }
 }
 
+pub(crate) struct IsSubscribed {
+    path: Option<Path>,
+    cur: Option<Dval>,
+    top_id: ExprId,
+    invalid: bool,
+}
+
+impl<C: Ctx, E: Clone> Register<C, E> for IsSubscribed {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|ctx, from, _, top_id| {
+            let mut t = IsSubscribed { path: None, cur: None, invalid: false, top_id };
+            match from {
+                [path] => {
+                    let path = path.current(ctx);
+                    t.subscribe(ctx, path)
+                }
+                _ => t.invalid = true,
+            }
+            Box::new(t)
+        });
+        ctx.functions.insert("is_subscribed".into(), f);
+        ctx.user.register_fn("is_subscribed".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for IsSubscribed {
+    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        if self.invalid {
+            IsSubscribed::err()
+        } else {
+            self.cur.as_ref().map(IsSubscribed::eval)
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        match from {
+            [name] => {
+                let up = match name.update(ctx, event) {
+                    None => false,
+                    Some(target) => {
+                        self.subscribe(ctx, Some(target));
+                        true
+                    }
+                };
+                if self.invalid {
+                    if up {
+                        IsSubscribed::err()
+                    } else {
+                        None
+                    }
+                } else if up {
+                    self.cur.as_ref().map(IsSubscribed::eval)
+                } else {
+                    self.cur.as_ref().and_then(|dv| match event {
+                        Event::Netidx(id, _) if dv.id() == *id => {
+                            Some(IsSubscribed::eval(dv))
+                        }
+                        Event::Variable(_, _, _)
+                        | Event::Netidx(_, _)
+                        | Event::Rpc(_, _)
+                        | Event::Timer(_)
+                        | Event::User(_) => None,
+                    })
+                }
+            }
+            exprs => {
+                let mut up = false;
+                for e in exprs {
+                    up = e.update(ctx, event).is_some() || up;
+                }
+                self.invalid = true;
+                if up {
+                    IsSubscribed::err()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl IsSubscribed {
+    fn eval(dv: &Dval) -> Value {
+        match dv.last() {
+            subscriber::Event::Update(_) | subscriber::Event::Stale => Value::True,
+            subscriber::Event::Unsubscribed | subscriber::Event::Unpublished(_) => {
+                Value::False
+            }
+        }
+    }
+
+    fn subscribe<C: Ctx, E>(&mut self, ctx: &mut ExecCtx<C, E>, name: Option<Value>) {
+        if let Some(path) = pathname(&mut self.invalid, name) {
+            if Some(&path) != self.path.as_ref() {
+                if let (Some(path), Some(dv)) = (self.path.take(), self.cur.take()) {
+                    ctx.user.unsubscribe(path, dv, self.top_id);
+                }
+                self.path = Some(path.clone());
+                self.cur = Some(ctx.user.durable_subscribe(
+                    UpdatesFlags::BEGIN_WITH_LAST,
+                    path,
+                    self.top_id,
+                ));
+            }
+        }
+    }
+
+    fn err() -> Option<Value> {
+        Some(Value::Error(Chars::from(
+            "is_subscribed(expr: path) expected 1 absolute path as argument",
+        )))
+    }
+}
+
 struct BoundVar {
     scope: Path,
     value: Value,
@@ -2171,7 +3118,6 @@ impl<C: Ctx, E: Clone> Apply<C, E> for AfterIdle {
 
 impl AfterIdle {
     fn maybe_set_timer<C: Ctx, E>(&mut self, ctx: &mut ExecCtx<C, E>) {
-        use std::time::Duration;
         if !self.invalid && !self.timer_set {
             match (&self.timeout, &self.cur) {
                 (Some(timeout), Some(_)) => match timeout.clone().cast_to::<f64>() {
@@ -2301,7 +3247,6 @@ impl<C: Ctx, E: Clone> Apply<C, E> for Timer {
 
 impl Timer {
     fn maybe_set_timer<C: Ctx, E>(&mut self, ctx: &mut ExecCtx<C, E>) {
-        use std::time::Duration;
         if !self.invalid && !self.timer_set {
             match (&self.timeout, &self.repeat) {
                 (None, _) | (_, None) => (),
@@ -2351,3 +3296,660 @@ impl Timer {
         }
     }
 }
+
+/// `now()` is the time it was evaluated, `now(trigger)` also updates
+/// to the current time every time `trigger` updates.
+pub struct Now;
+
+impl<C: Ctx, E: Clone> Register<C, E> for Now {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|_, _, _, _| Box::new(Now));
+        ctx.functions.insert("now".into(), f);
+        ctx.user.register_fn("now".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for Now {
+    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        Some(Value::DateTime(Utc::now()))
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        let mut up = false;
+        for n in from {
+            up |= n.update(ctx, event).is_some();
+        }
+        if up {
+            Some(Value::DateTime(Utc::now()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Format `d` with at most two units, e.g. 1h 5m, or 450ms
+fn humanize(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs == 0 {
+        format!("{}ms", d.subsec_millis())
+    } else {
+        let units = [
+            (secs / 86400, "d"),
+            (secs / 3600 % 24, "h"),
+            (secs / 60 % 60, "m"),
+            (secs % 60, "s"),
+        ];
+        units
+            .iter()
+            .skip_while(|(n, _)| *n == 0)
+            .take(2)
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, unit)| format!("{}{}", n, unit))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+pub struct TimeFormatEv;
+
+impl CachedCurEval for TimeFormatEv {
+    fn name() -> &'static str {
+        "time_format"
+    }
+
+    fn eval(from: &CachedVals) -> Option<Value> {
+        use std::fmt::Write;
+        match &*from.0 {
+            [fmt, src] => match (fmt, src) {
+                (None, _) | (_, None) => None,
+                (Some(Value::String(fmt)), Some(Value::Duration(d)))
+                    if &**fmt == "human" =>
+                {
+                    Some(Value::String(Chars::from(humanize(*d))))
+                }
+                (Some(Value::String(fmt)), Some(src)) => {
+                    match src.clone().cast(Typ::DateTime) {
+                        Some(Value::DateTime(ts)) if &**fmt == "human" => {
+                            let now = Utc::now();
+                            let s = match (now - ts).to_std() {
+                                Ok(d) => format!("{} ago", humanize(d)),
+                                Err(_) => match (ts - now).to_std() {
+                                    Ok(d) => format!("in {}", humanize(d)),
+                                    Err(_) => format!("{}", ts),
+                                },
+                            };
+                            Some(Value::String(Chars::from(s)))
+                        }
+                        Some(Value::DateTime(ts)) => {
+                            let mut buf = String::new();
+                            match write!(buf, "{}", ts.format(fmt)) {
+                                Ok(()) => Some(Value::String(Chars::from(buf))),
+                                Err(_) => Some(Value::Error(Chars::from(format!(
+                                    "time_format: invalid format string {}",
+                                    fmt
+                                )))),
+                            }
+                        }
+                        _ => Some(Value::Error(Chars::from(
+                            "time_format(fmt, src): expected a datetime or a duration",
+                        ))),
+                    }
+                }
+                (Some(_), Some(_)) => Some(Value::Error(Chars::from(
+                    "time_format(fmt, src): expected fmt to be a string",
+                ))),
+            },
+            _ => Some(Value::Error(Chars::from(
+                "time_format(fmt, src): expected 2 arguments",
+            ))),
+        }
+    }
+}
+
+pub type TimeFormat = CachedCur<TimeFormatEv>;
+
+pub struct TimeDiffEv;
+
+impl CachedCurEval for TimeDiffEv {
+    fn name() -> &'static str {
+        "time_diff"
+    }
+
+    fn eval(from: &CachedVals) -> Option<Value> {
+        match &*from.0 {
+            [a, b] => match (a, b) {
+                (None, _) | (_, None) => None,
+                (Some(a), Some(b)) => {
+                    match (a.clone().cast(Typ::DateTime), b.clone().cast(Typ::DateTime)) {
+                        (Some(Value::DateTime(a)), Some(Value::DateTime(b))) => Some(
+                            Value::Duration((a - b).to_std().unwrap_or(Duration::ZERO)),
+                        ),
+                        (_, _) => Some(Value::Error(Chars::from(
+                            "time_diff(a, b): expected a and b to be datetimes",
+                        ))),
+                    }
+                }
+            },
+            _ => Some(Value::Error(Chars::from("time_diff(a, b): expected 2 arguments"))),
+        }
+    }
+}
+
+pub type TimeDiff = CachedCur<TimeDiffEv>;
+
+pub(crate) struct After {
+    delay: Option<Value>,
+    queue: VecDeque<Value>,
+    id: TimerId,
+    eid: ExprId,
+    invalid: bool,
+}
+
+impl<C: Ctx, E: Clone> Register<C, E> for After {
+    fn register(ctx: &mut ExecCtx<C, E>) {
+        let f: InitFn<C, E> = Arc::new(|ctx, from, _, eid| match from {
+            [delay, src] => {
+                let mut t = After {
+                    delay: delay.current(ctx),
+                    queue: VecDeque::new(),
+                    id: TimerId::new(),
+                    eid,
+                    invalid: false,
+                };
+                if let Some(v) = src.current(ctx) {
+                    t.push(ctx, v)
+                }
+                Box::new(t)
+            }
+            _ => Box::new(After {
+                delay: None,
+                queue: VecDeque::new(),
+                id: TimerId::new(),
+                eid,
+                invalid: true,
+            }),
+        });
+        ctx.functions.insert("after".into(), f);
+        ctx.user.register_fn("after".into(), Path::root());
+    }
+}
+
+impl<C: Ctx, E: Clone> Apply<C, E> for After {
+    fn current(&self, _ctx: &mut ExecCtx<C, E>) -> Option<Value> {
+        self.usage()
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut ExecCtx<C, E>,
+        from: &mut [Node<C, E>],
+        event: &Event<E>,
+    ) -> Option<Value> {
+        match from {
+            [delay, src] => {
+                if let Some(delay) = delay.update(ctx, event) {
+                    self.invalid = false;
+                    self.delay = Some(delay);
+                }
+                if let Some(v) = src.update(ctx, event) {
+                    self.push(ctx, v);
+                }
+                match event {
+                    Event::Variable(_, _, _)
+                    | Event::Netidx(_, _)
+                    | Event::Rpc(_, _)
+                    | Event::User(_) => self.usage(),
+                    Event::Timer(id) => {
+                        if id != &self.id {
+                            self.usage()
+                        } else {
+                            self.queue.pop_front()
+                        }
+                    }
+                }
+            }
+            exprs => {
+                let mut up = false;
+                self.invalid = true;
+                for expr in exprs {
+                    up |= expr.update(ctx, event).is_some();
+                }
+                if up {
+                    self.usage()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl After {
+    /// Queue `v` to be delivered once the delay has passed. Values
+    /// are delivered in the order they arrived.
+    fn push<C: Ctx, E>(&mut self, ctx: &mut ExecCtx<C, E>, v: Value) {
+        if !self.invalid {
+            match self.delay.clone().map(|d| d.cast(Typ::Duration)) {
+                None => (),
+                Some(Some(Value::Duration(d))) => {
+                    self.queue.push_back(v);
+                    ctx.user.set_timer(self.id, d, self.eid);
+                }
+                Some(_) => {
+                    self.invalid = true;
+                }
+            }
+        }
+    }
+
+    fn usage(&self) -> Option<Value> {
+        if self.invalid {
+            Some(Value::Error(Chars::from(
+                "after(delay: duration, v: any): expected a duration and a value",
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse_expr, vm::RpcCallId};
+    use chrono::DateTime;
+    use fxhash::FxHashMap;
+
+    struct NoCtx;
+
+    impl Ctx for NoCtx {
+        fn clear(&mut self) {}
+
+        fn durable_subscribe(&mut self, _: UpdatesFlags, _: Path, _: ExprId) -> Dval {
+            unreachable!()
+        }
+
+        fn unsubscribe(&mut self, _: Path, _: Dval, _: ExprId) {}
+        fn ref_var(&mut self, _: Chars, _: Path, _: ExprId) {}
+        fn unref_var(&mut self, _: Chars, _: Path, _: ExprId) {}
+        fn register_fn(&mut self, _: Chars, _: Path) {}
+
+        fn set_var(
+            &mut self,
+            _: &mut FxHashMap<Path, FxHashMap<Chars, Value>>,
+            _: bool,
+            _: Path,
+            _: Chars,
+            _: Value,
+        ) {
+        }
+
+        fn call_rpc(&mut self, _: Path, _: Vec<(Chars, Value)>, _: ExprId, _: RpcCallId) {
+        }
+        fn set_timer(&mut self, _: TimerId, _: Duration, _: ExprId) {}
+    }
+
+    /// compile `expr` and feed it the variable updates in `events`,
+    /// returning the initial value and the result of each update.
+    fn run(
+        expr: &str,
+        events: &[(&'static str, Value)],
+    ) -> (Option<Value>, Vec<Option<Value>>) {
+        let mut ctx: ExecCtx<NoCtx, ()> = ExecCtx::new(NoCtx);
+        let mut node = Node::compile(&mut ctx, Path::root(), parse_expr(expr).unwrap());
+        let init = node.current(&mut ctx);
+        let ups = events
+            .iter()
+            .map(|(name, v)| {
+                let ev = Event::Variable(Path::root(), Chars::from(*name), v.clone());
+                node.update(&mut ctx, &ev)
+            })
+            .collect();
+        (init, ups)
+    }
+
+    #[test]
+    fn once() {
+        let ev = [("x", Value::U64(1)), ("x", Value::U64(2))];
+        let (init, ups) = run("once(x)", &ev);
+        assert_eq!(init, None);
+        assert_eq!(ups, vec![Some(Value::U64(1)), None]);
+        let (init, ups) = run("once(42)", &ev);
+        assert_eq!(init, Some(Value::I64(42)));
+        assert_eq!(ups, vec![None, None]);
+        let (init, _) = run("once(x, x)", &ev);
+        assert!(matches!(init, Some(Value::Error(_))));
+    }
+
+    #[test]
+    fn changed() {
+        let ev = [
+            ("x", Value::U64(1)),
+            ("x", Value::U64(1)),
+            ("x", Value::U64(2)),
+            ("x", Value::U64(1)),
+            ("x", Value::U64(1)),
+        ];
+        let (init, ups) = run("changed(x)", &ev);
+        assert_eq!(init, None);
+        let expected = vec![
+            Some(Value::U64(1)),
+            None,
+            Some(Value::U64(2)),
+            Some(Value::U64(1)),
+            None,
+        ];
+        assert_eq!(ups, expected);
+        let (_, ups) = run("uniq(x)", &ev);
+        assert_eq!(ups, expected);
+    }
+
+    #[test]
+    fn sample_on() {
+        let ev = [
+            ("t", Value::Null),
+            ("x", Value::U64(1)),
+            ("x", Value::U64(2)),
+            ("t", Value::Null),
+            ("t", Value::Null),
+            ("x", Value::U64(3)),
+        ];
+        let (init, ups) = run("sample_on(t, x)", &ev);
+        assert_eq!(init, None);
+        assert_eq!(
+            ups,
+            vec![None, None, None, Some(Value::U64(2)), Some(Value::U64(2)), None]
+        );
+        // the initial value of the trigger isn't a trigger
+        let (init, ups) = run("sample_on(true, x)", &ev);
+        assert_eq!(init, None);
+        assert_eq!(ups, vec![None; 6]);
+        // the source is available from the start
+        let (init, ups) = run("sample_on(t, 42)", &ev[..1]);
+        assert_eq!(init, None);
+        assert_eq!(ups, vec![Some(Value::I64(42))]);
+        let (init, _) = run("sample_on(t)", &ev);
+        assert!(matches!(init, Some(Value::Error(_))));
+    }
+
+    /// evaluate `expr` for each value of `x`
+    fn map_x(expr: &str, xs: &[Value]) -> Vec<Option<Value>> {
+        let ev = xs.iter().map(|v| ("x", v.clone())).collect::<Vec<_>>();
+        run(expr, &ev).1
+    }
+
+    #[test]
+    fn abs_floor_ceil() {
+        let xs = [
+            Value::I32(-3),
+            Value::I32(i32::MIN),
+            Value::U64(3),
+            Value::F32(-1.5),
+            Value::F64(2.5),
+            Value::Decimal(Decimal::new(-15, 1)),
+        ];
+        let res = map_x("abs(x)", &xs);
+        assert_eq!(
+            res,
+            vec![
+                Some(Value::I32(3)),
+                Some(Value::I32(i32::MAX)),
+                Some(Value::U64(3)),
+                Some(Value::F32(1.5)),
+                Some(Value::F64(2.5)),
+                Some(Value::Decimal(Decimal::new(15, 1))),
+            ]
+        );
+        let res = map_x("floor(x)", &xs);
+        assert_eq!(
+            res,
+            vec![
+                Some(Value::I32(-3)),
+                Some(Value::I32(i32::MIN)),
+                Some(Value::U64(3)),
+                Some(Value::F32(-2.)),
+                Some(Value::F64(2.)),
+                Some(Value::Decimal(Decimal::new(-2, 0))),
+            ]
+        );
+        let res = map_x("ceil(x)", &xs);
+        assert_eq!(
+            res[3..],
+            [
+                Some(Value::F32(-1.)),
+                Some(Value::F64(3.)),
+                Some(Value::Decimal(Decimal::new(-1, 0))),
+            ]
+        );
+        let res = map_x("abs(x)", &[Value::from("foo")]);
+        assert!(matches!(res[0], Some(Value::Error(_))));
+    }
+
+    #[test]
+    fn round() {
+        let res = map_x(
+            "round(2, x)",
+            &[
+                Value::F64(1.23456),
+                Value::F32(1.5),
+                Value::U32(1234),
+                Value::Decimal(Decimal::new(12345, 4)),
+            ],
+        );
+        assert_eq!(
+            res,
+            vec![
+                Some(Value::F64(1.23)),
+                Some(Value::F32(1.5)),
+                Some(Value::U32(1234)),
+                Some(Value::Decimal(Decimal::new(123, 2))),
+            ]
+        );
+        let res = map_x(
+            "round(-2, x)",
+            &[
+                Value::I64(1234),
+                Value::U32(1250),
+                Value::Z32(-1250),
+                Value::U32(u32::MAX),
+                Value::F64(1250.),
+                Value::Decimal(Decimal::new(1234, 0)),
+            ],
+        );
+        assert_eq!(
+            res[..3],
+            [Some(Value::I64(1200)), Some(Value::U32(1300)), Some(Value::Z32(-1300))]
+        );
+        assert!(matches!(res[3], Some(Value::Error(_))));
+        assert_eq!(
+            res[4..],
+            [Some(Value::F64(1300.)), Some(Value::Decimal(Decimal::new(1200, 0)))]
+        );
+        // every type rounds the midpoint away from zero
+        let res = map_x(
+            "round(0, x)",
+            &[
+                Value::F64(2.5),
+                Value::Decimal(Decimal::new(25, 1)),
+                Value::Decimal(Decimal::new(-25, 1)),
+            ],
+        );
+        assert_eq!(
+            res,
+            vec![
+                Some(Value::F64(3.)),
+                Some(Value::Decimal(Decimal::new(3, 0))),
+                Some(Value::Decimal(Decimal::new(-3, 0))),
+            ]
+        );
+        let res = map_x("round(-1, x)", &[Value::Decimal(Decimal::new(25, 0))]);
+        assert_eq!(res, vec![Some(Value::Decimal(Decimal::new(30, 0)))]);
+        // rounding up past the largest decimal overflows, and so does
+        // a multiple too large to be a decimal
+        let res = map_x("round(-28, x)", &[Value::Decimal(Decimal::MAX)]);
+        assert!(matches!(res[0], Some(Value::Error(_))));
+        let res = map_x("round(-19, x)", &[Value::Decimal(Decimal::new(5, 0))]);
+        assert_eq!(res, vec![Some(Value::Decimal(Decimal::ZERO))]);
+        let res = map_x("round(-40, x)", &[Value::Decimal(Decimal::new(5, 0))]);
+        assert!(matches!(res[0], Some(Value::Error(_))));
+        let res = map_x("round(\"foo\", x)", &[Value::F64(1.)]);
+        assert!(matches!(res[0], Some(Value::Error(_))));
+    }
+
+    #[test]
+    fn clamp() {
+        let res = map_x(
+            "clamp(0, 10, x)",
+            &[Value::U32(20), Value::U32(5), Value::F64(-1.), Value::Z64(-3)],
+        );
+        assert_eq!(
+            res,
+            vec![
+                Some(Value::U32(10)),
+                Some(Value::U32(5)),
+                Some(Value::F64(0.)),
+                Some(Value::Z64(0)),
+            ]
+        );
+        let res = map_x("clamp(10, 0, x)", &[Value::U32(5)]);
+        assert!(matches!(res[0], Some(Value::Error(_))));
+    }
+
+    #[test]
+    fn path() {
+        let s = |s: &'static str| Some(Value::from(s));
+        let res = map_x("path_dirname(x)", &[Value::from("/foo/bar"), Value::from("/")]);
+        assert_eq!(res, vec![s("/foo"), Some(Value::Null)]);
+        let res = map_x("path_basename(x)", &[Value::from("/foo/bar\\/baz")]);
+        assert_eq!(res, vec![s("bar\\/baz")]);
+        let res = map_x(
+            "path_concat(\"/foo/\", x, \"baz\")",
+            &[Value::from("bar"), Value::from("//bar/"), Value::from("")],
+        );
+        assert_eq!(res, vec![s("/foo/bar/baz"), s("/foo/bar/baz"), s("/foo/baz")]);
+        let res = map_x("path_concat(x)", &[Value::from("/foo//bar/")]);
+        assert_eq!(res, vec![s("/foo/bar")]);
+        let res = map_x("path_escape(x)", &[Value::from("a/b"), Value::from("ab")]);
+        assert_eq!(res, vec![s("a\\/b"), s("ab")]);
+        let res =
+            map_x("path_concat(\"/foo\", path_escape(x))", &[Value::from("bar/baz")]);
+        assert_eq!(res, vec![s("/foo/bar\\/baz")]);
+    }
+
+    #[test]
+    fn mean() {
+        let d = |n, scale| Value::Decimal(Decimal::new(n, scale));
+        let res = map_x("mean(x)", &[d(1, 1), d(2, 1), d(6, 1)]);
+        assert_eq!(res, vec![Some(d(1, 1)), Some(d(15, 2)), Some(d(3, 1))]);
+        // a non decimal sample falls back to floating point
+        let res = map_x("mean(x)", &[d(1, 1), Value::F64(0.2)]);
+        assert_eq!(res, vec![Some(d(1, 1)), Some(Value::F64((0.1 + 0.2) / 2.))]);
+        let res = map_x("mean(x)", &[Value::F64(0.1), d(2, 1)]);
+        assert_eq!(res, vec![Some(Value::F64(0.1)), Some(Value::F64((0.1 + 0.2) / 2.))]);
+    }
+
+    fn ts(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn is_err(v: &Option<Value>) -> bool {
+        matches!(v, Some(Value::Error(_)))
+    }
+
+    #[test]
+    fn time_diff() {
+        let secs = |n| Some(Value::Duration(Duration::from_secs(n)));
+        let xs = [
+            Value::from("2024-01-01T00:01:00Z"),
+            Value::DateTime(ts("2024-01-01T23:00:00Z")),
+            // a before b is clamped to zero
+            Value::from("2024-01-03T00:00:00Z"),
+        ];
+        let res = map_x("time_diff(\"2024-01-02T00:00:00Z\", x)", &xs);
+        assert_eq!(res, vec![secs(86340), secs(3600), secs(0)]);
+        // numbers are seconds since the epoch
+        let xs = [Value::U64(60), Value::from("1970-01-01T00:00:01Z")];
+        assert_eq!(map_x("time_diff(x, 0)", &xs), vec![secs(60), secs(1)]);
+        let res = map_x("time_diff(x, 0)", &[Value::from("yesterday"), Value::True]);
+        assert!(res.iter().all(is_err));
+        let (init, _) = run("time_diff(x)", &[]);
+        assert!(is_err(&init));
+    }
+
+    #[test]
+    fn time_format() {
+        let s = |s: &'static str| Some(Value::from(s));
+        let secs = |n| Value::Duration(Duration::from_secs(n));
+        let xs = [secs(3725), Value::Duration(Duration::from_millis(450)), secs(172805)];
+        let res = map_x("time_format(\"human\", x)", &xs);
+        assert_eq!(res, vec![s("1h 2m"), s("450ms"), s("2d")]);
+        let now = Utc::now();
+        let xs = [
+            Value::DateTime(now - chrono::Duration::seconds(3630)),
+            Value::DateTime(now + chrono::Duration::seconds(7230)),
+        ];
+        let res = map_x("time_format(\"human\", x)", &xs);
+        assert_eq!(res, vec![s("1h ago"), s("in 2h")]);
+        let xs = [
+            Value::from("2024-03-04T05:06:07Z"),
+            Value::DateTime(ts("2024-03-04T05:06:07Z")),
+            Value::U64(0),
+        ];
+        let res = map_x("time_format(\"%Y-%m-%d %H:%M\", x)", &xs);
+        let day = s("2024-03-04 05:06");
+        assert_eq!(res, vec![day.clone(), day, s("1970-01-01 00:00")]);
+        let res = map_x("time_format(\"%Y\", x)", &[Value::from("nope"), Value::True]);
+        assert!(res.iter().all(is_err));
+        assert!(map_x("time_format(\"%!\", x)", &[Value::U64(0)]).iter().all(is_err));
+        assert!(map_x("time_format(42, x)", &[Value::U64(0)]).iter().all(is_err));
+        let (init, _) = run("time_format(x)", &[]);
+        assert!(is_err(&init));
+    }
+
+    #[test]
+    fn time_arith() {
+        let ev = [
+            ("t", Value::DateTime(ts("2024-01-01T00:00:00Z"))),
+            ("d", Value::Duration(Duration::from_secs(90))),
+        ];
+        let (_, ups) = run("sum(t, d)", &ev);
+        assert_eq!(ups[1], Some(Value::DateTime(ts("2024-01-01T00:01:30Z"))));
+        let (_, ups) = run("sum(d, d)", &ev[1..]);
+        assert_eq!(ups, vec![Some(Value::Duration(Duration::from_secs(180)))]);
+        let (_, ups) = run("sum(t, 1)", &ev[..1]);
+        assert!(is_err(&ups[0]));
+        // the other side is cast to a datetime or duration
+        let (_, ups) = run("cmp(\"gt\", d, 60)", &ev[1..]);
+        assert_eq!(ups, vec![Some(Value::True)]);
+        let (_, ups) = run("cmp(\"lt\", t, \"2024-01-01T00:00:01Z\")", &ev[..1]);
+        assert_eq!(ups, vec![Some(Value::True)]);
+    }
+
+    #[test]
+    fn now_and_after() {
+        let before = Utc::now();
+        let (init, ups) = run("now(x)", &[("x", Value::Null)]);
+        for v in iter::once(init).chain(ups) {
+            match v {
+                Some(Value::DateTime(t)) => assert!(t >= before && t <= Utc::now()),
+                v => panic!("expected a datetime, got {:?}", v),
+            }
+        }
+        // values are only delivered when the timer fires
+        let ev = [("d", Value::from("5s")), ("x", Value::U64(1))];
+        let (init, ups) = run("after(d, x)", &ev);
+        assert_eq!(init, None);
+        assert_eq!(ups, vec![None, None]);
+        let ev = [("d", Value::from("soon")), ("x", Value::U64(1))];
+        let (_, ups) = run("after(d, x)", &ev);
+        assert_eq!(ups[0], None);
+        assert!(is_err(&ups[1]));
+        let (init, _) = run("after(x)", &[]);
+        assert!(is_err(&init));
+    }
+}
@@ -1,18 +1,19 @@
 pub use crate::stdfn::{RpcCallId, TimerId};
 use crate::{
-    expr::{Expr, ExprId, ExprKind},
+    expr::{Expr, ExprId, ExprKind, FnDef},
     stdfn,
 };
+use anyhow::{bail, Result};
 use arcstr::ArcStr;
 use chrono::prelude::*;
-use fxhash::{FxBuildHasher, FxHashMap};
+use fxhash::{FxBuildHasher, FxHashMap, FxHashSet};
 use netidx::{
     chars::Chars,
     path::Path,
     subscriber::{Dval, SubId, UpdatesFlags, Value},
 };
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fmt,
     sync::{Arc, Weak},
     time::Duration,
@@ -27,15 +28,24 @@ pub struct DbgCtx<E> {
         FxBuildHasher,
     >,
     current: HashMap<ExprId, (Option<Event<E>>, Value), FxBuildHasher>,
+    history: HashMap<
+        ExprId,
+        VecDeque<(DateTime<Local>, Option<Event<E>>, Value)>,
+        FxBuildHasher,
+    >,
 }
 
 impl<E: Clone> DbgCtx<E> {
+    /// The number of values kept for each expression
+    pub const HISTORY: usize = 50;
+
     fn new() -> Self {
         DbgCtx {
             trace: false,
             events: VecDeque::new(),
             watch: HashMap::with_hasher(FxBuildHasher::default()),
             current: HashMap::with_hasher(FxBuildHasher::default()),
+            history: HashMap::with_hasher(FxBuildHasher::default()),
         }
     }
 
@@ -49,6 +59,15 @@ impl<E: Clone> DbgCtx<E> {
         self.current.get(id)
     }
 
+    /// The most recent values produced by the expression `id`, oldest
+    /// first, along with the time and the event that triggered each one.
+    pub fn iter_history(
+        &self,
+        id: &ExprId,
+    ) -> impl Iterator<Item = &(DateTime<Local>, Option<Event<E>>, Value)> {
+        self.history.get(id).into_iter().flat_map(|h| h.iter())
+    }
+
     pub fn add_watch(
         &mut self,
         id: ExprId,
@@ -75,6 +94,11 @@ impl<E: Clone> DbgCtx<E> {
                 }
             }
         }
+        let history = self.history.entry(id).or_insert_with(VecDeque::new);
+        history.push_back((now, event.clone(), value.clone()));
+        if history.len() > Self::HISTORY {
+            history.pop_front();
+        }
         self.events.push_back((id, (now, event.clone(), value.clone())));
         self.current.insert(id, (event, value));
         if self.events.len() > MAX {
@@ -91,6 +115,7 @@ impl<E: Clone> DbgCtx<E> {
     pub fn clear(&mut self) {
         self.events.clear();
         self.current.clear();
+        self.history.clear();
         self.watch.retain(|_, v| {
             v.retain(|w| Weak::strong_count(w) > 0);
             v.len() > 0
@@ -208,9 +233,16 @@ pub struct ExecCtx<C: Ctx + 'static, E: 'static> {
     pub variables: FxHashMap<Path, FxHashMap<Chars, Value>>,
     pub dbg_ctx: DbgCtx<E>,
     pub user: C,
+    user_fns: FxHashSet<String>,
+    pub(crate) fn_depth: usize,
 }
 
 impl<C: Ctx, E: Clone> ExecCtx<C, E> {
+    /// The maximum depth of nested calls to functions defined with
+    /// `define_fn`. Bodies are expanded when they are compiled, so
+    /// a recursive function will always hit this limit.
+    pub const MAX_FN_DEPTH: usize = 16;
+
     pub fn lookup_var(&self, scope: &Path, name: &Chars) -> Option<(&Path, &Value)> {
         let mut iter = Path::dirnames(scope);
         loop {
@@ -228,29 +260,56 @@ impl<C: Ctx, E: Clone> ExecCtx<C, E> {
     }
 
     pub fn clear(&mut self) {
+        for name in self.user_fns.drain() {
+            self.functions.remove(&name);
+        }
         self.variables.clear();
         self.dbg_ctx.clear();
         self.user.clear();
     }
 
+    /// Define a function that can be called from any expression
+    /// compiled after it is defined. Redefining a function defined
+    /// with this method replaces it, however builtin functions can't
+    /// be replaced. Defined functions are removed by `clear`.
+    pub fn define_fn(&mut self, def: FnDef) -> Result<()> {
+        if self.functions.contains_key(&def.name) && !self.user_fns.contains(&def.name) {
+            bail!("can't redefine builtin function {}", def.name)
+        }
+        let name = def.name.clone();
+        self.functions.insert(name.clone(), stdfn::UserFn::init(def));
+        self.user.register_fn(Chars::from(name.clone()), Path::root());
+        self.user_fns.insert(name);
+        Ok(())
+    }
+
     pub fn no_std(user: C) -> Self {
         ExecCtx {
             functions: HashMap::with_hasher(FxBuildHasher::default()),
             variables: HashMap::with_hasher(FxBuildHasher::default()),
             dbg_ctx: DbgCtx::new(),
             user,
+            user_fns: HashSet::with_hasher(FxBuildHasher::default()),
+            fn_depth: 0,
         }
     }
 
     pub fn new(user: C) -> Self {
         let mut t = ExecCtx::no_std(user);
+        stdfn::Abs::register(&mut t);
+        stdfn::After::register(&mut t);
         stdfn::AfterIdle::register(&mut t);
         stdfn::All::register(&mut t);
         stdfn::And::register(&mut t);
         stdfn::Any::register(&mut t);
         stdfn::Array::register(&mut t);
+        stdfn::ArrayLen::register(&mut t);
+        stdfn::ArrayMap::register(&mut t);
         stdfn::Basename::register(&mut t);
         stdfn::Cast::register(&mut t);
+        stdfn::Ceil::register(&mut t);
+        stdfn::Changed::register(&mut t);
+        stdfn::Clamp::register(&mut t);
         stdfn::Cmp::register(&mut t);
         stdfn::Contains::register(&mut t);
         stdfn::Count::register(&mut t);
@@ -261,22 +320,34 @@ impl<C: Ctx, E: Clone> ExecCtx<C, E> {
         stdfn::Eval::register(&mut t);
         stdfn::FilterErr::register(&mut t);
         stdfn::Filter::register(&mut t);
+        stdfn::Floor::register(&mut t);
         stdfn::Get::register(&mut t);
+        stdfn::Guard::register(&mut t);
         stdfn::If::register(&mut t);
         stdfn::Index::register(&mut t);
         stdfn::Isa::register(&mut t);
         stdfn::IsErr::register(&mut t);
+        stdfn::IsSubscribed::register(&mut t);
         stdfn::Load::register(&mut t);
+        stdfn::MapGet::register(&mut t);
+        stdfn::Match::register(&mut t);
         stdfn::Max::register(&mut t);
         stdfn::Mean::register(&mut t);
         stdfn::Min::register(&mut t);
         stdfn::Not::register(&mut t);
+        stdfn::Now::register(&mut t);
         stdfn::Once::register(&mut t);
         stdfn::Or::register(&mut t);
+        stdfn::PathBasename::register(&mut t);
+        stdfn::PathConcat::register(&mut t);
+        stdfn::PathDirname::register(&mut t);
+        stdfn::PathEscape::register(&mut t);
         stdfn::Product::register(&mut t);
         stdfn::Replace::register(&mut t);
+        stdfn::Round::register(&mut t);
         stdfn::RpcCall::register(&mut t);
         stdfn::Sample::register(&mut t);
+        stdfn::SampleOn::register(&mut t);
         stdfn::Set::register(&mut t);
         stdfn::StartsWith::register(&mut t);
         stdfn::Store::register(&mut t);
@@ -285,11 +356,12 @@ impl<C: Ctx, E: Clone> ExecCtx<C, E> {
         stdfn::StripPrefix::register(&mut t);
         stdfn::StripSuffix::register(&mut t);
         stdfn::Sum::register(&mut t);
+        stdfn::TimeDiff::register(&mut t);
+        stdfn::TimeFormat::register(&mut t);
         stdfn::Timer::register(&mut t);
         stdfn::TrimEnd::register(&mut t);
         stdfn::Trim::register(&mut t);
         stdfn::TrimStart::register(&mut t);
-        stdfn::Uniq::register(&mut t);
         t
     }
 }
@@ -1289,7 +1289,10 @@ impl ContainerInner {
 
     fn process_publish_event(&mut self, e: PEvent) {
         match e {
-            PEvent::Subscribe(_, _) | PEvent::Unsubscribe(_, _) => (),
+            PEvent::Subscribe(_, _)
+            | PEvent::Unsubscribe(_, _)
+            | PEvent::SlowSubscriber(_)
+            | PEvent::SubscriberDropped(_) => (),
             PEvent::Destroyed(id) => {
                 match self.ctx.user.by_id.remove(&id) {
                     None => (),
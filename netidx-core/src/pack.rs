@@ -136,6 +136,12 @@ impl Pack for net::SocketAddr {
     }
 }
 
+/// Bytes payloads at least this large are handed to the buffer as
+/// `Bytes` via `BufMut::put` instead of being copied with
+/// `put_slice`, so that a buffer that understands it can avoid the
+/// copy.
+pub const ZERO_COPY_MIN: usize = 32 * 1024;
+
 impl Pack for Bytes {
     fn encoded_len(&self) -> usize {
         let len = Bytes::len(self);
@@ -144,7 +150,12 @@ impl Pack for Bytes {
 
     fn encode(&self, buf: &mut impl BufMut) -> Result<(), PackError> {
         encode_varint(Bytes::len(self) as u64, buf);
-        Ok(buf.put_slice(&*self))
+        if Bytes::len(self) >= ZERO_COPY_MIN {
+            buf.put(self.clone())
+        } else {
+            buf.put_slice(self)
+        }
+        Ok(())
     }
 
     fn decode(buf: &mut impl Buf) -> Result<Self, PackError> {
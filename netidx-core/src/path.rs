@@ -19,6 +19,40 @@ use std::{
 pub static ESC: char = '\\';
 pub static SEP: char = '/';
 
+#[doc(hidden)]
+pub use arcstr as __arcstr;
+
+/// Build a `Path` from one or more string literals, which are joined
+/// with `/`. The result is checked at compile time, it must already
+/// be in canonical form, that is, with no empty components or
+/// trailing separator. The path is a static, so building it doesn't
+/// allocate, and it can be used to initialize a `const`.
+///
+/// # Examples
+/// ```
+/// use netidx_core::{path, path::Path};
+/// const BASE: Path = path!("/app/feeds");
+/// assert_eq!(&*BASE, "/app/feeds");
+/// assert_eq!(&*path!("/app", "feeds", "price"), "/app/feeds/price");
+/// ```
+///
+/// ```compile_fail
+/// use netidx_core::path;
+/// let p = path!("/app//feeds/");
+/// ```
+#[macro_export]
+macro_rules! path {
+    ($first:literal $(, $rest:literal)* $(,)?) => {{
+        const _: () = assert!(
+            $crate::path::Path::is_canonical_const(concat!($first $(, "/", $rest)*)),
+            concat!("invalid path literal ", $first $(, "/", $rest)*)
+        );
+        $crate::path::Path::from_canonical_arcstr(
+            $crate::path::__arcstr::literal!(concat!($first $(, "/", $rest)*))
+        )
+    }};
+}
+
 fn is_canonical(s: &str) -> bool {
     for _ in Path::parts(s).filter(|p| *p == "") {
         return false;
@@ -227,6 +261,51 @@ impl Path {
         }
     }
 
+    /// Return true if `s` is already canonical, non empty, and has no
+    /// empty components, which means `Path::from(s)` won't change
+    /// it. This is what the `path!` macro checks at compile time.
+    ///
+    /// # Examples
+    /// ```
+    /// use netidx_core::path::Path;
+    /// assert!(Path::is_canonical_const("/"));
+    /// assert!(Path::is_canonical_const("/foo/bar"));
+    /// assert!(Path::is_canonical_const(r"/foo\/bar"));
+    /// assert!(!Path::is_canonical_const("/foo//bar"));
+    /// assert!(!Path::is_canonical_const("/foo/bar/"));
+    /// assert!(!Path::is_canonical_const(""));
+    /// ```
+    pub const fn is_canonical_const(s: &str) -> bool {
+        let b = s.as_bytes();
+        if b.is_empty() {
+            return false;
+        }
+        if b.len() == 1 && b[0] == b'/' {
+            return true;
+        }
+        let mut i = if b[0] == b'/' { 1 } else { 0 };
+        let mut esc = false;
+        let mut part_len = 0;
+        while i < b.len() {
+            if b[i] == b'/' && !esc {
+                if part_len == 0 {
+                    return false;
+                }
+                part_len = 0;
+            } else {
+                esc = b[i] == b'\\' && !esc;
+                part_len += 1;
+            }
+            i += 1;
+        }
+        part_len > 0
+    }
+
+    #[doc(hidden)]
+    pub const fn from_canonical_arcstr(s: ArcStr) -> Self {
+        Path(s)
+    }
+
     /// returns /
     pub fn root() -> Path {
         // CR estokes: need a good solution for using SEP here
@@ -381,6 +460,44 @@ impl Path {
         }
     }
 
+    /// Append every element of `parts` to the path. Unlike chaining
+    /// `append`, which allocates and canonicalizes a new path for
+    /// each part, the result is built in one buffer. As with
+    /// `append`, the parts are not escaped.
+    ///
+    /// # Examples
+    /// ```
+    /// use netidx_core::path::Path;
+    /// let p = Path::from("/foo").join(["bar", "baz"]);
+    /// assert_eq!(&*p, "/foo/bar/baz");
+    ///
+    /// let p = Path::root().join(vec![String::from("bar"), String::new()]);
+    /// assert_eq!(&*p, "/bar");
+    /// ```
+    pub fn join<I, T>(&self, parts: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut res = String::new();
+        for p in parts {
+            let p = p.as_ref();
+            if !p.is_empty() {
+                if res.is_empty() {
+                    res.reserve(self.len() + p.len() + 1);
+                    res.push_str(self.as_ref());
+                }
+                res.push(SEP);
+                res.push_str(p);
+            }
+        }
+        if res.is_empty() {
+            self.clone()
+        } else {
+            Path::from(res)
+        }
+    }
+
     /// return an iterator over the parts of the path. The path
     /// separator may be escaped with \. and a literal \ may be
     /// represented as \\.
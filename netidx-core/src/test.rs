@@ -69,3 +69,67 @@ fn test_array_pack() {
     Pack::encode(&a, &mut &mut buf[..]).unwrap();
     assert_eq!(<[u8; 64] as Pack>::decode(&mut &buf[..]).unwrap(), a)
 }
+
+#[test]
+fn test_check_addr() {
+    use crate::utils::check_addr;
+    use std::net::{IpAddr, SocketAddr};
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let res = |s: &str| [(s.parse::<SocketAddr>().unwrap(), ())];
+    let public4 = res("54.32.224.1:4654");
+    let public6 = res("[2600:1f18::1]:4654");
+    let private4 = res("192.168.0.1:4654");
+    let private6 = res("[fd00::1]:4654");
+    let local4 = res("127.0.0.1:4654");
+    let local6 = res("[::1]:4654");
+    assert!(check_addr(ip("54.32.224.2"), &public4).is_ok());
+    assert!(check_addr(ip("2600:1f18::2"), &public4).is_ok());
+    assert!(check_addr(ip("2600:1f18::2"), &public6).is_ok());
+    assert!(check_addr(ip("fe80::1"), &public6).is_err());
+    assert!(check_addr(ip("169.254.0.1"), &public4).is_err());
+    assert!(check_addr(ip("::"), &public6).is_err());
+    assert!(check_addr(ip("ff02::1"), &public6).is_err());
+    assert!(check_addr(ip("fd00::2"), &private6).is_ok());
+    assert!(check_addr(ip("fd00::2"), &private4).is_ok());
+    assert!(check_addr(ip("fd00::2"), &public4).is_ok());
+    assert!(check_addr(ip("fd00::2"), &public6).is_err());
+    assert!(check_addr(ip("192.168.0.2"), &public6).is_ok());
+    assert!(check_addr(ip("192.168.0.2"), &public4).is_err());
+    assert!(check_addr(ip("::ffff:192.168.0.2"), &public4).is_err());
+    assert!(check_addr(ip("::ffff:192.168.0.2"), &private4).is_ok());
+    assert!(check_addr(ip("::1"), &local6).is_ok());
+    assert!(check_addr(ip("::1"), &local4).is_ok());
+    assert!(check_addr(ip("::ffff:127.0.0.1"), &local4).is_ok());
+    assert!(check_addr(ip("::1"), &public6).is_err());
+}
+
+#[test]
+fn test_check_port_range_and_advertised_addr() {
+    use crate::utils::{check_advertised_addr, check_port_range};
+    use std::net::SocketAddr;
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    let public4 = [(addr("54.32.224.1:4654"), ())];
+    assert!(check_port_range(5000, 5100).is_ok());
+    assert!(check_port_range(5000, 5000).is_ok());
+    assert!(check_port_range(5100, 5000).is_err());
+    assert!(check_port_range(0, 5000).is_err());
+    assert!(check_advertised_addr(addr("54.32.224.2:15000"), &public4).is_ok());
+    assert!(check_advertised_addr(addr("54.32.224.2:0"), &public4).is_ok());
+    assert!(check_advertised_addr(addr("192.168.0.2:15000"), &public4).is_err());
+    assert!(check_advertised_addr(addr("0.0.0.0:15000"), &public4).is_err());
+}
+
+#[test]
+fn test_path_literal() {
+    use crate::path::Path;
+    let cases =
+        ["/", "/foo", "foo/bar", r"/foo\/bar", r"/foo\\", "", "//", "/foo/", r"/a\\/"];
+    for s in cases {
+        let canonical = !s.is_empty() && &*Path::from_str(s) == s;
+        assert_eq!(Path::is_canonical_const(s), canonical, "{}", s)
+    }
+    const P: Path = crate::path!("/foo", "bar", "baz");
+    assert_eq!(P, Path::root().append("foo").append("bar").append("baz"));
+    assert_eq!(Path::from("/foo").join(["bar", "", "baz"]), P);
+    assert_eq!(crate::path!("/").join(Vec::<String>::new()), Path::root());
+}
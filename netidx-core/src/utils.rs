@@ -168,38 +168,97 @@ macro_rules! atomic_id {
     };
 }
 
+/// Convert ipv4 mapped ipv6 addresses (::ffff:a.b.c.d) to the ipv4
+/// address they represent, other addresses are returned unchanged.
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+    }
+}
+
+/// True if `ip` is only routable within a site. ipv4 private
+/// addresses (10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16) and ipv6
+/// unique local addresses (fc00::/7).
+pub fn is_private(ip: IpAddr) -> bool {
+    match canonical_ip(ip) {
+        IpAddr::V4(ip) => ip.is_private(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xfe00) == 0xfc00,
+    }
+}
+
+/// True if `ip` is a link local address, 169.254.0.0/16 or fe80::/10
+pub fn is_link_local(ip: IpAddr) -> bool {
+    match canonical_ip(ip) {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
 pub fn check_addr<A>(ip: IpAddr, resolvers: &[(SocketAddr, A)]) -> Result<()> {
+    let ip = canonical_ip(ip);
+    if is_link_local(ip) {
+        bail!("addr is a link local address");
+    }
     match ip {
-        IpAddr::V4(ip) if ip.is_link_local() => {
-            bail!("addr is a link local address");
-        }
         IpAddr::V4(ip) if ip.is_broadcast() => {
             bail!("addr is a broadcast address");
         }
-        IpAddr::V4(ip) if ip.is_private() => {
-            let ok = resolvers.iter().all(|(a, _)| match a.ip() {
-                IpAddr::V4(ip) if ip.is_private() || ip.is_loopback() => true,
-                IpAddr::V6(_) => true,
-                _ => false,
-            });
-            if !ok {
-                bail!("addr is a private address, and the resolver is not")
-            }
-        }
         _ => (),
     }
+    if is_private(ip) {
+        // a resolver of the other address family may be dual stack,
+        // and we can't know its address in our family
+        let ok = resolvers.iter().all(|(a, _)| {
+            let a = canonical_ip(a.ip());
+            a.is_ipv4() != ip.is_ipv4() || is_private(a) || a.is_loopback()
+        });
+        if !ok {
+            bail!("addr is a private address, and the resolver is not")
+        }
+    }
     if ip.is_unspecified() {
         bail!("addr is an unspecified address");
     }
     if ip.is_multicast() {
         bail!("addr is a multicast address");
     }
-    if ip.is_loopback() && !resolvers.iter().all(|(a, _)| a.ip().is_loopback()) {
+    if ip.is_loopback()
+        && !resolvers.iter().all(|(a, _)| canonical_ip(a.ip()).is_loopback())
+    {
         bail!("addr is a loopback address and the resolver is not");
     }
     Ok(())
 }
 
+/// Check that `start..=end` is a range of ports a listener may be
+/// restricted to.
+pub fn check_port_range(start: u16, end: u16) -> Result<()> {
+    if start == 0 {
+        bail!("port 0 can't be part of a port range")
+    }
+    if start > end {
+        bail!("invalid port range {}-{}", start, end)
+    }
+    Ok(())
+}
+
+/// Check that `addr` may be advertised to `resolvers` in place of the
+/// address a listener is actually bound to, e.g. the external side of
+/// a NAT or a container port mapping. The ip must obey the same rules
+/// as `check_addr`, port 0 means the advertised port is the bound
+/// port.
+pub fn check_advertised_addr<A>(
+    addr: SocketAddr,
+    resolvers: &[(SocketAddr, A)],
+) -> Result<()> {
+    check_addr(addr.ip(), resolvers)
+        .map_err(|e| anyhow!("invalid advertised address {}, {}", addr, e))
+}
+
 pub fn is_sep(esc: &mut bool, c: char, escape: char, sep: char) -> bool {
     if c == sep {
         !*esc
@@ -362,9 +421,15 @@ pub fn bytes(t: &[u8]) -> Bytes {
     bytesmut(t).freeze()
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct ChanWrap<T>(pub mpsc::Sender<T>);
 
+impl<T> Clone for ChanWrap<T> {
+    fn clone(&self) -> Self {
+        ChanWrap(self.0.clone())
+    }
+}
+
 impl<T> PartialEq for ChanWrap<T> {
     fn eq(&self, other: &ChanWrap<T>) -> bool {
         self.0.same_receiver(&other.0)
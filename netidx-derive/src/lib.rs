@@ -3,8 +3,8 @@ use std::collections::HashSet;
 use proc_macro2::{token_stream, Delimiter, TokenStream, TokenTree};
 use quote::{format_ident, quote, ToTokens};
 use syn::{
-    parse_macro_input, parse_quote, AttrStyle, Attribute, Data, DeriveInput, Field,
-    Fields, GenericParam, Ident, Index,
+    ext::IdentExt, parse_macro_input, parse_quote, AttrStyle, Attribute, Data,
+    DeriveInput, Field, Fields, GenericParam, Ident, Index, LitStr,
 };
 
 fn parse_attr<R, F: FnMut(Ident, token_stream::IntoIter) -> R>(
@@ -618,3 +618,206 @@ pub fn derive_pack(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     };
     proc_macro::TokenStream::from(expanded)
 }
+
+fn from_value(name: &Ident, input: &DeriveInput) -> syn::Result<TokenStream> {
+    let ts = match &input.data {
+        Data::Struct(st) => match &st.fields {
+            Fields::Named(fields) => {
+                let names = fields.named.iter().map(|f| f.ident.as_ref().unwrap());
+                let keys = names.clone().map(|n| n.unraw().to_string());
+                let vars = (0..fields.named.len()).map(|i| format_ident!("__{}", i));
+                let fields = names.clone().zip(vars.clone()).map(|(n, v)| {
+                    let key = n.unraw().to_string();
+                    quote! {
+                        #n: netidx_netproto::value::FromValue::from_value(#v)
+                            .map_err(|e| e.context(concat!("invalid field ", #key)))?
+                    }
+                });
+                quote! {
+                    let [#(#vars),*] =
+                        netidx_netproto::value::struct_fields(v, [#(#keys),*])?;
+                    Ok(#name { #(#fields),* })
+                }
+            }
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => quote! {
+                Ok(#name(netidx_netproto::value::FromValue::from_value(v)?))
+            },
+            Fields::Unnamed(fields) => {
+                let vars = (0..fields.unnamed.len()).map(|i| format_ident!("__{}", i));
+                let fields = vars.clone().enumerate().map(|(i, v)| {
+                    let key = i.to_string();
+                    quote! {
+                        netidx_netproto::value::FromValue::from_value(#v)
+                            .map_err(|e| e.context(concat!("invalid field ", #key)))?
+                    }
+                });
+                quote! {
+                    let [#(#vars),*] = netidx_netproto::value::tuple_fields(v)?;
+                    Ok(#name(#(#fields),*))
+                }
+            }
+            Fields::Unit => quote! {
+                match v {
+                    netidx_netproto::value::Value::Null => Ok(#name),
+                    v => Err(netidx_netproto::__private::anyhow!(
+                        "expected null, got {}",
+                        v
+                    )),
+                }
+            },
+        },
+        Data::Enum(_) | Data::Union(_) => {
+            let e = "FromValue can only be derived for structs";
+            return Err(syn::Error::new_spanned(&input.ident, e));
+        }
+    };
+    Ok(ts)
+}
+
+/// Derive `FromValue` for a struct. Structs with named fields are
+/// read from a map, `[[<field>, <value>], ...]`. Tuple structs are
+/// read from an array, except for newtypes, which are read directly
+/// from the wrapped value, and unit structs are read from null.
+#[proc_macro_derive(FromValue)]
+pub fn derive_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident.clone();
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(typ) = param {
+            typ.bounds.push(parse_quote!(netidx_netproto::value::FromValue))
+        }
+    }
+    let from_value = match from_value(&name, &input) {
+        Ok(ts) => ts,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics netidx_netproto::value::FromValue
+            for #name #ty_generics #where_clause
+        {
+            fn from_value(
+                v: netidx_netproto::value::Value
+            ) -> netidx_netproto::__private::Result<Self> {
+                #from_value
+            }
+        }
+    };
+    proc_macro::TokenStream::from(expanded)
+}
+
+#[derive(Default)]
+struct PublishableAttrs {
+    skip: bool,
+    tree: bool,
+    rename: Option<String>,
+}
+
+fn publishable_attrs(f: &Field) -> syn::Result<PublishableAttrs> {
+    let mut attrs = PublishableAttrs::default();
+    for a in f.attrs.iter().filter(|a| a.path().is_ident("publishable")) {
+        a.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else if meta.path.is_ident("tree") {
+                attrs.tree = true;
+            } else if meta.path.is_ident("rename") {
+                let s: LitStr = meta.value()?.parse()?;
+                attrs.rename = Some(s.value());
+            } else {
+                return Err(meta.error("invalid publishable attribute"));
+            }
+            Ok(())
+        })?
+    }
+    Ok(attrs)
+}
+
+fn publishable(input: &DeriveInput) -> syn::Result<(TokenStream, TokenStream)> {
+    let fields = match &input.data {
+        Data::Struct(st) => match &st.fields {
+            Fields::Named(fields) => fields,
+            Fields::Unnamed(_) | Fields::Unit => {
+                let e = "Publishable can only be derived for structs with named fields";
+                return Err(syn::Error::new_spanned(&input.ident, e));
+            }
+        },
+        Data::Enum(_) | Data::Union(_) => {
+            let e = "Publishable can only be derived for structs with named fields";
+            return Err(syn::Error::new_spanned(&input.ident, e));
+        }
+    };
+    let mut publish = vec![];
+    let mut update = vec![];
+    for f in fields.named.iter() {
+        let attrs = publishable_attrs(f)?;
+        if attrs.skip {
+            continue;
+        }
+        let name = f.ident.as_ref().unwrap();
+        let key = attrs.rename.unwrap_or_else(|| name.unraw().to_string());
+        if attrs.tree {
+            publish.push(quote! {
+                netidx::publisher::Publishable::publish_fields(
+                    &self.#name,
+                    publisher,
+                    &base.append(#key),
+                    vals
+                )?;
+            });
+            update.push(quote! {
+                netidx::publisher::Publishable::update_fields(&self.#name, batch, vals);
+            });
+        } else {
+            publish.push(quote! {
+                vals.push(publisher.publish(base.append(#key), self.#name.clone())?);
+            });
+            update.push(quote! {
+                if let Some(val) = vals.next() {
+                    val.update_changed(batch, self.#name.clone());
+                }
+            });
+        }
+    }
+    Ok((quote! { #(#publish)* }, quote! { #(#update)* }))
+}
+
+/// Derive `Publishable` for a struct with named fields, publishing
+/// each field at `base/<field>`. Fields may be annotated with
+/// `#[publishable(skip)]` to leave them out, `#[publishable(tree)]`
+/// to publish a nested `Publishable` struct as a subtree, or
+/// `#[publishable(rename = "name")]` to change the child path.
+#[proc_macro_derive(Publishable, attributes(publishable))]
+pub fn derive_publishable(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident.clone();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let (publish, update) = match publishable(&input) {
+        Ok(r) => r,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let expanded = quote! {
+        impl #impl_generics netidx::publisher::Publishable
+            for #name #ty_generics #where_clause
+        {
+            fn publish_fields(
+                &self,
+                publisher: &netidx::publisher::Publisher,
+                base: &netidx::path::Path,
+                vals: &mut std::vec::Vec<netidx::publisher::Val>,
+            ) -> netidx::protocol::__private::Result<()> {
+                #publish
+                Ok(())
+            }
+
+            fn update_fields(
+                &self,
+                batch: &mut netidx::publisher::UpdateBatch,
+                vals: &mut std::slice::Iter<netidx::publisher::Val>,
+            ) {
+                #update
+            }
+        }
+    };
+    proc_macro::TokenStream::from(expanded)
+}
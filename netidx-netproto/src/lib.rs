@@ -3,6 +3,7 @@
 #[macro_use] extern crate combine;
 #[macro_use] extern crate netidx_core;
 #[macro_use] extern crate serde_derive;
+extern crate self as netidx_netproto;
 
 pub mod glob;
 pub mod publisher;
@@ -10,5 +11,12 @@ pub mod value_parser;
 pub mod value;
 pub mod resolver;
 
+/// Used by the code generated by the `FromValue` and `Publishable`
+/// derive macros, so users don't need a dependency on anyhow.
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow::{anyhow, Result};
+}
+
 #[cfg(test)]
 mod test;
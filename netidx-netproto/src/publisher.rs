@@ -1,24 +1,98 @@
 use crate::{resolver::UserInfo, value::Value};
 use bytes::Bytes;
+use chrono::prelude::*;
 use netidx_core::path::Path;
 use netidx_derive::Pack;
-use std::net::SocketAddr;
+use std::{cmp::min, net::SocketAddr, time::Duration};
 
 atomic_id!(Id);
 
+/// The optional protocol features a peer supports, exchanged in the
+/// hello. The protocol version sent before the hello remains 3,
+/// instead the protocol evolves by appending `#[pack(default)]`
+/// fields to messages, which older peers skip and newer peers fill
+/// in with a default when they are missing, and by adding messages
+/// that are only sent to peers that advertise the corresponding
+/// capability. Peers that predate capabilities decode as the
+/// default, which has none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Pack)]
+pub struct Capabilities {
+    /// incremented whenever a capability is added
+    pub revision: u32,
+    pub flags: u64,
+}
+
+impl Capabilities {
+    /// The peer understands `From::Unpublished`
+    pub const UNPUBLISHED: u64 = 0x1;
+
+    /// The peer understands `From::Timestamp`
+    pub const TIMESTAMPS: u64 = 0x2;
+
+    /// The peer can decompress batches compressed with zstd. This is
+    /// never negotiated on encrypted connections, because the size of
+    /// a batch compressed before it is encrypted reveals how much of
+    /// it repeats, which can leak secrets mixed with data an attacker
+    /// controls (see CRIME).
+    pub const ZSTD: u64 = 0x4;
+
+    /// The peer understands `To::Heartbeat`. A subscriber with this
+    /// capability sends heartbeats when it is idle, so the publisher
+    /// may disconnect it if it hears nothing for too long.
+    pub const HEARTBEAT: u64 = 0x8;
+
+    /// The peer understands `To::Filter`
+    pub const FILTER: u64 = 0x10;
+
+    /// The peer understands `From::Multicast` and `To::Multicast`
+    pub const MULTICAST: u64 = 0x20;
+
+    /// The peer understands `From::BatchEnd`
+    pub const BATCH_ID: u64 = 0x40;
+
+    /// The capabilities of this version of netidx
+    pub const CURRENT: Capabilities = Capabilities {
+        revision: 7,
+        flags: Self::UNPUBLISHED
+            | Self::TIMESTAMPS
+            | Self::ZSTD
+            | Self::HEARTBEAT
+            | Self::FILTER
+            | Self::MULTICAST
+            | Self::BATCH_ID,
+    };
+
+    /// The capabilities shared by both peers
+    pub fn negotiate(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            revision: min(self.revision, other.revision),
+            flags: self.flags & other.flags,
+        }
+    }
+
+    pub fn has(&self, flag: u64) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// These capabilities without `flag`
+    pub fn without(&self, flag: u64) -> Capabilities {
+        Capabilities { revision: self.revision, flags: self.flags & !flag }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Pack)]
 pub enum Hello {
     /// No authentication will be provided. The publisher may drop
     /// the connection at this point, if it chooses to allow this
     /// then it will return Anonymous.
-    Anonymous,
+    Anonymous(#[pack(default)] Capabilities),
     /// Authenticate using kerberos 5, following the hello, the
     /// subscriber and publisher will exchange tokens to complete the
     /// authentication.
-    Krb5(#[pack(default)] Option<UserInfo>),
+    Krb5(#[pack(default)] Option<UserInfo>, #[pack(default)] Capabilities),
     /// Authenticate using a local unix socket, only valid for
     /// publishers on the same machine as the subscriber.
-    Local(#[pack(default)] Option<UserInfo>),
+    Local(#[pack(default)] Option<UserInfo>, #[pack(default)] Capabilities),
     /// In order to prevent denial of service, spoofing, etc,
     /// authenticated publishers must prove that they are actually
     /// listening on the socket they claim to be listening on. To
@@ -36,7 +110,27 @@ pub enum Hello {
     /// Authenticate using transport layer security. In this case both
     /// the server AND the client must have certificates that are
     /// signed by a CA they mutually trust.
-    Tls(#[pack(default)] Option<UserInfo>),
+    Tls(#[pack(default)] Option<UserInfo>, #[pack(default)] Capabilities),
+}
+
+/// Limits the updates a publisher sends for a subscription. The
+/// default filter passes every update.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Pack)]
+pub struct UpdateFilter {
+    /// Send at most one update per interval. Updates that arrive
+    /// before the interval has elapsed are held back, and only the
+    /// most recent one is sent once it has.
+    pub min_interval: Option<Duration>,
+    /// Only send an update if the value changed. Numbers are only
+    /// considered changed if they moved by more than epsilon.
+    pub on_change: Option<f64>,
+}
+
+impl UpdateFilter {
+    /// true if the filter passes every update
+    pub fn is_empty(&self) -> bool {
+        self.min_interval.is_none() && self.on_change.is_none()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Pack)]
@@ -59,6 +153,18 @@ pub enum To {
     Unsubscribe(Id),
     /// Send a write to the specified value.
     Write(Id, bool, Value),
+    /// Indicates that the subscriber is idle, but still
+    /// functioning correctly. Only sent to publishers that advertise
+    /// the HEARTBEAT capability.
+    Heartbeat,
+    /// Replace the update filter of the specified subscription. Only
+    /// sent to publishers that advertise the FILTER capability.
+    Filter(Id, UpdateFilter),
+    /// Accept, if true, or give up, if false, delivery of updates to
+    /// the specified subscription by multicast after a
+    /// `From::Multicast` offer. Only sent to publishers that
+    /// advertise the MULTICAST capability.
+    Multicast(Id, bool),
 }
 
 #[derive(Debug, Clone, PartialEq, Pack)]
@@ -85,4 +191,44 @@ pub enum From {
     Heartbeat,
     /// Indicates the result of a write request
     WriteResult(Id, Value),
+    /// The publisher intentionally stopped publishing Id, and you
+    /// have been unsubscribed from it. Value is the terminal value
+    /// set by the publisher, or the last value if none was set. This
+    /// is not sent if the publisher is shutting down.
+    Unpublished(Id, Value),
+    /// The time, according to the publisher's clock, at which the
+    /// updates that follow were committed. It applies to every
+    /// update until the next timestamp. This is only sent if the
+    /// publisher has timestamps enabled, and the subscriber
+    /// advertised the TIMESTAMPS capability.
+    Timestamp(DateTime<Utc>),
+    /// An offer to deliver updates to Id by multicast. Updates are
+    /// sent to `group` in `Datagram`s tagged with `stream`, and the
+    /// current value of every multicast subscription is sent again
+    /// at least every `snapshot`. Updates keep arriving by unicast
+    /// until the offer is accepted with `To::Multicast`. Only sent
+    /// to anonymous subscribers that advertise the MULTICAST
+    /// capability, since datagrams aren't authenticated, and
+    /// subscribers ignore offers on authenticated connections.
+    Multicast { id: Id, group: SocketAddr, stream: u64, snapshot: Duration },
+    /// The updates since the last `BatchEnd` were committed together
+    /// by the publisher as batch `u64`. Batch ids increase with every
+    /// batch the publisher commits, so a subscriber may not see every
+    /// id. Only sent to subscribers that advertise the BATCH_ID
+    /// capability.
+    BatchEnd(u64),
+}
+
+/// The payload of a multicast datagram. `seq` increases by one with
+/// every datagram sent on `stream`, and `timestamp` is set if the
+/// publisher has timestamps enabled. A snapshot carries the current
+/// values of all multicast subscriptions, which only need to be
+/// delivered if they differ from the last value received.
+#[derive(Debug, Clone, PartialEq, Pack)]
+pub struct Datagram {
+    pub stream: u64,
+    pub seq: u64,
+    pub snapshot: bool,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub updates: Vec<(Id, Value)>,
 }
@@ -1,4 +1,4 @@
-use crate::glob::GlobSet;
+use crate::{glob::GlobSet, value::Value};
 use arcstr::ArcStr;
 use bytes::{Buf, BufMut, Bytes};
 use smallvec::SmallVec;
@@ -53,6 +53,20 @@ pub enum AuthWrite {
 pub struct ClientHelloWrite {
     pub write_addr: SocketAddr,
     pub auth: AuthWrite,
+    /// The unix domain socket that subscribers on the same machine
+    /// may use instead of `write_addr`
+    #[pack(default)]
+    pub local_endpoint: Option<ArcStr>,
+    /// Additional addresses, usually of the other address family,
+    /// that the publisher at `write_addr` is also listening on
+    #[pack(default)]
+    pub alt_addrs: SmallVec<[SocketAddr; 2]>,
+    /// The publisher will keep this connection open and send
+    /// `ToWrite::Heartbeat` on it to refresh its ttl, instead of
+    /// reconnecting. Only used if the resolver also sets
+    /// `ServerHelloWrite::heartbeat`.
+    #[pack(default)]
+    pub heartbeat: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
@@ -75,6 +89,11 @@ pub struct ServerHelloWrite {
     pub ttl_expired: bool,
     pub auth: AuthWrite,
     pub resolver_id: SocketAddr,
+    /// The resolver answers every `ToWrite::Heartbeat` sent by a
+    /// publisher that set `ClientHelloWrite::heartbeat` with
+    /// `FromWrite::Heartbeat`.
+    #[pack(default)]
+    pub heartbeat: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
@@ -112,6 +131,44 @@ pub enum ToRead {
     ListMatching(GlobSet),
     /// Get the change nr for the specified path
     GetChangeNr(Path),
+    /// List the paths published under the specified root path along
+    /// with metadata about each one
+    ListWithMeta(Path),
+    /// Push a `Changed` message whenever something is published or
+    /// unpublished under the specified path. There is no reply, the
+    /// first `Changed` is sent immediatly, so this should only be
+    /// sent on a connection dedicated to watching.
+    Watch(Path),
+    /// An administrative request. Requires the admin permission on
+    /// the root of the server's namespace. It is answered by the
+    /// member server that receives it, not the whole cluster, and it
+    /// should be sent alone on a dedicated connection.
+    Admin(ToAdmin),
+    /// List the paths, including defaults, published by the
+    /// publisher at the specified address. Paths you don't have
+    /// list permission on are left out.
+    ResolvedBy(SocketAddr),
+    /// Get the metadata the publisher attached to the specified path
+    Metadata(Path),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub enum ToAdmin {
+    /// List the publishers connected to the server
+    Publishers,
+    /// Dump every path published on the server
+    Dump,
+    /// Unpublish everything published by the publisher at the
+    /// specified address and forget about it, e.g. because it is
+    /// dead and its entries would otherwise linger until they time
+    /// out.
+    ClearPublisher(SocketAddr),
+    /// Get statistics about each client connected to the server
+    ClientStats,
+    /// Reload the permissions and quotas from the server's config
+    /// file. Fails if the server wasn't started from a file, or if
+    /// the file can't be loaded.
+    Reload,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
@@ -173,6 +230,26 @@ pub struct Publisher {
     pub target_auth: TargetAuth,
     #[pack(default)]
     pub user_info: Option<UserInfo>,
+    #[pack(default)]
+    pub local_endpoint: Option<ArcStr>,
+    /// Every address the publisher is listening on, including
+    /// `addr`, ordered by preference for the subscriber that asked.
+    /// Empty if the resolver server doesn't support multiple
+    /// addresses.
+    #[pack(default)]
+    pub addrs: SmallVec<[SocketAddr; 2]>,
+}
+
+impl Publisher {
+    /// The addresses to try, in order, when connecting to the
+    /// publisher.
+    pub fn connect_addrs(&self) -> &[SocketAddr] {
+        if self.addrs.is_empty() {
+            std::slice::from_ref(&self.addr)
+        } else {
+            &self.addrs
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
@@ -213,6 +290,10 @@ impl PartialEq for Referral {
 
 impl Eq for Referral {}
 
+/// User defined key value pairs describing a published path,
+/// e.g. its units, a description, or a display precision.
+pub type Metadata = Pooled<Vec<(Chars, Value)>>;
+
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
 pub struct Table {
     pub rows: Pooled<Vec<Path>>,
@@ -225,6 +306,62 @@ pub struct ListMatching {
     pub referrals: Pooled<Vec<Referral>>,
 }
 
+/// A child path returned by `ToRead::ListWithMeta`
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct ListEntry {
+    pub path: Path,
+    /// The number of publishers, including default publishers,
+    /// publishing exactly this path
+    pub publishers: u32,
+    /// true if the path has children, false if it is a leaf
+    pub has_children: bool,
+    /// The flags the path was published with
+    pub flags: u32,
+    /// The permissions the requesting user has on the path
+    pub permissions: u32,
+}
+
+/// One published path, as returned by `ToAdmin::Dump`
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct StoreEntry {
+    pub path: Path,
+    /// The address of the publisher
+    pub publisher: SocketAddr,
+    /// true if this is a default publisher
+    pub default: bool,
+    /// The flags the path was published with
+    pub flags: u32,
+}
+
+/// A client connected to a resolver server, as returned by
+/// `ToAdmin::ClientStats`
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct ClientStats {
+    pub addr: SocketAddr,
+    /// The authenticated user, if any
+    pub user: Option<ArcStr>,
+    /// true if the client is a publisher
+    pub publisher: bool,
+    /// When the client connected, in seconds since the unix epoch
+    pub connected: u64,
+    /// The number of batches received from the client
+    pub batches: u64,
+    /// The number of messages received from the client
+    pub messages: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub enum FromAdmin {
+    Publishers(Pooled<Vec<Publisher>>),
+    Dump(Pooled<Vec<StoreEntry>>),
+    /// The reply to `ClearPublisher`, false if the server didn't
+    /// know about the publisher
+    Cleared(bool),
+    ClientStats(Pooled<Vec<ClientStats>>),
+    /// The reply to `Reload`
+    Reloaded,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
 pub struct GetChangeNr {
     pub change_number: Z64,
@@ -243,6 +380,19 @@ pub enum FromRead {
     Error(Chars),
     ListMatching(ListMatching),
     GetChangeNr(GetChangeNr),
+    /// The server is shutting down, reconnect to the specified
+    /// resolver server. This is sent unsolicited, and does not count
+    /// as a reply to any request.
+    Migrate(SocketAddr),
+    ListWithMeta(Pooled<Vec<ListEntry>>),
+    /// Something was published or unpublished under the watched
+    /// path. This is sent unsolicited, and does not count as a reply
+    /// to any request.
+    Changed(Path),
+    Admin(FromAdmin),
+    /// The metadata of a path, empty if the path has none or is not
+    /// published
+    Metadata(Metadata),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Pack)]
@@ -263,6 +413,12 @@ pub enum ToWrite {
     PublishDefaultWithFlags(Path, u32),
     /// Unpublish a default publisher
     UnpublishDefault(Path),
+    /// Publish the path, set associated flags if any, and replace
+    /// it's metadata
+    PublishWithMetadata(Path, Option<u32>, Metadata),
+    /// Stop publishing everything you've published at or below the
+    /// path, including default publishers
+    UnpublishPrefix(Path),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Pack)]
@@ -272,4 +428,12 @@ pub enum FromWrite {
     Referral(Referral),
     Denied,
     Error(Chars),
+    /// The server is shutting down, reconnect to the specified
+    /// resolver server. This is sent unsolicited, and does not count
+    /// as a reply to any request.
+    Migrate(SocketAddr),
+    /// Acknowledge a heartbeat. This is only sent if both sides
+    /// agreed to heartbeats in the hello, and does not count as a
+    /// reply to any request.
+    Heartbeat,
 }
@@ -64,13 +64,16 @@ mod resolver {
         glob::{Glob, GlobSet},
         resolver::{
             Auth, AuthChallenge, AuthRead, AuthWrite, ClientHello, ClientHelloWrite,
-            FromRead, FromWrite, GetChangeNr, HashMethod, ListMatching, Publisher,
-            PublisherId, PublisherRef, ReadyForOwnershipCheck, Referral, Resolved,
-            Secret, ServerHelloWrite, Table, TargetAuth, ToRead, ToWrite,
+            ClientStats, FromAdmin, FromRead, FromWrite, GetChangeNr, HashMethod,
+            ListEntry, ListMatching, Metadata, Publisher, PublisherId, PublisherRef,
+            ReadyForOwnershipCheck, Referral, Resolved, Secret, ServerHelloWrite,
+            StoreEntry, Table, TargetAuth, ToAdmin, ToRead, ToWrite,
         },
+        value::Value,
     };
     use netidx_core::pack::PackError;
     use proptest::collection;
+    use smallvec::SmallVec;
     use std::net::SocketAddr;
 
     fn fuzz(b: Bytes) {
@@ -85,6 +88,7 @@ mod resolver {
         let _: Result<FromWrite> = Pack::decode(&mut &*b);
         let _: Result<GetChangeNr> = Pack::decode(&mut &*b);
         let _: Result<HashMethod> = Pack::decode(&mut &*b);
+        let _: Result<ListEntry> = Pack::decode(&mut &*b);
         let _: Result<ListMatching> = Pack::decode(&mut &*b);
         let _: Result<Publisher> = Pack::decode(&mut &*b);
         let _: Result<PublisherId> = Pack::decode(&mut &*b);
@@ -133,9 +137,29 @@ mod resolver {
         ]
     }
 
+    fn socket_addrs() -> impl Strategy<Value = SmallVec<[SocketAddr; 2]>> {
+        collection::vec(any::<SocketAddr>(), (0, 4)).prop_map(SmallVec::from_vec)
+    }
+
     fn client_hello_write() -> impl Strategy<Value = ClientHelloWrite> {
-        (any::<SocketAddr>(), auth_write())
-            .prop_map(|(write_addr, auth)| ClientHelloWrite { write_addr, auth })
+        (
+            any::<SocketAddr>(),
+            auth_write(),
+            option(arcstr()),
+            socket_addrs(),
+            any::<bool>(),
+        )
+            .prop_map(
+                |(write_addr, auth, local_endpoint, alt_addrs, heartbeat)| {
+                    ClientHelloWrite {
+                        write_addr,
+                        auth,
+                        local_endpoint,
+                        alt_addrs,
+                        heartbeat,
+                    }
+                },
+            )
     }
 
     fn client_hello() -> impl Strategy<Value = ClientHello> {
@@ -146,14 +170,10 @@ mod resolver {
     }
 
     fn server_hello_write() -> impl Strategy<Value = ServerHelloWrite> {
-        (any::<u64>(), any::<bool>(), any::<SocketAddr>(), auth_write()).prop_map(
-            |(ttl, ttl_expired, resolver_id, auth)| ServerHelloWrite {
-                ttl,
-                ttl_expired,
-                auth,
-                resolver_id,
-            },
-        )
+        (any::<u64>(), any::<bool>(), any::<SocketAddr>(), auth_write(), any::<bool>())
+            .prop_map(|(ttl, ttl_expired, resolver_id, auth, heartbeat)| {
+                ServerHelloWrite { ttl, ttl_expired, auth, resolver_id, heartbeat }
+            })
     }
 
     fn glob() -> impl Strategy<Value = Glob> {
@@ -168,6 +188,16 @@ mod resolver {
         })
     }
 
+    fn to_admin() -> impl Strategy<Value = ToAdmin> {
+        prop_oneof![
+            Just(ToAdmin::Publishers),
+            Just(ToAdmin::Dump),
+            any::<SocketAddr>().prop_map(ToAdmin::ClearPublisher),
+            Just(ToAdmin::ClientStats),
+            Just(ToAdmin::Reload),
+        ]
+    }
+
     fn to_read() -> impl Strategy<Value = ToRead> {
         prop_oneof![
             path().prop_map(ToRead::Resolve),
@@ -175,6 +205,11 @@ mod resolver {
             path().prop_map(ToRead::Table),
             globset().prop_map(ToRead::ListMatching),
             path().prop_map(ToRead::GetChangeNr),
+            path().prop_map(ToRead::ListWithMeta),
+            path().prop_map(ToRead::Watch),
+            to_admin().prop_map(ToRead::Admin),
+            any::<SocketAddr>().prop_map(ToRead::ResolvedBy),
+            path().prop_map(ToRead::Metadata),
         ]
     }
 
@@ -193,16 +228,32 @@ mod resolver {
         let hash_method = hash_method();
         let target_auth = target_auth();
         let user_info = option(user_info());
-        (resolver, id, addr, hash_method, target_auth, user_info).prop_map(
-            |(resolver, id, addr, hash_method, target_auth, user_info)| Publisher {
-                resolver,
-                id,
-                addr,
-                hash_method,
-                target_auth,
-                user_info,
-            },
-        )
+        let local_endpoint = option(arcstr());
+        let addrs = socket_addrs();
+        (resolver, id, addr, hash_method, target_auth, user_info, local_endpoint, addrs)
+            .prop_map(
+                |(
+                    resolver,
+                    id,
+                    addr,
+                    hash_method,
+                    target_auth,
+                    user_info,
+                    local_endpoint,
+                    addrs,
+                )| {
+                    Publisher {
+                        resolver,
+                        id,
+                        addr,
+                        hash_method,
+                        target_auth,
+                        user_info,
+                        local_endpoint,
+                        addrs,
+                    }
+                },
+            )
     }
 
     fn publisher_ref() -> impl Strategy<Value = PublisherRef> {
@@ -270,6 +321,18 @@ mod resolver {
             .prop_map(|(matched, referrals)| ListMatching { matched, referrals })
     }
 
+    fn list_entry() -> impl Strategy<Value = ListEntry> {
+        (path(), any::<u32>(), any::<bool>(), any::<u32>(), any::<u32>()).prop_map(
+            |(path, publishers, has_children, flags, permissions)| ListEntry {
+                path,
+                publishers,
+                has_children,
+                flags,
+                permissions,
+            },
+        )
+    }
+
     fn get_change_nr() -> impl Strategy<Value = GetChangeNr> {
         let change_number = any::<u64>().prop_map(|v| Z64(v));
         let resolver = any::<SocketAddr>();
@@ -283,6 +346,44 @@ mod resolver {
         )
     }
 
+    fn store_entry() -> impl Strategy<Value = StoreEntry> {
+        (path(), any::<SocketAddr>(), any::<bool>(), any::<u32>()).prop_map(
+            |(path, publisher, default, flags)| StoreEntry {
+                path,
+                publisher,
+                default,
+                flags,
+            },
+        )
+    }
+
+    fn client_stats() -> impl Strategy<Value = ClientStats> {
+        (
+            any::<SocketAddr>(),
+            option(arcstr()),
+            any::<bool>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<u64>(),
+        )
+            .prop_map(|(addr, user, publisher, connected, batches, messages)| {
+                ClientStats { addr, user, publisher, connected, batches, messages }
+            })
+    }
+
+    fn from_admin() -> impl Strategy<Value = FromAdmin> {
+        prop_oneof![
+            collection::vec(publisher(), (0, 100))
+                .prop_map(|v| FromAdmin::Publishers(Pooled::orphan(v))),
+            collection::vec(store_entry(), (0, 1000))
+                .prop_map(|v| FromAdmin::Dump(Pooled::orphan(v))),
+            any::<bool>().prop_map(FromAdmin::Cleared),
+            collection::vec(client_stats(), (0, 100))
+                .prop_map(|v| FromAdmin::ClientStats(Pooled::orphan(v))),
+            Just(FromAdmin::Reloaded),
+        ]
+    }
+
     fn from_read() -> impl Strategy<Value = FromRead> {
         prop_oneof![
             publisher().prop_map(FromRead::Publisher),
@@ -294,7 +395,13 @@ mod resolver {
             table().prop_map(FromRead::Table),
             referral().prop_map(FromRead::Referral),
             Just(FromRead::Denied),
-            chars().prop_map(FromRead::Error)
+            chars().prop_map(FromRead::Error),
+            any::<SocketAddr>().prop_map(FromRead::Migrate),
+            collection::vec(list_entry(), (0, 1000))
+                .prop_map(|v| FromRead::ListWithMeta(Pooled::orphan(v))),
+            path().prop_map(FromRead::Changed),
+            from_admin().prop_map(FromRead::Admin),
+            metadata().prop_map(FromRead::Metadata)
         ]
     }
 
@@ -306,6 +413,20 @@ mod resolver {
         any::<u8>().prop_map(|_| ReadyForOwnershipCheck)
     }
 
+    fn metadata() -> impl Strategy<Value = Metadata> {
+        collection::vec(
+            (
+                chars(),
+                prop_oneof![
+                    any::<i64>().prop_map(Value::I64),
+                    chars().prop_map(Value::String)
+                ],
+            ),
+            (0, 10),
+        )
+        .prop_map(Pooled::orphan)
+    }
+
     fn to_write() -> impl Strategy<Value = ToWrite> {
         prop_oneof![
             path().prop_map(ToWrite::Publish),
@@ -317,7 +438,11 @@ mod resolver {
                 .prop_map(|(path, flags)| ToWrite::PublishWithFlags(path, flags)),
             (path(), any::<u32>())
                 .prop_map(|(path, flags)| ToWrite::PublishDefaultWithFlags(path, flags)),
-            path().prop_map(ToWrite::UnpublishDefault)
+            path().prop_map(ToWrite::UnpublishDefault),
+            (path(), any::<Option<u32>>(), metadata()).prop_map(|(path, flags, meta)| {
+                ToWrite::PublishWithMetadata(path, flags, meta)
+            }),
+            path().prop_map(ToWrite::UnpublishPrefix)
         ]
     }
 
@@ -327,7 +452,9 @@ mod resolver {
             Just(FromWrite::Unpublished),
             referral().prop_map(FromWrite::Referral),
             Just(FromWrite::Denied),
-            chars().prop_map(FromWrite::Error)
+            chars().prop_map(FromWrite::Error),
+            any::<SocketAddr>().prop_map(FromWrite::Migrate),
+            Just(FromWrite::Heartbeat)
         ]
     }
 
@@ -387,11 +514,12 @@ mod resolver {
 mod publisher {
     use super::*;
     use crate::{
-        publisher::{From, Hello, Id, To},
-        value::Value,
+        publisher::{Capabilities, Datagram, From, Hello, Id, To, UpdateFilter},
+        value::{Typ, Value},
     };
     use chrono::prelude::*;
     use netidx_core::pack::PackError;
+    use netidx_derive::Pack;
     use proptest::collection;
     use std::{net::SocketAddr, time::Duration};
 
@@ -404,16 +532,57 @@ mod publisher {
         let _: Result<Value> = Pack::decode(&mut &*b);
     }
 
+    fn capabilities() -> impl Strategy<Value = Capabilities> {
+        (any::<u32>(), any::<u64>())
+            .prop_map(|(revision, flags)| Capabilities { revision, flags })
+    }
+
+    /// The hello as it was before capabilities were added
+    #[derive(Debug, Clone, PartialEq, Pack)]
+    enum HelloV3 {
+        Anonymous,
+        Krb5(#[pack(default)] Option<UserInfo>),
+        Local(#[pack(default)] Option<UserInfo>),
+        ResolverAuthenticate(SocketAddr),
+        Tls(#[pack(default)] Option<UserInfo>),
+    }
+
+    fn convert<T: Pack, U: Pack>(t: &T) -> U {
+        let mut b = BytesMut::new();
+        t.encode(&mut b).unwrap();
+        U::decode(&mut b.freeze()).unwrap()
+    }
+
+    #[test]
+    fn test_hello_compat() {
+        let caps = Capabilities::CURRENT;
+        let h: HelloV3 = convert(&Hello::Anonymous(caps));
+        assert_eq!(h, HelloV3::Anonymous);
+        let h: HelloV3 = convert(&Hello::Local(None, caps));
+        assert_eq!(h, HelloV3::Local(None));
+        let h: Hello = convert(&HelloV3::Anonymous);
+        assert_eq!(h, Hello::Anonymous(Capabilities::default()));
+        let h: Hello = convert(&HelloV3::Tls(None));
+        assert_eq!(h, Hello::Tls(None, Capabilities::default()));
+        assert!(!Capabilities::default().negotiate(&caps).has(Capabilities::UNPUBLISHED));
+    }
+
     fn hello() -> impl Strategy<Value = Hello> {
         prop_oneof![
-            Just(Hello::Anonymous),
-            option(user_info()).prop_map(Hello::Krb5),
-            option(user_info()).prop_map(Hello::Local),
-            option(user_info()).prop_map(Hello::Tls),
+            capabilities().prop_map(Hello::Anonymous),
+            (option(user_info()), capabilities()).prop_map(|(u, c)| Hello::Krb5(u, c)),
+            (option(user_info()), capabilities()).prop_map(|(u, c)| Hello::Local(u, c)),
+            (option(user_info()), capabilities()).prop_map(|(u, c)| Hello::Tls(u, c)),
             any::<SocketAddr>().prop_map(Hello::ResolverAuthenticate)
         ]
     }
 
+    fn update_filter() -> impl Strategy<Value = UpdateFilter> {
+        (option(duration()), option(0f64..1e6)).prop_map(|(min_interval, on_change)| {
+            UpdateFilter { min_interval, on_change }
+        })
+    }
+
     fn to() -> impl Strategy<Value = To> {
         prop_oneof![
             (path(), any::<SocketAddr>(), any::<u64>(), any::<u32>(), bytes()).prop_map(
@@ -430,7 +599,10 @@ mod publisher {
                 Id::mk(i),
                 r,
                 v
-            ))
+            )),
+            Just(To::Heartbeat),
+            (any::<u64>(), update_filter()).prop_map(|(i, f)| To::Filter(Id::mk(i), f)),
+            (any::<u64>(), any::<bool>()).prop_map(|(i, b)| To::Multicast(Id::mk(i), b))
         ]
     }
 
@@ -487,10 +659,38 @@ mod publisher {
             )),
             (any::<u64>(), value()).prop_map(|(i, v)| From::Update(Id::mk(i), v)),
             Just(From::Heartbeat),
-            (any::<u64>(), value()).prop_map(|(i, v)| From::WriteResult(Id::mk(i), v))
+            (any::<u64>(), value()).prop_map(|(i, v)| From::WriteResult(Id::mk(i), v)),
+            (any::<u64>(), value()).prop_map(|(i, v)| From::Unpublished(Id::mk(i), v)),
+            datetime().prop_map(From::Timestamp),
+            (any::<u64>(), any::<SocketAddr>(), any::<u64>(), duration()).prop_map(
+                |(i, group, stream, snapshot)| From::Multicast {
+                    id: Id::mk(i),
+                    group,
+                    stream,
+                    snapshot
+                }
+            ),
+            any::<u64>().prop_map(From::BatchEnd)
         ]
     }
 
+    fn datagram() -> impl Strategy<Value = Datagram> {
+        (
+            any::<u64>(),
+            any::<u64>(),
+            any::<bool>(),
+            option(datetime()),
+            collection::vec((any::<u64>(), value()), 0..10),
+        )
+            .prop_map(|(stream, seq, snapshot, timestamp, updates)| Datagram {
+                stream,
+                seq,
+                snapshot,
+                timestamp,
+                updates: updates.into_iter().map(|(i, v)| (Id::mk(i), v)).collect(),
+            })
+    }
+
     fn vequiv(v0: &Value, v1: &Value) -> bool {
         match (v0, v1) {
             (Value::Duration(d0), Value::Duration(d1)) => {
@@ -514,6 +714,46 @@ mod publisher {
         assert!(vequiv(&v, &v_))
     }
 
+    fn cast_round_trip(v: Value) {
+        for typ in Typ::all() {
+            let _ = v.clone().cast(*typ);
+        }
+        let typ = Typ::get(&v);
+        let s = v.clone().cast(Typ::String).unwrap();
+        assert!(vequiv(&v, &s.cast(typ).unwrap()));
+    }
+
+    #[test]
+    fn test_cast() {
+        for typ in Typ::all().iter().filter(|t| t.number()) {
+            let one = Value::True.cast(*typ).unwrap();
+            let zero = Value::False.cast(*typ).unwrap();
+            assert_eq!(one.cast(Typ::Bool), Some(Value::True));
+            assert_eq!(zero.cast(Typ::Bool), Some(Value::False));
+        }
+        assert_eq!(Value::F64(0.5).cast(Typ::Bool), Some(Value::True));
+        assert_eq!(Value::I64(-1).cast(Typ::Bool), Some(Value::True));
+        assert_eq!(
+            Value::from("aGVsbG8=").cast(Typ::Bytes),
+            Some(Value::Bytes(Bytes::from_static(b"hello")))
+        );
+        assert_eq!(Value::U64(42).cast(Typ::Bytes), None);
+        assert_eq!(Value::Bytes(Bytes::from_static(b"hello")).cast(Typ::U64), None);
+        let ts = Utc.timestamp_opt(1622505600, 500_000_000).unwrap();
+        assert_eq!(
+            Value::from("2021-06-01T00:00:00.5Z").cast(Typ::DateTime),
+            Some(Value::DateTime(ts))
+        );
+        assert_eq!(
+            Value::F64(1622505600.5).cast(Typ::DateTime),
+            Some(Value::DateTime(ts))
+        );
+        assert_eq!(Value::DateTime(ts).cast(Typ::F64), Some(Value::F64(1622505600.5)));
+        assert_eq!(Value::I64(-1).cast(Typ::Duration), None);
+        let e = Value::Error(Chars::from("e"));
+        assert_eq!(e.clone().cast(Typ::Result), Some(e));
+    }
+
     proptest! {
         #[test]
         fn test_fuzz(b in bytes()) {
@@ -535,9 +775,74 @@ mod publisher {
             check(a)
         }
 
+        #[test]
+        fn test_datagram(a in datagram()) {
+            check(a)
+        }
+
         #[test]
         fn test_value_roundtrip(v in value()) {
             round_trip(v)
         }
+
+        #[test]
+        fn test_value_cast_roundtrip(v in value()) {
+            cast_round_trip(v)
+        }
+    }
+}
+
+mod from_value {
+    use crate::value::{FromValue, Value};
+    use netidx_core::chars::Chars;
+    use netidx_derive::FromValue;
+
+    #[derive(Debug, PartialEq, FromValue)]
+    struct Point {
+        x: f64,
+        y: f64,
+        label: Option<Chars>,
+    }
+
+    #[derive(Debug, PartialEq, FromValue)]
+    struct Pair(u32, Chars);
+
+    #[derive(Debug, PartialEq, FromValue)]
+    struct Meters(f64);
+
+    fn kv(k: &'static str, v: Value) -> Value {
+        Value::from(vec![Value::from(k), v])
+    }
+
+    #[test]
+    fn test_named_struct() {
+        let v = Value::from(vec![
+            kv("y", Value::F64(2.)),
+            kv("x", Value::I64(1)),
+            kv("unknown", Value::Null),
+        ]);
+        let p = Point::from_value(v).unwrap();
+        assert_eq!(p, Point { x: 1., y: 2., label: None });
+        let v = Value::from(vec![
+            kv("x", Value::F64(1.)),
+            kv("y", Value::F64(2.)),
+            kv("label", Value::from("a")),
+        ]);
+        let p = Point::from_value(v).unwrap();
+        assert_eq!(p, Point { x: 1., y: 2., label: Some(Chars::from("a")) });
+        // named structs are only read from maps
+        let v = Value::from(vec![Value::F64(1.), Value::F64(2.), Value::from("a")]);
+        assert!(Point::from_value(v).is_err());
+        let v = Value::from(vec![kv("x", Value::F64(1.))]);
+        assert!(Point::from_value(v).is_err());
+        assert!(Point::from_value(Value::F64(1.)).is_err());
+    }
+
+    #[test]
+    fn test_tuple_struct() {
+        let v = Value::from(vec![Value::U32(42), Value::from("foo")]);
+        assert_eq!(Pair::from_value(v).unwrap(), Pair(42, Chars::from("foo")));
+        assert!(Pair::from_value(Value::from(vec![Value::U32(42)])).is_err());
+        assert_eq!(Meters::from_value(Value::I32(3)).unwrap(), Meters(3.));
     }
 }
@@ -2,7 +2,7 @@ use anyhow::{bail, Result as Res};
 use arcstr::ArcStr;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bytes::{Buf, BufMut, Bytes};
-use chrono::{naive::NaiveDateTime, prelude::*};
+use chrono::prelude::*;
 use fxhash::FxHashMap;
 use indexmap::{IndexMap, IndexSet};
 use netidx_core::{
@@ -1075,6 +1075,47 @@ pub trait FromValue {
     }
 }
 
+/// Split a struct value into the values of its fields, in the order
+/// given by `names`. The struct must be encoded as a map, `[[<name>,
+/// <value>], ...]`. Missing fields are null and unknown fields are
+/// ignored. This is used by the `FromValue` derive macro.
+#[doc(hidden)]
+pub fn struct_fields<const N: usize>(v: Value, names: [&str; N]) -> Res<[Value; N]> {
+    let elts = match v {
+        Value::Array(elts) => elts,
+        v => bail!("expected a map, got {}", v),
+    };
+    let mut fields: [Value; N] = std::array::from_fn(|_| Value::Null);
+    for kv in elts.iter() {
+        match kv {
+            Value::Array(kv) if kv.len() == 2 => match &kv[0] {
+                Value::String(k) => {
+                    if let Some(i) = names.iter().position(|n| *n == &**k) {
+                        fields[i] = kv[1].clone();
+                    }
+                }
+                k => bail!("expected a string key, got {}", k),
+            },
+            kv => bail!("expected a [key, value] pair, got {}", kv),
+        }
+    }
+    Ok(fields)
+}
+
+/// Split an array of exactly `N` elements into its elements. This is
+/// used by the `FromValue` derive macro.
+#[doc(hidden)]
+pub fn tuple_fields<const N: usize>(v: Value) -> Res<[Value; N]> {
+    match v {
+        Value::Array(elts) if elts.len() == N => {
+            let elts = elts.iter().cloned().collect::<Vec<_>>();
+            Ok(<[Value; N]>::try_from(elts).unwrap())
+        }
+        Value::Array(elts) => bail!("expected {} fields, got {}", N, elts.len()),
+        v => bail!("expected an array, got {}", v),
+    }
+}
+
 impl Value {
     pub fn to_string_naked(&self) -> String {
         struct WVal<'a>(&'a Value);
@@ -1250,7 +1291,29 @@ impl Value {
         }
     }
 
-    /// Whatever value is attempt to turn it into the type specified
+    /// Whatever value is attempt to turn it into the type specified.
+    ///
+    /// - values cast to string in the same typed format as
+    ///   `Display`, e.g. bytes are written as base64 with a bytes:
+    ///   prefix.
+    /// - strings are parsed, first as the bare representation of
+    ///   `typ` (see `Typ::parse`, e.g. plain base64 for bytes), then
+    ///   as a typed value literal, which is then cast. So every value
+    ///   cast to a string can be cast back to its original type.
+    /// - bytes only cast to and from strings (as base64), no other
+    ///   value casts to bytes.
+    /// - bool casts to numbers as 0 or 1, and numbers cast to bool as
+    ///   true if they are not zero. Note this includes negative
+    ///   numbers, which used to cast to false.
+    /// - numbers cast to datetime as seconds since the epoch, and to
+    ///   duration as seconds, fractions included.
+    /// - arrays cast to a scalar type by casting their first element,
+    ///   everything else casts to array as a one element array.
+    /// - null only casts to null and string.
+    ///
+    /// None is returned if the value can't be represented in `typ`,
+    /// e.g. a negative number as a duration, or if the cast has no
+    /// meaning, e.g. a bool to a datetime.
     pub fn cast(self, typ: Typ) -> Option<Value> {
         macro_rules! cast_number {
             ($v:expr, $typ:expr) => {
@@ -1269,15 +1332,31 @@ impl Value {
                         Ok(d) => Some(Value::Decimal(d)),
                         Err(_) => None,
                     },
-                    Typ::DateTime => Some(Value::DateTime(DateTime::from_utc(
-                        NaiveDateTime::from_timestamp_opt($v as i64, 0)?,
-                        Utc,
-                    ))),
+                    Typ::DateTime => {
+                        let f = $v as f64;
+                        if !f.is_finite() {
+                            None
+                        } else {
+                            let secs = f.floor();
+                            let nsecs = ((f - secs) * 1e9) as u32;
+                            Some(Value::DateTime(DateTime::from_timestamp(
+                                secs as i64,
+                                nsecs,
+                            )?))
+                        }
+                    }
                     Typ::Duration => {
-                        Some(Value::Duration(Duration::from_secs($v as u64)))
+                        let f = $v as f64;
+                        if f >= 0. && f < u64::MAX as f64 {
+                            Some(Value::Duration(Duration::from_secs_f64(f)))
+                        } else {
+                            None
+                        }
                     }
                     Typ::Bool => {
-                        Some(if $v as i64 > 0 { Value::True } else { Value::False })
+                        let f = $v as f64;
+                        let b = f != 0. && !f.is_nan();
+                        Some(if b { Value::True } else { Value::False })
                     }
                     Typ::String => Some(Value::String(Chars::from(format!("{}", self)))),
                     Typ::Bytes => None,
@@ -1290,9 +1369,10 @@ impl Value {
             };
         }
         match self {
-            Value::String(s) if typ != Typ::String => {
-                s.parse::<Value>().ok().and_then(|v| v.cast(typ))
-            }
+            Value::String(s) if typ != Typ::String => match typ.parse(&s) {
+                Ok(v) if Typ::get(&v) == typ => Some(v),
+                Ok(_) | Err(_) => s.parse::<Value>().ok().and_then(|v| v.cast(typ)),
+            },
             v @ Value::String(_) => Some(v),
             v if typ == Typ::String => Some(Value::String(Chars::from(format!("{}", v)))),
             Value::Array(elts) if typ != Typ::Array => {
@@ -1317,19 +1397,21 @@ impl Value {
                 Typ::Z64 => v.try_into().ok().map(Value::Z64),
                 Typ::F32 => v.try_into().ok().map(Value::F32),
                 Typ::F64 => v.try_into().ok().map(Value::F64),
-                Typ::String => Some(Value::String(Chars::from(format!("{}", v)))),
-                Typ::Bool
-                | Typ::Array
-                | Typ::Bytes
-                | Typ::DateTime
-                | Typ::Duration
-                | Typ::Null
-                | Typ::Result => None,
+                Typ::DateTime | Typ::Duration => {
+                    let f: f64 = v.try_into().ok()?;
+                    Value::F64(f).cast(typ)
+                }
+                Typ::Bool => Some(if v.is_zero() { Value::False } else { Value::True }),
+                Typ::Bytes => None,
+                Typ::Result => Some(Value::Ok),
+                Typ::Array => Some(Value::Array(Arc::from(Vec::from([self])))),
+                Typ::Null => Some(Value::Null),
+                Typ::String => unreachable!(),
             },
             Value::DateTime(v) => match typ {
                 Typ::U32 | Typ::V32 => {
                     let ts = v.timestamp();
-                    if ts < 0 && ts > u32::MAX as i64 {
+                    if ts < 0 || ts > u32::MAX as i64 {
                         None
                     } else {
                         if typ == Typ::U32 {
@@ -1367,7 +1449,7 @@ impl Value {
                 Typ::Z64 => Some(Value::Z64(v.timestamp())),
                 Typ::F32 | Typ::F64 => {
                     let dur = v.timestamp() as f64;
-                    let dur = dur + (v.timestamp_nanos() / 1_000_000_000) as f64;
+                    let dur = dur + v.timestamp_subsec_nanos() as f64 / 1e9;
                     if typ == Typ::F32 {
                         Some(Value::F32(dur as f32))
                     } else {
@@ -1375,8 +1457,19 @@ impl Value {
                     }
                 }
                 Typ::DateTime => Some(Value::DateTime(v)),
-                Typ::Decimal => None,
-                Typ::Duration => None,
+                Typ::Decimal => {
+                    let nsecs = Decimal::new(v.timestamp_subsec_nanos() as i64, 9);
+                    Decimal::from(v.timestamp()).checked_add(nsecs).map(Value::Decimal)
+                }
+                Typ::Duration => {
+                    let ts = v.timestamp();
+                    if ts < 0 {
+                        None
+                    } else {
+                        let d = Duration::new(ts as u64, v.timestamp_subsec_nanos());
+                        Some(Value::Duration(d))
+                    }
+                }
                 Typ::Bool => None,
                 Typ::Bytes => None,
                 Typ::Result => Some(Value::Ok),
@@ -1395,8 +1488,17 @@ impl Value {
                 Typ::Z64 => Some(Value::Z64(d.as_secs() as i64)),
                 Typ::F32 => Some(Value::F32(d.as_secs_f32())),
                 Typ::F64 => Some(Value::F64(d.as_secs_f64())),
-                Typ::Decimal => None,
-                Typ::DateTime => None,
+                Typ::Decimal => {
+                    let nsecs = Decimal::new(d.subsec_nanos() as i64, 9);
+                    Decimal::from(d.as_secs()).checked_add(nsecs).map(Value::Decimal)
+                }
+                Typ::DateTime => {
+                    let secs = i64::try_from(d.as_secs()).ok()?;
+                    Some(Value::DateTime(DateTime::from_timestamp(
+                        secs,
+                        d.subsec_nanos(),
+                    )?))
+                }
                 Typ::Duration => Some(Value::Duration(d)),
                 Typ::Bool => None,
                 Typ::Bytes => None,
@@ -1418,7 +1520,7 @@ impl Value {
                     Typ::Z64 => Some(Value::Z64(b as i64)),
                     Typ::F32 => Some(Value::F32(b as u32 as f32)),
                     Typ::F64 => Some(Value::F64(b as u64 as f64)),
-                    Typ::Decimal => None,
+                    Typ::Decimal => Some(Value::Decimal(Decimal::from(b as u32))),
                     Typ::DateTime => None,
                     Typ::Duration => None,
                     Typ::Bool => Some(self),
@@ -1430,7 +1532,14 @@ impl Value {
                 }
             }
             Value::Bytes(_) if typ == Typ::Bytes => Some(self),
-            Value::Bytes(_) => None,
+            Value::Bytes(_) => match typ {
+                Typ::Result => Some(Value::Ok),
+                Typ::Array => Some(Value::Array(Arc::from(Vec::from([self])))),
+                Typ::Null => Some(Value::Null),
+                _ => None,
+            },
+            Value::Ok | Value::Error(_) if typ == Typ::Result => Some(self),
+            Value::Ok | Value::Error(_) if typ == Typ::Bytes => None,
             Value::Ok => Value::True.cast(typ),
             Value::Error(_) => Value::False.cast(typ),
             Value::Null if typ == Typ::Null => Some(self),
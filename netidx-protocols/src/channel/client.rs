@@ -36,7 +36,10 @@ impl Receiver {
                 for (_, ev) in batch.drain(..) {
                     match ev {
                         Event::Update(v) => self.queued.push_back(v),
-                        Event::Unsubscribed => dead.store(true, Ordering::Relaxed),
+                        Event::Unsubscribed | Event::Unpublished(_) => {
+                            dead.store(true, Ordering::Relaxed)
+                        }
+                        Event::Stale => (),
                     }
                 }
             }
@@ -144,7 +147,9 @@ impl Connection {
         let acceptor = subscriber.subscribe(path.clone());
         time::timeout(to, acceptor.wait_subscribed()).await??;
         match acceptor.last() {
-            Event::Unsubscribed => bail!("connect failed, unsubscribed after connect"),
+            Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => {
+                bail!("connect failed, unsubscribed after connect")
+            }
             Event::Update(Value::String(s)) if &*s == "connection" => {
                 Self::connect_singleton(subscriber, path).await
             }
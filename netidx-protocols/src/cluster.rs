@@ -1,22 +1,29 @@
 use anyhow::Result;
 use bytes::Bytes;
-use futures::{channel::mpsc, prelude::*};
+use chrono::prelude::*;
+use futures::{channel::mpsc, prelude::*, select_biased};
+use fxhash::FxHashMap;
 use log::{info, warn};
 use netidx::{
     pack::Pack,
     path::Path,
     pool::Pooled,
     publisher::{Publisher, Val, Value, WriteRequest},
-    resolver_client::ChangeTracker,
-    subscriber::{Dval, Event, Subscriber},
+    resolver_client::{ChangeTracker, ResolverError},
+    subscriber::{Dval, Event, SubId, Subscriber, UpdatesFlags},
     utils,
 };
 use std::{
     collections::{HashMap, HashSet},
     iter,
     marker::PhantomData,
+    time::Duration,
+};
+use tokio::{
+    sync::watch,
+    task::{self, JoinHandle},
+    time::{self, Instant},
 };
-use tokio::time;
 use uuid::Uuid;
 
 pub fn uuid_string(id: Uuid) -> String {
@@ -25,6 +32,18 @@ pub fn uuid_string(id: Uuid) -> String {
     Simple::from_uuid(id).encode_lower(&mut buf).into()
 }
 
+/// list the members under `base`, once the last member is gone the
+/// base path doesn't exist and there are no members.
+async fn list_members(subscriber: &Subscriber, base: Path) -> Result<Pooled<Vec<Path>>> {
+    match subscriber.resolver().list(base).await {
+        Ok(l) => Ok(l),
+        Err(e) => match e.downcast_ref::<ResolverError>() {
+            Some(ResolverError::NoSuchPath(_)) => Ok(Pooled::orphan(vec![])),
+            _ => Err(e),
+        },
+    }
+}
+
 /// Simple clustering based on netidx. Each member publishes a uuid to
 /// a common base path, which is used to discover all other
 /// members. Commands may be sent to and received from all other
@@ -101,7 +120,14 @@ impl<T: Pack> Cluster<T> {
 
     fn subscribed_others(&self) -> usize {
         self.others.len()
-            - self.others.values().filter(|d| d.last() == Event::Unsubscribed).count()
+            - self
+                .others
+                .values()
+                .filter(|d| match d.last() {
+                    Event::Unsubscribed | Event::Unpublished(_) => true,
+                    Event::Update(_) | Event::Stale => false,
+                })
+                .count()
     }
 
     pub fn others(&self) -> usize {
@@ -116,7 +142,7 @@ impl<T: Pack> Cluster<T> {
             Ok(false)
         } else {
             let path = self.ctrack.path().clone();
-            let mut l = self.subscriber.resolver().list(path).await?;
+            let mut l = list_members(&self.subscriber, path).await?;
             let all = l.drain(..).filter(|p| p != &self.our_path).collect::<HashSet<_>>();
             self.others.retain(|p, _| all.contains(p));
             for path in all {
@@ -164,7 +190,7 @@ impl<T: Pack> Cluster<T> {
         }
     }
 
-    /// Send a command to just one other, identified by it's path.
+    /// Send a command to just one other, identified by its path.
     pub fn send_cmd_to_one(&self, path: &Path, cmd: &T) {
         if let Some(other) = self.others.get(path) {
             let cmd = utils::pack(cmd).unwrap();
@@ -173,3 +199,389 @@ impl<T: Pack> Cluster<T> {
         }
     }
 }
+
+/// The number of heartbeats a member may miss before its lease
+/// expires and it is no longer considered part of the cluster.
+const LEASE_HEARTBEATS: u32 = 3;
+
+/// A member of a lease based cluster, as seen by observers and other
+/// members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberInfo {
+    /// The path the member publishes its heartbeat at
+    pub path: Path,
+    /// When the member joined the cluster
+    pub joined: DateTime<Utc>,
+    /// True if the member holds the primary role
+    pub primary: bool,
+}
+
+impl MemberInfo {
+    fn encode(&self, seq: u64) -> Value {
+        Value::from(vec![
+            Value::DateTime(self.joined),
+            Value::U64(seq),
+            Value::from(self.primary),
+        ])
+    }
+
+    fn decode(path: Path, v: &Value) -> Option<MemberInfo> {
+        match v {
+            Value::Array(a) => match &**a {
+                [Value::DateTime(joined), _, primary] => Some(MemberInfo {
+                    path,
+                    joined: *joined,
+                    primary: primary.clone().cast_to::<bool>().ok()?,
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// members that joined first are preferred as primary, ties are
+    /// broken by path.
+    fn seniority(&self) -> (DateTime<Utc>, &Path) {
+        (self.joined, &self.path)
+    }
+}
+
+/// The state of a lease based cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Membership {
+    /// The member currently holding the primary role, if any
+    pub primary: Option<Path>,
+    /// All the live members, ordered from the oldest to the newest
+    pub members: Vec<MemberInfo>,
+}
+
+impl Membership {
+    fn new(mut members: Vec<MemberInfo>) -> Self {
+        members.sort_by(|m0, m1| m0.seniority().cmp(&m1.seniority()));
+        let primary = members.iter().find(|m| m.primary).map(|m| m.path.clone());
+        Membership { primary, members }
+    }
+}
+
+/// Keeps track of the heartbeats of every member under a base path
+struct Tracker {
+    subscriber: Subscriber,
+    ctrack: ChangeTracker,
+    us: Option<Path>,
+    lease: Duration,
+    updates: mpsc::Sender<Pooled<Vec<(SubId, Event)>>>,
+    members: FxHashMap<Path, (Dval, Option<MemberInfo>)>,
+    by_id: FxHashMap<SubId, Path>,
+}
+
+impl Tracker {
+    fn new(
+        subscriber: Subscriber,
+        base: Path,
+        us: Option<Path>,
+        lease: Duration,
+    ) -> (Self, mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>) {
+        let (updates, rx) = mpsc::channel(10);
+        let t = Tracker {
+            subscriber,
+            ctrack: ChangeTracker::new(base),
+            us,
+            lease,
+            updates,
+            members: FxHashMap::default(),
+            by_id: FxHashMap::default(),
+        };
+        (t, rx)
+    }
+
+    /// Look for members that have joined or left since the last
+    /// poll. If this fails or is cancelled the change is picked up by
+    /// the next poll.
+    async fn poll(&mut self) -> Result<()> {
+        let mut ctrack = self.ctrack.clone();
+        if self.subscriber.resolver().check_changed(&mut ctrack).await? {
+            let base = ctrack.path().clone();
+            let mut l = list_members(&self.subscriber, base).await?;
+            let all = l
+                .drain(..)
+                .filter(|p| Some(p) != self.us.as_ref())
+                .collect::<HashSet<_>>();
+            let by_id = &mut self.by_id;
+            self.members.retain(|p, (dv, _)| {
+                let keep = all.contains(p);
+                if !keep {
+                    by_id.remove(&dv.id());
+                }
+                keep
+            });
+            for path in all {
+                if !self.members.contains_key(&path) {
+                    let dv = self.subscriber.subscribe(path.clone());
+                    dv.updates(UpdatesFlags::BEGIN_WITH_LAST, self.updates.clone());
+                    dv.set_deadline(self.lease);
+                    self.by_id.insert(dv.id(), path.clone());
+                    self.members.insert(path, (dv, None));
+                }
+            }
+        }
+        self.ctrack = ctrack;
+        Ok(())
+    }
+
+    /// A member is live until its lease expires, it unpublishes, or
+    /// we lose our subscription to it.
+    fn process(&mut self, mut batch: Pooled<Vec<(SubId, Event)>>) {
+        for (id, ev) in batch.drain(..) {
+            if let Some(path) = self.by_id.get(&id) {
+                if let Some((_, info)) = self.members.get_mut(path) {
+                    *info = match ev {
+                        Event::Update(v) => MemberInfo::decode(path.clone(), &v),
+                        Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => {
+                            None
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn live(&self) -> Vec<MemberInfo> {
+        self.members.values().filter_map(|(_, info)| info.clone()).collect()
+    }
+}
+
+/// Lease based primary election. Every member publishes a heartbeat
+/// under a common base path, and holds a lease on membership that
+/// expires if `LEASE_HEARTBEATS` heartbeats are missed. The oldest
+/// live member is elected primary, and it keeps the role until it
+/// leaves, or an older member appears (e.g. when a partition heals).
+///
+/// A member only takes the primary role once it has been a member
+/// for a full lease, and no other live member claims it. So when the
+/// primary fails the role is vacant until the old primary's lease
+/// expires, and as long as members can see each other there is never
+/// more than one primary. A member that can't reach the others can't
+/// tell the difference between a partition and their failure, so
+/// within a lease of a partition there may briefly be two.
+///
+/// The protocol runs in a background task, which stops when the
+/// member is dropped.
+pub struct Member {
+    path: Path,
+    state: watch::Receiver<Membership>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl Member {
+    /// Join the cluster under `base`, sending a heartbeat every
+    /// `heartbeat`. Nothing else should publish under `base`.
+    pub async fn new(
+        publisher: &Publisher,
+        subscriber: Subscriber,
+        base: Path,
+        heartbeat: Duration,
+    ) -> Result<Member> {
+        let path = base.append(&uuid_string(Uuid::new_v4()));
+        let us = MemberInfo { path: path.clone(), joined: Utc::now(), primary: false };
+        let val = publisher.publish(path.clone(), us.encode(0))?;
+        publisher.flushed().await;
+        let lease = heartbeat * LEASE_HEARTBEATS;
+        let (tracker, updates) =
+            Tracker::new(subscriber, base, Some(path.clone()), lease);
+        let (tx, state) = watch::channel(Membership::new(vec![us.clone()]));
+        let publisher = publisher.clone();
+        let task = task::spawn(async move {
+            run_member(publisher, val, us, tracker, updates, heartbeat, tx).await
+        });
+        Ok(Member { path, state, task })
+    }
+
+    /// Return the path this member publishes its heartbeat at
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return true if this member currently holds the primary role
+    pub fn primary(&self) -> bool {
+        self.state.borrow().primary.as_ref() == Some(&self.path)
+    }
+
+    /// Return the current state of the cluster
+    pub fn membership(&self) -> Membership {
+        self.state.borrow().clone()
+    }
+
+    /// Wait for the membership, or the primary, to change
+    pub async fn changed(&mut self) -> Result<Membership> {
+        self.state.changed().await?;
+        Ok(self.state.borrow().clone())
+    }
+
+    /// Wait until this member holds the primary role
+    pub async fn wait_primary(&mut self) -> Result<()> {
+        while !self.primary() {
+            self.state.changed().await?
+        }
+        Ok(())
+    }
+}
+
+async fn run_member(
+    publisher: Publisher,
+    val: Val,
+    mut us: MemberInfo,
+    mut tracker: Tracker,
+    mut updates: mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>,
+    heartbeat: Duration,
+    state: watch::Sender<Membership>,
+) {
+    async fn send_heartbeat(publisher: &Publisher, val: &Val, us: &MemberInfo, seq: u64) {
+        let mut batch = publisher.start_batch();
+        val.update(&mut batch, us.encode(seq));
+        batch.commit(None).await;
+    }
+    let started = Instant::now();
+    let mut seq = 0;
+    let mut tick = time::interval(heartbeat);
+    loop {
+        select_biased! {
+            b = updates.select_next_some() => tracker.process(b),
+            _ = tick.tick().fuse() => {
+                // the heartbeat goes out before we talk to the
+                // resolver, and the poll is bounded well within the
+                // lease, so a slow resolver can't make us lose our
+                // lease while we still think we are primary
+                seq += 1;
+                send_heartbeat(&publisher, &val, &us, seq).await;
+                match time::timeout(heartbeat, tracker.poll()).await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => {
+                        warn!("cluster {} failed to poll members {}", us.path, e)
+                    }
+                    Err(_) => warn!("cluster {} timed out polling members", us.path),
+                }
+                let others = tracker.live();
+                let oldest = others.iter().all(|m| us.seniority() < m.seniority());
+                let primary = oldest
+                    && (us.primary
+                        || (started.elapsed() >= tracker.lease
+                            && !others.iter().any(|m| m.primary)));
+                if primary != us.primary {
+                    us.primary = primary;
+                    seq += 1;
+                    send_heartbeat(&publisher, &val, &us, seq).await;
+                }
+                let membership =
+                    Membership::new(iter::once(us.clone()).chain(others).collect());
+                state.send_if_modified(|cur| {
+                    let changed = cur != &membership;
+                    *cur = membership;
+                    changed
+                });
+            }
+        }
+    }
+}
+
+/// Watch the members of a lease based cluster without joining it.
+pub struct Observer {
+    state: watch::Receiver<Membership>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Observer {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl Observer {
+    /// Observe the cluster under `base`. `heartbeat` should match the
+    /// heartbeat interval of the members.
+    pub fn new(subscriber: Subscriber, base: Path, heartbeat: Duration) -> Observer {
+        let lease = heartbeat * LEASE_HEARTBEATS;
+        let (mut tracker, mut updates) = Tracker::new(subscriber, base, None, lease);
+        let (tx, state) = watch::channel(Membership::default());
+        let task = task::spawn(async move {
+            let mut tick = time::interval(heartbeat);
+            loop {
+                select_biased! {
+                    b = updates.select_next_some() => tracker.process(b),
+                    _ = tick.tick().fuse() => {
+                        if let Err(e) = tracker.poll().await {
+                            warn!("cluster observer failed to poll members {}", e)
+                        }
+                        let membership = Membership::new(tracker.live());
+                        tx.send_if_modified(|cur| {
+                            let changed = cur != &membership;
+                            *cur = membership;
+                            changed
+                        });
+                    }
+                }
+            }
+        });
+        Observer { state, task }
+    }
+
+    /// Return the current state of the cluster
+    pub fn membership(&self) -> Membership {
+        self.state.borrow().clone()
+    }
+
+    /// Wait for the membership, or the primary, to change
+    pub async fn changed(&mut self) -> Result<Membership> {
+        self.state.changed().await?;
+        Ok(self.state.borrow().clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::channel::test::Ctx;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn primary_election() {
+        let ctx = Ctx::new().await;
+        let base = Path::from("/cluster");
+        let hb = Duration::from_millis(100);
+        let mut observer = Observer::new(ctx.subscriber.clone(), base.clone(), hb);
+        let mut m0 =
+            Member::new(&ctx.publisher, ctx.subscriber.clone(), base.clone(), hb)
+                .await
+                .unwrap();
+        time::timeout(Duration::from_secs(5), m0.wait_primary()).await.unwrap().unwrap();
+        let mut m1 =
+            Member::new(&ctx.publisher, ctx.subscriber.clone(), base.clone(), hb)
+                .await
+                .unwrap();
+        let membership = time::timeout(Duration::from_secs(5), async {
+            loop {
+                let m = observer.changed().await.unwrap();
+                if m.members.len() == 2 {
+                    break m;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(membership.primary.as_ref(), Some(m0.path()));
+        assert_eq!(&membership.members[0].path, m0.path());
+        assert_eq!(&membership.members[1].path, m1.path());
+        time::sleep(hb * LEASE_HEARTBEATS * 2).await;
+        assert!(m0.primary());
+        assert!(!m1.primary());
+        drop(m0);
+        time::timeout(Duration::from_secs(5), m1.wait_primary()).await.unwrap().unwrap();
+        let membership = m1.membership();
+        assert_eq!(membership.members.len(), 1);
+        assert_eq!(membership.primary.as_ref(), Some(m1.path()));
+    }
+}
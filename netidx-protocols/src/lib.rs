@@ -12,3 +12,5 @@ pub mod rpc;
 pub mod view;
 pub mod channel;
 pub mod pack_channel;
+pub mod queue;
+pub mod transfer;
@@ -0,0 +1,88 @@
+use crate::pack_channel::client::Connection;
+use anyhow::Result;
+use arcstr::ArcStr;
+use netidx::{pack::Pack, path::Path, subscriber::Subscriber};
+use std::marker::PhantomData;
+
+/// A named consumer of a queue. Messages are received in sequence
+/// number order, and should be acknowledged with `ack` once they have
+/// been processed. Messages that haven't been acknowledged will be
+/// sent again the next time a consumer with the same name connects.
+pub struct Consumer<T: Pack + 'static> {
+    con: Connection,
+    next: u64,
+    _t: PhantomData<T>,
+}
+
+impl<T: Pack + 'static> Consumer<T> {
+    /// Connect to the queue at `path` as `name`, resuming after the
+    /// last message acknowledged by `name`.
+    pub async fn connect(
+        subscriber: &Subscriber,
+        path: Path,
+        name: &str,
+    ) -> Result<Self> {
+        let con = Connection::connect(subscriber, path).await?;
+        con.send_one(&ArcStr::from(name))?;
+        let next = con.recv_one().await?;
+        Ok(Consumer { con, next, _t: PhantomData })
+    }
+
+    /// return true if the connection is dead
+    pub fn is_dead(&self) -> bool {
+        self.con.is_dead()
+    }
+
+    /// The sequence number of the next message that will be received
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+    fn check(&mut self, seq: u64) -> Result<()> {
+        if seq != self.next {
+            bail!("expected message {} got {}", self.next, seq)
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Wait for the next message and return it along with its
+    /// sequence number.
+    pub async fn recv_one(&mut self) -> Result<(u64, T)> {
+        let (seq, msg) = self.con.recv_one::<(u64, T)>().await?;
+        self.check(seq)?;
+        Ok((seq, msg))
+    }
+
+    /// Receive all available messages, waiting for at least one to
+    /// arrive, and append them to `dst` along with their sequence
+    /// numbers.
+    pub async fn recv(&mut self, dst: &mut Vec<(u64, T)>) -> Result<()> {
+        let start = dst.len();
+        self.con
+            .recv(|m: (u64, T)| {
+                dst.push(m);
+                true
+            })
+            .await?;
+        for i in start..dst.len() {
+            self.check(dst[i].0)?
+        }
+        Ok(())
+    }
+
+    /// Acknowledge every message up to and including `seq`. Acks are
+    /// sent in the background, use `flush` to wait for them to be
+    /// written to the OS.
+    pub fn ack(&self, seq: u64) -> Result<()> {
+        if seq >= self.next {
+            bail!("can't ack message {}, it hasn't been received", seq)
+        }
+        self.con.send_one(&seq)
+    }
+
+    /// Wait for sent acks to flush to the OS
+    pub async fn flush(&self) -> Result<()> {
+        self.con.flush().await
+    }
+}
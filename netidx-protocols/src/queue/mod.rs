@@ -0,0 +1,17 @@
+//! An append only stream of typed messages with acknowledged
+//! delivery. The producer publishes a `server::Queue` at a path, and
+//! each named consumer connects to it with a `client::Consumer`. Every
+//! message is assigned a sequence number, and the producer remembers
+//! the last message each consumer acknowledged, so a consumer that
+//! disconnects resumes from where it left off when it reconnects.
+//!
+//! The protocol runs over a `pack_channel`. The consumer sends its
+//! name, the producer replies with the sequence number it will start
+//! sending from, and then sends `(seq, message)` pairs in order. The
+//! consumer sends the sequence numbers it has processed back as acks.
+
+pub mod client;
+pub mod server;
+
+#[cfg(test)]
+mod test;
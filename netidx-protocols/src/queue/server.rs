@@ -0,0 +1,213 @@
+use crate::pack_channel::server::{Connection, Listener};
+use anyhow::Result;
+use arcstr::ArcStr;
+use fxhash::FxHashMap;
+use log::{info, warn};
+use netidx::{
+    pack::Pack,
+    path::Path,
+    publisher::{PublishFlags, Publisher},
+};
+use parking_lot::Mutex;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::{
+    select,
+    sync::watch,
+    task::{self, JoinHandle},
+};
+
+/// The most messages sent to a consumer in one batch
+const BATCH: usize = 1000;
+
+struct Log<T> {
+    /// the sequence number of the first message in `items`
+    base: u64,
+    items: VecDeque<T>,
+    /// the sequence number of the next message each consumer needs
+    consumers: FxHashMap<ArcStr, u64>,
+}
+
+impl<T> Log<T> {
+    fn next(&self) -> u64 {
+        self.base + self.items.len() as u64
+    }
+
+    /// drop the messages every known consumer has acknowledged
+    fn trim(&mut self) {
+        if let Some(min) = self.consumers.values().min() {
+            while self.base < *min && self.items.pop_front().is_some() {
+                self.base += 1;
+            }
+        }
+    }
+}
+
+/// An append only stream of `T` with acknowledged delivery to each
+/// consumer. Messages are kept in memory until every consumer the
+/// queue knows about has acknowledged them. If there are no
+/// consumers yet, messages are kept until the first one connects.
+///
+/// A consumer is identified by the name it connects with, and it
+/// resumes from its last acknowledged message when it
+/// reconnects. Only one connection per consumer name should be open
+/// at a time. A consumer that will never come back should be removed
+/// with `remove_consumer`, otherwise the queue will grow without
+/// bound.
+pub struct Queue<T: Pack + Clone + Send + Sync + 'static> {
+    log: Arc<Mutex<Log<T>>>,
+    /// the sequence number of the next message to be pushed,
+    /// consumer connections stop when it is dropped
+    next: watch::Sender<u64>,
+    task: JoinHandle<()>,
+}
+
+impl<T: Pack + Clone + Send + Sync + 'static> Drop for Queue<T> {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl<T: Pack + Clone + Send + Sync + 'static> Queue<T> {
+    /// Publish a new queue at `path`.
+    pub async fn new(
+        publisher: &Publisher,
+        timeout: Option<Duration>,
+        path: Path,
+    ) -> Result<Self> {
+        Self::new_with_flags(publisher, PublishFlags::empty(), timeout, path).await
+    }
+
+    /// just like new, but with publish flags
+    pub async fn new_with_flags(
+        publisher: &Publisher,
+        flags: PublishFlags,
+        timeout: Option<Duration>,
+        path: Path,
+    ) -> Result<Self> {
+        let mut listener =
+            Listener::new_with_flags(publisher, flags, timeout, path.clone()).await?;
+        let (next, rx_next) = watch::channel(0);
+        let log = Arc::new(Mutex::new(Log {
+            base: 0,
+            items: VecDeque::new(),
+            consumers: FxHashMap::default(),
+        }));
+        let task = task::spawn({
+            let log = log.clone();
+            async move {
+                loop {
+                    match listener.accept().await {
+                        Err(e) => warn!("queue {} accept failed {}", path, e),
+                        Ok(con) => {
+                            let log = log.clone();
+                            let rx_next = rx_next.clone();
+                            let path = path.clone();
+                            task::spawn(async move {
+                                if let Err(e) = serve(log, rx_next, con).await {
+                                    info!("queue {} consumer disconnected {}", path, e)
+                                }
+                            });
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Queue { log, next, task })
+    }
+
+    /// Append `msg` to the queue and return its sequence number
+    pub fn push(&self, msg: T) -> u64 {
+        self.extend(std::iter::once(msg)) - 1
+    }
+
+    /// Append every message in `msgs` to the queue and return the
+    /// sequence number the next message will get.
+    pub fn extend(&self, msgs: impl IntoIterator<Item = T>) -> u64 {
+        let next = {
+            let mut log = self.log.lock();
+            log.items.extend(msgs);
+            log.next()
+        };
+        self.next.send_replace(next);
+        next
+    }
+
+    /// The number of messages that haven't been acknowledged by every
+    /// consumer.
+    pub fn len(&self) -> usize {
+        self.log.lock().items.len()
+    }
+
+    /// Return the sequence number of the next message `consumer`
+    /// needs, or None if it has never connected.
+    pub fn position(&self, consumer: &str) -> Option<u64> {
+        self.log.lock().consumers.get(consumer).copied()
+    }
+
+    /// Forget about `consumer`. Messages only it hasn't acknowledged
+    /// will be dropped. If it connects again it will start from the
+    /// oldest message still in the queue.
+    pub fn remove_consumer(&self, consumer: &str) {
+        let mut log = self.log.lock();
+        log.consumers.remove(consumer);
+        log.trim();
+    }
+}
+
+/// Send the messages between `pos` and the end of the log to the
+/// consumer, at most `BATCH` at a time. Return the new position.
+async fn send_pending<T: Pack>(
+    log: &Mutex<Log<T>>,
+    con: &Connection,
+    mut pos: u64,
+) -> Result<u64> {
+    loop {
+        let mut batch = con.start_batch();
+        {
+            let log = log.lock();
+            // a removed consumer may fall behind the log
+            pos = std::cmp::max(pos, log.base);
+            let start = (pos - log.base) as usize;
+            for msg in log.items.range(start..).take(BATCH) {
+                batch.queue(&pos)?;
+                batch.queue(msg)?;
+                pos += 1;
+            }
+        }
+        if batch.len() == 0 {
+            break Ok(pos);
+        }
+        con.send(batch).await?
+    }
+}
+
+async fn serve<T: Pack>(
+    log: Arc<Mutex<Log<T>>>,
+    mut next: watch::Receiver<u64>,
+    con: Connection,
+) -> Result<()> {
+    let name: ArcStr = con.recv_one().await?;
+    let mut pos = {
+        let mut log = log.lock();
+        let base = log.base;
+        *log.consumers.entry(name.clone()).or_insert(base)
+    };
+    con.send_one(&pos).await?;
+    loop {
+        pos = send_pending(&log, &con, pos).await?;
+        select! {
+            r = next.changed() => r?,
+            ack = con.recv_one::<u64>() => {
+                let ack = ack?;
+                if ack >= pos {
+                    bail!("ack {} of a message that wasn't sent", ack)
+                }
+                let mut log = log.lock();
+                if let Some(cur) = log.consumers.get_mut(&name) {
+                    *cur = std::cmp::max(*cur, ack + 1);
+                }
+                log.trim();
+            }
+        }
+    }
+}
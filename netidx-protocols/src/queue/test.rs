@@ -0,0 +1,45 @@
+use super::*;
+use crate::channel::test::Ctx;
+use std::time::Duration;
+use tokio::time;
+
+async fn recv_n(c: &mut client::Consumer<u64>, n: usize) -> Vec<(u64, u64)> {
+    let mut v = Vec::new();
+    while v.len() < n {
+        c.recv(&mut v).await.unwrap();
+    }
+    v
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resume_from_ack() {
+    let ctx = Ctx::new().await;
+    let queue: server::Queue<u64> =
+        server::Queue::new(&ctx.publisher, None, ctx.base.clone()).await.unwrap();
+    for i in 0..10 {
+        assert_eq!(queue.push(i * 10), i);
+    }
+    let mut c =
+        client::Consumer::connect(&ctx.subscriber, ctx.base.clone(), "a").await.unwrap();
+    assert_eq!(c.position(), 0);
+    let v = recv_n(&mut c, 10).await;
+    assert_eq!(v, (0..10).map(|i| (i, i * 10)).collect::<Vec<_>>());
+    c.ack(4).unwrap();
+    c.flush().await.unwrap();
+    while queue.position("a") != Some(5) {
+        time::sleep(Duration::from_millis(10)).await
+    }
+    assert_eq!(queue.len(), 5);
+    drop(c);
+    queue.extend((10..12).map(|i| i * 10));
+    let mut c =
+        client::Consumer::connect(&ctx.subscriber, ctx.base.clone(), "a").await.unwrap();
+    assert_eq!(c.position(), 5);
+    let v = recv_n(&mut c, 7).await;
+    assert_eq!(v, (5..12).map(|i| (i, i * 10)).collect::<Vec<_>>());
+    // a new consumer starts from the oldest message still queued
+    let mut c: client::Consumer<u64> =
+        client::Consumer::connect(&ctx.subscriber, ctx.base.clone(), "b").await.unwrap();
+    assert_eq!(c.position(), 5);
+    assert_eq!(c.recv_one().await.unwrap(), (5, 50));
+}
@@ -1,3 +1,30 @@
+//! A request/response convention layered on publish/subscribe.
+//!
+//! A procedure at `path` is published as,
+//!
+//! * `path` - the set of argument names. Calling the procedure is
+//!   writing an array of `(name, value)` pairs to this path, with
+//!   a reply requested.
+//! * `path/doc` - the procedure's doc string
+//! * `path/<arg>/val` - the default value of `<arg>`. Writing to it
+//!   sets `<arg>` for the next call made by the same client, for
+//!   clients that can't write arrays (e.g. the browser).
+//! * `path/<arg>/doc` - the doc string of `<arg>`
+//!
+//! Replies are correlated with calls by the publisher, which
+//! delivers the result of each write only to the client that made
+//! it, so concurrent calls from many clients never get crossed. If
+//! the procedure handler drops a call without replying the caller
+//! receives an error.
+//!
+//! Use `server::Proc` (or `define_rpc!`) to publish a procedure, and
+//! `client::Proc` (or `call_rpc!`) to call one. `client::Proc` has
+//! `call_with_timeout` for calls that must not wait forever.
+//!
+//! Because the publisher already correlates each reply with the write
+//! that requested it, calls don't need per call child paths or
+//! explicit call ids, and there is no separate `Server`/`Client`
+//! pair, `server::Proc` and `client::Proc` are the entry points.
 use anyhow::Result;
 use arcstr::ArcStr;
 use futures::{
@@ -505,7 +532,7 @@ pub mod client {
                     .map_err(|_| anyhow!("timeout subscribing to procedure"))??;
                     debug!("fetching args");
                     match self.0.call.last() {
-                        Event::Unsubscribed => (),
+                        Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => (),
                         Event::Update(v) => {
                             debug!("args are {:?}", v);
                             let args = v
@@ -548,6 +575,24 @@ pub mod client {
             trace!("procedure called");
             Ok(res)
         }
+
+        /// Call the procedure, failing if the reply doesn't arrive
+        /// within `timeout`, including the time it takes to
+        /// subscribe to the procedure. If the call times out, it may
+        /// or may not have been executed by the server.
+        pub async fn call_with_timeout<I, K>(
+            &self,
+            timeout: Duration,
+            args: I,
+        ) -> Result<Value>
+        where
+            I: IntoIterator<Item = (K, Value)>,
+            K: Borrow<str>,
+        {
+            time::timeout(timeout, self.call(args))
+                .await
+                .map_err(|_| anyhow!("timeout waiting for the procedure to reply"))?
+        }
     }
 }
 
@@ -557,7 +602,7 @@ mod test {
 
     use super::server::*;
     use super::*;
-    use tokio::{runtime::Runtime, time};
+    use tokio::runtime::Runtime;
 
     #[test]
     fn call_proc() {
@@ -583,7 +628,7 @@ mod test {
                         c.reply.send(Value::U32(42))
                     }
                 });
-                time::sleep(Duration::from_millis(100)).await;
+                ctx.publisher.flushed().await;
                 let proc: client::Proc =
                     client::Proc::new(&ctx.subscriber, proc_name.clone()).unwrap();
                 let res = call_rpc!(proc, arg1: "hello rpc").await.unwrap();
@@ -600,4 +645,43 @@ mod test {
             })
             .unwrap()
     }
+
+    #[test]
+    fn call_proc_timeout() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let proc_name = Path::from("/rpc/slow");
+                let (tx, mut rx) = mpsc::channel(10);
+                let _server_proc = define_rpc!(
+                    &ctx.publisher,
+                    proc_name.clone(),
+                    "never replies",
+                    |c, a| Some((c, a)),
+                    Some(tx),
+                    arg1: Value = Value::Null; "arg1 doc"
+                )
+                .unwrap();
+                // hold on to the calls, dropping them would reply with an error
+                let (held_tx, mut held_rx) = mpsc::unbounded();
+                task::spawn(async move {
+                    while let Some((c, _)) = rx.next().await {
+                        let _: std::result::Result<_, _> = held_tx.unbounded_send(c);
+                    }
+                });
+                ctx.publisher.flushed().await;
+                let proc: client::Proc =
+                    client::Proc::new(&ctx.subscriber, proc_name.clone()).unwrap();
+                let args = vec![("arg1", Value::from("hello rpc"))];
+                let res = proc.call_with_timeout(Duration::from_secs(1), args).await;
+                assert!(res.is_err());
+                // the call reached the server, it was the reply that timed out
+                let held: Vec<RpcCall> = held_rx.by_ref().take(1).collect().await;
+                assert_eq!(held.len(), 1);
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
 }
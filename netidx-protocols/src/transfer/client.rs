@@ -0,0 +1,148 @@
+use super::{ack_path, checksum, chunk_path, manifest_path, Manifest};
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use netidx::{
+    path::Path,
+    publisher::{FromValue, Value},
+    subscriber::{Dval, Event, Subscriber},
+};
+use sha3::{Digest, Sha3_512};
+use std::{cmp::min, collections::VecDeque, path::Path as FsPath};
+use tokio::{fs, io::AsyncWriteExt};
+
+async fn wait_value(dv: &Dval) -> Result<Value> {
+    dv.wait_subscribed().await?;
+    match dv.last() {
+        Event::Update(v) => Ok(v),
+        Event::Unsubscribed => bail!("unsubscribed"),
+        Event::Unpublished(_) => bail!("file removed"),
+        Event::Stale => bail!("stale"),
+    }
+}
+
+/// The receiving side of a transfer. Chunks are received in order,
+/// and each one is verified against its checksum and acked before it
+/// is returned. Up to `window` chunks are subscribed to ahead of the
+/// one being returned.
+pub struct Receiver {
+    subscriber: Subscriber,
+    base: Path,
+    manifest: Manifest,
+    ack: Dval,
+    /// subscriptions to the chunks starting at `next`
+    pending: VecDeque<Dval>,
+    /// the next chunk to be returned
+    next: u64,
+    hash: Sha3_512,
+    done: bool,
+}
+
+impl Receiver {
+    /// Connect to the transfer published at `base` and read its
+    /// manifest.
+    pub async fn connect(subscriber: &Subscriber, base: Path) -> Result<Self> {
+        let manifest = wait_value(&subscriber.subscribe(manifest_path(&base))).await?;
+        let manifest = Manifest::from_value(manifest)?;
+        let ack = subscriber.subscribe(ack_path(&base));
+        let mut t = Receiver {
+            subscriber: subscriber.clone(),
+            base,
+            manifest,
+            ack,
+            pending: VecDeque::new(),
+            next: 0,
+            hash: Sha3_512::new(),
+            done: false,
+        };
+        t.fill();
+        Ok(t)
+    }
+
+    fn fill(&mut self) {
+        loop {
+            let n = self.next + self.pending.len() as u64;
+            if n >= self.manifest.chunks
+                || self.pending.len() as u64 >= self.manifest.window
+            {
+                break;
+            }
+            self.pending.push_back(self.subscriber.subscribe(chunk_path(&self.base, n)));
+        }
+    }
+
+    /// The manifest of this transfer
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The number of chunks received so far
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+    async fn ack(&self, n: u64) -> Result<()> {
+        match self.ack.write_with_recipt(Value::from(n)).await {
+            Err(_) => bail!("the ack of {} was dropped", n),
+            Ok(Value::Error(e)) => bail!("the sender rejected the ack of {}: {}", n, e),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Receive, verify, and ack the next chunk. Return `None` once
+    /// every chunk has been received and the checksum of the whole
+    /// blob has been verified.
+    pub async fn recv_chunk(&mut self) -> Result<Option<Bytes>> {
+        let n = self.next;
+        if n >= self.manifest.chunks {
+            if !self.done {
+                if self.hash.clone().finalize()[..] != self.manifest.checksum[..] {
+                    bail!("the checksum of the transfer doesn't match")
+                }
+                self.ack(n).await?;
+                self.done = true;
+            }
+            return Ok(None);
+        }
+        let dv =
+            self.pending.pop_front().ok_or_else(|| anyhow!("missing chunk {}", n))?;
+        let (sum, chunk) = wait_value(&dv)
+            .await
+            .and_then(|v| v.cast_to::<(Bytes, Bytes)>())
+            .map_err(|e| anyhow!("chunk {}: {}", n, e))?;
+        drop(dv);
+        let start = n * self.manifest.chunk_size;
+        let len = min(self.manifest.chunk_size, self.manifest.size - start);
+        if chunk.len() as u64 != len {
+            bail!("chunk {} has length {} expected {}", n, chunk.len(), len)
+        }
+        if checksum(&chunk) != sum {
+            bail!("the checksum of chunk {} doesn't match", n)
+        }
+        self.hash.update(&chunk);
+        self.ack(n).await?;
+        self.next += 1;
+        self.fill();
+        Ok(Some(chunk))
+    }
+
+    /// Receive the whole blob
+    pub async fn recv(mut self) -> Result<Bytes> {
+        let mut buf = BytesMut::with_capacity(self.manifest.size as usize);
+        while let Some(chunk) = self.recv_chunk().await? {
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(buf.freeze())
+    }
+
+    /// Receive the whole blob and write it to `file`. Chunks are
+    /// written as they arrive, so if this fails `file` may be
+    /// incomplete, or, if only the checksum of the whole blob is
+    /// wrong, it may contain corrupt data.
+    pub async fn recv_to_file(mut self, file: impl AsRef<FsPath>) -> Result<()> {
+        let mut fd = fs::File::create(file).await?;
+        while let Some(chunk) = self.recv_chunk().await? {
+            fd.write_all(&chunk).await?;
+        }
+        Ok(fd.flush().await?)
+    }
+}
@@ -0,0 +1,121 @@
+//! A convention for transferring a file, or any blob of bytes, over
+//! netidx in fixed size chunks. The sender publishes a
+//! `server::Sender` at a base path, and a receiver reads it with a
+//! `client::Receiver`. The transfer is published as,
+//!
+//! * `base/manifest` - a `Manifest` describing the transfer, the
+//!   total size, the chunk size, the number of chunks, the number of
+//!   chunks published at once (the window), and the sha3 checksum of
+//!   the whole blob.
+//! * `base/chunk/<n>` - chunk `n` as a pair of its sha3 checksum and
+//!   its bytes. At most `window` chunks are published at any one
+//!   time.
+//! * `base/ack` - the receiver writes the number of each chunk it
+//!   has received and verified here, with a reply requested. The
+//!   sender unpublishes every chunk up to and including the acked
+//!   one, publishes the chunks that now fit in the window, and only
+//!   then replies, so a receiver that waits for the reply before
+//!   subscribing to the next chunk never races the sender, and a slow
+//!   receiver can never have more than `window` chunks in flight.
+//!   Once every chunk has been received and the checksum of the whole
+//!   blob is verified the receiver writes the number of chunks, and
+//!   the sender unpublishes the transfer.
+//!
+//! A transfer has exactly one receiver, and it is not restartable,
+//! once a chunk is acked it is gone. If multiple receivers need the
+//! same data publish a separate transfer for each of them.
+use anyhow::Result;
+use bytes::Bytes;
+use netidx::{
+    chars::Chars,
+    path::Path,
+    publisher::{FromValue, Value},
+};
+use sha3::{Digest, Sha3_512};
+
+pub mod client;
+pub mod server;
+
+#[cfg(test)]
+mod test;
+
+/// The description of a transfer published at `base/manifest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// the total size of the blob in bytes
+    pub size: u64,
+    /// the size of every chunk except possibly the last one
+    pub chunk_size: u64,
+    /// the number of chunks
+    pub chunks: u64,
+    /// the maximum number of chunks published at once
+    pub window: u64,
+    /// the sha3 512 checksum of the whole blob
+    pub checksum: Bytes,
+}
+
+impl From<Manifest> for Value {
+    fn from(m: Manifest) -> Value {
+        vec![
+            Value::from(("size", m.size)),
+            Value::from(("chunk_size", m.chunk_size)),
+            Value::from(("chunks", m.chunks)),
+            Value::from(("window", m.window)),
+            Value::from(("checksum", m.checksum)),
+        ]
+        .into()
+    }
+}
+
+impl FromValue for Manifest {
+    fn from_value(v: Value) -> Result<Self> {
+        let mut size = None;
+        let mut chunk_size = None;
+        let mut chunks = None;
+        let mut window = None;
+        let mut checksum = None;
+        for v in v.cast_to::<Vec<Value>>()? {
+            let (name, v) = v.cast_to::<(Chars, Value)>()?;
+            match &*name {
+                "size" => size = Some(v.cast_to::<u64>()?),
+                "chunk_size" => chunk_size = Some(v.cast_to::<u64>()?),
+                "chunks" => chunks = Some(v.cast_to::<u64>()?),
+                "window" => window = Some(v.cast_to::<u64>()?),
+                "checksum" => checksum = Some(v.cast_to::<Bytes>()?),
+                _ => (),
+            }
+        }
+        let m = Manifest {
+            size: size.ok_or_else(|| anyhow!("manifest is missing size"))?,
+            chunk_size: chunk_size
+                .ok_or_else(|| anyhow!("manifest is missing chunk_size"))?,
+            chunks: chunks.ok_or_else(|| anyhow!("manifest is missing chunks"))?,
+            window: window.ok_or_else(|| anyhow!("manifest is missing window"))?,
+            checksum: checksum.ok_or_else(|| anyhow!("manifest is missing checksum"))?,
+        };
+        if m.chunk_size == 0 || m.window == 0 {
+            bail!("chunk_size and window must be greater than 0")
+        }
+        if m.chunks != (m.size + m.chunk_size - 1) / m.chunk_size {
+            bail!("the number of chunks doesn't match the size and chunk size")
+        }
+        Ok(m)
+    }
+}
+
+/// compute the sha3 512 checksum of `data`
+pub fn checksum(data: &[u8]) -> Bytes {
+    Bytes::copy_from_slice(&Sha3_512::digest(data))
+}
+
+pub(crate) fn manifest_path(base: &Path) -> Path {
+    base.append("manifest")
+}
+
+pub(crate) fn chunk_path(base: &Path, n: u64) -> Path {
+    base.append("chunk").append(&n.to_string())
+}
+
+pub(crate) fn ack_path(base: &Path) -> Path {
+    base.append("ack")
+}
@@ -0,0 +1,193 @@
+use super::{ack_path, checksum, chunk_path, manifest_path, Manifest};
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{channel::mpsc, prelude::*};
+use netidx::{
+    chars::Chars,
+    path::Path,
+    pool::Pooled,
+    publisher::{Publisher, Val, Value, WriteRequest},
+};
+use std::{cmp::min, collections::VecDeque, path::Path as FsPath};
+use tokio::{
+    fs,
+    task::{self, JoinHandle},
+};
+
+/// The default chunk size
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The default number of chunks published at once
+pub const DEFAULT_WINDOW: usize = 8;
+
+struct Transfer {
+    publisher: Publisher,
+    base: Path,
+    data: Bytes,
+    manifest: Manifest,
+    _manifest: Val,
+    _ack: Val,
+    /// the published chunks, the first one is chunk `acked`
+    chunks: VecDeque<Val>,
+    /// the number of chunks the receiver has acked
+    acked: u64,
+}
+
+impl Transfer {
+    fn chunk(&self, n: u64) -> Bytes {
+        let start = n as usize * self.manifest.chunk_size as usize;
+        let end = min(start + self.manifest.chunk_size as usize, self.data.len());
+        self.data.slice(start..end)
+    }
+
+    /// publish chunks until the window is full or there are none left
+    fn fill(&mut self) -> Result<()> {
+        loop {
+            let n = self.acked + self.chunks.len() as u64;
+            if n >= self.manifest.chunks
+                || self.chunks.len() as u64 >= self.manifest.window
+            {
+                break Ok(());
+            }
+            let chunk = self.chunk(n);
+            let path = chunk_path(&self.base, n);
+            let val = self.publisher.publish(path, (checksum(&chunk), chunk))?;
+            self.chunks.push_back(val);
+        }
+    }
+
+    /// process an ack, returning true if the transfer is complete
+    fn ack(&mut self, v: Value) -> Result<bool> {
+        let n = v.cast_to::<u64>()?;
+        if n == self.manifest.chunks && self.acked == n {
+            return Ok(true);
+        }
+        if n < self.acked || n >= self.acked + self.chunks.len() as u64 {
+            bail!("chunk {} is not published", n)
+        }
+        for _ in self.acked..=n {
+            self.chunks.pop_front();
+        }
+        self.acked = n + 1;
+        self.fill()?;
+        Ok(false)
+    }
+
+    async fn run(
+        mut self,
+        mut writes: mpsc::Receiver<Pooled<Vec<WriteRequest>>>,
+    ) -> Result<()> {
+        let mut replies = Vec::new();
+        let mut done = false;
+        while !done {
+            let mut batch = match writes.next().await {
+                Some(batch) => batch,
+                None => bail!("the ack channel closed"),
+            };
+            for req in batch.drain(..) {
+                let res = self.ack(req.value).map(|fin| done |= fin);
+                if let Some(reply) = req.send_result {
+                    replies.push((reply, res));
+                }
+            }
+            // the receiver may subscribe to the next chunk as soon as
+            // it gets the reply, so it must be published first
+            self.publisher.flushed().await;
+            for (reply, res) in replies.drain(..) {
+                reply.send(match res {
+                    Ok(()) => Value::Ok,
+                    Err(e) => Value::Error(Chars::from(e.to_string())),
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sending side of a transfer. The blob stays published until
+/// the receiver has acked every chunk, or the `Sender` is dropped.
+pub struct Sender {
+    manifest: Manifest,
+    task: Option<JoinHandle<Result<()>>>,
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort()
+        }
+    }
+}
+
+impl Sender {
+    /// Publish `data` for transfer at `base`, split into chunks of
+    /// `chunk_size` bytes with at most `window` chunks published at
+    /// once. Use `DEFAULT_CHUNK_SIZE` and `DEFAULT_WINDOW` if you
+    /// have no reason to choose something else.
+    pub async fn new(
+        publisher: &Publisher,
+        base: Path,
+        data: Bytes,
+        chunk_size: usize,
+        window: usize,
+    ) -> Result<Self> {
+        if chunk_size == 0 || window == 0 {
+            bail!("chunk_size and window must be greater than 0")
+        }
+        let manifest = Manifest {
+            size: data.len() as u64,
+            chunk_size: chunk_size as u64,
+            chunks: ((data.len() + chunk_size - 1) / chunk_size) as u64,
+            window: window as u64,
+            checksum: checksum(&data),
+        };
+        let ack = publisher.publish(ack_path(&base), Value::Null)?;
+        let published = publisher.publish(manifest_path(&base), manifest.clone())?;
+        let (tx, rx) = mpsc::channel(3);
+        publisher.writes(ack.id(), tx);
+        let mut t = Transfer {
+            publisher: publisher.clone(),
+            base,
+            data,
+            manifest: manifest.clone(),
+            _manifest: published,
+            _ack: ack,
+            chunks: VecDeque::new(),
+            acked: 0,
+        };
+        t.fill()?;
+        publisher.flushed().await;
+        Ok(Sender { manifest, task: Some(task::spawn(t.run(rx))) })
+    }
+
+    /// Read `file` and publish it for transfer at `base`, see `new`
+    pub async fn from_file(
+        publisher: &Publisher,
+        base: Path,
+        file: impl AsRef<FsPath>,
+        chunk_size: usize,
+        window: usize,
+    ) -> Result<Self> {
+        let data = Bytes::from(fs::read(file).await?);
+        Self::new(publisher, base, data, chunk_size, window).await
+    }
+
+    /// The manifest of this transfer
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Wait for the receiver to ack every chunk and the checksum of
+    /// the whole blob. Once this returns successfully the transfer
+    /// is complete and everything it published has been unpublished.
+    pub async fn wait(&mut self) -> Result<()> {
+        match &mut self.task {
+            None => bail!("the transfer is already finished"),
+            Some(task) => {
+                let res = task.await;
+                self.task = None;
+                res?
+            }
+        }
+    }
+}
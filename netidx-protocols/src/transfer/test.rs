@@ -0,0 +1,64 @@
+use super::*;
+use crate::channel::test::Ctx;
+
+fn blob(len: usize) -> Bytes {
+    (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>().into()
+}
+
+#[test]
+fn manifest_roundtrip() {
+    let data = blob(1000);
+    let m = Manifest {
+        size: 1000,
+        chunk_size: 300,
+        chunks: 4,
+        window: 2,
+        checksum: checksum(&data),
+    };
+    assert_eq!(Manifest::from_value(m.clone().into()).unwrap(), m);
+    let bad = Manifest { chunks: 3, ..m };
+    assert!(Manifest::from_value(bad.into()).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transfer() {
+    let ctx = Ctx::new().await;
+    for (len, chunk_size, window) in [(0, 10, 1), (1000, 300, 2), (4096, 1024, 8)] {
+        let base = ctx.base.append(&format!("{}", len));
+        let data = blob(len);
+        let mut sender = server::Sender::new(
+            &ctx.publisher,
+            base.clone(),
+            data.clone(),
+            chunk_size,
+            window,
+        )
+        .await
+        .unwrap();
+        let receiver = client::Receiver::connect(&ctx.subscriber, base).await.unwrap();
+        assert_eq!(receiver.manifest(), sender.manifest());
+        assert_eq!(receiver.recv().await.unwrap(), data);
+        sender.wait().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn window() {
+    let ctx = Ctx::new().await;
+    let data = blob(1000);
+    let _sender =
+        server::Sender::new(&ctx.publisher, ctx.base.clone(), data.clone(), 100, 3)
+            .await
+            .unwrap();
+    let mut receiver =
+        client::Receiver::connect(&ctx.subscriber, ctx.base.clone()).await.unwrap();
+    // only the first window of chunks is published until they are acked
+    let r = ctx.subscriber.subscribe_nondurable_one(chunk_path(&ctx.base, 3), None).await;
+    assert!(r.is_err());
+    assert_eq!(receiver.recv_chunk().await.unwrap().unwrap(), data.slice(0..100));
+    assert_eq!(receiver.position(), 1);
+    ctx.subscriber
+        .subscribe_nondurable_one(chunk_path(&ctx.base, 3), None)
+        .await
+        .unwrap();
+}
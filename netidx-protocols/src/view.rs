@@ -1,8 +1,9 @@
 use netidx::{chars::Chars, protocol::value::Value};
-use netidx_bscript::expr::{Expr, ExprKind};
+use netidx_bscript::expr::{Expr, ExprKind, FnDef};
 use std::{
     boxed,
     cmp::{PartialEq, PartialOrd},
+    collections::BTreeMap,
     default::Default,
 };
 
@@ -12,7 +13,12 @@ pub struct Keybind {
     /// modifier keys are separated by a +. Available modifiers are
     /// ctrl, alt, shift, and super. As well as side specific variants
     /// of those, ctrl_l, ctrl_r, alt_l, alt_r, shift_l, shift_r,
-    /// super_l, super_r.
+    /// super_l, super_r. The last component is the name of the key,
+    /// e.g. ctrl+shift+a, or alt_l+F1.
+    ///
+    /// Keybinds fire when the key combo is pressed while the widget,
+    /// or one of it's children, has focus. Keybinds on the root
+    /// widget of a view therefore apply to the whole view.
     pub key: String,
     /// event() yields null when the key combo is pressed
     pub expr: Expr,
@@ -124,7 +130,8 @@ pub struct Table {
     /// ```ignore
     /// (null | column_types)
     /// column_types: [[<name>, typename, properties], ...]
-    /// typename: ("text" | "toggle" | "image" | "combo" | "spin" | "progress" | "hidden")
+    /// typename: ("text" | "toggle" | "image" | "combo" | "spin" | "progress"
+    ///           | "sparkline" | "hidden")
     /// properties: match typename
     ///   common:
     ///     ["source", <column-name>],
@@ -283,13 +290,45 @@ pub struct Table {
     ///       optional, the column specifying the inverted property for each row
     ///  ]
     ///
+    ///  "sparkline": [
+    ///    common,
+    ///
+    ///    ["history", <n>],
+    ///      optional, default 60. The number of values to draw. If the
+    ///      source value is an array of numbers it is taken to be the
+    ///      history sent by the publisher and the last n elements are
+    ///      drawn. Otherwise the browser accumulates the last n
+    ///      numeric values of each cell while it is subscribed.
+    ///
+    ///    ["color", <color-string>],
+    ///      optional, the color of the line, same format as the
+    ///      "foreground" attribute of text. Defaults to the text color.
+    ///
+    ///    ["color-column", <column-name>]
+    ///      optional, the column specifying the color for each row
+    ///
+    ///    ["min", <n>],
+    ///      optional, the value drawn at the bottom of the cell. If not
+    ///      specified the minimum of the drawn values is used.
+    ///
+    ///    ["min-column", <column-name>]
+    ///      optional, the column specifying the min for each row
+    ///
+    ///    ["max", <n>],
+    ///      optional, the value drawn at the top of the cell. If not
+    ///      specified the maximum of the drawn values is used.
+    ///
+    ///    ["max-column", <column-name>]
+    ///      optional, the column specifying the max for each row
+    ///  ]
+    ///
     ///  "hidden":
     ///    hidden is a special column type that has no properties. It
     ///    is used to hide data columns that other visible columns
     ///    depend on (so they must appear in the model), but that you
     ///    don't want to show to the user.
     ///
-    ///  all the properties of progress are optional. If none are set
+    ///  all the properties of progress and sparkline are optional. If none are set
     ///  the entire properties array may be omitted
     /// ```
     ///
@@ -318,6 +357,21 @@ pub struct Table {
     #[serde(default)]
     pub column_types: Expr,
     /// ```ignore
+    /// (null | footer)
+    /// footer: [[<column>, aggregate], ...]
+    /// aggregate: ("sum" | "avg" | "count" | "min" | "max")
+    /// ```
+    /// - null: no footer is shown
+    /// - footer: show a footer row pinned to the bottom of the table
+    /// displaying the specified aggregate of each listed column. Columns
+    /// not listed have an empty footer cell. Aggregates are computed
+    /// over the rows that pass the row filter, and update live as the
+    /// underlying data changes. "count" is the number of rows that have
+    /// a value in the column, the other aggregates ignore values that
+    /// are not numbers.
+    #[serde(default)]
+    pub footer: Expr,
+    /// ```ignore
     /// ("none" | "single" | "multi")
     /// ```
     /// - "none": user selection is not allowed. The cursor (text focus)
@@ -368,7 +422,9 @@ pub struct Table {
     /// When the user edits a cell event() will yield an array. The
     /// first element will be the full path to the source of the cell
     /// that was edited. The second element will be the new value of
-    /// the cell.
+    /// the cell. The cell shows the new value as pending until the
+    /// publisher updates it, if that doesn't happen within 10 seconds
+    /// the edit is reverted.
     #[serde(default)]
     pub on_edit: Expr,
     /// event() will yield the name of the column header that was
@@ -388,6 +444,7 @@ impl Default for Table {
             column_widths: Expr::default(),
             columns_resizable: Expr::default(),
             column_types: Expr::default(),
+            footer: Expr::default(),
             selection_mode: ExprKind::Constant(Value::from("single")).to_expr(),
             selection: Expr::default(),
             show_row_name: ExprKind::Constant(Value::True).to_expr(),
@@ -404,11 +461,18 @@ impl Default for Table {
 pub struct Image {
     /// (<icon-name> | icon-spec | <image-bytes> | image-spec)
     /// icon-spec: [<icon-name>, icon-size]
-    /// icon-size: ("menu" | "small-toolbar" | "large-toolbar" | "dnd" | "dialog")
+    /// icon-size: ("menu" | "small-toolbar" | "large-toolbar" | "dnd" | "dialog"
+    ///             | <pixels>)
     /// image-spec: [
     ///    ["image", <image-bytes>],
     ///      required, the image bytes.
     ///
+    ///    ["region", [<x>, <y>, <width>, <height>]],
+    ///      optional, display only the specified rectangle of the
+    ///      image, e.g. one icon from a sprite sheet. The region is
+    ///      cut out before the image is scaled. If it doesn't fit
+    ///      inside the image nothing is displayed.
+    ///
     ///    ["width", <desired-width>],
     ///      optional, if specified the image will be scaled to the
     ///      specified width. If keep-aspect is true then the height
@@ -422,16 +486,31 @@ pub struct Image {
     ///      if width is not specified.
     ///
     ///    ["keep-aspect", (true | false)]
-    ///      optional, keep the aspect ratio of the image.
+    ///      optional, keep the aspect ratio of the image. Ignored if
+    ///      scale is specified.
+    ///
+    ///    ["scale", ("fit" | "fill" | "stretch")]
+    ///      optional, how to scale the image when both width and
+    ///      height are specified. "fit" scales the whole image to fit
+    ///      inside width x height keeping the aspect ratio, "fill"
+    ///      scales the image to cover width x height keeping the
+    ///      aspect ratio and crops the excess around the center, and
+    ///      "stretch" scales to exactly width x height. The default
+    ///      is "fit", or "stretch" if keep-aspect is false.
     /// ]
     /// - <icon-name>: A string naming the stock icon from the current
     /// theme that should be displayed. The default size is "small-toolbar".
     /// - icon-spec: A pair specifying the icon name and the icon size.
-    /// - icon-size: The size of the icon
+    /// - icon-size: The size of the icon, either one of the named
+    /// sizes, or a number of pixels.
     /// - <image-bytes>: A bytes value containing the image in any
     /// format supported by gdk_pixbuf.
     /// - image-spec: an alist containing the image bytes in any format
     /// supported by gdk_pixbuf and some metadata.
+    ///
+    /// Images can be stored in netidx as bytes values, e.g.
+    /// `load("/app/icons/logo")`, or combined with metadata,
+    /// e.g. `[["image", load("/app/icons/sprites")], ["region", [0, 0, 32, 32]]]`
     #[serde(default)]
     pub spec: Expr,
     /// event will yield null when the image is clicked
@@ -514,20 +593,56 @@ pub struct ToggleButton {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SpinButton {
+    /// The current value of the spin button, as a float
     #[serde(default)]
-    pub current: Expr,
+    pub value: Expr,
     /// The minimum value that can be entered
     #[serde(default)]
     pub min: Expr,
     /// The maximum value that can be entered
     #[serde(default)]
     pub max: Expr,
+    /// The amount the value changes when the user clicks the up or
+    /// down buttons, as a float
+    #[serde(default)]
+    pub step: Expr,
+    /// The number of decimal places to display, as an integer
+    #[serde(default)]
+    pub digits: Expr,
     /// event() will yield the new value when the spinbutton is
     /// changed.
     #[serde(default)]
     pub on_change: Expr,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Calendar {
+    /// The currently selected date, as a datetime. Only the date part
+    /// is displayed.
+    #[serde(default)]
+    pub value: Expr,
+    /// event() will yield the selected date, as a datetime at
+    /// midnight UTC, when the user selects a day.
+    #[serde(default)]
+    pub on_change: Expr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RadioGroup {
+    #[serde(default)]
+    pub direction: Direction,
+    /// [choice, ...]
+    /// choice: [<id>, <label>]
+    #[serde(default)]
+    pub choices: Expr,
+    /// The id of the currently selected choice
+    #[serde(default)]
+    pub selected: Expr,
+    /// event() will yield the id of the choice the user just selected
+    #[serde(default)]
+    pub on_change: Expr,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ComboBox {
     /// [choice, ...]
@@ -629,6 +744,11 @@ pub struct Scale {
     /// The maximum value of the scale, as a float
     #[serde(default)]
     pub max: Expr,
+    /// The amount the value changes when the user moves the slider
+    /// with the keyboard, as a float. If null the default step is
+    /// used.
+    #[serde(default)]
+    pub step: Expr,
     /// event() will yield the new value of the scale when it changes
     #[serde(default)]
     pub on_change: Expr,
@@ -811,6 +931,48 @@ pub struct Notebook {
     pub on_switch_page: Expr,
 }
 
+/// One layout of a `Responsive` container. The layout matches when
+/// the browser window is within all of the bounds that are set, a
+/// layout with no bounds always matches.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Breakpoint {
+    /// The minimum window width in pixels
+    #[serde(default)]
+    pub min_width: Option<u32>,
+    /// The maximum window width in pixels
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    /// The minimum window height in pixels
+    #[serde(default)]
+    pub min_height: Option<u32>,
+    /// The maximum window height in pixels
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub widget: boxed::Box<Widget>,
+}
+
+impl Breakpoint {
+    pub fn matches(&self, width: u32, height: u32) -> bool {
+        self.min_width.map(|w| width >= w).unwrap_or(true)
+            && self.max_width.map(|w| width <= w).unwrap_or(true)
+            && self.min_height.map(|h| height >= h).unwrap_or(true)
+            && self.max_height.map(|h| height <= h).unwrap_or(true)
+    }
+}
+
+/// Alternative layouts of the same part of a view, e.g. a box with
+/// a side panel for wide windows, and one without it for narrow
+/// ones. Whenever the browser window is resized the first child that
+/// is a matching `Breakpoint` is shown, children that aren't
+/// breakpoints always match. If nothing matches the first child is
+/// shown.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Responsive {
+    #[serde(default)]
+    pub children: Vec<Widget>,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
 pub struct RGB {
     #[serde(default)]
@@ -867,6 +1029,17 @@ pub struct LinePlot {
     pub keep_points: Expr,
     #[serde(default)]
     pub series: Vec<Series>,
+    /// allow the user to zoom with the scroll wheel and pan by
+    /// dragging, double click restores the normal range
+    #[serde(default)]
+    pub zoom: bool,
+    /// draw a crosshair on the point nearest the mouse pointer and
+    /// show it's x and y values
+    #[serde(default)]
+    pub cursor: bool,
+    /// event() will yield [x, y] when the cursor moves to a new point
+    #[serde(default)]
+    pub on_cursor: Expr,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -887,6 +1060,9 @@ pub enum WidgetKind {
     SearchEntry(SearchEntry),
     ProgressBar(ProgressBar),
     Scale(Scale),
+    SpinButton(SpinButton),
+    Calendar(Calendar),
+    RadioGroup(RadioGroup),
     Image(Image),
     Frame(Frame),
     Box(Box),
@@ -898,6 +1074,8 @@ pub enum WidgetKind {
     Notebook(Notebook),
     NotebookPage(NotebookPage),
     LinePlot(LinePlot),
+    Responsive(Responsive),
+    Breakpoint(Breakpoint),
 }
 
 impl Default for WidgetKind {
@@ -935,6 +1113,34 @@ pub struct WidgetProps {
     /// Key bindings
     #[serde(default)]
     pub keybinds: Vec<Keybind>,
+    /// Translations of the text in the view, by locale, e.g. de_DE,
+    /// or de, and then by key. tr("key") yields the translation of
+    /// key for the browser's locale, or key itself if there is no
+    /// translation. Translations apply to the whole view, and should
+    /// normally be set on the root widget.
+    #[serde(default)]
+    pub translations: BTreeMap<String, BTreeMap<String, String>>,
+    /// Functions, `def name(a, b) = body`, that can be called from
+    /// any expression in the view. Like translations they apply to
+    /// the whole view, and should normally be set on the root widget.
+    #[serde(default)]
+    pub functions: Vec<FnDef>,
+    /// A GTK CSS style sheet applied while the view is displayed.
+    /// Every widget is named after it's position in the view, the
+    /// root widget is #netidx, it's second child is #netidx-b1, and
+    /// the child in row 2 column 0 of a grid below that is
+    /// #netidx-b1-g2-0. Like translations the style sheet applies to
+    /// the whole view, and should normally be set on the root widget.
+    #[serde(default)]
+    pub css: String,
+    /// If not empty, the runtime state of the widget can be read
+    /// anywhere in the view with widget("name/field"). A table
+    /// exports it's `selection`, an array of the selected paths, and
+    /// `scroll`, it's vertical scroll position. A notebook exports
+    /// it's current `page`, a responsive container the index of it's
+    /// current `layout`, and an entry or search entry it's `text`.
+    #[serde(default)]
+    pub name: String,
     /// (true | false)
     /// true: The widget can be interacted with
     /// false: The widget can't be interacted with
@@ -945,6 +1151,22 @@ pub struct WidgetProps {
     /// false: The widget and all it's children are not visible
     #[serde(default)]
     pub visible: Expr,
+    /// (null | true | false)
+    /// null: No interlock
+    /// true: Event handlers and keybinds run normally
+    /// false: Event handlers and keybinds of the widget and all it's
+    /// children are ignored, so they can't write anything. For
+    /// example, set to load("/plant/pump/armed") on the root widget,
+    /// no control in the view does anything until a second operator
+    /// arms it. See guard.
+    #[serde(default)]
+    pub interlock: Expr,
+    /// Redraw the widget at most this many times per second. Updates
+    /// arriving in between are coalesced, only the latest value of
+    /// each subscription is delivered. Set on the root widget it
+    /// limits the whole view.
+    #[serde(default)]
+    pub refresh_rate: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -0,0 +1,328 @@
+use crate::publisher;
+use anyhow::{Context, Result};
+use arcstr::ArcStr;
+use futures::{channel::mpsc, future, prelude::*, select_biased};
+use fxhash::{FxHashMap, FxHashSet};
+use log::{info, warn};
+use netidx::{
+    chars::Chars,
+    config::Config,
+    path::Path,
+    pool::Pooled,
+    protocol::glob::{Glob, GlobSet},
+    publisher::{Id, Publisher, PublisherBuilder, UpdateBatch, Val, Value, WriteRequest},
+    resolver_client::{ChangeTracker, DesiredAuth, ResolverRead},
+    subscriber::{Dval, Event, SubId, Subscriber, UpdatesFlags},
+};
+use netidx_tools_core::ClientParams;
+use std::{iter, mem, time::Duration};
+use structopt::StructOpt;
+use tokio::time;
+
+/// The name of the path each bridge publishes at the root of its
+/// destination. It holds the ids of every bridge the data has passed
+/// through, which is how loops are detected.
+const MARKER: &str = ".netidx-bridge";
+
+#[derive(StructOpt, Debug)]
+pub(super) struct Params {
+    #[structopt(long = "src-config", help = "path to the source realm client config")]
+    src_config: Option<String>,
+    #[structopt(long = "src-auth", help = "auth mechanism for the source realm")]
+    src_auth: Option<DesiredAuth>,
+    #[structopt(long = "src-upn", help = "kerberos upn for the source realm")]
+    src_upn: Option<String>,
+    #[structopt(long = "src-spn", help = "kerberos spn for the source realm")]
+    src_spn: Option<String>,
+    #[structopt(long = "src-identity", help = "tls identity for the source realm")]
+    src_identity: Option<String>,
+    #[structopt(long = "from", help = "the path to bridge from the source realm")]
+    from: String,
+    #[structopt(long = "to", help = "where to publish it, default the same path")]
+    to: Option<String>,
+    #[structopt(
+        long = "throttle",
+        help = "publish at most one update per path every N milliseconds"
+    )]
+    throttle: Option<u64>,
+    #[structopt(long = "writes", help = "forward writes back to the source realm")]
+    writes: bool,
+    #[structopt(
+        long = "poll-interval",
+        help = "how often to look for new paths (seconds)",
+        default_value = "5"
+    )]
+    poll_interval: u64,
+}
+
+impl Params {
+    fn source(&self) -> ClientParams {
+        ClientParams {
+            config: self.src_config.clone(),
+            auth: self.src_auth.clone(),
+            upn: self.src_upn.clone(),
+            spn: self.src_spn.clone(),
+            identity: self.src_identity.clone(),
+        }
+    }
+}
+
+/// true if the two configs share a resolver server
+fn same_realm(c0: &Config, c1: &Config) -> bool {
+    c0.addrs.iter().any(|(a0, _)| c1.addrs.iter().any(|(a1, _)| a0 == a1))
+}
+
+async fn maybe_interval(poll: &mut Option<time::Interval>) {
+    match poll {
+        None => future::pending().await,
+        Some(poll) => {
+            poll.tick().await;
+        }
+    }
+}
+
+struct Mirror {
+    dv: Dval,
+    val: Val,
+}
+
+struct Bridge {
+    id: Chars,
+    from: Path,
+    to: Path,
+    timeout: Option<Duration>,
+    forward_writes: bool,
+    subscriber: Subscriber,
+    publisher: Publisher,
+    updates: mpsc::Sender<Pooled<Vec<(SubId, Event)>>>,
+    writes: mpsc::Sender<Pooled<Vec<WriteRequest>>>,
+    mirrors: FxHashMap<Path, Mirror>,
+    by_sub: FxHashMap<SubId, Path>,
+    by_id: FxHashMap<Id, Path>,
+    /// the markers of bridges upstream of us
+    upstream: FxHashMap<Path, Dval>,
+    /// the bridge ids carried by each upstream marker
+    upstream_ids: FxHashMap<SubId, Vec<Chars>>,
+    marker: Val,
+    /// the latest value of each throttled path since the last flush
+    pending: FxHashMap<SubId, Value>,
+    throttled: bool,
+}
+
+impl Bridge {
+    fn marker_value(&self) -> Value {
+        let mut ids = iter::once(self.id.clone())
+            .chain(self.upstream_ids.values().flat_map(|ids| ids.iter().cloned()))
+            .collect::<Vec<_>>();
+        ids[1..].sort();
+        ids.dedup();
+        Value::from(ids)
+    }
+
+    fn add(&mut self, path: Path) -> Result<()> {
+        if Path::basename(&path) == Some(MARKER) {
+            let dv = self.subscriber.subscribe(path.clone());
+            dv.updates(UpdatesFlags::BEGIN_WITH_LAST, self.updates.clone());
+            self.upstream_ids.insert(dv.id(), vec![]);
+            self.upstream.insert(path, dv);
+            return Ok(());
+        }
+        let rel = Path::strip_prefix(&self.from, &path).unwrap_or("");
+        let dst = self.to.append(rel);
+        let dv = self.subscriber.subscribe(path.clone());
+        let val = self
+            .publisher
+            .publish(dst.clone(), Value::Null)
+            .with_context(|| format!("publishing {}", dst))?;
+        if self.forward_writes {
+            self.publisher.writes(val.id(), self.writes.clone());
+        }
+        dv.updates(
+            UpdatesFlags::BEGIN_WITH_LAST | UpdatesFlags::STOP_COLLECTING_LAST,
+            self.updates.clone(),
+        );
+        self.by_sub.insert(dv.id(), path.clone());
+        self.by_id.insert(val.id(), path.clone());
+        self.mirrors.insert(path, Mirror { dv, val });
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(dv) = self.upstream.remove(path) {
+            self.upstream_ids.remove(&dv.id());
+        }
+        if let Some(m) = self.mirrors.remove(path) {
+            self.by_sub.remove(&m.dv.id());
+            self.by_id.remove(&m.val.id());
+            self.pending.remove(&m.dv.id());
+        }
+    }
+
+    async fn rescan(
+        &mut self,
+        resolver: &ResolverRead,
+        ct: &mut ChangeTracker,
+        spec: &GlobSet,
+    ) -> Result<()> {
+        if !resolver.check_changed(ct).await.context("checking for changes")? {
+            return Ok(());
+        }
+        let mut present = FxHashSet::default();
+        for paths in resolver.list_matching(spec).await.context("listing")?.iter() {
+            present.extend(paths.iter().cloned());
+        }
+        let gone = self
+            .mirrors
+            .keys()
+            .chain(self.upstream.keys())
+            .filter(|p| !present.contains(*p))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in gone {
+            info!("{} is gone", path);
+            self.remove(&path);
+        }
+        for path in present {
+            if !self.mirrors.contains_key(&path) && !self.upstream.contains_key(&path) {
+                self.add(path)?
+            }
+        }
+        let mut batch = self.publisher.start_batch();
+        self.marker.update_changed(&mut batch, self.marker_value());
+        batch.commit(self.timeout).await;
+        Ok(())
+    }
+
+    fn update_upstream(&mut self, id: SubId, v: Value) -> Result<()> {
+        let ids = v.cast_to::<Vec<Chars>>().unwrap_or_else(|_| vec![]);
+        if ids.contains(&self.id) {
+            bail!("loop detected, {} is downstream of this bridge", self.from)
+        }
+        self.upstream_ids.insert(id, ids);
+        Ok(())
+    }
+
+    async fn process_updates(
+        &mut self,
+        mut batch: Pooled<Vec<(SubId, Event)>>,
+    ) -> Result<()> {
+        let mut pbatch = self.publisher.start_batch();
+        let mut upstream_changed = false;
+        for (id, ev) in batch.drain(..) {
+            let v = match ev {
+                Event::Update(v) | Event::Unpublished(v) => v,
+                Event::Unsubscribed | Event::Stale => continue,
+            };
+            if self.upstream_ids.contains_key(&id) {
+                self.update_upstream(id, v)?;
+                upstream_changed = true;
+            } else if self.throttled {
+                self.pending.insert(id, v);
+            } else {
+                self.update(&mut pbatch, id, v)
+            }
+        }
+        if upstream_changed {
+            self.marker.update_changed(&mut pbatch, self.marker_value());
+        }
+        pbatch.commit(self.timeout).await;
+        Ok(())
+    }
+
+    fn update(&self, batch: &mut UpdateBatch, id: SubId, v: Value) {
+        if let Some(m) = self.by_sub.get(&id).and_then(|p| self.mirrors.get(p)) {
+            m.val.update(batch, v)
+        }
+    }
+
+    async fn flush(&mut self) {
+        if self.pending.len() > 0 {
+            let mut batch = self.publisher.start_batch();
+            for (id, v) in mem::take(&mut self.pending) {
+                self.update(&mut batch, id, v)
+            }
+            batch.commit(self.timeout).await
+        }
+    }
+
+    fn process_writes(&mut self, mut batch: Pooled<Vec<WriteRequest>>) {
+        for req in batch.drain(..) {
+            if let Some(m) = self.by_id.get(&req.id).and_then(|p| self.mirrors.get(p)) {
+                m.dv.write(req.value);
+            }
+        }
+    }
+}
+
+pub(super) async fn run(
+    config: Config,
+    auth: DesiredAuth,
+    pparams: publisher::Params,
+    p: Params,
+) -> Result<()> {
+    let (src_config, src_auth) = p.source().load();
+    let from = Path::from(ArcStr::from(p.from.as_str()));
+    let to = p.to.as_ref().map(|s| Path::from(ArcStr::from(s.as_str())));
+    let to = to.unwrap_or_else(|| from.clone());
+    if !Path::is_absolute(&from) || !Path::is_absolute(&to) {
+        bail!("from and to must be absolute paths")
+    }
+    if same_realm(&src_config, &config)
+        && (Path::is_parent(&from, &to) || Path::is_parent(&to, &from))
+    {
+        bail!("{} and {} overlap in the same realm", from, to)
+    }
+    let resolver = ResolverRead::new(src_config.clone(), src_auth.clone());
+    let subscriber =
+        Subscriber::new(src_config, src_auth).context("creating subscriber")?;
+    let publisher = PublisherBuilder::new(config)
+        .desired_auth(auth)
+        .bind_cfg(pparams.bind)
+        .alt_bind_cfg(pparams.alt_bind)
+        .build()
+        .await
+        .context("creating publisher")?;
+    let id = Chars::from(uuid::Uuid::new_v4().to_string());
+    let marker = publisher
+        .publish(to.append(MARKER), Value::from(vec![id.clone()]))
+        .context("publishing the bridge marker")?;
+    let (tx_updates, mut rx_updates) = mpsc::channel(3);
+    let (tx_writes, mut rx_writes) = mpsc::channel(3);
+    let throttle = p.throttle.filter(|ms| *ms > 0).map(Duration::from_millis);
+    let mut bridge = Bridge {
+        id,
+        from: from.clone(),
+        to,
+        timeout: pparams.timeout.map(Duration::from_secs),
+        forward_writes: p.writes,
+        subscriber,
+        publisher,
+        updates: tx_updates,
+        writes: tx_writes,
+        mirrors: FxHashMap::default(),
+        by_sub: FxHashMap::default(),
+        by_id: FxHashMap::default(),
+        upstream: FxHashMap::default(),
+        upstream_ids: FxHashMap::default(),
+        marker,
+        pending: FxHashMap::default(),
+        throttled: throttle.is_some(),
+    };
+    let glob = Glob::new(Chars::from(format!("{}/**", from)))?;
+    let spec = GlobSet::new(true, iter::once(glob))?;
+    let mut ct = ChangeTracker::new(from);
+    let mut poll = time::interval(Duration::from_secs(p.poll_interval.max(1)));
+    let mut flush = throttle.map(time::interval);
+    loop {
+        select_biased! {
+            _ = poll.tick().fuse() => {
+                if let Err(e) = bridge.rescan(&resolver, &mut ct, &spec).await {
+                    warn!("failed to look for new paths {}, will retry", e)
+                }
+            },
+            _ = maybe_interval(&mut flush).fuse() => bridge.flush().await,
+            b = rx_updates.select_next_some() => bridge.process_updates(b).await?,
+            b = rx_writes.select_next_some() => bridge.process_writes(b),
+        }
+    }
+}
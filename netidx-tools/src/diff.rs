@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use futures::future;
+use netidx::{
+    chars::Chars,
+    config::Config,
+    path::Path,
+    protocol::glob::{Glob, GlobSet},
+    resolver_client::DesiredAuth,
+    subscriber::{Event, Subscriber, Value},
+};
+use std::{collections::BTreeMap, iter, process, time::Duration};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub(super) struct Params {
+    #[structopt(
+        long = "sync",
+        help = "write the values of src to the paths in dst that differ"
+    )]
+    sync: bool,
+    #[structopt(
+        long = "timeout",
+        help = "how long to wait for a subscription or a write (seconds)",
+        default_value = "10"
+    )]
+    timeout: u64,
+    #[structopt(name = "src")]
+    src: Path,
+    #[structopt(name = "dst")]
+    dst: Path,
+}
+
+/// The current value of every path published under `base`, keyed by
+/// the path relative to `base`.
+async fn snapshot(
+    subscriber: &Subscriber,
+    base: &Path,
+    timeout: Duration,
+) -> Result<BTreeMap<Path, Event>> {
+    let glob = Glob::new(Chars::from(format!("{}/**", base)))?;
+    let spec = GlobSet::new(true, iter::once(glob))?;
+    let mut paths = vec![];
+    for batch in subscriber.resolver().list_matching(&spec).await?.iter() {
+        paths.extend(batch.iter().cloned());
+    }
+    let subs = paths.iter().map(|path| async move {
+        match subscriber.subscribe_nondurable_one(path.clone(), Some(timeout)).await {
+            Ok(val) => val.last(),
+            Err(_) => Event::Unsubscribed,
+        }
+    });
+    let values = future::join_all(subs).await;
+    Ok(paths
+        .into_iter()
+        .zip(values)
+        .map(|(path, ev)| {
+            let rel = Path::strip_prefix(base, &path).unwrap_or("");
+            (Path::from(String::from(rel)), ev)
+        })
+        .collect())
+}
+
+fn fmt_event(ev: &Event) -> String {
+    match ev {
+        Event::Update(v) => format!("{}", v),
+        Event::Unsubscribed => String::from("<unsubscribed>"),
+        Event::Unpublished(v) => format!("<unpublished: {}>", v),
+        Event::Stale => String::from("<stale>"),
+    }
+}
+
+pub(super) async fn run(cfg: Config, auth: DesiredAuth, p: Params) -> Result<()> {
+    if !Path::is_absolute(&p.src) || !Path::is_absolute(&p.dst) {
+        bail!("src and dst must be absolute paths")
+    }
+    let timeout = Duration::from_secs(p.timeout);
+    let subscriber = Subscriber::new(cfg, auth).context("creating subscriber")?;
+    let (src, dst) = future::try_join(
+        snapshot(&subscriber, &p.src, timeout),
+        snapshot(&subscriber, &p.dst, timeout),
+    )
+    .await
+    .context("listing paths")?;
+    let mut differences = 0;
+    let mut to_write: Vec<(Path, Value)> = vec![];
+    for (rel, sv) in src.iter() {
+        match dst.get(rel) {
+            None => {
+                differences += 1;
+                println!("- {}: {}", rel, fmt_event(sv))
+            }
+            Some(dv) if dv == sv => (),
+            Some(dv) => {
+                differences += 1;
+                println!("~ {}: {} -> {}", rel, fmt_event(sv), fmt_event(dv));
+                if let Event::Update(v) = sv {
+                    to_write.push((p.dst.append(rel), v.clone()))
+                }
+            }
+        }
+    }
+    for (rel, dv) in dst.iter() {
+        if !src.contains_key(rel) {
+            differences += 1;
+            println!("+ {}: {}", rel, fmt_event(dv))
+        }
+    }
+    if !p.sync {
+        // like diff, exit with 1 when the trees differ
+        if differences > 0 {
+            process::exit(1)
+        }
+        return Ok(());
+    }
+    let mut failed = 0;
+    let writes = to_write.iter().map(|(path, v)| {
+        let subscriber = &subscriber;
+        async move {
+            let val =
+                subscriber.subscribe_nondurable_one(path.clone(), Some(timeout)).await?;
+            match val.write_with_timeout(v.clone(), timeout).await? {
+                Value::Error(e) => bail!("{}", e),
+                _ => Ok(()),
+            }
+        }
+    });
+    for ((path, _), r) in to_write.iter().zip(future::join_all(writes).await) {
+        if let Err(e) = r {
+            failed += 1;
+            eprintln!("failed to write {}: {}", path, e)
+        }
+    }
+    println!("wrote {} of {} changed values", to_write.len() - failed, to_write.len());
+    let missing = src.keys().filter(|rel| !dst.contains_key(*rel)).count();
+    if missing > 0 {
+        println!("{} paths in src are not published in dst and were skipped", missing)
+    }
+    if failed > 0 {
+        bail!("{} writes failed", failed)
+    }
+    Ok(())
+}
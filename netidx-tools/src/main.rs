@@ -1,4 +1,6 @@
 #![recursion_limit = "2048"]
+mod bridge;
+mod diff;
 mod publisher;
 mod record_client;
 mod resolver;
@@ -120,6 +122,22 @@ enum Opt {
         #[structopt(subcommand)]
         cmd: Stress,
     },
+    #[structopt(name = "bridge", about = "republish paths from another realm")]
+    Bridge {
+        #[structopt(flatten)]
+        common: ClientParams,
+        #[structopt(flatten)]
+        publisher: publisher::Params,
+        #[structopt(flatten)]
+        params: bridge::Params,
+    },
+    #[structopt(name = "diff", about = "compare two subtrees, or sync one to the other")]
+    Diff {
+        #[structopt(flatten)]
+        common: ClientParams,
+        #[structopt(flatten)]
+        params: diff::Params,
+    },
     #[structopt(name = "wsproxy", about = "websocket proxy")]
     WsProxy {
         #[structopt(flatten)]
@@ -178,6 +196,14 @@ async fn tokio_main() -> Result<()> {
                 stress_channel_subscriber::run(cfg, auth, params).await
             }
         },
+        Opt::Bridge { common, publisher, params } => {
+            let (cfg, auth) = common.load();
+            bridge::run(cfg, auth, publisher, params).await
+        }
+        Opt::Diff { common, params } => {
+            let (cfg, auth) = common.load();
+            diff::run(cfg, auth, params).await
+        }
         Opt::WsProxy { common, publisher, proxy } => {
             let (cfg, auth) = common.load();
             wsproxy::run(cfg, auth, publisher, proxy).await
@@ -31,6 +31,11 @@ pub(crate) struct Params {
         help = "configure the bind address e.g. local, 192.168.0.0/16"
     )]
     pub(crate) bind: Option<BindCfg>,
+    #[structopt(
+        long = "alt-bind",
+        help = "also listen on this address, usually of the other family, e.g. ::1/128"
+    )]
+    pub(crate) alt_bind: Option<BindCfg>,
     #[structopt(
         long = "timeout",
         help = "require subscribers to consume values before timeout (seconds)"
@@ -86,6 +91,7 @@ pub(super) async fn run(config: Config, auth: DesiredAuth, params: Params) -> Re
     let publisher = PublisherBuilder::new(config)
         .desired_auth(auth)
         .bind_cfg(params.bind)
+        .alt_bind_cfg(params.alt_bind)
         .build()
         .await
         .context("creating publisher")?;
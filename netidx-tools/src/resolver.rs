@@ -1,13 +1,27 @@
 use anyhow::{Context, Result};
 use arcstr::ArcStr;
+use chrono::prelude::*;
+use futures::future;
+use fxhash::FxHashMap;
 use netidx::{
     chars::Chars,
     config::Config,
     path::Path,
     protocol::glob::{Glob, GlobSet},
-    resolver_client::{ChangeTracker, DesiredAuth, ResolverRead, ResolverWrite},
+    resolver_client::{
+        ChangeTracker, DesiredAuth, FromAdmin, ResolverRead, ResolverWrite, StoreEntry,
+        ToAdmin,
+    },
+};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    iter,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
 };
-use std::{collections::HashSet, iter, net::SocketAddr, time::Duration};
 use structopt::StructOpt;
 use tokio::time;
 
@@ -51,6 +65,68 @@ pub(super) enum ResolverCmd {
         #[structopt(name = "socketaddr")]
         socketaddr: SocketAddr,
     },
+    #[structopt(name = "publishers", about = "list publishers (admin)")]
+    Publishers,
+    #[structopt(
+        name = "clear-publisher",
+        about = "unpublish everything published by a dead publisher (admin)"
+    )]
+    ClearPublisher {
+        #[structopt(name = "socketaddr")]
+        socketaddr: SocketAddr,
+    },
+    #[structopt(name = "dump", about = "dump everything published (admin)")]
+    Dump {
+        #[structopt(long = "file", short = "f", help = "write the dump to file")]
+        file: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "load",
+        about = "publish the entries in a dump, and keep them alive until killed (anonymous auth only)"
+    )]
+    Load {
+        #[structopt(name = "file")]
+        file: PathBuf,
+    },
+    #[structopt(name = "stats", about = "show per client statistics (admin)")]
+    Stats,
+    #[structopt(
+        name = "reload",
+        about = "reload permissions and quotas from the server config file (admin)"
+    )]
+    Reload,
+}
+
+/// Send an admin request to every member of the root resolver
+/// cluster, calling `f` with each member's reply.
+async fn admin<F>(resolver: &ResolverRead, req: ToAdmin, mut f: F) -> Result<()>
+where
+    F: FnMut(SocketAddr, FromAdmin) -> Result<()>,
+{
+    let replies = resolver.admin(&Path::root(), req).await.context("admin request")?;
+    for (member, reply) in replies {
+        f(member, reply)?
+    }
+    Ok(())
+}
+
+fn format_entry(e: &StoreEntry) -> String {
+    let kind = if e.default { "default" } else { "path" };
+    format!("{}\t{}\t{}\t{}", e.path, e.publisher, kind, e.flags)
+}
+
+fn parse_entry(s: &str) -> Result<StoreEntry> {
+    let mut fields = s.rsplitn(4, '\t');
+    let mut field = || fields.next().ok_or_else(|| anyhow!("truncated entry {}", s));
+    let flags = field()?.parse::<u32>()?;
+    let default = match field()? {
+        "default" => true,
+        "path" => false,
+        k => bail!("invalid entry kind {}", k),
+    };
+    let publisher = field()?.parse::<SocketAddr>()?;
+    let path = Path::from(ArcStr::from(field()?));
+    Ok(StoreEntry { path, publisher, default, flags })
 }
 
 pub(super) async fn run(
@@ -135,6 +211,123 @@ pub(super) async fn run(
                 .context("create resolver write")?;
             resolver.unpublish(vec![path]).await.context("remove publisher")?;
         }
+        ResolverCmd::Publishers => {
+            let resolver = ResolverRead::new(config, auth);
+            admin(&resolver, ToAdmin::Publishers, |member, reply| match reply {
+                FromAdmin::Publishers(publishers) => {
+                    for p in publishers.iter() {
+                        let user = p.user_info.as_ref().map(|u| &*u.name).unwrap_or("");
+                        println!("{}: {} {:?} {}", member, p.addr, p.target_auth, user)
+                    }
+                    Ok(())
+                }
+                m => bail!("unexpected reply from {} {:?}", member, m),
+            })
+            .await?
+        }
+        ResolverCmd::ClearPublisher { socketaddr } => {
+            let resolver = ResolverRead::new(config, auth);
+            let req = ToAdmin::ClearPublisher(socketaddr);
+            admin(&resolver, req, |member, reply| match reply {
+                FromAdmin::Cleared(found) => {
+                    let status = if found { "cleared" } else { "not found" };
+                    println!("{}: {}", member, status);
+                    Ok(())
+                }
+                m => bail!("unexpected reply from {} {:?}", member, m),
+            })
+            .await?
+        }
+        ResolverCmd::Dump { file } => {
+            let resolver = ResolverRead::new(config, auth);
+            let mut entries = HashSet::new();
+            admin(&resolver, ToAdmin::Dump, |member, reply| match reply {
+                FromAdmin::Dump(mut dump) => {
+                    entries.extend(dump.drain(..).map(|e| format_entry(&e)));
+                    Ok(())
+                }
+                m => bail!("unexpected reply from {} {:?}", member, m),
+            })
+            .await?;
+            let mut entries = entries.into_iter().collect::<Vec<_>>();
+            entries.sort();
+            let mut out: Box<dyn Write> = match file {
+                None => Box::new(io::stdout()),
+                Some(file) => Box::new(fs::File::create(file).context("create dump")?),
+            };
+            for e in entries {
+                writeln!(out, "{}", e)?
+            }
+        }
+        ResolverCmd::Load { file } => {
+            // the entries are published on behalf of the publisher
+            // addresses in the dump, an authenticated resolver would
+            // check that we own them, which we don't.
+            if !matches!(auth, DesiredAuth::Anonymous) {
+                bail!("load only works with anonymous auth")
+            }
+            let dump = fs::read_to_string(file).context("read dump")?;
+            let mut by_publisher: FxHashMap<SocketAddr, Vec<StoreEntry>> =
+                FxHashMap::default();
+            for line in dump.lines().filter(|l| !l.is_empty()) {
+                let e = parse_entry(line)?;
+                by_publisher.entry(e.publisher).or_insert_with(Vec::new).push(e);
+            }
+            let mut writers = Vec::new();
+            for (addr, mut entries) in by_publisher {
+                let resolver = ResolverWrite::new(config.clone(), auth.clone(), addr)
+                    .context("create resolver write")?;
+                let (defaults, paths): (Vec<_>, Vec<_>) =
+                    entries.drain(..).partition(|e| e.default);
+                let flags = |e: StoreEntry| (e.path, Some(e.flags).filter(|f| *f != 0));
+                resolver
+                    .publish_with_flags(paths.into_iter().map(flags))
+                    .await
+                    .context("publish")?;
+                resolver
+                    .publish_default_with_flags(defaults.into_iter().map(flags))
+                    .await
+                    .context("publish default")?;
+                writers.push(resolver);
+            }
+            future::pending::<()>().await
+        }
+        ResolverCmd::Stats => {
+            let resolver = ResolverRead::new(config, auth);
+            admin(&resolver, ToAdmin::ClientStats, |member, reply| match reply {
+                FromAdmin::ClientStats(stats) => {
+                    for s in stats.iter() {
+                        let connected = DateTime::<Utc>::from(
+                            SystemTime::UNIX_EPOCH + Duration::from_secs(s.connected),
+                        );
+                        println!(
+                            "{}: {} {} {} connected {} batches {} messages {}",
+                            member,
+                            s.addr,
+                            if s.publisher { "publisher" } else { "subscriber" },
+                            s.user.as_ref().map(|u| &**u).unwrap_or("anonymous"),
+                            connected,
+                            s.batches,
+                            s.messages
+                        )
+                    }
+                    Ok(())
+                }
+                m => bail!("unexpected reply from {} {:?}", member, m),
+            })
+            .await?
+        }
+        ResolverCmd::Reload => {
+            let resolver = ResolverRead::new(config, auth);
+            admin(&resolver, ToAdmin::Reload, |member, reply| match reply {
+                FromAdmin::Reloaded => {
+                    println!("{}: reloaded", member);
+                    Ok(())
+                }
+                m => bail!("unexpected reply from {} {:?}", member, m),
+            })
+            .await?
+        }
     }
     Ok(())
 }
@@ -3,6 +3,8 @@ use anyhow::{Context, Result};
 use daemonize::Daemonize;
 use futures::future;
 #[cfg(unix)]
+use log::{error, info};
+#[cfg(unix)]
 use netidx::resolver_server::config::file;
 use netidx::resolver_server::{config::Config, Server};
 #[cfg(unix)]
@@ -29,12 +31,35 @@ pub(crate) struct Params {
     id: usize,
 }
 
+/// Reload the permissions and quotas from the config file on SIGHUP
+#[cfg(unix)]
+async fn reload_on_sighup(server: &Server, config: &str) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup())?;
+    while let Some(()) = sighup.recv().await {
+        let res = match Config::load(config) {
+            Err(e) => Err(e),
+            Ok(config) => server.reload(config).await,
+        };
+        match res {
+            Ok(()) => info!("config reloaded successfully"),
+            Err(e) => error!("could not reload the config {}", e),
+        }
+    }
+    future::pending().await
+}
+
+#[cfg(windows)]
+async fn reload_on_sighup(_server: &Server, _config: &str) -> Result<()> {
+    future::pending().await
+}
+
 #[tokio::main]
 async fn tokio_run(config: Config, params: Params) -> Result<()> {
-    let _server = Server::new(config, params.delay_reads, params.id)
+    let server = Server::new(config, params.delay_reads, params.id)
         .await
         .context("starting server")?;
-    future::pending::<Result<()>>().await
+    reload_on_sighup(&server, &params.config).await
 }
 
 pub(crate) fn run(params: Params) -> Result<()> {
@@ -163,6 +163,25 @@ impl<'a> Out<'a> {
                     to_stdout.extend_from_slice(b"\n");
                 }
             }
+            Event::Stale => {
+                if !self.raw {
+                    to_stdout.extend_from_slice(b"Stale");
+                    to_stdout.extend_from_slice(b"|");
+                    to_stdout.extend_from_slice(self.path.as_bytes());
+                    to_stdout.extend_from_slice(b"\n");
+                }
+            }
+            Event::Unpublished(v) => {
+                if !self.raw {
+                    to_stdout.extend_from_slice(b"Unpublished");
+                    to_stdout.extend_from_slice(b"|");
+                    to_stdout.extend_from_slice(self.path.as_bytes());
+                    to_stdout.extend_from_slice(b"|");
+                    let w = &mut BytesWriter(to_stdout);
+                    write!(w, "{}|", Typ::get(v)).context("write line")?;
+                    writeln!(w, "{}", WVal(v)).context("finish write line")?
+                }
+            }
             Event::Update(v) => {
                 if self.raw {
                     let w = &mut BytesWriter(to_stdout);
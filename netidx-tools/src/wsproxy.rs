@@ -14,6 +14,7 @@ pub(super) async fn run(
     let publisher = PublisherBuilder::new(cfg.clone())
         .desired_auth(auth.clone())
         .bind_cfg(pcfg.bind)
+        .alt_bind_cfg(pcfg.alt_bind)
         .build()
         .await
         .context("creating publisher")?;
@@ -44,6 +44,21 @@ impl<T: Send + Sync + 'static> BatchSender<T> {
             true
         }
     }
+
+    /// send all of `ms`, the receiver will get them together in one
+    /// batch
+    pub(crate) fn send_many(&self, ms: impl IntoIterator<Item = T>) -> bool {
+        let mut inner = self.0 .0.lock();
+        if inner.recv_closed {
+            false
+        } else {
+            inner.queue.extend(ms);
+            if let Some(sender) = inner.notify.take() {
+                let _: result::Result<_, _> = sender.send(());
+            }
+            true
+        }
+    }
 }
 
 #[derive(Debug)]
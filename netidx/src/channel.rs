@@ -1,7 +1,10 @@
-use crate::{pack::Pack, utils};
+use crate::{
+    pack::{Pack, ZERO_COPY_MIN},
+    utils,
+};
 use anyhow::{anyhow, Error, Result};
 use byteorder::{BigEndian, ByteOrder};
-use bytes::{buf::UninitSlice, Buf, BufMut, BytesMut};
+use bytes::{buf::UninitSlice, Buf, BufMut, Bytes, BytesMut};
 use cross_krb5::K5Ctx;
 use futures::{
     channel::{
@@ -15,12 +18,20 @@ use futures::{
 use log::{info, trace};
 use netidx_core::pool::{Pool, Pooled};
 use parking_lot::Mutex;
+use smallvec::SmallVec;
 use std::{
     clone::Clone,
+    cmp,
+    collections::VecDeque,
     fmt::Debug,
+    io::IoSlice,
+    iter,
     mem::{self, MaybeUninit},
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::{
@@ -32,6 +43,11 @@ const BUF: usize = 8388608;
 const LEN_MASK: u32 = 0x7FFFFFFF;
 const MAX_BATCH: usize = 0x3FFFFFFF;
 const ENC_MASK: u32 = 0x80000000;
+const COMP_MASK: u32 = 0x40000000;
+const ZSTD_LEVEL: i32 = 1;
+// the largest batch that is compressed, and so the most a compressed
+// batch may decompress to
+const MAX_DECOMPRESSED: usize = 0x4000000;
 
 #[derive(Debug)]
 pub struct K5CtxWrap<C: K5Ctx + Debug + Send + Sync + 'static>(Arc<Mutex<C>>);
@@ -106,12 +122,15 @@ async fn flush_buf<B: Buf, S: AsyncWrite + Send + 'static>(
     soc: &mut WriteHalf<S>,
     buf: B,
     encrypted: bool,
+    compressed: bool,
 ) -> Result<()> {
-    let len = if encrypted {
-        buf.remaining() as u32 | ENC_MASK
-    } else {
-        buf.remaining() as u32
-    };
+    let mut len = buf.remaining() as u32;
+    if encrypted {
+        len |= ENC_MASK
+    }
+    if compressed {
+        len |= COMP_MASK
+    }
     let lenb = len.to_be_bytes();
     let mut buf = Buf::chain(&lenb[..], buf);
     while buf.has_remaining() {
@@ -123,23 +142,147 @@ async fn flush_buf<B: Buf, S: AsyncWrite + Send + 'static>(
     Ok(())
 }
 
+/// Write a batch made of several segments with vectored IO, so large
+/// payloads don't need to be copied into a contiguous buffer.
+async fn flush_segments<S: AsyncWrite + Send + 'static>(
+    soc: &mut WriteHalf<S>,
+    segments: Vec<Bytes>,
+) -> Result<()> {
+    let len: usize = segments.iter().map(|b| b.len()).sum();
+    let lenb = Bytes::copy_from_slice(&(len as u32).to_be_bytes());
+    let mut segments: VecDeque<Bytes> = iter::once(lenb).chain(segments).collect();
+    while !segments.is_empty() {
+        let mut n = {
+            let iov = segments
+                .iter()
+                .map(|b| IoSlice::new(&b[..]))
+                .collect::<SmallVec<[IoSlice; 16]>>();
+            soc.write_vectored(&iov[..]).await?
+        };
+        trace!("flush_segments wrote {}", n);
+        if n == 0 {
+            bail!("write zero")
+        }
+        while n > 0 {
+            let seg = &mut segments[0];
+            if n >= seg.len() {
+                n -= seg.len();
+                segments.pop_front();
+            } else {
+                seg.advance(n);
+                n = 0;
+            }
+        }
+    }
+    soc.flush().await?;
+    Ok(())
+}
+
+/// A batch ready to be written to the socket
+enum Flush {
+    Contiguous(BytesMut),
+    Segmented(Vec<Bytes>),
+}
+
+impl Flush {
+    fn len(&self) -> usize {
+        match self {
+            Flush::Contiguous(b) => b.len(),
+            Flush::Segmented(s) => s.iter().map(|b| b.len()).sum(),
+        }
+    }
+
+    fn into_contiguous(self) -> BytesMut {
+        match self {
+            Flush::Contiguous(data) => data,
+            Flush::Segmented(segments) => {
+                let len = segments.iter().map(|b| b.len()).sum();
+                let mut data = BytesMut::with_capacity(len);
+                for b in segments {
+                    data.extend_from_slice(&b[..]);
+                }
+                data
+            }
+        }
+    }
+}
+
+/// Compress a batch with zstd, prefixed by its uncompressed
+/// length. Return the batch unchanged, and false, if compression
+/// doesn't make it smaller.
+fn compress(data: BytesMut) -> Result<(BytesMut, bool)> {
+    let compressed = zstd::bulk::compress(&data[..], ZSTD_LEVEL)?;
+    if compressed.len() + mem::size_of::<u32>() >= data.len() {
+        Ok((data, false))
+    } else {
+        let mut buf = BytesMut::with_capacity(compressed.len() + mem::size_of::<u32>());
+        buf.put_u32(data.len() as u32);
+        buf.extend_from_slice(&compressed[..]);
+        Ok((buf, true))
+    }
+}
+
+/// Decompress a batch compressed by `compress`. The length comes
+/// from the peer, so it only bounds the output, the buffer grows as
+/// the batch is actually decoded.
+fn decompress(data: &[u8]) -> Result<Bytes> {
+    use std::io::Read;
+    if data.len() < mem::size_of::<u32>() {
+        bail!("truncated compressed batch")
+    }
+    let len = BigEndian::read_u32(&data[0..4]) as usize;
+    if len > MAX_DECOMPRESSED {
+        bail!("compressed batch length {} exceeds max size {}", len, MAX_DECOMPRESSED)
+    }
+    let mut dec =
+        zstd::stream::read::Decoder::with_buffer(&data[4..])?.take(len as u64 + 1);
+    let mut batch = Vec::with_capacity(cmp::min(len, BUF));
+    dec.read_to_end(&mut batch)?;
+    if batch.len() != len {
+        bail!("compressed batch decompressed to {} bytes not {}", batch.len(), len)
+    }
+    Ok(Bytes::from(batch))
+}
+
 fn flush_task<
     C: K5Ctx + Debug + Send + Sync + 'static,
     S: AsyncWrite + Send + 'static,
 >(
     ctx: Option<K5CtxWrap<C>>,
     mut soc: WriteHalf<S>,
-) -> Sender<BytesMut> {
-    let (tx, mut rx): (Sender<BytesMut>, Receiver<BytesMut>) = mpsc::channel(3);
+) -> Sender<(Flush, bool)> {
+    let (tx, mut rx): (Sender<(Flush, bool)>, Receiver<(Flush, bool)>) = mpsc::channel(3);
     task::spawn(async move {
         let res = loop {
             match rx.next().await {
                 None => break Ok(()),
-                Some(data) => match ctx {
-                    None => try_cf!(flush_buf(&mut soc, data, false).await),
-                    Some(ref ctx) => {
-                        let msg = try_cf!(ctx.lock().wrap_iov(true, data));
-                        try_cf!(flush_buf(&mut soc, msg, true).await);
+                Some((data, compress)) => match (ctx.as_ref(), data, compress) {
+                    (None, Flush::Contiguous(data), false) => {
+                        try_cf!(flush_buf(&mut soc, data, false, false).await)
+                    }
+                    (None, Flush::Segmented(segments), false) => {
+                        try_cf!(flush_segments(&mut soc, segments).await)
+                    }
+                    (ctx, data, compress) => {
+                        // compression and encryption need the whole
+                        // batch in one buffer
+                        let data = data.into_contiguous();
+                        let (data, compressed) = if compress {
+                            try_cf!(self::compress(data))
+                        } else {
+                            (data, false)
+                        };
+                        match ctx {
+                            None => {
+                                try_cf!(
+                                    flush_buf(&mut soc, data, false, compressed).await
+                                )
+                            }
+                            Some(ctx) => {
+                                let msg = try_cf!(ctx.lock().wrap_iov(true, data));
+                                try_cf!(flush_buf(&mut soc, msg, true, compressed).await)
+                            }
+                        }
                     }
                 },
             }
@@ -149,10 +292,108 @@ fn flush_task<
     tx
 }
 
+/// The batch currently being encoded. Small messages are encoded
+/// into `tail`, large `Bytes` payloads (see `pack::ZERO_COPY_MIN`) are
+/// kept by reference in `sealed`, along with everything encoded
+/// before them.
+struct Segments {
+    sealed: Vec<Bytes>,
+    sealed_len: usize,
+    tail: BytesMut,
+}
+
+impl Segments {
+    fn len(&self) -> usize {
+        self.sealed_len + self.tail.len()
+    }
+
+    fn seal_tail(&mut self) {
+        if !self.tail.is_empty() {
+            let b = self.tail.split().freeze();
+            self.sealed_len += b.len();
+            self.sealed.push(b);
+        }
+    }
+
+    fn take(&mut self) -> Flush {
+        if self.sealed.is_empty() {
+            Flush::Contiguous(self.tail.split())
+        } else {
+            self.seal_tail();
+            self.sealed_len = 0;
+            Flush::Segmented(mem::take(&mut self.sealed))
+        }
+    }
+
+    /// Undo everything written after `len` returned `len`. If the
+    /// undone data was already sealed, then the part before `len` is
+    /// copied back into the tail.
+    fn truncate(&mut self, len: usize) {
+        if len >= self.sealed_len {
+            self.tail.truncate(len - self.sealed_len);
+        } else {
+            let mut keep = 0;
+            let mut i = 0;
+            while i < self.sealed.len() && keep + self.sealed[i].len() <= len {
+                keep += self.sealed[i].len();
+                i += 1;
+            }
+            let mut tail = BytesMut::with_capacity(BUF);
+            for b in self.sealed.drain(i..) {
+                let n = cmp::min(b.len(), len - keep - tail.len());
+                tail.extend_from_slice(&b[..n]);
+            }
+            self.sealed_len = keep;
+            self.tail = tail;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.sealed.clear();
+        self.sealed_len = 0;
+        self.tail.clear();
+    }
+}
+
+unsafe impl BufMut for Segments {
+    fn remaining_mut(&self) -> usize {
+        self.tail.remaining_mut()
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        self.tail.advance_mut(cnt)
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        self.tail.chunk_mut()
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.tail.put_slice(src)
+    }
+
+    fn put<T: Buf>(&mut self, mut src: T)
+    where
+        Self: Sized,
+    {
+        if src.remaining() < ZERO_COPY_MIN {
+            self.tail.put(src)
+        } else {
+            self.seal_tail();
+            // for Bytes this is just a reference count increment
+            let b = src.copy_to_bytes(src.remaining());
+            self.sealed_len += b.len();
+            self.sealed.push(b);
+        }
+    }
+}
+
 pub(crate) struct WriteChannel {
-    to_flush: Sender<BytesMut>,
-    buf: BytesMut,
-    boundries: Vec<usize>,
+    to_flush: Sender<(Flush, bool)>,
+    buf: Segments,
+    ready: VecDeque<Flush>,
+    ready_len: usize,
+    compress_above: Option<usize>,
 }
 
 impl WriteChannel {
@@ -165,11 +406,26 @@ impl WriteChannel {
     ) -> WriteChannel {
         WriteChannel {
             to_flush: flush_task(ctx, socket),
-            buf: BytesMut::with_capacity(BUF),
-            boundries: Vec::new(),
+            buf: Segments {
+                sealed: Vec::new(),
+                sealed_len: 0,
+                tail: BytesMut::with_capacity(BUF),
+            },
+            ready: VecDeque::new(),
+            ready_len: 0,
+            compress_above: None,
         }
     }
 
+    /// Compress batches larger than `threshold` bytes with zstd, or
+    /// if None don't compress anything. Batches larger than the most
+    /// the other side will decompress are never compressed. Only
+    /// enable this if the other side agreed to decompress in the
+    /// hello, see `ReadChannel::set_decompression`.
+    pub(crate) fn set_compression(&mut self, threshold: Option<usize>) {
+        self.compress_above = threshold;
+    }
+
     /// Queue a message for sending. This only encodes the message and
     /// writes it to the buffer, you must call flush actually send it.
     pub(crate) fn queue_send<T: Pack>(&mut self, msg: &T) -> Result<()> {
@@ -177,19 +433,18 @@ impl WriteChannel {
         if len > MAX_BATCH as usize {
             return Err(anyhow!("message length {} exceeds max size {}", len, MAX_BATCH));
         }
-        if self.buf.remaining_mut() < len {
-            self.buf.reserve(self.buf.capacity());
+        if self.buf.len() + len > MAX_BATCH {
+            self.ready_len += self.buf.len();
+            self.ready.push_back(self.buf.take());
         }
-        let buf_len = self.buf.remaining();
-        if (buf_len - self.boundries.last().copied().unwrap_or(0)) + len > MAX_BATCH {
-            let prev_len: usize = self.boundries.iter().sum();
-            self.boundries.push(buf_len - prev_len);
+        if self.buf.tail.remaining_mut() < len {
+            self.buf.tail.reserve(self.buf.tail.capacity());
         }
+        let buf_len = self.buf.len();
         match msg.encode(&mut self.buf) {
             Ok(()) => Ok(()),
             Err(e) => {
-                self.buf.resize(buf_len, 0x0);
-                self.boundries.pop();
+                self.buf.truncate(buf_len);
                 Err(Error::from(e))
             }
         }
@@ -197,7 +452,8 @@ impl WriteChannel {
 
     /// Clear unflused queued messages
     pub(crate) fn clear(&mut self) {
-        self.boundries.clear();
+        self.ready.clear();
+        self.ready_len = 0;
         self.buf.clear();
     }
 
@@ -209,7 +465,7 @@ impl WriteChannel {
 
     /// Return the number of bytes queued for sending.
     pub(crate) fn bytes_queued(&self) -> usize {
-        self.buf.remaining()
+        self.ready_len + self.buf.len()
     }
 
     /// Initiate sending all outgoing messages. The actual send will
@@ -229,19 +485,20 @@ impl WriteChannel {
     /// channel is full. Return true if all data was flushed,
     /// otherwise false.
     pub(crate) fn try_flush(&mut self) -> Result<bool> {
-        while self.buf.has_remaining() {
-            let boundry = self.boundries.first().copied().unwrap_or(self.buf.len());
-            let chunk = self.buf.split_to(boundry);
-            match self.to_flush.try_send(chunk) {
-                Ok(()) => {
-                    if self.boundries.len() > 0 {
-                        self.boundries.remove(0);
-                    }
-                }
+        if self.buf.len() > 0 {
+            self.ready_len += self.buf.len();
+            self.ready.push_back(self.buf.take());
+        }
+        while let Some(chunk) = self.ready.pop_front() {
+            let len = chunk.len();
+            let compress = self
+                .compress_above
+                .map(|t| len > t && len <= MAX_DECOMPRESSED)
+                .unwrap_or(false);
+            match self.to_flush.try_send((chunk, compress)) {
+                Ok(()) => self.ready_len -= len,
                 Err(e) if e.is_full() => {
-                    let mut chunk = e.into_inner();
-                    chunk.unsplit(self.buf.split());
-                    self.buf = chunk;
+                    self.ready.push_front(e.into_inner().0);
                     return Ok(false);
                 }
                 Err(_) => bail!("can't flush to closed connection"),
@@ -371,11 +628,56 @@ impl DerefMut for PBuf {
     }
 }
 
+/// A batch read from the socket. Large unencrypted batches are read
+/// directly into a `Shared` buffer, so that decoding `Bytes` payloads
+/// from them does not copy.
+enum Chunk {
+    Pooled(PBuf),
+    Shared(Bytes),
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk::Pooled(PBuf::default())
+    }
+}
+
+impl Buf for Chunk {
+    fn advance(&mut self, cnt: usize) {
+        match self {
+            Chunk::Pooled(b) => b.advance(cnt),
+            Chunk::Shared(b) => b.advance(cnt),
+        }
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self {
+            Chunk::Pooled(b) => b.chunk(),
+            Chunk::Shared(b) => b.chunk(),
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        match self {
+            Chunk::Pooled(b) => b.remaining(),
+            Chunk::Shared(b) => b.remaining(),
+        }
+    }
+
+    fn copy_to_bytes(&mut self, len: usize) -> Bytes {
+        match self {
+            Chunk::Pooled(b) => b.copy_to_bytes(len),
+            Chunk::Shared(b) => b.copy_to_bytes(len),
+        }
+    }
+}
+
 fn read_task<C: K5Ctx + Debug + Send + Sync + 'static, S: AsyncRead + Send + 'static>(
     stop: oneshot::Receiver<()>,
     mut soc: ReadHalf<S>,
     ctx: Option<K5CtxWrap<C>>,
-) -> Receiver<PBuf> {
+    zstd: Arc<AtomicBool>,
+) -> Receiver<Chunk> {
     trace!("starting read task");
     let (mut tx, rx) = mpsc::channel(3);
     task::spawn(async move {
@@ -383,15 +685,43 @@ fn read_task<C: K5Ctx + Debug + Send + Sync + 'static, S: AsyncRead + Send + 'st
         let mut buf = PBuf::default();
         let res: Result<()> = 'main: loop {
             while buf.remaining() >= mem::size_of::<u32>() {
-                let (encrypted, len) = {
+                let (encrypted, compressed, len) = {
                     let hdr = BigEndian::read_u32(&*buf);
-                    if hdr > LEN_MASK {
-                        (true, (hdr & LEN_MASK) as usize)
-                    } else {
-                        (false, hdr as usize)
-                    }
+                    let encrypted = hdr & ENC_MASK != 0;
+                    let compressed = hdr & COMP_MASK != 0;
+                    (encrypted, compressed, (hdr & LEN_MASK & !COMP_MASK) as usize)
                 };
-                if buf.remaining() - mem::size_of::<u32>() < len {
+                if compressed && !zstd.load(Ordering::Relaxed) {
+                    break 'main Err(anyhow!("decompression was not negotiated"));
+                }
+                if !encrypted
+                    && !compressed
+                    && ctx.is_none()
+                    && len >= ZERO_COPY_MIN
+                    && buf.remaining() - mem::size_of::<u32>() < len
+                {
+                    // read the rest of a large batch directly into
+                    // its own buffer instead of copying it twice
+                    buf.advance(mem::size_of::<u32>());
+                    let mut batch = BytesMut::with_capacity(len);
+                    batch.extend_from_slice(&*buf);
+                    buf.advance(buf.remaining());
+                    while batch.len() < len {
+                        let rem = len - batch.len();
+                        let mut lim = (&mut batch).limit(rem);
+                        #[rustfmt::skip]
+                        select_biased! {
+                            _ = stop => break 'main Ok(()),
+                            i = soc.read_buf(&mut lim).fuse() => {
+                                if try_cf!(break, 'main, i) == 0 {
+                                    break 'main Err(anyhow!("EOF"));
+                                }
+                            }
+                        }
+                    }
+                    let batch = Chunk::Shared(batch.freeze());
+                    try_cf!(break, 'main, tx.send(batch).await);
+                } else if buf.remaining() - mem::size_of::<u32>() < len {
                     trace!(
                         "read_task: {} is less than batch len {}, reading more",
                         buf.remaining() - mem::size_of::<u32>(),
@@ -403,7 +733,14 @@ fn read_task<C: K5Ctx + Debug + Send + Sync + 'static, S: AsyncRead + Send + 'st
                         break 'main Err(anyhow!("encryption is required"));
                     }
                     buf.advance(mem::size_of::<u32>());
-                    try_cf!(break, 'main, tx.send(buf.split_to(len)).await);
+                    let batch = if compressed {
+                        let batch = try_cf!(break, 'main, decompress(&buf[..len]));
+                        buf.advance(len);
+                        Chunk::Shared(batch)
+                    } else {
+                        Chunk::Pooled(buf.split_to(len))
+                    };
+                    try_cf!(break, 'main, tx.send(batch).await);
                 } else {
                     let ctx = match ctx {
                         Some(ref ctx) => ctx,
@@ -412,8 +749,13 @@ fn read_task<C: K5Ctx + Debug + Send + Sync + 'static, S: AsyncRead + Send + 'st
                     buf.advance(mem::size_of::<u32>());
                     let decrypted = try_cf!(break, 'main, ctx.lock().unwrap(&buf[..len]));
                     buf.advance(len);
-                    buf.extend_from_slice(&*decrypted);
-                    try_cf!(break, 'main, tx.send(mem::take(&mut buf)).await);
+                    let batch = if compressed {
+                        Chunk::Shared(try_cf!(break, 'main, decompress(&*decrypted)))
+                    } else {
+                        buf.extend_from_slice(&*decrypted);
+                        Chunk::Pooled(mem::take(&mut buf))
+                    };
+                    try_cf!(break, 'main, tx.send(batch).await);
                 }
             }
             if buf.remaining_mut() < BUF {
@@ -443,9 +785,10 @@ fn read_task<C: K5Ctx + Debug + Send + Sync + 'static, S: AsyncRead + Send + 'st
 }
 
 pub(crate) struct ReadChannel {
-    buf: PBuf,
+    buf: Chunk,
     _stop: oneshot::Sender<()>,
-    incoming: stream::Fuse<Receiver<PBuf>>,
+    incoming: stream::Fuse<Receiver<Chunk>>,
+    zstd: Arc<AtomicBool>,
 }
 
 impl ReadChannel {
@@ -457,13 +800,23 @@ impl ReadChannel {
         socket: ReadHalf<S>,
     ) -> ReadChannel {
         let (stop_tx, stop_rx) = oneshot::channel();
+        let zstd = Arc::new(AtomicBool::new(false));
         ReadChannel {
-            buf: PBuf::default(),
+            buf: Chunk::default(),
             _stop: stop_tx,
-            incoming: read_task(stop_rx, socket, k5ctx).fuse(),
+            incoming: read_task(stop_rx, socket, k5ctx, zstd.clone()).fuse(),
+            zstd,
         }
     }
 
+    /// Accept batches compressed with zstd. Until this is called a
+    /// compressed batch is an error that closes the channel, so
+    /// only call it once the other side agreed to compress in the
+    /// hello, and before it can send anything compressed.
+    pub(crate) fn set_decompression(&self, enabled: bool) {
+        self.zstd.store(enabled, Ordering::Relaxed)
+    }
+
     /// Read a load of bytes from the socket into the read buffer
     pub(crate) async fn fill_buffer(&mut self) -> Result<()> {
         if let Some(chunk) = self.incoming.next().await {
@@ -553,6 +906,10 @@ impl Channel {
         (self.read, self.write)
     }
 
+    pub(crate) fn set_decompression(&self, enabled: bool) {
+        self.read.set_decompression(enabled)
+    }
+
     pub(crate) fn queue_send<T: Pack>(&mut self, msg: &T) -> Result<(), Error> {
         self.write.queue_send(msg)
     }
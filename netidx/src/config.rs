@@ -8,10 +8,18 @@ use crate::{
 };
 use anyhow::Result;
 use serde_json::from_str;
+use socket2::{SockRef, TcpKeepalive};
 use std::{
-    cmp::min, collections::BTreeMap, convert::AsRef, convert::Into, fs::read_to_string,
-    net::SocketAddr, path::Path as FsPath, str,
+    cmp::min,
+    collections::BTreeMap,
+    convert::{AsRef, Into, TryFrom},
+    fs::read_to_string,
+    net::{IpAddr, SocketAddr},
+    path::Path as FsPath,
+    str,
+    time::Duration,
 };
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
 
 /// The on disk format, encoded as JSON
 pub mod file {
@@ -45,6 +53,13 @@ pub mod file {
         }
     }
 
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub enum AuthTarget {
+        Publisher(SocketAddr),
+        Namespace(String),
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(deny_unknown_fields)]
     pub struct TlsIdentity {
@@ -63,17 +78,49 @@ pub mod file {
         pub askpass: Option<String>,
     }
 
+    fn default_refresh() -> u64 {
+        60
+    }
+
+    /// Discover the members of the resolver cluster using DNS. If
+    /// `port` is not specified then `name` is looked up as an SRV
+    /// record (e.g. `_netidx._tcp.example.com`), otherwise its A
+    /// and AAAA records are used with `port`. The name is looked up
+    /// again every `refresh` seconds, so resolver servers can be
+    /// added and removed without restarting clients.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Dns {
+        pub name: String,
+        #[serde(default)]
+        pub port: Option<u16>,
+        pub auth: Auth,
+        #[serde(default = "default_refresh")]
+        pub refresh: u64,
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(deny_unknown_fields)]
     pub struct Config {
         pub base: String,
+        #[serde(default)]
         pub addrs: Vec<(SocketAddr, Auth)>,
         #[serde(default)]
+        pub dns: Option<Dns>,
+        #[serde(default)]
         pub tls: Option<Tls>,
         #[serde(default)]
         pub default_auth: super::DefaultAuthMech,
         #[serde(default)]
         pub default_bind_config: Option<String>,
+        #[serde(default)]
+        pub publisher_ports: Option<(u16, u16)>,
+        #[serde(default)]
+        pub publisher_advertise: Option<SocketAddr>,
+        #[serde(default)]
+        pub socket: super::SocketOpts,
+        #[serde(default)]
+        pub auth_overrides: Vec<(AuthTarget, super::DesiredAuth)>,
     }
 
     impl Config {
@@ -244,20 +291,176 @@ impl Default for DefaultAuthMech {
     }
 }
 
+fn default_nodelay() -> bool {
+    true
+}
+
+/// Options for the tcp connections between subscribers and
+/// publishers. Anything not specified is left at the os default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SocketOpts {
+    /// Disable Nagle's algorithm. netidx batches messages itself, so
+    /// the default is true.
+    #[serde(default = "default_nodelay")]
+    pub nodelay: bool,
+    /// The size of the socket send buffer in bytes
+    #[serde(default)]
+    pub send_buffer: Option<usize>,
+    /// The size of the socket receive buffer in bytes. This limits
+    /// the tcp window, so it may need to be raised to use all the
+    /// bandwidth of a link with a high latency.
+    #[serde(default)]
+    pub recv_buffer: Option<usize>,
+    /// If set, enable tcp keepalive and send the first probe after
+    /// the connection has been idle for this many seconds.
+    #[serde(default)]
+    pub keepalive_time: Option<u64>,
+    /// The number of seconds between keepalive probes. Only used if
+    /// `keepalive_time` is set, and only on linux, mac os, and
+    /// windows.
+    #[serde(default)]
+    pub keepalive_interval: Option<u64>,
+    /// The local address subscribers bind to when connecting to
+    /// publishers, which selects the interface the connection goes
+    /// out on. It is ignored when connecting to an address of the
+    /// other family. Publishers select their interface with the bind
+    /// config.
+    #[serde(default)]
+    pub bind: Option<IpAddr>,
+}
+
+impl Default for SocketOpts {
+    fn default() -> Self {
+        SocketOpts {
+            nodelay: default_nodelay(),
+            send_buffer: None,
+            recv_buffer: None,
+            keepalive_time: None,
+            keepalive_interval: None,
+            bind: None,
+        }
+    }
+}
+
+impl SocketOpts {
+    fn keepalive(&self) -> Option<TcpKeepalive> {
+        self.keepalive_time.map(|time| {
+            let ka = TcpKeepalive::new().with_time(Duration::from_secs(time));
+            match self.keepalive_interval {
+                None => ka,
+                #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+                Some(i) => ka.with_interval(Duration::from_secs(i)),
+                #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+                Some(_) => ka,
+            }
+        })
+    }
+
+    /// Set the options on a connected socket
+    pub(crate) fn apply(&self, s: &TcpStream) -> Result<()> {
+        s.set_nodelay(self.nodelay)?;
+        let s = SockRef::from(s);
+        if let Some(n) = self.send_buffer {
+            s.set_send_buffer_size(n)?
+        }
+        if let Some(n) = self.recv_buffer {
+            s.set_recv_buffer_size(n)?
+        }
+        if let Some(ka) = self.keepalive() {
+            s.set_tcp_keepalive(&ka)?
+        }
+        Ok(())
+    }
+
+    /// The tcp window is negotiated when the connection is
+    /// established, so the receive buffer must already be set on the
+    /// listener for accepted connections to get a larger window.
+    pub(crate) fn apply_listener(&self, l: &TcpListener) -> Result<()> {
+        if let Some(n) = self.recv_buffer {
+            SockRef::from(l).set_recv_buffer_size(n)?
+        }
+        Ok(())
+    }
+
+    /// Connect to `addr` with these options
+    pub(crate) async fn connect(&self, addr: &SocketAddr) -> Result<TcpStream> {
+        let soc = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(n) = self.send_buffer {
+            soc.set_send_buffer_size(u32::try_from(n)?)?
+        }
+        if let Some(n) = self.recv_buffer {
+            soc.set_recv_buffer_size(u32::try_from(n)?)?
+        }
+        match self.bind {
+            Some(ip) if ip.is_ipv4() == addr.is_ipv4() => {
+                soc.bind(SocketAddr::new(ip, 0))?
+            }
+            Some(_) | None => (),
+        }
+        let s = soc.connect(*addr).await?;
+        self.apply(&s)?;
+        Ok(s)
+    }
+}
+
+/// The publishers an auth override applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthTarget {
+    /// The publisher at this address
+    Publisher(SocketAddr),
+    /// The publishers of paths under this namespace
+    Namespace(Path),
+}
+
+/// The resolved form of `file::Dns`
+#[derive(Debug, Clone)]
+pub struct Dns {
+    /// The SRV name, or the host name if `port` is specified
+    pub name: String,
+    pub port: Option<u16>,
+    /// The auth mechanism used for every discovered server
+    pub auth: Auth,
+    /// How often to look up `name` again
+    pub refresh: Duration,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub base: Path,
+    /// The addresses of all the members of the resolver cluster.
+    /// Writes go to all of them, reads to any one.
     pub addrs: Vec<(SocketAddr, Auth)>,
+    /// If specified, the members discovered from DNS are used in
+    /// addition to `addrs`
+    pub dns: Option<Dns>,
     pub tls: Option<Tls>,
     pub default_auth: DefaultAuthMech,
     pub default_bind_config: publisher::BindCfg,
+    /// If specified, publishers only listen on a port in this
+    /// inclusive range
+    pub publisher_ports: Option<(u16, u16)>,
+    /// If specified, publishers register this address with the
+    /// resolver instead of the address they are bound to, for when
+    /// they are reachable through a NAT or a container port
+    /// mapping. A port of 0 means the bound port.
+    pub publisher_advertise: Option<SocketAddr>,
+    pub socket: SocketOpts,
+    /// Subscribers connect to the publishers matching a target with
+    /// the corresponding auth instead of their desired auth. A
+    /// publisher address takes precedence over a namespace, the
+    /// longest matching namespace wins, and later entries win ties.
+    pub auth_overrides: Vec<(AuthTarget, DesiredAuth)>,
 }
 
 impl Config {
     pub fn parse(s: &str) -> Result<Config> {
         let cfg: file::Config = from_str(s)?;
-        if cfg.addrs.is_empty() {
-            bail!("you must specify at least one address");
+        if cfg.addrs.is_empty() && cfg.dns.is_none() {
+            bail!("you must specify at least one address or a dns name");
         }
         match cfg.default_auth {
             DefaultAuthMech::Anonymous
@@ -273,24 +476,23 @@ impl Config {
             Some(tls) => Some(Tls::load(tls)?),
             None => None,
         };
+        let check_tls = |name: &String| match &tls {
+            None => bail!("tls auth requires a valid tls configuration"),
+            Some(tls) => {
+                let mut rev_name = name.clone();
+                Tls::reverse_domain_name(&mut rev_name);
+                if tls::get_match(&tls.identities, &rev_name).is_none() {
+                    bail!("required identity for {} not found in tls identities", name)
+                }
+                Ok(())
+            }
+        };
         for (addr, auth) in &cfg.addrs {
             use file::Auth as FAuth;
             utils::check_addr::<()>(addr.ip(), &[])?;
             match auth {
                 FAuth::Anonymous | FAuth::Krb5(_) => (),
-                FAuth::Tls(name) => match &tls {
-                    None => bail!("tls auth requires a valid tls configuration"),
-                    Some(tls) => {
-                        let mut rev_name = name.clone();
-                        Tls::reverse_domain_name(&mut rev_name);
-                        if tls::get_match(&tls.identities, &rev_name).is_none() {
-                            bail!(
-                                "required identity for {} not found in tls identities",
-                                name
-                            )
-                        }
-                    }
-                },
+                FAuth::Tls(name) => check_tls(name)?,
                 FAuth::Local(_) => {
                     if !addr.ip().is_loopback() {
                         bail!("local auth is not allowed for remote servers")
@@ -298,20 +500,81 @@ impl Config {
                 }
             }
         }
+        if let Some(dns) = &cfg.dns {
+            use file::Auth as FAuth;
+            if dns.name.is_empty() {
+                bail!("the dns name must not be empty")
+            }
+            if dns.refresh == 0 {
+                bail!("the dns refresh interval must be at least 1 second")
+            }
+            match &dns.auth {
+                FAuth::Anonymous | FAuth::Krb5(_) => (),
+                FAuth::Tls(name) => check_tls(name)?,
+                FAuth::Local(_) => bail!("local auth is not allowed for dns discovery"),
+            }
+        }
         if !cfg.addrs.iter().all(|(a, _)| a.ip().is_loopback())
             && !cfg.addrs.iter().all(|(a, _)| !a.ip().is_loopback())
         {
             bail!("can't mix loopback addrs with non loopback addrs")
         }
+        if let Some((start, end)) = cfg.publisher_ports {
+            utils::check_port_range(start, end)?
+        }
+        if let Some(addr) = cfg.publisher_advertise {
+            utils::check_advertised_addr(addr, &cfg.addrs)?
+        }
+        for (target, auth) in &cfg.auth_overrides {
+            if let file::AuthTarget::Namespace(ns) = target {
+                if !Path::is_absolute(ns) {
+                    bail!("auth override namespace {} must be absolute", ns)
+                }
+            }
+            match auth {
+                DesiredAuth::Anonymous
+                | DesiredAuth::Local
+                | DesiredAuth::Krb5 { .. } => (),
+                DesiredAuth::Tls { identity: Some(id) } => check_tls(id)?,
+                DesiredAuth::Tls { identity: None } => {
+                    if tls.is_none() {
+                        bail!("tls auth requires a valid tls configuration")
+                    }
+                }
+            }
+        }
+        let auth_overrides = cfg
+            .auth_overrides
+            .into_iter()
+            .map(|(target, auth)| {
+                let target = match target {
+                    file::AuthTarget::Publisher(addr) => AuthTarget::Publisher(addr),
+                    file::AuthTarget::Namespace(ns) => {
+                        AuthTarget::Namespace(Path::from(ns))
+                    }
+                };
+                (target, auth)
+            })
+            .collect();
         Ok(Config {
             base: Path::from(cfg.base),
             addrs: cfg.addrs.into_iter().map(|(s, a)| (s, a.into())).collect(),
+            dns: cfg.dns.map(|d| Dns {
+                name: d.name,
+                port: d.port,
+                auth: d.auth.into(),
+                refresh: Duration::from_secs(d.refresh),
+            }),
             tls,
             default_auth: cfg.default_auth,
             default_bind_config: match cfg.default_bind_config {
                 None => publisher::BindCfg::default(),
                 Some(s) => s.parse()?,
             },
+            publisher_ports: cfg.publisher_ports,
+            publisher_advertise: cfg.publisher_advertise,
+            socket: cfg.socket,
+            auth_overrides,
         })
     }
 
@@ -106,6 +106,7 @@
 #[macro_use] extern crate bitflags;
 #[macro_use] extern crate anyhow;
 #[macro_use] extern crate netidx_core;
+extern crate self as netidx;
 
 pub use netidx_core::{chars, pack, pool, path, utils};
 pub use netidx_netproto as protocol;
@@ -309,3 +309,54 @@ pub(crate) mod local_auth {
         }
     }
 }
+
+pub(crate) mod local_transport {
+    use anyhow::{anyhow, Result};
+    use arcstr::ArcStr;
+    use rand::{thread_rng, Rng};
+    use std::{env, fs, path::PathBuf, process};
+    use tokio::net::{UnixListener, UnixStream};
+
+    pub(crate) type Stream = UnixStream;
+
+    /// A unix domain socket listener in the system temporary
+    /// directory. The socket name is randomized, so it can only be
+    /// found by subscribers the resolver told about it. The socket
+    /// file is removed when the listener is dropped.
+    pub(crate) struct Listener {
+        listener: UnixListener,
+        path: PathBuf,
+        endpoint: ArcStr,
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    impl Listener {
+        pub(crate) fn bind() -> Result<Listener> {
+            let name =
+                format!("netidx-{}-{:x}.sock", process::id(), thread_rng().gen::<u64>());
+            let path = env::temp_dir().join(name);
+            let endpoint = ArcStr::from(
+                path.to_str().ok_or_else(|| anyhow!("invalid temporary directory"))?,
+            );
+            let listener = UnixListener::bind(&path)?;
+            Ok(Listener { listener, path, endpoint })
+        }
+
+        pub(crate) fn endpoint(&self) -> &ArcStr {
+            &self.endpoint
+        }
+
+        pub(crate) async fn accept(&self) -> Result<Stream> {
+            Ok(self.listener.accept().await?.0)
+        }
+    }
+
+    pub(crate) async fn connect(endpoint: &str) -> Result<Stream> {
+        Ok(UnixStream::connect(endpoint).await?)
+    }
+}
@@ -101,3 +101,32 @@ pub(crate) mod local_auth {
         }
     }
 }
+
+pub(crate) mod local_transport {
+    use anyhow::Result;
+    use arcstr::ArcStr;
+
+    pub(crate) type Stream = tokio::net::TcpStream;
+
+    pub(crate) struct Listener {
+        endpoint: ArcStr,
+    }
+
+    impl Listener {
+        pub(crate) fn bind() -> Result<Listener> {
+            bail!("local transport not implemented on windows")
+        }
+
+        pub(crate) fn endpoint(&self) -> &ArcStr {
+            &self.endpoint
+        }
+
+        pub(crate) async fn accept(&self) -> Result<Stream> {
+            bail!("local transport not implemented on windows")
+        }
+    }
+
+    pub(crate) async fn connect(_endpoint: &str) -> Result<Stream> {
+        bail!("local transport not implemented on windows")
+    }
+}
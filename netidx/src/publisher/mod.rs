@@ -1,47 +1,72 @@
 mod server;
+mod tree;
 pub use crate::protocol::{
     publisher::Id,
     value::{FromValue, Typ, Value},
 };
 pub use crate::resolver_client::DesiredAuth;
 use crate::{
-    config::Config,
+    chars::Chars,
+    config::{Config, SocketOpts},
+    os::local_transport,
+    pack::Pack,
     path::Path,
     pool::{Pool, Pooled},
-    protocol::{publisher, resolver::UserInfo},
-    resolver_client::ResolverWrite,
+    protocol::{
+        publisher,
+        resolver::{Metadata, UserInfo},
+    },
+    resolver_client::{
+        stats::{Op, OpStats},
+        ResolverRead, ResolverWrite,
+    },
     resolver_server::auth::Permissions,
     tls,
     utils::{self, ChanId, ChanWrap},
 };
 use anyhow::{anyhow, Error, Result};
+use chrono::prelude::*;
 use futures::{
     channel::{
         mpsc::{unbounded, Sender, UnboundedReceiver, UnboundedSender},
         oneshot,
     },
     prelude::*,
+    select_biased,
     stream::FusedStream,
 };
 use fxhash::{FxHashMap, FxHashSet};
 use if_addrs::get_if_addrs;
-use log::{error, info};
+use log::{error, info, warn};
 use parking_lot::Mutex;
 use rand::{self, Rng};
+use smallvec::{smallvec, SmallVec};
 use std::{
     boxed::Box,
     collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     convert::{From, Into, TryInto},
     default::Default,
-    iter, mem,
+    fmt, iter, mem,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
     pin::Pin,
     result,
     str::FromStr,
-    sync::{Arc, Weak},
-    time::Duration, fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    fs,
+    net::{TcpListener, UdpSocket},
+    task,
+    time::{self, Instant},
 };
-use tokio::{net::TcpListener, task};
+
+pub use netidx_derive::Publishable;
+pub use tree::{Publishable, PublishedTree, PublishedValueTree, ValueTree};
 
 /// Control how the publisher picks a bind address. The address we
 /// give to the resolver server must be uniquely routable back to us,
@@ -49,14 +74,17 @@ use tokio::{net::TcpListener, task};
 /// furtherance of this goal there are a number of address rules to
 /// follow,
 ///
-/// - no unspecified (0.0.0.0)
+/// - no unspecified (0.0.0.0, or ::)
 /// - no broadcast (255.255.255.255)
-/// - no multicast addresses (224.0.0.0/8)
-/// - no link local addresses (169.254.0.0/16)
+/// - no multicast addresses (224.0.0.0/8, or ff00::/8)
+/// - no link local addresses (169.254.0.0/16, or fe80::/10)
 /// - loopback (127.0.0.1, or ::1) is only allowed if the default resolver is local
-/// - private addresses (192.168.0.0/16, 10.0.0.0/8, 172.16.0.0/12)
-/// are only allowed if the default resolver is also using a private
-/// address
+/// - private addresses (192.168.0.0/16, 10.0.0.0/8, 172.16.0.0/12,
+/// or fc00::/7) are only allowed if the default resolver is also
+/// using a private address, or is of the other address family
+///
+/// ipv4 mapped ipv6 addresses (::ffff:a.b.c.d) are treated as the
+/// ipv4 address they represent.
 ///
 /// As well as the above rules we will enumerate all the network
 /// interface addresses present at startup time and check that the
@@ -292,15 +320,23 @@ impl BindCfg {
 
 atomic_id!(ClId);
 
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(5);
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// datagrams are filled up to about this many bytes, values that
+// don't fit in one by themselves are sent by unicast instead
+const DATAGRAM_SIZE: usize = 8192;
+
 lazy_static! {
     static ref BATCHES: Pool<Vec<WriteRequest>> = Pool::new(100, 10_000);
     static ref TOPUB: Pool<HashMap<Path, Option<u32>>> = Pool::new(10, 10_000);
+    static ref METADATA: Pool<Vec<(Chars, Value)>> = Pool::new(1_000, 100);
     static ref TOUPUB: Pool<HashSet<Path>> = Pool::new(5, 10_000);
-    static ref TOUSUB: Pool<HashMap<Id, Subscribed>> = Pool::new(5, 10_000);
+    static ref TOUSUB: Pool<HashMap<Id, (Subscribed, Value)>> = Pool::new(5, 10_000);
     static ref RAWBATCH: Pool<Vec<BatchMsg>> = Pool::new(100, 10_000);
     static ref UPDATES: Pool<Vec<publisher::From>> = Pool::new(100, 10_000);
-    static ref RAWUNSUBS: Pool<Vec<(ClId, Id)>> = Pool::new(100, 10_000);
-    static ref UNSUBS: Pool<Vec<Id>> = Pool::new(100, 10_000);
+    static ref RAWUNSUBS: Pool<Vec<(ClId, Id, Option<Value>)>> = Pool::new(100, 10_000);
+    static ref UNSUBS: Pool<Vec<(Id, Option<Value>)>> = Pool::new(100, 10_000);
     static ref BATCH: Pool<FxHashMap<ClId, Update>> = Pool::new(100, 1000);
 
     // estokes 2021: This is reasonable because there will never be
@@ -396,6 +432,26 @@ bitflags! {
         /// to the same publisher then do not use this flag. e.g. do
         /// not use this flag for rpcs.
         const FORCE_LOCAL = 0x10;
+
+        /// If set, and the publisher was built with a multicast
+        /// config, anonymous subscribers that support it may receive
+        /// updates to this value by multicast instead of over their
+        /// own connection. Authenticated subscribers always receive
+        /// them over their connection. See
+        /// `PublisherBuilder::multicast`. Like DESTROY_ON_IDLE this
+        /// flag is not sent to the resolver.
+        const MULTICAST = 0x20;
+
+        /// If set, then updates to this value may be conflated. When
+        /// a subscriber falls behind and more than one batch is
+        /// queued for it, only the most recent update to this value
+        /// in the queued batches will be sent, the earlier ones are
+        /// dropped. Values without this flag are reliable, every
+        /// update is delivered in order. This is a good fit for
+        /// values where only the latest state matters, e.g. prices
+        /// or gauges. Like DESTROY_ON_IDLE this flag is not sent to
+        /// the resolver.
+        const CONFLATE = 0x40;
     }
 }
 
@@ -426,6 +482,10 @@ pub struct WriteRequest {
     pub client: ClId,
     /// the value being written
     pub value: Value,
+    /// the authenticated identity of the client requesting the
+    /// write, the same as `Publisher::user`. This is always None if
+    /// the auth mechanism is Anonymous.
+    pub user: Option<UserInfo>,
     pub send_result: Option<SendResult>,
 }
 
@@ -434,17 +494,84 @@ pub enum Event {
     Destroyed(Id),
     Subscribe(Id, ClId),
     Unsubscribe(Id, ClId),
+    /// The subscriber's send queue is full, commits will wait for it
+    /// to catch up. This is sent once each time the subscriber falls
+    /// behind, it is not sent again until its queue is found drained
+    /// at a heartbeat.
+    SlowSubscriber(ClId),
+    /// The subscriber was disconnected because it couldn't accept a
+    /// batch of updates within the timeout passed to `commit`.
+    SubscriberDropped(ClId),
+}
+
+/// The congestion of a subscriber connection, see `Publisher::congestion`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Congestion {
+    /// The number of update batches waiting to be sent
+    pub queue_depth: usize,
+    /// The number of bytes encoded but not yet written to the socket
+    pub bytes_buffered: usize,
+}
+
+/// The updates sent for a published value, see `Publisher::path_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathStats {
+    /// The number of updates committed
+    pub updates: u64,
+    /// The encoded size of the updates, counted once for each
+    /// subscriber they were sent to, or once if they were multicast
+    pub bytes: u64,
+}
+
+/// Congestion counters shared by the publisher and the client task
+#[derive(Debug, Default)]
+struct CongestionCtr {
+    queued: AtomicUsize,
+    buffered: AtomicUsize,
+    slow: AtomicBool,
+    timed_out: AtomicBool,
+}
+
+impl CongestionCtr {
+    fn get(&self) -> Congestion {
+        Congestion {
+            queue_depth: self.queued.load(Ordering::Relaxed),
+            bytes_buffered: self.buffered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// What happens to a value that isn't updated within its ttl, see
+/// `Val::set_ttl`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expiry {
+    /// Update the value to the specified stale marker. The next
+    /// update replaces the marker and restarts the ttl.
+    Stale(Value),
+    /// Unpublish the value, subscribers will receive the specified
+    /// terminal value, see `Val::unpublish`.
+    Unpublish(Value),
+}
+
+#[derive(Debug)]
+struct Ttl {
+    ttl: Duration,
+    deadline: Instant,
+    expired: bool,
+    expiry: Expiry,
 }
 
 #[derive(Debug)]
 struct Update {
     updates: Pooled<Vec<publisher::From>>,
-    unsubscribes: Option<Pooled<Vec<Id>>>,
+    unsubscribes: Option<Pooled<Vec<(Id, Option<Value>)>>>,
+    timestamp: Option<DateTime<Utc>>,
+    batch_id: u64,
 }
 
 impl Update {
     fn new() -> Self {
-        Self { updates: UPDATES.take(), unsubscribes: None }
+        Self { updates: UPDATES.take(), unsubscribes: None, timestamp: None, batch_id: 0 }
     }
 }
 
@@ -471,6 +598,18 @@ impl fmt::Debug for ExtendedAuthWrap {
     }
 }
 
+/// Snapshot hook, see `Publisher::set_snapshot`
+pub type Snapshot = Box<dyn Fn(ClId, Id, &Value) -> Value + Send + Sync + 'static>;
+
+#[repr(transparent)]
+struct SnapshotWrap(Snapshot);
+
+impl fmt::Debug for SnapshotWrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<Fn>")
+    }
+}
+
 /// This represents a published value. When it is dropped the value
 /// will be unpublished.
 pub struct Val(Id);
@@ -480,7 +619,7 @@ impl Drop for Val {
         PUBLISHERS.lock().retain(|t| match t.upgrade() {
             None => false,
             Some(t) => {
-                t.0.lock().destroy_val(self.0);
+                t.0.lock().destroy_val(self.0, None);
                 true
             }
         })
@@ -555,15 +694,58 @@ impl Val {
     /// will only take effect when the specified batch is committed.
     pub fn unsubscribe(&self, batch: &mut UpdateBatch, dst: ClId) {
         match &mut batch.unsubscribes {
-            Some(u) => u.push((dst, self.0)),
+            Some(u) => u.push((dst, self.0, None)),
             None => {
                 let mut u = RAWUNSUBS.take();
-                u.push((dst, self.0));
+                u.push((dst, self.0, None));
                 batch.unsubscribes = Some(u);
             }
         }
     }
 
+    /// Stop publishing this value. Subscribers will be told the value
+    /// was unpublished, and will receive `terminal` as its final
+    /// value. Dropping a `Val` does the same thing, except the final
+    /// value will be the current value, so subscribers receive
+    /// `Event::Unpublished(current)` where they used to receive
+    /// `Event::Unsubscribed`.
+    pub fn unpublish<T: Into<Value>>(self, terminal: T) {
+        let terminal = terminal.into();
+        PUBLISHERS.lock().retain(|t| match t.upgrade() {
+            None => false,
+            Some(t) => {
+                t.0.lock().destroy_val(self.0, Some(terminal.clone()));
+                true
+            }
+        })
+    }
+
+    /// Expire the value if it isn't updated for `ttl`, subscribers
+    /// will be told it expired as specified by `expiry`. Every
+    /// committed `update` or `update_changed` restarts the ttl, even
+    /// if the value didn't change. Setting a ttl replaces any
+    /// previous ttl, and starts it from now.
+    pub fn set_ttl(&self, ttl: Duration, expiry: Expiry) {
+        PUBLISHERS.lock().retain(|t| match t.upgrade() {
+            None => false,
+            Some(t) => {
+                t.set_ttl(self.0, Some((ttl, expiry.clone())));
+                true
+            }
+        })
+    }
+
+    /// Remove the ttl of the value, if any
+    pub fn clear_ttl(&self) {
+        PUBLISHERS.lock().retain(|t| match t.upgrade() {
+            None => false,
+            Some(t) => {
+                t.set_ttl(self.0, None);
+                true
+            }
+        })
+    }
+
     /// Get the unique `Id` of this `Val`
     pub fn id(&self) -> Id {
         self.0
@@ -703,6 +885,115 @@ impl Drop for DefaultHandle {
     }
 }
 
+/// A subtree whose values are published on demand. See
+/// `Publisher::publish_on_demand`. When this handle is dropped the
+/// default publisher is removed, and every value it created is
+/// unpublished.
+pub struct OnDemand {
+    vals: Arc<Mutex<FxHashMap<Path, Val>>>,
+    path: Path,
+    _stop: oneshot::Sender<()>,
+}
+
+impl OnDemand {
+    /// The base path of the subtree
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the paths that currently have a value
+    pub fn published(&self) -> Vec<Path> {
+        self.vals.lock().keys().cloned().collect()
+    }
+
+    /// Return the id of the value at `path` if it currently exists
+    pub fn id(&self, path: &Path) -> Option<Id> {
+        self.vals.lock().get(path).map(|v| v.id())
+    }
+
+    /// Queue an update to the value at `path` in `batch`, see
+    /// `Val::update`. Return false, and do nothing, if the value does
+    /// not currently exist, either because nobody asked for it yet,
+    /// or because its last subscriber went away.
+    pub fn update<T: Into<Value>>(
+        &self,
+        batch: &mut UpdateBatch,
+        path: &Path,
+        v: T,
+    ) -> bool {
+        match self.vals.lock().get(path) {
+            None => false,
+            Some(val) => {
+                val.update(batch, v);
+                true
+            }
+        }
+    }
+}
+
+impl Drop for OnDemand {
+    fn drop(&mut self) {
+        self.vals.lock().clear()
+    }
+}
+
+async fn on_demand_loop<F>(
+    publisher: PublisherWeak,
+    flags: PublishFlags,
+    mut requests: DefaultHandle,
+    vals: Arc<Mutex<FxHashMap<Path, Val>>>,
+    mut f: F,
+    mut stop: oneshot::Receiver<()>,
+) where
+    F: FnMut(&Path) -> Option<Value> + Send + 'static,
+{
+    let (tx_ev, mut rx_ev) = unbounded();
+    match publisher.upgrade() {
+        None => return,
+        Some(publisher) => publisher.events(tx_ev),
+    }
+    let mut by_id: FxHashMap<Id, Path> = HashMap::default();
+    loop {
+        select_biased! {
+            _ = stop => break,
+            e = rx_ev.select_next_some() => match e {
+                Event::Subscribe(_, _)
+                | Event::Unsubscribe(_, _)
+                | Event::SlowSubscriber(_)
+                | Event::SubscriberDropped(_) => (),
+                Event::Destroyed(id) => {
+                    if let Some(path) = by_id.remove(&id) {
+                        let mut vals = vals.lock();
+                        if vals.get(&path).map(|v| v.id() == id).unwrap_or(false) {
+                            vals.remove(&path);
+                        }
+                    }
+                }
+            },
+            (path, reply) = requests.select_next_some() => {
+                let publisher = match publisher.upgrade() {
+                    None => break,
+                    Some(publisher) => publisher,
+                };
+                if publisher.id(&path).is_none() {
+                    if let Some(init) = f(&path) {
+                        match publisher.publish_with_flags(flags, path.clone(), init) {
+                            Err(e) => warn!("failed to publish {} on demand {}", path, e),
+                            Ok(val) => {
+                                by_id.insert(val.id(), path.clone());
+                                vals.lock().insert(path, val);
+                            }
+                        }
+                    }
+                }
+                let _ = reply.send(());
+            },
+            complete => break,
+        }
+    }
+    vals.lock().clear()
+}
+
 #[derive(Debug, Clone)]
 pub enum BatchMsg {
     UpdateChanged(Id, Value),
@@ -714,7 +1005,9 @@ pub enum BatchMsg {
 pub struct UpdateBatch {
     origin: Publisher,
     updates: Pooled<Vec<BatchMsg>>,
-    unsubscribes: Option<Pooled<Vec<(ClId, Id)>>>,
+    unsubscribes: Option<Pooled<Vec<(ClId, Id, Option<Value>)>>>,
+    // the batch carries expired values, so it must not restart ttls
+    expiring: bool,
 }
 
 impl UpdateBatch {
@@ -759,65 +1052,98 @@ impl UpdateBatch {
         }
         let fut = {
             let mut batch = BATCH.take();
+            let mut multicast = vec![];
             let mut pb = self.origin.0.lock();
+            let now = if self.expiring || pb.ttl.is_empty() {
+                None
+            } else {
+                Some(Instant::now())
+            };
             for m in self.updates.drain(..) {
+                if let Some(now) = now {
+                    match &m {
+                        BatchMsg::Update(None, id, _)
+                        | BatchMsg::UpdateChanged(id, _) => pb.restart_ttl(id, now),
+                        BatchMsg::Update(Some(_), _, _) => (),
+                    }
+                }
                 match m {
                     BatchMsg::Update(None, id, v) => {
                         if let Some(pbl) = pb.by_id.get_mut(&id) {
-                            for cl in pbl.subscribed.iter() {
-                                batch
-                                    .entry(*cl)
-                                    .or_insert_with(Update::new)
-                                    .updates
-                                    .push(publisher::From::Update(id, v.clone()));
-                            }
+                            pbl.queue_update(&mut batch, &mut multicast, id, &v);
                             pbl.current = v;
                         }
                     }
                     BatchMsg::UpdateChanged(id, v) => {
                         if let Some(pbl) = pb.by_id.get_mut(&id) {
                             if pbl.current != v {
-                                for cl in pbl.subscribed.iter() {
-                                    batch
-                                        .entry(*cl)
-                                        .or_insert_with(Update::new)
-                                        .updates
-                                        .push(publisher::From::Update(id, v.clone()));
-                                }
+                                pbl.queue_update(&mut batch, &mut multicast, id, &v);
                                 pbl.current = v;
                             }
                         }
                     }
-                    BatchMsg::Update(Some(cl), id, v) => batch
-                        .entry(cl)
-                        .or_insert_with(Update::new)
-                        .updates
-                        .push(publisher::From::Update(id, v)),
+                    BatchMsg::Update(Some(cl), id, v) => {
+                        if let Some(pbl) = pb.by_id.get_mut(&id) {
+                            pbl.stats.updates += 1;
+                            pbl.stats.bytes += v.encoded_len() as u64;
+                        }
+                        batch
+                            .entry(cl)
+                            .or_insert_with(Update::new)
+                            .updates
+                            .push(publisher::From::Update(id, v))
+                    }
                 }
             }
             if let Some(usubs) = &mut self.unsubscribes {
-                for (cl, id) in usubs.drain(..) {
+                for (cl, id, terminal) in usubs.drain(..) {
                     let update = batch.entry(cl).or_insert_with(Update::new);
                     match &mut update.unsubscribes {
-                        Some(u) => u.push(id),
+                        Some(u) => u.push((id, terminal)),
                         None => {
                             let mut u = UNSUBS.take();
-                            u.push(id);
+                            u.push((id, terminal));
                             update.unsubscribes = Some(u);
                         }
                     }
                 }
             }
-            future::join_all(
+            let ts = if pb.settings.timestamps { Some(Utc::now()) } else { None };
+            pb.batch_id += 1;
+            for update in batch.values_mut() {
+                update.timestamp = ts;
+                update.batch_id = pb.batch_id;
+            }
+            if let Some(m) = &pb.multicast {
+                if !multicast.is_empty() {
+                    let _: Result<_, _> = m.queue.unbounded_send((ts, multicast));
+                }
+            }
+            let slack = pb.settings.slack;
+            let mut slow = vec![];
+            let fut = future::join_all(
                 batch
                     .drain()
-                    .filter_map(|(cl, batch)| {
-                        pb.clients.get(&cl).map(move |cl| (cl.msg_queue.clone(), batch))
+                    .filter_map(|(id, batch)| {
+                        pb.clients.get(&id).map(|cl| {
+                            let depth =
+                                cl.congestion.queued.fetch_add(1, Ordering::Relaxed);
+                            if depth + 1 >= slack
+                                && !cl.congestion.slow.swap(true, Ordering::Relaxed)
+                            {
+                                slow.push(id);
+                            }
+                            (cl.msg_queue.clone(), batch)
+                        })
                     })
                     .map(|(mut q, batch)| async move {
                         let _: Result<_, _> = q.send((timeout, batch)).await;
                     }),
-            )
+            );
+            for id in slow {
+                pb.send_event(Event::SlowSubscriber(id));
+            }
+            fut
         };
         fut.await;
     }
@@ -826,6 +1152,7 @@ impl UpdateBatch {
 #[derive(Debug)]
 struct Client {
     msg_queue: MsgQ,
+    congestion: Arc<CongestionCtr>,
     subscribed: FxHashMap<Id, Permissions>,
     user: Option<UserInfo>,
 }
@@ -835,7 +1162,12 @@ pub struct Published {
     current: Value,
     subscribed: Subscribed,
     path: Path,
+    flags: Option<u32>,
+    stats: PathStats,
     aliases: Option<Box<FxHashSet<Path>>>,
+    // Some if the value may be multicast, holding the subscribers
+    // that accepted multicast delivery
+    multicast: Option<Box<FxHashSet<ClId>>>,
 }
 
 impl Published {
@@ -850,34 +1182,91 @@ impl Published {
     pub fn subscribed(&self) -> &FxHashSet<ClId> {
         &self.subscribed
     }
+
+    pub fn stats(&self) -> PathStats {
+        self.stats
+    }
+
+    // queue v for every subscriber that doesn't receive it by multicast
+    fn queue_update(
+        &mut self,
+        batch: &mut FxHashMap<ClId, Update>,
+        multicast: &mut Vec<(Id, Value)>,
+        id: Id,
+        v: &Value,
+    ) {
+        let mcast = match &self.multicast {
+            Some(m) if self.multicasting(v) => Some(m),
+            Some(_) | None => None,
+        };
+        let mut sent = 0;
+        for cl in self.subscribed.iter() {
+            if !mcast.map(|m| m.contains(cl)).unwrap_or(false) {
+                sent += 1;
+                batch
+                    .entry(*cl)
+                    .or_insert_with(Update::new)
+                    .updates
+                    .push(publisher::From::Update(id, v.clone()));
+            }
+        }
+        if mcast.is_some() {
+            sent += 1;
+            multicast.push((id, v.clone()))
+        }
+        self.stats.updates += 1;
+        if sent > 0 {
+            self.stats.bytes += sent * v.encoded_len() as u64;
+        }
+    }
+
+    // true if v will be multicast
+    fn multicasting(&self, v: &Value) -> bool {
+        match &self.multicast {
+            None => false,
+            // leave room for the datagram header
+            Some(m) => !m.is_empty() && v.encoded_len() <= DATAGRAM_SIZE - 64,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct PublisherInner {
     addr: SocketAddr,
+    alt_addrs: SmallVec<[SocketAddr; 2]>,
     stop: Option<oneshot::Sender<()>>,
     clients: FxHashMap<ClId, Client>,
     hc_subscribed: FxHashMap<BTreeSet<ClId>, Subscribed>,
     by_path: HashMap<Path, Id>,
     by_id: FxHashMap<Id, Published>,
     destroy_on_idle: FxHashSet<Id>,
+    conflate: FxHashSet<Id>,
     on_write_chans: FxHashMap<ChanWrap<Pooled<Vec<WriteRequest>>>, (ChanId, HashSet<Id>)>,
     on_event_chans: Vec<UnboundedSender<Event>>,
     on_event_by_id_chans: FxHashMap<Id, Vec<UnboundedSender<Event>>>,
     extended_auth: Option<ExtendedAuthWrap>,
+    snapshot: FxHashMap<Id, SnapshotWrap>,
     on_write: FxHashMap<Id, Vec<(ChanId, Sender<Pooled<Vec<WriteRequest>>>)>>,
     resolver: ResolverWrite,
     advertised: HashMap<Path, HashSet<Path>>,
     to_publish: Pooled<HashMap<Path, Option<u32>>>,
+    metadata: HashMap<Path, Metadata>,
     to_publish_default: Pooled<HashMap<Path, Option<u32>>>,
     to_unpublish: Pooled<HashSet<Path>>,
     to_unpublish_default: Pooled<HashSet<Path>>,
-    to_unsubscribe: Pooled<HashMap<Id, Subscribed>>,
+    to_unsubscribe: Pooled<HashMap<Id, (Subscribed, Value)>>,
     publish_triggered: bool,
     trigger_publish: UnboundedSender<Option<oneshot::Sender<()>>>,
     wait_clients: FxHashMap<Id, Vec<oneshot::Sender<()>>>,
     wait_any_client: Vec<oneshot::Sender<()>>,
     default: BTreeMap<Path, UnboundedSender<(Path, oneshot::Sender<()>)>>,
+    settings: Settings,
+    ttl: FxHashMap<Id, Ttl>,
+    ttl_trigger: Option<UnboundedSender<()>>,
+    batch_id: u64,
+    heartbeat: Duration,
+    heartbeat_timeout: Duration,
+    multicast: Option<Multicast>,
 }
 
 impl PublisherInner {
@@ -922,17 +1311,72 @@ impl PublisherInner {
         self.trigger_publish();
     }
 
+    fn publish_val(
+        &mut self,
+        mut flags: PublishFlags,
+        path: Path,
+        init: Value,
+        tx: Option<Sender<Pooled<Vec<WriteRequest>>>>,
+    ) -> Result<Id> {
+        let id = Id::new();
+        let destroy_on_idle = flags.contains(PublishFlags::DESTROY_ON_IDLE);
+        flags.remove(PublishFlags::DESTROY_ON_IDLE);
+        let conflate = flags.contains(PublishFlags::CONFLATE);
+        flags.remove(PublishFlags::CONFLATE);
+        let multicast = match &self.multicast {
+            None => false,
+            Some(m) => {
+                flags.contains(PublishFlags::MULTICAST)
+                    || m.cfg.namespaces.iter().any(|b| Path::is_parent(&**b, &*path))
+            }
+        };
+        flags.remove(PublishFlags::MULTICAST);
+        self.check_publish(&path)?;
+        let subscribed = self
+            .hc_subscribed
+            .entry(BTreeSet::new())
+            .or_insert_with(|| Arc::new(HashSet::default()))
+            .clone();
+        self.by_id.insert(
+            id,
+            Published {
+                current: init,
+                subscribed,
+                path: path.clone(),
+                flags: if flags.is_empty() { None } else { Some(flags.bits()) },
+                stats: PathStats::default(),
+                aliases: None,
+                multicast: if multicast { Some(Box::default()) } else { None },
+            },
+        );
+        if destroy_on_idle {
+            self.destroy_on_idle.insert(id);
+        }
+        if conflate {
+            self.conflate.insert(id);
+        }
+        if let Some(tx) = tx {
+            self.writes(id, tx);
+        }
+        self.publish(id, flags, path);
+        Ok(id)
+    }
+
     fn unpublish(&mut self, path: &Path) {
         self.by_path.remove(path);
         if !self.is_advertised(path) {
+            self.metadata.remove(path);
             self.to_publish.remove(path);
             self.to_unpublish.insert(path.clone());
             self.trigger_publish();
         }
     }
 
-    fn destroy_val(&mut self, id: Id) {
+    fn destroy_val(&mut self, id: Id, terminal: Option<Value>) {
         if let Some(pbl) = self.by_id.remove(&id) {
+            self.ttl.remove(&id);
+            self.conflate.remove(&id);
+            self.snapshot.remove(&id);
             let path = pbl.path;
             for path in iter::once(&path).chain(pbl.aliases.iter().flat_map(|v| v.iter()))
             {
@@ -955,7 +1399,8 @@ impl PublisherInner {
             self.send_event(Event::Destroyed(id));
             self.on_event_by_id_chans.remove(&id);
             if pbl.subscribed.len() > 0 {
-                self.to_unsubscribe.insert(id, pbl.subscribed);
+                let terminal = terminal.unwrap_or(pbl.current);
+                self.to_unsubscribe.insert(id, (pbl.subscribed, terminal));
             }
         }
     }
@@ -966,6 +1411,7 @@ impl PublisherInner {
             Event::Destroyed(id) => id,
             Event::Subscribe(id, _) => id,
             Event::Unsubscribe(id, _) => id,
+            Event::SlowSubscriber(_) | Event::SubscriberDropped(_) => return,
         };
         if let Some(chans) = self.on_event_by_id_chans.get_mut(id) {
             chans.retain(|chan| chan.unbounded_send(event).is_ok());
@@ -975,6 +1421,23 @@ impl PublisherInner {
         }
     }
 
+    fn restart_ttl(&mut self, id: &Id, now: Instant) {
+        if let Some(ttl) = self.ttl.get_mut(id) {
+            let deadline = now + ttl.ttl;
+            // the ttl loop only waits for the earliest deadline of the
+            // values that haven't expired, so it must be woken if this
+            // value has expired, or if its deadline moved earlier
+            let wake = ttl.expired || deadline < ttl.deadline;
+            ttl.deadline = deadline;
+            ttl.expired = false;
+            if wake {
+                if let Some(trigger) = &self.ttl_trigger {
+                    let _: Result<_, _> = trigger.unbounded_send(());
+                }
+            }
+        }
+    }
+
     fn trigger_publish(&mut self) {
         if !self.publish_triggered {
             self.publish_triggered = true;
@@ -1033,13 +1496,338 @@ fn rand_port(current: u16) -> u16 {
     current + rng.gen_range(0u16..10u16)
 }
 
+/// Publisher level settings that may be changed while the publisher
+/// is running. See `Publisher::set_settings`,
+/// `Publisher::load_settings`, `Publisher::watch_settings`, and
+/// `Publisher::publish_settings`.
+///
+/// Only the fields below can be reloaded. Everything else, e.g. the
+/// bind address, authentication, or the resolver config, is fixed
+/// when the publisher is built. A settings file that names any other
+/// field is rejected, and the current settings are kept.
+///
+/// Some related settings don't belong to the publisher, so they
+/// aren't here. Permissions and rate limits are enforced by the
+/// resolver server, and are reloaded with its config, see
+/// `resolver_server::Server::reload`. Priorities and update filters
+/// are chosen by each subscriber, see `Subscriber::set_priority` and
+/// `Subscriber::set_filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// The maximum number of simultaneous subscribers. Lowering this
+    /// will not disconnect existing clients, but new clients will be
+    /// refused until the number of clients falls below the limit.
+    #[serde(default = "Settings::default_max_clients")]
+    pub max_clients: usize,
+    /// The maximum number of queued batches for a client before
+    /// there is pushback. A client's queue is allocated when it
+    /// connects, so the pushback only changes for clients that
+    /// connect after the change. The threshold for
+    /// `Event::SlowSubscriber` changes immediately for everyone.
+    #[serde(default = "Settings::default_slack")]
+    pub slack: usize,
+    /// Timestamp committed batches, see
+    /// `PublisherBuilder::timestamps`.
+    #[serde(default)]
+    pub timestamps: bool,
+    /// Compress batches larger than this many bytes, see
+    /// `PublisherBuilder::compression`. Connected clients pick up
+    /// changes at their next heartbeat.
+    #[serde(default)]
+    pub compression: Option<usize>,
+    /// Conflate updates to values published with
+    /// `PublishFlags::CONFLATE` when a subscriber falls behind. If
+    /// this is turned off those values are delivered reliably, like
+    /// every other value, until it is turned back on.
+    #[serde(default = "Settings::default_conflate")]
+    pub conflate: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_clients: Self::default_max_clients(),
+            slack: Self::default_slack(),
+            timestamps: false,
+            compression: None,
+            conflate: Self::default_conflate(),
+        }
+    }
+}
+
+impl Settings {
+    fn default_max_clients() -> usize {
+        768
+    }
+
+    fn default_slack() -> usize {
+        3
+    }
+
+    fn default_conflate() -> bool {
+        true
+    }
+
+    /// Load settings from a json file. Missing fields take their
+    /// default values.
+    pub fn load<P: AsRef<std::path::Path>>(file: P) -> Result<Settings> {
+        Ok(serde_json::from_reader(std::fs::File::open(file)?)?)
+    }
+}
+
+/// Multicast distribution of updates, for LAN deployments where many
+/// subscribers subscribe to the same values. Updates to multicast
+/// values are sent once to `group` instead of once to every
+/// subscriber. Subscribers that support it join the group when they
+/// subscribe, and subscribers that don't, or can't, keep receiving
+/// updates over their connection as usual. Datagrams can be lost, so
+/// the current value of every multicast value is sent again every
+/// `snapshot`, a lost update is repaired within that time.
+///
+/// Anyone who can join the group can read multicast datagrams, they
+/// are neither encrypted nor authenticated, so multicast is only
+/// offered to anonymous subscribers, and only accepted by them.
+/// Publishers that require authentication send every update over
+/// each subscriber's connection. Values with a snapshot hook are
+/// never multicast, see `Publisher::set_snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticastCfg {
+    /// The multicast group, and port, to send updates to
+    pub group: SocketAddr,
+    /// Values published under any of these paths are multicast as
+    /// if they were published with `PublishFlags::MULTICAST`
+    pub namespaces: Vec<Path>,
+    /// How often the current values are sent again
+    pub snapshot: Duration,
+    /// The number of hops datagrams may travel, ipv4 only
+    pub ttl: u32,
+}
+
+impl MulticastCfg {
+    /// Multicast to `group`, with no namespaces, a snapshot every
+    /// second, and a ttl of 1, the local network.
+    pub fn new(group: SocketAddr) -> Self {
+        Self { group, namespaces: vec![], snapshot: Duration::from_secs(1), ttl: 1 }
+    }
+}
+
+#[derive(Debug)]
+struct Multicast {
+    cfg: MulticastCfg,
+    // distinguishes our datagrams from other publishers sending to
+    // the same group
+    stream: u64,
+    queue: UnboundedSender<(Option<DateTime<Utc>>, Vec<(Id, Value)>)>,
+}
+
+async fn send_datagrams(
+    socket: &UdpSocket,
+    group: SocketAddr,
+    mut dgram: publisher::Datagram,
+    seq: &mut u64,
+    updates: Vec<(Id, Value)>,
+) {
+    async fn send(
+        socket: &UdpSocket,
+        group: SocketAddr,
+        dgram: &mut publisher::Datagram,
+        seq: &mut u64,
+    ) {
+        dgram.seq = *seq;
+        *seq += 1;
+        match utils::pack(&*dgram) {
+            Err(e) => warn!("failed to encode datagram {}", e),
+            Ok(buf) => {
+                if let Err(e) = socket.send_to(&buf, group).await {
+                    warn!("failed to send datagram to {}, {}", group, e)
+                }
+            }
+        }
+        dgram.updates.clear();
+    }
+    let base = dgram.encoded_len();
+    let mut len = base;
+    for (id, v) in updates {
+        let ulen = id.encoded_len() + v.encoded_len();
+        if len + ulen > DATAGRAM_SIZE && !dgram.updates.is_empty() {
+            send(socket, group, &mut dgram, seq).await;
+            len = base;
+        }
+        len += ulen;
+        dgram.updates.push((id, v));
+    }
+    if !dgram.updates.is_empty() {
+        send(socket, group, &mut dgram, seq).await;
+    }
+}
+
+async fn multicast_loop(
+    publisher: PublisherWeak,
+    socket: UdpSocket,
+    mut updates: UnboundedReceiver<(Option<DateTime<Utc>>, Vec<(Id, Value)>)>,
+) {
+    let (group, stream, snapshot) = match publisher.upgrade() {
+        None => return,
+        Some(pb) => match &pb.0.lock().multicast {
+            None => return,
+            Some(m) => (m.cfg.group, m.stream, m.cfg.snapshot),
+        },
+    };
+    let dgram = |snapshot, timestamp| publisher::Datagram {
+        stream,
+        seq: 0,
+        snapshot,
+        timestamp,
+        updates: vec![],
+    };
+    let mut seq = 0;
+    let mut snap = time::interval(snapshot);
+    loop {
+        select_biased! {
+            u = updates.next() => match u {
+                None => break,
+                Some((ts, u)) => {
+                    send_datagrams(&socket, group, dgram(false, ts), &mut seq, u).await
+                }
+            },
+            _ = snap.tick().fuse() => {
+                let (queued, current) = match publisher.upgrade() {
+                    None => break,
+                    Some(pb) => {
+                        // updates are queued while holding the lock,
+                        // so they must all be sent before the snapshot
+                        // or they could overwrite newer values
+                        let pb = pb.0.lock();
+                        let mut queued = vec![];
+                        while let Ok(Some(u)) = updates.try_next() {
+                            queued.push(u)
+                        }
+                        let current = pb
+                            .by_id
+                            .iter()
+                            .filter(|(_, p)| p.multicasting(&p.current))
+                            .map(|(id, p)| (*id, p.current.clone()))
+                            .collect::<Vec<_>>();
+                        (queued, current)
+                    }
+                };
+                for (ts, u) in queued {
+                    send_datagrams(&socket, group, dgram(false, ts), &mut seq, u).await
+                }
+                send_datagrams(&socket, group, dgram(true, None), &mut seq, current).await
+            },
+        }
+    }
+}
+
+/// The metrics published by `Publisher::publish_resolver_stats`.
+/// When it is dropped the metrics will be unpublished.
+pub struct ResolverStatsCtl {
+    _stop: oneshot::Sender<()>,
+}
+
+/// The metrics published by `Publisher::publish_path_stats`. When
+/// it is dropped the metrics will be unpublished.
+pub struct PathStatsCtl {
+    _stop: oneshot::Sender<()>,
+}
+
+/// Returned by `Publisher::watch_settings`. When it is dropped the
+/// settings file is no longer watched.
+pub struct SettingsWatch {
+    _stop: oneshot::Sender<()>,
+    check: UnboundedSender<oneshot::Sender<()>>,
+}
+
+impl SettingsWatch {
+    /// Check the settings file now instead of waiting for the next
+    /// interval, and wait for the check to finish.
+    pub async fn check(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.check.unbounded_send(tx).is_ok() {
+            let _: result::Result<_, _> = rx.await;
+        }
+    }
+}
+
+/// The control interface published by `Publisher::publish_settings`.
+/// When it is dropped the control paths will be unpublished.
+pub struct SettingsCtl {
+    max_clients: Val,
+    slack: Val,
+    timestamps: Val,
+    compression: Val,
+    conflate: Val,
+}
+
+/// A field of `Settings` published by `Publisher::publish_settings`
+#[derive(Debug, Clone, Copy)]
+enum Setting {
+    MaxClients,
+    Slack,
+    Timestamps,
+    Compression,
+    Conflate,
+}
+
+impl Setting {
+    /// Set this field of `settings` to `v`, returning the value as it
+    /// should be published.
+    fn apply(
+        self,
+        settings: &mut Settings,
+        v: Value,
+    ) -> result::Result<Value, &'static str> {
+        match self {
+            Setting::MaxClients | Setting::Slack => match v.cast_to::<u64>() {
+                Ok(n) if n > 0 => {
+                    match self {
+                        Setting::MaxClients => settings.max_clients = n as usize,
+                        _ => settings.slack = n as usize,
+                    }
+                    Ok(Value::from(n))
+                }
+                Ok(_) | Err(_) => Err("expected a positive integer"),
+            },
+            Setting::Compression => match v.cast_to::<u64>() {
+                Ok(n) => {
+                    settings.compression = if n == 0 { None } else { Some(n as usize) };
+                    Ok(Value::from(n))
+                }
+                Err(_) => Err("expected a non negative integer"),
+            },
+            Setting::Timestamps | Setting::Conflate => match v.cast_to::<bool>() {
+                Ok(b) => {
+                    match self {
+                        Setting::Timestamps => settings.timestamps = b,
+                        _ => settings.conflate = b,
+                    }
+                    Ok(Value::from(b))
+                }
+                Err(_) => Err("expected a bool"),
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PublisherBuilder {
     config: Option<Config>,
     desired_auth: Option<DesiredAuth>,
     bind_cfg: Option<BindCfg>,
+    alt_bind_cfg: Option<BindCfg>,
+    port_range: Option<(u16, u16)>,
+    advertise: Option<SocketAddr>,
     max_clients: usize,
     slack: usize,
+    timestamps: bool,
+    compress_above: Option<usize>,
+    heartbeat: Duration,
+    heartbeat_timeout: Duration,
+    local_endpoint: bool,
+    multicast: Option<MulticastCfg>,
+    socket_opts: Option<SocketOpts>,
 }
 
 impl PublisherBuilder {
@@ -1048,17 +1836,52 @@ impl PublisherBuilder {
             config: Some(config),
             desired_auth: None,
             bind_cfg: None,
-            max_clients: 768,
-            slack: 3,
+            alt_bind_cfg: None,
+            port_range: None,
+            advertise: None,
+            max_clients: Settings::default_max_clients(),
+            slack: Settings::default_slack(),
+            timestamps: false,
+            compress_above: None,
+            heartbeat: DEFAULT_HEARTBEAT,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            local_endpoint: false,
+            multicast: None,
+            socket_opts: None,
         }
     }
 
     pub async fn build(&mut self) -> Result<Publisher> {
-        let cfg = self.config.take().unwrap();
+        let mut cfg = self.config.take().unwrap();
+        if let Some(opts) = self.socket_opts.take() {
+            cfg.socket = opts;
+        }
+        if let Some(ports) = self.port_range.take() {
+            cfg.publisher_ports = Some(ports);
+        }
+        if let Some(addr) = self.advertise.take() {
+            cfg.publisher_advertise = Some(addr);
+        }
         let desired_auth = self.desired_auth.take().unwrap_or_else(|| cfg.default_auth());
         let bind_cfg =
             self.bind_cfg.take().unwrap_or_else(|| cfg.default_bind_config.clone());
-        Publisher::new(cfg, desired_auth, bind_cfg, self.max_clients, self.slack).await
+        let pb = Publisher::new_inner(
+            cfg,
+            desired_auth,
+            bind_cfg,
+            self.alt_bind_cfg.take(),
+            self.max_clients,
+            self.slack,
+            self.local_endpoint,
+        )
+        .await?;
+        pb.set_timestamps(self.timestamps);
+        pb.set_compression(self.compress_above);
+        pb.set_heartbeat(self.heartbeat, self.heartbeat_timeout)?;
+        if let Some(cfg) = self.multicast.take() {
+            pb.start_multicast(cfg).await?;
+        }
+        Ok(pb)
     }
 
     /// The desired authentication mechanism you want to use. If not
@@ -1076,6 +1899,36 @@ impl PublisherBuilder {
         self
     }
 
+    /// Also listen on an address selected by `bind`, usually of the
+    /// other address family than `bind_cfg`, e.g. `::1/128` along
+    /// with the default `local`. Both addresses are registered with
+    /// the resolver, and subscribers will prefer the one matching
+    /// their own address family. default None.
+    pub fn alt_bind_cfg(&mut self, bind: Option<BindCfg>) -> &mut Self {
+        self.alt_bind_cfg = bind;
+        self
+    }
+
+    /// Only listen on a port in the inclusive range `(start, end)`,
+    /// e.g. the ports a firewall or container allows. This applies
+    /// to `alt_bind_cfg` too. An `Exact` or `ElasticExact` bind config
+    /// with a port outside the range is an error. If not specified
+    /// then the config default will be used.
+    pub fn port_range(&mut self, ports: Option<(u16, u16)>) -> &mut Self {
+        self.port_range = ports;
+        self
+    }
+
+    /// Register `addr` with the resolver instead of the address the
+    /// listener selected by `bind_cfg` is bound to, e.g. the external
+    /// side of a NAT or a container port mapping. If the port of
+    /// `addr` is 0 the bound port is advertised. If not specified
+    /// then the config default will be used.
+    pub fn advertise(&mut self, addr: Option<SocketAddr>) -> &mut Self {
+        self.advertise = addr;
+        self
+    }
+
     /// The maximum number of simultaneous subscribers. default 768.
     pub fn max_clients(&mut self, max_clients: usize) -> &mut Self {
         self.max_clients = max_clients;
@@ -1089,6 +1942,63 @@ impl PublisherBuilder {
         self.slack = slack;
         self
     }
+
+    /// If true, every committed batch is stamped with the time of
+    /// the commit, and subscribers that support it receive the
+    /// stamp with their updates. default false.
+    pub fn timestamps(&mut self, timestamps: bool) -> &mut Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// If set, batches larger than this many bytes are compressed
+    /// with zstd before they are sent to subscribers that support
+    /// it. Compression costs cpu on both sides, so it is mostly
+    /// useful for large batches, e.g. initial snapshots, sent over
+    /// slow links. default None.
+    pub fn compression(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.compress_above = threshold;
+        self
+    }
+
+    /// Send a heartbeat to idle subscribers every `interval`, and
+    /// disconnect subscribers that support heartbeats if nothing has
+    /// been heard from them for `timeout`. This detects half open
+    /// connections, e.g. after a NAT timeout, much faster than tcp
+    /// keepalive. `timeout` must be longer than the heartbeat
+    /// interval configured on the subscriber side. default 5s, 60s.
+    pub fn heartbeat(&mut self, interval: Duration, timeout: Duration) -> &mut Self {
+        self.heartbeat = interval;
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// If true, in addition to the tcp listener, listen on a unix
+    /// domain socket and advertise it to the resolver. Subscribers
+    /// on the same machine will connect to it instead of using tcp,
+    /// authentication is the same over both transports. Only
+    /// supported on unix. default false.
+    pub fn local_endpoint(&mut self, local_endpoint: bool) -> &mut Self {
+        self.local_endpoint = local_endpoint;
+        self
+    }
+
+    /// If set, values published with `PublishFlags::MULTICAST`, or
+    /// under one of the configured namespaces, are distributed by
+    /// multicast to subscribers that support it. Multicast is never
+    /// authenticated, so it is only offered to anonymous
+    /// subscribers. See `MulticastCfg`. default None.
+    pub fn multicast(&mut self, cfg: Option<MulticastCfg>) -> &mut Self {
+        self.multicast = cfg;
+        self
+    }
+
+    /// The options for tcp connections from subscribers. If not
+    /// specified then the `socket` section of the config is used.
+    pub fn socket_opts(&mut self, opts: SocketOpts) -> &mut Self {
+        self.socket_opts = Some(opts);
+        self
+    }
 }
 
 /// Publish values. Publisher is internally wrapped in an Arc, so
@@ -1112,62 +2022,154 @@ impl Publisher {
         max_clients: usize,
         slack: usize,
     ) -> Result<Publisher> {
+        Self::new_inner(resolver, desired_auth, bind_cfg, None, max_clients, slack, false)
+            .await
+    }
+
+    async fn bind(
+        resolver: &Config,
+        bind_cfg: BindCfg,
+        advertise: Option<SocketAddr>,
+    ) -> Result<(SocketAddr, TcpListener)> {
+        async fn try_bind(
+            public: IpAddr,
+            private: IpAddr,
+            port: u16,
+        ) -> Result<Option<(SocketAddr, TcpListener)>> {
+            let mkaddr = |ip: IpAddr, port: u16| -> Result<SocketAddr> {
+                Ok((ip, port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| anyhow!("socketaddrs bug"))?)
+            };
+            match TcpListener::bind(&mkaddr(private, port)?).await {
+                Ok(l) => Ok(Some((mkaddr(public, port)?, l))),
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Ok(None),
+                Err(e) => bail!(e),
+            }
+        }
         let (public, private) = bind_cfg.select()?;
-        utils::check_addr(public, &resolver.addrs)?;
+        match advertise {
+            None => utils::check_addr(public, &resolver.addrs)?,
+            Some(addr) => utils::check_advertised_addr(addr, &resolver.addrs)?,
+        }
+        if let Some((start, end)) = resolver.publisher_ports {
+            utils::check_port_range(start, end)?
+        }
+        let check_port = |port: u16| match resolver.publisher_ports {
+            Some((start, end)) if port < start || port > end => {
+                bail!("port {} is outside the port range {}-{}", port, start, end)
+            }
+            Some(_) | None => Ok(()),
+        };
         let (addr, listener) = match bind_cfg {
-            BindCfg::Exact(addr) => {
+            BindCfg::Exact(addr)
+                if addr.port() != 0 || resolver.publisher_ports.is_none() =>
+            {
+                check_port(addr.port())?;
                 let l = TcpListener::bind(&addr).await?;
                 (l.local_addr()?, l)
             }
             BindCfg::ElasticExact { public, private } => {
+                check_port(private.port())?;
                 let l = TcpListener::bind(&private).await?;
                 (public, l)
             }
-            BindCfg::Match { .. } | BindCfg::Local | BindCfg::Elastic { .. } => {
-                let mkaddr = |ip: IpAddr, port: u16| -> Result<SocketAddr> {
-                    Ok((ip, port)
-                        .to_socket_addrs()?
-                        .next()
-                        .ok_or_else(|| anyhow!("socketaddrs bug"))?)
-                };
-                let mut port = 5000;
-                loop {
-                    if port >= 32768 {
-                        bail!("couldn't allocate a port");
+            BindCfg::Exact(_)
+            | BindCfg::Match { .. }
+            | BindCfg::Local
+            | BindCfg::Elastic { .. } => match resolver.publisher_ports {
+                None => {
+                    let mut port = 5000;
+                    loop {
+                        if port >= 32768 {
+                            bail!("couldn't allocate a port");
+                        }
+                        port = rand_port(port);
+                        if let Some(r) = try_bind(public, private, port).await? {
+                            break r;
+                        }
                     }
-                    port = rand_port(port);
-                    let addr = mkaddr(private, port)?;
-                    match TcpListener::bind(&addr).await {
-                        Ok(l) => break (mkaddr(public, port)?, l),
-                        Err(e) => {
-                            if e.kind() != std::io::ErrorKind::AddrInUse {
-                                bail!(e)
-                            }
+                }
+                Some((start, end)) => {
+                    // try every port in the range, starting at a random one
+                    let n = (end - start) as u32 + 1;
+                    let offset = rand::thread_rng().gen_range(0..n);
+                    let mut res = None;
+                    for i in 0..n {
+                        let port = start + ((offset + i) % n) as u16;
+                        res = try_bind(public, private, port).await?;
+                        if res.is_some() {
+                            break;
                         }
                     }
+                    res.ok_or_else(|| {
+                        anyhow!("no free port in the range {}-{}", start, end)
+                    })?
                 }
+            },
+        };
+        let addr = match advertise {
+            None => addr,
+            Some(adv) if adv.port() == 0 => SocketAddr::new(adv.ip(), addr.port()),
+            Some(adv) => adv,
+        };
+        Ok((addr, listener))
+    }
+
+    async fn new_inner(
+        resolver: Config,
+        desired_auth: DesiredAuth,
+        bind_cfg: BindCfg,
+        alt_bind_cfg: Option<BindCfg>,
+        max_clients: usize,
+        slack: usize,
+        local_endpoint: bool,
+    ) -> Result<Publisher> {
+        let socket_opts = resolver.socket.clone();
+        let (addr, listener) =
+            Self::bind(&resolver, bind_cfg, resolver.publisher_advertise).await?;
+        socket_opts.apply_listener(&listener)?;
+        let (alt_addrs, alt) = match alt_bind_cfg {
+            None => (SmallVec::new(), None),
+            Some(bind_cfg) => {
+                let (alt_addr, l) = Self::bind(&resolver, bind_cfg, None).await?;
+                socket_opts.apply_listener(&l)?;
+                (smallvec![alt_addr], Some(l))
             }
         };
         let tls_ctx = resolver.tls.clone().map(tls::CachedAcceptor::new);
-        let resolver = ResolverWrite::new(resolver, desired_auth.clone(), addr)?;
+        let local =
+            if local_endpoint { Some(local_transport::Listener::bind()?) } else { None };
+        let resolver = ResolverWrite::new_with_endpoints(
+            resolver,
+            desired_auth.clone(),
+            addr,
+            local.as_ref().map(|l| l.endpoint().clone()),
+            alt_addrs.clone(),
+        )?;
         let (stop, receive_stop) = oneshot::channel();
         let (tx_trigger, rx_trigger) = unbounded();
         let pb = Publisher(Arc::new(Mutex::new(PublisherInner {
             addr,
+            alt_addrs,
             stop: Some(stop),
             clients: HashMap::default(),
             hc_subscribed: HashMap::default(),
             by_path: HashMap::new(),
             by_id: HashMap::default(),
             destroy_on_idle: HashSet::default(),
+            conflate: HashSet::default(),
             on_write_chans: HashMap::default(),
             on_event_chans: Vec::new(),
             on_event_by_id_chans: HashMap::default(),
             extended_auth: None,
+            snapshot: HashMap::default(),
             on_write: HashMap::default(),
             resolver,
             advertised: HashMap::new(),
             to_publish: TOPUB.take(),
+            metadata: HashMap::new(),
             to_publish_default: TOPUB.take(),
             to_unpublish: TOUPUB.take(),
             to_unpublish_default: TOUPUB.take(),
@@ -1177,6 +2179,13 @@ impl Publisher {
             wait_clients: HashMap::default(),
             wait_any_client: Vec::new(),
             default: BTreeMap::new(),
+            settings: Settings { max_clients, slack, ..Settings::default() },
+            ttl: HashMap::default(),
+            ttl_trigger: None,
+            batch_id: 0,
+            heartbeat: DEFAULT_HEARTBEAT,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            multicast: None,
         })));
         task::spawn({
             let pb_weak = pb.downgrade();
@@ -1184,11 +2193,12 @@ impl Publisher {
                 server::start(
                     pb_weak.clone(),
                     listener,
+                    alt,
+                    local,
                     receive_stop,
                     desired_auth,
                     tls_ctx,
-                    max_clients,
-                    slack,
+                    socket_opts,
                 )
                 .await;
                 info!("accept loop shutdown");
@@ -1205,6 +2215,31 @@ impl Publisher {
         Ok(pb)
     }
 
+    async fn start_multicast(&self, cfg: MulticastCfg) -> Result<()> {
+        if !cfg.group.ip().is_multicast() {
+            bail!("{} is not a multicast address", cfg.group.ip())
+        }
+        let socket = match cfg.group {
+            SocketAddr::V4(_) => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+                socket.set_multicast_ttl_v4(cfg.ttl)?;
+                socket
+            }
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?,
+        };
+        let (tx, rx) = unbounded();
+        let stream = rand::thread_rng().gen::<u64>();
+        self.0.lock().multicast = Some(Multicast { cfg, stream, queue: tx });
+        task::spawn({
+            let pb_weak = self.downgrade();
+            async move {
+                multicast_loop(pb_weak, socket, rx).await;
+                info!("multicast loop shutdown")
+            }
+        });
+        Ok(())
+    }
+
     /// Set an extended authorization function for the publisher. `f`
     /// will be called on every subscription with the client, id, and
     /// user info. If it returns false then the subscription will be
@@ -1225,6 +2260,366 @@ impl Publisher {
         self.0.lock().extended_auth = None;
     }
 
+    /// Set a hook that computes the first value each new subscriber
+    /// to `id` receives, instead of the current value. It is called
+    /// with the subscriber's id, the id of the value, and the current
+    /// value. Updates are still sent to every subscriber, so this can
+    /// be used to implement a snapshot + delta protocol, e.g. send
+    /// the full book to new subscribers, and only the changes after
+    /// that, without publishing a separate snapshot path.
+    ///
+    /// The snapshot is sent in place of the current value, so updates
+    /// queued in a batch that isn't committed yet will be delivered
+    /// after it. The hook is also used to resync subscribers that
+    /// stop receiving the value by multicast.
+    ///
+    /// Multicast snapshots send the current value to every member of
+    /// the group, so setting a hook stops multicasting `id`, and it
+    /// stays unicast even if the hook is later cleared.
+    ///
+    /// The hook is called with the publisher locked, calling
+    /// publisher methods from it will deadlock. Setting a new hook
+    /// replaces the old one, the hook is removed when the value is
+    /// unpublished.
+    pub fn set_snapshot(&self, id: Id, f: Snapshot) {
+        let mut pb = self.0.lock();
+        if let Some(pbl) = pb.by_id.get_mut(&id) {
+            pbl.multicast = None;
+            pb.snapshot.insert(id, SnapshotWrap(f));
+        }
+    }
+
+    /// Remove the snapshot hook of `id`, new subscribers will receive
+    /// the current value.
+    pub fn clear_snapshot(&self, id: Id) {
+        self.0.lock().snapshot.remove(&id);
+    }
+
+    /// Get the current publisher settings
+    pub fn settings(&self) -> Settings {
+        self.0.lock().settings
+    }
+
+    /// Replace the publisher settings. The new settings take effect
+    /// immediately, there is no need to restart the publisher.
+    pub fn set_settings(&self, settings: Settings) {
+        self.0.lock().settings = settings;
+    }
+
+    /// Enable or disable timestamping committed batches. See
+    /// `PublisherBuilder::timestamps`.
+    pub fn set_timestamps(&self, timestamps: bool) {
+        self.0.lock().settings.timestamps = timestamps;
+    }
+
+    /// Set the compression threshold. See
+    /// `PublisherBuilder::compression`. Connected subscribers pick up
+    /// the change at their next heartbeat.
+    pub fn set_compression(&self, threshold: Option<usize>) {
+        self.0.lock().settings.compression = threshold;
+    }
+
+    /// Set the heartbeat interval and timeout. See
+    /// `PublisherBuilder::heartbeat`. This only applies to
+    /// subscribers that connect after it is called.
+    pub fn set_heartbeat(&self, interval: Duration, timeout: Duration) -> Result<()> {
+        if interval.is_zero() || timeout <= interval {
+            bail!("the heartbeat timeout must be longer than the interval")
+        }
+        let mut pb = self.0.lock();
+        pb.heartbeat = interval;
+        pb.heartbeat_timeout = timeout;
+        Ok(())
+    }
+
+    /// Reload the publisher settings from the json file `file`. If
+    /// the file can't be read or parsed then the current settings are
+    /// left unchanged.
+    pub fn load_settings<P: AsRef<std::path::Path>>(&self, file: P) -> Result<()> {
+        let settings = Settings::load(file)?;
+        info!("publisher {} reloaded settings {:?}", self.addr(), settings);
+        Ok(self.set_settings(settings))
+    }
+
+    /// Reload the publisher settings from the json file `file`
+    /// whenever its contents change, checking every `interval`, or
+    /// when `SettingsWatch::check` is called. The file is loaded at
+    /// the first check if it exists. If the file can't be read or
+    /// parsed the current settings are left unchanged and the error
+    /// is logged. The file stops being watched when the returned
+    /// `SettingsWatch` is dropped.
+    pub fn watch_settings(&self, file: PathBuf, interval: Duration) -> SettingsWatch {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let (check_tx, mut check_rx) = unbounded::<oneshot::Sender<()>>();
+        let t = self.downgrade();
+        task::spawn(async move {
+            let mut loaded: Option<Vec<u8>> = None;
+            let mut missing = false;
+            let mut interval = time::interval(interval);
+            let mut stop = stop_rx.fuse();
+            loop {
+                let fin = select_biased! {
+                    _ = stop => break,
+                    fin = check_rx.next() => fin,
+                    _ = interval.tick().fuse() => None,
+                };
+                let t = match t.upgrade() {
+                    None => break,
+                    Some(t) => t,
+                };
+                match fs::read(&file).await {
+                    Err(e) => {
+                        if !missing {
+                            warn!("can't read settings file {:?} {}", file, e);
+                            missing = true;
+                        }
+                    }
+                    Ok(contents) => {
+                        missing = false;
+                        if loaded.as_ref() != Some(&contents) {
+                            match serde_json::from_slice::<Settings>(&contents) {
+                                Err(e) => {
+                                    warn!("failed to load settings from {:?} {}", file, e)
+                                }
+                                Ok(settings) => {
+                                    info!(
+                                        "publisher {} reloaded settings {:?}",
+                                        t.addr(),
+                                        settings
+                                    );
+                                    t.set_settings(settings)
+                                }
+                            }
+                            loaded = Some(contents);
+                        }
+                    }
+                }
+                if let Some(fin) = fin {
+                    let _: result::Result<_, _> = fin.send(());
+                }
+            }
+        });
+        SettingsWatch { _stop: stop_tx, check: check_tx }
+    }
+
+    /// Publish the publisher settings as writable values under
+    /// `base`, e.g. `base/max_clients` and `base/conflate`, one for
+    /// each field of `Settings`. Writing to one of these paths will
+    /// update the running publisher. A compression threshold of 0
+    /// turns compression off. The control paths are unpublished when
+    /// the returned `SettingsCtl` is dropped.
+    pub fn publish_settings(&self, base: Path) -> Result<SettingsCtl> {
+        let cur = self.settings();
+        let (tx, mut rx) = futures::channel::mpsc::channel(3);
+        let publish = |name: &str, v: Value| {
+            self.publish_with_flags_and_writes(
+                PublishFlags::empty(),
+                base.append(name),
+                v,
+                Some(tx.clone()),
+            )
+        };
+        let ctl = SettingsCtl {
+            max_clients: publish("max_clients", Value::from(cur.max_clients as u64))?,
+            slack: publish("slack", Value::from(cur.slack as u64))?,
+            timestamps: publish("timestamps", Value::from(cur.timestamps))?,
+            compression: publish(
+                "compression",
+                Value::from(cur.compression.unwrap_or(0) as u64),
+            )?,
+            conflate: publish("conflate", Value::from(cur.conflate))?,
+        };
+        let ids = [
+            (ctl.max_clients.id(), Setting::MaxClients),
+            (ctl.slack.id(), Setting::Slack),
+            (ctl.timestamps.id(), Setting::Timestamps),
+            (ctl.compression.id(), Setting::Compression),
+            (ctl.conflate.id(), Setting::Conflate),
+        ];
+        let t = self.downgrade();
+        task::spawn(async move {
+            while let Some(mut batch) = rx.next().await {
+                let t = match t.upgrade() {
+                    None => break,
+                    Some(t) => t,
+                };
+                let mut updates = t.start_batch();
+                let mut settings = t.settings();
+                let mut results = vec![];
+                for req in batch.drain(..) {
+                    let setting = match ids.iter().find(|(id, _)| *id == req.id) {
+                        None => continue,
+                        Some((_, setting)) => setting,
+                    };
+                    let res = match setting.apply(&mut settings, req.value) {
+                        Err(e) => Value::Error(e.into()),
+                        Ok(v) => {
+                            updates.updates.push(BatchMsg::Update(None, req.id, v));
+                            Value::Ok
+                        }
+                    };
+                    results.extend(req.send_result.map(|r| (r, res)));
+                }
+                t.set_settings(settings);
+                // only answer once the new settings are in effect
+                for (r, res) in results {
+                    r.send(res)
+                }
+                updates.commit(None).await
+            }
+        });
+        Ok(ctl)
+    }
+
+    /// Round trip metrics for the requests this publisher has sent
+    /// to the resolver server, see `ResolverWrite::stats`.
+    pub fn resolver_stats(&self) -> BTreeMap<Op, OpStats> {
+        self.0.lock().resolver.stats()
+    }
+
+    /// Publish the resolver round trip metrics of this publisher
+    /// under `base/publisher`, and if `read` is specified those of
+    /// the read client under `base/subscriber`, e.g. with base
+    /// `/sys/app/myapp/resolver`. They are refreshed every
+    /// `interval`. For each kind of request there is
+    /// `<op>/{count, errors, mean, p50, p99, max}`. The metrics are
+    /// unpublished when the returned `ResolverStatsCtl` is dropped.
+    pub fn publish_resolver_stats(
+        &self,
+        base: Path,
+        interval: Duration,
+        read: Option<ResolverRead>,
+    ) -> ResolverStatsCtl {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let t = self.downgrade();
+        task::spawn(async move {
+            let mut published: FxHashMap<Path, Val> = HashMap::default();
+            let mut interval = time::interval(interval);
+            let mut stop = stop_rx.fuse();
+            loop {
+                select_biased! {
+                    _ = stop => break,
+                    _ = interval.tick().fuse() => (),
+                }
+                let t = match t.upgrade() {
+                    None => break,
+                    Some(t) => t,
+                };
+                let stats = iter::once(("publisher", t.resolver_stats()))
+                    .chain(read.as_ref().map(|r| ("subscriber", r.stats())));
+                let mut batch = t.start_batch();
+                for (client, stats) in stats {
+                    for (op, s) in stats {
+                        let base = base.append(client).append(op.name());
+                        let l = &s.latency;
+                        let metrics = [
+                            ("count", Value::from(l.count())),
+                            ("errors", Value::from(s.errors)),
+                            ("mean", Value::from(l.mean())),
+                            ("p50", Value::from(l.quantile(0.5))),
+                            ("p99", Value::from(l.quantile(0.99))),
+                            ("max", Value::from(l.max())),
+                        ];
+                        for (name, v) in metrics {
+                            let path = base.append(name);
+                            match published.get(&path) {
+                                Some(val) => val.update_changed(&mut batch, v),
+                                None => match t.publish(path.clone(), v) {
+                                    Ok(val) => {
+                                        published.insert(path, val);
+                                    }
+                                    Err(e) => {
+                                        warn!("failed to publish {} {}", path, e)
+                                    }
+                                },
+                            }
+                        }
+                    }
+                }
+                batch.commit(None).await
+            }
+        });
+        ResolverStatsCtl { _stop: stop_tx }
+    }
+
+    /// Publish the update metrics of every value published by this
+    /// publisher under `base`, e.g. with base `/sys/app/myapp/stats`
+    /// the metrics of `/app/v` are published under
+    /// `/sys/app/myapp/stats/app/v`. They are refreshed every
+    /// `interval`. For each value there is
+    /// `{updates, bytes, updates_per_sec, bytes_per_sec}`, the rates
+    /// are measured over the last interval. Values published under
+    /// `base` are not included. The metrics are unpublished when the
+    /// returned `PathStatsCtl` is dropped.
+    pub fn publish_path_stats(&self, base: Path, interval: Duration) -> PathStatsCtl {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let t = self.downgrade();
+        task::spawn(async move {
+            let mut published: FxHashMap<Path, Val> = HashMap::default();
+            let mut last: FxHashMap<Path, PathStats> = HashMap::default();
+            let mut last_tick = Instant::now();
+            let mut interval = time::interval(interval);
+            let mut stop = stop_rx.fuse();
+            loop {
+                select_biased! {
+                    _ = stop => break,
+                    _ = interval.tick().fuse() => (),
+                }
+                let t = match t.upgrade() {
+                    None => break,
+                    Some(t) => t,
+                };
+                let now = Instant::now();
+                let elapsed = (now - last_tick).as_secs_f64();
+                last_tick = now;
+                let mut current: FxHashMap<Path, PathStats> = HashMap::default();
+                let mut live: FxHashSet<Path> = HashSet::default();
+                let mut batch = t.start_batch();
+                for (path, s) in t.all_path_stats() {
+                    if Path::is_parent(&base, &path) {
+                        continue;
+                    }
+                    // the rates are 0 until there is a previous sample
+                    let rate = |n: u64, prev: Option<u64>| match prev {
+                        Some(prev) if elapsed > 0. => {
+                            n.saturating_sub(prev) as f64 / elapsed
+                        }
+                        Some(_) | None => 0.,
+                    };
+                    let prev = last.get(&path);
+                    let updates_rate = rate(s.updates, prev.map(|p| p.updates));
+                    let bytes_rate = rate(s.bytes, prev.map(|p| p.bytes));
+                    let metrics = [
+                        ("updates", Value::from(s.updates)),
+                        ("bytes", Value::from(s.bytes)),
+                        ("updates_per_sec", Value::from(updates_rate)),
+                        ("bytes_per_sec", Value::from(bytes_rate)),
+                    ];
+                    let base = base.append(&path);
+                    for (name, v) in metrics {
+                        let path = base.append(name);
+                        match published.get(&path) {
+                            Some(val) => val.update_changed(&mut batch, v),
+                            None => match t.publish(path.clone(), v) {
+                                Ok(val) => {
+                                    published.insert(path.clone(), val);
+                                }
+                                Err(e) => warn!("failed to publish {} {}", path, e),
+                            },
+                        }
+                        live.insert(path);
+                    }
+                    current.insert(path, s);
+                }
+                // unpublish the metrics of values that were unpublished
+                published.retain(|path, _| live.contains(path));
+                last = current;
+                batch.commit(None).await
+            }
+        });
+        PathStatsCtl { _stop: stop_tx }
+    }
+
     /// Perform a clean shutdown of the publisher, remove all
     /// published paths from the resolver server, shutdown the
     /// listener, and close the connection to all clients. Dropping
@@ -1252,6 +2647,12 @@ impl Publisher {
         self.0.lock().addr
     }
 
+    /// get the additional addresses the publisher is bound to, see
+    /// `PublisherBuilder::alt_bind_cfg`
+    pub fn alt_addrs(&self) -> SmallVec<[SocketAddr; 2]> {
+        self.0.lock().alt_addrs.clone()
+    }
+
     /// Publish `Path` with initial value `init` and flags `flags`. It
     /// is an error for the same publisher to publish the same path
     /// twice, however different publishers may publish a given path
@@ -1264,7 +2665,7 @@ impl Publisher {
     /// that might cause you to miss a write.
     pub fn publish_with_flags_and_writes<T>(
         &self,
-        mut flags: PublishFlags,
+        flags: PublishFlags,
         path: Path,
         init: T,
         tx: Option<Sender<Pooled<Vec<WriteRequest>>>>,
@@ -1274,28 +2675,7 @@ impl Publisher {
         <T as TryInto<Value>>::Error: std::error::Error + Send + Sync + 'static,
     {
         let init: Value = init.try_into()?;
-        let id = Id::new();
-        let destroy_on_idle = flags.contains(PublishFlags::DESTROY_ON_IDLE);
-        flags.remove(PublishFlags::DESTROY_ON_IDLE);
-        let mut pb = self.0.lock();
-        pb.check_publish(&path)?;
-        let subscribed = pb
-            .hc_subscribed
-            .entry(BTreeSet::new())
-            .or_insert_with(|| Arc::new(HashSet::default()))
-            .clone();
-        pb.by_id.insert(
-            id,
-            Published { current: init, subscribed, path: path.clone(), aliases: None },
-        );
-        if destroy_on_idle {
-            pb.destroy_on_idle.insert(id);
-        }
-        if let Some(tx) = tx {
-            pb.writes(id, tx);
-        }
-        pb.publish(id, flags, path.clone());
-        Ok(Val(id))
+        Ok(Val(self.0.lock().publish_val(flags, path, init, tx)?))
     }
 
     /// Publish `Path` with initial value `init` and flags `flags`. It
@@ -1367,6 +2747,54 @@ impl Publisher {
         self.publish_with_flags(PublishFlags::empty(), path, init)
     }
 
+    /// Publish every leaf of `tree` under `base`, see `ValueTree`.
+    /// The returned handle can be used to move the published tree to
+    /// a new structure, publishing and unpublishing only the values
+    /// that were added or removed. When it is dropped all the values
+    /// in the tree are unpublished.
+    pub fn publish_tree_with_flags(
+        &self,
+        flags: PublishFlags,
+        base: Path,
+        tree: &ValueTree,
+    ) -> Result<PublishedValueTree> {
+        PublishedValueTree::new(self, flags, base, tree)
+    }
+
+    /// Publish every leaf of `tree` under `base` with no flags. See
+    /// `publish_tree_with_flags`
+    pub fn publish_tree(
+        &self,
+        base: Path,
+        tree: &ValueTree,
+    ) -> Result<PublishedValueTree> {
+        self.publish_tree_with_flags(PublishFlags::empty(), base, tree)
+    }
+
+    /// Attach user defined metadata, e.g. units, a description, or
+    /// a display precision, to the path of the published value
+    /// `id`. The metadata is stored in the resolver server along with
+    /// the path, where subscribers can get it with
+    /// `ResolverRead::metadata`. It replaces any metadata previously
+    /// set, and it is removed when the value is unpublished. Aliases
+    /// don't share the metadata of the value.
+    ///
+    /// This requires a resolver server that supports metadata.
+    pub fn set_metadata<I>(&self, id: Id, meta: I)
+    where
+        I: IntoIterator<Item = (Chars, Value)>,
+    {
+        let mut pb = self.0.lock();
+        if let Some(pbv) = pb.by_id.get(&id) {
+            let (path, flags) = (pbv.path.clone(), pbv.flags);
+            let mut m = METADATA.take();
+            m.extend(meta);
+            pb.metadata.insert(path.clone(), m);
+            pb.to_publish.insert(path, flags);
+            pb.trigger_publish()
+        }
+    }
+
     /// Create an alias for an already published path
     pub fn alias(&self, id: Id, path: Path) -> Result<()> {
         self.alias_with_flags(id, PublishFlags::empty(), path)
@@ -1471,6 +2899,53 @@ impl Publisher {
         self.publish_default_with_flags(PublishFlags::empty(), base)
     }
 
+    /// Publish values under `base` on demand. When a subscriber asks
+    /// for a path under `base` that doesn't exist, `f` is called with
+    /// the path. If it returns a value then that value is published
+    /// at the path with `flags`, otherwise the subscriber is told the
+    /// path doesn't exist.
+    ///
+    /// Values created this way are published with `DESTROY_ON_IDLE`,
+    /// so they are unpublished when their last subscriber goes away,
+    /// and `f` will be called again if the path is requested
+    /// again. Use the returned `OnDemand` handle to update the values
+    /// that currently exist. Dropping it stops publishing the subtree.
+    ///
+    /// `f` is called from a background task, it should not block.
+    pub fn publish_on_demand_with_flags<F>(
+        &self,
+        mut flags: PublishFlags,
+        base: Path,
+        f: F,
+    ) -> Result<OnDemand>
+    where
+        F: FnMut(&Path) -> Option<Value> + Send + 'static,
+    {
+        flags.remove(PublishFlags::DESTROY_ON_IDLE);
+        let requests = self.publish_default_with_flags(flags, base.clone())?;
+        flags.insert(PublishFlags::DESTROY_ON_IDLE);
+        let vals = Arc::new(Mutex::new(HashMap::default()));
+        let (stop_tx, stop_rx) = oneshot::channel();
+        task::spawn(on_demand_loop(
+            self.downgrade(),
+            flags,
+            requests,
+            vals.clone(),
+            f,
+            stop_rx,
+        ));
+        Ok(OnDemand { vals, path: base, _stop: stop_tx })
+    }
+
+    /// Publish values under `base` on demand with no flags. See
+    /// `publish_on_demand_with_flags`.
+    pub fn publish_on_demand<F>(&self, base: Path, f: F) -> Result<OnDemand>
+    where
+        F: FnMut(&Path) -> Option<Value> + Send + 'static,
+    {
+        self.publish_on_demand_with_flags(PublishFlags::empty(), base, f)
+    }
+
     /// Start a new update batch. Updates are queued in the batch (see
     /// `Val::update`), and then the batch can be either discarded, or
     /// committed. If discarded then none of the updates will have any
@@ -1480,7 +2955,36 @@ impl Publisher {
     ///
     /// Multiple batches may be started concurrently.
     pub fn start_batch(&self) -> UpdateBatch {
-        UpdateBatch { origin: self.clone(), updates: RAWBATCH.take(), unsubscribes: None }
+        UpdateBatch {
+            origin: self.clone(),
+            updates: RAWBATCH.take(),
+            unsubscribes: None,
+            expiring: false,
+        }
+    }
+
+    fn set_ttl(&self, id: Id, ttl: Option<(Duration, Expiry)>) {
+        let mut pb = self.0.lock();
+        if pb.by_id.contains_key(&id) {
+            match ttl {
+                None => {
+                    pb.ttl.remove(&id);
+                }
+                Some((ttl, expiry)) => {
+                    let deadline = Instant::now() + ttl;
+                    pb.ttl.insert(id, Ttl { ttl, deadline, expired: false, expiry });
+                    let running = match &pb.ttl_trigger {
+                        None => false,
+                        Some(trigger) => trigger.unbounded_send(()).is_ok(),
+                    };
+                    if !running {
+                        let (tx, rx) = unbounded();
+                        pb.ttl_trigger = Some(tx);
+                        task::spawn(ttl_loop(self.downgrade(), rx));
+                    }
+                }
+            }
+        }
     }
 
     /// Wait until all previous publish or unpublish commands have
@@ -1610,11 +3114,40 @@ impl Publisher {
         self.0.lock().clients.get(client).and_then(|c| c.user.clone())
     }
 
+    /// Get the congestion of every subscriber connection. A
+    /// subscriber that is persistently congested isn't keeping up
+    /// with the rate of updates, see also `Event::SlowSubscriber`.
+    pub fn congestion(&self) -> Vec<(ClId, Congestion)> {
+        let pb = self.0.lock();
+        pb.clients.iter().map(|(id, cl)| (*id, cl.congestion.get())).collect()
+    }
+
     /// Get the number of clients subscribed to a published `Val`
     pub fn subscribed_len(&self, id: &Id) -> usize {
         self.0.lock().by_id.get(&id).map(|p| p.subscribed.len()).unwrap_or(0)
     }
 
+    /// Get the updates sent for a published `Val` since it was
+    /// published
+    pub fn path_stats(&self, id: &Id) -> Option<PathStats> {
+        self.0.lock().by_id.get(&id).map(|p| p.stats)
+    }
+
+    /// Get the updates sent for every published `Val`, by path,
+    /// with the most bytes first. This is useful to find out which
+    /// values dominate the bandwidth used by the publisher.
+    pub fn all_path_stats(&self) -> Vec<(Path, PathStats)> {
+        let mut stats = self
+            .0
+            .lock()
+            .by_id
+            .values()
+            .map(|p| (p.path.clone(), p.stats))
+            .collect::<Vec<_>>();
+        stats.sort_by(|(_, s0), (_, s1)| s1.bytes.cmp(&s0.bytes));
+        stats
+    }
+
     /// Register `tx` to receive writes to the specified published
     /// value. You can register multiple channels, and you can
     /// register the same channel on multiple ids. If no channels are
@@ -1643,6 +3176,10 @@ impl Publisher {
 
     /// Register `tx` to receive a message about publisher events
     ///
+    /// Events about subscribers, such as `SlowSubscriber`, are only
+    /// delivered to channels registered here, not to channels
+    /// registered with `events_for_id`.
+    ///
     /// if you don't want to receive events on a given channel anymore
     /// you can just drop it.
     pub fn events(&self, tx: UnboundedSender<Event>) {
@@ -1660,20 +3197,75 @@ impl Publisher {
     }
 }
 
+async fn ttl_loop(publisher: PublisherWeak, mut trigger: UnboundedReceiver<()>) {
+    loop {
+        let publisher = match publisher.upgrade() {
+            None => break,
+            Some(publisher) => publisher,
+        };
+        let mut batch = publisher.start_batch();
+        batch.expiring = true;
+        let next = {
+            let mut pb = publisher.0.lock();
+            let now = Instant::now();
+            let mut next: Option<Instant> = None;
+            let mut unpublish = Vec::new();
+            for (id, ttl) in pb.ttl.iter_mut().filter(|(_, ttl)| !ttl.expired) {
+                if ttl.deadline > now {
+                    next = Some(next.map_or(ttl.deadline, |n| n.min(ttl.deadline)));
+                } else {
+                    ttl.expired = true;
+                    match &ttl.expiry {
+                        Expiry::Stale(v) => {
+                            batch.updates.push(BatchMsg::Update(None, *id, v.clone()))
+                        }
+                        Expiry::Unpublish(v) => unpublish.push((*id, v.clone())),
+                    }
+                }
+            }
+            for (id, terminal) in unpublish {
+                pb.destroy_val(id, Some(terminal));
+                pb.trigger_publish();
+            }
+            next
+        };
+        batch.commit(None).await;
+        drop(publisher);
+        let wait = async move {
+            match next {
+                None => future::pending().await,
+                Some(deadline) => time::sleep_until(deadline).await,
+            }
+        };
+        select_biased! {
+            r = trigger.next() => if r.is_none() {
+                break
+            },
+            () = wait.fuse() => (),
+        }
+    }
+}
+
 async fn publish_loop(
     publisher: PublisherWeak,
     mut trigger_rx: UnboundedReceiver<Option<oneshot::Sender<()>>>,
 ) {
     while let Some(reply) = trigger_rx.next().await {
         if let Some(publisher) = publisher.upgrade() {
-            let mut to_publish;
+            let mut to_publish: Vec<(Path, Option<u32>, Option<Metadata>)>;
             let mut to_publish_default;
             let mut to_unpublish;
             let mut to_unpublish_default;
             let mut to_unsubscribe;
             let resolver = {
                 let mut pb = publisher.0.lock();
-                to_publish = mem::replace(&mut pb.to_publish, TOPUB.take());
+                to_publish = mem::replace(&mut pb.to_publish, TOPUB.take())
+                    .drain()
+                    .map(|(path, flags)| {
+                        let meta = pb.metadata.get(&path).cloned();
+                        (path, flags, meta)
+                    })
+                    .collect();
                 to_publish_default =
                     mem::replace(&mut pb.to_publish_default, TOPUB.take());
                 to_unpublish = mem::replace(&mut pb.to_unpublish, TOUPUB.take());
@@ -1684,7 +3276,8 @@ async fn publish_loop(
                 pb.resolver.clone()
             };
             if to_publish.len() > 0 {
-                if let Err(e) = resolver.publish_with_flags(to_publish.drain()).await {
+                let r = resolver.publish_with_metadata(to_publish.drain(..)).await;
+                if let Err(e) = r {
                     error!("failed to publish some paths {} will retry", e);
                 }
             }
@@ -1709,9 +3302,9 @@ async fn publish_loop(
             }
             if to_unsubscribe.len() > 0 {
                 let mut usubs = RAWUNSUBS.take();
-                for (id, subs) in to_unsubscribe.drain() {
+                for (id, (subs, terminal)) in to_unsubscribe.drain() {
                     for cl in subs.iter() {
-                        usubs.push((*cl, id));
+                        usubs.push((*cl, id, Some(terminal.clone())));
                     }
                 }
                 let mut batch = publisher.start_batch();
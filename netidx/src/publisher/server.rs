@@ -1,16 +1,18 @@
 use super::{
-    ClId, Client, Event, PublisherInner, PublisherWeak, SendResult, Update, WriteRequest,
-    BATCHES,
+    ClId, Client, CongestionCtr, Event, PublisherInner, PublisherWeak, SendResult,
+    Update, WriteRequest, BATCHES,
 };
 use crate::{
     channel::{self, Channel, K5CtxWrap, ReadChannel, WriteChannel},
     chars::Chars,
+    config::SocketOpts,
+    os::local_transport,
     pack::BoundedBytes,
     path::Path,
     pool::Pooled,
     protocol::{
         self,
-        publisher::{self, Id},
+        publisher::{self, Capabilities, Id, UpdateFilter},
         value::Value,
     },
     resolver_client::DesiredAuth,
@@ -20,6 +22,7 @@ use crate::{
 };
 use anyhow::{anyhow, Error, Result};
 use bytes::Bytes;
+use chrono::prelude::*;
 use cross_krb5::ServerCtx;
 use futures::{
     channel::{
@@ -39,16 +42,19 @@ use std::{
     collections::{hash_map::Entry, BTreeSet, Bound, HashMap, HashSet},
     convert::From,
     default::Default,
+    io,
     iter::{self, FromIterator},
     mem,
     net::SocketAddr,
     pin::Pin,
-    sync::Arc,
-    time::{Duration, SystemTime},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream},
-    task, time,
+    task,
+    time::{self, error::Elapsed},
 };
 
 const MAX_DEFERRED: usize = 1000000;
@@ -61,6 +67,7 @@ fn subscribe(
     client: ClId,
     path: Path,
     permissions: Permissions,
+    multicast: bool,
     deferred_subs: &mut DeferredSubs,
 ) -> Result<()> {
     match t.by_path.get(&path) {
@@ -120,8 +127,21 @@ fn subscribe(
                         e.insert(Arc::clone(&ut.subscribed));
                     }
                 }
-                let m = publisher::From::Subscribed(path, id, ut.current.clone());
+                let v = match t.snapshot.get(&id) {
+                    None => ut.current.clone(),
+                    Some(f) => f.0(client, id, &ut.current),
+                };
+                let m = publisher::From::Subscribed(path, id, v);
                 con.queue_send(&m)?;
+                let offer = multicast && ut.multicast.is_some();
+                if let (true, Some(m)) = (offer, &t.multicast) {
+                    con.queue_send(&publisher::From::Multicast {
+                        id,
+                        group: m.cfg.group,
+                        stream: m.stream,
+                        snapshot: m.cfg.snapshot,
+                    })?
+                }
                 if let Some(waiters) = t.wait_clients.remove(&id) {
                     for tx in waiters {
                         let _ = tx.send(());
@@ -136,6 +156,9 @@ fn subscribe(
 
 fn unsubscribe(t: &mut PublisherInner, client: ClId, id: Id) {
     if let Some(ut) = t.by_id.get_mut(&id) {
+        if let Some(m) = &mut ut.multicast {
+            m.remove(&client);
+        }
 	let current_subs = BTreeSet::from_iter(ut.subscribed.iter().copied());
         let new_subs =
             BTreeSet::from_iter(ut.subscribed.iter().filter(|a| *a != &client).copied());
@@ -161,7 +184,7 @@ fn unsubscribe(t: &mut PublisherInner, client: ClId, id: Id) {
         }
         t.send_event(Event::Unsubscribe(id, client));
         if nsubs == 0 && t.destroy_on_idle.remove(&id) {
-            t.destroy_val(id)
+            t.destroy_val(id, None)
         }
     }
 }
@@ -226,6 +249,7 @@ fn write(
                 path: pbv.path.clone(),
                 client,
                 value: v.clone(),
+                user: cl.user.clone(),
                 send_result: send_result.clone(),
             };
             write_batches
@@ -267,10 +291,75 @@ fn check_token(
     Ok((valid, permissions))
 }
 
-const HB: Duration = Duration::from_secs(5);
-
 const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
 
+fn earliest(d0: Option<Instant>, d1: Option<Instant>) -> Option<Instant> {
+    match (d0, d1) {
+        (Some(d0), Some(d1)) => Some(d0.min(d1)),
+        (d0, d1) => d0.or(d1),
+    }
+}
+
+/// The state of a subscription with an update filter
+struct Filtered {
+    filter: UpdateFilter,
+    last_sent: Option<(Instant, Value)>,
+    pending: Option<(Option<DateTime<Utc>>, Value)>,
+}
+
+impl Filtered {
+    fn new(filter: UpdateFilter) -> Self {
+        Filtered { filter, last_sent: None, pending: None }
+    }
+
+    fn unchanged(epsilon: f64, v0: &Value, v1: &Value) -> bool {
+        if v0.number() && v1.number() {
+            match (v0.clone().cast_to::<f64>(), v1.clone().cast_to::<f64>()) {
+                (Ok(f0), Ok(f1)) => (f0 - f1).abs() <= epsilon,
+                (_, _) => v0 == v1,
+            }
+        } else {
+            v0 == v1
+        }
+    }
+
+    /// returns the value if it should be sent now
+    fn filter(
+        &mut self,
+        now: Instant,
+        ts: Option<DateTime<Utc>>,
+        v: Value,
+    ) -> Option<Value> {
+        if let (Some(eps), Some((_, last))) = (self.filter.on_change, &self.last_sent) {
+            if Self::unchanged(eps, last, &v) {
+                self.pending = None;
+                return None;
+            }
+        }
+        match (self.filter.min_interval, &self.last_sent) {
+            (Some(i), Some((at, _))) if now.saturating_duration_since(*at) < i => {
+                self.pending = Some((ts, v));
+                None
+            }
+            (_, _) => {
+                self.pending = None;
+                self.last_sent = Some((now, v.clone()));
+                Some(v)
+            }
+        }
+    }
+
+    /// when the pending value, if any, should be sent
+    fn deadline(&self) -> Option<Instant> {
+        match (&self.pending, &self.last_sent) {
+            (Some(_), Some((at, _))) => {
+                Some(*at + self.filter.min_interval.unwrap_or(Duration::ZERO))
+            }
+            (_, _) => None,
+        }
+    }
+}
+
 enum BlockedWrite {
     Wrote,
     Reply(publisher::From),
@@ -294,8 +383,14 @@ struct ClientCtx {
     deferred_subs_batch: Vec<(Path, Permissions)>,
     wait_write_res: Vec<(Id, oneshot::Receiver<Value>)>,
     gc_on_write: Vec<ChanWrap<Pooled<Vec<WriteRequest>>>>,
+    unpublished: FxHashMap<Id, Value>,
+    filters: FxHashMap<Id, Filtered>,
+    filter_deadline: Option<Instant>,
+    caps: Capabilities,
     msg_sent: bool,
+    last_recvd: Instant,
     tls_ctx: Option<tls::CachedAcceptor>,
+    congestion: Arc<CongestionCtr>,
 }
 
 impl ClientCtx {
@@ -305,6 +400,7 @@ impl ClientCtx {
         publisher: PublisherWeak,
         desired_auth: DesiredAuth,
         tls_ctx: Option<tls::CachedAcceptor>,
+        congestion: Arc<CongestionCtr>,
     ) -> ClientCtx {
         let mut deferred_subs: DeferredSubs =
             Batched::new(SelectAll::new(), MAX_DEFERRED);
@@ -323,8 +419,14 @@ impl ClientCtx {
             deferred_subs_batch: Vec::new(),
             wait_write_res: Vec::new(),
             gc_on_write: Vec::new(),
+            unpublished: HashMap::default(),
+            filters: HashMap::default(),
+            filter_deadline: None,
+            caps: Capabilities::default(),
             msg_sent: false,
+            last_recvd: Instant::now(),
             tls_ctx,
+            congestion,
         }
     }
 
@@ -359,7 +461,10 @@ impl ClientCtx {
     }
 
     // CR estokes: Implement periodic rekeying to improve security
-    async fn hello(&mut self, mut con: TcpStream) -> Result<Channel> {
+    async fn hello<S>(&mut self, mut con: S) -> Result<Channel>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         use protocol::publisher::Hello;
         static NO: &str = "authentication mechanism not supported";
         debug!("hello_client");
@@ -369,43 +474,49 @@ impl ClientCtx {
         }
         let hello: Hello = channel::read_raw(&mut con).await?;
         debug!("hello_client received {:?}", hello);
+        let caps = Capabilities::CURRENT;
         match hello {
-            Hello::Anonymous => {
-                channel::write_raw(&mut con, &Hello::Anonymous).await?;
+            Hello::Anonymous(theirs) => {
+                self.caps = caps.negotiate(&theirs);
+                channel::write_raw(&mut con, &Hello::Anonymous(caps)).await?;
                 self.client_arrived();
-                Ok(Channel::new::<ServerCtx, TcpStream>(None, con))
+                Ok(Channel::new::<ServerCtx, S>(None, con))
             }
-            Hello::Local(uifo) => {
-                channel::write_raw(&mut con, &Hello::Local(None)).await?;
+            Hello::Local(uifo, theirs) => {
+                self.caps = caps.negotiate(&theirs);
+                channel::write_raw(&mut con, &Hello::Local(None, caps)).await?;
                 self.set_user(uifo);
                 self.client_arrived();
-                Ok(Channel::new::<ServerCtx, TcpStream>(None, con))
+                Ok(Channel::new::<ServerCtx, S>(None, con))
             }
-            Hello::Krb5(uifo) => match &self.desired_auth {
+            Hello::Krb5(uifo, theirs) => match &self.desired_auth {
                 DesiredAuth::Anonymous | DesiredAuth::Tls { .. } => bail!(NO),
                 DesiredAuth::Local => {
-                    channel::write_raw(&mut con, &Hello::Local(None)).await?;
+                    self.caps = caps.negotiate(&theirs);
+                    channel::write_raw(&mut con, &Hello::Local(None, caps)).await?;
                     self.set_user(uifo);
                     self.client_arrived();
-                    Ok(Channel::new::<ServerCtx, TcpStream>(None, con))
+                    Ok(Channel::new::<ServerCtx, S>(None, con))
                 }
                 DesiredAuth::Krb5 { upn: _, spn } => {
                     let spn = spn.as_ref().map(|s| s.as_str());
                     let ctx = krb5_authentication(HELLO_TIMEOUT, spn, &mut con).await?;
                     self.set_user(uifo);
                     let mut con = Channel::new(Some(K5CtxWrap::new(ctx)), con);
-                    con.send_one(&Hello::Krb5(None)).await?;
+                    self.caps = caps.negotiate(&theirs).without(Capabilities::ZSTD);
+                    con.send_one(&Hello::Krb5(None, caps)).await?;
                     self.client_arrived();
                     Ok(con)
                 }
             },
-            Hello::Tls(uifo) => match &self.desired_auth {
+            Hello::Tls(uifo, theirs) => match &self.desired_auth {
                 DesiredAuth::Anonymous | DesiredAuth::Krb5 { .. } => bail!(NO),
                 DesiredAuth::Local => {
-                    channel::write_raw(&mut con, &Hello::Local(None)).await?;
+                    self.caps = caps.negotiate(&theirs);
+                    channel::write_raw(&mut con, &Hello::Local(None, caps)).await?;
                     self.set_user(uifo);
                     self.client_arrived();
-                    Ok(Channel::new::<ServerCtx, TcpStream>(None, con))
+                    Ok(Channel::new::<ServerCtx, S>(None, con))
                 }
                 DesiredAuth::Tls { identity } => {
                     let tls =
@@ -420,16 +531,17 @@ impl ClientCtx {
                     self.set_user(uifo);
                     let mut con = Channel::new::<
                         ServerCtx,
-                        tokio_rustls::server::TlsStream<TcpStream>,
+                        tokio_rustls::server::TlsStream<S>,
                     >(None, tls);
-                    con.send_one(&Hello::Tls(None)).await?;
+                    self.caps = caps.negotiate(&theirs).without(Capabilities::ZSTD);
+                    con.send_one(&Hello::Tls(None, caps)).await?;
                     self.client_arrived();
                     Ok(con)
                 }
             },
             Hello::ResolverAuthenticate(id) => {
                 info!("hello_client processing listener ownership check from resolver");
-                let mut con = Channel::new::<ServerCtx, TcpStream>(None, con);
+                let mut con = Channel::new::<ServerCtx, S>(None, con);
                 let secret = self
                     .secrets
                     .read()
@@ -468,6 +580,8 @@ impl ClientCtx {
                 }
                 Some(t) => {
                     let mut pb = t.0.lock();
+                    let multicast = self.caps.has(Capabilities::MULTICAST)
+                        && matches!(self.desired_auth, DesiredAuth::Anonymous);
                     for (path, perms) in self.deferred_subs_batch.drain(..) {
                         if !pb.by_path.contains_key(path.as_ref()) {
                             let m = publisher::From::NoSuchValue(path);
@@ -479,6 +593,7 @@ impl ClientCtx {
                                 self.client,
                                 path,
                                 perms,
+                                multicast,
                                 &mut self.deferred_subs,
                             )?
                         }
@@ -495,6 +610,10 @@ impl ClientCtx {
         let t_st = self.publisher.upgrade().ok_or_else(|| anyhow!("dead publisher"))?;
         let mut pb = t_st.0.lock();
         let secrets = self.secrets.read();
+        // multicast bypasses authentication, so it is only offered to
+        // anonymous clients
+        let multicast = self.caps.has(Capabilities::MULTICAST)
+            && matches!(self.desired_auth, DesiredAuth::Anonymous);
         let mut gc = false;
         for msg in self.batch.drain(..) {
             match msg {
@@ -507,6 +626,7 @@ impl ClientCtx {
                             self.client,
                             path,
                             Permissions::all(),
+                            multicast,
                             &mut self.deferred_subs,
                         )?,
                         DesiredAuth::Krb5 { .. }
@@ -535,6 +655,7 @@ impl ClientCtx {
                                         self.client,
                                         path,
                                         permissions,
+                                        multicast,
                                         &mut self.deferred_subs,
                                     )?
                                 }
@@ -553,10 +674,69 @@ impl ClientCtx {
                     v,
                     r,
                 )?,
+                Heartbeat => (),
+                Filter(id, filter) => {
+                    let subscribed = pb
+                        .clients
+                        .get(&self.client)
+                        .map(|cl| cl.subscribed.contains_key(&id))
+                        .unwrap_or(false);
+                    if filter.is_empty() || !subscribed {
+                        // flush anything held back by the old filter
+                        if let Some(f) = self.filters.remove(&id) {
+                            if let Some((_, v)) = f.pending {
+                                con.queue_send(&From::Update(id, v))?
+                            }
+                        }
+                    } else {
+                        // filters are per subscriber, multicast isn't
+                        let pbl = pb.by_id.get_mut(&id);
+                        if let Some(m) = pbl.and_then(|p| p.multicast.as_mut()) {
+                            m.remove(&self.client);
+                        }
+                        match self.filters.entry(id) {
+                            Entry::Vacant(e) => {
+                                e.insert(Filtered::new(filter));
+                            }
+                            Entry::Occupied(mut e) => {
+                                let f = e.get_mut();
+                                f.filter = filter;
+                                self.filter_deadline =
+                                    earliest(self.filter_deadline, f.deadline());
+                            }
+                        }
+                    }
+                }
+                Multicast(id, accept) => {
+                    let pb = &mut *pb;
+                    let subscribed = pb
+                        .clients
+                        .get(&self.client)
+                        .map(|cl| cl.subscribed.contains_key(&id))
+                        .unwrap_or(false);
+                    if let Some(pbl) = pb.by_id.get_mut(&id) {
+                        if let Some(m) = &mut pbl.multicast {
+                            if accept && subscribed && !self.filters.contains_key(&id) {
+                                m.insert(self.client);
+                            } else if m.remove(&self.client) {
+                                // datagrams may have been lost
+                                let v = match pb.snapshot.get(&id) {
+                                    None => pbl.current.clone(),
+                                    Some(f) => f.0(self.client, id, &pbl.current),
+                                };
+                                con.queue_send(&From::Update(id, v))?
+                            }
+                        }
+                    }
+                }
                 Unsubscribe(id) => {
                     gc = true;
+                    self.filters.remove(&id);
                     unsubscribe(&mut *pb, self.client, id);
-                    con.queue_send(&From::Unsubscribed(id))?;
+                    match self.unpublished.remove(&id) {
+                        None => con.queue_send(&From::Unsubscribed(id))?,
+                        Some(v) => con.queue_send(&From::Unpublished(id, v))?,
+                    }
                 }
             }
         }
@@ -598,12 +778,43 @@ impl ClientCtx {
         con: &mut WriteChannel,
         (timeout, mut up): (Option<Duration>, Update),
     ) -> Result<()> {
-        use publisher::To;
+        use publisher::{From, To};
+        let now = Instant::now();
+        let timestamps = self.caps.has(Capabilities::TIMESTAMPS);
+        let mut timestamp = up.timestamp.filter(|_| timestamps);
+        let mut sent = false;
         for m in up.updates.drain(..) {
-            con.queue_send(&m)?
+            let m = match m {
+                From::Update(id, v) => match self.filters.get_mut(&id) {
+                    None => From::Update(id, v),
+                    Some(f) => match f.filter(now, up.timestamp, v) {
+                        Some(v) => From::Update(id, v),
+                        None => {
+                            self.filter_deadline =
+                                earliest(self.filter_deadline, f.deadline());
+                            continue;
+                        }
+                    },
+                },
+                m => m,
+            };
+            if let Some(ts) = timestamp.take() {
+                con.queue_send(&From::Timestamp(ts))?
+            }
+            con.queue_send(&m)?;
+            sent = true;
+        }
+        if sent && self.caps.has(Capabilities::BATCH_ID) {
+            con.queue_send(&From::BatchEnd(up.batch_id))?
         }
         if let Some(usubs) = &mut up.unsubscribes {
-            for id in usubs.drain(..) {
+            for (id, terminal) in usubs.drain(..) {
+                if let Some(v) = terminal {
+                    // older subscribers don't understand Unpublished
+                    if self.caps.has(Capabilities::UNPUBLISHED) {
+                        self.unpublished.insert(id, v);
+                    }
+                }
                 self.batch.push(To::Unsubscribe(id));
             }
         }
@@ -618,11 +829,108 @@ impl ClientCtx {
         Ok(())
     }
 
-    async fn run(
+    /// Drop every update to a conflated value in `ups` that is
+    /// followed by a later update to the same value.
+    fn conflate(&self, ups: &mut [(Option<Duration>, Update)]) {
+        use publisher::From;
+        let mut last: FxHashMap<Id, (usize, usize)> = HashMap::default();
+        {
+            let t = match self.publisher.upgrade() {
+                None => return,
+                Some(t) => t,
+            };
+            let pb = t.0.lock();
+            if !pb.settings.conflate || pb.conflate.is_empty() {
+                return;
+            }
+            for (i, (_, up)) in ups.iter().enumerate() {
+                for (j, m) in up.updates.iter().enumerate() {
+                    if let From::Update(id, _) = m {
+                        if pb.conflate.contains(id) {
+                            last.insert(*id, (i, j));
+                        }
+                    }
+                }
+            }
+        }
+        if last.is_empty() {
+            return;
+        }
+        for (i, (_, up)) in ups.iter_mut().enumerate() {
+            let mut j = 0;
+            up.updates.retain(|m| {
+                let keep = match m {
+                    From::Update(id, _) => last.get(id).map_or(true, |l| *l == (i, j)),
+                    _ => true,
+                };
+                j += 1;
+                keep
+            });
+        }
+    }
+
+    /// Handle `up` along with any other batches that are already
+    /// queued, conflating updates across them if possible.
+    fn handle_queued_updates(
+        &mut self,
+        con: &mut WriteChannel,
+        up: (Option<Duration>, Update),
+        queue: &mut Receiver<(Option<Duration>, Update)>,
+    ) -> Result<()> {
+        let c = self.congestion.clone();
+        c.queued.fetch_sub(1, Ordering::Relaxed);
+        match queue.try_next() {
+            Err(_) | Ok(None) => self.handle_updates(con, up)?,
+            Ok(Some(next)) => {
+                c.queued.fetch_sub(1, Ordering::Relaxed);
+                let mut ups = vec![up, next];
+                while let Ok(Some(up)) = queue.try_next() {
+                    c.queued.fetch_sub(1, Ordering::Relaxed);
+                    ups.push(up);
+                }
+                self.conflate(&mut ups);
+                for up in ups {
+                    self.handle_updates(con, up)?
+                }
+            }
+        }
+        c.buffered.store(con.bytes_queued(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn handle_filter_deadline(&mut self, con: &mut WriteChannel) -> Result<()> {
+        use publisher::From;
+        let now = Instant::now();
+        let timestamps = self.caps.has(Capabilities::TIMESTAMPS);
+        let mut next: Option<Instant> = None;
+        for (id, f) in self.filters.iter_mut() {
+            match f.deadline() {
+                None => (),
+                Some(d) if d > now => next = earliest(next, Some(d)),
+                Some(_) => {
+                    if let Some((ts, v)) = f.pending.take() {
+                        if let Some(ts) = ts.filter(|_| timestamps) {
+                            con.queue_send(&From::Timestamp(ts))?
+                        }
+                        con.queue_send(&From::Update(*id, v.clone()))?;
+                        f.last_sent = Some((now, v));
+                        self.msg_sent = true;
+                    }
+                }
+            }
+        }
+        self.filter_deadline = next;
+        Ok(())
+    }
+
+    async fn run<S>(
         mut self,
-        con: TcpStream,
+        con: S,
         mut updates: Receiver<(Option<Duration>, Update)>,
-    ) -> Result<()> {
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         async fn flush(c: &mut WriteChannel, timeout: Option<Duration>) -> Result<()> {
             if c.bytes_queued() > 0 {
                 if let Some(timeout) = timeout {
@@ -663,22 +971,71 @@ impl ClientCtx {
                 }
             }
         }
-        let mut hb = time::interval(HB);
+        async fn wait_deadline(deadline: Option<Instant>) {
+            match deadline {
+                None => future::pending().await,
+                Some(d) => time::sleep_until(d.into()).await,
+            }
+        }
         let (mut read_con, mut write_con) =
             time::timeout(HELLO_TIMEOUT, self.hello(con)).await??.split();
+        let (interval, timeout) = match self.publisher.upgrade() {
+            None => bail!("dead publisher"),
+            Some(pb) => {
+                let pb = pb.0.lock();
+                if self.caps.has(Capabilities::ZSTD) {
+                    write_con.set_compression(pb.settings.compression);
+                }
+                (pb.heartbeat, pb.heartbeat_timeout)
+            }
+        };
+        let mut hb = time::interval_at(time::Instant::now() + interval, interval);
+        self.last_recvd = Instant::now();
         loop {
             select_biased! {
                 r = flush(&mut write_con, self.flush_timeout).fuse() => {
+                    if r.as_ref().err().map(|e| e.is::<Elapsed>()).unwrap_or(false) {
+                        self.congestion.timed_out.store(true, Ordering::Relaxed);
+                    }
                     r?;
                     self.flushing_updates = false;
                     self.flush_timeout = None;
+                    let c = &self.congestion;
+                    c.buffered.store(write_con.bytes_queued(), Ordering::Relaxed);
                 },
                 _ = hb.tick().fuse() => {
+                    // the queue empties every time we take a batch off it,
+                    // so it only counts as drained if it is still empty,
+                    // with nothing left to write, when the heartbeat comes
+                    // around
+                    let c = &self.congestion;
+                    if c.queued.load(Ordering::Relaxed) == 0
+                        && write_con.bytes_queued() == 0
+                    {
+                        c.slow.store(false, Ordering::Relaxed);
+                    }
+                    // pick up a changed compression threshold
+                    if self.caps.has(Capabilities::ZSTD) {
+                        if let Some(pb) = self.publisher.upgrade() {
+                            write_con.set_compression(pb.0.lock().settings.compression);
+                        }
+                    }
                     if !self.msg_sent {
                         write_con.queue_send(&publisher::From::Heartbeat)?;
                     }
                     self.msg_sent = false;
+                    // while writes are blocked we aren't reading, so
+                    // silence from the subscriber isn't meaningful
+                    if !self.blocked_writes.is_empty() {
+                        self.last_recvd = Instant::now();
+                    } else if self.caps.has(Capabilities::HEARTBEAT)
+                        && self.last_recvd.elapsed() > timeout
+                    {
+                        bail!("subscriber timed out")
+                    }
                 },
+                _ = wait_deadline(self.filter_deadline).fuse() =>
+                    self.handle_filter_deadline(&mut write_con)?,
                 s = self.deferred_subs.next() =>
                     self.handle_deferred_sub(&mut write_con, s)?,
                 r = read_from_subscriber(
@@ -687,7 +1044,10 @@ impl ClientCtx {
                     &mut self.blocked_writes
                 ).fuse() => match r {
                     Err(e) => return Err(Error::from(e)),
-                    Ok(None) => self.handle_batch(&mut write_con)?,
+                    Ok(None) => {
+                        self.last_recvd = Instant::now();
+                        self.handle_batch(&mut write_con)?
+                    }
                     Ok(Some(m)) => {
                         write_con.queue_send(&m)?;
                         self.msg_sent = true;
@@ -696,7 +1056,9 @@ impl ClientCtx {
                 u = read_updates(self.flushing_updates, &mut updates).fuse() => {
                     match u {
                         None => break Ok(()),
-                        Some(u) => self.handle_updates(&mut write_con, u)?,
+                        Some(u) => {
+                            self.handle_queued_updates(&mut write_con, u, &mut updates)?
+                        }
                     }
                 },
             }
@@ -704,14 +1066,91 @@ impl ClientCtx {
     }
 }
 
+// returns false if the publisher is dead
+fn spawn_client<S>(
+    t: &PublisherWeak,
+    s: S,
+    desired_auth: &DesiredAuth,
+    tls_ctx: &Option<tls::CachedAcceptor>,
+) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let clid = ClId::new();
+    let t_weak = t.clone();
+    let t = match t.upgrade() {
+        None => return false,
+        Some(t) => t,
+    };
+    let mut pb = t.0.lock();
+    let secrets = pb.resolver.secrets();
+    let (tx, rx) = channel(pb.settings.slack);
+    if pb.clients.len() < pb.settings.max_clients {
+        let congestion = Arc::new(CongestionCtr::default());
+        pb.clients.insert(
+            clid,
+            Client {
+                msg_queue: tx,
+                congestion: congestion.clone(),
+                subscribed: HashMap::default(),
+                user: None,
+            },
+        );
+        let desired_auth = desired_auth.clone();
+        let tls_ctx = tls_ctx.clone();
+        task::spawn(async move {
+            let ctx = ClientCtx::new(
+                clid,
+                secrets,
+                t_weak.clone(),
+                desired_auth,
+                tls_ctx,
+                congestion,
+            );
+            let r = ctx.run(s, rx).await;
+            info!("accept_loop client shutdown {:?}", r);
+            if let Some(t) = t_weak.upgrade() {
+                let mut pb = t.0.lock();
+                if let Some(cl) = pb.clients.remove(&clid) {
+                    for (id, _) in cl.subscribed {
+                        unsubscribe(&mut *pb, clid, id);
+                    }
+                    pb.hc_subscribed.retain(|_, v| Arc::get_mut(v).is_none());
+                    if cl.congestion.timed_out.load(Ordering::Relaxed) {
+                        pb.send_event(Event::SubscriberDropped(clid));
+                    }
+                }
+            }
+        });
+    }
+    true
+}
+
+async fn accept_alt(alt: &Option<TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match alt {
+        None => future::pending().await,
+        Some(l) => l.accept().await,
+    }
+}
+
+async fn accept_local(
+    local: &Option<local_transport::Listener>,
+) -> Result<local_transport::Stream> {
+    match local {
+        None => future::pending().await,
+        Some(l) => l.accept().await,
+    }
+}
+
 pub(super) async fn start(
     t: PublisherWeak,
     serv: TcpListener,
+    alt: Option<TcpListener>,
+    local: Option<local_transport::Listener>,
     stop: oneshot::Receiver<()>,
     desired_auth: DesiredAuth,
     tls_ctx: Option<tls::CachedAcceptor>,
-    max_clients: usize,
-    slack: usize,
+    socket_opts: SocketOpts,
 ) {
     let mut stop = stop.fuse();
     loop {
@@ -721,46 +1160,28 @@ pub(super) async fn start(
                 Err(e) => info!("accept error {}", e), // CR estokes: Handle this
                 Ok((s, addr)) => {
                     debug!("accepted client {:?}", addr);
-                    let clid = ClId::new();
-                    let t_weak = t.clone();
-                    let t = match t.upgrade() {
-                        None => return,
-                        Some(t) => t
-                    };
-                    let mut pb = t.0.lock();
-                    let secrets = pb.resolver.secrets();
-                    let (tx, rx) = channel(slack);
-                    try_cf!("nodelay", continue, s.set_nodelay(true));
-                    if pb.clients.len() < max_clients {
-                        pb.clients.insert(clid, Client {
-                            msg_queue: tx,
-                            subscribed: HashMap::default(),
-                            user: None,
-                        });
-                        let desired_auth = desired_auth.clone();
-                        let tls_ctx = tls_ctx.clone();
-                        task::spawn(async move {
-                            let ctx = ClientCtx::new(
-                                clid,
-                                secrets,
-                                t_weak.clone(),
-                                desired_auth,
-                                tls_ctx,
-                            );
-                            let r = ctx.run(s, rx).await;
-                            info!("accept_loop client shutdown {:?}", r);
-                            if let Some(t) = t_weak.upgrade() {
-                                let mut pb = t.0.lock();
-                                if let Some(cl) = pb.clients.remove(&clid) {
-                                    for (id, _) in cl.subscribed {
-                                        unsubscribe(&mut *pb, clid, id);
-                                    }
-                                    pb.hc_subscribed.retain(|_, v| {
-                                        Arc::get_mut(v).is_none()
-                                    });
-                                }
-                            }
-                        });
+                    try_cf!("socket options", continue, socket_opts.apply(&s));
+                    if !spawn_client(&t, s, &desired_auth, &tls_ctx) {
+                        return
+                    }
+                }
+            },
+            cl = accept_alt(&alt).fuse() => match cl {
+                Err(e) => info!("alt accept error {}", e),
+                Ok((s, addr)) => {
+                    debug!("accepted client {:?}", addr);
+                    try_cf!("socket options", continue, socket_opts.apply(&s));
+                    if !spawn_client(&t, s, &desired_auth, &tls_ctx) {
+                        return
+                    }
+                }
+            },
+            cl = accept_local(&local).fuse() => match cl {
+                Err(e) => info!("local accept error {}", e),
+                Ok(s) => {
+                    debug!("accepted local client");
+                    if !spawn_client(&t, s, &desired_auth, &tls_ctx) {
+                        return
                     }
                 }
             },
@@ -0,0 +1,219 @@
+use super::{Id, PublishFlags, Publisher, PublisherWeak, UpdateBatch, Val, Value};
+use crate::path::Path;
+use anyhow::Result;
+use fxhash::FxHashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    slice,
+};
+
+/// A type whose fields can be published as a tree of values, one
+/// child path per field. Rather than implementing this by hand use
+/// `#[derive(Publishable)]`, which publishes each named field under
+/// `base/<field>`. Fields must be `Clone + Into<Value>`, except those
+/// marked `#[publishable(tree)]`, which must themselves be
+/// `Publishable` and are published as a subtree. Fields marked
+/// `#[publishable(skip)]` are not published, and
+/// `#[publishable(rename = "name")]` changes the child path.
+///
+/// ```ignore
+/// #[derive(Clone, Publishable)]
+/// struct Stats {
+///     cpu: f64,
+///     mem: u64,
+///     #[publishable(tree)]
+///     disk: DiskStats,
+/// }
+///
+/// let tree = PublishedTree::new(&publisher, Path::from("/stats"), &stats)?;
+/// let mut batch = publisher.start_batch();
+/// tree.update(&mut batch, &stats);
+/// batch.commit(None).await;
+/// ```
+pub trait Publishable {
+    /// Publish each field under `base`, and push the published
+    /// values onto `vals` in field order.
+    fn publish_fields(
+        &self,
+        publisher: &Publisher,
+        base: &Path,
+        vals: &mut Vec<Val>,
+    ) -> Result<()>;
+
+    /// Queue an update for each field that changed. `vals` yields the
+    /// values pushed by `publish_fields`, in the same order.
+    fn update_fields(&self, batch: &mut UpdateBatch, vals: &mut slice::Iter<Val>);
+}
+
+/// A `Publishable` value published under a base path. Dropping it
+/// unpublishes all the values in the tree.
+pub struct PublishedTree<T> {
+    base: Path,
+    vals: Vec<Val>,
+    phantom: PhantomData<fn(&T)>,
+}
+
+impl<T: Publishable> PublishedTree<T> {
+    /// Publish the fields of `t` under `base`
+    pub fn new(publisher: &Publisher, base: Path, t: &T) -> Result<Self> {
+        let mut vals = Vec::new();
+        t.publish_fields(publisher, &base, &mut vals)?;
+        Ok(PublishedTree { base, vals, phantom: PhantomData })
+    }
+
+    /// Queue an update for every field of `t` that changed since it
+    /// was last updated.
+    pub fn update(&self, batch: &mut UpdateBatch, t: &T) {
+        t.update_fields(batch, &mut self.vals.iter())
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The published values, in field order
+    pub fn vals(&self) -> &[Val] {
+        &self.vals
+    }
+}
+
+/// A nested structure of values. A leaf is published at the path of
+/// it's position in the tree, and the children of a node are
+/// published under it's path, so
+///
+/// ```ignore
+/// Node({"cpu": Leaf(0.5), "disk": Node({"free": Leaf(100)})})
+/// ```
+///
+/// published at `/stats` publishes `/stats/cpu` and
+/// `/stats/disk/free`. Child names containing a `/` publish deeper
+/// in the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueTree {
+    Leaf(Value),
+    Node(BTreeMap<String, ValueTree>),
+}
+
+impl From<Value> for ValueTree {
+    fn from(v: Value) -> Self {
+        ValueTree::Leaf(v)
+    }
+}
+
+impl From<BTreeMap<String, ValueTree>> for ValueTree {
+    fn from(children: BTreeMap<String, ValueTree>) -> Self {
+        ValueTree::Node(children)
+    }
+}
+
+impl ValueTree {
+    fn flatten(&self, base: &Path, leaves: &mut FxHashMap<Path, Value>) {
+        match self {
+            ValueTree::Leaf(v) => {
+                leaves.insert(base.clone(), v.clone());
+            }
+            ValueTree::Node(children) => {
+                for (name, t) in children {
+                    t.flatten(&base.append(name), leaves)
+                }
+            }
+        }
+    }
+}
+
+/// A `ValueTree` published under a base path, see
+/// `Publisher::publish_tree`. Dropping it unpublishes all the values
+/// in the tree.
+pub struct PublishedValueTree {
+    publisher: PublisherWeak,
+    flags: PublishFlags,
+    base: Path,
+    vals: FxHashMap<Path, Val>,
+}
+
+impl PublishedValueTree {
+    pub(super) fn new(
+        publisher: &Publisher,
+        flags: PublishFlags,
+        base: Path,
+        tree: &ValueTree,
+    ) -> Result<Self> {
+        let mut t = PublishedValueTree {
+            publisher: publisher.downgrade(),
+            flags,
+            base,
+            vals: HashMap::default(),
+        };
+        t.sync(None, tree)?;
+        Ok(t)
+    }
+
+    /// Make the published tree match `tree`. Leaves that are new are
+    /// published, leaves that are gone are unpublished, and the rest
+    /// are updated in `batch` if their value changed. The additions
+    /// and removals are sent to the resolver together. If any new
+    /// leaf can't be published then nothing is changed and an error
+    /// is returned.
+    pub fn update(&mut self, batch: &mut UpdateBatch, tree: &ValueTree) -> Result<()> {
+        self.sync(Some(batch), tree)
+    }
+
+    fn sync(&mut self, batch: Option<&mut UpdateBatch>, tree: &ValueTree) -> Result<()> {
+        let publisher =
+            self.publisher.upgrade().ok_or_else(|| anyhow!("publisher is dead"))?;
+        let mut leaves = HashMap::default();
+        tree.flatten(&self.base, &mut leaves);
+        let mut pb = publisher.0.lock();
+        let mut added: Vec<(Path, Id)> = Vec::new();
+        for (path, v) in leaves.iter() {
+            if !self.vals.contains_key(path) {
+                match pb.publish_val(self.flags, path.clone(), v.clone(), None) {
+                    Ok(id) => added.push((path.clone(), id)),
+                    Err(e) => {
+                        for (_, id) in added {
+                            pb.destroy_val(id, None)
+                        }
+                        bail!("can't publish {}, {}", path, e)
+                    }
+                }
+            }
+        }
+        let stale = self
+            .vals
+            .keys()
+            .filter(|p| !leaves.contains_key(*p))
+            .cloned()
+            .collect::<Vec<_>>();
+        // the vals must not be dropped while the publisher is locked
+        let mut removed = Vec::with_capacity(stale.len());
+        for path in stale {
+            if let Some(val) = self.vals.remove(&path) {
+                pb.destroy_val(val.id(), None);
+                removed.push(val);
+            }
+        }
+        drop(pb);
+        drop(removed);
+        if let Some(batch) = batch {
+            for (path, val) in self.vals.iter() {
+                if let Some(v) = leaves.remove(path) {
+                    val.update_changed(batch, v)
+                }
+            }
+        }
+        for (path, id) in added {
+            self.vals.insert(path, Val(id));
+        }
+        Ok(())
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The paths of the currently published leaves
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.vals.keys()
+    }
+}
@@ -13,7 +13,10 @@ use futures::channel::oneshot;
 use fxhash::FxHashMap;
 use netidx_core::pack::BoundedBytes;
 use std::{fmt::Debug, str::FromStr, time::Duration};
-use tokio::{net::TcpStream, task, time};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task, time,
+};
 
 pub(super) const HELLO_TO: Duration = Duration::from_secs(15);
 
@@ -86,9 +89,9 @@ pub(super) type ResponseChan<F> = oneshot::Receiver<Response<F>>;
 pub(crate) async fn krb5_authentication(
     principal: Option<&str>,
     target_principal: &str,
-    con: &mut TcpStream,
+    con: &mut (impl AsyncRead + AsyncWrite + Unpin),
 ) -> Result<ClientCtx> {
-    async fn send(con: &mut TcpStream, token: &[u8]) -> Result<()> {
+    async fn send(con: &mut (impl AsyncWrite + Unpin), token: &[u8]) -> Result<()> {
         let token = BoundedBytes::<L>(utils::bytes(&*token));
         Ok(time::timeout(HELLO_TO, channel::write_raw(con, &token)).await??)
     }
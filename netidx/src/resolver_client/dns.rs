@@ -0,0 +1,63 @@
+use crate::{config::Dns, protocol::resolver::Auth, utils};
+use anyhow::Result;
+use log::warn;
+use std::{net::SocketAddr, time::Duration};
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Finds the members of the resolver cluster using DNS
+pub(super) struct Discovery {
+    dns: Dns,
+    resolver: TokioAsyncResolver,
+}
+
+impl Discovery {
+    pub(super) fn new(dns: Dns) -> Result<Self> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+        Ok(Self { dns, resolver })
+    }
+
+    pub(super) fn refresh(&self) -> Duration {
+        self.dns.refresh
+    }
+
+    async fn lookup_srv(&self) -> Result<Vec<SocketAddr>> {
+        let mut addrs = vec![];
+        let srv = self.resolver.srv_lookup(self.dns.name.as_str()).await?;
+        for r in srv.iter() {
+            match self.resolver.lookup_ip(r.target().clone()).await {
+                Ok(ips) => {
+                    addrs.extend(ips.iter().map(|ip| SocketAddr::new(ip, r.port())))
+                }
+                Err(e) => warn!("failed to resolve srv target {} {}", r.target(), e),
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Look up the current members of the cluster. Addresses that
+    /// wouldn't be valid in the config file are skipped.
+    pub(super) async fn lookup(&self) -> Result<Vec<(SocketAddr, Auth)>> {
+        let addrs = match self.dns.port {
+            None => self.lookup_srv().await?,
+            Some(port) => {
+                let ips = self.resolver.lookup_ip(self.dns.name.as_str()).await?;
+                ips.iter().map(|ip| SocketAddr::new(ip, port)).collect()
+            }
+        };
+        let mut res: Vec<(SocketAddr, Auth)> = vec![];
+        for addr in addrs {
+            match utils::check_addr::<()>(addr.ip(), &[]) {
+                Err(e) => warn!("ignoring resolver server {} from dns {}", addr, e),
+                Ok(()) => {
+                    if !res.iter().any(|(a, _)| a == &addr) {
+                        res.push((addr, self.dns.auth.clone()))
+                    }
+                }
+            }
+        }
+        if res.is_empty() {
+            bail!("no resolver servers found for {}", self.dns.name)
+        }
+        Ok(res)
+    }
+}
@@ -1,22 +1,26 @@
 pub(crate) mod common;
+mod dns;
 mod read_client;
+pub mod stats;
 mod write_client;
 
 pub use crate::protocol::{
     glob::{Glob, GlobSet},
-    resolver::{Resolved, Table},
+    resolver::{
+        ClientStats, FromAdmin, ListEntry, Metadata, Resolved, StoreEntry, Table, ToAdmin,
+    },
 };
 use crate::{
-    config::Config,
+    config::{Config, Dns},
     pack::Z64,
     path::Path,
     pool::{Pool, Pooled},
     protocol::resolver::{
-        FromRead, FromWrite, Publisher, PublisherId, Referral, ToRead, ToWrite,
+        Auth, FromRead, FromWrite, Publisher, PublisherId, Referral, ToRead, ToWrite,
     },
     tls,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use arcstr::ArcStr;
 pub use common::DesiredAuth;
 use common::{
@@ -24,29 +28,68 @@ use common::{
     RAWFROMREADPOOL, RAWFROMWRITEPOOL, RAWTOREADPOOL, RAWTOWRITEPOOL, RESOLVEDPOOL,
     TOREADPOOL, TOWRITEPOOL,
 };
-use futures::future;
+use dns::Discovery;
+use futures::{channel::mpsc, future, prelude::*, stream};
 use fxhash::FxHashMap;
+use log::{info, warn};
 use parking_lot::{Mutex, RwLock};
 use read_client::ReadClient;
+use smallvec::SmallVec;
+use stats::{Op, OpStats, Stats};
 use std::{
     collections::{
         hash_map::Entry,
         BTreeMap,
         Bound::{self, Included, Unbounded},
-        HashMap, HashSet,
+        HashMap, HashSet, VecDeque,
     },
-    iter::IntoIterator,
+    error, fmt,
+    iter::{self, IntoIterator},
     marker::PhantomData,
+    mem,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     result,
     sync::Arc,
     time::Duration,
 };
-use tokio::time::Instant;
+use tokio::{
+    sync::watch,
+    task,
+    time::{self, Instant},
+};
 use write_client::WriteClient;
 
 const MAX_REFERRALS: usize = 128;
 
+/// Typed failures returned (inside an `anyhow::Error`) by the
+/// resolver client. Use `Error::downcast_ref::<ResolverError>` to
+/// decide whether to retry, give up, or ask for different
+/// credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolverError {
+    /// The path does not exist in the resolver. Retrying is
+    /// pointless until something publishes it.
+    NoSuchPath(Path),
+    /// The resolver server refused the request for this path under
+    /// the current credentials.
+    PermissionDenied(Path),
+    /// None of the resolver servers responsible for the request
+    /// could be reached. It may be worth retrying later.
+    Unreachable,
+}
+
+impl fmt::Display for ResolverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchPath(p) => write!(f, "no such path {}", p),
+            Self::PermissionDenied(p) => write!(f, "permission denied {}", p),
+            Self::Unreachable => write!(f, "resolver unreachable"),
+        }
+    }
+}
+
+impl error::Error for ResolverError {}
+
 trait ToPath {
     fn path(&self) -> Option<&Path>;
 }
@@ -54,8 +97,16 @@ trait ToPath {
 impl ToPath for ToRead {
     fn path(&self) -> Option<&Path> {
         match self {
-            ToRead::List(p) | ToRead::Table(p) | ToRead::Resolve(p) => Some(p),
-            ToRead::ListMatching(_) | ToRead::GetChangeNr(_) => None,
+            ToRead::List(p)
+            | ToRead::ListWithMeta(p)
+            | ToRead::Table(p)
+            | ToRead::Resolve(p)
+            | ToRead::Watch(p)
+            | ToRead::Metadata(p) => Some(p),
+            ToRead::ListMatching(_)
+            | ToRead::GetChangeNr(_)
+            | ToRead::Admin(_)
+            | ToRead::ResolvedBy(_) => None,
         }
     }
 }
@@ -67,9 +118,11 @@ impl ToPath for ToWrite {
             ToWrite::Publish(p)
             | ToWrite::Unpublish(p)
             | ToWrite::UnpublishDefault(p)
+            | ToWrite::UnpublishPrefix(p)
             | ToWrite::PublishDefault(p)
             | ToWrite::PublishWithFlags(p, _)
-            | ToWrite::PublishDefaultWithFlags(p, _) => Some(p),
+            | ToWrite::PublishDefaultWithFlags(p, _)
+            | ToWrite::PublishWithMetadata(p, _, _) => Some(p),
         }
     }
 }
@@ -84,6 +137,35 @@ impl Router {
         Router { cached: BTreeMap::new() }
     }
 
+    /// Find the cached referral responsible for `path`. Expired
+    /// referrals are pushed to `gc`.
+    fn route(
+        &self,
+        now: Instant,
+        path: &Path,
+        gc: &mut Vec<Path>,
+    ) -> Option<Arc<Referral>> {
+        let mut r = self.cached.range::<str, (Bound<&str>, Bound<&str>)>((
+            Unbounded,
+            Included(path.as_ref()),
+        ));
+        loop {
+            match r.next_back() {
+                None => break None,
+                Some((p, (exp, r))) => {
+                    if !Path::is_parent(p, path) {
+                        continue;
+                    } else if exp.is_none() || now < exp.unwrap() {
+                        break Some(r.clone());
+                    } else {
+                        gc.push(p.clone());
+                        break None;
+                    }
+                }
+            }
+        }
+    }
+
     fn route_batch<T>(
         &mut self,
         pool: &Pool<Vec<(usize, T)>>,
@@ -98,45 +180,8 @@ impl Router {
         let mut id = 0;
         for v in batch.iter() {
             let v = v.clone();
-            match v.path() {
-                None => batches.entry(None).or_insert_with(|| pool.take()).push((id, v)),
-                Some(path) => {
-                    let mut r = self.cached.range::<str, (Bound<&str>, Bound<&str>)>((
-                        Unbounded,
-                        Included(path.as_ref()),
-                    ));
-                    loop {
-                        match r.next_back() {
-                            None => {
-                                batches
-                                    .entry(None)
-                                    .or_insert_with(|| pool.take())
-                                    .push((id, v));
-                                break;
-                            }
-                            Some((p, (exp, r))) => {
-                                if !Path::is_parent(p, path) {
-                                    continue;
-                                } else {
-                                    if exp.is_none() || now < exp.unwrap() {
-                                        batches
-                                            .entry(Some(r.clone()))
-                                            .or_insert_with(|| pool.take())
-                                            .push((id, v))
-                                    } else {
-                                        gc.push(p.clone());
-                                        batches
-                                            .entry(None)
-                                            .or_insert_with(|| pool.take())
-                                            .push((id, v))
-                                    }
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            let r = v.path().and_then(|path| self.route(now, path, &mut gc));
+            batches.entry(r).or_insert_with(|| pool.take()).push((id, v));
             id += 1;
         }
         for p in gc {
@@ -187,10 +232,15 @@ where
         resolver: Arc<Referral>,
         desired_auth: DesiredAuth,
         writer_addr: SocketAddr,
+        local_endpoint: Option<ArcStr>,
+        alt_addrs: SmallVec<[SocketAddr; 2]>,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
     ) -> Self;
     fn send(&mut self, batch: Pooled<Vec<(usize, T)>>) -> ResponseChan<F>;
+    /// The members of the resolver cluster changed. Return false if
+    /// the connection can't follow the change and must be replaced.
+    fn set_members(&mut self, resolver: Arc<Referral>) -> bool;
 }
 
 impl Connection<ToRead, FromRead> for ReadClient {
@@ -198,6 +248,8 @@ impl Connection<ToRead, FromRead> for ReadClient {
         resolver: Arc<Referral>,
         desired_auth: DesiredAuth,
         _writer_addr: SocketAddr,
+        _local_endpoint: Option<ArcStr>,
+        _alt_addrs: SmallVec<[SocketAddr; 2]>,
         _secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
     ) -> Self {
@@ -207,6 +259,10 @@ impl Connection<ToRead, FromRead> for ReadClient {
     fn send(&mut self, batch: Pooled<Vec<(usize, ToRead)>>) -> ResponseChan<FromRead> {
         ReadClient::send(self, batch)
     }
+
+    fn set_members(&mut self, _resolver: Arc<Referral>) -> bool {
+        false
+    }
 }
 
 impl Connection<ToWrite, FromWrite> for WriteClient {
@@ -214,15 +270,30 @@ impl Connection<ToWrite, FromWrite> for WriteClient {
         resolver: Arc<Referral>,
         desired_auth: DesiredAuth,
         writer_addr: SocketAddr,
+        local_endpoint: Option<ArcStr>,
+        alt_addrs: SmallVec<[SocketAddr; 2]>,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
     ) -> Self {
-        WriteClient::new(resolver, desired_auth, writer_addr, secrets, tls)
+        WriteClient::new(
+            resolver,
+            desired_auth,
+            writer_addr,
+            local_endpoint,
+            alt_addrs,
+            secrets,
+            tls,
+        )
     }
 
     fn send(&mut self, batch: Pooled<Vec<(usize, ToWrite)>>) -> ResponseChan<FromWrite> {
         WriteClient::send(self, batch)
     }
+
+    fn set_members(&mut self, resolver: Arc<Referral>) -> bool {
+        WriteClient::set_members(self, resolver);
+        true
+    }
 }
 
 #[derive(Debug)]
@@ -236,8 +307,11 @@ where
     default: Arc<Referral>,
     by_server: HashMap<Arc<Referral>, C>,
     writer_addr: SocketAddr,
+    local_endpoint: Option<ArcStr>,
+    alt_addrs: SmallVec<[SocketAddr; 2]>,
     secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
     tls: Option<tls::CachedConnector>,
+    dns_ready: Option<watch::Receiver<bool>>,
     phantom: PhantomData<(T, F)>,
     f_pool: Pool<Vec<F>>,
     fi_pool: Pool<Vec<(usize, F)>>,
@@ -263,6 +337,8 @@ where
                     r.clone(),
                     self.desired_auth.clone(),
                     self.writer_addr,
+                    self.local_endpoint.clone(),
+                    self.alt_addrs.clone(),
                     self.secrets.clone(),
                     self.tls.clone(),
                 );
@@ -271,6 +347,21 @@ where
             }
         }
     }
+
+    /// Replace the members of the default resolver cluster
+    fn set_default_members(&mut self, addrs: Vec<(SocketAddr, Auth)>) {
+        let new = Arc::new(Referral {
+            path: self.default.path.clone(),
+            ttl: None,
+            addrs: Pooled::orphan(addrs),
+        });
+        let old = mem::replace(&mut self.default, self.router.add_referral(new));
+        if let Some(mut con) = self.by_server.remove(&old) {
+            if con.set_members(self.default.clone()) {
+                self.by_server.insert(self.default.clone(), con);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -281,7 +372,7 @@ where
 
 impl<C, T, F> ResolverWrap<C, T, F>
 where
-    C: Connection<T, F> + Clone + 'static,
+    C: Connection<T, F> + Clone + Send + Sync + 'static,
     T: ToPath + Clone + Send + Sync + 'static,
     F: ToReferral + Clone + Send + Sync + 'static,
 {
@@ -289,38 +380,119 @@ where
         default: Config,
         desired_auth: DesiredAuth,
         writer_addr: SocketAddr,
+        local_endpoint: Option<ArcStr>,
+        alt_addrs: SmallVec<[SocketAddr; 2]>,
         f_pool: Pool<Vec<F>>,
         fi_pool: Pool<Vec<(usize, F)>>,
         ti_pool: Pool<Vec<(usize, T)>>,
     ) -> ResolverWrap<C, T, F> {
         let secrets = Arc::new(RwLock::new(HashMap::default()));
         let tls = default.tls.clone().map(tls::CachedConnector::new);
+        let dns = default.dns.clone();
+        let seeds = default.addrs.clone();
         let mut router = Router::new();
         let default: Arc<Referral> = Arc::new(default.to_referral());
         router.add_referral(default.clone());
-        ResolverWrap(Arc::new(Mutex::new(ResolverWrapInner {
+        let t = ResolverWrap(Arc::new(Mutex::new(ResolverWrapInner {
             router,
             desired_auth,
             default,
             by_server: HashMap::new(),
             writer_addr,
+            local_endpoint,
+            alt_addrs,
             secrets,
             tls,
+            dns_ready: None,
             f_pool,
             fi_pool,
             ti_pool,
             phantom: PhantomData,
-        })))
+        })));
+        if let Some(dns) = dns {
+            t.start_discovery(dns, seeds);
+        }
+        t
+    }
+
+    /// Periodically look up the members of the default cluster in
+    /// DNS. Requests wait for the first lookup to finish, after that
+    /// changes are picked up by new connections, while existing
+    /// write connections add and drop servers as needed.
+    fn start_discovery(&self, dns: Dns, mut seeds: Vec<(SocketAddr, Auth)>) {
+        let (ready_tx, ready_rx) = watch::channel(false);
+        self.0.lock().dns_ready = Some(ready_rx);
+        let inner = Arc::downgrade(&self.0);
+        task::spawn(async move {
+            let discovery = match Discovery::new(dns) {
+                Ok(d) => d,
+                Err(e) => {
+                    warn!("resolver discovery disabled, failed to init dns {}", e);
+                    let _ = ready_tx.send(true);
+                    return;
+                }
+            };
+            seeds.sort_by_key(|(a, _)| *a);
+            let mut current = seeds.clone();
+            loop {
+                match discovery.lookup().await {
+                    Err(e) => warn!("failed to look up resolver servers {}", e),
+                    Ok(mut found) => {
+                        for (addr, auth) in &seeds {
+                            if !found.iter().any(|(a, _)| a == addr) {
+                                found.push((*addr, auth.clone()))
+                            }
+                        }
+                        found.sort_by_key(|(a, _)| *a);
+                        if found != current {
+                            match inner.upgrade() {
+                                None => break,
+                                Some(inner) => {
+                                    info!("resolver servers changed {:?}", found);
+                                    inner.lock().set_default_members(found.clone())
+                                }
+                            }
+                            current = found;
+                        }
+                    }
+                }
+                let _ = ready_tx.send(true);
+                time::sleep(discovery.refresh()).await;
+                if inner.strong_count() == 0 {
+                    break;
+                }
+            }
+        });
     }
 
     fn secrets(&self) -> Arc<RwLock<FxHashMap<SocketAddr, u128>>> {
         Arc::clone(&self.0.lock().secrets)
     }
 
+    fn add_root(&self, path: Path, cfg: Config) -> Result<()> {
+        if !Path::is_absolute(&path) {
+            bail!("root {} is not an absolute path", path)
+        }
+        if cfg.addrs.is_empty() {
+            bail!("root {} has no resolver servers", path)
+        }
+        let r = Referral { path, ttl: None, addrs: Pooled::orphan(cfg.addrs) };
+        self.0.lock().router.add_referral(Arc::new(r));
+        Ok(())
+    }
+
     async fn send(
         &self,
         batch: &Pooled<Vec<T>>,
     ) -> Result<(Pooled<FxHashMap<PublisherId, Publisher>>, Pooled<Vec<F>>)> {
+        let ready = self.0.lock().dns_ready.clone();
+        if let Some(mut ready) = ready {
+            while !*ready.borrow() {
+                if ready.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
         let mut referrals = 0;
         loop {
             let mut waiters = Vec::new();
@@ -338,7 +510,7 @@ where
             let mut referral = false;
             let mut publishers = None;
             for r in future::join_all(waiters).await {
-                let (mut p, mut r) = r?;
+                let (mut p, mut r) = r.context(ResolverError::Unreachable)?;
                 match publishers.as_mut() {
                     None => {
                         publishers = Some(p);
@@ -387,19 +559,186 @@ impl ChangeTracker {
     }
 }
 
+/// A change to the children of a watched path, see
+/// `ResolverRead::watch`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WatchEvent {
+    Added(Path),
+    Removed(Path),
+}
+
+#[derive(Debug)]
+struct CachedResolve {
+    expires: Instant,
+    publishers: Vec<Publisher>,
+    resolved: Resolved,
+}
+
+/// The default maximum number of resolutions in the resolve cache
+const MAX_CACHED: usize = 100_000;
+
+/// A cache of recent resolve results, so that clients that
+/// repeatedly resolve the same paths don't have to ask the resolver
+/// server every time. Disabled unless a ttl is set.
+#[derive(Debug)]
+struct ResolveCache {
+    ttl: Option<Duration>,
+    capacity: usize,
+    by_path: HashMap<Path, CachedResolve>,
+    // paths in the order they were cached, which is also the order
+    // they expire in unless the ttl changes. Entries that were
+    // invalidated or replaced are skipped when they come up.
+    by_age: VecDeque<(Instant, Path)>,
+}
+
+impl ResolveCache {
+    fn new() -> Self {
+        Self {
+            ttl: None,
+            capacity: MAX_CACHED,
+            by_path: HashMap::new(),
+            by_age: VecDeque::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_path.clear();
+        self.by_age.clear();
+    }
+
+    /// drop expired entries, and then the oldest entries until at
+    /// most `keep` are left
+    fn evict(&mut self, now: Instant, keep: usize) {
+        while let Some((expires, _)) = self.by_age.front() {
+            if *expires > now && self.by_path.len() <= keep {
+                break;
+            }
+            let (expires, path) = self.by_age.pop_front().unwrap();
+            if let Entry::Occupied(e) = self.by_path.entry(path) {
+                if e.get().expires == expires {
+                    e.remove();
+                }
+            }
+        }
+    }
+
+    fn get(&mut self, now: Instant, path: &Path) -> Option<(&[Publisher], &Resolved)> {
+        let fresh = match self.by_path.get(path) {
+            Some(c) => c.expires > now,
+            None => return None,
+        };
+        if !fresh {
+            self.by_path.remove(path);
+            return None;
+        }
+        self.by_path.get(path).map(|c| (&*c.publishers, &c.resolved))
+    }
+
+    fn insert(
+        &mut self,
+        now: Instant,
+        path: Path,
+        publishers: &FxHashMap<PublisherId, Publisher>,
+        resolved: &Resolved,
+    ) {
+        if let Some(ttl) = self.ttl {
+            if resolved.publishers.len() > 0 && self.capacity > 0 {
+                self.evict(now, self.capacity - 1);
+                let publishers = resolved
+                    .publishers
+                    .iter()
+                    .filter_map(|pref| publishers.get(&pref.id).cloned())
+                    .collect();
+                let resolved = resolved.clone();
+                let expires = now + ttl;
+                self.by_age.push_back((expires, path.clone()));
+                let c = CachedResolve { expires, publishers, resolved };
+                self.by_path.insert(path, c);
+            }
+        }
+    }
+}
+
+/// Resolve and list paths in the resolver cluster.
+///
+/// `list_with_meta`, `resolved_by`, and `metadata` require a resolver
+/// server that supports them, older servers will drop the connection.
 #[derive(Debug, Clone)]
-pub struct ResolverRead(ResolverWrap<ReadClient, ToRead, FromRead>);
+pub struct ResolverRead(
+    ResolverWrap<ReadClient, ToRead, FromRead>,
+    Arc<Mutex<ResolveCache>>,
+    Stats,
+);
 
 impl ResolverRead {
     pub fn new(default: Config, desired_auth: DesiredAuth) -> Self {
-        ResolverRead(ResolverWrap::new(
-            default,
-            desired_auth,
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
-            RAWFROMREADPOOL.clone(),
-            FROMREADPOOL.clone(),
-            TOREADPOOL.clone(),
-        ))
+        ResolverRead(
+            ResolverWrap::new(
+                default,
+                desired_auth,
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
+                None,
+                SmallVec::new(),
+                RAWFROMREADPOOL.clone(),
+                FROMREADPOOL.clone(),
+                TOREADPOOL.clone(),
+            ),
+            Arc::new(Mutex::new(ResolveCache::new())),
+            Stats::default(),
+        )
+    }
+
+    /// Round trip metrics for the requests this client has sent,
+    /// by kind of request. Requests answered from the resolve cache
+    /// are not counted.
+    pub fn stats(&self) -> BTreeMap<Op, OpStats> {
+        self.2.snapshot()
+    }
+
+    /// Cache the results of `resolve` for `ttl`. Paths with no
+    /// publishers are never cached. `None`, the default, disables the
+    /// cache and drops everything in it.
+    pub fn set_cache_ttl(&self, ttl: Option<Duration>) {
+        let mut cache = self.1.lock();
+        cache.ttl = ttl;
+        if ttl.is_none() {
+            cache.clear();
+        }
+    }
+
+    /// Keep at most `capacity` resolutions in the cache, when it is
+    /// full the oldest are dropped to make room. Expired resolutions
+    /// are dropped as new ones are added. The default is 100,000.
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        let mut cache = self.1.lock();
+        cache.capacity = capacity;
+        cache.evict(Instant::now(), capacity);
+    }
+
+    /// Route everything under `path` to the resolver cluster
+    /// described by `cfg` instead of the default cluster, e.g. serve
+    /// `/lab` from a lab cluster while everything else comes from
+    /// production. Roots never expire, adding a root that already
+    /// exists replaces it. Connections to the root use the same
+    /// desired auth and tls configuration as the default cluster.
+    ///
+    /// A root only affects paths under it, listing a parent of the
+    /// root will not show it unless the default cluster also has it.
+    pub fn add_root(&self, path: Path, cfg: Config) -> Result<()> {
+        self.0.add_root(path.clone(), cfg)?;
+        self.1.lock().by_path.retain(|p, _| !Path::is_parent(&path, p));
+        Ok(())
+    }
+
+    /// Drop any cached resolution of `path`.
+    pub fn invalidate(&self, path: &Path) {
+        self.1.lock().by_path.remove(path);
+    }
+
+    /// Drop every cached resolution that refers to a publisher at
+    /// `addr`, e.g. because the connection to it was lost.
+    pub fn invalidate_publisher(&self, addr: SocketAddr) {
+        self.1.lock().by_path.retain(|_, c| c.publishers.iter().all(|p| p.addr != addr))
     }
 
     /// send the specified messages to the resolver, and return the answers (in send order)
@@ -407,7 +746,10 @@ impl ResolverRead {
         &self,
         batch: &Pooled<Vec<ToRead>>,
     ) -> Result<(Pooled<FxHashMap<PublisherId, Publisher>>, Pooled<Vec<FromRead>>)> {
-        self.0.send(batch).await
+        let start = Instant::now();
+        let res = self.0.send(batch).await;
+        self.2.record_read(batch, start, &res);
+        res
     }
 
     /// resolve the specified paths, results are in send order
@@ -418,9 +760,31 @@ impl ResolverRead {
     where
         I: IntoIterator<Item = Path>,
     {
+        let now = Instant::now();
+        let mut cached: Vec<(usize, Resolved)> = Vec::new();
         let mut to = RAWTOREADPOOL.take();
-        to.extend(batch.into_iter().map(ToRead::Resolve));
-        let (publishers, mut result) = self.send(&to).await?;
+        let mut publishers = PUBLISHERPOOL.take();
+        {
+            let mut cache = self.1.lock();
+            for (i, path) in batch.into_iter().enumerate() {
+                match cache.get(now, &path) {
+                    None => to.push(ToRead::Resolve(path)),
+                    Some((pbs, resolved)) => {
+                        for pb in pbs {
+                            publishers.insert(pb.id, pb.clone());
+                        }
+                        cached.push((i, resolved.clone()));
+                    }
+                }
+            }
+        }
+        if to.is_empty() {
+            let mut out = RESOLVEDPOOL.take();
+            out.extend(cached.into_iter().map(|(_, r)| r));
+            return Ok((publishers, out));
+        }
+        let (mut fetched, mut result) = self.send(&to).await?;
+        publishers.extend(fetched.drain());
         if result.len() != to.len() {
             bail!(
                 "unexpected number of resolve results {} expected {}",
@@ -429,19 +793,48 @@ impl ResolverRead {
             )
         } else {
             let mut out = RESOLVEDPOOL.take();
-            for r in result.drain(..) {
+            let mut cached = cached.into_iter().peekable();
+            let mut cache = self.1.lock();
+            for (i, r) in result.drain(..).enumerate() {
+                while let Some((_, r)) = cached.next_if(|(j, _)| *j == out.len()) {
+                    out.push(r);
+                }
                 match r {
                     FromRead::Resolved(r) => {
+                        if let Some(path) = to[i].path() {
+                            cache.insert(now, path.clone(), &publishers, &r);
+                        }
                         out.push(r);
                     }
+                    FromRead::Denied => {
+                        let path = to[i].path().cloned().unwrap_or_else(Path::root);
+                        return Err(ResolverError::PermissionDenied(path).into());
+                    }
                     m => bail!("unexpected resolve response {:?}", m),
                 }
             }
+            out.extend(cached.map(|(_, r)| r));
             Ok((publishers, out))
         }
     }
 
-    /// list children of the specified path. Order is unspecified.
+    /// resolve a single path. Unlike `resolve`, a path with no
+    /// publishers is reported as `ResolverError::NoSuchPath`.
+    pub async fn resolve_one(
+        &self,
+        path: Path,
+    ) -> Result<(Pooled<FxHashMap<PublisherId, Publisher>>, Resolved)> {
+        let (publishers, mut resolved) = self.resolve(iter::once(path.clone())).await?;
+        match resolved.pop() {
+            Some(r) if r.publishers.len() > 0 => Ok((publishers, r)),
+            Some(_) | None => Err(ResolverError::NoSuchPath(path).into()),
+        }
+    }
+
+    /// list children of the specified path. Order is
+    /// unspecified. Listing a path that has neither children nor
+    /// publishers fails with `ResolverError::NoSuchPath`, except for
+    /// the root, which always exists.
     pub async fn list(&self, path: Path) -> Result<Pooled<Vec<Path>>> {
         let mut to = RAWTOREADPOOL.take();
         to.push(ToRead::List(path.clone()));
@@ -451,6 +844,9 @@ impl ResolverRead {
         } else {
             let mut from_server = match result.pop().unwrap() {
                 FromRead::List(paths) => paths,
+                FromRead::Denied => {
+                    return Err(ResolverError::PermissionDenied(path).into())
+                }
                 m => bail!("unexpected result from list {:?}", m),
             };
             from_server.sort();
@@ -461,11 +857,109 @@ impl ResolverRead {
                     }
                 }
             }
+            // an empty list doesn't tell a leaf from a path that
+            // doesn't exist, ask whether anyone publishes it
+            if from_server.is_empty() && path != Path::root() {
+                let (_, resolved) = self.resolve(iter::once(path.clone())).await?;
+                if resolved.iter().all(|r| r.publishers.is_empty()) {
+                    return Err(ResolverError::NoSuchPath(path).into());
+                }
+            }
             Ok(from_server)
         }
     }
 
+    /// list children of the specified path along with metadata
+    /// about each one, the number of publishers, whether it has
+    /// children, its flags, and the permissions you have on it. This
+    /// saves a round of resolves when you need to know more than
+    /// just the names. Order is unspecified.
+    ///
+    /// Children that are referrals to another resolver cluster are
+    /// included, but since that cluster wasn't asked about them only
+    /// `has_children` is set.
+    pub async fn list_with_meta(&self, path: Path) -> Result<Pooled<Vec<ListEntry>>> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::ListWithMeta(path.clone()));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from list_with_meta got {}", result.len());
+        }
+        let mut from_server = match result.pop().unwrap() {
+            FromRead::ListWithMeta(entries) => entries,
+            FromRead::Denied => return Err(ResolverError::PermissionDenied(path).into()),
+            m => bail!("unexpected result from list_with_meta {:?}", m),
+        };
+        from_server.sort_by(|e0, e1| e0.path.cmp(&e1.path));
+        for p in (self.0).0.lock().router.cached.keys() {
+            if Path::is_immediate_parent(&path, p) {
+                if let Err(i) = from_server.binary_search_by(|e| e.path.cmp(p)) {
+                    let e = ListEntry {
+                        path: p.clone(),
+                        publishers: 0,
+                        has_children: true,
+                        flags: 0,
+                        permissions: 0,
+                    };
+                    from_server.insert(i, e)
+                }
+            }
+        }
+        Ok(from_server)
+    }
+
+    /// list the paths, including defaults, published by the
+    /// publisher at `addr`, e.g. to find out what will go away if
+    /// its host goes down. Only the cluster you are connected to is
+    /// asked, and paths you don't have list permission on are left
+    /// out. Sorted.
+    pub async fn resolved_by(&self, addr: SocketAddr) -> Result<Pooled<Vec<Path>>> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::ResolvedBy(addr));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from resolved_by got {}", result.len());
+        }
+        match result.pop().unwrap() {
+            FromRead::List(mut paths) => {
+                paths.sort();
+                Ok(paths)
+            }
+            m => bail!("unexpected result from resolved_by {:?}", m),
+        }
+    }
+
+    /// get the metadata the publisher of `path` attached to it, see
+    /// `Publisher::set_metadata`. The metadata is empty if the path
+    /// has none, or isn't published.
+    pub async fn metadata(&self, path: Path) -> Result<Metadata> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::Metadata(path.clone()));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from metadata got {}", result.len());
+        }
+        match result.pop().unwrap() {
+            FromRead::Metadata(meta) => Ok(meta),
+            FromRead::Denied => Err(ResolverError::PermissionDenied(path).into()),
+            m => bail!("unexpected result from metadata {:?}", m),
+        }
+    }
+
     async fn send_and_aggregate<F: FnMut(FromRead) -> Result<Pooled<Vec<Referral>>>>(
+        &self,
+        message: ToRead,
+        process_reply: F,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let res = self.send_and_aggregate_inner(message.clone(), process_reply).await;
+        self.2.record_aggregate(&message, start, &res);
+        res
+    }
+
+    async fn send_and_aggregate_inner<
+        F: FnMut(FromRead) -> Result<Pooled<Vec<Referral>>>,
+    >(
         &self,
         message: ToRead,
         mut process_reply: F,
@@ -489,7 +983,7 @@ impl ResolverRead {
                 }
             }
             for r in future::join_all(waiters).await {
-                let (_, mut r) = r?;
+                let (_, mut r) = r.context(ResolverError::Unreachable)?;
                 for (_, reply) in r.drain(..) {
                     let mut referrals = process_reply(reply)?;
                     for r in referrals.drain(..) {
@@ -509,7 +1003,8 @@ impl ResolverRead {
     /// globset. You will get a list of batches of paths. If your
     /// globset is configured to match only published paths, then the
     /// batches should be disjoint, otherwise there may be some
-    /// duplicate structural elements.
+    /// duplicate structural elements. Globs you don't have list
+    /// permission on match nothing.
     pub async fn list_matching(
         &self,
         globset: &GlobSet,
@@ -607,20 +1102,85 @@ impl ResolverRead {
                     }
                     Ok(table)
                 }
+                FromRead::Denied => Err(ResolverError::PermissionDenied(path).into()),
                 m => bail!("unexpected result from table {:?}", m),
             }
         }
     }
+
+    /// Watch the children of `path`. The stream begins with an
+    /// `Added` event for each existing child, and then yields
+    /// `Added` or `Removed` as children come and go. The resolver
+    /// server pushes a notification whenever anything under `path`
+    /// is published or unpublished, so unlike `check_changed` there
+    /// is no need to poll. Watching requires the same permission as
+    /// `list`. The watch stops when the stream is dropped.
+    pub fn watch(&self, path: Path) -> impl Stream<Item = WatchEvent> + Unpin {
+        let (tx, rx) = mpsc::channel(3);
+        task::spawn(read_client::watch(self.clone(), path, tx));
+        rx.flat_map(stream::iter)
+    }
+
+    /// Send an administrative request to every member server of the
+    /// cluster responsible for `path`, and return each member's
+    /// reply. This requires the admin permission on the root of that
+    /// cluster's namespace, anonymous servers refuse all admin
+    /// requests.
+    pub async fn admin(
+        &self,
+        path: &Path,
+        req: ToAdmin,
+    ) -> Result<Vec<(SocketAddr, FromAdmin)>> {
+        let (referral, desired_auth, tls) = self.route(path);
+        read_client::admin(&referral, &desired_auth, &tls, req).await
+    }
+
+    /// The resolver cluster responsible for `path`, and the
+    /// parameters needed to connect to it.
+    fn route(
+        &self,
+        path: &Path,
+    ) -> (Arc<Referral>, DesiredAuth, Option<tls::CachedConnector>) {
+        let mut inner = (self.0).0.lock();
+        let mut gc = Vec::new();
+        let r = inner.router.route(Instant::now(), path, &mut gc);
+        for p in gc {
+            inner.router.cached.remove(&p);
+        }
+        let r = r.unwrap_or_else(|| inner.default.clone());
+        (r, inner.desired_auth.clone(), inner.tls.clone())
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct ResolverWrite(ResolverWrap<WriteClient, ToWrite, FromWrite>);
+pub struct ResolverWrite(ResolverWrap<WriteClient, ToWrite, FromWrite>, Stats);
 
 impl ResolverWrite {
     pub fn new(
         default: Config,
         desired_auth: DesiredAuth,
         writer_addr: SocketAddr,
+    ) -> Result<Self> {
+        Self::new_with_endpoints(
+            default,
+            desired_auth,
+            writer_addr,
+            None,
+            SmallVec::new(),
+        )
+    }
+
+    /// Same as `new`, but also advertise `local_endpoint`, a unix
+    /// domain socket that subscribers on the same machine may use to
+    /// reach the publisher at `writer_addr`, and `alt_addrs`, more
+    /// addresses, usually of the other address family, that it is
+    /// listening on.
+    pub fn new_with_endpoints(
+        default: Config,
+        desired_auth: DesiredAuth,
+        writer_addr: SocketAddr,
+        local_endpoint: Option<ArcStr>,
+        alt_addrs: SmallVec<[SocketAddr; 2]>,
     ) -> Result<Self> {
         match &desired_auth {
             DesiredAuth::Local
@@ -636,21 +1196,35 @@ impl ResolverWrite {
                 }
             },
         }
-        Ok(ResolverWrite(ResolverWrap::new(
-            default,
-            desired_auth,
-            writer_addr,
-            RAWFROMWRITEPOOL.clone(),
-            FROMWRITEPOOL.clone(),
-            TOWRITEPOOL.clone(),
-        )))
+        Ok(ResolverWrite(
+            ResolverWrap::new(
+                default,
+                desired_auth,
+                writer_addr,
+                local_endpoint,
+                alt_addrs,
+                RAWFROMWRITEPOOL.clone(),
+                FROMWRITEPOOL.clone(),
+                TOWRITEPOOL.clone(),
+            ),
+            Stats::default(),
+        ))
+    }
+
+    /// Round trip metrics for the requests this client has sent, by
+    /// kind of request.
+    pub fn stats(&self) -> BTreeMap<Op, OpStats> {
+        self.1.snapshot()
     }
 
     pub async fn send(
         &self,
         batch: &Pooled<Vec<ToWrite>>,
     ) -> Result<Pooled<Vec<FromWrite>>> {
-        let (_, r) = self.0.send(batch).await?;
+        let start = Instant::now();
+        let res = self.0.send(batch).await;
+        self.1.record_write(batch, start, &res);
+        let (_, r) = res?;
         Ok(r)
     }
 
@@ -667,12 +1241,15 @@ impl ResolverWrite {
         let mut to = RAWTOWRITEPOOL.take();
         let len = to.len();
         to.extend(batch.into_iter().map(f));
-        let (_, mut from) = self.0.send(&to).await?;
+        let mut from = self.send(&to).await?;
         if from.len() != to.len() {
             bail!("unexpected number of responses {} vs expected {}", from.len(), len);
         }
         for (i, reply) in from.drain(..).enumerate() {
-            if reply != expected {
+            if reply == FromWrite::Denied {
+                let path = to[i].path().cloned().unwrap_or_else(Path::root);
+                return Err(ResolverError::PermissionDenied(path).into());
+            } else if reply != expected {
                 bail!("unexpected response to {:?}, {:?}", &to[i], reply)
             }
         }
@@ -694,6 +1271,23 @@ impl ResolverWrite {
         .await
     }
 
+    /// Publish paths, replacing their metadata if it is specified.
+    /// Sending metadata requires a resolver server that supports
+    /// it, older servers will drop the connection.
+    pub async fn publish_with_metadata<I>(&self, batch: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Path, Option<u32>, Option<Metadata>)>,
+    {
+        self.send_expect(batch, FromWrite::Published, |(path, flags, meta)| {
+            match (flags, meta) {
+                (flags, Some(meta)) => ToWrite::PublishWithMetadata(path, flags, meta),
+                (Some(flags), None) => ToWrite::PublishWithFlags(path, flags),
+                (None, None) => ToWrite::Publish(path),
+            }
+        })
+        .await
+    }
+
     pub async fn publish_default<I: IntoIterator<Item = Path>>(
         &self,
         batch: I,
@@ -725,12 +1319,24 @@ impl ResolverWrite {
         self.send_expect(batch, FromWrite::Unpublished, ToWrite::UnpublishDefault).await
     }
 
+    /// Unpublish everything this publisher published at or below each
+    /// path in `batch`, including default publishers, in one round
+    /// trip. This requires a resolver server that understands
+    /// `ToWrite::UnpublishPrefix`, older servers will drop the
+    /// connection.
+    pub async fn unpublish_prefix<I: IntoIterator<Item = Path>>(
+        &self,
+        batch: I,
+    ) -> Result<()> {
+        self.send_expect(batch, FromWrite::Unpublished, ToWrite::UnpublishPrefix).await
+    }
+
     // CR estokes: this is broken on complex clusters, but it's also
     // redundant, consider removing it.
     pub async fn clear(&self) -> Result<()> {
         let mut batch = RAWTOWRITEPOOL.take();
         batch.push(ToWrite::Clear);
-        let (_, r) = self.0.send(&batch).await?;
+        let r = self.send(&batch).await?;
         if r.len() != 1 {
             bail!("unexpected response to clear command {:?}", r)
         } else {
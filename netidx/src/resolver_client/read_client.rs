@@ -1,13 +1,18 @@
-use super::common::{
-    krb5_authentication, DesiredAuth, Response, ResponseChan, FROMREADPOOL, HELLO_TO,
-    PUBLISHERPOOL, RAWFROMREADPOOL,
+use super::{
+    common::{
+        krb5_authentication, DesiredAuth, Response, ResponseChan, FROMREADPOOL, HELLO_TO,
+        PUBLISHERPOOL, RAWFROMREADPOOL,
+    },
+    ResolverError, ResolverRead, WatchEvent,
 };
 use crate::{
     channel::{self, Channel, K5CtxWrap},
     os::local_auth::AuthClient,
+    path::Path,
     pool::Pooled,
     protocol::resolver::{
-        Auth, AuthRead, ClientHello, FromRead, Publisher, Referral, ToRead,
+        Auth, AuthRead, ClientHello, FromAdmin, FromRead, Publisher, Referral, ToAdmin,
+        ToRead,
     },
     tls,
     utils::Either,
@@ -17,12 +22,17 @@ use cross_krb5::ClientCtx;
 use futures::{
     channel::{mpsc, oneshot},
     prelude::*,
+    select_biased,
 };
 use fxhash::FxHashSet;
 use log::{info, warn};
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use std::{
-    cmp::max, collections::HashSet, fmt::Debug, net::SocketAddr, sync::Arc,
+    cmp::max,
+    collections::{BTreeSet, HashSet},
+    fmt::Debug,
+    net::SocketAddr,
+    sync::Arc,
     time::Duration,
 };
 use tokio::{net::TcpStream, task, time};
@@ -40,12 +50,20 @@ macro_rules! cwt {
 
 async fn connect(
     bad_addrs: &mut FxHashSet<SocketAddr>,
+    prefer: Option<(SocketAddr, Auth)>,
     resolver: &Referral,
     desired_auth: &DesiredAuth,
     tls: &Option<tls::CachedConnector>,
-) -> Result<Channel> {
+) -> Result<(Channel, (SocketAddr, Auth))> {
+    if resolver.addrs.is_empty() {
+        bail!("no resolver servers are known");
+    }
     let mut addrs = resolver.addrs.clone();
     addrs.as_mut_slice().shuffle(&mut thread_rng());
+    if let Some(prefer) = prefer {
+        addrs.retain(|(addr, _)| addr != &prefer.0);
+        addrs.insert(0, prefer);
+    }
     let mut n = 0;
     loop {
         let (addr, auth) = &addrs[n % addrs.len()];
@@ -166,7 +184,7 @@ async fn connect(
                 }
             }
         };
-        break Ok(con);
+        break Ok((con, (*addr, auth.clone())));
     }
 }
 
@@ -180,9 +198,21 @@ fn partition_publishers(m: FromRead) -> Either<FromRead, Publisher> {
         | FromRead::GetChangeNr(_)
         | FromRead::List(_)
         | FromRead::ListMatching(_)
+        | FromRead::ListWithMeta(_)
         | FromRead::Referral(_)
         | FromRead::Resolved(_)
-        | FromRead::Table(_) => Either::Left(m),
+        | FromRead::Table(_)
+        | FromRead::Migrate(_)
+        | FromRead::Changed(_)
+        | FromRead::Admin(_)
+        | FromRead::Metadata(_) => Either::Left(m),
+    }
+}
+
+async fn receive_idle(con: &mut Option<Channel>) -> Result<FromRead> {
+    match con {
+        Some(c) => c.receive().await,
+        None => future::pending().await,
     }
 }
 
@@ -193,9 +223,31 @@ async fn connection(
     tls: Option<tls::CachedConnector>,
 ) {
     let mut con: Option<Channel> = None;
+    let mut current: Option<(SocketAddr, Auth)> = None;
+    let mut migrate: Option<SocketAddr> = None;
     let mut bad_addrs: FxHashSet<SocketAddr> = HashSet::default();
     'main: loop {
-        match receiver.next().await {
+        // the server may ask us to move while we aren't waiting on
+        // a reply, so keep reading the connection between requests
+        let m = select_biased! {
+            m = receiver.next() => m,
+            r = receive_idle(&mut con).fuse() => {
+                match r {
+                    Ok(FromRead::Migrate(addr)) => {
+                        migrate = Some(addr);
+                        con = None;
+                    }
+                    Ok(FromRead::Changed(_)) => (),
+                    Ok(m) => warn!("read connection unexpected message {:?}", m),
+                    Err(e) => {
+                        info!("read connection closed {}", e);
+                        con = None;
+                    }
+                }
+                continue 'main;
+            }
+        };
+        match m {
             None => break,
             Some((tx_batch, reply)) => {
                 let mut tries: usize = 0;
@@ -211,11 +263,34 @@ async fn connection(
                     let c = match con {
                         Some(ref mut c) => c,
                         None => {
-                            match connect(&mut bad_addrs, &resolver, &desired_auth, &tls)
-                                .await
+                            let prefer = match (migrate.take(), current.take()) {
+                                (Some(alternate), Some((addr, auth))) => {
+                                    info!(
+                                        "resolver {} is draining, moving to {}",
+                                        addr, alternate
+                                    );
+                                    let auth = resolver
+                                        .addrs
+                                        .iter()
+                                        .find(|(a, _)| a == &alternate)
+                                        .map(|(_, auth)| auth.clone())
+                                        .unwrap_or(auth);
+                                    Some((alternate, auth))
+                                }
+                                (_, _) => None,
+                            };
+                            match connect(
+                                &mut bad_addrs,
+                                prefer,
+                                &resolver,
+                                &desired_auth,
+                                &tls,
+                            )
+                            .await
                             {
-                                Ok(c) => {
+                                Ok((c, addr)) => {
                                     con = Some(c);
+                                    current = Some(addr);
                                     con.as_mut().unwrap()
                                 }
                                 Err(e) => {
@@ -234,7 +309,10 @@ async fn connection(
                         max(HELLO_TO, Duration::from_micros(tx_batch.len() as u64 * 50));
                     for (_, m) in &*tx_batch {
                         match m {
-                            ToRead::List(_) | ToRead::ListMatching(_) => {
+                            ToRead::List(_)
+                            | ToRead::ListWithMeta(_)
+                            | ToRead::ListMatching(_)
+                            | ToRead::ResolvedBy(_) => {
                                 timeout += HELLO_TO;
                             }
                             _ => (),
@@ -257,15 +335,16 @@ async fn connection(
                             let mut rx_batch = RAWFROMREADPOOL.take();
                             let mut publishers = PUBLISHERPOOL.take();
                             while rx_batch.len() < tx_batch.len() {
-                                let f =
-                                    c.receive_batch_fn(|m| {
-                                        match partition_publishers(m) {
-                                            Either::Left(m) => rx_batch.push(m),
-                                            Either::Right(p) => {
-                                                publishers.insert(p.id, p);
-                                            }
+                                let f = c.receive_batch_fn(|m| match m {
+                                    FromRead::Migrate(addr) => migrate = Some(addr),
+                                    FromRead::Changed(_) => (),
+                                    m => match partition_publishers(m) {
+                                        Either::Left(m) => rx_batch.push(m),
+                                        Either::Right(p) => {
+                                            publishers.insert(p.id, p);
                                         }
-                                    });
+                                    },
+                                });
                                 match time::timeout(timeout, f).await {
                                     Ok(Ok(())) => (),
                                     Ok(Err(e)) => {
@@ -288,6 +367,9 @@ async fn connection(
                                     .map(|(i, m)| (tx_batch[i].0, m)),
                             );
                             let _ = reply.send((publishers, result));
+                            if migrate.is_some() {
+                                con = None;
+                            }
                             break;
                         }
                     }
@@ -323,3 +405,170 @@ impl ReadClient {
         rx
     }
 }
+
+/// List `path` and send the difference from `children` to `tx`
+async fn watch_diff(
+    resolver: &ResolverRead,
+    path: &Path,
+    children: &mut BTreeSet<Path>,
+    tx: &mut mpsc::Sender<Vec<WatchEvent>>,
+) -> Result<()> {
+    let current = match resolver.list(path.clone()).await {
+        Ok(mut l) => l.drain(..).collect::<BTreeSet<_>>(),
+        // the watched path may not exist yet, or may be gone with
+        // its last child
+        Err(e) => match e.downcast_ref::<ResolverError>() {
+            Some(ResolverError::NoSuchPath(_)) => BTreeSet::new(),
+            _ => return Err(e),
+        },
+    };
+    let mut events = children
+        .difference(&current)
+        .map(|p| WatchEvent::Removed(p.clone()))
+        .collect::<Vec<_>>();
+    events.extend(current.difference(children).map(|p| WatchEvent::Added(p.clone())));
+    *children = current;
+    if !events.is_empty() {
+        tx.send(events).await?
+    }
+    Ok(())
+}
+
+pub(super) async fn watch(
+    resolver: ResolverRead,
+    path: Path,
+    mut tx: mpsc::Sender<Vec<WatchEvent>>,
+) {
+    let mut children = BTreeSet::new();
+    let mut con: Option<(Channel, Arc<Referral>)> = None;
+    let mut prefer: Option<(SocketAddr, Auth)> = None;
+    let mut bad_addrs: FxHashSet<SocketAddr> = HashSet::default();
+    let mut batch: Vec<FromRead> = Vec::new();
+    let mut changed = true;
+    let mut check = time::interval(Duration::from_secs(10));
+    loop {
+        if tx.is_closed() {
+            break;
+        }
+        if changed {
+            match watch_diff(&resolver, &path, &mut children, &mut tx).await {
+                Ok(()) => changed = false,
+                Err(e) => {
+                    if tx.is_closed() {
+                        break;
+                    }
+                    warn!("watch {} failed to list {}, will retry", path, e);
+                    let wait = thread_rng().gen_range(1..12);
+                    time::sleep(Duration::from_secs(wait)).await;
+                    continue;
+                }
+            }
+        }
+        let (c, referral) = match &mut con {
+            Some((c, referral)) => (c, referral),
+            None => {
+                // list populated the router, so this is the cluster
+                // that owns path
+                let (referral, desired_auth, tls) = resolver.route(&path);
+                let prefer = prefer.take();
+                match connect(&mut bad_addrs, prefer, &referral, &desired_auth, &tls)
+                    .await
+                {
+                    Ok((mut c, _)) => {
+                        match c.send_one(&ToRead::Watch(path.clone())).await {
+                            Ok(()) => {
+                                let con = con.insert((c, referral));
+                                (&mut con.0, &mut con.1)
+                            }
+                            Err(e) => {
+                                warn!("watch {} failed to send {}, will retry", path, e);
+                                let wait = thread_rng().gen_range(1..12);
+                                time::sleep(Duration::from_secs(wait)).await;
+                                continue;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("watch {} failed to connect {}, will retry", path, e);
+                        let wait = thread_rng().gen_range(1..12);
+                        time::sleep(Duration::from_secs(wait)).await;
+                        continue;
+                    }
+                }
+            }
+        };
+        select_biased! {
+            _ = check.tick().fuse() => (),
+            r = c.receive_batch(&mut batch).fuse() => match r {
+                Err(e) => {
+                    warn!("watch {} connection failed {}", path, e);
+                    con = None;
+                    changed = true;
+                }
+                Ok(()) => {
+                    for m in batch.drain(..) {
+                        match m {
+                            FromRead::Changed(_) => changed = true,
+                            FromRead::Migrate(addr) => {
+                                prefer = referral
+                                    .addrs
+                                    .iter()
+                                    .find(|(a, _)| a == &addr)
+                                    .cloned();
+                                con = None;
+                                changed = true;
+                                break;
+                            }
+                            FromRead::Denied => {
+                                // list will report the denial, and keep
+                                // retrying in case the permissions change
+                                warn!("watch {} permission denied", path);
+                                con = None;
+                                changed = true;
+                                break;
+                            }
+                            m => warn!("watch {} unexpected message {:?}", path, m),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Send `req` to every member server of `resolver` and collect their
+/// replies. Admin requests are answered by the member that receives
+/// them, so each member gets a dedicated connection.
+pub(super) async fn admin(
+    resolver: &Referral,
+    desired_auth: &DesiredAuth,
+    tls: &Option<tls::CachedConnector>,
+    req: ToAdmin,
+) -> Result<Vec<(SocketAddr, FromAdmin)>> {
+    let mut replies = Vec::new();
+    for (addr, auth) in resolver.addrs.iter() {
+        let member = Referral {
+            path: resolver.path.clone(),
+            ttl: None,
+            addrs: Pooled::orphan(vec![(*addr, auth.clone())]),
+        };
+        let mut bad_addrs = HashSet::default();
+        let (mut con, _) = connect(&mut bad_addrs, None, &member, desired_auth, tls)
+            .await
+            .with_context(|| format!("connecting to {}", addr))?;
+        con.send_one(&ToRead::Admin(req.clone())).await?;
+        let reply = loop {
+            match con.receive::<FromRead>().await? {
+                FromRead::Admin(reply) => break reply,
+                FromRead::Migrate(_) | FromRead::Changed(_) => (),
+                FromRead::Denied => {
+                    return Err(ResolverError::PermissionDenied(Path::root()).into())
+                }
+                FromRead::Error(e) => bail!("{} failed the request {}", addr, e),
+                m => bail!("unexpected reply from {} {:?}", addr, m),
+            }
+        };
+        replies.push((*addr, reply));
+    }
+    Ok(replies)
+}
@@ -0,0 +1,207 @@
+use crate::{
+    pool::Pooled,
+    protocol::resolver::{FromRead, FromWrite, ToRead, ToWrite},
+};
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, slice, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+/// The number of histogram buckets, the last one counts everything
+/// slower than 2^22 us, about 4 seconds.
+const BUCKETS: usize = 24;
+
+/// The kinds of resolver requests that are measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Op {
+    Resolve,
+    /// list, list_with_meta, and resolved_by
+    List,
+    ListMatching,
+    Table,
+    CheckChanged,
+    /// publish and publish_default, with or without flags
+    Publish,
+    /// unpublish, unpublish_default, and clear
+    Unpublish,
+    Other,
+}
+
+impl Op {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Resolve => "resolve",
+            Op::List => "list",
+            Op::ListMatching => "list-matching",
+            Op::Table => "table",
+            Op::CheckChanged => "check-changed",
+            Op::Publish => "publish",
+            Op::Unpublish => "unpublish",
+            Op::Other => "other",
+        }
+    }
+
+    fn of_read(batch: &[ToRead]) -> Op {
+        match batch.first() {
+            Some(ToRead::Resolve(_)) => Op::Resolve,
+            Some(ToRead::List(_) | ToRead::ListWithMeta(_) | ToRead::ResolvedBy(_)) => {
+                Op::List
+            }
+            Some(ToRead::ListMatching(_)) => Op::ListMatching,
+            Some(ToRead::Table(_)) => Op::Table,
+            Some(ToRead::GetChangeNr(_)) => Op::CheckChanged,
+            Some(_) | None => Op::Other,
+        }
+    }
+
+    fn of_write(batch: &[ToWrite]) -> Op {
+        match batch.first() {
+            Some(
+                ToWrite::Publish(_)
+                | ToWrite::PublishDefault(_)
+                | ToWrite::PublishWithFlags(_, _)
+                | ToWrite::PublishDefaultWithFlags(_, _)
+                | ToWrite::PublishWithMetadata(_, _, _),
+            ) => Op::Publish,
+            Some(
+                ToWrite::Unpublish(_)
+                | ToWrite::UnpublishDefault(_)
+                | ToWrite::UnpublishPrefix(_)
+                | ToWrite::Clear,
+            ) => Op::Unpublish,
+            Some(ToWrite::Heartbeat) | None => Op::Other,
+        }
+    }
+}
+
+/// A latency histogram with power of two buckets. Bucket `i` counts
+/// latencies of less than 2^i microseconds that didn't fit in bucket
+/// `i - 1`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    total_us: u64,
+    max_us: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        let i = (64 - us.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[i] += 1;
+        self.total_us = self.total_us.saturating_add(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// The number of latencies recorded
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            n => Duration::from_micros(self.total_us / n),
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_us)
+    }
+
+    /// An upper bound on the `q` quantile of the recorded latencies,
+    /// e.g. `quantile(0.99)`. It is accurate to within a factor of
+    /// two.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let n = self.count();
+        if n == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((q.clamp(0., 1.) * n as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (upper, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return upper.min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    /// The upper bound and count of each bucket
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets.iter().enumerate().map(|(i, count)| {
+            let upper = if i == BUCKETS - 1 { self.max_us } else { (1 << i) - 1 };
+            (Duration::from_micros(upper), *count)
+        })
+    }
+}
+
+/// Round trip metrics for one kind of resolver request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    /// The time from sending a request until all the replies arrived,
+    /// including referrals, failed requests are included.
+    pub latency: Histogram,
+    /// The number of requests that failed, or were refused by the
+    /// resolver server.
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Stats(Arc<Mutex<BTreeMap<Op, OpStats>>>);
+
+impl Stats {
+    fn record(&self, op: Op, start: Instant, failed: bool) {
+        let latency = start.elapsed();
+        let mut stats = self.0.lock();
+        let s = stats.entry(op).or_insert_with(OpStats::default);
+        s.latency.record(latency);
+        if failed {
+            s.errors += 1;
+        }
+    }
+
+    pub(super) fn record_read<P>(
+        &self,
+        batch: &[ToRead],
+        start: Instant,
+        res: &Result<(P, Pooled<Vec<FromRead>>)>,
+    ) {
+        let failed = match res {
+            Err(_) => true,
+            Ok((_, replies)) => {
+                replies.iter().any(|r| matches!(r, FromRead::Denied | FromRead::Error(_)))
+            }
+        };
+        self.record(Op::of_read(batch), start, failed)
+    }
+
+    pub(super) fn record_write<P>(
+        &self,
+        batch: &[ToWrite],
+        start: Instant,
+        res: &Result<(P, Pooled<Vec<FromWrite>>)>,
+    ) {
+        let failed = match res {
+            Err(_) => true,
+            Ok((_, replies)) => replies
+                .iter()
+                .any(|r| matches!(r, FromWrite::Denied | FromWrite::Error(_))),
+        };
+        self.record(Op::of_write(batch), start, failed)
+    }
+
+    pub(super) fn record_aggregate<T>(
+        &self,
+        message: &ToRead,
+        start: Instant,
+        res: &Result<T>,
+    ) {
+        self.record(Op::of_read(slice::from_ref(message)), start, res.is_err())
+    }
+
+    pub(super) fn snapshot(&self) -> BTreeMap<Op, OpStats> {
+        self.0.lock().clone()
+    }
+}
@@ -16,6 +16,7 @@ use crate::{
     tls, utils,
 };
 use anyhow::{anyhow, Result};
+use arcstr::ArcStr;
 use cross_krb5::{ClientCtx, K5Ctx};
 use futures::{
     channel::{mpsc, oneshot},
@@ -23,12 +24,13 @@ use futures::{
     prelude::*,
     select_biased,
 };
-use fxhash::{FxBuildHasher, FxHashMap};
-use indexmap::IndexMap;
+use fxhash::{FxBuildHasher, FxHashMap, FxHashSet};
+use indexmap::{IndexMap, IndexSet};
 use log::{debug, info, warn};
 use parking_lot::{Mutex, RwLock};
 use rand::{thread_rng, Rng};
-use std::{cmp::max, fmt::Debug, net::SocketAddr, sync::Arc, time::Duration};
+use smallvec::SmallVec;
+use std::{cmp::max, fmt::Debug, mem, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{
     net::TcpStream,
     sync::broadcast::{self, error::RecvError},
@@ -66,17 +68,72 @@ struct Connection {
     resolver_addr: SocketAddr,
     resolver_auth: Auth,
     write_addr: SocketAddr,
+    local_endpoint: Option<ArcStr>,
+    alt_addrs: SmallVec<[SocketAddr; 2]>,
     published: IndexMap<Path, ToWrite, FxBuildHasher>,
+    /// prefix unpublishes from failed batches, kept apart from
+    /// `published` because they cover everything below the path
+    unpublish_prefixes: IndexSet<Path, FxBuildHasher>,
     secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
+    members: Arc<RwLock<FxHashSet<SocketAddr>>>,
     security_context: Option<K5CtxWrap<ClientCtx>>,
     tls: Option<tls::CachedConnector>,
     desired_auth: DesiredAuth,
     degraded: bool,
     active: bool,
+    migrate: Option<SocketAddr>,
+    /// the resolver acknowledges heartbeats, so keep the connection
+    /// open and heartbeat on it instead of reconnecting
+    in_band_hb: bool,
+    hb_outstanding: bool,
     heartbeat: Interval,
     disconnect: Interval,
 }
 
+/// receive a reply, remembering any migration request the resolver
+/// sends along the way
+async fn receive_reply(
+    con: &mut Channel,
+    migrate: &mut Option<SocketAddr>,
+) -> Result<FromWrite> {
+    loop {
+        match con.receive().await? {
+            FromWrite::Migrate(addr) => *migrate = Some(addr),
+            FromWrite::Heartbeat => (),
+            m => break Ok(m),
+        }
+    }
+}
+
+/// Keep track of the publications we would need to replay to a
+/// resolver server that doesn't know about us.
+fn track_published(
+    published: &mut IndexMap<Path, ToWrite, FxBuildHasher>,
+    batch: &[(usize, ToWrite)],
+) {
+    for (_, tx) in batch {
+        match tx {
+            ToWrite::Publish(p)
+            | ToWrite::PublishDefault(p)
+            | ToWrite::PublishWithFlags(p, _)
+            | ToWrite::PublishDefaultWithFlags(p, _)
+            | ToWrite::PublishWithMetadata(p, _, _) => {
+                published.insert(p.clone(), tx.clone());
+            }
+            ToWrite::Unpublish(p) | ToWrite::UnpublishDefault(p) => {
+                published.remove(p);
+            }
+            ToWrite::UnpublishPrefix(p) => {
+                published.retain(|k, _| !Path::is_parent(p, k));
+            }
+            ToWrite::Clear => {
+                published.clear();
+            }
+            ToWrite::Heartbeat => (),
+        }
+    }
+}
+
 impl Connection {
     fn set_ttl(&mut self, ttl: u64) {
         let linger = Duration::from_secs(max(1, ttl / 10));
@@ -86,13 +143,40 @@ impl Connection {
         self.disconnect = time::interval_at(now + linger, linger);
     }
 
+    /// Replay the prefix unpublishes of failed batches. They go
+    /// before everything else so they can't remove paths published
+    /// below them since. Older resolvers drop the connection on
+    /// `UnpublishPrefix`, so each one is only tried once.
+    async fn replay_unpublish_prefixes(&mut self, con: &mut Channel) -> Result<()> {
+        if self.unpublish_prefixes.is_empty() {
+            return Ok(());
+        }
+        let prefixes = mem::take(&mut self.unpublish_prefixes);
+        for p in prefixes.iter() {
+            con.queue_send(&ToWrite::UnpublishPrefix(p.clone()))?
+        }
+        con.flush().await?;
+        for p in prefixes.iter() {
+            match receive_reply(con, &mut self.migrate).await {
+                Ok(FromWrite::Unpublished) => (),
+                Ok(r) => warn!("unexpected response to unpublish prefix {} {:?}", p, r),
+                Err(e) => {
+                    warn!("unpublish prefix {} failed, not retrying {}", p, e);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn republish(&mut self, con: &mut Channel, ttl_expired: bool) -> Result<()> {
+        self.replay_unpublish_prefixes(con).await?;
         let len = self.published.len();
         if len == 0 {
             info!("connected to resolver {:?} for write", self.resolver_addr);
             if self.degraded {
                 con.send_one(&ToWrite::Clear).await?;
-                match con.receive().await? {
+                match receive_reply(con, &mut self.migrate).await? {
                     FromWrite::Unpublished => {
                         self.degraded = false;
                     }
@@ -112,12 +196,13 @@ impl Connection {
             let mut has_clear = false;
             let mut to_remove: Vec<Option<Path>> = vec![];
             for msg in self.published.values() {
-                let reply = con.receive().await?;
+                let reply = receive_reply(con, &mut self.migrate).await?;
                 match msg {
                     ToWrite::Publish(_)
                     | ToWrite::PublishDefault(_)
                     | ToWrite::PublishWithFlags(_, _)
-                    | ToWrite::PublishDefaultWithFlags(_, _) => match reply {
+                    | ToWrite::PublishDefaultWithFlags(_, _)
+                    | ToWrite::PublishWithMetadata(_, _, _) => match reply {
                         FromWrite::Published => success += 1,
                         r => {
                             warn!("republish unexpected response to {:?} from resolver {:?}", msg, r)
@@ -142,7 +227,7 @@ impl Connection {
                             warn!("republish unexpected response to {:?} from resolver {:?}", msg, r)
                         }
                     },
-                    ToWrite::Heartbeat => (),
+                    ToWrite::UnpublishPrefix(_) | ToWrite::Heartbeat => (),
                 }
             }
             for p in to_remove {
@@ -199,6 +284,9 @@ impl Connection {
             let h = ClientHello::WriteOnly(ClientHelloWrite {
                 write_addr: self.write_addr,
                 auth,
+                local_endpoint: self.local_endpoint.clone(),
+                alt_addrs: self.alt_addrs.clone(),
+                heartbeat: true,
             });
             debug!("write_con connection established hello {:?}", h);
             h
@@ -382,6 +470,8 @@ impl Connection {
                 con.send_one(&ReadyForOwnershipCheck)
             )??;
         }
+        self.in_band_hb = r.heartbeat;
+        self.hb_outstanding = false;
         if !r.ttl_expired && !self.degraded {
             info!("connected to resolver {:?} for write", self.resolver_addr);
             self.con = Some(con);
@@ -399,11 +489,54 @@ impl Connection {
         warn!("write connection {:?} failed {}", self.resolver_addr, e);
     }
 
+    /// The resolver we are connected to is shutting down, move all
+    /// our publications to the alternate server it gave us. If the
+    /// alternate is another member of the cluster we already have a
+    /// connection to it, so return false to shut this one down
+    /// instead.
+    async fn migrate(&mut self, alternate: SocketAddr) -> bool {
+        if self.members.read().contains(&alternate) {
+            info!(
+                "write_con resolver {:?} is draining, {:?} is already a member",
+                self.resolver_addr, alternate
+            );
+            return false;
+        }
+        info!(
+            "write_con resolver {:?} is draining, moving to {:?}",
+            self.resolver_addr, alternate
+        );
+        self.con = None;
+        self.security_context = None;
+        self.secrets.write().remove(&self.resolver_addr);
+        self.resolver_addr = alternate;
+        if let Err(e) = self.connect().await {
+            self.handle_failed_connect(e);
+            self.degraded = true;
+        }
+        true
+    }
+
+    async fn receive_idle(con: &mut Option<Channel>) -> Result<FromWrite> {
+        match con {
+            Some(c) => c.receive().await,
+            None => future::pending().await,
+        }
+    }
+
     async fn send_heartbeat(&mut self) {
         for _ in 0..3 {
+            if self.hb_outstanding {
+                info!("write_con heartbeat was not acknowledged, reconnecting");
+                self.hb_outstanding = false;
+                self.con = None;
+            }
             match self.con {
                 Some(ref mut c) => match c.send_one(&ToWrite::Heartbeat).await {
-                    Ok(()) => break,
+                    Ok(()) => {
+                        self.hb_outstanding = self.in_band_hb;
+                        break;
+                    }
                     Err(e) => {
                         info!("write_con heartbeat send error {}", e);
                         self.con = None;
@@ -434,23 +567,7 @@ impl Connection {
                 }
             },
         };
-        for (_, tx) in tx.batch.iter() {
-            match tx {
-                ToWrite::Publish(p)
-                | ToWrite::PublishDefault(p)
-                | ToWrite::PublishWithFlags(p, _)
-                | ToWrite::PublishDefaultWithFlags(p, _) => {
-                    self.published.insert(p.clone(), tx.clone());
-                }
-                ToWrite::Unpublish(p) | ToWrite::UnpublishDefault(p) => {
-                    self.published.remove(p);
-                }
-                ToWrite::Clear => {
-                    self.published.clear();
-                }
-                ToWrite::Heartbeat => (),
-            }
-        }
+        track_published(&mut self.published, &tx.batch);
         let timeout = max(HELLO_TO, Duration::from_micros(tx.batch.len() as u64 * 100));
         for (_, m) in &*tx.batch {
             c.queue_send(m)?;
@@ -458,7 +575,12 @@ impl Connection {
         c.flush_timeout(timeout).await?;
         let mut rx_batch = RAWFROMWRITEPOOL.take();
         while rx_batch.len() < tx.batch.len() {
-            time::timeout(timeout, c.receive_batch(&mut *rx_batch)).await??
+            let f = c.receive_batch_fn(|m| match m {
+                FromWrite::Migrate(addr) => self.migrate = Some(addr),
+                FromWrite::Heartbeat => self.hb_outstanding = false,
+                m => rx_batch.push(m),
+            });
+            time::timeout(timeout, f).await??
         }
         for ((_, tx), rx) in tx.batch.iter().zip(rx_batch.iter()) {
             match tx {
@@ -485,36 +607,53 @@ impl Connection {
 
     async fn start(
         mut receiver: broadcast::Receiver<Arc<ToCon>>,
+        mut stop: oneshot::Receiver<()>,
+        published: IndexMap<Path, ToWrite, FxBuildHasher>,
         resolver_addr: SocketAddr,
         resolver_auth: Auth,
         write_addr: SocketAddr,
+        local_endpoint: Option<ArcStr>,
+        alt_addrs: SmallVec<[SocketAddr; 2]>,
         desired_auth: DesiredAuth,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
+        members: Arc<RwLock<FxHashSet<SocketAddr>>>,
         tls: Option<tls::CachedConnector>,
     ) {
         let now = Instant::now();
+        // a server that joined after we started publishing needs to
+        // hear about everything right away
+        let degraded = !published.is_empty();
+        let first_hb = if degraded { now } else { now + HB };
         let mut t = Self {
             resolver_addr,
             resolver_auth,
             write_addr,
-            published: IndexMap::default(),
+            local_endpoint,
+            alt_addrs,
+            published,
+            unpublish_prefixes: IndexSet::default(),
             secrets,
+            members,
             desired_auth,
             security_context: None,
             tls,
             con: None,
-            degraded: false,
+            degraded,
             active: false,
-            heartbeat: time::interval_at(now + HB, HB),
+            migrate: None,
+            in_band_hb: false,
+            hb_outstanding: false,
+            heartbeat: time::interval_at(first_hb, HB),
             disconnect: time::interval_at(now + LINGER, LINGER),
         };
         loop {
             #[rustfmt::skip]
             select_biased! {
+                _ = stop => break,
                 _ = t.disconnect.tick().fuse() => {
                     if t.active {
                         t.active = false;
-                    } else if t.con.is_some() {
+                    } else if t.con.is_some() && !t.in_band_hb {
                         info!("write_con dropping inactive connection");
                         t.con = None;
                     }
@@ -526,6 +665,15 @@ impl Connection {
                         t.send_heartbeat().await;
                     }
                 },
+                m = Self::receive_idle(&mut t.con).fuse() => match m {
+                    Ok(FromWrite::Migrate(addr)) => t.migrate = Some(addr),
+                    Ok(FromWrite::Heartbeat) => t.hb_outstanding = false,
+                    Ok(m) => warn!("write_con unexpected message {:?}", m),
+                    Err(e) => {
+                        info!("write_con connection closed {}", e);
+                        t.con = None;
+                    }
+                },
                 batch = receiver.recv().fuse() => match batch {
 		    Err(RecvError::Closed) => break,
 		    Err(RecvError::Lagged(_)) => {
@@ -542,11 +690,17 @@ impl Connection {
 				    ToWrite::Publish(_)
 					| ToWrite::PublishDefault(_)
 					| ToWrite::PublishWithFlags(_, _)
-					| ToWrite::PublishDefaultWithFlags(_, _) => (),
+					| ToWrite::PublishDefaultWithFlags(_, _)
+					| ToWrite::PublishWithMetadata(_, _, _) => (),
 				    ToWrite::Unpublish(p) | ToWrite::UnpublishDefault(p) => {
 					t.published.insert(p.clone(), tx.clone());
 				    }
+				    ToWrite::UnpublishPrefix(p) => {
+					t.published.retain(|k, _| !Path::is_parent(p, k));
+					t.unpublish_prefixes.insert(p.clone());
+				    }
 				    ToWrite::Clear => {
+					t.unpublish_prefixes.clear();
 					t.published.insert(Path::from(""), ToWrite::Clear);
 				    },
 				    ToWrite::Heartbeat => (),
@@ -557,54 +711,134 @@ impl Connection {
                     }
                 }
             }
+            if let Some(alternate) = t.migrate.take() {
+                if !t.migrate(alternate).await {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The write connections to each member of the resolver cluster
+struct Members {
+    sender: broadcast::Sender<Arc<ToCon>>,
+    running: FxHashMap<SocketAddr, oneshot::Sender<()>>,
+    /// the addresses in `running`, shared with the connections so
+    /// they can tell when a migration target is already a member
+    addrs: Arc<RwLock<FxHashSet<SocketAddr>>>,
+    desired_auth: DesiredAuth,
+    secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
+    write_addr: SocketAddr,
+    local_endpoint: Option<ArcStr>,
+    alt_addrs: SmallVec<[SocketAddr; 2]>,
+    tls: Option<tls::CachedConnector>,
+}
+
+impl Members {
+    /// Start a connection to every member of `resolver` we aren't
+    /// already connected to, and stop the connections to servers
+    /// that are no longer members. New connections will republish
+    /// everything in `published`.
+    fn update(
+        &mut self,
+        resolver: &Referral,
+        published: &IndexMap<Path, ToWrite, FxBuildHasher>,
+    ) {
+        self.running.retain(|addr, _| resolver.addrs.iter().any(|(a, _)| a == addr));
+        *self.addrs.write() = resolver.addrs.iter().map(|(a, _)| *a).collect();
+        for (addr, auth) in resolver.addrs.iter() {
+            if self.running.contains_key(addr) {
+                continue;
+            }
+            let (stop_tx, stop_rx) = oneshot::channel();
+            self.running.insert(*addr, stop_tx);
+            let addr = *addr;
+            let auth = auth.clone();
+            let desired_auth = self.desired_auth.clone();
+            let secrets = self.secrets.clone();
+            let members = self.addrs.clone();
+            let tls = self.tls.clone();
+            let write_addr = self.write_addr;
+            let local_endpoint = self.local_endpoint.clone();
+            let alt_addrs = self.alt_addrs.clone();
+            let receiver = self.sender.subscribe();
+            let published = published.clone();
+            task::spawn(async move {
+                Connection::start(
+                    receiver,
+                    stop_rx,
+                    published,
+                    addr,
+                    auth,
+                    write_addr,
+                    local_endpoint,
+                    alt_addrs,
+                    desired_auth,
+                    secrets,
+                    members,
+                    tls,
+                )
+                .await;
+                info!("write task for {:?} exited", addr);
+            });
         }
     }
 }
 
 async fn write_mgr(
     mut receiver: mpsc::UnboundedReceiver<Batch>,
+    mut updates: mpsc::UnboundedReceiver<Arc<Referral>>,
     resolver: Arc<Referral>,
     desired_auth: DesiredAuth,
     secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
     write_addr: SocketAddr,
+    local_endpoint: Option<ArcStr>,
+    alt_addrs: SmallVec<[SocketAddr; 2]>,
     tls: Option<tls::CachedConnector>,
 ) -> Result<()> {
     let (sender, _) = broadcast::channel(100);
-    for (addr, auth) in resolver.addrs.iter() {
-        let addr = *addr;
-        let auth = auth.clone();
-        let desired_auth = desired_auth.clone();
-        let secrets = secrets.clone();
-        let tls = tls.clone();
-        let receiver = sender.subscribe();
-        task::spawn(async move {
-            Connection::start(
-                receiver,
-                addr,
-                auth,
-                write_addr,
-                desired_auth,
-                secrets,
-                tls,
-            )
-            .await;
-            info!("write task for {:?} exited", addr);
-        });
-    }
-    while let Some((batch, reply)) = receiver.next().await {
-        let mut replies = vec![];
-        let mut waiters = vec![];
-        for _ in resolver.addrs.iter() {
-            let (tx, rx) = oneshot::channel();
-            replies.push(tx);
-            waiters.push(rx);
-        }
-        let tx_batch = Arc::new(ToCon { batch, replies: Mutex::new(replies) });
-        let _ = sender.send(tx_batch);
-        match select_ok(waiters).await {
-            Err(e) => warn!("write_mgr: write failed on all writers {}", e),
-            Ok((rx_batch, _)) => {
-                let _ = reply.send(rx_batch);
+    let mut published: IndexMap<Path, ToWrite, FxBuildHasher> = IndexMap::default();
+    let mut members = Members {
+        sender,
+        running: FxHashMap::default(),
+        addrs: Arc::new(RwLock::new(FxHashSet::default())),
+        desired_auth,
+        secrets,
+        write_addr,
+        local_endpoint,
+        alt_addrs,
+        tls,
+    };
+    members.update(&resolver, &published);
+    loop {
+        select_biased! {
+            r = updates.select_next_some() => members.update(&r, &published),
+            m = receiver.next() => match m {
+                None => break,
+                Some((batch, reply)) => {
+                    if members.running.is_empty() {
+                        warn!("write_mgr: no resolver servers");
+                        continue;
+                    }
+                    track_published(&mut published, &batch);
+                    let mut replies = vec![];
+                    let mut waiters = vec![];
+                    for _ in 0..members.running.len() {
+                        let (tx, rx) = oneshot::channel();
+                        replies.push(tx);
+                        waiters.push(rx);
+                    }
+                    let replies = Mutex::new(replies);
+                    let tx_batch = Arc::new(ToCon { batch, replies });
+                    let _ = members.sender.send(tx_batch);
+                    match select_ok(waiters).await {
+                        Err(e) => warn!("write_mgr: write failed on all writers {}", e),
+                        Ok((rx_batch, _)) => {
+                            let _ = reply.send(rx_batch);
+                        }
+                    }
+                }
             }
         }
     }
@@ -612,23 +846,45 @@ async fn write_mgr(
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct WriteClient(mpsc::UnboundedSender<Batch>);
+pub(crate) struct WriteClient {
+    batches: mpsc::UnboundedSender<Batch>,
+    members: mpsc::UnboundedSender<Arc<Referral>>,
+}
 
 impl WriteClient {
     pub(crate) fn new(
         resolver: Arc<Referral>,
         desired_auth: DesiredAuth,
         write_addr: SocketAddr,
+        local_endpoint: Option<ArcStr>,
+        alt_addrs: SmallVec<[SocketAddr; 2]>,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
     ) -> Self {
         let (to_tx, to_rx) = mpsc::unbounded();
+        let (members_tx, members_rx) = mpsc::unbounded();
         task::spawn(async move {
-            let r =
-                write_mgr(to_rx, resolver, desired_auth, secrets, write_addr, tls).await;
+            let r = write_mgr(
+                to_rx,
+                members_rx,
+                resolver,
+                desired_auth,
+                secrets,
+                write_addr,
+                local_endpoint,
+                alt_addrs,
+                tls,
+            )
+            .await;
             info!("write manager exited {:?}", r);
         });
-        Self(to_tx)
+        Self { batches: to_tx, members: members_tx }
+    }
+
+    /// Change the members of the resolver cluster. Servers that
+    /// joined are sent everything we've published.
+    pub(crate) fn set_members(&mut self, resolver: Arc<Referral>) {
+        let _ = self.members.unbounded_send(resolver);
     }
 
     pub(crate) fn send(
@@ -636,7 +892,7 @@ impl WriteClient {
         batch: Pooled<Vec<(usize, ToWrite)>>,
     ) -> ResponseChan<FromWrite> {
         let (tx, rx) = oneshot::channel();
-        let _ = self.0.unbounded_send((batch, tx));
+        let _ = self.batches.unbounded_send((batch, tx));
         rx
     }
 }
@@ -28,6 +28,7 @@ bitflags! {
         const LIST             = 0x08;
         const PUBLISH          = 0x10;
         const PUBLISH_DEFAULT  = 0x20;
+        const ADMIN            = 0x40;
     }
 }
 
@@ -60,9 +61,12 @@ impl TryFrom<&str> for Permissions {
                 'd' => {
                     p |= Permissions::PUBLISH_DEFAULT;
                 }
+                'a' => {
+                    p |= Permissions::ADMIN;
+                }
                 c => {
                     return Err(anyhow!(
-                        "unrecognized permission bit {}, valid bits are !swlpd",
+                        "unrecognized permission bit {}, valid bits are !swlpda",
                         c
                     ))
                 }
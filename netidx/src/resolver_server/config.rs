@@ -2,7 +2,11 @@ use self::file::IdMapType;
 use crate::{
     chars::Chars,
     path::Path,
-    protocol::resolver::{self, Referral},
+    pool::Pooled,
+    protocol::{
+        resolver::{self, Metadata, Referral},
+        value::Value,
+    },
     tls, utils,
 };
 use anyhow::Result;
@@ -18,7 +22,7 @@ use std::{
     default::Default,
     fs::read_to_string,
     net::{IpAddr, SocketAddr},
-    path::Path as FsPath,
+    path::{Path as FsPath, PathBuf},
     time::Duration,
 };
 
@@ -103,7 +107,7 @@ impl Default for PMap {
 
 /// The on disk format, encoded as JSON
 pub mod file {
-    use super::{super::config::check_addrs, resolver, Chars, PMap};
+    use super::{super::config::check_addrs, resolver, Chars, PMap, Value};
     use crate::{path::Path, pool::Pooled};
     use anyhow::Result;
     use std::{
@@ -212,6 +216,56 @@ pub mod file {
         3600
     }
 
+    fn default_ban_secs() -> u64 {
+        60
+    }
+
+    /// Per client ip limits, a rate of 0 means unlimited. A client
+    /// that exceeds any limit is disconnected and banned for
+    /// `ban_secs`.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct RateLimits {
+        #[serde(default)]
+        pub hellos_per_sec: u32,
+        #[serde(default)]
+        pub reads_per_sec: u32,
+        #[serde(default)]
+        pub publishes_per_sec: u32,
+        #[serde(default)]
+        pub max_paths_per_writer: usize,
+        #[serde(default = "default_ban_secs")]
+        pub ban_secs: u64,
+    }
+
+    impl Default for RateLimits {
+        fn default() -> Self {
+            RateLimits {
+                hellos_per_sec: 0,
+                reads_per_sec: 0,
+                publishes_per_sec: 0,
+                max_paths_per_writer: 0,
+                ban_secs: default_ban_secs(),
+            }
+        }
+    }
+
+    /// Namespace quotas enforced when a path is published, 0 means
+    /// unlimited. Publishes over quota are refused with an error,
+    /// but the writer stays connected. The path counts don't include
+    /// default publishers, and the per user quota doesn't apply to
+    /// anonymous users, they are only covered by the per ip quota.
+    #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Quotas {
+        #[serde(default)]
+        pub max_paths_per_user: usize,
+        #[serde(default)]
+        pub max_paths_per_ip: usize,
+        #[serde(default)]
+        pub max_depth: usize,
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[serde(deny_unknown_fields)]
     pub struct MemberServer {
@@ -230,6 +284,31 @@ pub mod file {
         pub id_map_type: IdMapType,
         #[serde(default = "default_id_map_timeout")]
         pub id_map_timeout: u64,
+        #[serde(default)]
+        pub rate_limits: RateLimits,
+        #[serde(default)]
+        pub quotas: Quotas,
+        /// The number of shards to split the store into, each one is
+        /// run by its own task. It will be rounded up to a power of
+        /// two. 0, the default, means one shard per cpu.
+        #[serde(default)]
+        pub shards: usize,
+        /// More addresses to listen on that only accept read only
+        /// clients, e.g. browsers and discovery tools. They are
+        /// served from a replica of the store, so heavy read traffic
+        /// doesn't slow down publishers.
+        #[serde(default)]
+        pub read_only_addrs: Vec<SocketAddr>,
+    }
+
+    /// A structural entry in the namespace, it is listed, along
+    /// with it's metadata, even when nothing is published there.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Placeholder {
+        pub path: String,
+        #[serde(default)]
+        pub metadata: Vec<(String, Value)>,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -239,6 +318,8 @@ pub mod file {
         pub parent: Option<Referral>,
         pub member_servers: Vec<MemberServer>,
         pub perms: PMap,
+        #[serde(default)]
+        pub placeholders: Vec<Placeholder>,
     }
 }
 
@@ -262,14 +343,20 @@ pub struct MemberServer {
     #[allow(dead_code)]
     pub(crate) id_map: IdMap,
     pub(crate) id_map_timeout: chrono::Duration,
+    pub(super) rate_limits: file::RateLimits,
+    pub(super) quotas: file::Quotas,
+    pub(super) shards: usize,
+    pub(super) read_only_addrs: Vec<SocketAddr>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub(super) parent: Option<Referral>,
     pub(super) children: BTreeMap<Path, Referral>,
+    pub(super) placeholders: BTreeMap<Path, Metadata>,
     pub(super) perms: PMap,
     pub member_servers: Vec<MemberServer>,
+    pub(super) source: Option<PathBuf>,
 }
 
 impl Config {
@@ -314,6 +401,31 @@ impl Config {
             }
             children
         };
+        let placeholders = {
+            let root = parent.as_ref().map(|r| r.path.as_ref()).unwrap_or("/");
+            let mut placeholders = BTreeMap::new();
+            for p in cfg.placeholders {
+                let path = Path::from(p.path);
+                if !Path::is_absolute(&path) {
+                    bail!("placeholder paths must be absolute {}", path)
+                }
+                if !Path::is_parent(root, &path) || &*path == root {
+                    bail!("placeholder paths must be under the root path {}", path)
+                }
+                if children.keys().any(|c| Path::is_parent(c, &path)) {
+                    bail!("placeholder {} is inside a referral", path)
+                }
+                let meta = p
+                    .metadata
+                    .into_iter()
+                    .map(|(k, v)| (Chars::from(k), v))
+                    .collect::<Vec<_>>();
+                if placeholders.insert(path.clone(), Pooled::orphan(meta)).is_some() {
+                    bail!("duplicate placeholder {}", path)
+                }
+            }
+            placeholders
+        };
         let member_servers = cfg
             .member_servers
             .into_iter()
@@ -390,6 +502,9 @@ impl Config {
                 if m.hello_timeout == 0 {
                     bail!("hello_timeout must be positive")
                 }
+                if m.read_only_addrs.iter().any(|a| a.port() != 0 && a == &m.addr) {
+                    bail!("read_only_addrs must not include addr")
+                }
                 Ok(MemberServer {
                     addr: m.addr,
                     bind_addr: m.bind_addr,
@@ -400,18 +515,44 @@ impl Config {
                     writer_ttl: Duration::from_secs(m.writer_ttl),
                     id_map,
 		    id_map_timeout: chrono::Duration::seconds(m.id_map_timeout as i64),
+                    rate_limits: m.rate_limits,
+                    quotas: m.quotas,
+                    shards: match m.shards {
+                        0 => num_cpus::get(),
+                        n => n,
+                    },
+                    read_only_addrs: m.read_only_addrs,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
-        Ok(Config { parent, children, perms: cfg.perms, member_servers })
+        Ok(Config {
+            parent,
+            children,
+            placeholders,
+            perms: cfg.perms,
+            member_servers,
+            source: None,
+        })
     }
 
-    /// Load the cluster config from the specified file.
+    /// Load the cluster config from the specified file. A server
+    /// started with a config loaded from a file will reload it from
+    /// the same file when asked to by an admin.
     pub fn load<P: AsRef<FsPath>>(file: P) -> Result<Config> {
-        Config::parse(&read_to_string(file)?)
+        let mut cfg = Config::parse(&read_to_string(file.as_ref())?)?;
+        cfg.source = Some(file.as_ref().to_path_buf());
+        Ok(cfg)
     }
 
     pub(super) fn root(&self) -> &str {
         self.parent.as_ref().map(|r| r.path.as_ref()).unwrap_or("/")
     }
+
+    /// true if `other` has the same root, child referral paths, and
+    /// placeholders
+    pub(super) fn same_namespace(&self, other: &Config) -> bool {
+        self.root() == other.root()
+            && self.children.keys().eq(other.children.keys())
+            && self.placeholders == other.placeholders
+    }
 }
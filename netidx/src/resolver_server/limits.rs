@@ -0,0 +1,308 @@
+use super::{
+    auth::{Entity, UserInfo, ANONYMOUS},
+    config::file::{Quotas as QuotaLimits, RateLimits},
+};
+use crate::path::Path;
+use anyhow::Result;
+use fxhash::FxHashMap;
+use log::warn;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// How long an idle client ip is remembered
+const IDLE: Duration = Duration::from_secs(60);
+
+/// Counters describing the activity of the rate limiter since the
+/// server started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitStats {
+    /// The number of requests that were refused because they
+    /// exceeded a limit
+    pub throttled: u64,
+    /// The number of times a client ip was banned
+    pub bans: u64,
+    /// The number of client ips that are currently banned
+    pub banned: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Limit {
+    Hello,
+    Read,
+    Publish,
+}
+
+impl Limit {
+    fn name(&self) -> &'static str {
+        match self {
+            Limit::Hello => "hello",
+            Limit::Read => "read",
+            Limit::Publish => "publish",
+        }
+    }
+}
+
+/// A token bucket holding up to one second worth of tokens. Batches
+/// larger than the bucket are allowed to overdraw it, after which
+/// the client must wait until it is paid back.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(now: Instant) -> Self {
+        Bucket { tokens: f64::MAX, last: now }
+    }
+
+    fn take(&mut self, now: Instant, rate: u32, n: usize) -> bool {
+        if rate == 0 {
+            return true;
+        }
+        let rate = rate as f64;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last = now;
+        if self.tokens < 0. {
+            false
+        } else {
+            self.tokens -= n as f64;
+            true
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    hello: Bucket,
+    read: Bucket,
+    publish: Bucket,
+    banned_until: Option<Instant>,
+    last: Instant,
+}
+
+impl Client {
+    fn new(now: Instant) -> Self {
+        Client {
+            hello: Bucket::new(now),
+            read: Bucket::new(now),
+            publish: Bucket::new(now),
+            banned_until: None,
+            last: now,
+        }
+    }
+
+    fn banned(&self, now: Instant) -> bool {
+        self.banned_until.map(|t| now < t).unwrap_or(false)
+    }
+}
+
+/// Per client ip rate limits and temporary bans
+#[derive(Debug)]
+pub(super) struct Limiter {
+    limits: RateLimits,
+    clients: Mutex<(Instant, FxHashMap<IpAddr, Client>)>,
+    throttled: AtomicU64,
+    bans: AtomicU64,
+}
+
+impl Limiter {
+    pub(super) fn new(limits: RateLimits) -> Self {
+        Limiter {
+            limits,
+            clients: Mutex::new((Instant::now(), FxHashMap::default())),
+            throttled: AtomicU64::new(0),
+            bans: AtomicU64::new(0),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        let l = &self.limits;
+        l.hellos_per_sec > 0
+            || l.reads_per_sec > 0
+            || l.publishes_per_sec > 0
+            || l.max_paths_per_writer > 0
+    }
+
+    fn ban(&self, ip: IpAddr, cl: &mut Client, now: Instant, why: &str) -> Result<()> {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        if self.limits.ban_secs > 0 {
+            self.bans.fetch_add(1, Ordering::Relaxed);
+            cl.banned_until = Some(now + Duration::from_secs(self.limits.ban_secs));
+            warn!("banning {} for {}s, {}", ip, self.limits.ban_secs, why);
+        }
+        bail!("{} {}", ip, why)
+    }
+
+    fn with_client<R, F>(&self, ip: IpAddr, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Client, Instant) -> Result<R>,
+    {
+        let now = Instant::now();
+        let mut inner = self.clients.lock();
+        let (last_gc, clients) = &mut *inner;
+        if now.saturating_duration_since(*last_gc) > IDLE {
+            *last_gc = now;
+            clients.retain(|_, cl| {
+                cl.banned(now) || now.saturating_duration_since(cl.last) < IDLE
+            });
+        }
+        let cl = clients.entry(ip).or_insert_with(|| Client::new(now));
+        cl.last = now;
+        if cl.banned(now) {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            bail!("{} is temporarily banned", ip)
+        }
+        f(cl, now)
+    }
+
+    /// Account for `n` requests of kind `limit` from `ip`, fail if
+    /// the ip is banned, or if the request exceeds the limit, in
+    /// which case the ip is banned.
+    pub(super) fn check(&self, ip: IpAddr, limit: Limit, n: usize) -> Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        self.with_client(ip, |cl, now| {
+            let (bucket, rate) = match limit {
+                Limit::Hello => (&mut cl.hello, self.limits.hellos_per_sec),
+                Limit::Read => (&mut cl.read, self.limits.reads_per_sec),
+                Limit::Publish => (&mut cl.publish, self.limits.publishes_per_sec),
+            };
+            if bucket.take(now, rate, n) {
+                Ok(())
+            } else {
+                self.ban(ip, cl, now, &format!("exceeded the {} rate", limit.name()))
+            }
+        })
+    }
+
+    /// Check that a writer from `ip` with `n` published paths is
+    /// within the path limit, ban the ip if it isn't.
+    pub(super) fn check_paths(&self, ip: IpAddr, n: usize) -> Result<()> {
+        let max = self.limits.max_paths_per_writer;
+        if max == 0 || n <= max {
+            return Ok(());
+        }
+        self.with_client(ip, |cl, now| {
+            self.ban(ip, cl, now, &format!("published more than {} paths", max))
+        })
+    }
+
+    pub(super) fn stats(&self) -> LimitStats {
+        let now = Instant::now();
+        let banned = self.clients.lock().1.values().filter(|cl| cl.banned(now)).count();
+        LimitStats {
+            throttled: self.throttled.load(Ordering::Relaxed),
+            bans: self.bans.load(Ordering::Relaxed),
+            banned,
+        }
+    }
+}
+
+/// The number of paths published by each user and each publisher
+/// ip, shared by all the shards of the store.
+#[derive(Debug)]
+pub(super) struct Quotas {
+    limits: RwLock<QuotaLimits>,
+    used: Mutex<(FxHashMap<Entity, usize>, FxHashMap<IpAddr, usize>)>,
+}
+
+impl Quotas {
+    pub(super) fn new(limits: QuotaLimits) -> Self {
+        Quotas {
+            limits: RwLock::new(limits),
+            used: Mutex::new((FxHashMap::default(), FxHashMap::default())),
+        }
+    }
+
+    fn enabled(l: &QuotaLimits) -> bool {
+        l.max_paths_per_user > 0 || l.max_paths_per_ip > 0
+    }
+
+    /// Replace the limits. Paths aren't counted while the path
+    /// quotas are disabled, so paths published before they are
+    /// enabled don't count against them.
+    pub(super) fn set_limits(&self, limits: QuotaLimits) {
+        let mut l = self.limits.write();
+        if !Self::enabled(&limits) {
+            let mut inner = self.used.lock();
+            inner.0.clear();
+            inner.1.clear();
+        }
+        *l = limits;
+    }
+
+    fn user(uifo: &UserInfo) -> Option<Entity> {
+        if uifo.id == ANONYMOUS.id {
+            None
+        } else {
+            Some(uifo.id)
+        }
+    }
+
+    /// Check that `path` is not deeper than the depth quota
+    pub(super) fn check_depth(&self, path: &Path) -> Result<()> {
+        let max = self.limits.read().max_depth;
+        if max > 0 && Path::levels(path) > max {
+            bail!("quota exceeded, paths may be at most {} levels deep", max)
+        }
+        Ok(())
+    }
+
+    /// Account for one more path published by `uifo` from `ip`, fail
+    /// without accounting for it if that would exceed either quota.
+    pub(super) fn acquire(&self, uifo: &UserInfo, ip: IpAddr) -> Result<()> {
+        let l = self.limits.read();
+        if !Self::enabled(&l) {
+            return Ok(());
+        }
+        let mut inner = self.used.lock();
+        let (users, ips) = &mut *inner;
+        if let Some(user) = Self::user(uifo) {
+            let n = users.get(&user).copied().unwrap_or(0);
+            if l.max_paths_per_user > 0 && n >= l.max_paths_per_user {
+                let max = l.max_paths_per_user;
+                bail!("quota exceeded, user may publish at most {} paths", max)
+            }
+        }
+        let n = ips.get(&ip).copied().unwrap_or(0);
+        if l.max_paths_per_ip > 0 && n >= l.max_paths_per_ip {
+            let max = l.max_paths_per_ip;
+            bail!("quota exceeded, {} may publish at most {} paths", ip, max)
+        }
+        *ips.entry(ip).or_insert(0) += 1;
+        if let Some(user) = Self::user(uifo) {
+            *users.entry(user).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    /// Account for `n` paths published by `uifo` from `ip` that were
+    /// unpublished.
+    pub(super) fn release(&self, uifo: &UserInfo, ip: IpAddr, n: usize) {
+        if n == 0 || !Self::enabled(&self.limits.read()) {
+            return;
+        }
+        fn sub<K: std::hash::Hash + Eq>(m: &mut FxHashMap<K, usize>, k: K, n: usize) {
+            if let Some(c) = m.get_mut(&k) {
+                *c = c.saturating_sub(n);
+                if *c == 0 {
+                    m.remove(&k);
+                }
+            }
+        }
+        let mut inner = self.used.lock();
+        let (users, ips) = &mut *inner;
+        sub(ips, ip, n);
+        if let Some(user) = Self::user(uifo) {
+            sub(users, user, n);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 pub(crate) mod auth;
 pub mod config;
+mod limits;
 pub(crate) mod secctx;
 mod shard_store;
 mod store;
@@ -10,23 +11,31 @@ use crate::{
     channel::{self, Channel, K5CtxWrap},
     chars::Chars,
     pack::Pack,
+    path::Path,
     pool::{Pool, Pooled},
     protocol::{
         publisher,
         resolver::{
-            AuthChallenge, AuthRead, AuthWrite, ClientHello, ClientHelloWrite, FromWrite,
-            HashMethod, Publisher, PublisherId, ReadyForOwnershipCheck, Secret,
-            ServerHelloWrite, ToRead, ToWrite,
+            AuthChallenge, AuthRead, AuthWrite, ClientHello, ClientHelloWrite,
+            ClientStats, FromAdmin, FromRead, FromWrite, HashMethod, Publisher,
+            PublisherId, ReadyForOwnershipCheck, Secret, ServerHelloWrite, ToAdmin,
+            ToRead, ToWrite,
         },
     },
     tls, utils,
 };
 use anyhow::Result;
-use auth::{UserInfo, ANONYMOUS};
+use auth::{Permissions, UserInfo, ANONYMOUS};
 use config::{Config, MemberServer};
 use cross_krb5::{AcceptFlags, K5ServerCtx, ServerCtx, Step};
-use futures::{channel::oneshot, prelude::*, select_biased};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    select_biased,
+};
 use fxhash::FxHashMap;
+pub use limits::LimitStats;
+use limits::{Limit, Limiter, Quotas};
 use log::{debug, error, info, trace, warn};
 use netidx_core::{pack::BoundedBytes, utils::make_sha3_token};
 use parking_lot::Mutex as SyncMutex;
@@ -34,17 +43,18 @@ use rand::{thread_rng, Rng};
 use secctx::{K5SecData, LocalSecData, SecCtx, TlsSecData};
 use shard_store::Store;
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeSet, HashMap},
     fmt::Debug,
-    mem,
-    net::SocketAddr,
+    iter, mem,
+    net::{IpAddr, SocketAddr},
     ops::Deref,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream},
-    sync::{Mutex, RwLock},
+    sync::{broadcast, watch, Mutex, RwLock},
     task,
     time::{self, Instant},
 };
@@ -52,23 +62,59 @@ use tokio::{
 lazy_static! {
     static ref WRITE_BATCHES: Pool<Vec<ToWrite>> = Pool::new(100, 10_000);
     static ref READ_BATCHES: Pool<Vec<ToRead>> = Pool::new(100, 10_000);
+    static ref PUBLISHERS: Pool<Vec<Publisher>> = Pool::new(10, 10_000);
+    static ref CLIENT_STATS: Pool<Vec<ClientStats>> = Pool::new(10, 10_000);
 }
 
 atomic_id!(CId);
 
-struct CTracker(SyncMutex<HashSet<CId>>);
+struct CTracker(SyncMutex<FxHashMap<CId, ClientStats>>);
 
 impl CTracker {
     fn new() -> Self {
-        CTracker(SyncMutex::new(HashSet::new()))
+        CTracker(SyncMutex::new(HashMap::default()))
     }
 
-    fn open(&self) -> CId {
+    fn open(&self, addr: SocketAddr) -> CId {
         let id = CId::new();
-        self.0.lock().insert(id);
+        let connected = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let stats = ClientStats {
+            addr,
+            user: None,
+            publisher: false,
+            connected,
+            batches: 0,
+            messages: 0,
+        };
+        self.0.lock().insert(id, stats);
         id
     }
 
+    /// Record who is connected on `id` once they have authenticated
+    fn identify(&self, id: CId, uifo: &UserInfo, publisher: bool) {
+        if let Some(stats) = self.0.lock().get_mut(&id) {
+            stats.user = uifo.user_info.as_ref().map(|u| u.name.clone());
+            stats.publisher = publisher;
+        }
+    }
+
+    /// Account for a batch of `n` messages received on `id`
+    fn received(&self, id: CId, n: usize) {
+        if let Some(stats) = self.0.lock().get_mut(&id) {
+            stats.batches += 1;
+            stats.messages += n as u64;
+        }
+    }
+
+    fn stats(&self) -> Pooled<Vec<ClientStats>> {
+        let mut stats = CLIENT_STATS.take();
+        stats.extend(self.0.lock().values().cloned());
+        stats
+    }
+
     fn close(&self, id: CId) {
         self.0.lock().remove(&id);
     }
@@ -80,7 +126,14 @@ impl CTracker {
 
 enum ClientInfo {
     CleaningUp(Vec<oneshot::Sender<()>>),
-    Running { publisher: Arc<Publisher>, stop: oneshot::Sender<()> },
+    /// `uifo` is the user that connected the publisher, its quota
+    /// is charged for everything published and released when the
+    /// publisher is cleared, whoever clears it.
+    Running {
+        publisher: Arc<Publisher>,
+        uifo: Arc<UserInfo>,
+        stop: oneshot::Sender<()>,
+    },
 }
 
 struct ClinfosInner(FxHashMap<SocketAddr, ClientInfo>);
@@ -113,26 +166,25 @@ impl ClinfosInner {
         }
     }
 
-    async fn remove(
-        &mut self,
-        ctx: &Ctx,
-        publisher: &Arc<Publisher>,
-        uifo: &Arc<UserInfo>,
-    ) -> Result<()> {
+    async fn remove(&mut self, ctx: &Ctx, publisher: &Arc<Publisher>) -> Result<()> {
         let cleanup = self
             .wait_running(&publisher.addr, |e| async {
                 match e {
-                    Entry::Vacant(_) => false,
+                    Entry::Vacant(_) => None,
                     Entry::Occupied(mut e) => {
-                        *e.get_mut() = ClientInfo::CleaningUp(Vec::new());
+                        let ifo =
+                            mem::replace(e.get_mut(), ClientInfo::CleaningUp(Vec::new()));
                         ctx.secctx.remove(&publisher.id).await;
-                        true
+                        match ifo {
+                            ClientInfo::Running { uifo, .. } => Some(uifo),
+                            ClientInfo::CleaningUp(_) => unreachable!(),
+                        }
                     }
                 }
             })
             .await;
-        if cleanup {
-            ctx.store.handle_clear(uifo.clone(), publisher.clone()).await?;
+        if let Some(uifo) = cleanup {
+            ctx.store.handle_clear(uifo, publisher.clone()).await?;
             self.0.remove(&publisher.addr);
         }
         Ok(())
@@ -145,7 +197,7 @@ impl ClinfosInner {
         hello: &ClientHelloWrite,
     ) -> Result<(Arc<Publisher>, bool, oneshot::Receiver<()>)> {
         enum R {
-            ClearClient(Arc<Publisher>),
+            ClearClient(Arc<Publisher>, Arc<UserInfo>),
             Finished(Arc<Publisher>, bool, oneshot::Receiver<()>),
         }
         loop {
@@ -160,10 +212,21 @@ impl ClinfosInner {
                                 hash_method: HashMethod::Sha3_512,
                                 target_auth: hello.auth.clone().try_into()?,
                                 user_info: None,
+                                local_endpoint: hello.local_endpoint.clone(),
+                                addrs: iter::once(hello.write_addr)
+                                    .chain(
+                                        hello
+                                            .alt_addrs
+                                            .iter()
+                                            .copied()
+                                            .filter(|a| a != &hello.write_addr),
+                                    )
+                                    .collect(),
                             });
                             let (tx, rx) = oneshot::channel();
                             e.insert(ClientInfo::Running {
                                 publisher: publisher.clone(),
+                                uifo: uifo.clone(),
                                 stop: tx,
                             });
                             Ok(R::Finished(publisher, true, rx))
@@ -171,7 +234,7 @@ impl ClinfosInner {
                         Entry::Occupied(mut e) => {
                             let ifo = e.get_mut();
                             match ifo {
-                                ClientInfo::Running { publisher, stop } => {
+                                ClientInfo::Running { publisher, uifo, stop } => {
                                     let anon = publisher.target_auth.is_anonymous();
                                     match &hello.auth {
                                         AuthWrite::Anonymous if anon => (),
@@ -181,9 +244,10 @@ impl ClinfosInner {
                                         | AuthWrite::Local
                                         | AuthWrite::Tls { .. } => {
                                             let publisher = publisher.clone();
+                                            let uifo = uifo.clone();
                                             *ifo = ClientInfo::CleaningUp(Vec::new());
                                             ctx.secctx.remove(&publisher.id).await;
-                                            return Ok(R::ClearClient(publisher));
+                                            return Ok(R::ClearClient(publisher, uifo));
                                         }
                                     }
                                     let (tx, rx) = oneshot::channel();
@@ -198,8 +262,8 @@ impl ClinfosInner {
                 .await?;
             match r {
                 R::Finished(publisher, t, rx) => break Ok((publisher, t, rx)),
-                R::ClearClient(publisher) => {
-                    ctx.store.handle_clear(uifo.clone(), publisher).await?;
+                R::ClearClient(publisher, uifo) => {
+                    ctx.store.handle_clear(uifo, publisher).await?;
                     self.0.remove(&hello.write_addr);
                 }
             }
@@ -235,24 +299,110 @@ struct Ctx {
     ctracker: CTracker,
     secctx: SecCtx,
     cfg: MemberServer,
+    cluster_cfg: Config,
     id: SocketAddr,
     store: Store,
+    /// serves the read only addresses, if there are any
+    replica: Option<Store>,
+    quotas: Arc<Quotas>,
     delay_reads: Option<Instant>,
+    drain: watch::Sender<Option<SocketAddr>>,
+    limiter: Arc<Limiter>,
+}
+
+/// Apply the permissions and quotas in `cfg`, which must be a
+/// config for the same cluster. Everything else in the config only
+/// takes effect when the server is restarted.
+async fn reload(ctx: &Ctx, cfg: &Config) -> Result<()> {
+    let member = match cfg.member_servers.iter().find(|m| m.addr == ctx.id) {
+        Some(member) => member,
+        None => bail!("{} is not a member server in the new config", ctx.id),
+    };
+    if !ctx.cluster_cfg.same_namespace(cfg) {
+        bail!("changing the root, referrals, or placeholders requires a restart")
+    }
+    ctx.secctx.reload(cfg).await?;
+    ctx.quotas.set_limits(member.quotas);
+    info!("reloaded permissions and quotas");
+    Ok(())
+}
+
+/// Wait until the server starts draining and return the address
+/// clients should migrate to. Once it has returned it will never
+/// return again.
+async fn wait_drain(
+    drain: &mut Option<watch::Receiver<Option<SocketAddr>>>,
+) -> SocketAddr {
+    loop {
+        let rx = match drain {
+            Some(rx) => rx,
+            None => future::pending().await,
+        };
+        let alternate = *rx.borrow_and_update();
+        match alternate {
+            Some(alternate) => {
+                *drain = None;
+                break alternate;
+            }
+            None => {
+                if rx.changed().await.is_err() {
+                    *drain = None
+                }
+            }
+        }
+    }
+}
+
+/// Wait for the next batch of changes and return the watched paths
+/// they touch. If we fell behind then every watched path is
+/// returned.
+async fn wait_changed(
+    changes: &mut Option<broadcast::Receiver<Arc<Vec<Path>>>>,
+    watches: &BTreeSet<Path>,
+) -> BTreeSet<Path> {
+    use broadcast::error::{RecvError, TryRecvError};
+    let rx = match changes {
+        Some(rx) => rx,
+        None => future::pending().await,
+    };
+    let mut hit = BTreeSet::new();
+    let mut add = |paths: &[Path]| {
+        for w in watches {
+            if paths.iter().any(|p| Path::is_parent(w, p) || &**p == "/") {
+                hit.insert(w.clone());
+            }
+        }
+    };
+    match rx.recv().await {
+        Ok(paths) => add(&paths),
+        Err(RecvError::Lagged(_)) => return watches.clone(),
+        Err(RecvError::Closed) => future::pending().await,
+    }
+    loop {
+        match rx.try_recv() {
+            Ok(paths) => add(&paths),
+            Err(TryRecvError::Lagged(_)) => break watches.clone(),
+            Err(TryRecvError::Empty | TryRecvError::Closed) => break hit,
+        }
+    }
 }
 
 async fn client_loop_write(
     ctx: Arc<Ctx>,
     connection_id: CId,
+    ip: IpAddr,
     con: Channel,
     server_stop: oneshot::Receiver<()>,
     rx_stop: oneshot::Receiver<()>,
     uifo: Arc<UserInfo>,
     publisher: Arc<Publisher>,
+    heartbeat: bool,
 ) -> Result<()> {
     debug!("starting write loop for {:?}", connection_id);
     let mut con = Some(con);
     let mut server_stop = server_stop.fuse();
     let mut rx_stop = rx_stop.fuse();
+    let mut drain = Some(ctx.drain.subscribe());
     let mut batch = WRITE_BATCHES.take();
     let mut act = false;
     let mut timeout =
@@ -277,6 +427,12 @@ async fn client_loop_write(
         select_biased! {
             _ = server_stop => break Ok(()),
             _ = rx_stop => break Ok(()),
+            alternate = wait_drain(&mut drain).fuse() => {
+                if let Some(c) = con.as_mut() {
+                    debug!("asking {:?} to migrate to {}", connection_id, alternate);
+                    c.send_one(&FromWrite::Migrate(alternate)).await?
+                }
+            },
             _ = timeout.tick().fuse() => {
                 if act {
 		    trace!("checking timeout, {:?} was active", connection_id);
@@ -285,7 +441,7 @@ async fn client_loop_write(
 		    trace!("dropping inactive connection {:?} ", connection_id);
                     drop(con);
                     ctx.ctracker.close(connection_id);
-                    ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+                    ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
                     bail!("write client timed out");
                 }
             },
@@ -299,14 +455,31 @@ async fn client_loop_write(
                 Ok(()) => {
 		    trace!("{:?} received a batch", connection_id);
                     act = true;
-                    if batch.len() == 1 && batch[0] == ToWrite::Heartbeat {
-			trace!("{:?} batch is just a heartbeat", connection_id);
-                        continue 'main
-                    }
+                    ctx.ctracker.received(connection_id, batch.len());
                     let c = match con.as_mut() {
 			Some(c) => c,
 			None => unreachable!("bug, con is none and we received a batch"),
 		    };
+                    let len = batch.len();
+                    batch.retain(|m| m != &ToWrite::Heartbeat);
+                    if heartbeat && batch.len() < len {
+			trace!("{:?} acking {} heartbeats", connection_id, len - batch.len());
+                        for _ in batch.len()..len {
+                            c.queue_send(&FromWrite::Heartbeat)?
+                        }
+                        c.flush().await?
+                    }
+                    if batch.is_empty() {
+			trace!("{:?} batch is just a heartbeat", connection_id);
+                        continue 'main
+                    }
+                    if let Err(e) = check_write_limits(&ctx, ip, &batch) {
+                        warn!("write client {:?} {}", connection_id, e);
+                        batch.clear();
+                        con = None;
+                        ctx.ctracker.close(connection_id);
+                        continue 'main;
+                    }
 		    trace!("{:?} checking batch of len {} for clear", connection_id, batch.len());
                     while let Some((i, _)) =
                         batch.iter().enumerate().find(|(_, m)| *m == &ToWrite::Clear)
@@ -319,12 +492,15 @@ async fn client_loop_write(
                                 ToWrite::Publish(_)
                                     | ToWrite::PublishDefault(_)
                                     | ToWrite::PublishWithFlags(_, _)
-                                    | ToWrite::PublishDefaultWithFlags(_, _) =>
+                                    | ToWrite::PublishDefaultWithFlags(_, _)
+                                    | ToWrite::PublishWithMetadata(_, _, _) =>
                                     c.queue_send(&FromWrite::Published)?,
                                 ToWrite::Unpublish(_) =>
                                     c.queue_send(&FromWrite::Unpublished)?,
                                 ToWrite::UnpublishDefault(_) =>
                                     c.queue_send(&FromWrite::Unpublished)?,
+                                ToWrite::UnpublishPrefix(_) =>
+                                    c.queue_send(&FromWrite::Unpublished)?,
                                 ToWrite::Clear => {
 				    trace!("{:?} handling clear", connection_id);
                                     ctx.store.handle_clear(
@@ -339,16 +515,28 @@ async fn client_loop_write(
                         batch = Pooled::orphan(rest);
                     }
 		    trace!("{:?} handling write batch of size {}", connection_id, batch.len());
-                    if let Err(e) = ctx.store.handle_batch_write(
+                    match ctx.store.handle_batch_write(
                         Some(c),
                         uifo.clone(),
                         publisher.clone(),
                         batch.drain(..)
                     ).await {
-                        warn!("handle_write_batch failed {}", e);
-                        con = None;
-                        ctx.ctracker.close(connection_id);
-                        continue 'main;
+                        Ok(None) => (),
+                        Ok(Some(paths)) => {
+                            // republishing a path doesn't add to the count
+                            if let Err(e) = ctx.limiter.check_paths(ip, paths) {
+                                warn!("write client {:?} {}", connection_id, e);
+                                con = None;
+                                ctx.ctracker.close(connection_id);
+                                continue 'main;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("handle_write_batch failed {}", e);
+                            con = None;
+                            ctx.ctracker.close(connection_id);
+                            continue 'main;
+                        }
                     }
                 }
             },
@@ -356,19 +544,51 @@ async fn client_loop_write(
     }
 }
 
+/// Account for the publishes in `batch` against the rate limits. The
+/// number of paths published by the writer is checked once the batch
+/// is applied, since only the store knows which paths are new.
+fn check_write_limits(ctx: &Ctx, ip: IpAddr, batch: &[ToWrite]) -> Result<()> {
+    let published = batch
+        .iter()
+        .filter(|m| match m {
+            ToWrite::Publish(_)
+            | ToWrite::PublishDefault(_)
+            | ToWrite::PublishWithFlags(_, _)
+            | ToWrite::PublishDefaultWithFlags(_, _)
+            | ToWrite::PublishWithMetadata(_, _, _) => true,
+            ToWrite::Heartbeat
+            | ToWrite::Unpublish(_)
+            | ToWrite::UnpublishDefault(_)
+            | ToWrite::UnpublishPrefix(_)
+            | ToWrite::Clear => false,
+        })
+        .count();
+    if published > 0 {
+        ctx.limiter.check(ip, Limit::Publish, published)?;
+    }
+    Ok(())
+}
+
 const TOKEN_MAX: usize = 4096;
 
-async fn recv<T: Pack + Debug>(timeout: Duration, con: &mut TcpStream) -> Result<T> {
+async fn recv<T: Pack + Debug>(
+    timeout: Duration,
+    con: &mut (impl AsyncRead + Unpin),
+) -> Result<T> {
     Ok(time::timeout(timeout, channel::read_raw(con)).await??)
 }
-async fn send(timeout: Duration, con: &mut TcpStream, msg: &impl Pack) -> Result<()> {
+async fn send(
+    timeout: Duration,
+    con: &mut (impl AsyncWrite + Unpin),
+    msg: &impl Pack,
+) -> Result<()> {
     Ok(time::timeout(timeout, channel::write_raw(con, msg)).await??)
 }
 
 pub(crate) async fn krb5_authentication(
     timeout: Duration,
     spn: Option<&str>,
-    con: &mut TcpStream,
+    con: &mut (impl AsyncRead + AsyncWrite + Unpin),
 ) -> Result<ServerCtx> {
     // the GSS token shouldn't ever be bigger than 1 MB
     const L: usize = 1 * 1024 * 1024;
@@ -458,11 +678,12 @@ async fn write_client_anonymous_auth(
         ttl_expired,
         resolver_id: ctx.id,
         auth: AuthWrite::Anonymous,
+        heartbeat: true,
     };
     info!("hello_write accepting Anonymous authentication");
     debug!("hello_write sending hello {:?}", h);
     if let Err(e) = send(ctx.cfg.hello_timeout, &mut con, &h).await {
-        ctx.clinfos.lock().await.remove(&ctx, &publisher, uifo).await?;
+        ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
         Err(e)?;
     }
     Ok((
@@ -488,6 +709,7 @@ async fn write_client_local_auth(
         ttl_expired: true, // re auth always clears
         resolver_id: ctx.id,
         auth: AuthWrite::Local,
+        heartbeat: true,
     };
     debug!("hello_write sending {:?}", h);
     send(ctx.cfg.hello_timeout, &mut con, &h).await?;
@@ -519,15 +741,16 @@ async fn write_client_reuse_local(
         ttl_expired,
         resolver_id: ctx.id,
         auth: AuthWrite::Reuse,
+        heartbeat: true,
     };
     match time::timeout(ctx.cfg.hello_timeout, con.send_one(&h)).await {
         Ok(Ok(())) => (),
         Err(e) => {
-            ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+            ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
             Err(e)?
         }
         Ok(Err(e)) => {
-            ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+            ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
             Err(e)?
         }
     }
@@ -550,6 +773,7 @@ async fn write_client_krb5_auth(
         ttl_expired: true, // re auth always clears
         resolver_id: ctx.id,
         auth: AuthWrite::Krb5 { spn: Chars::from("") },
+        heartbeat: true,
     };
     debug!("hello_write sending {:?}", h);
     time::timeout(ctx.cfg.hello_timeout, con.send_one(&h)).await??;
@@ -585,17 +809,18 @@ async fn write_client_reuse_krb5(
         ttl_expired,
         resolver_id: ctx.id,
         auth: AuthWrite::Reuse,
+        heartbeat: true,
     };
     info!("hello_write reusing krb5 context");
     debug!("hello_write sending {:?}", h);
     match time::timeout(ctx.cfg.hello_timeout, con.send_one(&h)).await {
         Ok(Ok(())) => (),
         Err(e) => {
-            ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+            ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
             Err(e)?
         }
         Ok(Err(e)) => {
-            ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+            ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
             Err(e)?
         }
     }
@@ -638,6 +863,7 @@ async fn write_client_tls_auth(
         ttl_expired: true,
         resolver_id: ctx.id,
         auth: AuthWrite::Tls { name: Chars::from("") },
+        heartbeat: true,
     };
     debug!("hello_write sending {:?}", h);
     time::timeout(ctx.cfg.hello_timeout, con.send_one(&h)).await??;
@@ -672,17 +898,18 @@ async fn write_client_reuse_tls(
         ttl_expired,
         resolver_id: ctx.id,
         auth: AuthWrite::Reuse,
+        heartbeat: true,
     };
     info!("hello_write reusing tls context");
     debug!("hello_write sending {:?}", h);
     match time::timeout(ctx.cfg.hello_timeout, con.send_one(&h)).await {
         Ok(Ok(())) => (),
         Err(e) => {
-            ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+            ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
             Err(e)?
         }
         Ok(Err(e)) => {
-            ctx.clinfos.lock().await.remove(&ctx, &publisher, &uifo).await?;
+            ctx.clinfos.lock().await.remove(&ctx, &publisher).await?;
             Err(e)?
         }
     }
@@ -692,6 +919,7 @@ async fn write_client_reuse_tls(
 async fn hello_client_write(
     ctx: Arc<Ctx>,
     connection_id: CId,
+    ip: IpAddr,
     con: TcpStream,
     server_stop: oneshot::Receiver<()>,
     hello: ClientHelloWrite,
@@ -700,6 +928,9 @@ async fn hello_client_write(
     info!("hello_write starting negotiation");
     debug!("hello_write client_hello: {:?}", hello);
     utils::check_addr(hello.write_addr.ip(), &[(ctx.id, ())])?;
+    for addr in &hello.alt_addrs {
+        utils::check_addr(addr.ip(), &[(ctx.id, ())])?;
+    }
     let (con, uifo, publisher, rx_stop) = match hello.auth {
         AuthWrite::Anonymous => write_client_anonymous_auth(&ctx, con, &hello).await?,
         AuthWrite::Local => match &ctx.secctx {
@@ -721,37 +952,184 @@ async fn hello_client_write(
             SecCtx::Anonymous => bail!(NO),
         },
     };
-    Ok(client_loop_write(ctx, connection_id, con, server_stop, rx_stop, uifo, publisher)
-        .await?)
+    ctx.ctracker.identify(connection_id, &uifo, true);
+    Ok(client_loop_write(
+        ctx,
+        connection_id,
+        ip,
+        con,
+        server_stop,
+        rx_stop,
+        uifo,
+        publisher,
+        hello.heartbeat,
+    )
+    .await?)
+}
+
+/// Answer a batch containing admin requests, in order. Anything else
+/// in the batch is answered with an error.
+async fn handle_admin(
+    ctx: &Ctx,
+    con: &mut Channel,
+    uifo: &Arc<UserInfo>,
+    batch: impl Iterator<Item = ToRead>,
+) -> Result<()> {
+    // an anonymous server has no way to know who is asking, so it
+    // never allows admin requests
+    let allowed = {
+        let secctx = ctx.secctx.read().await;
+        secctx
+            .pmap()
+            .map(|pmap| pmap.allowed("/", Permissions::ADMIN, uifo))
+            .unwrap_or(false)
+    };
+    for m in batch {
+        let req = match m {
+            ToRead::Admin(req) if allowed => req,
+            ToRead::Admin(_) => {
+                con.queue_send(&FromRead::Denied)?;
+                continue;
+            }
+            _ => {
+                let e = "admin requests must be sent alone";
+                con.queue_send(&FromRead::Error(Chars::from(e)))?;
+                continue;
+            }
+        };
+        let reply = match req {
+            ToAdmin::Publishers => {
+                let mut publishers = PUBLISHERS.take();
+                for ifo in ctx.clinfos.lock().await.0.values() {
+                    if let ClientInfo::Running { publisher, .. } = ifo {
+                        publishers.push((**publisher).clone())
+                    }
+                }
+                FromAdmin::Publishers(publishers)
+            }
+            ToAdmin::Dump => FromAdmin::Dump(ctx.store.dump().await?),
+            ToAdmin::ClearPublisher(addr) => {
+                let mut clinfos = ctx.clinfos.lock().await;
+                let publisher = match clinfos.0.get(&addr) {
+                    Some(ClientInfo::Running { publisher, .. }) => {
+                        Some(publisher.clone())
+                    }
+                    Some(ClientInfo::CleaningUp(_)) | None => None,
+                };
+                match publisher {
+                    None => FromAdmin::Cleared(false),
+                    Some(publisher) => {
+                        info!("admin request clearing publisher {}", addr);
+                        clinfos.remove(ctx, &publisher).await?;
+                        FromAdmin::Cleared(true)
+                    }
+                }
+            }
+            ToAdmin::ClientStats => FromAdmin::ClientStats(ctx.ctracker.stats()),
+            ToAdmin::Reload => {
+                let res = match &ctx.cluster_cfg.source {
+                    None => Err(anyhow!("the config was not loaded from a file")),
+                    Some(file) => match Config::load(file) {
+                        Err(e) => Err(e),
+                        Ok(cfg) => reload(ctx, &cfg).await,
+                    },
+                };
+                match res {
+                    Ok(()) => FromAdmin::Reloaded,
+                    Err(e) => {
+                        warn!("admin request to reload the config failed {}", e);
+                        let e = format!("reload failed {}", e);
+                        con.queue_send(&FromRead::Error(Chars::from(e)))?;
+                        continue;
+                    }
+                }
+            }
+        };
+        con.queue_send(&FromRead::Admin(reply))?
+    }
+    Ok(con.flush().await?)
 }
 
 async fn client_loop_read(
     ctx: Arc<Ctx>,
+    connection_id: CId,
+    ip: IpAddr,
     mut con: Channel,
     server_stop: oneshot::Receiver<()>,
     uifo: Arc<UserInfo>,
+    read_only: bool,
 ) -> Result<()> {
+    let store = match &ctx.replica {
+        Some(replica) if read_only => replica.clone(),
+        Some(_) | None => ctx.store.clone(),
+    };
     let mut batch = READ_BATCHES.take();
     let mut server_stop = server_stop.fuse();
+    let mut drain = Some(ctx.drain.subscribe());
     let mut act = false;
+    let mut watches: BTreeSet<Path> = BTreeSet::new();
+    let mut changes = None;
     let mut timeout =
         time::interval_at(Instant::now() + ctx.cfg.reader_ttl, ctx.cfg.reader_ttl);
     loop {
         select_biased! {
             _ = server_stop => break Ok(()),
+            alternate = wait_drain(&mut drain).fuse() => {
+                con.send_one(&FromRead::Migrate(alternate)).await?
+            },
             _ = timeout.tick().fuse() => {
-                if act {
+                // watching clients may legitimately be silent
+                if act || !watches.is_empty() {
                     act = false;
                 } else {
                     bail!("client timed out");
                 }
             }
+            hit = wait_changed(&mut changes, &watches).fuse() => {
+                for path in hit {
+                    con.queue_send(&FromRead::Changed(path))?
+                }
+                if con.bytes_queued() > 0 {
+                    con.flush().await?
+                }
+            }
             m = con.receive_batch(&mut batch).fuse() => {
                 m?;
                 act = true;
-                ctx.store.handle_batch_read(
+                ctx.ctracker.received(connection_id, batch.len());
+                ctx.limiter.check(ip, Limit::Read, batch.len())?;
+                if batch.iter().any(|m| matches!(m, ToRead::Admin(_))) {
+                    handle_admin(&ctx, &mut con, &uifo, batch.drain(..)).await?;
+                    continue
+                }
+                if batch.iter().any(|m| matches!(m, ToRead::Watch(_))) {
+                    let secctx = ctx.secctx.read().await;
+                    let pmap = secctx.pmap();
+                    for m in batch.iter() {
+                        if let ToRead::Watch(path) = m {
+                            // watching reveals the same thing as listing
+                            let allowed = pmap
+                                .map(|pm| pm.allowed(path, Permissions::LIST, &uifo))
+                                .unwrap_or(true);
+                            if !allowed {
+                                con.queue_send(&FromRead::Denied)?;
+                                continue;
+                            }
+                            if changes.is_none() {
+                                changes = Some(store.watch());
+                            }
+                            watches.insert(path.clone());
+                            con.queue_send(&FromRead::Changed(path.clone()))?
+                        }
+                    }
+                }
+                if con.bytes_queued() > 0 {
+                    con.flush().await?
+                }
+                store.handle_batch_read(
                     &mut con,
                     uifo.clone(),
+                    ip,
                     batch.drain(..)
                 ).await?;
             },
@@ -761,9 +1139,12 @@ async fn client_loop_read(
 
 async fn hello_client_read(
     ctx: Arc<Ctx>,
+    connection_id: CId,
+    ip: IpAddr,
     mut con: TcpStream,
     server_stop: oneshot::Receiver<()>,
     hello: AuthRead,
+    read_only: bool,
 ) -> Result<()> {
     static NO: &str = "authentication mechanism not supported";
     let (con, uifo) = match hello {
@@ -811,7 +1192,9 @@ async fn hello_client_read(
             SecCtx::Anonymous | SecCtx::Local(_) | SecCtx::Krb5(_) => bail!(NO),
         },
     };
-    Ok(client_loop_read(ctx, con, server_stop, uifo).await?)
+    ctx.ctracker.identify(connection_id, &uifo, false);
+    Ok(client_loop_read(ctx, connection_id, ip, con, server_stop, uifo, read_only)
+        .await?)
 }
 
 async fn hello_client(
@@ -819,7 +1202,9 @@ async fn hello_client(
     connection_id: CId,
     mut s: TcpStream,
     server_stop: oneshot::Receiver<()>,
+    read_only: bool,
 ) -> Result<()> {
+    let ip = s.peer_addr()?.ip();
     s.set_nodelay(true)?;
     send(ctx.cfg.hello_timeout, &mut s, &3u64).await?;
     let version: u64 = recv(ctx.cfg.hello_timeout, &mut s).await?;
@@ -834,19 +1219,78 @@ async fn hello_client(
                     bail!("no read clients allowed yet");
                 }
             }
-            Ok(hello_client_read(ctx, s, server_stop, hello).await?)
+            Ok(hello_client_read(
+                ctx,
+                connection_id,
+                ip,
+                s,
+                server_stop,
+                hello,
+                read_only,
+            )
+            .await?)
+        }
+        ClientHello::WriteOnly(_) if read_only => {
+            bail!("only read only clients are allowed on this address")
         }
         ClientHello::WriteOnly(hello) => {
-            Ok(hello_client_write(ctx, connection_id, s, server_stop, hello).await?)
+            Ok(hello_client_write(ctx, connection_id, ip, s, server_stop, hello).await?)
         }
     }
 }
 
+/// Start serving a newly accepted client, unless it is over the
+/// hello rate limit, then wait while we are over the connection
+/// limit.
+async fn accept_client(
+    ctx: &Arc<Ctx>,
+    client_stops: &mut Vec<oneshot::Sender<()>>,
+    client: TcpStream,
+    addr: SocketAddr,
+    read_only: bool,
+) {
+    if ctx.limiter.check(addr.ip(), Limit::Hello, 1).is_err() {
+        debug!("refusing connection from {}", addr);
+        return;
+    }
+    let (tx, rx) = oneshot::channel();
+    client_stops.push(tx);
+    let connection_id = ctx.ctracker.open(addr);
+    task::spawn({
+        let ctx = Arc::clone(ctx);
+        async move {
+            let r = hello_client(Arc::clone(&ctx), connection_id, client, rx, read_only)
+                .await;
+            ctx.ctracker.close(connection_id);
+            info!("server_loop client shutting down {:?}", r);
+        }
+    });
+    while ctx.ctracker.num_open() > ctx.cfg.max_connections {
+        time::sleep(Duration::from_millis(10u64)).await;
+    }
+    debug!("I have {} writers", ctx.clinfos.lock().await.0.len())
+}
+
+struct Drain {
+    deadline: Instant,
+    alternate: SocketAddr,
+    finished: oneshot::Sender<()>,
+}
+
+#[derive(Debug)]
+struct Reload {
+    cfg: Config,
+    finished: oneshot::Sender<Result<()>>,
+}
+
 async fn server_loop(
     cfg: Config,
     delay_reads: bool,
     stop: oneshot::Receiver<()>,
-    ready: oneshot::Sender<SocketAddr>,
+    drain: oneshot::Receiver<Drain>,
+    mut reloads: mpsc::UnboundedReceiver<Reload>,
+    ready: oneshot::Sender<(SocketAddr, Vec<SocketAddr>)>,
+    limiter: Arc<Limiter>,
     id: usize,
 ) -> Result<()> {
     debug!("server task start I am id: {}", id);
@@ -858,73 +1302,135 @@ async fn server_loop(
     debug!("creating security context");
     let secctx = SecCtx::new(&cfg, &member).await?;
     debug!("creating resolver store");
+    let quotas = Arc::new(Quotas::new(member.quotas));
     let store = Store::new(
         cfg.parent.clone().map(|s| s.into()),
         cfg.children.iter().map(|(p, s)| (p.clone(), s.clone().into())).collect(),
+        cfg.placeholders.clone(),
         secctx.clone(),
+        quotas.clone(),
         id,
+        member.shards,
+        !member.read_only_addrs.is_empty(),
     );
     let listen_addr = SocketAddr::new(member.bind_addr, id.port());
     debug!("creating tcp listener on {:?}", listen_addr);
     let listener = TcpListener::bind(listen_addr).await?;
+    let (read_only_tx, mut read_only) = mpsc::unbounded();
+    let mut read_only_addrs = vec![];
+    let mut read_only_listeners = vec![];
+    for addr in &member.read_only_addrs {
+        debug!("creating read only tcp listener on {:?}", addr);
+        let listener = TcpListener::bind(addr).await?;
+        read_only_addrs.push(listener.local_addr()?);
+        let tx = read_only_tx.clone();
+        read_only_listeners.push(task::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Err(e) => warn!("accept failed: {}", e),
+                    Ok(cl) => {
+                        if tx.unbounded_send(cl).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }));
+    }
+    let replica = store.replica();
     let ctx = Arc::new(Ctx {
         cfg: member,
+        cluster_cfg: cfg,
         secctx,
         clinfos: Clinfos::new(),
         ctracker: CTracker::new(),
         id,
         delay_reads,
         store,
+        replica,
+        quotas,
+        drain: watch::channel(None).0,
+        limiter,
     });
     let mut stop = stop.fuse();
+    let mut drain = drain.fuse();
     let mut client_stops: Vec<oneshot::Sender<()>> = Vec::new();
-    let max_connections = ctx.cfg.max_connections;
     debug!("signaling ready");
     let mut listen_addr = listener.local_addr()?;
     listen_addr.set_ip(id.ip());
-    let _ = ready.send(listen_addr);
-    loop {
+    let _ = ready.send((listen_addr, read_only_addrs));
+    let drain = loop {
         select_biased! {
             _ = stop => {
 		debug!("server loop stop requested");
                 for cl in client_stops.drain(..) {
                     let _ = cl.send(());
                 }
+                for l in read_only_listeners {
+                    l.abort()
+                }
                 return Ok(())
             },
+            d = drain => if let Ok(d) = d {
+                break d
+            },
+            r = reloads.select_next_some() => {
+                let _ = r.finished.send(reload(&ctx, &r.cfg).await);
+            },
             cl = listener.accept().fuse() => match cl {
                 Err(e) => warn!("accept failed: {}", e),
-                Ok((client, _)) => {
-                    let (tx, rx) = oneshot::channel();
-                    client_stops.push(tx);
-                    let connection_id = ctx.ctracker.open();
-                    task::spawn({
-                        let ctx = Arc::clone(&ctx);
-                        async move {
-                            let r = hello_client(
-                                Arc::clone(&ctx),
-                                connection_id,
-                                client,
-                                rx
-                            ).await;
-                            ctx.ctracker.close(connection_id);
-                            info!("server_loop client shutting down {:?}", r);
-                        }
-                    });
-                    while ctx.ctracker.num_open() > max_connections {
-                        time::sleep(Duration::from_millis(10u64)).await;
-                    }
-                    debug!("I have {} writers", ctx.clinfos.lock().await.0.len())
+                Ok((client, addr)) => {
+                    accept_client(&ctx, &mut client_stops, client, addr, false).await
                 }
             },
+            (client, addr) = read_only.select_next_some() => {
+                accept_client(&ctx, &mut client_stops, client, addr, true).await
+            },
         }
+    };
+    info!("draining, clients will migrate to {}", drain.alternate);
+    drop(listener);
+    for l in read_only_listeners {
+        l.abort()
     }
+    ctx.drain.send_replace(Some(drain.alternate));
+    let mut check = time::interval(Duration::from_millis(100));
+    loop {
+        select_biased! {
+            _ = stop => break,
+            _ = check.tick().fuse() => {
+                if ctx.ctracker.num_open() == 0 || Instant::now() >= drain.deadline {
+                    break
+                }
+            },
+        }
+    }
+    for cl in client_stops.drain(..) {
+        let _ = cl.send(());
+    }
+    let _ = drain.finished.send(());
+    Ok(())
 }
 
+/// A resolver server. A cluster is one or more member servers, each
+/// listed in `member_servers`, and each started with its index in
+/// that list as `id`. Members are independent, they never copy
+/// publishes or unpublishes between themselves. Instead the write
+/// client of each publisher sends every operation to all the
+/// members, succeeds as soon as one of them accepts it, and
+/// republishes everything to a member when it reconnects to it
+/// (e.g. after it is restarted). So members are only as consistent
+/// as the publishers' connections to them. Read clients use any
+/// member, and move to another one when it fails, so a cluster
+/// remains available as long as at least one member is up.
 #[derive(Debug)]
 pub struct Server {
     stop: Option<oneshot::Sender<()>>,
+    drain: Option<oneshot::Sender<Drain>>,
+    reload: mpsc::UnboundedSender<Reload>,
     local_addr: SocketAddr,
+    read_only_addrs: Vec<SocketAddr>,
+    limiter: Arc<Limiter>,
 }
 
 impl Drop for Server {
@@ -938,23 +1444,98 @@ impl Drop for Server {
 impl Server {
     pub async fn new(cfg: Config, delay_reads: bool, id: usize) -> Result<Server> {
         let (send_stop, recv_stop) = oneshot::channel();
+        let (send_drain, recv_drain) = oneshot::channel();
         let (send_ready, recv_ready) = oneshot::channel();
-        task::spawn(async move {
-            let res = server_loop(cfg, delay_reads, recv_stop, send_ready, id).await;
-            match &res {
-                Ok(_) => info!("resolver server shutdown"),
-                Err(e) => error!("resolver server failed {}", e),
+        let (send_reload, recv_reload) = mpsc::unbounded();
+        let limiter = match cfg.member_servers.get(id) {
+            None => bail!("no member server with id {}", id),
+            Some(member) => Arc::new(Limiter::new(member.rate_limits)),
+        };
+        task::spawn({
+            let limiter = limiter.clone();
+            async move {
+                let res = server_loop(
+                    cfg,
+                    delay_reads,
+                    recv_stop,
+                    recv_drain,
+                    recv_reload,
+                    send_ready,
+                    limiter,
+                    id,
+                )
+                .await;
+                match &res {
+                    Ok(_) => info!("resolver server shutdown"),
+                    Err(e) => error!("resolver server failed {}", e),
+                }
+                res
             }
-            res
         });
-	let local_addr = match recv_ready.await {
+	let (local_addr, read_only_addrs) = match recv_ready.await {
 	    Err(_) => bail!("resolver server shutdown"),
-	    Ok(addr) => addr,
+	    Ok(addrs) => addrs,
 	};
-        Ok(Server { stop: Some(send_stop), local_addr })
+        Ok(Server {
+            stop: Some(send_stop),
+            drain: Some(send_drain),
+            reload: send_reload,
+            local_addr,
+            read_only_addrs,
+            limiter,
+        })
     }
 
     pub fn local_addr(&self) -> &SocketAddr {
         &self.local_addr
     }
+
+    /// The addresses the read only listeners are bound to, in the
+    /// same order as `read_only_addrs` in the member server config.
+    pub fn read_only_addrs(&self) -> &[SocketAddr] {
+        &self.read_only_addrs
+    }
+
+    /// Return statistics about clients that were throttled or banned
+    /// by the rate limits in the member server config.
+    pub fn limit_stats(&self) -> LimitStats {
+        self.limiter.stats()
+    }
+
+    /// Reload the permissions and quotas from `cfg` without
+    /// disconnecting any clients. Requests received after the
+    /// reload completes are checked against the new permissions.
+    /// `cfg` must be a config for the same cluster, with this server
+    /// at the same address, and with the same root and referrals.
+    /// Other changes, e.g. to the listen address or the auth
+    /// mechanism, only take effect when the server is restarted.
+    pub async fn reload(&self, cfg: Config) -> Result<()> {
+        let (finished, wait) = oneshot::channel();
+        if self.reload.unbounded_send(Reload { cfg, finished }).is_err() {
+            bail!("resolver server shutdown")
+        }
+        match wait.await {
+            Err(_) => bail!("resolver server shutdown"),
+            Ok(res) => res,
+        }
+    }
+
+    /// Gracefully shut down the server. Stop accepting new
+    /// connections, and ask connected clients to migrate to the
+    /// resolver server at `alternate`. Publishers will republish
+    /// their data there, and subscribers will send their next
+    /// request there. Once all clients have disconnected, or the
+    /// deadline has passed, the remaining connections are closed
+    /// and the server shuts down.
+    ///
+    /// `alternate` should be another member of the same cluster, or
+    /// a replacement for this server, using the same auth mechanism.
+    pub async fn drain(mut self, deadline: Instant, alternate: SocketAddr) {
+        if let Some(drain) = self.drain.take() {
+            let (finished, wait) = oneshot::channel();
+            if drain.send(Drain { deadline, alternate, finished }).is_ok() {
+                let _ = wait.await;
+            }
+        }
+    }
 }
@@ -62,6 +62,13 @@ impl<S: 'static + SecDataCommon> SecCtxData<S> {
         Ok(Self { users, pmap, data: HashMap::default() })
     }
 
+    /// Replace the permissions with the ones in `cfg`
+    fn reload(&mut self, cfg: &Config) -> Result<()> {
+        let root = cfg.root();
+        self.pmap = PMap::from_file(&cfg.perms, &mut self.users, root, &cfg.children)?;
+        Ok(())
+    }
+
     pub(super) fn remove(&mut self, id: &PublisherId) {
         self.data.remove(&id);
     }
@@ -191,6 +198,18 @@ impl SecCtx {
         }
     }
 
+    /// Replace the permissions with the ones in `cfg`. Requests
+    /// that are already being processed finish with the old
+    /// permissions.
+    pub(super) async fn reload(&self, cfg: &Config) -> Result<()> {
+        match self {
+            SecCtx::Krb5(a) => a.1.write().await.reload(cfg),
+            SecCtx::Local(a) => a.1.write().await.reload(cfg),
+            SecCtx::Tls(a) => a.1.write().await.reload(cfg),
+            SecCtx::Anonymous => Ok(()),
+        }
+    }
+
     pub(super) async fn remove(&self, id: &PublisherId) {
         match self {
             SecCtx::Krb5(a) => a.1.write().await.remove(id),
@@ -1,7 +1,11 @@
 use super::{
     auth::{Permissions, UserInfo},
+    limits::Quotas,
     secctx::{SecCtx, SecCtxDataReadGuard},
-    store::{self, COLS_POOL, MAX_READ_BATCH, MAX_WRITE_BATCH, PATH_POOL, REF_POOL},
+    store::{
+        self, COLS_POOL, LIST_ENTRY_POOL, MAX_READ_BATCH, MAX_WRITE_BATCH, PATH_POOL,
+        REF_POOL,
+    },
 };
 use chrono::prelude::*;
 use crate::{
@@ -12,10 +16,12 @@ use crate::{
     protocol::{
         glob::Scope,
         resolver::{
-            FromRead, FromWrite, GetChangeNr, ListMatching, Publisher, PublisherId,
-            Referral, Resolved, Table, ToRead, ToWrite,
+            FromRead, FromWrite, GetChangeNr, ListEntry, ListMatching, Metadata,
+            Publisher, PublisherId, Referral, Resolved, StoreEntry, Table, ToRead,
+            ToWrite,
         },
     },
+    utils,
 };
 use anyhow::Result;
 use futures::{
@@ -33,12 +39,13 @@ use std::{
     collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet, VecDeque},
     hash::{Hash, Hasher},
     iter,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     result,
+    mem,
     sync::Arc,
     time::SystemTime,
 };
-use tokio::task;
+use tokio::{sync::broadcast, task};
 
 type ReadB = Vec<(u64, ToRead)>;
 type ReadR = VecDeque<(u64, FromRead)>;
@@ -55,9 +62,12 @@ lazy_static! {
     static ref FROM_WRITE_POOL: Pool<WriteR> = Pool::new(100, 10_000);
     static ref COLS_HPOOL: Pool<HashMap<Path, Z64>> = Pool::new(32, 10_000);
     static ref PATH_HPOOL: Pool<HashSet<Path>> = Pool::new(32, 10_000);
+    static ref ENTRY_HPOOL: Pool<HashMap<Path, ListEntry>> = Pool::new(32, 10_000);
     static ref PATH_BPOOL: Pool<Vec<Pooled<Vec<Path>>>> = Pool::new(32, 1024);
     static ref READ_SHARD_BATCH: Pool<Vec<Pooled<ReadB>>> = Pool::new(100, 1024);
     static ref WRITE_SHARD_BATCH: Pool<Vec<Pooled<WriteB>>> = Pool::new(100, 1024);
+    static ref STORE_ENTRY_POOL: Pool<Vec<StoreEntry>> = Pool::new(10, 1_000_000);
+    static ref MIRROR_POOL: Pool<Vec<ToWrite>> = Pool::new(100, 10_000);
 }
 
 struct ReadRequest {
@@ -70,17 +80,33 @@ struct ReadResponse {
     batch: Pooled<ReadR>,
 }
 
+struct WriteResponse {
+    batch: Pooled<WriteR>,
+    /// The number of distinct non default paths the publisher has
+    /// in the shard after the batch
+    published: usize,
+}
+
 struct WriteRequest {
     uifo: Arc<UserInfo>,
     publisher: Arc<Publisher>,
     batch: Pooled<WriteB>,
 }
 
+/// Writes that were checked and applied by a shard, to be applied
+/// to its replica
+struct MirrorRequest {
+    publisher: Arc<Publisher>,
+    batch: Pooled<Vec<ToWrite>>,
+}
+
 #[derive(Clone)]
 struct Shard {
     read: UnboundedSender<(ReadRequest, oneshot::Sender<ReadResponse>)>,
-    write: UnboundedSender<(WriteRequest, oneshot::Sender<Pooled<WriteR>>)>,
+    write: UnboundedSender<(WriteRequest, oneshot::Sender<WriteResponse>)>,
     internal: UnboundedSender<(PublisherId, oneshot::Sender<HashSet<Path>>)>,
+    dump: UnboundedSender<oneshot::Sender<Vec<StoreEntry>>>,
+    mirror: UnboundedSender<MirrorRequest>,
 }
 
 impl Shard {
@@ -88,18 +114,23 @@ impl Shard {
         shard: usize,
         parent: Option<Referral>,
         children: BTreeMap<Path, Referral>,
+        placeholders: BTreeMap<Path, Metadata>,
         secctx: SecCtx,
+        quotas: Arc<Quotas>,
         resolver: SocketAddr,
+        replica: Option<Shard>,
     ) -> Self {
         let (read, read_rx) = unbounded();
         let (write, write_rx) = unbounded();
         let (internal, mut internal_rx) = unbounded();
+        let (dump, mut dump_rx) = unbounded();
+        let (mirror, mut mirror_rx) = unbounded();
         let mut read_rx = read_rx.fuse();
         let mut write_rx = write_rx.fuse();
-        let t = Shard { read, write, internal };
+        let t = Shard { read, write, internal, dump, mirror };
         task::spawn(async move {
 	    let mut last_shrink = Utc::now();
-            let mut store = store::Store::new(parent, children);
+            let mut store = store::Store::new(parent, children, placeholders);
             loop {
                 select! {
                     batch = read_rx.next() => match batch {
@@ -120,19 +151,40 @@ impl Shard {
                         None => break,
                         Some((req, reply)) => {
 			    let secctx = secctx.read().await;
+                            let publisher = req.publisher.clone();
+                            let mut mirror = MIRROR_POOL.take();
+                            if replica.is_some() {
+                                mirror.extend(req.batch.iter().map(|(_, m)| m.clone()));
+                            }
                             let r = Shard::process_write_batch(
                                 &mut store,
                                 &secctx,
+                                &quotas,
                                 req
                             ).await;
+                            if let Some(replica) = &replica {
+                                replica.send_mirror(publisher, mirror, &r.batch)
+                            }
                             let _ = reply.send(r);
                         }
                     },
+                    req = mirror_rx.next() => match req {
+                        None => break,
+                        Some(req) => Shard::process_mirror_batch(&mut store, req).await,
+                    },
                     id = internal_rx.next() => match id {
                         None => break,
                         Some((id, reply)) => {
                             let _ = reply.send(store.published_for_id(&id));
                         }
+                    },
+                    reply = dump_rx.next() => match reply {
+                        None => break,
+                        Some(reply) => {
+                            let mut entries = Vec::new();
+                            store.dump(&mut entries, shard == 0);
+                            let _ = reply.send(entries);
+                        }
                     }
                 }
 		let now = Utc::now();
@@ -228,6 +280,28 @@ impl Shard {
 			}
                     }
 		}
+		ToRead::ListWithMeta(path) => {
+		    n += 10;
+                    if let Some(r) = store.check_referral(&path) {
+			(id, FromRead::Referral(r))
+                    } else {
+			let allowed = pmap
+                            .map(|pmap| pmap.allowed(&*path, Permissions::LIST, &*uifo))
+                            .unwrap_or(true);
+			if allowed {
+                            let mut entries = store.list_with_meta(&path, shard == 0);
+                            for e in entries.iter_mut() {
+                                e.permissions = pmap
+                                    .map(|pmap| pmap.permissions(&*e.path, &*uifo))
+                                    .unwrap_or(Permissions::all())
+                                    .bits();
+                            }
+                            (id, FromRead::ListWithMeta(entries))
+			} else {
+                            (id, FromRead::Denied)
+			}
+                    }
+		}
 		ToRead::ListMatching(set) => {
 		    n += 1000;
                     let mut referrals = REF_POOL.take();
@@ -298,6 +372,30 @@ impl Shard {
 			}
                     }
 		}
+		ToRead::ResolvedBy(addr) => {
+		    n += 10;
+                    let mut paths = store.published_by_addr(&addr);
+                    if let Some(pmap) = pmap {
+                        paths.retain(|p| pmap.allowed(&*p, Permissions::LIST, &*uifo));
+                    }
+                    (id, FromRead::List(paths))
+		}
+		ToRead::Metadata(path) => {
+		    n += 1;
+                    if let Some(r) = store.check_referral(&path) {
+			(id, FromRead::Referral(r))
+                    } else {
+			let allowed = pmap
+                            .map(|pmap| pmap.allowed(&*path, Permissions::LIST, &*uifo))
+                            .unwrap_or(true);
+			if allowed {
+                            (id, FromRead::Metadata(store.metadata(&path)))
+			} else {
+                            (id, FromRead::Denied)
+			}
+                    }
+		}
+		ToRead::Watch(_) | ToRead::Admin(_) => unreachable!(),
             })
 	}
         resp
@@ -306,10 +404,12 @@ impl Shard {
     async fn process_write_batch<'a>(
         store: &mut store::Store,
         secctx: &SecCtxDataReadGuard<'a>,
+        quotas: &Quotas,
         mut req: WriteRequest,
-    ) -> Pooled<WriteR> {
+    ) -> WriteResponse {
         let uifo = &*req.uifo;
         let publisher = req.publisher;
+        let ip = publisher.addr.ip();
         let pmap = secctx.pmap();
         let publish = |s: &mut store::Store,
                        path: Path,
@@ -326,11 +426,22 @@ impl Shard {
                 } else {
                     Permissions::PUBLISH
                 };
-                if pmap.map(|p| p.allowed(&*path, perm, uifo)).unwrap_or(true) {
-                    s.publish(path, &publisher, default, flags);
-                    FromWrite::Published
-                } else {
-                    FromWrite::Denied
+                if !pmap.map(|p| p.allowed(&*path, perm, uifo)).unwrap_or(true) {
+                    return FromWrite::Denied;
+                }
+                let quota = quotas.check_depth(&path).and_then(|()| {
+                    if default || s.is_published(&publisher.id, &path) {
+                        Ok(())
+                    } else {
+                        quotas.acquire(uifo, ip)
+                    }
+                });
+                match quota {
+                    Err(e) => FromWrite::Error(e.to_string().into()),
+                    Ok(()) => {
+                        s.publish(path, &publisher, default, flags);
+                        FromWrite::Published
+                    }
                 }
             }
         };
@@ -345,6 +456,7 @@ impl Shard {
 		ToWrite::Heartbeat => unreachable!(),
 		ToWrite::Clear => {
 		    n += 1000;
+                    quotas.release(uifo, ip, store.published_count(&publisher.id));
                     store.clear(&publisher);
                     (id, FromWrite::Unpublished)
 		}
@@ -364,6 +476,14 @@ impl Shard {
 		    n += 1;
                     (id, publish(store, path, true, Some(flags)))
 		}
+		ToWrite::PublishWithMetadata(path, flags, meta) => {
+		    n += 1;
+                    let r = publish(store, path.clone(), false, flags);
+                    if r == FromWrite::Published {
+                        store.set_metadata(path, meta);
+                    }
+                    (id, r)
+		}
 		ToWrite::Unpublish(path) => {
 		    n += 5;
                     if !Path::is_absolute(&*path) {
@@ -371,6 +491,9 @@ impl Shard {
                     } else if let Some(r) = store.check_referral(&path) {
 			(id, FromWrite::Referral(r))
                     } else {
+			if store.is_published(&publisher.id, &path) {
+			    quotas.release(uifo, ip, 1);
+			}
 			store.unpublish(&publisher, false, path);
 			(id, FromWrite::Unpublished)
                     }
@@ -386,9 +509,78 @@ impl Shard {
 			(id, FromWrite::Unpublished)
                     }
 		}
+		ToWrite::UnpublishPrefix(path) => {
+		    n += 5;
+                    if !Path::is_absolute(&*path) {
+			(id, FromWrite::Error("absolute paths required".into()))
+                    } else if let Some(r) = store.check_referral(&path) {
+			(id, FromWrite::Referral(r))
+                    } else {
+			let c = store.unpublish_prefix(&publisher, &path);
+			quotas.release(uifo, ip, c);
+			n += c;
+			(id, FromWrite::Unpublished)
+                    }
+		}
             })
 	}
-        resp
+        let published = store.published_count(&publisher.id);
+        WriteResponse { batch: resp, published }
+    }
+
+    /// Send the writes in `batch` that changed the primary store, as
+    /// shown by their `replies`, to this replica
+    fn send_mirror(
+        &self,
+        publisher: Arc<Publisher>,
+        mut batch: Pooled<Vec<ToWrite>>,
+        replies: &WriteR,
+    ) {
+        let mut replies = replies.iter();
+        batch.retain(|_| match replies.next() {
+            Some((_, FromWrite::Published | FromWrite::Unpublished)) => true,
+            Some(_) | None => false,
+        });
+        if !batch.is_empty() {
+            let _ = self.mirror.unbounded_send(MirrorRequest { publisher, batch });
+        }
+    }
+
+    /// Apply writes that were already checked and applied by the
+    /// primary shard
+    async fn process_mirror_batch(store: &mut store::Store, mut req: MirrorRequest) {
+        let publisher = &req.publisher;
+        let mut n = 0;
+        for m in req.batch.drain(..) {
+            if n > 5_000 {
+                n = 0;
+                task::yield_now().await;
+            }
+            n += 1;
+            match m {
+                ToWrite::Heartbeat => (),
+                ToWrite::Clear => store.clear(publisher),
+                ToWrite::Publish(path) => store.publish(path, publisher, false, None),
+                ToWrite::PublishDefault(path) => {
+                    store.publish(path, publisher, true, None)
+                }
+                ToWrite::PublishWithFlags(path, flags) => {
+                    store.publish(path, publisher, false, Some(flags))
+                }
+                ToWrite::PublishDefaultWithFlags(path, flags) => {
+                    store.publish(path, publisher, true, Some(flags))
+                }
+                ToWrite::PublishWithMetadata(path, flags, meta) => {
+                    store.publish(path.clone(), publisher, false, flags);
+                    store.set_metadata(path, meta)
+                }
+                ToWrite::Unpublish(path) => store.unpublish(publisher, false, path),
+                ToWrite::UnpublishDefault(path) => store.unpublish(publisher, true, path),
+                ToWrite::UnpublishPrefix(path) => {
+                    store.unpublish_prefix(publisher, &path);
+                }
+            }
+        }
     }
 }
 
@@ -404,28 +596,93 @@ macro_rules! same {
     };
 }
 
+/// The path a write changes, as reported to watchers. Clear and
+/// UnpublishPrefix may remove paths below a watched path, so they are
+/// reported as the root.
+fn changed_path(m: &ToWrite) -> Option<Path> {
+    match m {
+        ToWrite::Heartbeat => None,
+        ToWrite::Clear | ToWrite::UnpublishPrefix(_) => Some(Path::root()),
+        ToWrite::Publish(p)
+        | ToWrite::Unpublish(p)
+        | ToWrite::UnpublishDefault(p)
+        | ToWrite::PublishDefault(p)
+        | ToWrite::PublishWithFlags(p, _)
+        | ToWrite::PublishDefaultWithFlags(p, _)
+        | ToWrite::PublishWithMetadata(p, _, _) => Some(p.clone()),
+    }
+}
+
+/// Order the addresses of a publisher for a subscriber connecting
+/// from `client`. Addresses of the same family as the subscriber
+/// come first, otherwise the order the publisher gave is kept.
+fn order_addrs(pb: &mut Publisher, client: IpAddr) {
+    let v4 = utils::canonical_ip(client).is_ipv4();
+    pb.addrs.sort_by_key(|a| utils::canonical_ip(a.ip()).is_ipv4() != v4);
+}
+
 #[derive(Clone)]
 pub(super) struct Store {
     shards: Vec<Shard>,
+    replica: Option<Vec<Shard>>,
     shard_mask: usize,
+    changes: broadcast::Sender<Arc<Vec<Path>>>,
 }
 
 impl Store {
     pub(super) fn new(
         parent: Option<Referral>,
         children: BTreeMap<Path, Referral>,
+        placeholders: BTreeMap<Path, Metadata>,
         secctx: SecCtx,
+        quotas: Arc<Quotas>,
         resolver: SocketAddr,
+        shards: usize,
+        read_replica: bool,
     ) -> Self {
-        let shards = std::cmp::max(1, num_cpus::get().next_power_of_two());
+        let shards = std::cmp::max(1, shards.next_power_of_two());
         let shard_mask = shards - 1;
+        let new_shard = |i, replica| {
+            Shard::new(
+                i,
+                parent.clone(),
+                children.clone(),
+                placeholders.clone(),
+                secctx.clone(),
+                quotas.clone(),
+                resolver,
+                replica,
+            )
+        };
+        let replica = if read_replica {
+            Some((0..shards).map(|i| new_shard(i, None)).collect::<Vec<_>>())
+        } else {
+            None
+        };
         let shards = (0..shards)
-            .into_iter()
-            .map(|i| {
-                Shard::new(i, parent.clone(), children.clone(), secctx.clone(), resolver)
-            })
+            .map(|i| new_shard(i, replica.as_ref().map(|r| r[i].clone())))
             .collect();
-        Store { shards, shard_mask }
+        let (changes, _) = broadcast::channel(100);
+        Store { shards, replica, shard_mask, changes }
+    }
+
+    /// The read replica, if the store was created with one. Every
+    /// write applied to the store is applied to the replica shortly
+    /// afterwards, so reads from it don't contend with writes, but
+    /// may briefly lag behind them. It must not be written to.
+    pub(super) fn replica(&self) -> Option<Store> {
+        self.replica.as_ref().map(|shards| Store {
+            shards: shards.clone(),
+            replica: None,
+            shard_mask: self.shard_mask,
+            changes: self.changes.clone(),
+        })
+    }
+
+    /// Receive the paths changed by every write batch processed
+    /// from now on.
+    pub(super) fn watch(&self) -> broadcast::Receiver<Arc<Vec<Path>>> {
+        self.changes.subscribe()
     }
 
     fn shard(&self, path: &Path) -> usize {
@@ -450,6 +707,7 @@ impl Store {
         &self,
         con: &mut Channel,
         uifo: Arc<UserInfo>,
+        client: IpAddr,
         mut msgs: impl Iterator<Item = ToRead>,
     ) -> Result<()> {
         let mut finished = false;
@@ -468,6 +726,11 @@ impl Store {
                         by_shard[s].push((n, ToRead::Resolve(path)));
                         c += 1;
                     }
+                    Some(ToRead::Metadata(path)) => {
+                        let s = self.shard(&path);
+                        by_shard[s].push((n, ToRead::Metadata(path)));
+                        c += 1;
+                    }
                     Some(ToRead::GetChangeNr(path)) => {
                         for b in by_shard.iter_mut() {
                             b.push((n, ToRead::GetChangeNr(path.clone())));
@@ -480,6 +743,12 @@ impl Store {
                         }
                         c += 10000;
                     }
+                    Some(ToRead::ListWithMeta(path)) => {
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToRead::ListWithMeta(path.clone())));
+                        }
+                        c += 10000;
+                    }
                     Some(ToRead::Table(path)) => {
                         for b in by_shard.iter_mut() {
                             b.push((n, ToRead::Table(path.clone())));
@@ -492,6 +761,14 @@ impl Store {
                         }
                         c += 100000;
                     }
+                    Some(ToRead::ResolvedBy(addr)) => {
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToRead::ResolvedBy(addr)));
+                        }
+                        c += 10000;
+                    }
+                    // watches and admin requests are handled by the connection
+                    Some(ToRead::Watch(_) | ToRead::Admin(_)) => continue,
                 }
                 n += 1;
             }
@@ -513,7 +790,8 @@ impl Store {
             for r in replies.iter_mut() {
                 publishers.extend(r.publishers.drain());
             }
-            for (_, p) in publishers.drain() {
+            for (_, mut p) in publishers.drain() {
+                order_addrs(&mut p, client);
                 con.queue_send(&FromRead::Publisher(p))?;
             }
             let mut replies = {
@@ -543,6 +821,10 @@ impl Store {
                     match replies[0].pop_front().unwrap() {
                         (_, FromRead::Publisher(_)) => unreachable!(),
                         (_, FromRead::Resolved(_)) => unreachable!(),
+                        (_, FromRead::Metadata(_)) => unreachable!(),
+                        (_, FromRead::Migrate(_)) => unreachable!(),
+                        (_, FromRead::Changed(_)) => unreachable!(),
+                        (_, FromRead::Admin(_)) => unreachable!(),
                         (_, m @ FromRead::Referral(_)) => {
                             same!(con, replies, &m, "desynced referral");
                         }
@@ -571,6 +853,32 @@ impl Store {
                             paths.extend(hpaths.drain());
                             con.queue_send(&FromRead::List(paths))?;
                         }
+                        (_, FromRead::ListWithMeta(mut entries)) => {
+                            let mut by_path = ENTRY_HPOOL.take();
+                            for i in 0..replies.len() {
+                                if i > 0 {
+                                    match replies[i].pop_front().unwrap() {
+                                        (_, FromRead::ListWithMeta(e)) => entries = e,
+                                        _ => panic!("desynced list with meta"),
+                                    }
+                                }
+                                for e in entries.drain(..) {
+                                    match by_path.get_mut(&e.path) {
+                                        None => {
+                                            by_path.insert(e.path.clone(), e);
+                                        }
+                                        Some(cur) => {
+                                            cur.publishers += e.publishers;
+                                            cur.has_children |= e.has_children;
+                                            cur.flags |= e.flags;
+                                        }
+                                    }
+                                }
+                            }
+                            let mut entries = LIST_ENTRY_POOL.take();
+                            entries.extend(by_path.drain().map(|(_, e)| e));
+                            con.queue_send(&FromRead::ListWithMeta(entries))?;
+                        }
                         (_, FromRead::ListMatching(mut lm)) => {
                             let referrals = lm.referrals;
                             let mut matched = PATH_BPOOL.take();
@@ -643,20 +951,30 @@ impl Store {
         }
     }
 
+    /// Apply the writes in `msgs`, replying on `con` if it is given.
+    /// Return the number of distinct non default paths the publisher
+    /// has afterwards, or None if there was nothing to write.
     pub(super) async fn handle_batch_write(
         &self,
         mut con: Option<&mut Channel>,
         uifo: Arc<UserInfo>,
         publisher: Arc<Publisher>,
         mut msgs: impl Iterator<Item = ToWrite>,
-    ) -> Result<()> {
+    ) -> Result<Option<usize>> {
 	trace!("handling write from {:?}", &publisher);
         let mut finished = false;
+        let mut published = None;
+        let watched = self.changes.receiver_count() > 0;
+        let mut changed = Vec::new();
         loop {
             let mut n = 0;
             let mut by_shard = self.write_shard_batch();
             for _ in 0..MAX_WRITE_BATCH {
-                match msgs.next() {
+                let m = msgs.next();
+                if watched {
+                    changed.extend(m.as_ref().and_then(changed_path));
+                }
+                match m {
                     None => {
                         finished = true;
                         break;
@@ -680,6 +998,11 @@ impl Store {
                             b.push((n, ToWrite::UnpublishDefault(path.clone())));
                         }
                     }
+                    Some(ToWrite::UnpublishPrefix(path)) => {
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToWrite::UnpublishPrefix(path.clone())));
+                        }
+                    }
                     Some(ToWrite::PublishDefault(path)) => {
                         for b in by_shard.iter_mut() {
                             b.push((n, ToWrite::PublishDefault(path.clone())));
@@ -689,6 +1012,11 @@ impl Store {
                         let s = self.shard(&path);
                         by_shard[s].push((n, ToWrite::PublishWithFlags(path, flags)));
                     }
+                    Some(ToWrite::PublishWithMetadata(path, flags, meta)) => {
+                        let s = self.shard(&path);
+                        let m = ToWrite::PublishWithMetadata(path, flags, meta);
+                        by_shard[s].push((n, m));
+                    }
                     Some(ToWrite::PublishDefaultWithFlags(path, flags)) => {
                         for b in by_shard.iter_mut() {
                             b.push((
@@ -703,9 +1031,9 @@ impl Store {
 	    trace!("handle_write_batch dispatching {} messages to shards", n);
             if by_shard.iter().all(|v| v.is_empty()) {
                 assert!(finished);
-                break Ok(());
+                break Ok(published);
             }
-            let mut replies =
+            let all_replies =
                 join_all(by_shard.drain(..).enumerate().map(|(i, batch)| {
                     let (tx, rx) = oneshot::channel();
                     let publisher = publisher.clone();
@@ -715,8 +1043,13 @@ impl Store {
                 }))
                 .await
                 .into_iter()
-                .collect::<result::Result<Vec<Pooled<WriteR>>, Canceled>>()?;
+                .collect::<result::Result<Vec<WriteResponse>, Canceled>>()?;
+            published = Some(all_replies.iter().map(|r| r.published).sum());
+            let mut replies = all_replies.into_iter().map(|r| r.batch).collect::<Vec<_>>();
 	    trace!("handle_write_batch {} shards replied", replies.len());
+            if !changed.is_empty() {
+                let _ = self.changes.send(Arc::new(mem::take(&mut changed)));
+            }
             if let Some(ref mut c) = con {
                 for i in 0..n {
                     if replies.len() == 1
@@ -752,6 +1085,9 @@ impl Store {
                             (_, m @ FromWrite::Unpublished) => {
                                 same!(c, replies, &m, "desynced unpublish");
                             }
+                            (_, FromWrite::Migrate(_) | FromWrite::Heartbeat) => {
+                                unreachable!()
+                            }
                         }
                     }
                 }
@@ -760,11 +1096,26 @@ impl Store {
 	    trace!("handle_write_batch processed replies");
             if finished {
 		trace!("handle_write_batch finished");
-                break Ok(());
+                break Ok(published);
             }
         }
     }
 
+    /// Everything published in the store, in no particular order
+    pub(super) async fn dump(&self) -> Result<Pooled<Vec<StoreEntry>>> {
+        let replies = join_all(self.shards.iter().map(|shard| {
+            let (tx, rx) = oneshot::channel();
+            let _ = shard.dump.unbounded_send(tx);
+            rx
+        }))
+        .await;
+        let mut entries = STORE_ENTRY_POOL.take();
+        for r in replies {
+            entries.extend(r?);
+        }
+        Ok(entries)
+    }
+
     pub(super) async fn handle_clear(
         &self,
         uifo: Arc<UserInfo>,
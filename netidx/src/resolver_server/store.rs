@@ -3,12 +3,17 @@ use super::{
     secctx::SecCtxDataReadGuard,
 };
 use crate::{
+    chars::Chars,
     pack::Z64,
     path::Path,
     pool::{Pool, Pooled},
     protocol::{
         glob::{GlobSet, Scope},
-        resolver::{Publisher, PublisherId, PublisherRef, Referral},
+        resolver::{
+            ListEntry, Metadata, Publisher, PublisherId, PublisherRef, Referral,
+            StoreEntry,
+        },
+        value::Value,
     },
     utils,
 };
@@ -38,6 +43,8 @@ lazy_static! {
     pub(super) static ref PATH_POOL: Pool<Vec<Path>> = Pool::new(100, 10_000);
     pub(super) static ref COLS_POOL: Pool<Vec<(Path, Z64)>> = Pool::new(100, 10_000);
     pub(super) static ref REF_POOL: Pool<Vec<Referral>> = Pool::new(100, 100);
+    pub(super) static ref LIST_ENTRY_POOL: Pool<Vec<ListEntry>> = Pool::new(100, 10_000);
+    static ref METADATA_POOL: Pool<Vec<(Chars, Value)>> = Pool::new(100, 1_000);
 }
 
 type Set<T> = ISet<T, 8>;
@@ -127,6 +134,7 @@ pub(super) struct Store {
     publishers_by_addr: FxHashMap<SocketAddr, PublisherId>,
     published_by_path: HashMap<Path, Set<PublisherId>>,
     flags_by_path: HashMap<Path, u32>,
+    metadata_by_path: HashMap<Path, Metadata>,
     published_by_id: FxHashMap<PublisherId, HashSet<Path>>,
     published_by_level: FxHashMap<usize, BTreeMap<Path, Z64>>,
     columns: HashMap<Path, HashMap<Path, Z64>>,
@@ -134,6 +142,7 @@ pub(super) struct Store {
     defaults_by_id: FxHashMap<PublisherId, HashSet<Path>>,
     parent: Option<Referral>,
     children: BTreeMap<Path, Referral>,
+    placeholders: BTreeMap<Path, Metadata>,
     sets: HCSet<PublisherId>,
 }
 
@@ -141,12 +150,14 @@ impl Store {
     pub(super) fn new(
         parent: Option<Referral>,
         children: BTreeMap<Path, Referral>,
+        placeholders: BTreeMap<Path, Metadata>,
     ) -> Self {
         let mut t = Store {
             publishers_by_id: HashMap::default(),
             publishers_by_addr: HashMap::default(),
             published_by_path: HashMap::default(),
             flags_by_path: HashMap::default(),
+            metadata_by_path: HashMap::default(),
             published_by_id: HashMap::default(),
             published_by_level: HashMap::default(),
             columns: HashMap::new(),
@@ -154,6 +165,7 @@ impl Store {
             defaults_by_id: HashMap::default(),
             parent,
             children,
+            placeholders,
             sets: HCSet::new(),
         };
         let children = t.children.keys().cloned().collect::<Vec<_>>();
@@ -165,6 +177,11 @@ impl Store {
             // even be added anywhere.
             t.add_parents(child.append("z").as_ref());
         }
+        // placeholders are added the same way, and are never removed
+        let placeholders = t.placeholders.keys().cloned().collect::<Vec<_>>();
+        for path in placeholders {
+            t.add_parents(path.append("z").as_ref());
+        }
         t
     }
 
@@ -173,6 +190,7 @@ impl Store {
 	self.publishers_by_addr.shrink_to_fit();
 	self.published_by_path.shrink_to_fit();
 	self.flags_by_path.shrink_to_fit();
+	self.metadata_by_path.shrink_to_fit();
 	self.published_by_id.shrink_to_fit();
 	for v in self.published_by_id.values_mut() {
 	    v.shrink_to_fit()
@@ -189,6 +207,23 @@ impl Store {
 	self.sets.gc()
     }
 
+    /// True if `p`, which is at level `n`, has children in
+    /// `published_by_level`
+    fn has_children(&self, p: &str, n: usize) -> bool {
+        with_trailing(p, |tmp| {
+            self.published_by_level
+                .get(&(n + 1))
+                .map(|l| {
+                    let mut r = l.range::<str, (Bound<&str>, Bound<&str>)>((
+                        Excluded(tmp),
+                        Unbounded,
+                    ));
+                    r.next().map(|(o, _)| Path::is_parent(p, o)).unwrap_or(false)
+                })
+                .unwrap_or(false)
+        })
+    }
+
     fn remove_parents(&mut self, mut p: &str) {
         let mut save = false;
         loop {
@@ -198,20 +233,8 @@ impl Store {
                     || self.published_by_path.contains_key(p)
                     || self.defaults.contains_key(p)
                     || self.children.contains_key(p)
-                    || with_trailing(p, |tmp| {
-                        self.published_by_level
-                            .get(&(n + 1))
-                            .map(|l| {
-                                let mut r = l.range::<str, (Bound<&str>, Bound<&str>)>((
-                                    Excluded(tmp),
-                                    Unbounded,
-                                ));
-                                r.next()
-                                    .map(|(o, _)| Path::is_parent(p, o))
-                                    .unwrap_or(false)
-                            })
-                            .unwrap_or(false)
-                    });
+                    || self.placeholders.contains_key(p)
+                    || self.has_children(p, n);
             }
             if save {
                 let m = self.published_by_level.entry(n).or_insert_with(BTreeMap::new);
@@ -480,9 +503,14 @@ impl Store {
                 && !self.defaults.contains_key(&path)
             {
                 self.flags_by_path.remove(&path);
-                if let Some(s) = self.published_by_level.get_mut(&n) {
-                    s.remove(&path);
-                };
+                self.metadata_by_path.remove(&path);
+                // a path that is no longer published is still listed
+                // as long as something is published under it
+                if !self.has_children(&path, n) {
+                    if let Some(s) = self.published_by_level.get_mut(&n) {
+                        s.remove(&path);
+                    };
+                }
             }
             if !self.defaults_by_id.contains_key(&publisher.id)
                 && !self.published_by_id.contains_key(&publisher.id)
@@ -497,6 +525,30 @@ impl Store {
         self.published_by_id.get(id).map(|s| s.clone()).unwrap_or_else(HashSet::new)
     }
 
+    /// True if `path` is published, not as a default, by `id`
+    pub(super) fn is_published(&self, id: &PublisherId, path: &Path) -> bool {
+        self.published_by_id.get(id).map(|s| s.contains(path)).unwrap_or(false)
+    }
+
+    /// The number of paths published, not as a default, by `id`
+    pub(super) fn published_count(&self, id: &PublisherId) -> usize {
+        self.published_by_id.get(id).map(|s| s.len()).unwrap_or(0)
+    }
+
+    /// The paths, including defaults, published by the publisher
+    /// at `addr`
+    pub(super) fn published_by_addr(&self, addr: &SocketAddr) -> Pooled<Vec<Path>> {
+        let mut paths = PATH_POOL.take();
+        if let Some(id) = self.publishers_by_addr.get(addr) {
+            for by_id in [&self.published_by_id, &self.defaults_by_id] {
+                if let Some(s) = by_id.get(id) {
+                    paths.extend(s.iter().cloned())
+                }
+            }
+        }
+        paths
+    }
+
     fn defaults_for_id(&self, id: &PublisherId) -> HashSet<Path> {
         self.defaults_by_id.get(id).map(|s| s.clone()).unwrap_or_else(HashSet::new)
     }
@@ -510,10 +562,79 @@ impl Store {
         }
     }
 
+    /// Unpublish everything `publisher` published at or below
+    /// `prefix`, including defaults. Return the number of non default
+    /// paths that were unpublished.
+    pub(super) fn unpublish_prefix(
+        &mut self,
+        publisher: &Arc<Publisher>,
+        prefix: &Path,
+    ) -> usize {
+        let under = |s: &HashSet<Path>| -> Vec<Path> {
+            s.iter().filter(|p| Path::is_parent(prefix, p)).cloned().collect()
+        };
+        let published =
+            self.published_by_id.get(&publisher.id).map(under).unwrap_or_default();
+        let defaults =
+            self.defaults_by_id.get(&publisher.id).map(under).unwrap_or_default();
+        let n = published.len();
+        for path in published {
+            self.unpublish(publisher, false, path);
+        }
+        for path in defaults {
+            self.unpublish(publisher, true, path);
+        }
+        n
+    }
+
+    /// Append everything published in the store to `entries`. Default
+    /// publishers are present in every shard, so they are only
+    /// included if `defaults` is true.
+    pub(super) fn dump(&self, entries: &mut Vec<StoreEntry>, defaults: bool) {
+        let mut add = |path: &Path, pubs: &Set<PublisherId>, default: bool| {
+            let flags = self.get_flags(path);
+            for id in pubs {
+                if let Some(publisher) = self.publishers_by_id.get(id) {
+                    entries.push(StoreEntry {
+                        path: path.clone(),
+                        publisher: publisher.addr,
+                        default,
+                        flags,
+                    })
+                }
+            }
+        };
+        for (path, pubs) in &self.published_by_path {
+            add(path, pubs, false)
+        }
+        if defaults {
+            for (path, pubs) in &self.defaults {
+                add(path, pubs, true)
+            }
+        }
+    }
+
     fn get_flags(&self, path: &str) -> u32 {
         self.flags_by_path.get(path).copied().unwrap_or(0)
     }
 
+    /// Replace the metadata of `path`, which must be published.
+    /// Empty metadata removes it.
+    pub(super) fn set_metadata(&mut self, path: Path, meta: Metadata) {
+        if meta.is_empty() {
+            self.metadata_by_path.remove(&path);
+        } else {
+            self.metadata_by_path.insert(path, meta);
+        }
+    }
+
+    pub(super) fn metadata(&self, path: &str) -> Metadata {
+        match self.metadata_by_path.get(path).or_else(|| self.placeholders.get(path)) {
+            Some(meta) => meta.clone(),
+            None => METADATA_POOL.take(),
+        }
+    }
+
     fn record_publisher(
         &self,
         sec: Option<(&SecCtxDataReadGuard, &UserInfo)>,
@@ -679,6 +800,46 @@ impl Store {
         })
     }
 
+    /// list the children of `parent` along with what this shard
+    /// knows about each one. Permissions are left empty for the
+    /// caller to fill in. Default publishers are present in every
+    /// shard, so they are only counted if `defaults` is true.
+    pub(super) fn list_with_meta(
+        &self,
+        parent: &Path,
+        defaults: bool,
+    ) -> Pooled<Vec<ListEntry>> {
+        let mut entries = LIST_ENTRY_POOL.take();
+        let grandchildren = self.published_by_level.get(&(Path::levels(parent) + 2));
+        for path in self.list(parent).drain(..) {
+            let count = |s: Option<&Set<PublisherId>>| s.map(|s| s.len()).unwrap_or(0);
+            let publishers = count(self.published_by_path.get(&path))
+                + if defaults { count(self.defaults.get(&path)) } else { 0 };
+            let has_children = grandchildren
+                .map(|l| {
+                    with_trailing(&*path, |tmp| {
+                        l.range::<str, (Bound<&str>, Bound<&str>)>((
+                            Excluded(tmp),
+                            Unbounded,
+                        ))
+                        .next()
+                        .map(|(p, _)| Path::is_parent(&path, p))
+                        .unwrap_or(false)
+                    })
+                })
+                .unwrap_or(false);
+            let flags = self.get_flags(&path);
+            entries.push(ListEntry {
+                publishers: publishers as u32,
+                has_children,
+                flags,
+                permissions: 0,
+                path,
+            })
+        }
+        entries
+    }
+
     pub(super) fn list_matching(&self, pat: &GlobSet) -> Pooled<Vec<Path>> {
         let mut paths = PATH_POOL.take();
         let mut cur: Option<&str> = None;
@@ -1,15 +1,28 @@
-use super::store::Store;
+use super::{
+    auth::ANONYMOUS,
+    config::{
+        file::{Quotas as QuotaLimits, RateLimits},
+        Config,
+    },
+    limits::{Limit, Limiter, Quotas},
+    store::Store,
+    LimitStats,
+};
 use crate::{
     pack::Z64,
     path::Path,
-    protocol::resolver::{HashMethod, Publisher, PublisherId, PublisherRef, TargetAuth},
+    protocol::{
+        resolver::{HashMethod, Publisher, PublisherId, PublisherRef, TargetAuth},
+        value::Value,
+    },
 };
 use bytes::Bytes;
 use fxhash::FxHashMap;
 use rand::{self, thread_rng, Rng};
+use smallvec::smallvec;
 use std::{
     collections::{BTreeMap, HashMap},
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
 };
 
@@ -28,7 +41,7 @@ fn test_resolver_store() {
             "127.0.0.1:105",
         ),
     ];
-    let mut store = Store::new(None, BTreeMap::new());
+    let mut store = Store::new(None, BTreeMap::new(), BTreeMap::new());
     for (paths, addr) in &apps {
         let parsed = paths.iter().map(|p| Path::from(*p)).collect::<Vec<_>>();
         let addr = addr.parse::<SocketAddr>().unwrap();
@@ -39,6 +52,8 @@ fn test_resolver_store() {
             resolver: addr,
             target_auth: TargetAuth::Anonymous,
             user_info: None,
+            local_endpoint: None,
+            addrs: smallvec![addr],
         });
         if thread_rng().gen() {
             let path = Path::from(String::from(Path::dirname(&parsed[0]).unwrap()));
@@ -181,3 +196,199 @@ fn test_resolver_store() {
     let cols = store.columns(&Path::from("/app/test"));
     assert_eq!(cols.len(), 0);
 }
+
+#[test]
+fn test_rate_limits() {
+    let limiter = Limiter::new(RateLimits {
+        reads_per_sec: 10,
+        max_paths_per_writer: 100,
+        ..RateLimits::default()
+    });
+    let ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let other: IpAddr = "127.0.0.2".parse().unwrap();
+    assert!(limiter.check(ip, Limit::Hello, 1000).is_ok());
+    assert!(limiter.check(ip, Limit::Read, 15).is_ok());
+    assert!(limiter.check(ip, Limit::Read, 1).is_err());
+    assert!(limiter.check(ip, Limit::Hello, 1).is_err());
+    assert!(limiter.check(other, Limit::Read, 5).is_ok());
+    assert!(limiter.check_paths(other, 100).is_ok());
+    assert!(limiter.check_paths(other, 101).is_err());
+    assert!(limiter.check(other, Limit::Read, 1).is_err());
+    assert_eq!(limiter.stats(), LimitStats { throttled: 4, bans: 2, banned: 2 });
+}
+
+#[test]
+fn test_quotas() {
+    let quotas = Quotas::new(QuotaLimits {
+        max_paths_per_user: 1,
+        max_paths_per_ip: 2,
+        max_depth: 3,
+    });
+    let ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let other: IpAddr = "127.0.0.2".parse().unwrap();
+    assert!(quotas.check_depth(&Path::from("/a/b/c")).is_ok());
+    assert!(quotas.check_depth(&Path::from("/a/b/c/d")).is_err());
+    // anonymous users are only subject to the ip quota
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_err());
+    assert!(quotas.acquire(&ANONYMOUS, other).is_ok());
+    quotas.release(&ANONYMOUS, ip, 1);
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_err());
+}
+
+#[test]
+fn test_quota_reload() {
+    let limits = QuotaLimits { max_paths_per_user: 0, max_paths_per_ip: 1, max_depth: 0 };
+    let quotas = Quotas::new(limits);
+    let ip: IpAddr = "127.0.0.1".parse().unwrap();
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_err());
+    quotas.set_limits(QuotaLimits { max_paths_per_ip: 2, max_depth: 2, ..limits });
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_err());
+    assert!(quotas.check_depth(&Path::from("/a/b/c")).is_err());
+    // disabling the quotas forgets the paths counted so far
+    quotas.set_limits(QuotaLimits::default());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    quotas.set_limits(limits);
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_ok());
+    assert!(quotas.acquire(&ANONYMOUS, ip).is_err());
+}
+
+#[test]
+fn test_shards_config() {
+    let cfg = |shards: &str| {
+        let s = format!(
+            r#"{{
+  "parent": null,
+  "children": [],
+  "member_servers": [{{
+    "pid_file": "",
+    "addr": "127.0.0.1:0",
+    "max_connections": 768,
+    "hello_timeout": 10,
+    "reader_ttl": 60,
+    "writer_ttl": 120,
+    "auth": "Anonymous"
+    {}
+  }}],
+  "perms": {{}}
+}}"#,
+            shards
+        );
+        Config::parse(&s).unwrap().member_servers[0].shards
+    };
+    assert_eq!(cfg(""), num_cpus::get());
+    assert_eq!(cfg(r#", "shards": 0"#), num_cpus::get());
+    assert_eq!(cfg(r#", "shards": 3"#), 3);
+}
+
+#[test]
+fn test_placeholders() {
+    let cfg = |placeholders: &str| {
+        let s = format!(
+            r#"{{
+  "parent": null,
+  "children": [],
+  "member_servers": [{{
+    "pid_file": "",
+    "addr": "127.0.0.1:0",
+    "max_connections": 768,
+    "hello_timeout": 10,
+    "reader_ttl": 60,
+    "writer_ttl": 120,
+    "auth": "Anonymous"
+  }}],
+  "perms": {{}},
+  "placeholders": {}
+}}"#,
+            placeholders
+        );
+        Config::parse(&s)
+    };
+    assert!(cfg(r#"[{"path": "relative"}]"#).is_err());
+    assert!(cfg(r#"[{"path": "/"}]"#).is_err());
+    assert!(cfg(r#"[{"path": "/app"}, {"path": "/app"}]"#).is_err());
+    let cfg = cfg(r#"[
+  {"path": "/app/test", "metadata": [["doc", {"type": "String", "value": "tests"}]]},
+  {"path": "/sys"}
+]"#)
+    .unwrap();
+    let mut store = Store::new(None, BTreeMap::new(), cfg.placeholders.clone());
+    let paths = store.list(&Path::from("/"));
+    assert_eq!(paths.len(), 2);
+    assert_eq!(paths[0].as_ref(), "/app");
+    assert_eq!(paths[1].as_ref(), "/sys");
+    let entries = store.list_with_meta(&Path::from("/app"), true);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path.as_ref(), "/app/test");
+    assert_eq!(entries[0].publishers, 0);
+    assert!(!entries[0].has_children);
+    let meta = store.metadata("/app/test");
+    assert_eq!(meta.len(), 1);
+    assert_eq!(meta[0].0.as_ref(), "doc");
+    assert_eq!(meta[0].1, Value::from("tests"));
+    assert_eq!(store.metadata("/sys").len(), 0);
+    // placeholders survive things being published and unpublished under them
+    let addr = "127.0.0.1:100".parse::<SocketAddr>().unwrap();
+    let publisher = Arc::new(Publisher {
+        id: PublisherId::new(),
+        addr,
+        hash_method: HashMethod::Sha3_512,
+        resolver: addr,
+        target_auth: TargetAuth::Anonymous,
+        user_info: None,
+        local_endpoint: None,
+        addrs: smallvec![addr],
+    });
+    let path = Path::from("/app/test/v0");
+    store.publish(path.clone(), &publisher, false, None);
+    assert!(store.list_with_meta(&Path::from("/app"), true)[0].has_children);
+    store.unpublish(&publisher, false, path);
+    assert_eq!(store.list(&Path::from("/app/test")).len(), 0);
+    let paths = store.list(&Path::from("/app"));
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].as_ref(), "/app/test");
+    assert_eq!(store.metadata("/app/test").len(), 1);
+}
+
+#[test]
+fn test_unpublish_prefix() {
+    let publisher = |addr: &str| {
+        let addr = addr.parse::<SocketAddr>().unwrap();
+        Arc::new(Publisher {
+            id: PublisherId::new(),
+            addr,
+            hash_method: HashMethod::Sha3_512,
+            resolver: addr,
+            target_auth: TargetAuth::Anonymous,
+            user_info: None,
+            local_endpoint: None,
+            addrs: smallvec![addr],
+        })
+    };
+    let p0 = publisher("127.0.0.1:100");
+    let p1 = publisher("127.0.0.1:101");
+    let mut store = Store::new(None, BTreeMap::new(), BTreeMap::new());
+    for path in ["/app/a", "/app/a/v0", "/app/a/b/v1", "/app/ab/v2", "/app/c/v3"] {
+        store.publish(Path::from(path), &p0, false, None);
+    }
+    store.publish(Path::from("/app/a/d"), &p0, true, None);
+    store.publish(Path::from("/app/a/v0"), &p1, false, None);
+    assert_eq!(store.unpublish_prefix(&p0, &Path::from("/app/a")), 3);
+    assert_eq!(store.published_count(&p0.id), 2);
+    assert!(store.is_published(&p0.id, &Path::from("/app/ab/v2")));
+    assert!(store.is_published(&p0.id, &Path::from("/app/c/v3")));
+    // other publishers of the same paths are not affected
+    assert!(store.is_published(&p1.id, &Path::from("/app/a/v0")));
+    let paths = store.list(&Path::from("/app/a"));
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].as_ref(), "/app/a/v0");
+    let paths = store.list(&Path::from("/app"));
+    assert_eq!(paths.len(), 3);
+    assert_eq!(store.unpublish_prefix(&p0, &Path::from("/app/a")), 0);
+    assert_eq!(store.unpublish_prefix(&p0, &Path::from("/")), 2);
+    assert_eq!(store.published_count(&p0.id), 0);
+}
@@ -1,18 +1,22 @@
 use super::{
-    ConId, DvDead, DvState, Event, NoSuchValue, PermissionDenied, SubId, SubStatus,
-    SubscribeValRequest, Subscriber, SubscriberInner, SubscriberWeak, ToCon,
-    UpdatesFlags, Val, ValInner, ValWeak, WUpdateChan, BATCHES, DECODE_BATCHES,
+    record::Recorder, use_tick, Charge, ConId, DvDead, DvState, Event, Memory,
+    NoSuchValue, PermissionDenied, Priority, SubId, SubStatus, SubscribeValRequest,
+    Subscriber, SubscriberInner, SubscriberWeak, SyncBatch, SyncChan, TagCounters, ToCon,
+    Updates, UpdatesFlags, Val, ValInner, ValWeak, WUpdateChan, BATCHES, DECODE_BATCHES,
 };
 pub use crate::protocol::value::{FromValue, Typ, Value};
 pub use crate::resolver_client::DesiredAuth;
 use crate::{
     batch_channel::BatchReceiver,
     channel::{self, Channel, K5CtxWrap, ReadChannel, WriteChannel},
+    config::SocketOpts,
+    os::local_transport,
+    pack::Pack,
     path::Path,
     pool::Pooled,
     protocol::{
         self,
-        publisher::{From, Id, To},
+        publisher::{Capabilities, Datagram, From, Id, To},
         resolver::TargetAuth,
     },
     resolver_client::common::krb5_authentication,
@@ -20,6 +24,8 @@ use crate::{
     utils::{ChanId, ChanWrap},
 };
 use anyhow::{anyhow, Error, Result};
+use arcstr::ArcStr;
+use chrono::prelude::*;
 use cross_krb5::ClientCtx;
 use futures::{
     channel::{
@@ -31,54 +37,235 @@ use futures::{
     stream::FuturesUnordered,
 };
 use fxhash::{FxHashMap, FxHashSet};
-use log::{info, trace};
+use log::{debug, info, trace};
 use parking_lot::Mutex;
 use protocol::resolver::UserInfo;
 use smallvec::SmallVec;
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     mem,
-    net::SocketAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::{
-    net::TcpStream,
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, UdpSocket},
     task,
     time::{self, Instant},
 };
 use triomphe::Arc as TArc;
 
-struct Sub {
-    path: Path,
-    sub_id: SubId,
-    streams: SmallVec<[(ChanId, ChanWrap<Pooled<Vec<(SubId, Event)>>>); 1]>,
-    last: Option<TArc<Mutex<Event>>>,
-    val: ValWeak,
+pub(super) struct Sub {
+    pub(super) path: Path,
+    pub(super) sub_id: SubId,
+    pub(super) streams:
+        SmallVec<[(ChanId, ChanWrap<Pooled<Vec<(SubId, Event)>>>, bool); 1]>,
+    pub(super) last: Option<TArc<Mutex<(Option<DateTime<Utc>>, Event)>>>,
+    pub(super) val: ValWeak,
+    pub(super) tag: Option<Arc<TagCounters>>,
+    pub(super) priority: Priority,
+    pub(super) filtered: bool,
+    pub(super) recorder: Option<Recorder>,
+    pub(super) groups: SmallVec<[SyncChan; 1]>,
+    // the size of last, counted against the memory budget
+    pub(super) charge: Charge,
+}
+
+impl Sub {
+    /// hold `m` for every sync group this subscription is in
+    fn queue_sync(&self, held: &mut Held, m: &Value) {
+        for g in self.groups.iter() {
+            held.entry(g.clone())
+                .or_insert_with(|| BATCHES.take())
+                .push((self.sub_id, Event::Update(m.clone())))
+        }
+    }
+
+    pub(super) fn queue_update(
+        &mut self,
+        by_chan: &mut ByChan,
+        ts: Option<DateTime<Utc>>,
+        m: Value,
+    ) {
+        if let Some(r) = &self.recorder {
+            r.record(ts, &self.path, Event::Update(m.clone()))
+        }
+        for (chan_id, c, timestamped) in self.streams.iter() {
+            let v = if *timestamped { with_timestamp(ts, m.clone()) } else { m.clone() };
+            by_chan
+                .entry(*chan_id)
+                .or_insert_with(|| (c.clone(), BATCHES.take()))
+                .1
+                .push((self.sub_id, Event::Update(v)))
+        }
+        if let Some(last) = &self.last {
+            self.charge.set(Pack::encoded_len(&m));
+            *last.lock() = (ts, Event::Update(m));
+        }
+    }
+}
+
+pub(super) fn with_timestamp(ts: Option<DateTime<Utc>>, v: Value) -> Value {
+    let ts = ts.unwrap_or_else(Utc::now);
+    Value::from(vec![Value::DateTime(ts), v])
 }
 
-type ByChan = FxHashMap<
+pub(super) type ByChan = FxHashMap<
     ChanId,
     (ChanWrap<Pooled<Vec<(SubId, Event)>>>, Pooled<Vec<(SubId, Event)>>),
 >;
 
-fn unsubscribe(
+type Held = FxHashMap<SyncChan, Pooled<Vec<(SubId, Event)>>>;
+
+/// Finish sending `batch` to the full channel `tx`, counting it
+/// against the memory budget until it is sent.
+fn send_blocked(
+    memory: &Arc<Memory>,
+    mut tx: WUpdateChan,
+    batch: Pooled<Vec<(SubId, Event)>>,
+) -> BlockedChannelFut {
+    let bytes = batch.iter().map(|(_, ev)| Pack::encoded_len(ev)).sum();
+    let charge = Charge::new(memory, bytes);
+    Box::pin(async move {
+        let _ = tx.0.send(batch).await;
+        drop(charge)
+    })
+}
+
+fn batch_len(batch: &Updates) -> usize {
+    batch.iter().map(|(_, ev)| Pack::encoded_len(ev)).sum()
+}
+
+/// Updates for a full channel registered with COALESCE or UNBOUNDED,
+/// waiting for it to have room.
+struct Overflow {
+    chan: WUpdateChan,
+    coalesce: bool,
+    queued: VecDeque<Updates>,
+    // when coalescing everything is queued in one batch, this is the
+    // position of the last event of each subscription in it
+    latest: FxHashMap<SubId, usize>,
+    charge: Charge,
+}
+
+impl Overflow {
+    fn new(memory: &Arc<Memory>, chan: WUpdateChan, coalesce: bool) -> Self {
+        Self {
+            chan,
+            coalesce,
+            queued: VecDeque::new(),
+            latest: HashMap::default(),
+            charge: Charge::new(memory, 0),
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.latest.clear();
+        if let Some(b) = self.queued.front() {
+            for (i, (sub_id, _)) in b.iter().enumerate() {
+                self.latest.insert(*sub_id, i);
+            }
+        }
+    }
+
+    fn push(&mut self, mut batch: Updates) {
+        if batch.is_empty() {
+            return;
+        }
+        let mut bytes = self.charge.bytes;
+        match self.queued.back_mut() {
+            Some(q) if self.coalesce => {
+                let update = |ev: &Event| matches!(ev, Event::Update(_));
+                for (sub_id, ev) in batch.drain(..) {
+                    bytes += Pack::encoded_len(&ev);
+                    match self.latest.get(&sub_id).copied() {
+                        Some(i) if update(&q[i].1) && update(&ev) => {
+                            bytes -= Pack::encoded_len(&q[i].1);
+                            q[i].1 = ev;
+                        }
+                        Some(_) | None => {
+                            self.latest.insert(sub_id, q.len());
+                            q.push((sub_id, ev));
+                        }
+                    }
+                }
+            }
+            Some(_) | None => {
+                bytes += batch_len(&batch);
+                self.queued.push_back(batch);
+                if self.coalesce {
+                    self.reindex()
+                }
+            }
+        }
+        self.charge.set(bytes)
+    }
+
+    fn pop(&mut self) -> Option<Updates> {
+        let batch = self.queued.pop_front()?;
+        self.latest.clear();
+        self.charge.set(self.charge.bytes - batch_len(&batch));
+        Some(batch)
+    }
+
+    fn requeue(&mut self, batch: Updates) {
+        self.charge.set(self.charge.bytes + batch_len(&batch));
+        self.queued.push_front(batch);
+        if self.coalesce {
+            self.reindex()
+        }
+    }
+
+    /// wait for the channel to take everything that is still queued
+    async fn finish(mut self) {
+        while let Some(batch) = self.pop() {
+            if self.chan.0.send(batch).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+type ReadyFut =
+    Pin<Box<dyn Future<Output = (ChanId, WUpdateChan, bool)> + Send + Sync + 'static>>;
+
+/// Wait for `chan` to have room, false if it was closed instead.
+fn wait_ready(id: ChanId, mut chan: WUpdateChan) -> ReadyFut {
+    Box::pin(async move {
+        let ok = future::poll_fn(|cx| chan.0.poll_ready(cx)).await.is_ok();
+        (id, chan, ok)
+    })
+}
+
+pub(super) fn unsubscribe(
     subscriber: &mut SubscriberInner,
     by_chan: &mut ByChan,
     sub: Sub,
     id: Id,
     conid: ConId,
+    unpublished: Option<Value>,
 ) {
-    for (chan_id, c) in sub.streams.iter() {
+    let ev = match &unpublished {
+        None => Event::Unsubscribed,
+        Some(v) => Event::Unpublished(v.clone()),
+    };
+    if let Some(r) = &sub.recorder {
+        r.record(None, &sub.path, ev.clone())
+    }
+    for (chan_id, c, _) in sub.streams.iter() {
         by_chan
             .entry(*chan_id)
             .or_insert_with(|| (c.clone(), BATCHES.take()))
             .1
-            .push((sub.sub_id, Event::Unsubscribed))
+            .push((sub.sub_id, ev.clone()))
     }
     if let Some(last) = &sub.last {
-        *last.lock() = Event::Unsubscribed;
+        *last.lock() = (None, ev);
     }
     if let Some(dsw) = subscriber
         .durable_alive
@@ -92,12 +279,13 @@ fn unsubscribe(
                 waiting: Vec::new(),
                 tries: 0,
                 next_try: Instant::now(),
+                unpublished,
             }));
             subscriber.durable_dead.insert(sub.path.clone(), dsw);
             let _ = subscriber.trigger_resub.unbounded_send(());
         }
     }
-    match subscriber.subscribed.entry(sub.path) {
+    match subscriber.subscribed.entry(sub.path.clone()) {
         Entry::Vacant(_) => (),
         Entry::Occupied(e) => match e.get() {
             SubStatus::Pending(_) => (),
@@ -113,28 +301,34 @@ fn unsubscribe(
             },
         },
     }
+    subscriber.prune(&sub.path)
 }
 
-async fn hello_publisher(
-    mut con: TcpStream,
+async fn hello_publisher<S>(
+    mut con: S,
     tls_ctx: Option<tls::CachedConnector>,
     uifo: Option<UserInfo>,
     desired_auth: &DesiredAuth,
     target_auth: &TargetAuth,
-) -> Result<Channel> {
+) -> Result<(Channel, Capabilities)>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     use protocol::publisher::Hello;
+    let caps = Capabilities::CURRENT;
     channel::write_raw(&mut con, &3u64).await?;
     if channel::read_raw::<u64, _>(&mut con).await? != 3 {
         bail!("incompatible protocol version")
     }
     match (desired_auth, target_auth) {
         (DesiredAuth::Anonymous, TargetAuth::Anonymous) => {
-            channel::write_raw(&mut con, &Hello::Anonymous).await?;
+            channel::write_raw(&mut con, &Hello::Anonymous(caps)).await?;
             match channel::read_raw(&mut con).await? {
-                Hello::Anonymous => (),
+                Hello::Anonymous(theirs) => {
+                    Ok((Channel::new::<ClientCtx, S>(None, con), caps.negotiate(&theirs)))
+                }
                 _ => bail!("unexpected response from publisher"),
             }
-            Ok(Channel::new::<ClientCtx, TcpStream>(None, con))
         }
         (
             DesiredAuth::Anonymous,
@@ -152,49 +346,52 @@ async fn hello_publisher(
             DesiredAuth::Local | DesiredAuth::Krb5 { .. } | DesiredAuth::Tls { .. },
             TargetAuth::Local,
         ) => {
-            channel::write_raw(&mut con, &Hello::Local(uifo)).await?;
+            channel::write_raw(&mut con, &Hello::Local(uifo, caps)).await?;
             match channel::read_raw(&mut con).await? {
-                Hello::Local(_) => (),
+                Hello::Local(_, theirs) => {
+                    Ok((Channel::new::<ClientCtx, S>(None, con), caps.negotiate(&theirs)))
+                }
                 _ => bail!("unexpected response from publisher"),
             }
-            Ok(Channel::new::<ClientCtx, TcpStream>(None, con))
         }
         (DesiredAuth::Local, TargetAuth::Krb5 { .. } | TargetAuth::Tls { .. }) => {
             bail!("local auth not supported")
         }
         (DesiredAuth::Krb5 { upn, .. }, TargetAuth::Krb5 { spn }) => {
             let upn = upn.as_ref().map(|p| p.as_str());
-            channel::write_raw(&mut con, &Hello::Krb5(uifo)).await?;
+            channel::write_raw(&mut con, &Hello::Krb5(uifo, caps)).await?;
             let ctx = krb5_authentication(upn, spn, &mut con).await?;
             let mut con = Channel::new(Some(K5CtxWrap::new(ctx)), con);
             match con.receive::<Hello>().await? {
-                Hello::Krb5(_) => (),
+                Hello::Krb5(_, theirs) => {
+                    Ok((con, caps.negotiate(&theirs).without(Capabilities::ZSTD)))
+                }
                 _ => bail!("protocol error"),
             }
-            Ok(con)
         }
         (DesiredAuth::Krb5 { .. }, TargetAuth::Tls { .. }) => {
             bail!("desired authentication mechanism not supported")
         }
-        (DesiredAuth::Tls { .. }, TargetAuth::Tls { name }) => {
+        (DesiredAuth::Tls { identity }, TargetAuth::Tls { name }) => {
             let tls = tls_ctx.clone().ok_or_else(|| anyhow!("no tls ctx"))?;
+            // use the identity an auth override asked for, otherwise
+            // the one matching the publisher's name
             let ctx = task::spawn_blocking({
-                let name = name.clone();
-                move || tls.load(&name)
+                let identity = identity.clone().unwrap_or_else(|| String::from(&**name));
+                move || tls.load(&identity)
             })
             .await??;
             let name = rustls::ServerName::try_from(&**name)?;
-            channel::write_raw(&mut con, &Hello::Tls(uifo)).await?;
+            channel::write_raw(&mut con, &Hello::Tls(uifo, caps)).await?;
             let tls = ctx.connect(name, con).await?;
-            let mut con = Channel::new::<
-                ClientCtx,
-                tokio_rustls::client::TlsStream<TcpStream>,
-            >(None, tls);
+            let mut con =
+                Channel::new::<ClientCtx, tokio_rustls::client::TlsStream<S>>(None, tls);
             match con.receive::<Hello>().await? {
-                Hello::Tls(_) => (),
+                Hello::Tls(_, theirs) => {
+                    Ok((con, caps.negotiate(&theirs).without(Capabilities::ZSTD)))
+                }
                 _ => bail!("protocol error"),
             }
-            Ok(con)
         }
         (DesiredAuth::Tls { .. }, TargetAuth::Krb5 { .. }) => {
             bail!("desired authentication mechanism not supported")
@@ -204,8 +401,15 @@ async fn hello_publisher(
 
 const PERIOD: Duration = Duration::from_secs(100);
 
+// the most normal priority updates held back while we are behind,
+// past this the backlog is delivered anyway
+const MAX_BACKLOG: usize = 100_000;
+
+// queued counts the batches that have been decoded but not yet
+// processed, if it is above zero we are falling behind the publisher
 fn decode_task(
     mut con: ReadChannel,
+    queued: Arc<AtomicUsize>,
     stop: oneshot::Receiver<()>,
 ) -> Receiver<Result<(Pooled<Vec<From>>, bool)>> {
     let (mut send, recv) = mpsc::channel(3);
@@ -218,17 +422,19 @@ fn decode_task(
                 _ = stop => { break Ok(()); },
                 r = con.receive_batch_fn(|up| {
                     match up {
-                        From::Update(_, _) => (),
+                        From::Update(_, _) | From::Timestamp(_) | From::BatchEnd(_) => (),
                         _ => { only_updates = false }
                     }
                     buf.push(up);
                 }).fuse() => match r {
                     Err(e) => {
                         buf.clear();
+                        queued.fetch_add(1, Ordering::Relaxed);
                         try_cf!(send.send(Err(e)).await)
                     }
                     Ok(()) => {
                         let batch = mem::replace(&mut buf, DECODE_BATCHES.take());
+                        queued.fetch_add(1, Ordering::Relaxed);
                         try_cf!(send.send(Ok((batch, only_updates))).await)
                     }
                 }
@@ -239,10 +445,78 @@ fn decode_task(
     recv
 }
 
+// join `group` on a socket bound to its port. Other connections may
+// join the same group, so the port must be shared.
+fn join_group(group: SocketAddr) -> Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let domain = Domain::for_address(group);
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    match group.ip() {
+        IpAddr::V4(ip) => {
+            let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, group.port()));
+            socket.bind(&addr.into())?;
+            socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+        }
+        IpAddr::V6(ip) => {
+            let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, group.port()));
+            socket.set_only_v6(true)?;
+            socket.bind(&addr.into())?;
+            socket.join_multicast_v6(&ip, 0)?;
+        }
+    }
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// receive the datagrams of `stream`, the receiver ends if nothing is
+// heard for `silence`, in which case multicast isn't working.
+fn multicast_task(
+    socket: UdpSocket,
+    stream: u64,
+    silence: Duration,
+    stop: oneshot::Receiver<()>,
+) -> Receiver<Datagram> {
+    let (mut send, recv) = mpsc::channel(3);
+    let mut stop = stop.fuse();
+    task::spawn(async move {
+        let mut buf = vec![0u8; u16::MAX as usize];
+        let r: Result<(), anyhow::Error> = loop {
+            select_biased! {
+                _ = stop => break Ok(()),
+                r = time::timeout(silence, socket.recv(&mut buf)).fuse() => match r {
+                    Err(_) => break Err(anyhow!("no datagrams for {:?}", silence)),
+                    Ok(Err(e)) => break Err(Error::from(e)),
+                    Ok(Ok(len)) => match Datagram::decode(&mut &buf[..len]) {
+                        Err(e) => debug!("invalid datagram {}", e),
+                        Ok(d) if d.stream != stream => (),
+                        Ok(d) => try_cf!(send.send(d).await),
+                    },
+                },
+            }
+        };
+        info!("multicast task shutting down {:?}", r);
+    });
+    recv
+}
+
+struct MulticastSub {
+    group: SocketAddr,
+    stream: u64,
+    // the subscriptions receiving multicast updates, and the last
+    // value delivered to each, to skip unchanged snapshot values
+    subs: FxHashMap<Id, Option<Value>>,
+    seq: Option<u64>,
+    datagrams: Receiver<Datagram>,
+    _stop: oneshot::Sender<()>,
+}
+
 type BlockedChannelFut = Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>>;
 
 pub(super) struct ConnectionCtx {
     addr: SocketAddr,
+    addrs: SmallVec<[SocketAddr; 2]>,
+    local_endpoint: Option<ArcStr>,
     subscriber: SubscriberWeak,
     target_auth: TargetAuth,
     desired_auth: DesiredAuth,
@@ -252,19 +526,42 @@ pub(super) struct ConnectionCtx {
     from_sub: BatchReceiver<ToCon>,
     pending: HashMap<Path, SubscribeValRequest>,
     subscriptions: FxHashMap<Id, Sub>,
-    msg_recvd: bool,
+    caps: Capabilities,
+    msg_sent: bool,
+    last_recvd: Instant,
     pending_flushes: Vec<oneshot::Sender<()>>,
     pending_writes: FxHashMap<Id, VecDeque<oneshot::Sender<Value>>>,
     by_receiver: FxHashMap<ChanWrap<Pooled<Vec<(SubId, Event)>>>, ChanId>,
     by_chan: ByChan,
+    // updates for sync groups waiting for the end of their batch
+    held: Held,
+    // updates for sync groups that aren't part of a batch
+    loose: Held,
     gc_chan: FxHashSet<ChanId>,
     blocked_channels: FuturesUnordered<BlockedChannelFut>,
+    // channels that don't block when they are full, true if coalescing
+    nonblocking: FxHashMap<ChanId, bool>,
+    overflow: FxHashMap<ChanId, Overflow>,
+    ready: FuturesUnordered<ReadyFut>,
     timed_out: Vec<Path>,
+    queued: Arc<AtomicUsize>,
+    deferred: Vec<(Id, Option<DateTime<Utc>>, Value)>,
+    coalesced: FxHashMap<Id, (Option<DateTime<Utc>>, Value)>,
+    backlog: Charge,
+    timestamp: Option<DateTime<Utc>>,
+    multicast: Option<MulticastSub>,
+    multicast_failed: bool,
+    recorder: Option<Recorder>,
+    socket_opts: SocketOpts,
+    memory: Arc<Memory>,
+    idle_since: Option<Instant>,
 }
 
 impl ConnectionCtx {
     pub(super) fn new(
         addr: SocketAddr,
+        addrs: SmallVec<[SocketAddr; 2]>,
+        local_endpoint: Option<ArcStr>,
         subscriber: SubscriberWeak,
         conid: ConId,
         tls_ctx: Option<tls::CachedConnector>,
@@ -273,8 +570,17 @@ impl ConnectionCtx {
         desired_auth: DesiredAuth,
         from_sub: BatchReceiver<ToCon>,
     ) -> Self {
+        let (recorder, socket_opts, memory) = match subscriber.upgrade() {
+            None => (None, SocketOpts::default(), Arc::new(Memory::default())),
+            Some(s) => {
+                let t = s.0.lock();
+                (t.recorder.clone(), t.socket_opts.clone(), t.memory.clone())
+            }
+        };
         Self {
             addr,
+            addrs,
+            local_endpoint,
             subscriber,
             target_auth,
             desired_auth,
@@ -284,23 +590,55 @@ impl ConnectionCtx {
             from_sub,
             pending: HashMap::default(),
             subscriptions: HashMap::default(),
-            msg_recvd: false,
+            caps: Capabilities::default(),
+            msg_sent: false,
+            last_recvd: Instant::now(),
             pending_flushes: Vec::new(),
             pending_writes: HashMap::default(),
             by_receiver: HashMap::default(),
             by_chan: HashMap::default(),
+            held: HashMap::default(),
+            loose: HashMap::default(),
             gc_chan: HashSet::default(),
             blocked_channels: FuturesUnordered::<BlockedChannelFut>::new(),
+            nonblocking: HashMap::default(),
+            overflow: HashMap::default(),
+            ready: FuturesUnordered::new(),
             timed_out: Vec::new(),
+            queued: Arc::new(AtomicUsize::new(0)),
+            deferred: Vec::new(),
+            coalesced: HashMap::default(),
+            backlog: Charge::new(&memory, 0),
+            timestamp: None,
+            multicast: None,
+            multicast_failed: false,
+            recorder,
+            socket_opts,
+            memory,
+            idle_since: None,
         }
     }
 
-    fn handle_heartbeat(&mut self, now: Instant) -> Result<()> {
-        if !self.msg_recvd {
+    fn handle_heartbeat(
+        &mut self,
+        write_con: &mut WriteChannel,
+        timeout: Duration,
+    ) -> Result<()> {
+        if !self.msg_sent && self.caps.has(Capabilities::HEARTBEAT) {
+            write_con.queue_send(&To::Heartbeat)?;
+        }
+        self.msg_sent = false;
+        // while channels are blocked we aren't reading, so silence
+        // from the publisher isn't meaningful
+        if !self.blocked_channels.is_empty() {
+            self.last_recvd = Instant::now();
+        } else if self.last_recvd.elapsed() > timeout {
             bail!("hung publisher");
-        } else {
-            self.msg_recvd = false;
         }
+        Ok(())
+    }
+
+    fn handle_deadlines(&mut self, now: Instant) {
         for (path, req) in self.pending.iter() {
             if let Some(deadline) = req.deadline {
                 if deadline < now {
@@ -313,7 +651,6 @@ impl ConnectionCtx {
                 let _ = req.finished.send(Err(anyhow!("timed out")));
             }
         }
-        Ok(())
     }
 
     fn handle_connect_stream(
@@ -323,9 +660,11 @@ impl ConnectionCtx {
         mut tx: WUpdateChan,
         flags: UpdatesFlags,
     ) -> Result<()> {
+        let mut send_last = None;
         if let Some(sub) = self.subscriptions.get_mut(&id) {
             let mut already_have = false;
-            for (id, c) in sub.streams.iter() {
+            let mut batch = None;
+            for (id, c, _) in sub.streams.iter() {
                 if &tx == c {
                     trace!("ignore already registered stream");
                     already_have = true;
@@ -340,43 +679,113 @@ impl ConnectionCtx {
                 && !(already_have && flags.contains(UpdatesFlags::NO_SPURIOUS))
             {
                 if let Some(last) = &sub.last {
-                    let m = last.lock().clone();
+                    let m = match last.lock().clone() {
+                        (ts, Event::Update(v))
+                            if flags.contains(UpdatesFlags::TIMESTAMPED) =>
+                        {
+                            Event::Update(with_timestamp(ts, v))
+                        }
+                        (_, ev) => ev,
+                    };
                     let mut b = BATCHES.take();
                     trace!("pushing {:?} to new stream", m);
                     b.push((sub_id, m));
-                    if let Err(e) = tx.0.try_send(b) {
-                        if e.is_disconnected() {
-                            trace!("channel closed while sending last");
-                            return Ok(());
-                        } else if e.is_full() {
-                            trace!("no slack in channel for last adding to blocked");
-                            let b = e.into_inner();
-                            let mut tx = tx.clone();
-                            self.blocked_channels.push(Box::pin(async move {
-                                let _ = tx.0.send(b).await;
-                            }))
-                        }
-                    }
+                    batch = Some(b);
                 }
             }
             if flags.contains(UpdatesFlags::STOP_COLLECTING_LAST) {
                 trace!("no longer collecting last");
                 sub.last = None;
+                sub.charge.set(0);
+            }
+            let chan_id = *self.by_receiver.entry(tx.clone()).or_insert_with(ChanId::new);
+            if flags.intersects(UpdatesFlags::COALESCE | UpdatesFlags::UNBOUNDED) {
+                self.nonblocking
+                    .entry(chan_id)
+                    .or_insert(flags.contains(UpdatesFlags::COALESCE));
             }
             if !already_have {
                 trace!("adding new channel to streams");
-                let id = self.by_receiver.entry(tx.clone()).or_insert_with(ChanId::new);
-                sub.streams.push((*id, tx));
+                let timestamped = flags.contains(UpdatesFlags::TIMESTAMPED);
+                sub.streams.push((chan_id, tx.clone(), timestamped));
+            }
+            send_last = batch.map(|b| (chan_id, b));
+        }
+        if let Some((chan_id, b)) = send_last {
+            if !self.send_batch(chan_id, &mut tx, b) {
+                trace!("channel closed while sending last");
             }
         }
         Ok(())
     }
 
+    /// Send `batch` to `chan`, or if it's full then either wait for
+    /// it, or queue the batch, depending on how it was
+    /// registered. Return false if the channel is closed.
+    fn send_batch(&mut self, id: ChanId, chan: &mut WUpdateChan, batch: Updates) -> bool {
+        if let Some(o) = self.overflow.get_mut(&id) {
+            o.push(batch);
+            return true;
+        }
+        match chan.0.try_send(batch) {
+            Ok(()) => true,
+            Err(e) if e.is_full() => {
+                let batch = e.into_inner();
+                match self.nonblocking.get(&id) {
+                    None => {
+                        let f = send_blocked(&self.memory, chan.clone(), batch);
+                        self.blocked_channels.push(f)
+                    }
+                    Some(coalesce) => {
+                        // every clone of a sender has a slot of its own,
+                        // so only the sender that is full can tell us when
+                        // there is room again
+                        let fresh = chan.clone();
+                        let full = mem::replace(chan, fresh);
+                        let mut o = Overflow::new(&self.memory, chan.clone(), *coalesce);
+                        o.push(batch);
+                        self.overflow.insert(id, o);
+                        self.ready.push(wait_ready(id, full))
+                    }
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn handle_ready(&mut self, (id, mut chan, ok): (ChanId, WUpdateChan, bool)) {
+        if !ok {
+            self.overflow.remove(&id);
+            return;
+        }
+        let o = match self.overflow.get_mut(&id) {
+            Some(o) => o,
+            None => return,
+        };
+        if let Some(batch) = o.pop() {
+            if let Err(e) = chan.0.try_send(batch) {
+                if e.is_full() {
+                    o.requeue(e.into_inner())
+                } else {
+                    self.overflow.remove(&id);
+                    return;
+                }
+            }
+        }
+        if o.queued.is_empty() {
+            self.overflow.remove(&id);
+        } else {
+            self.ready.push(wait_ready(id, chan))
+        }
+    }
+
     fn handle_from_sub(
         &mut self,
         write_con: &mut WriteChannel,
         mut batch: Pooled<Vec<ToCon>>,
     ) -> Result<()> {
+        let mut flushed = false;
         for msg in batch.drain(..) {
             match msg {
                 ToCon::Subscribe(req) => {
@@ -398,9 +807,40 @@ impl ConnectionCtx {
                     info!("unsubscribe {:?}", id);
                     write_con.queue_send(&To::Unsubscribe(id))?
                 }
+                ToCon::Tag(id, tag) => {
+                    if let Some(sub) = self.subscriptions.get_mut(&id) {
+                        sub.tag = tag;
+                    }
+                }
+                ToCon::Priority(id, priority) => {
+                    // held back updates must not be overtaken by updates
+                    // sent under the new priority
+                    flushed |= self.flush_backlog();
+                    if let Some(sub) = self.subscriptions.get_mut(&id) {
+                        sub.priority = priority;
+                    }
+                }
+                ToCon::Filter(id, filter) => {
+                    if self.caps.has(Capabilities::FILTER) {
+                        if let Some(sub) = self.subscriptions.get_mut(&id) {
+                            sub.filtered = !filter.is_empty();
+                        }
+                        // the publisher stops multicasting to filtered
+                        // subscriptions
+                        if !filter.is_empty() {
+                            self.leave_multicast(id);
+                        }
+                        write_con.queue_send(&To::Filter(id, filter))?
+                    }
+                }
                 ToCon::Stream { id, sub_id, tx, flags } => {
                     self.handle_connect_stream(id, sub_id, tx, flags)?
                 }
+                ToCon::Sync { id, sub_id, tx } => {
+                    // synced updates are never held back
+                    flushed |= self.flush_backlog();
+                    self.handle_connect_sync(id, sub_id, tx)
+                }
                 ToCon::Write(id, v, tx) => {
                     write_con.queue_send(&To::Write(id, tx.is_some(), v))?;
                     if let Some(tx) = tx {
@@ -411,8 +851,24 @@ impl ConnectionCtx {
                     }
                 }
                 ToCon::Flush(tx) => self.pending_flushes.push(tx),
+                ToCon::Evict(id, bytes) => {
+                    self.memory.evicted(bytes);
+                    self.leave_multicast(id);
+                    if let Some(s) = self.subscriptions.remove(&id) {
+                        if let Some(subscriber) = self.subscriber.upgrade() {
+                            let mut t = subscriber.0.lock();
+                            let by_chan = &mut self.by_chan;
+                            unsubscribe(&mut *t, by_chan, s, id, self.conid, None);
+                        }
+                        write_con.queue_send(&To::Unsubscribe(id))?
+                    }
+                }
             }
         }
+        if flushed {
+            self.send_updates();
+        }
+        self.send_sync(None);
         Ok(())
     }
 
@@ -422,23 +878,25 @@ impl ConnectionCtx {
         con: &mut WriteChannel,
         subscriber: &Subscriber,
     ) -> Result<()> {
+        // a timestamp only applies to the batch it was sent in, the
+        // publisher may stop sending them at any time
+        self.timestamp = None;
+        // anything held back goes out before the batch, which may
+        // unsubscribe
+        self.flush_backlog();
         for m in batch.drain(..) {
             match m {
                 From::Update(i, m) => match self.subscriptions.get(&i) {
                     Some(sub) => {
-                        for (chan_id, c) in sub.streams.iter() {
-                            self.by_chan
-                                .entry(*chan_id)
-                                .or_insert_with(|| (c.clone(), BATCHES.take()))
-                                .1
-                                .push((sub.sub_id, Event::Update(m.clone())));
-                        }
-                        if let Some(last) = &sub.last {
-                            *last.lock() = Event::Update(m);
+                        if let Some(tag) = &sub.tag {
+                            tag.record(&m);
                         }
+                        self.queue_update(i, self.timestamp, m)
                     }
                     None => con.queue_send(&To::Unsubscribe(i))?,
                 },
+                From::Timestamp(ts) => self.timestamp = Some(ts),
+                From::BatchEnd(id) => self.send_sync(Some(id)),
                 From::Heartbeat => (),
                 From::WriteResult(id, v) => {
                     if let Entry::Occupied(mut e) = self.pending_writes.entry(id) {
@@ -461,10 +919,41 @@ impl ConnectionCtx {
                         let _ = r.finished.send(Err(Error::from(PermissionDenied)));
                     }
                 }
+                From::Multicast { id, group, stream, snapshot } => {
+                    let filtered = match self.subscriptions.get(&id) {
+                        None => true,
+                        Some(sub) => sub.filtered,
+                    };
+                    // anyone on the network can read or forge the
+                    // datagrams, so they are only trusted in place of an
+                    // anonymous connection
+                    let anonymous = matches!(self.target_auth, TargetAuth::Anonymous);
+                    if !filtered
+                        && anonymous
+                        && self.join_multicast(id, group, stream, snapshot)
+                    {
+                        con.queue_send(&To::Multicast(id, true))?
+                    }
+                }
                 From::Unsubscribed(id) => {
+                    self.leave_multicast(id);
+                    if let Some(s) = self.subscriptions.remove(&id) {
+                        let mut t = subscriber.0.lock();
+                        unsubscribe(&mut *t, &mut self.by_chan, s, id, self.conid, None);
+                    }
+                }
+                From::Unpublished(id, v) => {
+                    self.leave_multicast(id);
                     if let Some(s) = self.subscriptions.remove(&id) {
                         let mut t = subscriber.0.lock();
-                        unsubscribe(&mut *t, &mut self.by_chan, s, id, self.conid);
+                        unsubscribe(
+                            &mut *t,
+                            &mut self.by_chan,
+                            s,
+                            id,
+                            self.conid,
+                            Some(v),
+                        );
                     }
                 }
                 From::Subscribed(p, id, m) => {
@@ -498,13 +987,15 @@ impl ConnectionCtx {
                             },
                             None => {
                                 trace!("subscribe success");
-                                let last = TArc::new(Mutex::new(Event::Update(m)));
+                                let last =
+                                    TArc::new(Mutex::new((None, Event::Update(m))));
                                 let s = Val(Arc::new(ValInner {
                                     sub_id: req.sub_id,
                                     id,
                                     conid: self.conid,
                                     connection: req.con,
                                     last: last.clone(),
+                                    used: AtomicU64::new(use_tick()),
                                 }));
                                 match req.finished.send(Ok(s.clone())) {
                                     Err(e) => {
@@ -513,6 +1004,20 @@ impl ConnectionCtx {
                                     }
                                     Ok(()) => {
                                         trace!("storing finished subscripiton");
+                                        if let Some(r) = &self.recorder {
+                                            let ev = last.lock().1.clone();
+                                            r.record(None, &req.path, ev)
+                                        }
+                                        let filter = req.filter;
+                                        if !filter.is_empty()
+                                            && self.caps.has(Capabilities::FILTER)
+                                        {
+                                            con.queue_send(&To::Filter(id, filter))?
+                                        }
+                                        let charge = Charge::new(
+                                            &self.memory,
+                                            Pack::encoded_len(&last.lock().1),
+                                        );
                                         self.subscriptions.insert(
                                             id,
                                             Sub {
@@ -521,6 +1026,12 @@ impl ConnectionCtx {
                                                 last: Some(last),
                                                 streams: SmallVec::new(),
                                                 val: s.downgrade(),
+                                                tag: req.tag,
+                                                priority: req.priority,
+                                                filtered: !filter.is_empty(),
+                                                recorder: self.recorder.clone(),
+                                                groups: SmallVec::new(),
+                                                charge,
                                             },
                                         );
                                     }
@@ -541,54 +1052,268 @@ impl ConnectionCtx {
             }
         }
         self.send_updates();
+        self.send_sync(None);
+        Ok(())
+    }
+
+    fn queue_update(&mut self, i: Id, ts: Option<DateTime<Utc>>, m: Value) {
+        if let Some(sub) = self.subscriptions.get_mut(&i) {
+            let mcast = self.multicast.as_mut().and_then(|mc| mc.subs.get_mut(&i));
+            if let Some(last) = mcast {
+                *last = Some(m.clone());
+            }
+            if !sub.groups.is_empty() {
+                let batched = self.caps.has(Capabilities::BATCH_ID) && !sub.filtered;
+                let held = if batched { &mut self.held } else { &mut self.loose };
+                sub.queue_sync(held, &m)
+            }
+            sub.queue_update(&mut self.by_chan, ts, m)
+        }
+    }
+
+    fn handle_connect_sync(&mut self, id: Id, sub_id: SubId, tx: SyncChan) {
+        if let Some(sub) = self.subscriptions.get_mut(&id) {
+            sub.groups.retain(|c| !c.0.is_closed());
+            if !sub.groups.contains(&tx) {
+                // the group starts with the current value
+                if let Some(last) = &sub.last {
+                    if let (_, Event::Update(v)) = &*last.lock() {
+                        self.loose
+                            .entry(tx.clone())
+                            .or_insert_with(|| BATCHES.take())
+                            .push((sub_id, Event::Update(v.clone())));
+                    }
+                }
+                sub.groups.push(tx);
+            }
+        }
+    }
+
+    /// Send the updates held for sync groups. `id` is the batch the
+    /// publisher committed them in, if they were part of one.
+    fn send_sync(&mut self, id: Option<u64>) {
+        let held = match id {
+            Some(_) => &mut self.held,
+            None => &mut self.loose,
+        };
+        for (mut c, updates) in held.drain() {
+            let b = SyncBatch { publisher: self.addr, id, updates };
+            if let Err(e) = c.0.try_send(b) {
+                if e.is_full() {
+                    let b = e.into_inner();
+                    self.blocked_channels.push(Box::pin(async move {
+                        let _ = c.0.send(b).await;
+                    }))
+                }
+            }
+        }
+    }
+
+    // returns true if we are able to receive `id` from `group`
+    fn join_multicast(
+        &mut self,
+        id: Id,
+        group: SocketAddr,
+        stream: u64,
+        snapshot: Duration,
+    ) -> bool {
+        if self.multicast_failed {
+            return false;
+        }
+        if self.multicast.is_none() {
+            match join_group(group) {
+                Err(e) => {
+                    debug!("failed to join multicast group {}, {}", group, e);
+                    self.multicast_failed = true;
+                    return false;
+                }
+                Ok(socket) => {
+                    let silence = Duration::max(snapshot * 3, Duration::from_secs(1));
+                    let (tx_stop, rx_stop) = oneshot::channel();
+                    self.multicast = Some(MulticastSub {
+                        group,
+                        stream,
+                        subs: HashMap::default(),
+                        seq: None,
+                        datagrams: multicast_task(socket, stream, silence, rx_stop),
+                        _stop: tx_stop,
+                    });
+                }
+            }
+        }
+        match &mut self.multicast {
+            // we only join one group per connection
+            Some(m) if m.group == group && m.stream == stream => {
+                let last = self.subscriptions.get(&id).and_then(|s| match &s.last {
+                    Some(l) => match &l.lock().1 {
+                        Event::Update(v) => Some(v.clone()),
+                        Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => {
+                            None
+                        }
+                    },
+                    None => None,
+                });
+                m.subs.insert(id, last);
+                true
+            }
+            Some(_) | None => false,
+        }
+    }
+
+    fn leave_multicast(&mut self, id: Id) {
+        if let Some(m) = &mut self.multicast {
+            m.subs.remove(&id);
+            // otherwise silence would look like a failure
+            if m.subs.is_empty() {
+                self.multicast = None;
+            }
+        }
+    }
+
+    fn handle_datagram(&mut self, d: Datagram) {
+        let m = match &mut self.multicast {
+            None => return,
+            Some(m) => m,
+        };
+        match m.seq {
+            // reordered or duplicated
+            Some(seq) if d.seq <= seq => return,
+            Some(seq) if d.seq > seq + 1 => {
+                trace!("lost {} datagrams, waiting for a snapshot", d.seq - seq - 1)
+            }
+            Some(_) | None => (),
+        }
+        m.seq = Some(d.seq);
+        for (id, v) in d.updates {
+            match m.subs.get_mut(&id) {
+                None => (),
+                Some(last) => {
+                    if d.snapshot && last.as_ref() == Some(&v) {
+                        continue;
+                    }
+                    *last = Some(v.clone());
+                    if let Some(sub) = self.subscriptions.get_mut(&id) {
+                        if let Some(tag) = &sub.tag {
+                            tag.record(&v);
+                        }
+                        sub.queue_sync(&mut self.loose, &v);
+                        sub.queue_update(&mut self.by_chan, d.timestamp, v)
+                    }
+                }
+            }
+        }
+        self.send_updates();
+        self.send_sync(None)
+    }
+
+    // multicast stopped working, get updates by unicast again
+    fn handle_multicast_failed(&mut self, con: &mut WriteChannel) -> Result<()> {
+        if let Some(m) = self.multicast.take() {
+            info!("multicast from {} failed, falling back to unicast", m.group);
+            self.multicast_failed = true;
+            for id in m.subs.keys() {
+                con.queue_send(&To::Multicast(*id, false))?
+            }
+        }
         Ok(())
     }
 
     // This is the fast path for the common case where the batch contains
     // only updates. As of 2020-04-30, sending to an mpsc channel is
     // pretty slow, about 250ns, so we go to great lengths to avoid it.
-    fn process_updates_batch(&mut self, mut batch: Pooled<Vec<From>>) {
+    //
+    // While we are behind the publisher high priority updates are sent
+    // as they arrive, normal priority updates are held back in order,
+    // and low priority updates are coalesced, for as many batches as it
+    // takes to catch up. The first batch that finds us caught up sends
+    // the backlog ahead of itself.
+    fn process_updates_batch(&mut self, mut batch: Pooled<Vec<From>>, behind: bool) {
+        self.timestamp = None;
+        if !behind {
+            self.flush_backlog();
+        }
+        let mut bytes = self.backlog.bytes;
         for m in batch.drain(..) {
-            if let From::Update(i, m) = m {
-                if let Some(sub) = self.subscriptions.get(&i) {
-                    for (chan_id, c) in sub.streams.iter() {
-                        self.by_chan
-                            .entry(*chan_id)
-                            .or_insert_with(|| (c.clone(), BATCHES.take()))
-                            .1
-                            .push((sub.sub_id, Event::Update(m.clone())))
+            if let From::Timestamp(ts) = m {
+                self.timestamp = Some(ts);
+            } else if let From::BatchEnd(id) = m {
+                self.send_sync(Some(id));
+            } else if let From::Update(i, m) = m {
+                let ts = self.timestamp;
+                let (priority, synced) = match self.subscriptions.get(&i) {
+                    None => continue,
+                    Some(sub) => {
+                        if let Some(tag) = &sub.tag {
+                            tag.record(&m);
+                        }
+                        (sub.priority, !sub.groups.is_empty())
+                    }
+                };
+                match priority {
+                    // deferring an update would move it out of its batch
+                    _ if !behind || synced => self.queue_update(i, ts, m),
+                    Priority::High => self.queue_update(i, ts, m),
+                    Priority::Normal => {
+                        bytes += Pack::encoded_len(&m);
+                        self.deferred.push((i, ts, m))
                     }
-                    if let Some(last) = &sub.last {
-                        *last.lock() = Event::Update(m);
+                    Priority::Low => {
+                        bytes += Pack::encoded_len(&m);
+                        if let Some((_, old)) = self.coalesced.insert(i, (ts, m)) {
+                            bytes -= Pack::encoded_len(&old);
+                        }
                     }
                 }
             }
         }
-        self.send_updates()
+        self.backlog.set(bytes);
+        if self.deferred.len() >= MAX_BACKLOG {
+            self.flush_backlog();
+        }
+        self.send_updates();
+        self.send_sync(None)
+    }
+
+    // queue the updates held back while we were behind, normal
+    // priority in the order they arrived, then low priority. Returns
+    // true if anything was queued.
+    fn flush_backlog(&mut self) -> bool {
+        if self.deferred.is_empty() && self.coalesced.is_empty() {
+            return false;
+        }
+        let mut deferred = mem::take(&mut self.deferred);
+        for (i, ts, m) in deferred.drain(..) {
+            self.queue_update(i, ts, m);
+        }
+        self.deferred = deferred;
+        let mut coalesced = mem::take(&mut self.coalesced);
+        for (i, (ts, m)) in coalesced.drain() {
+            self.queue_update(i, ts, m);
+        }
+        self.coalesced = coalesced;
+        self.backlog.set(0);
+        true
     }
 
     fn send_updates(&mut self) {
-        for (id, (c, batch)) in self.by_chan.iter_mut() {
+        let mut by_chan = mem::take(&mut self.by_chan);
+        for (id, (c, batch)) in by_chan.iter_mut() {
             let batch = mem::replace(batch, BATCHES.take());
-            if let Err(e) = c.0.try_send(batch) {
-                if e.is_full() {
-                    let batch = e.into_inner();
-                    let mut c = c.clone();
-                    self.blocked_channels.push(Box::pin(async move {
-                        let _ = c.0.send(batch).await;
-                    }))
-                } else if e.is_disconnected() {
-                    self.by_receiver.remove(c);
-                    self.gc_chan.insert(*id);
-                }
+            if !self.send_batch(*id, c, batch) {
+                self.by_receiver.remove(c);
+                self.gc_chan.insert(*id);
             }
         }
+        self.by_chan = by_chan;
         for id in self.gc_chan.drain() {
             self.by_chan.remove(&id);
+            self.nonblocking.remove(&id);
+            self.overflow.remove(&id);
         }
     }
 
-    // return true if we should keep running, false if we are idle
+    // return true if we should keep running, false if we have been
+    // idle longer than the pool's idle timeout, or we were reaped
     fn maybe_disconnect_idle(&mut self) -> bool {
         match self.subscriber.upgrade() {
             None => false,
@@ -599,6 +1324,15 @@ impl ConnectionCtx {
                 {
                     let mut inner = subscriber.0.lock();
                     if self.from_sub.len() == 0 {
+                        let now = Instant::now();
+                        let since = *self.idle_since.get_or_insert(now);
+                        let reaped = inner.reap.remove(&self.conid);
+                        if !reaped && now - since < inner.pool.idle_timeout {
+                            inner.idle.insert(self.conid, (self.addr, since));
+                            return true;
+                        }
+                        inner.idle.remove(&self.conid);
+                        inner.pool_stats.reaped += 1;
                         // we do this here the make sure we
                         // hold the lock and there can be no
                         // subscriptions while we clean up.
@@ -612,6 +1346,8 @@ impl ConnectionCtx {
                         }
                         return false;
                     }
+                } else if self.idle_since.take().is_some() {
+                    subscriber.0.lock().idle.remove(&self.conid);
                 }
                 true
             }
@@ -624,8 +1360,11 @@ impl ConnectionCtx {
         batch: Pooled<Vec<From>>,
     ) -> Result<bool> {
         if let Some(subscriber) = self.subscriber.upgrade() {
-            self.msg_recvd = true;
+            self.last_recvd = Instant::now();
             self.process_batch(batch, write_con, &subscriber)?;
+            if self.memory.excess() > 0 {
+                subscriber.evict()
+            }
         }
         Ok(self.maybe_disconnect_idle())
     }
@@ -634,6 +1373,7 @@ impl ConnectionCtx {
         &mut self,
         mut batches: Receiver<Result<(Pooled<Vec<From>>, bool)>>,
         write_con: &mut WriteChannel,
+        (interval, timeout): (Duration, Duration),
     ) -> Result<()> {
         async fn read_batch(
             batches: &mut Receiver<Result<(Pooled<Vec<From>>, bool)>>,
@@ -647,6 +1387,20 @@ impl ConnectionCtx {
                 }
             }
         }
+        async fn next_ready(
+            ready: &mut FuturesUnordered<ReadyFut>,
+        ) -> (ChanId, WUpdateChan, bool) {
+            match ready.next().await {
+                Some(r) => r,
+                None => future::pending().await,
+            }
+        }
+        async fn read_datagram(m: &mut Option<MulticastSub>) -> Option<Datagram> {
+            match m {
+                None => future::pending().await,
+                Some(m) => m.datagrams.next().await,
+            }
+        }
         async fn flush(
             con: &mut WriteChannel,
             pending: &mut Vec<oneshot::Sender<()>>,
@@ -666,6 +1420,8 @@ impl ConnectionCtx {
             }
         }
         let mut periodic = time::interval_at(Instant::now() + PERIOD, PERIOD);
+        let mut hb = time::interval_at(Instant::now() + interval, interval);
+        self.last_recvd = Instant::now();
         loop {
             select_biased! {
                 // this has to come first because batch_channel isn't cancel safe
@@ -673,9 +1429,23 @@ impl ConnectionCtx {
                     Some(batch) => self.handle_from_sub(write_con, batch)?,
                     None => break Ok(()),
                 },
-                r = flush(write_con, &mut self.pending_flushes).fuse() => r?,
+                r = flush(write_con, &mut self.pending_flushes).fuse() => {
+                    r?;
+                    self.msg_sent = true;
+                },
+                _ = hb.tick().fuse() => {
+                    self.handle_heartbeat(write_con, timeout)?;
+                    if !self.maybe_disconnect_idle() {
+                        break Ok(())
+                    }
+                },
+                d = read_datagram(&mut self.multicast).fuse() => match d {
+                    Some(d) => self.handle_datagram(d),
+                    None => self.handle_multicast_failed(write_con)?,
+                },
+                r = next_ready(&mut self.ready).fuse() => self.handle_ready(r),
                 now = periodic.tick().fuse() => {
-                    self.handle_heartbeat(now)?;
+                    self.handle_deadlines(now);
                     if !self.maybe_disconnect_idle() {
                         break Ok(())
                     }
@@ -685,10 +1455,13 @@ impl ConnectionCtx {
                     &mut self.blocked_channels
                 ).fuse() => match r {
                     Some(Ok((batch, true))) => {
-                        self.msg_recvd = true;
-                        self.process_updates_batch(batch);
+                        self.last_recvd = Instant::now();
+                        // more batches are waiting behind this one
+                        let behind = self.queued.fetch_sub(1, Ordering::Relaxed) > 1;
+                        self.process_updates_batch(batch, behind);
                     },
                     Some(Ok((batch, false))) => {
+                        self.queued.fetch_sub(1, Ordering::Relaxed);
                         if !self.handle_updates(write_con, batch)? {
                             break Ok(())
                         }
@@ -700,29 +1473,83 @@ impl ConnectionCtx {
         }
     }
 
+    /// connect to the first of the publisher's addresses that works,
+    /// in the order the resolver gave them to us
+    async fn connect_tcp(&self) -> Result<TcpStream> {
+        let mut err = anyhow!("publisher {} has no addresses", self.addr);
+        for addr in self.addrs.iter() {
+            match time::timeout(PERIOD, self.socket_opts.connect(addr)).await {
+                Ok(Ok(soc)) => return Ok(soc),
+                Ok(Err(e)) => err = e,
+                Err(e) => err = Error::from(e),
+            }
+            debug!("failed to connect to {} at {}, {}", self.addr, addr, err);
+        }
+        Err(err)
+    }
+
     pub(super) async fn start(mut self) -> Result<()> {
-        let soc = time::timeout(PERIOD, TcpStream::connect(self.addr)).await??;
-        soc.set_nodelay(true)?;
         const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
-        let con = time::timeout(
-            HELLO_TIMEOUT,
-            hello_publisher(
-                soc,
-                self.tls_ctx.clone(),
-                self.uifo.take(),
-                &self.desired_auth,
-                &self.target_auth,
-            ),
-        )
-        .await??;
+        // the local endpoint only exists if the publisher is on this
+        // machine, otherwise fall back to tcp. Don't take the
+        // publisher's word for it, a remote publisher could otherwise
+        // point us at some other process's socket.
+        let ip = self.addr.ip();
+        let on_this_host = ip.is_loopback()
+            || match self.subscriber.upgrade() {
+                None => false,
+                Some(s) => s.0.lock().interfaces.iter().any(|i| i.ip() == ip),
+            };
+        let local = match &self.local_endpoint {
+            None => None,
+            Some(_) if !on_this_host => None,
+            Some(endpoint) => match local_transport::connect(endpoint).await {
+                Ok(soc) => Some(soc),
+                Err(e) => {
+                    debug!("local endpoint {} unavailable {}", endpoint, e);
+                    None
+                }
+            },
+        };
+        let tls_ctx = self.tls_ctx.clone();
+        let uifo = self.uifo.take();
+        let (desired_auth, target_auth) = (&self.desired_auth, &self.target_auth);
+        let (con, caps) = match local {
+            Some(soc) => {
+                let hello =
+                    hello_publisher(soc, tls_ctx, uifo, desired_auth, target_auth);
+                time::timeout(HELLO_TIMEOUT, hello).await??
+            }
+            None => {
+                let soc = self.connect_tcp().await?;
+                let hello =
+                    hello_publisher(soc, tls_ctx, uifo, desired_auth, target_auth);
+                time::timeout(HELLO_TIMEOUT, hello).await??
+            }
+        };
+        con.set_decompression(caps.has(Capabilities::ZSTD));
+        self.caps = caps;
+        // the publisher is reachable again, forget about past failures
+        let heartbeat = match self.subscriber.upgrade() {
+            None => bail!("dead subscriber"),
+            Some(subscriber) => {
+                let mut t = subscriber.0.lock();
+                t.health.remove(&self.addr);
+                (t.heartbeat, t.heartbeat_timeout)
+            }
+        };
         let (read_con, mut write_con) = con.split();
         let (tx_stop, rx_stop) = oneshot::channel();
-        let res = self.run(decode_task(read_con, rx_stop), &mut write_con).await;
+        let batches = decode_task(read_con, self.queued.clone(), rx_stop);
+        let res = self.run(batches, &mut write_con, heartbeat).await;
         let _ = tx_stop.send(());
         if let Some(subscriber) = self.subscriber.upgrade() {
             let mut batch = DECODE_BATCHES.take();
             batch.extend(self.subscriptions.keys().map(|id| From::Unsubscribed(*id)));
             self.process_batch(batch, &mut write_con, &subscriber)?;
+            for (_, o) in self.overflow.drain() {
+                task::spawn(o.finish());
+            }
             for (_, req) in self.pending {
                 let _ = req.finished.send(Err(anyhow!("connection died")));
             }
@@ -1,9 +1,13 @@
 mod connection;
-pub use crate::protocol::value::{FromValue, Typ, Value};
+mod record;
+pub use crate::protocol::{
+    publisher::UpdateFilter,
+    value::{FromValue, Typ, Value},
+};
 pub use crate::resolver_client::DesiredAuth;
 use crate::{
     batch_channel::{self, BatchSender},
-    config::Config,
+    config::{AuthTarget, Config, DefaultAuthMech, SocketOpts},
     pack::{Pack, PackError},
     path::Path,
     pool::{Pool, Pooled},
@@ -11,13 +15,15 @@ use crate::{
         publisher::{From, Id},
         resolver::{Publisher, PublisherId, Resolved, TargetAuth},
     },
-    publisher::PublishFlags,
+    publisher::{BindCfg, PublishFlags},
     resolver_client::ResolverRead,
     tls,
     utils::{BatchItem, Batched, ChanWrap},
 };
 use anyhow::{anyhow, Error, Result};
+use arcstr::ArcStr;
 use bytes::{Buf, BufMut, Bytes};
+use chrono::prelude::*;
 use futures::{
     channel::{
         mpsc::{self, Sender, UnboundedReceiver, UnboundedSender},
@@ -27,7 +33,7 @@ use futures::{
     select_biased,
     stream::FuturesUnordered,
 };
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use if_addrs::{get_if_addrs, IfAddr, Interface as NetworkInterface};
 use log::{info, trace, warn};
 use netidx_netproto::resolver::{PublisherRef, UserInfo};
@@ -37,13 +43,17 @@ use smallvec::SmallVec;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::{
     cmp::{max, Eq, PartialEq},
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     error, fmt,
     hash::Hash,
     iter, mem,
     net::SocketAddr,
+    path::PathBuf,
     result,
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
     time::Duration,
 };
 use tokio::{
@@ -55,6 +65,8 @@ use triomphe::Arc as TArc;
 lazy_static! {
     static ref BATCHES: Pool<Vec<(SubId, Event)>> = Pool::new(64, 16384);
     static ref DECODE_BATCHES: Pool<Vec<From>> = Pool::new(64, 16384);
+    static ref WRITE_BATCHES: Pool<Vec<(ConId, BatchSender<ToCon>, ToCon)>> =
+        Pool::new(64, 16384);
 }
 
 #[derive(Debug)]
@@ -106,6 +118,27 @@ bitflags! {
         /// channel, do not send the last again to that
         /// channel.
         const NO_SPURIOUS          = 0x04;
+
+        /// If set then updates sent to this channel are pairs of
+        /// `[DateTime, Value]`, where the timestamp is the time the
+        /// publisher committed the update. If the publisher does not
+        /// send timestamps then the time the update arrived is used
+        /// instead.
+        const TIMESTAMPED          = 0x08;
+
+        /// If set, and the channel is full, then instead of waiting
+        /// for it to have room, which stops processing every update
+        /// from the publisher, keep only the latest update of each
+        /// subscription until it does. The receiver always sees the
+        /// latest value, but may miss intermediate values. Other
+        /// events are never dropped.
+        const COALESCE             = 0x10;
+
+        /// If set, and the channel is full, then queue updates for it
+        /// without limit instead of waiting for it to have room. The
+        /// queued updates count against the memory budget. If
+        /// COALESCE is also set it takes precedence.
+        const UNBOUNDED            = 0x20;
     }
 }
 
@@ -113,6 +146,124 @@ type Updates = Pooled<Vec<(SubId, Event)>>;
 pub type UpdateChan = Sender<Updates>;
 type WUpdateChan = ChanWrap<Updates>;
 type Streams = SmallVec<[(UpdatesFlags, WUpdateChan); 1]>;
+type SyncChan = ChanWrap<SyncBatch>;
+
+/// Running totals for every subscription sharing a tag
+#[derive(Debug, Default)]
+struct TagCounters {
+    updates: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl TagCounters {
+    fn record(&self, v: &Value) {
+        self.updates.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(Pack::encoded_len(v) as u64, Ordering::Relaxed);
+    }
+}
+
+/// Aggregate statistics for all the subscriptions carrying a tag,
+/// see `Subscriber::tag`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagStats {
+    /// The number of subscriptions currently carrying the tag
+    pub subscriptions: usize,
+    /// The total number of updates received by tagged subscriptions
+    pub updates: u64,
+    /// The total encoded size of the updates received by tagged
+    /// subscriptions
+    pub bytes: u64,
+    /// The number of tagged durable subscriptions that are currently
+    /// not subscribed and are waiting to be resubscribed
+    pub stale: usize,
+}
+
+/// Memory used by the cached values of subscriptions, and by
+/// updates waiting for a full channel, see
+/// `Subscriber::set_memory_budget`.
+#[derive(Debug, Default)]
+struct Memory {
+    // zero means unlimited
+    budget: AtomicUsize,
+    used: AtomicUsize,
+    // charged bytes of subscriptions that are being evicted
+    evicting: AtomicUsize,
+}
+
+impl Memory {
+    /// The number of bytes that must be freed to get under budget,
+    /// not counting evictions that are already in progress.
+    fn excess(&self) -> usize {
+        match self.budget.load(Ordering::Relaxed) {
+            0 => 0,
+            budget => {
+                let used = self.used.load(Ordering::Relaxed);
+                let evicting = self.evicting.load(Ordering::Relaxed);
+                used.saturating_sub(evicting).saturating_sub(budget)
+            }
+        }
+    }
+
+    fn evicted(&self, bytes: usize) {
+        let _ = self.evicting.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            Some(n.saturating_sub(bytes))
+        });
+    }
+}
+
+/// Bytes charged against a `Memory`, released when dropped.
+#[derive(Debug)]
+struct Charge {
+    memory: Arc<Memory>,
+    bytes: usize,
+}
+
+impl Charge {
+    fn new(memory: &Arc<Memory>, bytes: usize) -> Self {
+        memory.used.fetch_add(bytes, Ordering::Relaxed);
+        Self { memory: memory.clone(), bytes }
+    }
+
+    fn set(&mut self, bytes: usize) {
+        if bytes > self.bytes {
+            self.memory.used.fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            self.memory.used.fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.memory.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// A logical clock ordering the uses of `Val`s, for eviction
+static USE_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn use_tick() -> u64 {
+    USE_CLOCK.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The priority of a subscription, see `Subscriber::set_priority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// Updates are delivered first when the connection is backed up
+    High,
+    /// Updates are delivered in order
+    Normal,
+    /// Updates are delivered last, and only the most recent update
+    /// is delivered, when the connection is backed up
+    Low,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
 
 #[derive(Debug)]
 struct SubscribeValRequest {
@@ -126,22 +277,43 @@ struct SubscribeValRequest {
     con: BatchSender<ToCon>,
     deadline: Option<Instant>,
     streams: Streams,
+    tag: Option<Arc<TagCounters>>,
+    priority: Priority,
+    filter: UpdateFilter,
 }
 
 #[derive(Debug)]
 enum ToCon {
     Subscribe(SubscribeValRequest),
     Unsubscribe(Id),
+    Tag(Id, Option<Arc<TagCounters>>),
+    Priority(Id, Priority),
+    Filter(Id, UpdateFilter),
     Stream { id: Id, sub_id: SubId, tx: WUpdateChan, flags: UpdatesFlags },
+    Sync { id: Id, sub_id: SubId, tx: SyncChan },
     Write(Id, Value, Option<oneshot::Sender<Value>>),
     Flush(oneshot::Sender<()>),
+    // evict the subscription to stay under the memory budget, the
+    // bytes are what the subscriber expects it to free
+    Evict(Id, usize),
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
 pub enum Event {
+    /// The subscription died, because the publisher went away, or
+    /// the connection to it failed.
     Unsubscribed,
+    /// A new value
     Update(Value),
+    /// The publisher intentionally stopped publishing the value. The
+    /// terminal value set by the publisher is included.
+    Unpublished(Value),
+    /// No update arrived within the deadline set by
+    /// `Dval::set_deadline`. The last value is still current as far
+    /// as we know, but it may be out of date. The next update clears
+    /// it.
+    Stale,
 }
 
 impl Pack for Event {
@@ -149,6 +321,8 @@ impl Pack for Event {
         match self {
             Event::Unsubscribed => 1,
             Event::Update(v) => Pack::encoded_len(v),
+            Event::Unpublished(v) => 1 + Pack::encoded_len(v),
+            Event::Stale => 1,
         }
     }
 
@@ -156,15 +330,29 @@ impl Pack for Event {
         match self {
             Event::Unsubscribed => Ok(buf.put_u8(0x40)),
             Event::Update(v) => Pack::encode(v, buf),
+            Event::Unpublished(v) => {
+                buf.put_u8(0x41);
+                Pack::encode(v, buf)
+            }
+            Event::Stale => Ok(buf.put_u8(0x42)),
         }
     }
 
     fn decode(buf: &mut impl Buf) -> result::Result<Self, PackError> {
-        if buf.chunk()[0] == 0x40 {
-            buf.advance(1);
-            Ok(Event::Unsubscribed)
-        } else {
-            Ok(Event::Update(Pack::decode(buf)?))
+        match buf.chunk()[0] {
+            0x40 => {
+                buf.advance(1);
+                Ok(Event::Unsubscribed)
+            }
+            0x41 => {
+                buf.advance(1);
+                Ok(Event::Unpublished(Pack::decode(buf)?))
+            }
+            0x42 => {
+                buf.advance(1);
+                Ok(Event::Stale)
+            }
+            _ => Ok(Event::Update(Pack::decode(buf)?)),
         }
     }
 }
@@ -175,7 +363,14 @@ struct ValInner {
     id: Id,
     conid: ConId,
     connection: BatchSender<ToCon>,
-    last: TArc<Mutex<Event>>,
+    last: TArc<Mutex<(Option<DateTime<Utc>>, Event)>>,
+    used: AtomicU64,
+}
+
+impl ValInner {
+    fn touch(&self) {
+        self.used.store(use_tick(), Ordering::Relaxed)
+    }
 }
 
 impl Drop for ValInner {
@@ -205,7 +400,26 @@ impl Val {
 
     /// Get the last event value.
     pub fn last(&self) -> Event {
-        self.0.last.lock().clone()
+        self.0.touch();
+        self.0.last.lock().1.clone()
+    }
+
+    /// Get the last value along with the time the publisher
+    /// committed it. Return None if the publisher did not send a
+    /// timestamp with the last value, or if the last event wasn't an
+    /// update.
+    pub fn last_timestamped(&self) -> Option<(DateTime<Utc>, Value)> {
+        self.0.touch();
+        match &*self.0.last.lock() {
+            (Some(ts), Event::Update(v)) => Some((*ts, v.clone())),
+            (
+                _,
+                Event::Update(_)
+                | Event::Unsubscribed
+                | Event::Unpublished(_)
+                | Event::Stale,
+            ) => None,
+        }
     }
 
     /// Register `tx` to receive updates to this `Val`.
@@ -219,7 +433,13 @@ impl Val {
     /// register a duplicate channel and begin_with_last is true you
     /// will get an update with the current state, even though the
     /// channel registration will be ignored.
+    ///
+    /// Each channel gets its own flags, so consumers with different
+    /// needs can share a subscription. What happens when the channel
+    /// is full, COALESCE, UNBOUNDED, or by default waiting for room,
+    /// is decided by the first registration of the channel.
     pub fn updates(&self, flags: UpdatesFlags, tx: UpdateChan) {
+        self.0.touch();
         let m = ToCon::Stream {
             tx: ChanWrap(tx),
             sub_id: self.0.sub_id,
@@ -240,6 +460,7 @@ impl Val {
     /// update values you are subscribed to, or trigger some other
     /// observable action.
     pub fn write(&self, v: Value) {
+        self.0.touch();
         self.0.connection.send(ToCon::Write(self.0.id, v, None));
     }
 
@@ -253,10 +474,37 @@ impl Val {
     /// are required.
     pub fn write_with_recipt(&self, v: Value) -> oneshot::Receiver<Value> {
         let (tx, rx) = oneshot::channel();
+        self.0.touch();
         self.0.connection.send(ToCon::Write(self.0.id, v, Some(tx)));
         rx
     }
 
+    /// Write `v` and wait for the publisher to reply, see
+    /// `write_with_recipt`. Fails if the reply doesn't arrive within
+    /// `timeout`, or if the write is dropped. In either case the
+    /// publisher may or may not have applied the write.
+    pub async fn write_with_timeout(&self, v: Value, timeout: Duration) -> Result<Value> {
+        wait_write_result(self.write_with_recipt(v), timeout).await
+    }
+
+    /// Queue a write of `v` in `batch`, it will be sent when the
+    /// batch is committed, see `WriteBatch`.
+    pub fn queue_write(&self, batch: &mut WriteBatch, v: Value) {
+        batch.push(self, v, None)
+    }
+
+    /// Queue a write of `v` in `batch`, and require the publisher to
+    /// reply, see `write_with_recipt`.
+    pub fn queue_write_with_recipt(
+        &self,
+        batch: &mut WriteBatch,
+        v: Value,
+    ) -> oneshot::Receiver<Value> {
+        let (tx, rx) = oneshot::channel();
+        batch.push(self, v, Some(tx));
+        rx
+    }
+
     /// Get the unique id of this subscription.
     pub fn id(&self) -> SubId {
         self.0.sub_id
@@ -269,12 +517,46 @@ impl Val {
     }
 }
 
+/// A batch of writes to subscriptions. Writes to the same publisher
+/// are sent together in one message when the batch is committed, and
+/// the publisher receives them as one batch of write requests, so a
+/// burst of writes costs a single wakeup on both sides.
+#[must_use = "write batches do nothing unless committed"]
+pub struct WriteBatch(Pooled<Vec<(ConId, BatchSender<ToCon>, ToCon)>>);
+
+impl WriteBatch {
+    fn push(&mut self, val: &Val, v: Value, tx: Option<oneshot::Sender<Value>>) {
+        let con = val.0.connection.clone();
+        val.0.touch();
+        self.0.push((val.0.conid, con, ToCon::Write(val.0.id, v, tx)))
+    }
+
+    /// return the number of queued writes in the batch
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Send the queued writes. Writes to each publisher are sent in
+    /// the order they were queued.
+    pub fn commit(mut self) {
+        self.0.sort_by_key(|(conid, _, _)| *conid);
+        let mut writes = self.0.drain(..).peekable();
+        while let Some((conid, con, m)) = writes.next() {
+            let rest = iter::from_fn(|| {
+                writes.next_if(|(id, _, _)| *id == conid).map(|(_, _, m)| m)
+            });
+            con.send_many(iter::once(m).chain(rest));
+        }
+    }
+}
+
 #[derive(Debug)]
 struct DvDead {
     queued_writes: Vec<(Value, Option<oneshot::Sender<Value>>)>,
     waiting: Vec<oneshot::Sender<()>>,
     tries: usize,
     next_try: Instant,
+    unpublished: Option<Value>,
 }
 
 #[derive(Debug)]
@@ -288,6 +570,23 @@ struct DvalInner {
     sub_id: SubId,
     sub: DvState,
     streams: Streams,
+    groups: SmallVec<[SyncChan; 1]>,
+    deadline: Option<(task::JoinHandle<()>, WUpdateChan)>,
+}
+
+impl DvalInner {
+    fn clear_deadline(&mut self) {
+        if let Some((jh, chan)) = self.deadline.take() {
+            jh.abort();
+            self.streams.retain(|(_, c)| c != &chan);
+        }
+    }
+}
+
+impl Drop for DvalInner {
+    fn drop(&mut self) {
+        self.clear_deadline()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -351,19 +650,36 @@ impl Dval {
     }
     
     /// Get the last value published by the publisher, or Unsubscribed
-    /// if the subscription is currently dead.
+    /// if the subscription is currently dead. If the subscription
+    /// died because the value was unpublished then return
+    /// Unpublished with the terminal value.
     pub fn last(&self) -> Event {
         match &self.0.lock().sub {
-            DvState::Dead(_) => Event::Unsubscribed,
+            DvState::Dead(d) => match &d.unpublished {
+                None => Event::Unsubscribed,
+                Some(v) => Event::Unpublished(v.clone()),
+            },
             DvState::Subscribed(val) => val.last(),
         }
     }
 
+    /// Get the last value along with the time the publisher
+    /// committed it. Return None if the subscription is currently
+    /// dead, or if the publisher did not send a timestamp with the
+    /// last value.
+    pub fn last_timestamped(&self) -> Option<(DateTime<Utc>, Value)> {
+        match &self.0.lock().sub {
+            DvState::Dead(_) => None,
+            DvState::Subscribed(val) => val.last_timestamped(),
+        }
+    }
+
     /// Register `tx` to receive updates to this `Dval`.
     ///
     /// You may register multiple different channels to receive
     /// updates from a `Dval`, and you may register one channel to
-    /// receive updates from multiple `Dval`s.
+    /// receive updates from multiple `Dval`s. Each channel gets it's
+    /// own flags, see `Val::updates`.
     pub fn updates(
         &self,
         flags: UpdatesFlags,
@@ -380,6 +696,58 @@ impl Dval {
         }
     }
 
+    /// Return a stream of the updates to this `Dval` cast to `T`. An
+    /// update that can't be cast will yield an error, but the stream
+    /// will continue. Unsubscribed and Unpublished events are not
+    /// included, use `updates` if you need to observe them.
+    ///
+    /// This pairs well with `#[derive(FromValue)]` from
+    /// `netidx-derive` for structured values.
+    pub fn typed<T: FromValue>(
+        &self,
+        flags: UpdatesFlags,
+    ) -> impl Stream<Item = Result<T>> + Unpin {
+        let (tx, rx) = mpsc::channel(3);
+        self.updates(flags, tx);
+        rx.flat_map(|mut batch| {
+            let batch = batch
+                .drain(..)
+                .filter_map(|(_, ev)| match ev {
+                    Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => None,
+                    Event::Update(v) => Some(v.cast_to::<T>()),
+                })
+                .collect::<Vec<_>>();
+            stream::iter(batch)
+        })
+    }
+
+    /// Return a stream of the updates to this `Dval` paired with the
+    /// time the publisher committed them, or the time they arrived
+    /// if the publisher does not send timestamps. This is useful for
+    /// measuring latency, or plotting values against source time.
+    /// Unsubscribed and Unpublished events are not included.
+    pub fn timestamped(
+        &self,
+        flags: UpdatesFlags,
+    ) -> impl Stream<Item = (DateTime<Utc>, Value)> + Unpin {
+        let (tx, rx) = mpsc::channel(3);
+        self.updates(flags | UpdatesFlags::TIMESTAMPED, tx);
+        rx.flat_map(|mut batch| {
+            let batch = batch
+                .drain(..)
+                .filter_map(|(_, ev)| match ev {
+                    Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => None,
+                    Event::Update(Value::Array(a)) => match &*a {
+                        [Value::DateTime(ts), v] => Some((*ts, v.clone())),
+                        _ => None,
+                    },
+                    Event::Update(_) => None,
+                })
+                .collect::<Vec<_>>();
+            stream::iter(batch)
+        })
+    }
+
     /// Wait until the `Dval` is subscribed and then return. This is
     /// not a guarantee that the `Dval` will stay subscribed for any
     /// length of time, just that at the moment this method returns
@@ -442,6 +810,30 @@ impl Dval {
         rx
     }
 
+    /// Write `v` and wait for the publisher to reply, see
+    /// `Val::write_with_timeout`. The timeout includes any time the
+    /// write spends queued waiting for us to subscribe.
+    pub async fn write_with_timeout(&self, v: Value, timeout: Duration) -> Result<Value> {
+        wait_write_result(self.write_with_recipt(v), timeout).await
+    }
+
+    /// Queue a write of `v` in `batch`, see `Val::queue_write`. If we
+    /// aren't currently subscribed the write is queued until we are,
+    /// as in `write`, and false is returned.
+    pub fn queue_write(&self, batch: &mut WriteBatch, v: Value) -> bool {
+        let mut t = self.0.lock();
+        match &mut t.sub {
+            DvState::Subscribed(ref val) => {
+                val.queue_write(batch, v);
+                true
+            }
+            DvState::Dead(dead) => {
+                dead.queued_writes.push((v, None));
+                false
+            }
+        }
+    }
+
     /// Clear the write queue
     pub fn clear_queued_writes(&self) {
         let mut t = self.0.lock();
@@ -462,6 +854,161 @@ impl Dval {
     pub fn id(&self) -> SubId {
         self.0.lock().sub_id
     }
+
+    /// Deliver `Event::Stale` to every channel registered with
+    /// `updates` if no update arrives within `deadline`, counting
+    /// from now, or from the most recent update. Stale is sent once,
+    /// the next update clears it and starts the deadline again. Stale
+    /// is not sent while the subscription is dead, since you already
+    /// got `Unsubscribed` or `Unpublished`.
+    ///
+    /// Setting a new deadline replaces the old one. This must be
+    /// called from within a tokio runtime.
+    pub fn set_deadline(&self, deadline: Duration) {
+        let (tx, rx) = mpsc::channel(3);
+        let tx = ChanWrap(tx);
+        let mut t = self.0.lock();
+        t.clear_deadline();
+        let sub_id = t.sub_id;
+        let dead = match t.sub {
+            DvState::Dead(_) => true,
+            DvState::Subscribed(_) => false,
+        };
+        let dv = self.downgrade();
+        let ours = tx.clone();
+        let jh = task::spawn(async move {
+            watch_deadline(dv, sub_id, deadline, dead, ours, rx).await
+        });
+        t.deadline = Some((jh, tx.clone()));
+        drop(t);
+        self.updates(UpdatesFlags::empty(), tx.0);
+    }
+
+    /// Stop watching the deadline set by `set_deadline`
+    pub fn clear_deadline(&self) {
+        self.0.lock().clear_deadline()
+    }
+}
+
+async fn watch_deadline(
+    dv: DvalWeak,
+    sub_id: SubId,
+    deadline: Duration,
+    dead: bool,
+    ours: WUpdateChan,
+    mut rx: mpsc::Receiver<Updates>,
+) {
+    // dead subscriptions aren't marked stale
+    let mut stale = dead;
+    loop {
+        match time::timeout(deadline, rx.next()).await {
+            Ok(None) => break,
+            Ok(Some(mut batch)) => {
+                for (_, ev) in batch.drain(..) {
+                    stale = match ev {
+                        Event::Update(_) => false,
+                        Event::Unsubscribed | Event::Unpublished(_) | Event::Stale => {
+                            true
+                        }
+                    }
+                }
+            }
+            Err(_) if stale => (),
+            Err(_) => {
+                stale = true;
+                let chans = match dv.upgrade() {
+                    None => break,
+                    Some(dv) => {
+                        dv.0.lock()
+                            .streams
+                            .iter()
+                            .filter(|(_, c)| c != &ours)
+                            .map(|(_, c)| c.clone())
+                            .collect::<Vec<_>>()
+                    }
+                };
+                for mut c in chans {
+                    let mut b = BATCHES.take();
+                    b.push((sub_id, Event::Stale));
+                    let _: result::Result<_, _> = c.0.send(b).await;
+                }
+            }
+        }
+    }
+}
+
+/// Updates to members of a `SyncGroup` that one publisher committed
+/// together.
+#[derive(Debug)]
+pub struct SyncBatch {
+    /// The publisher that sent the updates
+    pub publisher: SocketAddr,
+    /// The id the publisher gave the batch. Ids increase with every
+    /// batch the publisher commits. This is None for updates that
+    /// were not committed together, such as the current value of a
+    /// member when it joins or resubscribes, updates to filtered or
+    /// multicast subscriptions, and all updates from publishers that
+    /// don't send batch ids.
+    pub id: Option<u64>,
+    pub updates: Pooled<Vec<(SubId, Event)>>,
+}
+
+/// A set of `Dval`s whose updates are delivered together, one batch
+/// per batch committed by the publisher, so a structure published as
+/// many paths, e.g. the levels of an order book, can be read without
+/// ever observing a state the publisher didn't commit. Batches are
+/// only consistent per publisher, members published by different
+/// publishers arrive in separate batches. Updates to values the
+/// publisher conflates may be moved into a later batch.
+///
+/// Only updates are delivered to the group, use `Dval::updates` or
+/// `Dval::state` to follow the other events of a member.
+#[derive(Debug)]
+pub struct SyncGroup {
+    tx: SyncChan,
+    rx: mpsc::Receiver<SyncBatch>,
+}
+
+impl SyncGroup {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(3);
+        SyncGroup { tx: ChanWrap(tx), rx }
+    }
+
+    /// Add `dv` to the group. The first batch containing it will be
+    /// its current value, if it has one. It stays in the group,
+    /// across resubscriptions, until the group is dropped.
+    /// Subscriptions replayed from a recording can't be added.
+    pub fn add(&self, dv: &Dval) {
+        let mut t = dv.0.lock();
+        if !t.groups.contains(&self.tx) {
+            t.groups.push(self.tx.clone());
+            if let DvState::Subscribed(ref sub) = t.sub {
+                let m =
+                    ToCon::Sync { tx: self.tx.clone(), sub_id: t.sub_id, id: sub.0.id };
+                sub.0.connection.send(m);
+            }
+        }
+    }
+
+    /// Wait for the next batch of updates
+    pub async fn next(&mut self) -> SyncBatch {
+        match self.rx.next().await {
+            Some(batch) => batch,
+            None => unreachable!("the group holds a sender"),
+        }
+    }
+}
+
+async fn wait_write_result(
+    reply: oneshot::Receiver<Value>,
+    timeout: Duration,
+) -> Result<Value> {
+    match time::timeout(timeout, reply).await {
+        Err(_) => bail!("timed out waiting for the publisher to reply"),
+        Ok(Err(_)) => bail!("the write was dropped before the publisher replied"),
+        Ok(Ok(v)) => Ok(v),
+    }
 }
 
 #[derive(Debug)]
@@ -471,6 +1018,29 @@ enum SubStatus {
 }
 
 const REMEBER_FAILED: Duration = Duration::from_secs(60);
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(5);
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Connection failures to a publisher address. The more recent
+/// failures an address has the less likely it is to be chosen.
+/// Failures are forgotten after `REMEBER_FAILED`, or as soon as a
+/// connection to the address succeeds.
+#[derive(Debug, Clone, Copy)]
+struct Health {
+    failures: u32,
+    last_failure: Instant,
+}
+
+/// What we need to retry a subscription with a different publisher
+/// when the one we chose fails.
+#[derive(Debug)]
+struct Failover {
+    publishers: Arc<Pooled<FxHashMap<PublisherId, Publisher>>>,
+    resolved: Resolved,
+    addr: SocketAddr,
+    streams: Streams,
+    deadline: Option<Instant>,
+}
 
 fn pick(n: usize) -> usize {
     let mut rng = rand::thread_rng();
@@ -503,10 +1073,16 @@ impl Connection {
     fn is_empty(&self) -> bool {
         self.primary.is_none() && self.isolated.is_empty()
     }
+
+    fn len(&self) -> usize {
+        self.primary.iter().count() + self.isolated.len()
+    }
 }
 
 struct Chosen {
     addr: SocketAddr,
+    addrs: SmallVec<[SocketAddr; 2]>,
+    local_endpoint: Option<ArcStr>,
     target_auth: TargetAuth,
     token: Bytes,
     uifo: Option<UserInfo>,
@@ -518,18 +1094,156 @@ struct SubscriberInner {
     id: SubscriberId,
     resolver: ResolverRead,
     connections: FxHashMap<SocketAddr, Connection>,
-    recently_failed: FxHashMap<SocketAddr, Instant>,
+    health: FxHashMap<SocketAddr, Health>,
     subscribed: HashMap<Path, SubStatus>,
     durable_dead: HashMap<Path, DvalWeak>,
     durable_pending: HashMap<Path, DvalWeak>,
     durable_alive: HashMap<Path, DvalWeak>,
     trigger_resub: UnboundedSender<()>,
     desired_auth: DesiredAuth,
+    auth_overrides: Vec<(AuthTarget, DesiredAuth)>,
     tls_ctx: Option<tls::CachedConnector>,
     interfaces: Vec<NetworkInterface>,
+    tags: FxHashMap<ArcStr, Arc<TagCounters>>,
+    tagged: HashMap<Path, ArcStr>,
+    // subscriptions whose tag changed while they were pending
+    retag: HashSet<Path>,
+    // subscriptions whose priority changed while they were pending
+    reprioritize: HashSet<Path>,
+    priorities: HashMap<Path, Priority>,
+    // subscriptions whose filter changed while they were pending
+    refilter: HashSet<Path>,
+    filters: HashMap<Path, UpdateFilter>,
+    heartbeat: Duration,
+    heartbeat_timeout: Duration,
+    socket_opts: SocketOpts,
+    recorder: Option<record::Recorder>,
+    replay: Option<BatchSender<ToCon>>,
+    memory: Arc<Memory>,
+    evictions: Vec<UnboundedSender<Path>>,
+    pool: ConnectionPoolCfg,
+    pool_stats: ConnectionPoolStats,
+    // connections with no subscriptions, and when they became idle
+    idle: FxHashMap<ConId, (SocketAddr, Instant)>,
+    // idle connections that were closed to make room for a new one
+    reap: FxHashSet<ConId>,
 }
 
 impl SubscriberInner {
+    fn open_connections(&self) -> usize {
+        self.connections.values().map(|c| c.len()).sum()
+    }
+
+    /// If the connection pool is full close the connections that have
+    /// been idle the longest until there is room for a new one,
+    /// return false if that isn't possible.
+    fn make_room(&mut self) -> bool {
+        let max = match self.pool.max_connections {
+            None => return true,
+            Some(max) => max,
+        };
+        while self.open_connections() >= max {
+            let oldest = self
+                .idle
+                .iter()
+                .min_by_key(|(_, (_, since))| *since)
+                .map(|(id, (addr, _))| (*id, *addr));
+            let (id, addr) = match oldest {
+                None => return false,
+                Some(oldest) => oldest,
+            };
+            // the connection task notices it has been reaped the next
+            // time it checks if it is idle
+            self.idle.remove(&id);
+            self.reap.insert(id);
+            if let Entry::Occupied(mut e) = self.connections.entry(addr) {
+                let c = e.get_mut();
+                c.remove(id);
+                if c.is_empty() {
+                    e.remove();
+                }
+            }
+        }
+        true
+    }
+
+    fn tag_counters(&self, path: &Path) -> Option<Arc<TagCounters>> {
+        self.tagged.get(path).and_then(|tag| self.tags.get(tag)).cloned()
+    }
+
+    /// Tell the subscription of `path` about a change to its tag. If
+    /// it is still pending the change is sent once it's subscribed.
+    fn send_tag(&mut self, path: &Path) {
+        match self.subscribed.get(path) {
+            None => (),
+            Some(SubStatus::Pending(_)) => {
+                self.retag.insert(path.clone());
+            }
+            Some(SubStatus::Subscribed(val)) => {
+                if let Some(val) = val.upgrade() {
+                    let tag = self.tag_counters(path);
+                    val.0.connection.send(ToCon::Tag(val.0.id, tag));
+                }
+            }
+        }
+    }
+
+    /// Tell the subscription of `path` about a change to its
+    /// priority. If it is still pending the change is sent once it's
+    /// subscribed.
+    fn send_priority(&mut self, path: &Path) {
+        match self.subscribed.get(path) {
+            None => (),
+            Some(SubStatus::Pending(_)) => {
+                self.reprioritize.insert(path.clone());
+            }
+            Some(SubStatus::Subscribed(val)) => {
+                if let Some(val) = val.upgrade() {
+                    let priority = self.priorities.get(path).copied().unwrap_or_default();
+                    val.0.connection.send(ToCon::Priority(val.0.id, priority));
+                }
+            }
+        }
+    }
+
+    /// Tell the subscription of `path` about a change to its filter.
+    /// If the subscription is still pending the change is sent once
+    /// it completes.
+    fn send_filter(&mut self, path: &Path) {
+        match self.subscribed.get(path) {
+            None => (),
+            Some(SubStatus::Pending(_)) => {
+                self.refilter.insert(path.clone());
+            }
+            Some(SubStatus::Subscribed(val)) => {
+                if let Some(val) = val.upgrade() {
+                    let filter = self.filters.get(path).copied().unwrap_or_default();
+                    val.0.connection.send(ToCon::Filter(val.0.id, filter));
+                }
+            }
+        }
+    }
+
+    /// Forget the tag, priority and filter of `path` once nothing is
+    /// subscribed to it
+    fn prune(&mut self, path: &Path) {
+        if !self.is_subscribed_or_pending(path) {
+            self.tagged.remove(path);
+            self.retag.remove(path);
+            self.priorities.remove(path);
+            self.reprioritize.remove(path);
+            self.filters.remove(path);
+            self.refilter.remove(path);
+        }
+    }
+
+    fn is_subscribed_or_pending(&self, path: &Path) -> bool {
+        self.subscribed.contains_key(path)
+            || self.durable_dead.contains_key(path)
+            || self.durable_pending.contains_key(path)
+            || self.durable_alive.contains_key(path)
+    }
+
     fn durable_id(&self, path: &Path) -> Option<SubId> {
         self.durable_dead
             .get(path)
@@ -539,6 +1253,50 @@ impl SubscriberInner {
             .map(|d| d.id())
     }
 
+    /// The auth to use for a new connection to the publisher at
+    /// `addr`, made to subscribe to `path`, see
+    /// `Config::auth_overrides`.
+    fn desired_auth_for(&self, addr: &SocketAddr, path: &Path) -> DesiredAuth {
+        let mut best: Option<(usize, &DesiredAuth)> = None;
+        for (target, auth) in &self.auth_overrides {
+            let rank = match target {
+                AuthTarget::Publisher(a) if a == addr => usize::MAX,
+                AuthTarget::Namespace(ns) if Path::is_parent(ns, path) => {
+                    Path::levels(ns)
+                }
+                AuthTarget::Publisher(_) | AuthTarget::Namespace(_) => continue,
+            };
+            if best.map(|(r, _)| rank >= r).unwrap_or(true) {
+                best = Some((rank, auth))
+            }
+        }
+        match best {
+            Some((_, auth)) => auth.clone(),
+            // without an override the tls identity is chosen by the
+            // publisher's name
+            None => match &self.desired_auth {
+                DesiredAuth::Tls { .. } => DesiredAuth::Tls { identity: None },
+                auth => auth.clone(),
+            },
+        }
+    }
+
+    /// the number of recent failures of `addr`, 0 is healthy
+    fn failures(&self, addr: &SocketAddr) -> u32 {
+        self.health.get(addr).map(|h| h.failures).unwrap_or(0)
+    }
+
+    fn record_failure(&mut self, addr: SocketAddr) {
+        let now = Instant::now();
+        let h = self
+            .health
+            .entry(addr)
+            .or_insert_with(|| Health { failures: 0, last_failure: now });
+        h.failures = h.failures.saturating_add(1);
+        h.last_failure = now;
+    }
+
+    /// choose randomly among the healthiest publishers
     fn choose_random_addr(
         &mut self,
         publishers: &Pooled<FxHashMap<PublisherId, Publisher>>,
@@ -546,39 +1304,27 @@ impl SubscriberInner {
         flags: PublishFlags,
     ) -> Option<Chosen> {
         use rand::seq::IteratorRandom;
-        let res = resolved
+        let best = resolved
             .publishers
             .iter()
-            .filter_map(|pref| {
-                publishers
-                    .get(&pref.id)
-                    .filter(|pb| !self.recently_failed.contains_key(&pb.addr))
-                    .map(|pb| (pref, pb))
-            })
+            .filter_map(|pref| publishers.get(&pref.id))
+            .map(|pb| self.failures(&pb.addr))
+            .min()?;
+        resolved
+            .publishers
+            .iter()
+            .filter_map(|pref| publishers.get(&pref.id).map(|pb| (pref, pb)))
+            .filter(|(_, pb)| self.failures(&pb.addr) == best)
             .choose(&mut rand::thread_rng())
             .map(|(pref, pb)| Chosen {
                 addr: pb.addr,
+                addrs: pb.connect_addrs().into(),
+                local_endpoint: pb.local_endpoint.clone(),
                 target_auth: pb.target_auth.clone(),
                 token: pref.token.clone(),
                 uifo: pb.user_info.clone(),
                 flags,
-            });
-        if let Some(chosen) = res {
-            Some(chosen)
-        } else {
-            resolved
-                .publishers
-                .iter()
-                .filter_map(|pref| publishers.get(&pref.id).map(|pb| (pref, pb)))
-                .choose(&mut rand::thread_rng())
-                .map(|(pref, pb)| Chosen {
-                    addr: pb.addr,
-                    target_auth: pb.target_auth.clone(),
-                    token: pref.token.clone(),
-                    uifo: pb.user_info.clone(),
-                    flags,
-                })
-        }
+            })
     }
 
     fn choose_existing_addr(
@@ -593,6 +1339,8 @@ impl SubscriberInner {
                 if self.connections.contains_key(&pb.addr) {
                     return Some(Chosen {
                         addr: pb.addr,
+                        addrs: pb.connect_addrs().into(),
+                        local_endpoint: pb.local_endpoint.clone(),
                         target_auth: pb.target_auth.clone(),
                         token: pref.token.clone(),
                         uifo: pb.user_info.clone(),
@@ -640,12 +1388,11 @@ impl SubscriberInner {
                 .publishers
                 .iter()
                 .filter_map(|r| publishers.get(&r.id).map(|pb| (r, pb)))
-                .filter(|(_, p)| !self.recently_failed.contains_key(&p.addr)),
+                .filter(|(_, p)| self.failures(&p.addr) == 0),
         );
         let mut all_far = true;
-        buf.sort_by_key(|(_, pb): &(&PublisherRef, &Publisher)| {
-            let ip = pb.addr.ip();
-            let pri = self.interfaces.iter().fold(2, |cur, i| match &i.addr {
+        let ip_pri = |ip: IpAddr| {
+            self.interfaces.iter().fold(2, |cur, i| match &i.addr {
                 IfAddr::V4(ifv4) => match ip {
                     IpAddr::V6(_) => cur,
                     IpAddr::V4(ipv4) => {
@@ -670,7 +1417,12 @@ impl SubscriberInner {
                         }
                     }
                 },
-            });
+            })
+        };
+        buf.sort_by_key(|(_, pb): &(&PublisherRef, &Publisher)| {
+            // a publisher is as close as the closest of its addresses
+            let pri =
+                pb.connect_addrs().iter().fold(2, |cur, a| min(cur, ip_pri(a.ip())));
             if pri < 2 {
                 all_far = false;
             }
@@ -685,6 +1437,8 @@ impl SubscriberInner {
         } else {
             buf.first().map(|(pref, pb)| Chosen {
                 addr: pb.addr,
+                addrs: pb.connect_addrs().into(),
+                local_endpoint: pb.local_endpoint.clone(),
                 target_auth: pb.target_auth.clone(),
                 token: pref.token.clone(),
                 uifo: pb.user_info.clone(),
@@ -715,9 +1469,9 @@ impl SubscriberInner {
         }
     }
 
-    fn gc_recently_failed(&mut self) {
+    fn gc_health(&mut self) {
         let now = Instant::now();
-        self.recently_failed.retain(|_, v| (now - *v) < REMEBER_FAILED)
+        self.health.retain(|_, h| (now - h.last_failure) < REMEBER_FAILED)
     }
 }
 
@@ -730,6 +1484,42 @@ impl SubscriberWeak {
     }
 }
 
+/// How a subscriber manages its connections to publishers, see
+/// `Subscriber::set_connection_pool`. The default is no limit on the
+/// number of connections, and closing connections as soon as they
+/// have no subscriptions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionPoolCfg {
+    /// The most publisher connections that may be open at once. When
+    /// a new connection is needed and the limit has been reached the
+    /// connection that has been idle the longest is closed to make
+    /// room, and if no connections are idle the subscription
+    /// fails. None means no limit.
+    pub max_connections: Option<usize>,
+    /// How long to keep a connection that has no subscriptions open
+    /// in case another subscription to the same publisher comes
+    /// along. Idle connections are checked once per heartbeat
+    /// interval, so the real time may be up to one interval longer.
+    pub idle_timeout: Duration,
+}
+
+/// Statistics about a subscriber's connections to publishers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionPoolStats {
+    /// The number of open connections, including idle ones
+    pub open: usize,
+    /// The number of open connections with no subscriptions
+    pub idle: usize,
+    /// The number of connections opened
+    pub opened: u64,
+    /// The number of subscriptions that used an already open connection
+    pub reused: u64,
+    /// The number of connections closed because they were idle
+    pub reaped: u64,
+    /// The number of subscriptions that failed because the pool was full
+    pub refused: u64,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct DurableStats {
     pub alive: usize,
@@ -740,17 +1530,48 @@ pub struct DurableStats {
 pub struct SubscriberBuilder {
     cfg: Option<Config>,
     desired_auth: Option<DesiredAuth>,
+    resolve_cache_ttl: Option<Duration>,
+    heartbeat: Duration,
+    heartbeat_timeout: Duration,
+    record: Option<PathBuf>,
+    socket_opts: Option<SocketOpts>,
+    auth_overrides: Vec<(AuthTarget, DesiredAuth)>,
+    memory_budget: Option<usize>,
+    connection_pool: ConnectionPoolCfg,
 }
 
 impl SubscriberBuilder {
     pub fn new() -> Self {
-        Self { cfg: None, desired_auth: None }
+        Self {
+            cfg: None,
+            desired_auth: None,
+            resolve_cache_ttl: None,
+            heartbeat: DEFAULT_HEARTBEAT,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            record: None,
+            socket_opts: None,
+            auth_overrides: vec![],
+            memory_budget: None,
+            connection_pool: ConnectionPoolCfg::default(),
+        }
     }
 
     pub fn build(&mut self) -> Result<Subscriber> {
-        let cfg = self.cfg.take().ok_or_else(|| anyhow!("config is required"))?;
+        let mut cfg = self.cfg.take().ok_or_else(|| anyhow!("config is required"))?;
+        if let Some(opts) = self.socket_opts.take() {
+            cfg.socket = opts;
+        }
+        cfg.auth_overrides.extend(self.auth_overrides.drain(..));
         let desired_auth = self.desired_auth.take().unwrap_or_else(|| cfg.default_auth());
-        Subscriber::new(cfg, desired_auth)
+        let t = Subscriber::new(cfg, desired_auth)?;
+        t.resolver().set_cache_ttl(self.resolve_cache_ttl);
+        t.set_heartbeat(self.heartbeat, self.heartbeat_timeout)?;
+        t.set_memory_budget(self.memory_budget);
+        t.set_connection_pool(self.connection_pool);
+        if let Some(file) = self.record.take() {
+            t.0.lock().recorder = Some(record::Recorder::new(&file)?);
+        }
+        Ok(t)
     }
 
     pub fn config(&mut self, cfg: Config) -> &mut Self {
@@ -762,6 +1583,72 @@ impl SubscriberBuilder {
         self.desired_auth = Some(auth);
         self
     }
+
+    /// Cache resolutions for `ttl` so that repeatedly subscribing to
+    /// the same paths doesn't hit the resolver server every time.
+    /// Cached resolutions are dropped when a connection to one of
+    /// their publishers is lost. Default is no caching.
+    pub fn resolve_cache_ttl(&mut self, ttl: Option<Duration>) -> &mut Self {
+        self.resolve_cache_ttl = ttl;
+        self
+    }
+
+    /// Send a heartbeat to idle publishers that support it every
+    /// `interval`, and drop connections to publishers we haven't
+    /// heard from in `timeout`, which must be longer than the
+    /// heartbeat interval of the publisher. Half open connections,
+    /// e.g. after a NAT timeout, are then detected quickly instead of
+    /// waiting for tcp keepalive. default 5s, 60s.
+    pub fn heartbeat(&mut self, interval: Duration, timeout: Duration) -> &mut Self {
+        self.heartbeat = interval;
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Record every event delivered to every subscription to
+    /// `file`, which is replaced if it exists. The recording can
+    /// later be played back with `Subscriber::replay`. `flush` waits
+    /// for everything recorded so far to be written. Default no
+    /// recording.
+    pub fn record(&mut self, file: impl AsRef<std::path::Path>) -> &mut Self {
+        self.record = Some(file.as_ref().to_path_buf());
+        self
+    }
+
+    /// The options for tcp connections to publishers. If not
+    /// specified then the `socket` section of the config is used.
+    pub fn socket_opts(&mut self, opts: SocketOpts) -> &mut Self {
+        self.socket_opts = Some(opts);
+        self
+    }
+
+    /// Connect to the publishers matching `target` with `auth`
+    /// instead of the desired auth, e.g. to use a different
+    /// identity for publishers in another security domain. Overrides
+    /// added here are checked after the ones in the config, so they
+    /// win ties, see `Config::auth_overrides`. Since a connection is
+    /// shared by all the subscriptions to a publisher, a namespace
+    /// override only applies if it matches the subscription that
+    /// opened the connection.
+    pub fn auth_override(&mut self, target: AuthTarget, auth: DesiredAuth) -> &mut Self {
+        self.auth_overrides.push((target, auth));
+        self
+    }
+
+    /// Limit the memory used by cached values and queued updates to
+    /// about `bytes`, see `Subscriber::set_memory_budget`. Default
+    /// unlimited.
+    pub fn memory_budget(&mut self, bytes: Option<usize>) -> &mut Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    /// Limit and pool connections to publishers, see
+    /// `Subscriber::set_connection_pool`.
+    pub fn connection_pool(&mut self, cfg: ConnectionPoolCfg) -> &mut Self {
+        self.connection_pool = cfg;
+        self
+    }
 }
 
 /// create subscriptions
@@ -773,13 +1660,16 @@ impl Subscriber {
     pub fn new(resolver: Config, desired_auth: DesiredAuth) -> Result<Subscriber> {
         let (tx, rx) = mpsc::unbounded();
         let tls_ctx = resolver.tls.clone().map(tls::CachedConnector::new);
+        let socket_opts = resolver.socket.clone();
+        let auth_overrides = resolver.auth_overrides.clone();
         let resolver = ResolverRead::new(resolver, desired_auth.clone());
         let t = Subscriber(Arc::new(Mutex::new(SubscriberInner {
             id: SubscriberId::new(),
             resolver,
             desired_auth,
+            auth_overrides,
             connections: HashMap::default(),
-            recently_failed: HashMap::default(),
+            health: HashMap::default(),
             subscribed: HashMap::default(),
             durable_dead: HashMap::default(),
             durable_pending: HashMap::default(),
@@ -787,11 +1677,66 @@ impl Subscriber {
             trigger_resub: tx,
             tls_ctx,
             interfaces: get_if_addrs()?,
+            tags: HashMap::default(),
+            tagged: HashMap::default(),
+            retag: HashSet::default(),
+            reprioritize: HashSet::default(),
+            refilter: HashSet::default(),
+            priorities: HashMap::default(),
+            filters: HashMap::default(),
+            heartbeat: DEFAULT_HEARTBEAT,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            socket_opts,
+            recorder: None,
+            replay: None,
+            memory: Arc::new(Memory::default()),
+            evictions: vec![],
+            pool: ConnectionPoolCfg::default(),
+            pool_stats: ConnectionPoolStats::default(),
+            idle: HashMap::default(),
+            reap: HashSet::default(),
         })));
         t.start_resub_task(rx);
         Ok(t)
     }
 
+    /// Create a subscriber that replays `file`, a recording made by
+    /// a subscriber built with `SubscriberBuilder::record`, instead
+    /// of talking to the network. The api is the same, so this is
+    /// useful for writing deterministic tests of code that uses
+    /// netidx.
+    ///
+    /// Subscriptions to paths that were subscribed when the
+    /// recording was made succeed, and receive the recorded events
+    /// at the same offset from the creation of the replaying
+    /// subscriber as they had from the first recorded event. Replay
+    /// uses tokio time, so pausing the tokio clock in a test runs it
+    /// as fast as possible. Subscriptions to paths that were not
+    /// recorded, or that were not subscribed at that point in the
+    /// recording, fail, and durable subscriptions to them will retry
+    /// as usual. Writes are discarded.
+    pub fn replay(file: impl AsRef<std::path::Path>) -> Result<Subscriber> {
+        let records = record::load(file.as_ref())?;
+        let cfg = Config {
+            base: Path::root(),
+            addrs: vec![],
+            dns: None,
+            tls: None,
+            default_auth: DefaultAuthMech::Anonymous,
+            default_bind_config: BindCfg::Local,
+            publisher_ports: None,
+            publisher_advertise: None,
+            socket: SocketOpts::default(),
+            auth_overrides: vec![],
+        };
+        let t = Subscriber::new(cfg, DesiredAuth::Anonymous)?;
+        let (tx, rx) = batch_channel::channel();
+        let replay = record::Replay::new(t.downgrade(), ConId::new(), records);
+        task::spawn(replay.run(rx));
+        t.0.lock().replay = Some(tx);
+        Ok(t)
+    }
+
     /// Return a unique identifier for this subscriber instance. The
     /// identifier will be unique across all subscribers created in
     /// this process, but not across processes or machines.
@@ -799,6 +1744,113 @@ impl Subscriber {
         self.0.lock().id
     }
 
+    /// Set the heartbeat interval and timeout. See
+    /// `SubscriberBuilder::heartbeat`. This only applies to
+    /// connections made after it is called.
+    pub fn set_heartbeat(&self, interval: Duration, timeout: Duration) -> Result<()> {
+        if interval.is_zero() || timeout <= interval {
+            bail!("the heartbeat timeout must be longer than the interval")
+        }
+        let mut t = self.0.lock();
+        t.heartbeat = interval;
+        t.heartbeat_timeout = timeout;
+        Ok(())
+    }
+
+    /// Limit the memory used by the last values of subscriptions,
+    /// and by updates waiting for full update channels, to about
+    /// `bytes`. When it is exceeded the least recently used non
+    /// durable subscriptions are unsubscribed until it isn't. A `Val`
+    /// is used when it is subscribed, read, written, or when an
+    /// update channel is registered with it. Evicted subscriptions
+    /// receive `Event::Unsubscribed`, and their paths are sent to
+    /// the channels returned by `evictions`. Durable subscriptions
+    /// are never evicted, but they count against the budget.
+    ///
+    /// `None`, the default, means unlimited.
+    pub fn set_memory_budget(&self, bytes: Option<usize>) {
+        let t = self.0.lock();
+        t.memory.budget.store(bytes.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// The approximate number of bytes currently counted against the
+    /// memory budget.
+    pub fn memory_used(&self) -> usize {
+        self.0.lock().memory.used.load(Ordering::Relaxed)
+    }
+
+    /// Return a channel that receives the path of every subscription
+    /// that is evicted to stay within the memory budget.
+    pub fn evictions(&self) -> UnboundedReceiver<Path> {
+        let (tx, rx) = mpsc::unbounded();
+        self.0.lock().evictions.push(tx);
+        rx
+    }
+
+    /// Set how connections to publishers are pooled. A subscriber
+    /// that talks to many publishers can use this to bound the
+    /// number of connections it holds, and to keep connections it is
+    /// likely to need again open for a while instead of reconnecting
+    /// each time. Connections to the same publisher are always
+    /// shared, except for isolated publications. This applies to new
+    /// connections and to the next idle check of existing ones.
+    pub fn set_connection_pool(&self, cfg: ConnectionPoolCfg) {
+        self.0.lock().pool = cfg;
+    }
+
+    /// Return the current connection pool configuration
+    pub fn connection_pool(&self) -> ConnectionPoolCfg {
+        self.0.lock().pool
+    }
+
+    /// Return statistics about connections to publishers
+    pub fn connection_pool_stats(&self) -> ConnectionPoolStats {
+        let t = self.0.lock();
+        ConnectionPoolStats {
+            open: t.open_connections(),
+            idle: t.idle.len(),
+            ..t.pool_stats
+        }
+    }
+
+    /// Evict the least recently used non durable subscriptions until
+    /// enough memory will be freed to get under budget.
+    fn evict(&self) {
+        let mut guard = self.0.lock();
+        let t = &mut *guard;
+        let mut excess = t.memory.excess();
+        if excess == 0 {
+            return;
+        }
+        let mut vals = t
+            .subscribed
+            .iter()
+            .filter_map(|(path, st)| match st {
+                SubStatus::Pending(_) => None,
+                SubStatus::Subscribed(_)
+                    if t.durable_alive.contains_key(path)
+                        || t.durable_pending.contains_key(path) =>
+                {
+                    None
+                }
+                SubStatus::Subscribed(val) => val.upgrade().map(|v| (path.clone(), v)),
+            })
+            .collect::<Vec<_>>();
+        vals.sort_by_key(|(_, v)| v.0.used.load(Ordering::Relaxed));
+        for (path, val) in vals {
+            if excess == 0 {
+                break;
+            }
+            let bytes = Pack::encoded_len(&val.0.last.lock().1);
+            info!("evicting {} to stay within the memory budget", path);
+            t.memory.evicting.fetch_add(bytes, Ordering::Relaxed);
+            val.0.connection.send(ToCon::Evict(val.0.id, bytes));
+            excess = excess.saturating_sub(bytes);
+            t.subscribed.remove(&path);
+            t.evictions.retain(|tx| tx.unbounded_send(path.clone()).is_ok());
+        }
+    }
+
     /// return stats about durable subscriptions
     pub fn durable_stats(&self) -> DurableStats {
         let t = self.0.lock();
@@ -810,17 +1862,147 @@ impl Subscriber {
     }
 
     pub fn is_subscribed_or_pending(&self, path: &Path) -> bool {
+        self.0.lock().is_subscribed_or_pending(path)
+    }
+
+    /// Attach an application defined tag to the subscription of
+    /// `path`, replacing any previous tag. The tag applies to both
+    /// durable and non durable subscriptions, and it survives
+    /// resubscription. `path` must already be subscribed, or have a
+    /// subscription in progress, otherwise this does nothing. Updates
+    /// received by tagged subscriptions are accumulated per tag, see
+    /// `tag_stats`.
+    ///
+    /// Tags are forgotten once the last subscription of the path is
+    /// dropped, but the accumulated counts for the tag are kept.
+    pub fn tag(&self, path: &Path, tag: ArcStr) {
+        let mut t = self.0.lock();
+        if !t.is_subscribed_or_pending(path) {
+            return;
+        }
+        t.tags.entry(tag.clone()).or_insert_with(Arc::default);
+        t.tagged.insert(path.clone(), tag);
+        t.send_tag(path)
+    }
+
+    /// Remove the tag from the subscription of `path`, if any.
+    pub fn untag(&self, path: &Path) {
+        let mut t = self.0.lock();
+        if t.tagged.remove(path).is_some() {
+            t.send_tag(path)
+        }
+    }
+
+    /// Return aggregate statistics for every tag that has ever been
+    /// used with this subscriber.
+    pub fn tag_stats(&self) -> FxHashMap<ArcStr, TagStats> {
         let t = self.0.lock();
-        t.subscribed.contains_key(path)
-            || t.durable_dead.contains_key(path)
-            || t.durable_pending.contains_key(path)
-            || t.durable_alive.contains_key(path)
+        let mut stats: FxHashMap<ArcStr, TagStats> = t
+            .tags
+            .iter()
+            .map(|(tag, c)| {
+                let s = TagStats {
+                    subscriptions: 0,
+                    updates: c.updates.load(Ordering::Relaxed),
+                    bytes: c.bytes.load(Ordering::Relaxed),
+                    stale: 0,
+                };
+                (tag.clone(), s)
+            })
+            .collect();
+        for (path, tag) in t.tagged.iter() {
+            let stale =
+                t.durable_dead.contains_key(path) || t.durable_pending.contains_key(path);
+            let live =
+                t.subscribed.contains_key(path) || t.durable_alive.contains_key(path);
+            if let Some(s) = stats.get_mut(tag) {
+                if stale || live {
+                    s.subscriptions += 1;
+                }
+                if stale {
+                    s.stale += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    /// Set the priority of the subscription of `path`. The priority
+    /// applies to both durable and non durable subscriptions, and it
+    /// survives resubscription. `path` must already be subscribed,
+    /// or have a subscription in progress, otherwise this does
+    /// nothing. The priority is forgotten when the last subscription
+    /// of `path` is dropped.
+    ///
+    /// Priorities only matter when updates from a publisher arrive
+    /// faster than they can be processed. While that lasts, updates to
+    /// high priority subscriptions are delivered as they arrive,
+    /// updates to normal priority subscriptions are held back in
+    /// order, and updates to low priority subscriptions are held back
+    /// with intermediate values dropped in favor of the most recent
+    /// one. Once the connection catches up the held back updates are
+    /// delivered, normal priority first. Updates to subscriptions in a
+    /// sync group are never held back.
+    pub fn set_priority(&self, path: &Path, priority: Priority) {
+        let mut t = self.0.lock();
+        if !t.is_subscribed_or_pending(path) {
+            return;
+        }
+        match priority {
+            Priority::Normal => t.priorities.remove(path),
+            p => t.priorities.insert(path.clone(), p),
+        };
+        t.send_priority(path)
+    }
+
+    /// Ask the publisher of `path` to filter the updates it sends
+    /// for the subscription, e.g. to receive at most one update per
+    /// second from a value that changes much faster than that. The
+    /// filter survives resubscription. `path` must already be
+    /// subscribed, or have a subscription in progress, otherwise this
+    /// does nothing. The filter is forgotten when it is set back to
+    /// the default filter, or when the last subscription of `path` is
+    /// dropped.
+    ///
+    /// Filtering is done by the publisher, so it saves both bandwidth
+    /// and processing. Publishers running older versions of netidx
+    /// ignore the filter. Since the publisher filters by value, the
+    /// filter applies to every subscription of `path` made by this
+    /// subscriber.
+    pub fn set_filter(&self, path: &Path, filter: UpdateFilter) {
+        let mut t = self.0.lock();
+        if !t.is_subscribed_or_pending(path) {
+            return;
+        }
+        if filter.is_empty() {
+            t.filters.remove(path);
+        } else {
+            t.filters.insert(path.clone(), filter);
+        }
+        t.send_filter(path)
+    }
+
+    /// Create a durable subscription to `path` carrying `tag`. This
+    /// is the same as calling `subscribe` followed by `tag`.
+    pub fn subscribe_tagged(&self, path: Path, tag: ArcStr) -> Dval {
+        let dv = self.subscribe(path.clone());
+        self.tag(&path, tag);
+        dv
     }
 
     pub fn resolver(&self) -> ResolverRead {
         self.0.lock().resolver.clone()
     }
 
+    /// Resolve and subscribe to paths under `path` using the resolver
+    /// cluster described by `cfg` instead of the one the subscriber
+    /// was created with. This allows one subscriber to merge
+    /// namespaces from several clusters, e.g. `/prod` from one and
+    /// `/lab` from another. See `ResolverRead::add_root`.
+    pub fn add_root(&self, path: Path, cfg: Config) -> Result<()> {
+        self.resolver().add_root(path, cfg)
+    }
+
     fn downgrade(&self) -> SubscriberWeak {
         SubscriberWeak(Arc::downgrade(&self.0))
     }
@@ -903,6 +2085,9 @@ impl Subscriber {
                 for p in dead.iter().chain(batch.iter().map(|(p, _)| p)) {
                     durable_dead.remove(p);
                 }
+                for p in dead.iter() {
+                    subscriber.prune(p)
+                }
                 let timeout = 30 + max(10, batch.len() / 10000) * max_tries;
                 (batch, Duration::from_secs(timeout as u64))
             };
@@ -957,6 +2142,14 @@ impl Subscriber {
                                             | UpdatesFlags::NO_SPURIOUS,
                                     });
                                 }
+                                dv.groups.retain(|g| !g.0.is_closed());
+                                for tx in &dv.groups {
+                                    sub.0.connection.send(ToCon::Sync {
+                                        tx: tx.clone(),
+                                        sub_id: dv.sub_id,
+                                        id: sub.0.id,
+                                    });
+                                }
                                 if let DvState::Dead(d) = &mut dv.sub {
                                     for (v, resp) in d.queued_writes.drain(..) {
                                         sub.0
@@ -969,6 +2162,7 @@ impl Subscriber {
                             }
                         }
                     }
+                    subscriber.prune(&p)
                 }
                 update_retry(&mut *subscriber, retry);
             }
@@ -1052,6 +2246,8 @@ impl Subscriber {
         tls_ctx: Option<tls::CachedConnector>,
         uifo: Option<UserInfo>,
         addr: SocketAddr,
+        addrs: SmallVec<[SocketAddr; 2]>,
+        local_endpoint: Option<ArcStr>,
         target_auth: &TargetAuth,
         desired_auth: &DesiredAuth,
     ) -> (ConId, BatchSender<ToCon>) {
@@ -1063,6 +2259,8 @@ impl Subscriber {
         task::spawn(async move {
             let res = connection::ConnectionCtx::new(
                 addr,
+                addrs,
+                local_endpoint,
                 subscriber.clone(),
                 conid,
                 tls_ctx,
@@ -1074,6 +2272,12 @@ impl Subscriber {
             .start()
             .await;
             if let Some(subscriber) = subscriber.upgrade() {
+                {
+                    let mut t = subscriber.0.lock();
+                    t.resolver.invalidate_publisher(addr);
+                    t.idle.remove(&conid);
+                    t.reap.remove(&conid);
+                }
                 if let Entry::Occupied(mut e) =
                     subscriber.0.lock().connections.entry(addr)
                 {
@@ -1088,7 +2292,7 @@ impl Subscriber {
                         info!("connection to {} closed", addr)
                     }
                     Err(e) => {
-                        subscriber.0.lock().recently_failed.insert(addr, Instant::now());
+                        subscriber.0.lock().record_failure(addr);
                         warn!("connection to {} failed {}", addr, e)
                     }
                 }
@@ -1114,6 +2318,13 @@ impl Subscriber {
     /// attempt will be made, and the result of that one attempt will
     /// be given to each concurrent caller upon success or failure.
     ///
+    /// If a path is published by more than one publisher, and the
+    /// one chosen can't be reached or fails the subscription, then
+    /// the others will be tried in turn before giving up. Publishers
+    /// that have failed recently are less likely to be chosen. `Dval`
+    /// resubscribes through this same path, so when the publisher of
+    /// a durable subscription dies it fails over in the same way.
+    ///
     /// The timeout, if specified, will apply to each subscription
     /// individually. Any subscription that does not complete
     /// successfully before the specified timeout will result in an
@@ -1164,7 +2375,7 @@ impl Subscriber {
         #[derive(Debug)]
         enum St {
             Resolve(Streams),
-            Subscribing(oneshot::Receiver<Result<Val>>),
+            Subscribing(oneshot::Receiver<Result<Val>>, Option<Box<Failover>>),
             WaitingOther(oneshot::Receiver<Result<Val>>, Streams),
             Subscribed(Val, Streams),
             Error(Error),
@@ -1172,9 +2383,9 @@ impl Subscriber {
         let now = Instant::now();
         let mut pending: HashMap<Path, St> = HashMap::new();
         // Init
-        let r = {
+        let (r, replay) = {
             let mut t = self.0.lock();
-            t.gc_recently_failed();
+            t.gc_health();
             for (p, chans) in batch {
                 let streams: Streams = chans.into_iter().collect();
                 trace!("subscribing to {} streams {}", p, streams.len());
@@ -1192,6 +2403,7 @@ impl Subscriber {
                         SubStatus::Subscribed(r) => match r.upgrade() {
                             Some(r) => {
                                 trace!("already subscribed to {}", p);
+                                r.0.touch();
                                 pending.insert(p, St::Subscribed(r, streams));
                             }
                             None => {
@@ -1202,8 +2414,41 @@ impl Subscriber {
                     },
                 }
             }
-            t.resolver.clone()
+            (t.resolver.clone(), t.replay.clone())
         };
+        // Replay, there is nothing to resolve or connect to
+        if let Some(con) = replay {
+            let t = self.0.lock();
+            let deadline = timeout.map(|t| now + t);
+            let to_subscribe = pending
+                .iter()
+                .filter(|(_, s)| matches!(s, St::Resolve(_)))
+                .map(|(p, _)| p.clone())
+                .collect::<SmallVec<[_; 100]>>();
+            for p in to_subscribe {
+                let streams = match pending.remove(&p) {
+                    Some(St::Resolve(streams)) => streams,
+                    _ => unreachable!(),
+                };
+                let (tx, rx) = oneshot::channel();
+                con.send(ToCon::Subscribe(SubscribeValRequest {
+                    path: p.clone(),
+                    sub_id: t.durable_id(&p).unwrap_or_else(SubId::new),
+                    timestamp: 0,
+                    permissions: 0,
+                    token: Bytes::new(),
+                    resolver: SocketAddr::from(([0, 0, 0, 0], 0)),
+                    finished: tx,
+                    con: con.clone(),
+                    deadline,
+                    streams,
+                    tag: t.tag_counters(&p),
+                    priority: t.priorities.get(&p).copied().unwrap_or_default(),
+                    filter: t.filters.get(&p).copied().unwrap_or_default(),
+                }));
+                pending.insert(p, St::Subscribing(rx, None));
+            }
+        }
         // Resolve, Connect, Subscribe
         {
             let to_resolve = pending
@@ -1234,67 +2479,39 @@ impl Subscriber {
                 Ok(Ok((publishers, mut res))) => {
                     let mut t = self.0.lock();
                     let deadline = timeout.map(|t| now + t);
-                    let desired_auth = t.desired_auth.clone();
+                    let publishers = Arc::new(publishers);
                     for (p, resolved) in to_resolve.into_iter().zip(res.drain(..)) {
                         if resolved.publishers.len() == 0 {
                             pending.insert(p, St::Error(anyhow!("path not found")));
                         } else if let Some(ch) = t.choose_addr(&publishers, &resolved) {
-                            let tls_ctx = t.tls_ctx.clone();
-                            let sub_id = t.durable_id(&p).unwrap_or_else(SubId::new);
-                            let con = t.connections.entry(ch.addr).or_insert_with(|| {
-                                Connection { primary: None, isolated: HashMap::default() }
-                            });
-                            let con = if ch.flags.contains(PublishFlags::ISOLATED) {
-                                let (id, c) = self.start_connection(
-                                    tls_ctx,
-                                    ch.uifo,
-                                    ch.addr,
-                                    &ch.target_auth,
-                                    &desired_auth,
-                                );
-                                con.isolated.insert(id, c.clone());
-                                c
-                            } else {
-                                match &con.primary {
-                                    Some((_, c)) => c.clone(),
-                                    None => {
-                                        let (id, c) = self.start_connection(
-                                            tls_ctx,
-                                            ch.uifo,
-                                            ch.addr,
-                                            &ch.target_auth,
-                                            &desired_auth,
-                                        );
-                                        con.primary = Some((id, c.clone()));
-                                        c
-                                    }
-                                }
-                            };
-                            let (tx, rx) = oneshot::channel();
-                            let con_ = con.clone();
                             let streams = match pending.remove(&p) {
                                 Some(St::Resolve(streams)) => streams,
                                 _ => unreachable!(),
                             };
-                            let r = con.send(ToCon::Subscribe(SubscribeValRequest {
-                                path: p.clone(),
-                                sub_id,
-                                timestamp: resolved.timestamp,
-                                permissions: resolved.permissions as u32,
-                                token: ch.token,
-                                resolver: resolved.resolver,
-                                finished: tx,
-                                con: con_,
-                                deadline,
-                                streams,
-                            }));
-                            if r {
-                                pending.insert(p, St::Subscribing(rx));
+                            // if there is more than one publisher keep
+                            // enough state to try the others
+                            let failover = if resolved.publishers.len() > 1 {
+                                Some(Box::new(Failover {
+                                    publishers: publishers.clone(),
+                                    resolved: resolved.clone(),
+                                    addr: ch.addr,
+                                    streams: streams.clone(),
+                                    deadline,
+                                }))
                             } else {
-                                pending.insert(
-                                    p,
-                                    St::Error(Error::from(anyhow!("connection closed"))),
-                                );
+                                None
+                            };
+                            let r = self.send_subscribe(
+                                &mut t, &p, ch, &resolved, streams, deadline,
+                            );
+                            match r {
+                                Some(rx) => {
+                                    pending.insert(p, St::Subscribing(rx, failover));
+                                }
+                                None => {
+                                    let e = Error::from(anyhow!("connection closed"));
+                                    pending.insert(p, St::Error(e));
+                                }
                             }
                         } else {
                             let e = anyhow!("missing publisher record");
@@ -1322,6 +2539,9 @@ impl Subscriber {
                 }
                 St::Error(e) => {
                     let mut t = sub.0.lock();
+                    t.retag.remove(path.as_ref());
+                    t.reprioritize.remove(path.as_ref());
+                    t.refilter.remove(path.as_ref());
                     if let Some(sub) = t.subscribed.remove(path.as_ref()) {
                         match sub {
                             SubStatus::Subscribed(_) => unreachable!(),
@@ -1333,6 +2553,7 @@ impl Subscriber {
                             }
                         }
                     }
+                    t.prune(&path);
                     (path, Err(e))
                 }
                 St::WaitingOther(w, streams) => match w.await {
@@ -1351,13 +2572,43 @@ impl Subscriber {
                         (path, Ok(raw))
                     }
                 },
-                St::Subscribing(w) => {
-                    let res = match w.await {
-                        Err(e) => Err(anyhow!("connection died {}", e)),
-                        Ok(Err(e)) => Err(e),
-                        Ok(Ok(raw)) => Ok(raw),
+                St::Subscribing(mut w, mut failover) => {
+                    let res = loop {
+                        let res = match w.await {
+                            Err(e) => Err(anyhow!("connection died {}", e)),
+                            Ok(Err(e)) => Err(e),
+                            Ok(Ok(raw)) => Ok(raw),
+                        };
+                        match (res, &mut failover) {
+                            (Err(e), Some(f)) => match sub.failover(&path, f) {
+                                None => break Err(e),
+                                Some(rx) => {
+                                    warn!(
+                                        "subscribing to {} failed {}, trying {}",
+                                        path, e, f.addr
+                                    );
+                                    w = rx;
+                                }
+                            },
+                            (res, _) => break res,
+                        }
                     };
                     let mut t = sub.0.lock();
+                    let retag = if t.retag.remove(&path) {
+                        Some(t.tag_counters(&path))
+                    } else {
+                        None
+                    };
+                    let reprioritize = if t.reprioritize.remove(&path) {
+                        Some(t.priorities.get(&path).copied().unwrap_or_default())
+                    } else {
+                        None
+                    };
+                    let refilter = if t.refilter.remove(&path) {
+                        Some(t.filters.get(&path).copied().unwrap_or_default())
+                    } else {
+                        None
+                    };
                     match t.subscribed.entry(path.clone()) {
                         Entry::Vacant(_) => unreachable!(),
                         Entry::Occupied(mut e) => match res {
@@ -1368,10 +2619,23 @@ impl Subscriber {
                                         let err = Err(anyhow!("{}", err));
                                         let _ = w.send(err);
                                     }
+                                    t.prune(&path);
                                     (path, Err(err))
                                 }
                             },
                             Ok(raw) => {
+                                if let Some(tag) = retag {
+                                    raw.0.connection.send(ToCon::Tag(raw.0.id, tag));
+                                }
+                                if let Some(priority) = reprioritize {
+                                    let m = ToCon::Priority(raw.0.id, priority);
+                                    raw.0.connection.send(m);
+                                }
+                                if let Some(filter) = refilter {
+                                    raw.0
+                                        .connection
+                                        .send(ToCon::Filter(raw.0.id, filter));
+                                }
                                 let s = mem::replace(
                                     e.get_mut(),
                                     SubStatus::Subscribed(raw.downgrade()),
@@ -1394,6 +2658,138 @@ impl Subscriber {
         pending.drain().map(|(path, st)| wait_result(self.clone(), path, st)).collect()
     }
 
+    /// Send a subscribe request for `path` to the chosen publisher,
+    /// connecting to it first if necessary. Returns None if the
+    /// connection is already closed.
+    fn send_subscribe(
+        &self,
+        t: &mut SubscriberInner,
+        path: &Path,
+        ch: Chosen,
+        resolved: &Resolved,
+        streams: Streams,
+        deadline: Option<Instant>,
+    ) -> Option<oneshot::Receiver<Result<Val>>> {
+        let tls_ctx = t.tls_ctx.clone();
+        let desired_auth = t.desired_auth_for(&ch.addr, path);
+        let sub_id = t.durable_id(path).unwrap_or_else(SubId::new);
+        let tag = t.tag_counters(path);
+        let priority = t.priorities.get(path).copied().unwrap_or_default();
+        let filter = t.filters.get(path).copied().unwrap_or_default();
+        let primary = t
+            .connections
+            .get(&ch.addr)
+            .and_then(|c| c.primary.as_ref().map(|(id, _)| *id))
+            .filter(|_| !ch.flags.contains(PublishFlags::ISOLATED));
+        match primary {
+            Some(id) => {
+                t.idle.remove(&id);
+                t.pool_stats.reused += 1;
+            }
+            None => {
+                if !t.make_room() {
+                    t.pool_stats.refused += 1;
+                    let (tx, rx) = oneshot::channel();
+                    let _ = tx.send(Err(anyhow!("the connection pool is full")));
+                    return Some(rx);
+                }
+                t.pool_stats.opened += 1;
+            }
+        }
+        let con = t.connections.entry(ch.addr).or_insert_with(|| Connection {
+            primary: None,
+            isolated: HashMap::default(),
+        });
+        let con = if ch.flags.contains(PublishFlags::ISOLATED) {
+            let (id, c) = self.start_connection(
+                tls_ctx,
+                ch.uifo,
+                ch.addr,
+                ch.addrs.clone(),
+                ch.local_endpoint.clone(),
+                &ch.target_auth,
+                &desired_auth,
+            );
+            con.isolated.insert(id, c.clone());
+            c
+        } else {
+            match &con.primary {
+                Some((_, c)) => c.clone(),
+                None => {
+                    let (id, c) = self.start_connection(
+                        tls_ctx,
+                        ch.uifo,
+                        ch.addr,
+                        ch.addrs.clone(),
+                        ch.local_endpoint.clone(),
+                        &ch.target_auth,
+                        &desired_auth,
+                    );
+                    con.primary = Some((id, c.clone()));
+                    c
+                }
+            }
+        };
+        let (tx, rx) = oneshot::channel();
+        let con_ = con.clone();
+        let sent = con.send(ToCon::Subscribe(SubscribeValRequest {
+            path: path.clone(),
+            sub_id,
+            timestamp: resolved.timestamp,
+            permissions: resolved.permissions as u32,
+            token: ch.token,
+            resolver: resolved.resolver,
+            finished: tx,
+            con: con_,
+            deadline,
+            streams,
+            tag,
+            priority,
+            filter,
+        }));
+        if sent {
+            Some(rx)
+        } else {
+            None
+        }
+    }
+
+    /// The subscription to `path` via `f.addr` failed, pick one of
+    /// the other publishers of `path` and try again. Returns None if
+    /// there are no publishers left to try, or if the deadline has
+    /// passed.
+    fn failover(
+        &self,
+        path: &Path,
+        f: &mut Failover,
+    ) -> Option<oneshot::Receiver<Result<Val>>> {
+        if let Some(deadline) = f.deadline {
+            if Instant::now() >= deadline {
+                return None;
+            }
+        }
+        let mut t = self.0.lock();
+        let mut failed = f.addr;
+        loop {
+            let publishers = &f.publishers;
+            f.resolved.publishers.retain(|pref| match publishers.get(&pref.id) {
+                Some(pb) => pb.addr != failed,
+                None => false,
+            });
+            let ch = t.choose_addr(&f.publishers, &f.resolved)?;
+            let addr = ch.addr;
+            let streams = f.streams.clone();
+            match self.send_subscribe(&mut t, path, ch, &f.resolved, streams, f.deadline)
+            {
+                None => failed = addr,
+                Some(rx) => {
+                    f.addr = addr;
+                    break Some(rx);
+                }
+            }
+        }
+    }
+
     /// Subscribe to just one value.
     ///
     /// This is sufficient for a small number of paths, but if you
@@ -1435,6 +2831,24 @@ impl Subscriber {
         I: IntoIterator<Item = (UpdatesFlags, Sender<Pooled<Vec<(SubId, Event)>>>)>,
     {
         let mut t = self.0.lock();
+        let (s, new) = Self::subscribe_locked(&mut t, path, updates);
+        if new {
+            let _ = t.trigger_resub.unbounded_send(());
+        }
+        s
+    }
+
+    /// Return the existing dval for `path`, or add a new one, and
+    /// true, to the dead set. Adding several dvals under the same
+    /// lock ensures they all go out in the same batch.
+    fn subscribe_locked<I>(
+        t: &mut SubscriberInner,
+        path: Path,
+        updates: I,
+    ) -> (Dval, bool)
+    where
+        I: IntoIterator<Item = (UpdatesFlags, Sender<Pooled<Vec<(SubId, Event)>>>)>,
+    {
         if let Some(s) = t
             .durable_dead
             .get(&path)
@@ -1445,7 +2859,7 @@ impl Subscriber {
                 for (f, c) in updates {
                     s.updates(f, c)
                 }
-                return s;
+                return (s, false);
             }
         }
         let s = Dval(Arc::new(Mutex::new(DvalInner {
@@ -1455,14 +2869,16 @@ impl Subscriber {
                 waiting: Vec::new(),
                 tries: 0,
                 next_try: Instant::now(),
+                unpublished: None,
             })),
             streams: SmallVec::from_iter(
                 updates.into_iter().map(|(f, c)| (f, ChanWrap(c))),
             ),
+            groups: SmallVec::new(),
+            deadline: None,
         })));
         t.durable_dead.insert(path, s.downgrade());
-        let _ = t.trigger_resub.unbounded_send(());
-        s
+        (s, true)
     }
 
     /// Create a durable value subscription to `path` with updates
@@ -1497,11 +2913,46 @@ impl Subscriber {
         self.subscribe_internal(path, [])
     }
 
+    /// Create durable value subscriptions to all of `paths` at
+    /// once, returning the dvals in the same order as the paths.
+    ///
+    /// This is the same as calling `subscribe` on each path, except
+    /// that it is guaranteed that all the new subscriptions will be
+    /// resolved in one batched call to the resolver, and that
+    /// connections will be set up once for each publisher, no
+    /// matter how many of the paths it publishes. Use this when
+    /// subscribing to a large number of paths at once, e.g. the
+    /// cells of a table.
+    pub fn subscribe_many(&self, paths: impl IntoIterator<Item = Path>) -> Vec<Dval> {
+        let mut t = self.0.lock();
+        let mut new = false;
+        let dvs = paths
+            .into_iter()
+            .map(|path| {
+                let (s, n) = Self::subscribe_locked(&mut t, path, []);
+                new |= n;
+                s
+            })
+            .collect::<Vec<_>>();
+        if new {
+            let _ = t.trigger_resub.unbounded_send(());
+        }
+        dvs
+    }
+
+    /// Start a new write batch, see `WriteBatch`. Multiple batches
+    /// may be started concurrently.
+    pub fn start_write_batch(&self) -> WriteBatch {
+        WriteBatch(WRITE_BATCHES.take())
+    }
+
     /// This will return when all pending operations are flushed out
     /// to the publishers. This is primarially used to provide
     /// pushback in the case you want to do a lot of writes, and you
     /// need pushback in case a publisher is slow to process them,
     /// however it applies to durable_subscribe and unsubscribe as well.
+    /// If the subscriber is recording it also waits for everything
+    /// recorded so far to be written.
     pub async fn flush(&self) {
         let flushes = {
             let t = self.0.lock();
@@ -1519,5 +2970,9 @@ impl Subscriber {
         for flush in flushes {
             let _ = flush.await;
         }
+        let recorder = self.0.lock().recorder.clone();
+        if let Some(recorder) = recorder {
+            recorder.flush().await
+        }
     }
 }
@@ -0,0 +1,422 @@
+use super::{
+    connection::{unsubscribe, with_timestamp, ByChan, Sub},
+    use_tick, Charge, ConId, Event, Memory, NoSuchValue, SubId, SubscriberWeak, ToCon,
+    UpdatesFlags, Val, ValInner, WUpdateChan, BATCHES,
+};
+use crate::{
+    batch_channel::BatchReceiver,
+    pack::{self, Pack},
+    path::Path,
+    protocol::publisher::Id,
+    utils::ChanId,
+};
+use anyhow::{Error, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use chrono::prelude::*;
+use futures::{
+    channel::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    future,
+    prelude::*,
+    select_biased,
+};
+use fxhash::FxHashMap;
+use log::warn;
+use parking_lot::Mutex;
+use smallvec::SmallVec;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    task,
+    time::{self, Instant},
+};
+use triomphe::Arc as TArc;
+
+const MAGIC: &[u8] = b"NXREC001";
+
+/// The time the event was received, the time the publisher
+/// committed it if it sent one, the path, and the event.
+type Record = (DateTime<Utc>, Option<DateTime<Utc>>, Path, Event);
+
+#[derive(Debug)]
+enum ToRecorder {
+    Record(Record),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writes every event delivered to the subscriber's subscriptions to
+/// a file, see `SubscriberBuilder::record`.
+#[derive(Debug, Clone)]
+pub(super) struct Recorder(UnboundedSender<ToRecorder>);
+
+impl Recorder {
+    pub(super) fn new(file: &std::path::Path) -> Result<Self> {
+        let mut f = fs::File::create(file)?;
+        f.write_all(MAGIC)?;
+        let (tx, rx) = mpsc::unbounded();
+        task::spawn(Self::write_task(tokio::fs::File::from_std(f), rx));
+        Ok(Self(tx))
+    }
+
+    async fn write_task(mut file: tokio::fs::File, rx: UnboundedReceiver<ToRecorder>) {
+        let mut rx = rx.ready_chunks(10_000);
+        let mut buf = BytesMut::new();
+        let mut flushes = Vec::new();
+        while let Some(mut batch) = rx.next().await {
+            for m in batch.drain(..) {
+                match m {
+                    ToRecorder::Flush(tx) => flushes.push(tx),
+                    ToRecorder::Record(r) => {
+                        pack::encode_varint(Pack::encoded_len(&r) as u64, &mut buf);
+                        if let Err(e) = Pack::encode(&r, &mut buf) {
+                            warn!("could not record update to {}, {}", r.2, e);
+                        }
+                    }
+                }
+            }
+            let res = async {
+                file.write_all(&buf).await?;
+                file.flush().await
+            };
+            if let Err(e) = res.await {
+                warn!("writing the recording failed, stopping, {}", e);
+                break;
+            }
+            buf.clear();
+            for tx in flushes.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    pub(super) fn record(&self, ts: Option<DateTime<Utc>>, path: &Path, ev: Event) {
+        let r = (Utc::now(), ts, path.clone(), ev);
+        let _ = self.0.unbounded_send(ToRecorder::Record(r));
+    }
+
+    /// wait for everything recorded so far to be written
+    pub(super) async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.0.unbounded_send(ToRecorder::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+pub(super) fn load(file: &std::path::Path) -> Result<Vec<Record>> {
+    let mut buf = Bytes::from(fs::read(file)?);
+    if !buf.starts_with(MAGIC) {
+        bail!("{} is not a subscriber recording", file.display())
+    }
+    buf.advance(MAGIC.len());
+    let mut records = Vec::new();
+    while buf.has_remaining() {
+        // the recording may end in a partially written record if
+        // the recording process died
+        let len = match pack::decode_varint(&mut buf) {
+            Ok(len) if len as usize <= buf.remaining() => len as usize,
+            Ok(_) | Err(_) => break,
+        };
+        let mut r = buf.split_to(len);
+        records.push(<Record as Pack>::decode(&mut r)?);
+    }
+    Ok(records)
+}
+
+/// Stands in for the connections to publishers when the subscriber
+/// is replaying a recording. Subscriptions to recorded paths succeed
+/// immediatly, and the recorded events are delivered to them at the
+/// same offset from the start of the replay as they had from the
+/// start of the recording.
+pub(super) struct Replay {
+    subscriber: SubscriberWeak,
+    conid: ConId,
+    records: Vec<Record>,
+    start: Instant,
+    // the indexes of the records of each path
+    by_path: HashMap<Path, Vec<usize>>,
+    // the index of the next record to play
+    next: usize,
+    subscriptions: FxHashMap<Id, Sub>,
+    by_sub_path: HashMap<Path, Id>,
+    by_receiver: FxHashMap<WUpdateChan, ChanId>,
+    by_chan: ByChan,
+    memory: Arc<Memory>,
+}
+
+impl Replay {
+    pub(super) fn new(
+        subscriber: SubscriberWeak,
+        conid: ConId,
+        records: Vec<Record>,
+    ) -> Self {
+        let mut by_path: HashMap<Path, Vec<usize>> = HashMap::new();
+        for (i, (_, _, path, _)) in records.iter().enumerate() {
+            by_path.entry(path.clone()).or_insert_with(Vec::new).push(i);
+        }
+        let memory = match subscriber.upgrade() {
+            None => Arc::new(Memory::default()),
+            Some(s) => s.0.lock().memory.clone(),
+        };
+        Self {
+            subscriber,
+            conid,
+            records,
+            start: Instant::now(),
+            by_path,
+            next: 0,
+            subscriptions: HashMap::default(),
+            by_sub_path: HashMap::new(),
+            by_receiver: HashMap::default(),
+            by_chan: HashMap::default(),
+            memory,
+        }
+    }
+
+    fn due(&self, i: usize) -> Option<Instant> {
+        let first = self.records.first()?.0;
+        let offset = (self.records.get(i)?.0 - first).to_std().unwrap_or(Duration::ZERO);
+        Some(self.start + offset)
+    }
+
+    fn connect_stream(
+        &mut self,
+        id: Id,
+        sub_id: SubId,
+        tx: WUpdateChan,
+        flags: UpdatesFlags,
+    ) {
+        if let Some(sub) = self.subscriptions.get_mut(&id) {
+            let by_receiver = &mut self.by_receiver;
+            let already_have = sub.streams.iter().any(|(_, c, _)| c == &tx);
+            sub.streams.retain(|(_, c, _)| {
+                let closed = c.0.is_closed();
+                if closed {
+                    by_receiver.remove(c);
+                }
+                !closed
+            });
+            let chan_id = *by_receiver.entry(tx.clone()).or_insert_with(ChanId::new);
+            if flags.contains(UpdatesFlags::BEGIN_WITH_LAST)
+                && !(already_have && flags.contains(UpdatesFlags::NO_SPURIOUS))
+            {
+                if let Some(last) = &sub.last {
+                    let m = match last.lock().clone() {
+                        (ts, Event::Update(v))
+                            if flags.contains(UpdatesFlags::TIMESTAMPED) =>
+                        {
+                            Event::Update(with_timestamp(ts, v))
+                        }
+                        (_, ev) => ev,
+                    };
+                    self.by_chan
+                        .entry(chan_id)
+                        .or_insert_with(|| (tx.clone(), BATCHES.take()))
+                        .1
+                        .push((sub_id, m));
+                }
+            }
+            if flags.contains(UpdatesFlags::STOP_COLLECTING_LAST) {
+                sub.last = None;
+                sub.charge.set(0);
+            }
+            if !already_have {
+                let timestamped = flags.contains(UpdatesFlags::TIMESTAMPED);
+                sub.streams.push((chan_id, tx, timestamped));
+            }
+        }
+    }
+
+    fn handle_from_sub(&mut self, m: ToCon) {
+        match m {
+            ToCon::Subscribe(req) => {
+                // make sure everything that is due has been played
+                self.play();
+                if let Some(id) = self.by_sub_path.get(&req.path).copied() {
+                    let val = self.subscriptions.get(&id).and_then(|s| s.val.upgrade());
+                    if let Some(val) = val {
+                        for (f, c) in req.streams {
+                            let f = f | UpdatesFlags::BEGIN_WITH_LAST;
+                            self.connect_stream(id, req.sub_id, c, f)
+                        }
+                        let _ = req.finished.send(Ok(val));
+                        return;
+                    }
+                }
+                // the last event of path that has been played
+                let last = self.by_path.get(&req.path).and_then(|idxs| {
+                    let played = idxs.partition_point(|i| *i < self.next);
+                    played.checked_sub(1).map(|i| &self.records[idxs[i]])
+                });
+                let (ts, v) = match last {
+                    Some((_, ts, _, Event::Update(v))) => (*ts, v.clone()),
+                    None
+                    | Some((
+                        _,
+                        _,
+                        _,
+                        Event::Unsubscribed | Event::Unpublished(_) | Event::Stale,
+                    )) => {
+                        let _ = req.finished.send(Err(Error::from(NoSuchValue)));
+                        return;
+                    }
+                };
+                let id = Id::new();
+                let charge = Charge::new(&self.memory, Pack::encoded_len(&v));
+                let last = TArc::new(Mutex::new((ts, Event::Update(v))));
+                let val = Val(Arc::new(ValInner {
+                    sub_id: req.sub_id,
+                    id,
+                    conid: self.conid,
+                    connection: req.con,
+                    last: last.clone(),
+                    used: AtomicU64::new(use_tick()),
+                }));
+                if req.finished.send(Ok(val.clone())).is_ok() {
+                    self.by_sub_path.insert(req.path.clone(), id);
+                    self.subscriptions.insert(
+                        id,
+                        Sub {
+                            path: req.path,
+                            sub_id: req.sub_id,
+                            streams: SmallVec::new(),
+                            last: Some(last),
+                            val: val.downgrade(),
+                            tag: req.tag,
+                            priority: req.priority,
+                            filtered: false,
+                            recorder: None,
+                            groups: SmallVec::new(),
+                            charge,
+                        },
+                    );
+                    for (f, c) in req.streams {
+                        let f = f | UpdatesFlags::BEGIN_WITH_LAST;
+                        self.connect_stream(id, req.sub_id, c, f)
+                    }
+                }
+            }
+            ToCon::Unsubscribe(id) => {
+                if let Some(sub) = self.subscriptions.remove(&id) {
+                    if self.by_sub_path.get(&sub.path) == Some(&id) {
+                        self.by_sub_path.remove(&sub.path);
+                    }
+                }
+            }
+            ToCon::Tag(id, tag) => {
+                if let Some(sub) = self.subscriptions.get_mut(&id) {
+                    sub.tag = tag;
+                }
+            }
+            ToCon::Priority(id, priority) => {
+                if let Some(sub) = self.subscriptions.get_mut(&id) {
+                    sub.priority = priority;
+                }
+            }
+            ToCon::Stream { id, sub_id, tx, flags } => {
+                self.connect_stream(id, sub_id, tx, flags)
+            }
+            // the recording already reflects any filter that was in
+            // place, and there is no one to write to. Dropping the
+            // reply channel fails writes that asked for one.
+            ToCon::Filter(_, _) | ToCon::Write(_, _, _) => (),
+            // recordings don't keep the publisher's batches
+            ToCon::Sync { .. } => (),
+            ToCon::Flush(tx) => {
+                let _ = tx.send(());
+            }
+            ToCon::Evict(id, bytes) => {
+                self.memory.evicted(bytes);
+                if let Some(sub) = self.subscriptions.remove(&id) {
+                    if self.by_sub_path.get(&sub.path) == Some(&id) {
+                        self.by_sub_path.remove(&sub.path);
+                    }
+                    if let Some(subscriber) = self.subscriber.upgrade() {
+                        let mut t = subscriber.0.lock();
+                        let by_chan = &mut self.by_chan;
+                        unsubscribe(&mut t, by_chan, sub, id, self.conid, None);
+                    }
+                }
+            }
+        }
+    }
+
+    fn play(&mut self) {
+        let now = Instant::now();
+        while let Some(due) = self.due(self.next) {
+            if due > now {
+                break;
+            }
+            let (_, ts, path, ev) = &self.records[self.next];
+            self.next += 1;
+            let id = match self.by_sub_path.get(path) {
+                None => continue,
+                Some(id) => *id,
+            };
+            match ev {
+                Event::Update(v) => {
+                    if let Some(sub) = self.subscriptions.get_mut(&id) {
+                        if let Some(tag) = &sub.tag {
+                            tag.record(v);
+                        }
+                        sub.queue_update(&mut self.by_chan, *ts, v.clone())
+                    }
+                }
+                Event::Unsubscribed | Event::Unpublished(_) => {
+                    let unpublished = match ev {
+                        Event::Unpublished(v) => Some(v.clone()),
+                        Event::Unsubscribed | Event::Update(_) | Event::Stale => None,
+                    };
+                    self.by_sub_path.remove(path);
+                    let sub = self.subscriptions.remove(&id);
+                    if let (Some(sub), Some(subscriber)) =
+                        (sub, self.subscriber.upgrade())
+                    {
+                        let mut t = subscriber.0.lock();
+                        let by_chan = &mut self.by_chan;
+                        unsubscribe(&mut t, by_chan, sub, id, self.conid, unpublished);
+                    }
+                }
+                Event::Stale => (),
+            }
+        }
+    }
+
+    async fn send_updates(&mut self) {
+        for (_, (mut c, batch)) in self.by_chan.drain() {
+            let _ = c.0.send(batch).await;
+        }
+    }
+
+    pub(super) async fn run(mut self, from_sub: BatchReceiver<ToCon>) {
+        async fn wait(due: Option<Instant>) {
+            match due {
+                None => future::pending().await,
+                Some(due) => time::sleep_until(due).await,
+            }
+        }
+        loop {
+            let due = self.due(self.next);
+            select_biased! {
+                batch = from_sub.recv().fuse() => match batch {
+                    None => break,
+                    Some(mut batch) => {
+                        for m in batch.drain(..) {
+                            self.handle_from_sub(m)
+                        }
+                    }
+                },
+                () = wait(due).fuse() => self.play(),
+            }
+            self.send_updates().await
+        }
+    }
+}
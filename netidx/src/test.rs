@@ -5,13 +5,20 @@ mod resolver {
         path::Path,
         protocol::glob::{Glob, GlobSet},
         publisher::PublishFlags,
-        resolver_client::{ChangeTracker, DesiredAuth, ResolverRead, ResolverWrite},
-        resolver_server::{config::Config as ServerConfig, Server},
+        resolver_client::{
+            stats::Op, ChangeTracker, DesiredAuth, FromAdmin, ResolverError,
+            ResolverRead, ResolverWrite, ToAdmin, WatchEvent,
+        },
+        resolver_server::{auth::Permissions, config::Config as ServerConfig, Server},
     };
-    use netidx_netproto::resolver::TargetAuth;
+    use futures::prelude::*;
+    use netidx_netproto::resolver::{Auth, TargetAuth};
     use rand::{thread_rng, Rng};
     use std::{iter, net::SocketAddr, time::Duration};
-    use tokio::{runtime::Runtime, time};
+    use tokio::{
+        runtime::Runtime,
+        time::{self, Instant},
+    };
 
     fn p(p: &'static str) -> Path {
         Path::from(p)
@@ -52,6 +59,108 @@ mod resolver {
         });
     }
 
+    #[test]
+    fn round_trip_stats() {
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            w.publish([p("/app/v0"), p("/app/v1")]).await.unwrap();
+            r.resolve([p("/app/v0")]).await.unwrap();
+            r.list(p("/app")).await.unwrap();
+            r.list(p("/")).await.unwrap();
+            r.check_changed(&mut ChangeTracker::new(p("/app"))).await.unwrap();
+            let ws = w.stats();
+            assert_eq!(ws.keys().collect::<Vec<_>>(), vec![&Op::Publish]);
+            assert_eq!(ws[&Op::Publish].latency.count(), 1);
+            assert_eq!(ws[&Op::Publish].errors, 0);
+            let rs = r.stats();
+            assert_eq!(
+                rs.keys().collect::<Vec<_>>(),
+                vec![&Op::Resolve, &Op::List, &Op::CheckChanged]
+            );
+            assert_eq!(rs[&Op::Resolve].latency.count(), 1);
+            assert_eq!(rs[&Op::List].latency.count(), 2);
+            assert_eq!(rs[&Op::CheckChanged].latency.count(), 1);
+            for s in rs.values() {
+                let l = &s.latency;
+                assert_eq!(s.errors, 0);
+                assert!(l.quantile(0.5) <= l.quantile(0.99));
+                assert!(l.quantile(0.99) <= l.max());
+                assert!(l.mean() <= l.max());
+            }
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn watch_children() {
+        async fn next(w: &mut (impl Stream<Item = WatchEvent> + Unpin)) -> WatchEvent {
+            time::timeout(Duration::from_secs(30), w.next()).await.unwrap().unwrap()
+        }
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            w.publish(vec![p("/app/v0"), p("/app/v1"), p("/foo/bar")]).await.unwrap();
+            let mut watch = r.watch(p("/app"));
+            assert_eq!(next(&mut watch).await, WatchEvent::Added(p("/app/v0")));
+            assert_eq!(next(&mut watch).await, WatchEvent::Added(p("/app/v1")));
+            // changes outside the watched path are not reported
+            w.publish(iter::once(p("/foo/baz"))).await.unwrap();
+            w.publish(iter::once(p("/app/v2/x"))).await.unwrap();
+            assert_eq!(next(&mut watch).await, WatchEvent::Added(p("/app/v2")));
+            w.unpublish(iter::once(p("/app/v0"))).await.unwrap();
+            assert_eq!(next(&mut watch).await, WatchEvent::Removed(p("/app/v0")));
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn watch_last_child() {
+        async fn next(w: &mut (impl Stream<Item = WatchEvent> + Unpin)) -> WatchEvent {
+            time::timeout(Duration::from_secs(30), w.next()).await.unwrap().unwrap()
+        }
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            // watching a path that doesn't exist yet
+            let mut watch = r.watch(p("/app"));
+            w.publish(iter::once(p("/app/v0"))).await.unwrap();
+            assert_eq!(next(&mut watch).await, WatchEvent::Added(p("/app/v0")));
+            // removing the last child removes /app as well
+            w.unpublish(iter::once(p("/app/v0"))).await.unwrap();
+            assert_eq!(next(&mut watch).await, WatchEvent::Removed(p("/app/v0")));
+            w.publish(iter::once(p("/app/v1"))).await.unwrap();
+            assert_eq!(next(&mut watch).await, WatchEvent::Added(p("/app/v1")));
+            drop(server)
+        });
+    }
+
     #[test]
     fn publish_default() {
         let _ = env_logger::try_init();
@@ -87,166 +196,896 @@ mod resolver {
         });
     }
 
-    struct Ctx {
-        _local: Server,
-        _root: (Server, Server),
-        _huge0: (Server, Server),
-        _huge1: (Server, Server),
-        _huge1_sub: (Server, Server),
-        cfg_local: ClientConfig,
-        cfg_root: ClientConfig,
-        cfg_huge0: ClientConfig,
-        cfg_huge1: ClientConfig,
-        cfg_huge1_sub: ClientConfig,
+    #[test]
+    fn resolve_cache() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let paths = [p("/app/a"), p("/app/b"), p("/app/c")];
+            w.publish(paths.iter().cloned()).await.unwrap();
+            // requests answered from the cache are not counted
+            let sent = || r.stats().get(&Op::Resolve).map(|s| s.latency.count());
+            r.set_cache_ttl(Some(Duration::from_millis(500)));
+            r.resolve([p("/app/a")]).await.unwrap();
+            r.resolve([p("/app/a")]).await.unwrap();
+            assert_eq!(sent(), Some(1));
+            // expiry
+            time::sleep(Duration::from_millis(600)).await;
+            r.resolve([p("/app/a")]).await.unwrap();
+            assert_eq!(sent(), Some(2));
+            // invalidate_publisher
+            r.invalidate_publisher("127.0.0.1:2".parse().unwrap());
+            r.resolve([p("/app/a")]).await.unwrap();
+            assert_eq!(sent(), Some(2));
+            r.invalidate_publisher(paddr);
+            r.resolve([p("/app/a")]).await.unwrap();
+            assert_eq!(sent(), Some(3));
+            // the oldest entry is dropped to make room
+            r.set_cache_ttl(Some(Duration::from_secs(60)));
+            r.set_cache_capacity(2);
+            r.invalidate(&p("/app/a"));
+            for path in paths.iter() {
+                r.resolve([path.clone()]).await.unwrap();
+            }
+            assert_eq!(sent(), Some(6));
+            r.resolve([p("/app/b"), p("/app/c")]).await.unwrap();
+            assert_eq!(sent(), Some(6));
+            r.resolve([p("/app/a")]).await.unwrap();
+            assert_eq!(sent(), Some(7));
+            drop(server)
+        });
     }
 
-    impl Ctx {
-        fn random_server(&self) -> ClientConfig {
-            match thread_rng().gen_range(0. ..=1.) {
-                n if n >= 0. && n <= 0.20 => self.cfg_root.clone(),
-                n if n > 0.20 && n <= 0.40 => self.cfg_huge0.clone(),
-                n if n > 0.40 && n <= 0.60 => self.cfg_huge1.clone(),
-                n if n > 0.60 && n <= 0.80 => self.cfg_huge1_sub.clone(),
-                _ => self.cfg_local.clone(),
-            }
-        }
+    #[test]
+    fn no_such_path() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            w.publish([p("/foo/bar")]).await.unwrap();
+            let missing = |e: anyhow::Error| e.downcast_ref::<ResolverError>().cloned();
+            let e = r.resolve_one(p("/foo/baz")).await.unwrap_err();
+            assert_eq!(missing(e), Some(ResolverError::NoSuchPath(p("/foo/baz"))));
+            // the batch api reports missing paths as unpublished
+            let (_, resolved) = r.resolve([p("/foo/bar"), p("/foo/baz")]).await.unwrap();
+            assert_eq!(resolved[0].publishers.len(), 1);
+            assert_eq!(resolved[1].publishers.len(), 0);
+            let e = r.list(p("/foo/baz")).await.unwrap_err();
+            assert_eq!(missing(e), Some(ResolverError::NoSuchPath(p("/foo/baz"))));
+            // a leaf exists, it just has no children
+            assert_eq!(&**r.list(p("/foo/bar")).await.unwrap(), &[]);
+            assert_eq!(&**r.list(p("/foo")).await.unwrap(), &[p("/foo/bar")]);
+            let (_, resolved) = r.resolve_one(p("/foo/bar")).await.unwrap();
+            assert_eq!(resolved.publishers.len(), 1);
+            drop(server)
+        });
+    }
 
-        async fn new() -> Ctx {
-            let server_cfg_local = ServerConfig::load("../cfg/complex-local-server.json")
-                .expect("local server config");
-            let cfg_local = ClientConfig::load("../cfg/complex-local-client.json")
-                .expect("local client config");
-            let server_cfg_root = ServerConfig::load("../cfg/complex-root-server.json")
-                .expect("root server config");
-            let cfg_root = ClientConfig::load("../cfg/complex-root-client.json")
-                .expect("root client config");
-            let server_cfg_huge0 = ServerConfig::load("../cfg/complex-huge0-server.json")
-                .expect("huge0 server config");
-            let cfg_huge0 = ClientConfig::load("../cfg/complex-huge0-client.json")
-                .expect("huge0 client config");
-            let server_cfg_huge1 = ServerConfig::load("../cfg/complex-huge1-server.json")
-                .expect("huge1 server config");
-            let cfg_huge1 = ClientConfig::load("../cfg/complex-huge1-client.json")
-                .expect("huge1 client config");
-            let server_cfg_huge1_sub =
-                ServerConfig::load("../cfg/complex-huge1-sub-server.json")
-                    .expect("huge1 sub server config");
-            let cfg_huge1_sub =
-                ClientConfig::load("../cfg/complex-huge1-sub-client.json")
-                    .expect("huge1 sub client config");
-            let server_local =
-                Server::new(server_cfg_local, false, 0).await.expect("local server");
-            let server0_root = Server::new(server_cfg_root.clone(), false, 0)
-                .await
-                .expect("root server 0");
-            let server1_root =
-                Server::new(server_cfg_root, false, 1).await.expect("root server 1");
-            let server0_huge0 = Server::new(server_cfg_huge0.clone(), false, 0)
-                .await
-                .expect("huge0 server0");
-            let server1_huge0 =
-                Server::new(server_cfg_huge0, false, 1).await.expect("huge0 server1");
-            let server0_huge1 = Server::new(server_cfg_huge1.clone(), false, 0)
-                .await
-                .expect("huge1 server0");
-            let server1_huge1 =
-                Server::new(server_cfg_huge1, false, 1).await.expect("huge1 server0");
-            let server0_huge1_sub = Server::new(server_cfg_huge1_sub.clone(), false, 0)
-                .await
-                .expect("huge1 sub server0");
-            let server1_huge1_sub = Server::new(server_cfg_huge1_sub, false, 1)
-                .await
-                .expect("huge1 sub server0");
-            Ctx {
-                _local: server_local,
-                _root: (server0_root, server1_root),
-                _huge0: (server0_huge0, server1_huge0),
-                _huge1: (server0_huge1, server1_huge1),
-                _huge1_sub: (server0_huge1_sub, server1_huge1_sub),
-                cfg_local,
-                cfg_root,
-                cfg_huge0,
-                cfg_huge1,
-                cfg_huge1_sub,
-            }
-        }
+    #[cfg(unix)]
+    #[test]
+    fn permission_denied() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let auth_path = std::env::temp_dir()
+                .join(format!("netidx-test-denied-{}", std::process::id()));
+            let auth_path = auth_path.to_str().unwrap().to_string();
+            // local auth with no permissions at all
+            let server_cfg = ServerConfig::parse(&format!(
+                r#"{{
+  "parent": null,
+  "children": [],
+  "member_servers": [
+    {{
+      "pid_file": "",
+      "addr": "127.0.0.1:0",
+      "max_connections": 768,
+      "hello_timeout": 10,
+      "reader_ttl": 60,
+      "writer_ttl": 120,
+      "auth": {{"Local": "{}"}}
+    }}
+  ],
+  "perms": {{}}
+}}"#,
+                auth_path
+            ))
+            .expect("parse server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            let auth = Auth::Local { path: Chars::from(auth_path) };
+            client_cfg.addrs[0] = (*server.local_addr(), auth);
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Local);
+            let denied = |e: anyhow::Error| e.downcast_ref::<ResolverError>().cloned();
+            let e = r.resolve([p("/foo/bar")]).await.unwrap_err();
+            assert_eq!(denied(e), Some(ResolverError::PermissionDenied(p("/foo/bar"))));
+            let e = r.list(p("/foo")).await.unwrap_err();
+            assert_eq!(denied(e), Some(ResolverError::PermissionDenied(p("/foo"))));
+            drop(server)
+        });
     }
 
-    async fn check_list(local: bool, r: &ResolverRead) {
-        let mut l = r.list(p("/")).await.unwrap();
-        l.sort();
-        if local {
-            assert_eq!(&*l, &[p("/app"), p("/local"), p("/tmp")]);
-        } else {
-            assert_eq!(&*l, &[p("/app"), p("/tmp")]);
-        }
-        if local {
-            let mut l = r.list(p("/local")).await.unwrap();
-            l.sort();
-            assert_eq!(&*l, &[p("/local/bar"), p("/local/foo")])
-        }
-        let mut l = r.list(p("/tmp")).await.unwrap();
-        l.sort();
-        assert_eq!(&*l, &[p("/tmp/x"), p("/tmp/y"), p("/tmp/z")]);
-        let mut l = r.list(p("/app")).await.unwrap();
-        l.sort();
-        assert_eq!(&*l, &[p("/app/huge0"), p("/app/huge1")]);
-        let mut l = r.list(p("/app/huge0")).await.unwrap();
-        l.sort();
-        assert_eq!(&*l, &[p("/app/huge0/x"), p("/app/huge0/y"), p("/app/huge0/z")]);
-        let mut l = r.list(p("/app/huge1")).await.unwrap();
-        l.sort();
-        assert_eq!(
-            &*l,
-            &[
-                p("/app/huge1/sub"),
-                p("/app/huge1/x"),
-                p("/app/huge1/y"),
-                p("/app/huge1/z")
-            ]
-        );
-        let mut l = r.list(p("/app/huge1/sub")).await.unwrap();
-        l.sort();
-        assert_eq!(
-            &*l,
-            &[p("/app/huge1/sub/x"), p("/app/huge1/sub/y"), p("/app/huge1/sub/z")]
-        );
-        let pat = Glob::new(Chars::from("/app/huge*/*")).unwrap();
-        let pset = GlobSet::new(true, iter::once(pat)).unwrap();
-        let mut l = Vec::new();
-        for mut b in r.list_matching(&pset).await.unwrap().drain(..) {
-            l.extend(b.drain(..));
-        }
-        l.sort();
-        assert_eq!(
-            &*l,
-            &[
-                p("/app/huge0/x"),
-                p("/app/huge0/y"),
-                p("/app/huge0/z"),
-                p("/app/huge1/x"),
-                p("/app/huge1/y"),
-                p("/app/huge1/z")
-            ]
-        );
+    #[test]
+    fn unreachable() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            // nothing is listening here once the listener is dropped
+            let addr =
+                std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+            client_cfg.addrs[0].0 = addr;
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let res = time::timeout(Duration::from_secs(120), r.resolve([p("/foo")]));
+            let e = res.await.expect("resolve timed out").unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<ResolverError>(),
+                Some(&ResolverError::Unreachable)
+            );
+            // the reason it was unreachable is kept
+            assert!(e.chain().count() > 1);
+        });
     }
 
-    async fn check_resolve(
-        ctx: &Ctx,
-        r: &ResolverRead,
-        paths: &[Path],
-        addrs: &[SocketAddr],
-    ) {
-        let (publishers, mut answer) = r.resolve(paths.iter().cloned()).await.unwrap();
-        let mut i = 0;
-        for (p, r) in paths.iter().zip(answer.drain(..)) {
-            let mut r_addrs =
-                r.publishers.iter().map(|pr| publishers[&pr.id].addr).collect::<Vec<_>>();
-            r_addrs.sort();
-            assert_eq!(r_addrs.len(), addrs.len());
-            assert_eq!(r_addrs, addrs);
-            assert!(publishers.values().all(|p| p.target_auth == TargetAuth::Anonymous));
-            match p.as_ref() {
+    #[test]
+    fn list_with_meta() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let flags = PublishFlags::USE_EXISTING.bits();
+            w.publish_with_flags([(p("/foo/bar"), Some(flags)), (p("/foo/baz"), None)])
+                .await
+                .unwrap();
+            w.publish_default(iter::once(p("/app"))).await.unwrap();
+            let all = Permissions::all().bits();
+            let l = r.list_with_meta(p("/")).await.unwrap();
+            assert_eq!(l.len(), 2);
+            assert_eq!(
+                (&l[0].path, l[0].publishers, l[0].has_children),
+                (&p("/app"), 1, false)
+            );
+            assert_eq!(
+                (&l[1].path, l[1].publishers, l[1].has_children),
+                (&p("/foo"), 0, true)
+            );
+            assert!(l.iter().all(|e| e.permissions == all));
+            let l = r.list_with_meta(p("/foo")).await.unwrap();
+            assert_eq!(l.len(), 2);
+            assert_eq!(
+                (&l[0].path, l[0].publishers, l[0].flags),
+                (&p("/foo/bar"), 1, flags)
+            );
+            assert_eq!((&l[1].path, l[1].publishers, l[1].flags), (&p("/foo/baz"), 1, 0));
+            assert!(l.iter().all(|e| !e.has_children));
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn list_with_meta_shards() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::parse(
+                r#"{
+  "parent": null,
+  "children": [],
+  "member_servers": [
+    {
+      "pid_file": "",
+      "addr": "127.0.0.1:0",
+      "max_connections": 768,
+      "hello_timeout": 10,
+      "reader_ttl": 60,
+      "writer_ttl": 120,
+      "auth": "Anonymous",
+      "shards": 4
+    }
+  ],
+  "perms": {}
+}"#,
+            )
+            .expect("parse server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            // default publishers are in every shard, they must still
+            // only be counted once
+            let mut writers = vec![];
+            for port in 1..=2 {
+                let paddr = SocketAddr::from(([127, 0, 0, 1], port));
+                let w =
+                    ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                        .unwrap();
+                w.publish_default(iter::once(p("/app"))).await.unwrap();
+                w.publish([p("/foo/bar")]).await.unwrap();
+                writers.push(w);
+            }
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let l = r.list_with_meta(p("/")).await.unwrap();
+            assert_eq!(l.len(), 2);
+            assert_eq!(
+                (&l[0].path, l[0].publishers, l[0].has_children),
+                (&p("/app"), 2, false)
+            );
+            assert_eq!(
+                (&l[1].path, l[1].publishers, l[1].has_children),
+                (&p("/foo"), 0, true)
+            );
+            let l = r.list_with_meta(p("/foo")).await.unwrap();
+            assert_eq!(l.len(), 1);
+            assert_eq!((&l[0].path, l[0].publishers), (&p("/foo/bar"), 2));
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn resolved_by() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr0: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let paddr1: SocketAddr = "127.0.0.1:2".parse().unwrap();
+            let w0 =
+                ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr0)
+                    .unwrap();
+            let w1 =
+                ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr1)
+                    .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            w0.publish([p("/a/b"), p("/a/c"), p("/z")]).await.unwrap();
+            w0.publish_default(iter::once(p("/app"))).await.unwrap();
+            w1.publish([p("/a/b"), p("/q")]).await.unwrap();
+            let l = r.resolved_by(paddr0).await.unwrap();
+            assert_eq!(&**l, &[p("/a/b"), p("/a/c"), p("/app"), p("/z")]);
+            let l = r.resolved_by(paddr1).await.unwrap();
+            assert_eq!(&**l, &[p("/a/b"), p("/q")]);
+            w1.clear().await.unwrap();
+            let l = r.resolved_by(paddr1).await.unwrap();
+            assert_eq!(&**l, &[]);
+            drop(server)
+        });
+    }
+
+    /// Write a local auth server config to a file, so it can be
+    /// reloaded, granting the user running the test every permission
+    /// including admin. Anonymous servers refuse admin requests.
+    /// Anonymous clients may still publish and resolve. A
+    /// `max_paths_per_user` of 0 is unlimited.
+    #[cfg(unix)]
+    fn admin_server_config(
+        name: &str,
+        writer_ttl: u64,
+        max_paths_per_user: usize,
+    ) -> (ServerConfig, Auth) {
+        use std::os::unix::fs::MetadataExt;
+        let base = std::env::temp_dir()
+            .join(format!("netidx-test-{}-{}", name, std::process::id()));
+        let base = base.to_str().unwrap().to_string();
+        // with DoNotMap our user name is our uid
+        let probe = format!("{}.uid", base);
+        std::fs::write(&probe, b"").unwrap();
+        let uid = std::fs::metadata(&probe).unwrap().uid();
+        std::fs::remove_file(&probe).unwrap();
+        let auth_path = format!("{}.auth", base);
+        let cfg_path = format!("{}.json", base);
+        std::fs::write(
+            &cfg_path,
+            format!(
+                r#"{{
+  "parent": null,
+  "children": [],
+  "member_servers": [
+    {{
+      "pid_file": "",
+      "addr": "127.0.0.1:0",
+      "max_connections": 768,
+      "hello_timeout": 10,
+      "reader_ttl": 60,
+      "writer_ttl": {},
+      "id_map_type": "DoNotMap",
+      "auth": {{"Local": "{}"}},
+      "quotas": {{"max_paths_per_user": {}}}
+    }}
+  ],
+  "perms": {{
+    "/": {{
+      "{}": "swlpda",
+      "": "swlpd"
+    }}
+  }}
+}}"#,
+                writer_ttl, auth_path, max_paths_per_user, uid
+            ),
+        )
+        .unwrap();
+        let cfg = ServerConfig::load(&cfg_path).expect("load admin server config");
+        (cfg, Auth::Local { path: Chars::from(auth_path) })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn admin() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let (server_cfg, auth) = admin_server_config("admin", 120, 0);
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server =
+                Server::new(server_cfg.clone(), false, 0).await.expect("start server");
+            client_cfg.addrs[0] = (*server.local_addr(), auth);
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let anon = ResolverRead::new(client_cfg.clone(), DesiredAuth::Anonymous);
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Local);
+            w.publish([p("/foo/bar"), p("/foo/baz")]).await.unwrap();
+            w.publish_default(iter::once(p("/app"))).await.unwrap();
+            let root = Path::root();
+            let e = anon.admin(&root, ToAdmin::ClearPublisher(paddr)).await.unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<ResolverError>(),
+                Some(&ResolverError::PermissionDenied(root.clone()))
+            );
+            let mut dump = match r.admin(&root, ToAdmin::Dump).await.unwrap().pop() {
+                Some((_, FromAdmin::Dump(dump))) => dump,
+                m => panic!("unexpected reply to dump {:?}", m),
+            };
+            dump.sort_by(|e0, e1| e0.path.cmp(&e1.path));
+            let dump = dump
+                .iter()
+                .map(|e| (e.path.clone(), e.publisher, e.default))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                dump,
+                vec![
+                    (p("/app"), paddr, true),
+                    (p("/foo/bar"), paddr, false),
+                    (p("/foo/baz"), paddr, false)
+                ]
+            );
+            match r.admin(&root, ToAdmin::Publishers).await.unwrap().pop() {
+                Some((_, FromAdmin::Publishers(publishers))) => {
+                    assert_eq!(publishers.len(), 1);
+                    assert_eq!(publishers[0].addr, paddr);
+                }
+                m => panic!("unexpected reply to publishers {:?}", m),
+            }
+            match r.admin(&root, ToAdmin::ClientStats).await.unwrap().pop() {
+                Some((_, FromAdmin::ClientStats(stats))) => {
+                    assert!(stats.iter().any(|s| s.publisher && s.messages >= 2));
+                }
+                m => panic!("unexpected reply to client stats {:?}", m),
+            }
+            // the publisher goes away without clearing what it published
+            drop(w);
+            match r.admin(&root, ToAdmin::ClearPublisher(paddr)).await.unwrap().pop() {
+                Some((_, FromAdmin::Cleared(true))) => (),
+                m => panic!("unexpected reply to clear publisher {:?}", m),
+            }
+            let l = r.list(p("/")).await.unwrap();
+            assert_eq!(&**l, &[]);
+            match r.admin(&root, ToAdmin::Reload).await.unwrap().pop() {
+                Some((_, FromAdmin::Reloaded)) => (),
+                m => panic!("unexpected reply to reload {:?}", m),
+            }
+            server.reload(server_cfg).await.unwrap();
+            drop(server)
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn admin_clear_releases_quota() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let (server_cfg, auth) = admin_server_config("admin_quota", 120, 2);
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0] = (*server.local_addr(), auth);
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let r = ResolverRead::new(client_cfg.clone(), DesiredAuth::Local);
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Local, paddr)
+                .unwrap();
+            w.publish([p("/foo/a"), p("/foo/b")]).await.unwrap();
+            assert!(w.publish([p("/foo/c")]).await.is_err());
+            // the publisher goes away, and an admin clears it
+            drop(w);
+            let root = Path::root();
+            match r.admin(&root, ToAdmin::ClearPublisher(paddr)).await.unwrap().pop() {
+                Some((_, FromAdmin::Cleared(true))) => (),
+                m => panic!("unexpected reply to clear publisher {:?}", m),
+            }
+            // the owner gets its whole quota back
+            let w = ResolverWrite::new(client_cfg, DesiredAuth::Local, paddr).unwrap();
+            w.publish([p("/foo/c"), p("/foo/d")]).await.unwrap();
+            assert!(w.publish([p("/foo/e")]).await.is_err());
+            let mut l = r.list(p("/foo")).await.unwrap();
+            l.sort();
+            assert_eq!(&**l, &[p("/foo/c"), p("/foo/d")]);
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn drain_migrates_clients() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let old =
+                Server::new(server_cfg.clone(), false, 0).await.expect("start server");
+            let new = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *old.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let paths = vec![p("/foo/bar"), p("/foo/baz")];
+            w.publish(paths.iter().cloned()).await.unwrap();
+            let (_, resolved) = r.resolve(paths.clone()).await.unwrap();
+            assert!(resolved.iter().all(|r| r.publishers.len() == 1));
+            let deadline = Instant::now() + Duration::from_secs(2);
+            old.drain(deadline, *new.local_addr()).await;
+            let (publishers, resolved) = r.resolve(paths.clone()).await.unwrap();
+            for r in resolved.iter() {
+                assert_eq!(r.publishers.len(), 1);
+                let pb = publishers.get(&r.publishers[0].id).unwrap();
+                assert_eq!(pb.addr, paddr);
+            }
+            drop(new)
+        });
+    }
+
+    #[test]
+    fn drain_to_member() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let mut server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            server_cfg.member_servers.push(server_cfg.member_servers[0].clone());
+            let s0 = Server::new(server_cfg.clone(), false, 0).await.expect("server 0");
+            let s1 = Server::new(server_cfg, false, 1).await.expect("server 1");
+            let auth = client_cfg.addrs[0].1.clone();
+            client_cfg.addrs =
+                vec![(*s0.local_addr(), auth.clone()), (*s1.local_addr(), auth)];
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            w.publish([p("/foo/bar")]).await.unwrap();
+            let (_, resolved) = r.resolve([p("/foo/bar")]).await.unwrap();
+            assert!(resolved.iter().all(|r| r.publishers.len() == 1));
+            let deadline = Instant::now() + Duration::from_secs(2);
+            s0.drain(deadline, *s1.local_addr()).await;
+            // the publisher already has a connection to s1, it must
+            // not open a second one that would evict the first
+            for i in 0..5 {
+                let path = Path::from(format!("/foo/{}", i));
+                w.publish([path.clone()]).await.unwrap();
+                time::sleep(Duration::from_millis(100)).await;
+                let (publishers, resolved) =
+                    r.resolve([p("/foo/bar"), path]).await.unwrap();
+                for r in resolved.iter() {
+                    assert_eq!(r.publishers.len(), 1);
+                    let pb = publishers.get(&r.publishers[0].id).unwrap();
+                    assert_eq!(pb.addr, paddr);
+                }
+            }
+            drop(s1)
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn in_band_heartbeat() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let (server_cfg, auth) = admin_server_config("in_band_heartbeat", 2, 0);
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0] = (*server.local_addr(), auth);
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Local);
+            let paths = vec![p("/foo/bar"), p("/foo/baz")];
+            w.publish(paths.iter().cloned()).await.unwrap();
+            // several ttls pass with nothing to publish
+            time::sleep(Duration::from_secs(6)).await;
+            let (_, resolved) = r.resolve(paths.clone()).await.unwrap();
+            assert!(resolved.iter().all(|r| r.publishers.len() == 1));
+            // the heartbeats all arrived on the connection that published
+            let root = Path::root();
+            match r.admin(&root, ToAdmin::ClientStats).await.unwrap().pop() {
+                Some((_, FromAdmin::ClientStats(stats))) => {
+                    let pubs = stats.iter().filter(|s| s.publisher).collect::<Vec<_>>();
+                    assert_eq!(pubs.len(), 1);
+                    assert!(pubs[0].batches >= 3);
+                }
+                m => panic!("unexpected reply to client stats {:?}", m),
+            }
+            drop(w);
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn unpublish_prefix_path_limit() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::parse(
+                r#"{
+  "parent": null,
+  "children": [],
+  "member_servers": [
+    {
+      "pid_file": "",
+      "addr": "127.0.0.1:0",
+      "max_connections": 768,
+      "hello_timeout": 10,
+      "reader_ttl": 60,
+      "writer_ttl": 120,
+      "auth": "Anonymous",
+      "rate_limits": {"max_paths_per_writer": 20}
+    }
+  ],
+  "perms": {}
+}"#,
+            )
+            .expect("parse server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            // each round stays under the limit, but the rounds together
+            // don't, so retracted paths must stop counting
+            for round in 0..5 {
+                let paths = (0..15)
+                    .map(|i| p("/app/a").append(&format!("v{}", i)))
+                    .collect::<Vec<_>>();
+                let publish = w.publish(paths.iter().cloned());
+                time::timeout(Duration::from_secs(10), publish)
+                    .await
+                    .expect("publish timed out")
+                    .unwrap();
+                assert_eq!(r.list(p("/app/a")).await.unwrap().len(), 15, "{}", round);
+                w.unpublish_prefix(iter::once(p("/app/a"))).await.unwrap();
+                let e = r.list(p("/app")).await.unwrap_err();
+                assert_eq!(
+                    e.downcast_ref::<ResolverError>(),
+                    Some(&ResolverError::NoSuchPath(p("/app"))),
+                    "{}",
+                    round
+                );
+            }
+            // republishing the same paths doesn't count them again
+            let paths = (0..15).map(|i| p("/app/a").append(&format!("v{}", i)));
+            for _ in 0..3 {
+                let publish = w.publish(paths.clone());
+                time::timeout(Duration::from_secs(10), publish)
+                    .await
+                    .expect("republish timed out")
+                    .unwrap();
+            }
+            w.publish(paths.chain(iter::once(p("/app/b")))).await.unwrap();
+            let (_, resolved) =
+                r.resolve(vec![p("/app/a/v0"), p("/app/b")]).await.unwrap();
+            assert!(resolved.iter().all(|r| r.publishers.len() == 1));
+            drop(w);
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn read_only_addrs() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::parse(
+                r#"{
+  "parent": null,
+  "children": [],
+  "member_servers": [
+    {
+      "pid_file": "",
+      "addr": "127.0.0.1:0",
+      "max_connections": 768,
+      "hello_timeout": 10,
+      "reader_ttl": 60,
+      "writer_ttl": 120,
+      "auth": "Anonymous",
+      "read_only_addrs": ["127.0.0.1:0"]
+    }
+  ],
+  "perms": {}
+}"#,
+            )
+            .expect("parse server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            let mut ro_cfg = client_cfg.clone();
+            client_cfg.addrs[0].0 = *server.local_addr();
+            ro_cfg.addrs[0].0 = server.read_only_addrs()[0];
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w =
+                ResolverWrite::new(client_cfg, DesiredAuth::Anonymous, paddr).unwrap();
+            let r = ResolverRead::new(ro_cfg.clone(), DesiredAuth::Anonymous);
+            let paths = vec![p("/foo/bar"), p("/foo/baz")];
+            w.publish(paths.iter().cloned()).await.unwrap();
+            // the replica catches up shortly after the publish
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let mut l = r.list(p("/foo")).await.unwrap();
+                l.sort();
+                if &**l == &paths[..] {
+                    break;
+                }
+                assert!(Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            let (publishers, resolved) = r.resolve(paths.clone()).await.unwrap();
+            for r in resolved.iter() {
+                assert_eq!(r.publishers.len(), 1);
+                let pb = publishers.get(&r.publishers[0].id).unwrap();
+                assert_eq!(pb.addr, paddr);
+            }
+            // publishers may not use the read only address
+            let paddr: SocketAddr = "127.0.0.1:2".parse().unwrap();
+            let w = ResolverWrite::new(ro_cfg, DesiredAuth::Anonymous, paddr).unwrap();
+            let res =
+                time::timeout(Duration::from_secs(5), w.publish([p("/foo/qux")])).await;
+            assert!(!matches!(res, Ok(Ok(()))));
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn member_failover() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let mut server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            server_cfg.member_servers.push(server_cfg.member_servers[0].clone());
+            let s0 = Server::new(server_cfg.clone(), false, 0).await.expect("server 0");
+            let s1 = Server::new(server_cfg, false, 1).await.expect("server 1");
+            let stopped = *s0.local_addr();
+            let auth = client_cfg.addrs[0].1.clone();
+            client_cfg.addrs = vec![(stopped, auth.clone()), (*s1.local_addr(), auth)];
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let to = Duration::from_secs(30);
+            time::timeout(to, w.publish([p("/foo/bar")])).await.unwrap().unwrap();
+            drop(s0);
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while tokio::net::TcpStream::connect(stopped).await.is_ok() {
+                assert!(Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            // only the remaining member can accept this, and it gets
+            // writes in order, so it has both paths once it does
+            time::timeout(to, w.publish([p("/foo/baz")])).await.unwrap().unwrap();
+            let paths = vec![p("/foo/bar"), p("/foo/baz")];
+            // clients try the members in a random order, those that
+            // try the stopped member first have to move to the other
+            for _ in 0..10 {
+                let r = ResolverRead::new(client_cfg.clone(), DesiredAuth::Anonymous);
+                let (publishers, resolved) =
+                    time::timeout(to, r.resolve(paths.clone())).await.unwrap().unwrap();
+                for r in resolved.iter() {
+                    assert_eq!(r.publishers.len(), 1);
+                    let pb = publishers.get(&r.publishers[0].id).unwrap();
+                    assert_eq!(pb.addr, paddr);
+                }
+            }
+            drop(s1)
+        });
+    }
+
+    struct Ctx {
+        _local: Server,
+        _root: (Server, Server),
+        _huge0: (Server, Server),
+        _huge1: (Server, Server),
+        _huge1_sub: (Server, Server),
+        cfg_local: ClientConfig,
+        cfg_root: ClientConfig,
+        cfg_huge0: ClientConfig,
+        cfg_huge1: ClientConfig,
+        cfg_huge1_sub: ClientConfig,
+    }
+
+    impl Ctx {
+        fn random_server(&self) -> ClientConfig {
+            match thread_rng().gen_range(0. ..=1.) {
+                n if n >= 0. && n <= 0.20 => self.cfg_root.clone(),
+                n if n > 0.20 && n <= 0.40 => self.cfg_huge0.clone(),
+                n if n > 0.40 && n <= 0.60 => self.cfg_huge1.clone(),
+                n if n > 0.60 && n <= 0.80 => self.cfg_huge1_sub.clone(),
+                _ => self.cfg_local.clone(),
+            }
+        }
+
+        async fn new() -> Ctx {
+            let server_cfg_local = ServerConfig::load("../cfg/complex-local-server.json")
+                .expect("local server config");
+            let cfg_local = ClientConfig::load("../cfg/complex-local-client.json")
+                .expect("local client config");
+            let server_cfg_root = ServerConfig::load("../cfg/complex-root-server.json")
+                .expect("root server config");
+            let cfg_root = ClientConfig::load("../cfg/complex-root-client.json")
+                .expect("root client config");
+            let server_cfg_huge0 = ServerConfig::load("../cfg/complex-huge0-server.json")
+                .expect("huge0 server config");
+            let cfg_huge0 = ClientConfig::load("../cfg/complex-huge0-client.json")
+                .expect("huge0 client config");
+            let server_cfg_huge1 = ServerConfig::load("../cfg/complex-huge1-server.json")
+                .expect("huge1 server config");
+            let cfg_huge1 = ClientConfig::load("../cfg/complex-huge1-client.json")
+                .expect("huge1 client config");
+            let server_cfg_huge1_sub =
+                ServerConfig::load("../cfg/complex-huge1-sub-server.json")
+                    .expect("huge1 sub server config");
+            let cfg_huge1_sub =
+                ClientConfig::load("../cfg/complex-huge1-sub-client.json")
+                    .expect("huge1 sub client config");
+            let server_local =
+                Server::new(server_cfg_local, false, 0).await.expect("local server");
+            let server0_root = Server::new(server_cfg_root.clone(), false, 0)
+                .await
+                .expect("root server 0");
+            let server1_root =
+                Server::new(server_cfg_root, false, 1).await.expect("root server 1");
+            let server0_huge0 = Server::new(server_cfg_huge0.clone(), false, 0)
+                .await
+                .expect("huge0 server0");
+            let server1_huge0 =
+                Server::new(server_cfg_huge0, false, 1).await.expect("huge0 server1");
+            let server0_huge1 = Server::new(server_cfg_huge1.clone(), false, 0)
+                .await
+                .expect("huge1 server0");
+            let server1_huge1 =
+                Server::new(server_cfg_huge1, false, 1).await.expect("huge1 server0");
+            let server0_huge1_sub = Server::new(server_cfg_huge1_sub.clone(), false, 0)
+                .await
+                .expect("huge1 sub server0");
+            let server1_huge1_sub = Server::new(server_cfg_huge1_sub, false, 1)
+                .await
+                .expect("huge1 sub server0");
+            Ctx {
+                _local: server_local,
+                _root: (server0_root, server1_root),
+                _huge0: (server0_huge0, server1_huge0),
+                _huge1: (server0_huge1, server1_huge1),
+                _huge1_sub: (server0_huge1_sub, server1_huge1_sub),
+                cfg_local,
+                cfg_root,
+                cfg_huge0,
+                cfg_huge1,
+                cfg_huge1_sub,
+            }
+        }
+    }
+
+    async fn check_list(local: bool, r: &ResolverRead) {
+        let mut l = r.list(p("/")).await.unwrap();
+        l.sort();
+        if local {
+            assert_eq!(&*l, &[p("/app"), p("/local"), p("/tmp")]);
+        } else {
+            assert_eq!(&*l, &[p("/app"), p("/tmp")]);
+        }
+        if local {
+            let mut l = r.list(p("/local")).await.unwrap();
+            l.sort();
+            assert_eq!(&*l, &[p("/local/bar"), p("/local/foo")])
+        }
+        let mut l = r.list(p("/tmp")).await.unwrap();
+        l.sort();
+        assert_eq!(&*l, &[p("/tmp/x"), p("/tmp/y"), p("/tmp/z")]);
+        let mut l = r.list(p("/app")).await.unwrap();
+        l.sort();
+        assert_eq!(&*l, &[p("/app/huge0"), p("/app/huge1")]);
+        let mut l = r.list(p("/app/huge0")).await.unwrap();
+        l.sort();
+        assert_eq!(&*l, &[p("/app/huge0/x"), p("/app/huge0/y"), p("/app/huge0/z")]);
+        let mut l = r.list(p("/app/huge1")).await.unwrap();
+        l.sort();
+        assert_eq!(
+            &*l,
+            &[
+                p("/app/huge1/sub"),
+                p("/app/huge1/x"),
+                p("/app/huge1/y"),
+                p("/app/huge1/z")
+            ]
+        );
+        let mut l = r.list(p("/app/huge1/sub")).await.unwrap();
+        l.sort();
+        assert_eq!(
+            &*l,
+            &[p("/app/huge1/sub/x"), p("/app/huge1/sub/y"), p("/app/huge1/sub/z")]
+        );
+        let pat = Glob::new(Chars::from("/app/huge*/*")).unwrap();
+        let pset = GlobSet::new(true, iter::once(pat)).unwrap();
+        let mut l = Vec::new();
+        for mut b in r.list_matching(&pset).await.unwrap().drain(..) {
+            l.extend(b.drain(..));
+        }
+        l.sort();
+        assert_eq!(
+            &*l,
+            &[
+                p("/app/huge0/x"),
+                p("/app/huge0/y"),
+                p("/app/huge0/z"),
+                p("/app/huge1/x"),
+                p("/app/huge1/y"),
+                p("/app/huge1/z")
+            ]
+        );
+    }
+
+    async fn check_resolve(
+        ctx: &Ctx,
+        r: &ResolverRead,
+        paths: &[Path],
+        addrs: &[SocketAddr],
+    ) {
+        let (publishers, mut answer) = r.resolve(paths.iter().cloned()).await.unwrap();
+        let mut i = 0;
+        for (p, r) in paths.iter().zip(answer.drain(..)) {
+            let mut r_addrs =
+                r.publishers.iter().map(|pr| publishers[&pr.id].addr).collect::<Vec<_>>();
+            r_addrs.sort();
+            assert_eq!(r_addrs.len(), addrs.len());
+            assert_eq!(r_addrs, addrs);
+            assert!(publishers.values().all(|p| p.target_auth == TargetAuth::Anonymous));
+            match p.as_ref() {
                 "/tmp/x" | "/tmp/y" | "/tmp/z" => assert!(
                     r.resolver == ctx.cfg_root.addrs[0].0
                         || r.resolver == ctx.cfg_root.addrs[1].0
@@ -266,271 +1105,2537 @@ mod resolver {
                 "/local/foo" | "/local/bar" => {
                     assert_eq!(r.resolver, ctx.cfg_local.addrs[0].0)
                 }
-                p => unreachable!("unexpected path {}", p),
+                p => unreachable!("unexpected path {}", p),
+            }
+            i += 1
+        }
+        assert_eq!(i, paths.len());
+    }
+
+    async fn run_publish_resolve_complex() {
+        let ctx = Ctx::new().await;
+        let waddrs =
+            vec!["127.0.0.1:5543".parse().unwrap(), "127.0.0.1:5544".parse().unwrap()];
+        let paths = [
+            "/tmp/x",
+            "/tmp/y",
+            "/tmp/z",
+            "/app/huge0/x",
+            "/app/huge0/y",
+            "/app/huge0/z",
+            "/app/huge1/x",
+            "/app/huge1/y",
+            "/app/huge1/z",
+            "/app/huge1/sub/x",
+            "/app/huge1/sub/y",
+            "/app/huge1/sub/z",
+        ]
+        .iter()
+        .map(|r| Path::from(*r))
+        .collect::<Vec<_>>();
+        let local_paths = ["/local/foo", "/local/bar"]
+            .iter()
+            .map(|r| Path::from(*r))
+            .collect::<Vec<_>>();
+        let mut ct_root = ChangeTracker::new(Path::from("/"));
+        let mut ct_app = ChangeTracker::new(Path::from("/app"));
+        let r_root = ResolverRead::new(ctx.cfg_root.clone(), DesiredAuth::Anonymous);
+        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
+        let w0 =
+            ResolverWrite::new(ctx.random_server(), DesiredAuth::Anonymous, waddrs[0])
+                .unwrap();
+        let w1 =
+            ResolverWrite::new(ctx.random_server(), DesiredAuth::Anonymous, waddrs[1])
+                .unwrap();
+        w0.publish(paths.iter().cloned()).await.unwrap();
+        let wl =
+            ResolverWrite::new(ctx.cfg_local.clone(), DesiredAuth::Anonymous, waddrs[0])
+                .unwrap();
+        wl.publish(local_paths.iter().cloned()).await.unwrap();
+        time::sleep(Duration::from_millis(1000)).await;
+        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
+        check_list(false, &r_root).await;
+        check_resolve(&ctx, &r_root, &paths, &[waddrs[0]][..]).await;
+        w1.publish(paths.iter().cloned()).await.unwrap();
+        time::sleep(Duration::from_millis(1000)).await;
+        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
+        // CR estokes: it is not strictly guaranteed that both servers
+        // in the cluster will have finished publishing all the paths
+        // when this method returns, as such this test could fail
+        // spuriously.
+        check_list(false, &r_root).await;
+        check_resolve(&ctx, &r_root, &paths, &waddrs).await;
+        let r_local = ResolverRead::new(ctx.cfg_local.clone(), DesiredAuth::Anonymous);
+        check_list(true, &r_local).await;
+        check_resolve(&ctx, &r_local, &paths, &waddrs).await;
+        check_resolve(&ctx, &r_local, &local_paths, &[waddrs[0]]).await;
+        let r_huge0 = ResolverRead::new(ctx.cfg_huge0.clone(), DesiredAuth::Anonymous);
+        check_list(false, &r_huge0).await;
+        check_resolve(&ctx, &r_huge0, &paths, &waddrs).await;
+        let r_huge1 = ResolverRead::new(ctx.cfg_huge1.clone(), DesiredAuth::Anonymous);
+        check_list(false, &r_huge1).await;
+        check_resolve(&ctx, &r_huge1, &paths, &waddrs).await;
+        let r_huge1_sub =
+            ResolverRead::new(ctx.cfg_huge1.clone(), DesiredAuth::Anonymous);
+        check_list(false, &r_huge1_sub).await;
+        check_resolve(&ctx, &r_huge1_sub, &paths, &waddrs).await;
+        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
+        w0.unpublish(paths.iter().cloned()).await.unwrap();
+        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
+        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
+        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
+        check_list(false, &r_root).await;
+        check_resolve(&ctx, &r_root, &paths, &[waddrs[1]][..]).await;
+        check_list(false, &r_huge1_sub).await;
+        check_resolve(&ctx, &r_huge1_sub, &paths, &[waddrs[1]][..]).await;
+        check_list(false, &r_huge1).await;
+        check_resolve(&ctx, &r_huge1, &paths, &[waddrs[1]][..]).await;
+        check_list(false, &r_huge0).await;
+        check_resolve(&ctx, &r_huge0, &paths, &[waddrs[1]][..]).await;
+    }
+
+    #[test]
+    fn publish_resolve_complex() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(run_publish_resolve_complex())
+    }
+}
+
+mod publisher {
+    use crate::{
+        chars::Chars,
+        config::{AuthTarget, Config as ClientConfig, SocketOpts},
+        pack::Pack,
+        path::Path,
+        pool::Pooled,
+        publisher::{
+            BindCfg, DesiredAuth, Event as PEvent, Expiry, MulticastCfg, PathStats,
+            PublishFlags, Publishable, PublishedTree, Publisher, PublisherBuilder,
+            Settings, Val, ValueTree, WriteRequest,
+        },
+        resolver_client::{ResolverRead, ResolverWrite},
+        resolver_server::{config::Config as ServerConfig, Server},
+        subscriber::{
+            ConnectionPoolCfg, ConnectionPoolStats, Dval, Event, Priority, SubId,
+            Subscriber, SubscriberBuilder, SyncGroup, TagStats, UpdateFilter,
+            UpdatesFlags, Value,
+        },
+    };
+    use bytes::Bytes;
+    use futures::{channel::mpsc, channel::oneshot, prelude::*, select_biased};
+    use parking_lot::Mutex;
+    use rand::{thread_rng, Rng};
+    use std::{
+        collections::BTreeMap,
+        iter,
+        net::{IpAddr, SocketAddr},
+        ops::DerefMut,
+        sync::Arc,
+        time::Duration,
+    };
+    use tokio::{runtime::Runtime, task, time};
+
+    /// Start a resolver server, and return it along with a client
+    /// config that points to it.
+    async fn start_resolver() -> (Server, ClientConfig) {
+        let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+            .expect("load simple server config");
+        let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+            .expect("load simple client config");
+        let server = Server::new(server_cfg, false, 0).await.expect("start server");
+        client_cfg.addrs[0].0 = *server.local_addr();
+        (server, client_cfg)
+    }
+
+    /// A resolver server, and an anonymous publisher and subscriber
+    /// that use it.
+    struct Ctx {
+        server: Server,
+        cfg: ClientConfig,
+        publisher: Publisher,
+        subscriber: Subscriber,
+    }
+
+    impl Ctx {
+        async fn new() -> Self {
+            let (server, cfg) = start_resolver().await;
+            let publisher = Publisher::new(
+                cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let subscriber = Subscriber::new(cfg.clone(), DesiredAuth::Anonymous).unwrap();
+            Self { server, cfg, publisher, subscriber }
+        }
+    }
+
+    #[test]
+    fn bindcfg() {
+        let _ = env_logger::try_init();
+        let addr: IpAddr = "192.168.0.0".parse().unwrap();
+        let netmask: IpAddr = "255.255.0.0".parse().unwrap();
+        assert_eq!(BindCfg::Match { addr, netmask }, "192.168.0.0/16".parse().unwrap());
+        let addr: IpAddr = "ffff:1c00:2700:3c00::".parse().unwrap();
+        let netmask: IpAddr = "ffff:ffff:ffff:ffff::".parse().unwrap();
+        let bc: BindCfg = "ffff:1c00:2700:3c00::/64".parse().unwrap();
+        assert_eq!(BindCfg::Match { addr, netmask }, bc);
+        let addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        assert_eq!(BindCfg::Exact(addr), "127.0.0.1:1234".parse().unwrap());
+        let addr: SocketAddr = "[ffff:1c00:2700:3c00::]:1234".parse().unwrap();
+        assert_eq!(BindCfg::Exact(addr), "[ffff:1c00:2700:3c00::]:1234".parse().unwrap());
+        assert!("192.168.0.1".parse::<BindCfg>().is_err());
+        assert!("192.168.0.1:12345/16".parse::<BindCfg>().is_err());
+        assert!("192.168.0.1/8/foo".parse::<BindCfg>().is_err());
+        assert!("ffff:1c00:2700:3c00::".parse::<BindCfg>().is_err());
+    }
+
+    async fn run_publisher(
+        cfg: ClientConfig,
+        default_destroyed: Arc<Mutex<bool>>,
+        tx: oneshot::Sender<()>,
+        auth: DesiredAuth,
+    ) {
+        let check_user = match &auth {
+            DesiredAuth::Tls { .. } => true,
+            _ => false,
+        };
+        let publisher =
+            Publisher::new(cfg, auth, "127.0.0.1/32".parse().unwrap(), 768, 3)
+                .await
+                .unwrap();
+        let vp = publisher.publish("/app/v0".into(), Value::U64(0)).unwrap();
+        publisher.alias(vp.id(), "/app/v1".into()).unwrap();
+        let mut dfp: Option<Val> = None;
+        let mut _adv: Option<Val> = None;
+        let mut df = publisher.publish_default("/app/q".into()).unwrap();
+        df.advertise("/app/q/adv".into()).unwrap();
+        publisher.flushed().await;
+        tx.send(()).unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        let (tx_ev, mut rx_ev) = mpsc::unbounded();
+        publisher.events(tx_ev);
+        publisher.writes(vp.id(), tx);
+        loop {
+            select_biased! {
+                e = rx_ev.select_next_some() => match e {
+                    PEvent::Subscribe(_, _)
+                    | PEvent::Unsubscribe(_, _)
+                    | PEvent::SlowSubscriber(_)
+                    | PEvent::SubscriberDropped(_) => (),
+                    PEvent::Destroyed(id) => {
+                        assert!(id == dfp.unwrap().id());
+                        dfp = None;
+                        *default_destroyed.lock() = true;
+                    }
+                },
+                (p, reply) = df.select_next_some() => {
+                    assert!(p.starts_with("/app/q"));
+                    if &*p == "/app/q/foo" {
+                        let f = PublishFlags::DESTROY_ON_IDLE;
+                        let p =
+                            publisher.publish_with_flags(f, p, Value::True).unwrap();
+                        dfp = Some(p);
+                        let _ = reply.send(());
+                    } else if &*p == "/app/q/adv" {
+                        _adv = Some(publisher.publish(p, Value::False).unwrap());
+                        let _ = reply.send(());
+                    } else {
+                        panic!("unexpected default subscription {}", p);
+                    }
+                },
+                mut batch = rx.select_next_some() => {
+                    let mut ub = publisher.start_batch();
+                    for req in batch.drain(..) {
+                        if check_user {
+                            assert!(req.user.is_some());
+                            assert_eq!(req.user, publisher.user(&req.client));
+                        }
+                        vp.update(&mut ub, req.value);
+                    }
+                    ub.commit(None).await;
+                }
+            }
+        }
+    }
+
+    async fn run_subscriber(
+        cfg: ClientConfig,
+        default_destroyed: Arc<Mutex<bool>>,
+        auth: DesiredAuth,
+    ) {
+        let subscriber = Subscriber::new(cfg, auth).unwrap();
+        let vs =
+            subscriber.subscribe_nondurable_one("/app/v0".into(), None).await.unwrap();
+        // we should be able to subscribe to an alias and it should
+        // behave as if we just cloned the existing
+        // subscription. E.G. no extra values in the channel.
+        let va =
+            subscriber.subscribe_nondurable_one("/app/v1".into(), None).await.unwrap();
+        let q =
+            subscriber.subscribe_nondurable_one("/app/q/foo".into(), None).await.unwrap();
+        assert_eq!(q.last(), Event::Update(Value::True));
+        let (_, res) =
+            subscriber.resolver().resolve(iter::once("/app/q/adv".into())).await.unwrap();
+        assert_eq!(res.len(), 1);
+        let a =
+            subscriber.subscribe_nondurable_one("/app/q/adv".into(), None).await.unwrap();
+        assert_eq!(a.last(), Event::Update(Value::False));
+        drop(q);
+        drop(a);
+        let mut c: u64 = 0;
+        let (tx, mut rx) = mpsc::channel(10);
+        let flags = UpdatesFlags::BEGIN_WITH_LAST | UpdatesFlags::NO_SPURIOUS;
+        vs.updates(flags, tx.clone());
+        va.updates(flags, tx);
+        loop {
+            match rx.next().await {
+                None => panic!("publisher died"),
+                Some(mut batch) => {
+                    for (_, v) in batch.drain(..) {
+                        match v {
+                            Event::Update(Value::U64(v)) => {
+                                assert_eq!(c, v);
+                                c += 1;
+                                vs.write(Value::U64(c));
+                            }
+                            v => panic!("unexpected value from publisher {:?}", v),
+                        }
+                    }
+                }
+            }
+            if c == 100 {
+                break;
+            }
+        }
+        if !*default_destroyed.lock() {
+            panic!("default publisher value was not destroyed on idle")
+        }
+    }
+
+    #[test]
+    fn publish_subscribe() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let default_destroyed = Arc::new(Mutex::new(false));
+            let (tx, ready) = oneshot::channel();
+            task::spawn(run_publisher(
+                client_cfg.clone(),
+                default_destroyed.clone(),
+                tx,
+                DesiredAuth::Anonymous,
+            ));
+            time::timeout(Duration::from_secs(1), ready).await.unwrap().unwrap();
+            run_subscriber(client_cfg, default_destroyed, DesiredAuth::Anonymous).await;
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn publish_subscribe_large_bytes() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            // big enough to take the zero copy path on both sides
+            let blob = Bytes::from((0..(1 << 20)).map(|i| i as u8).collect::<Vec<_>>());
+            let small = Bytes::from_static(b"small");
+            let vb = publisher.publish("/app/blob".into(), blob.clone()).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let s = subscriber
+                .subscribe_nondurable_one("/app/blob".into(), None)
+                .await
+                .unwrap();
+            assert_eq!(s.last(), Event::Update(Value::Bytes(blob.clone())));
+            let (tx, mut rx) = mpsc::channel(10);
+            s.updates(UpdatesFlags::empty(), tx);
+            let mut batch = publisher.start_batch();
+            vb.update(&mut batch, small.clone());
+            vb.update(&mut batch, blob.clone());
+            vb.update(&mut batch, small.clone());
+            batch.commit(None).await;
+            let mut received = vec![];
+            while received.len() < 3 {
+                let mut batch = rx.next().await.unwrap();
+                received.extend(batch.drain(..).map(|(_, e)| e));
+            }
+            let expected = [small.clone(), blob, small]
+                .into_iter()
+                .map(|b| Event::Update(Value::Bytes(b)))
+                .collect::<Vec<_>>();
+            assert_eq!(received, expected);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn publish_subscribe_typed() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let vn = publisher.publish("/app/n".into(), 1u64).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let s = subscriber.subscribe("/app/n".into());
+            let mut typed = s.typed::<f64>(UpdatesFlags::BEGIN_WITH_LAST);
+            assert_eq!(typed.next().await.unwrap().unwrap(), 1.);
+            let mut batch = publisher.start_batch();
+            vn.update(&mut batch, "not a number");
+            vn.update(&mut batch, 42i64);
+            batch.commit(None).await;
+            assert!(typed.next().await.unwrap().is_err());
+            assert_eq!(typed.next().await.unwrap().unwrap(), 42.);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn unpublish_terminal_value() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let va = publisher.publish("/app/a".into(), 1u64).unwrap();
+            let vb = publisher.publish("/app/b".into(), 2u64).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let sa = subscriber.subscribe("/app/a".into());
+            let sb = subscriber.subscribe("/app/b".into());
+            sa.wait_subscribed().await.unwrap();
+            sb.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            sa.updates(UpdatesFlags::empty(), tx.clone());
+            sb.updates(UpdatesFlags::empty(), tx);
+            va.unpublish("done");
+            drop(vb);
+            let mut events = vec![];
+            while events.len() < 2 {
+                let mut batch = rx.next().await.unwrap();
+                events.extend(batch.drain(..));
+            }
+            events.sort_by_key(|(id, _)| *id != sa.id());
+            assert_eq!(events[0].1, Event::Unpublished(Value::from("done")));
+            assert_eq!(events[1].1, Event::Unpublished(Value::U64(2)));
+            assert_eq!(sa.last(), Event::Unpublished(Value::from("done")));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn write_with_timeout() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            let vp = publisher
+                .publish_with_flags_and_writes(
+                    PublishFlags::empty(),
+                    "/app/ctl".into(),
+                    Value::Null,
+                    Some(tx),
+                )
+                .unwrap();
+            publisher.flushed().await;
+            task::spawn(async move {
+                let mut held = vec![];
+                while let Some(mut batch) = rx.next().await {
+                    for req in batch.drain(..) {
+                        match (req.value, req.send_result) {
+                            (Value::String(s), Some(r)) if &*s == "slow" => held.push(r),
+                            (_, Some(r)) => r.send(Value::from("ack")),
+                            (_, None) => (),
+                        }
+                    }
+                }
+            });
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let s = subscriber.subscribe("/app/ctl".into());
+            let to = Duration::from_secs(5);
+            let r = s.write_with_timeout(Value::from("go"), to).await.unwrap();
+            assert_eq!(r, Value::from("ack"));
+            let to = Duration::from_millis(200);
+            assert!(s.write_with_timeout(Value::from("slow"), to).await.is_err());
+            drop(vp);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn write_batch() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            let vp0 = publisher
+                .publish_with_flags_and_writes(
+                    PublishFlags::empty(),
+                    "/app/w0".into(),
+                    Value::Null,
+                    Some(tx.clone()),
+                )
+                .unwrap();
+            let vp1 = publisher
+                .publish_with_flags_and_writes(
+                    PublishFlags::empty(),
+                    "/app/w1".into(),
+                    Value::Null,
+                    Some(tx),
+                )
+                .unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let s0 = subscriber.subscribe("/app/w0".into());
+            let s1 = subscriber.subscribe("/app/w1".into());
+            s0.wait_subscribed().await.unwrap();
+            s1.wait_subscribed().await.unwrap();
+            let mut batch = subscriber.start_write_batch();
+            for i in 0..100u64 {
+                assert!(s0.queue_write(&mut batch, Value::U64(i)));
+                assert!(s1.queue_write(&mut batch, Value::U64(i)));
+            }
+            assert_eq!(batch.len(), 200);
+            batch.commit();
+            // the whole burst reaches the write handler at once, in order
+            let reqs =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(reqs.len(), 200);
+            for id in [vp0.id(), vp1.id()] {
+                let vals = reqs
+                    .iter()
+                    .filter(|r| r.id == id)
+                    .map(|r| r.value.clone())
+                    .collect::<Vec<_>>();
+                assert_eq!(vals, (0..100u64).map(Value::U64).collect::<Vec<_>>());
+            }
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn value_ttl() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let ttl = Duration::from_millis(1000);
+            let vs = publisher.publish("/app/stale".into(), 1u64).unwrap();
+            let vu = publisher.publish("/app/unpub".into(), 1u64).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let ss = subscriber.subscribe("/app/stale".into());
+            let su = subscriber.subscribe("/app/unpub".into());
+            ss.wait_subscribed().await.unwrap();
+            su.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(100);
+            ss.updates(UpdatesFlags::empty(), tx);
+            // start the ttls once subscribed, so a slow subscription
+            // can't eat into them
+            vs.set_ttl(ttl, Expiry::Stale(Value::from("stale")));
+            vu.set_ttl(ttl, Expiry::Unpublish(Value::from("dead")));
+            async fn wait_for(dv: &Dval, ev: Event) {
+                let deadline = time::Instant::now() + Duration::from_secs(10);
+                while dv.last() != ev {
+                    assert!(time::Instant::now() < deadline, "{:?}", dv.last());
+                    time::sleep(Duration::from_millis(10)).await
+                }
+            }
+            // updates keep the value fresh
+            for i in 2..10u64 {
+                time::sleep(ttl / 4).await;
+                let mut batch = publisher.start_batch();
+                vs.update(&mut batch, i);
+                batch.commit(None).await;
+            }
+            wait_for(&ss, Event::Update(Value::U64(9))).await;
+            while let Some(Some(mut batch)) = rx.next().now_or_never() {
+                for (_, ev) in batch.drain(..) {
+                    assert_ne!(ev, Event::Update(Value::from("stale")));
+                }
+            }
+            wait_for(&ss, Event::Update(Value::from("stale"))).await;
+            wait_for(&su, Event::Unpublished(Value::from("dead"))).await;
+            let mut batch = publisher.start_batch();
+            vs.update(&mut batch, 10u64);
+            batch.commit(None).await;
+            wait_for(&ss, Event::Update(Value::U64(10))).await;
+            // and the revived value expires again
+            wait_for(&ss, Event::Update(Value::from("stale"))).await;
+            drop(vu);
+            drop(server);
+        });
+    }
+
+    #[derive(Clone, Publishable)]
+    struct Disk {
+        free: u64,
+    }
+
+    #[derive(Clone, Publishable)]
+    struct Stats {
+        cpu: f64,
+        #[publishable(rename = "hostname")]
+        host: String,
+        #[publishable(skip)]
+        _private: u64,
+        #[publishable(tree)]
+        disk: Disk,
+    }
+
+    #[test]
+    fn publish_tree() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let mut stats = Stats {
+                cpu: 0.5,
+                host: String::from("foo"),
+                _private: 42,
+                disk: Disk { free: 100 },
+            };
+            let tree =
+                PublishedTree::new(&publisher, "/app/stats".into(), &stats).unwrap();
+            assert_eq!(tree.vals().len(), 3);
+            assert!(publisher.id("/app/stats/_private").is_none());
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let host = subscriber.subscribe("/app/stats/hostname".into());
+            let free = subscriber.subscribe("/app/stats/disk/free".into());
+            host.wait_subscribed().await.unwrap();
+            free.wait_subscribed().await.unwrap();
+            assert_eq!(host.last(), Event::Update(Value::from("foo")));
+            assert_eq!(free.last(), Event::Update(Value::U64(100)));
+            stats.disk.free = 50;
+            let mut batch = publisher.start_batch();
+            tree.update(&mut batch, &stats);
+            batch.commit(None).await;
+            time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(free.last(), Event::Update(Value::U64(50)));
+            drop(server);
+        });
+    }
+
+    fn value_tree(leaves: &[(&str, Value)]) -> ValueTree {
+        let mut root = BTreeMap::new();
+        for (name, v) in leaves {
+            root.insert(String::from(*name), ValueTree::Leaf(v.clone()));
+        }
+        ValueTree::Node(root)
+    }
+
+    #[test]
+    fn publish_value_tree() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let tree = value_tree(&[("a", Value::U64(1)), ("b", Value::U64(2))]);
+            let mut published =
+                publisher.publish_tree("/app/tree".into(), &tree).unwrap();
+            assert_eq!(published.paths().count(), 2);
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let a = subscriber.subscribe("/app/tree/a".into());
+            let b = subscriber.subscribe("/app/tree/b".into());
+            a.wait_subscribed().await.unwrap();
+            b.wait_subscribed().await.unwrap();
+            assert_eq!(a.last(), Event::Update(Value::U64(1)));
+            let id_a = publisher.id("/app/tree/a").unwrap();
+            let tree = value_tree(&[("a", Value::U64(3)), ("c", Value::U64(4))]);
+            let mut batch = publisher.start_batch();
+            published.update(&mut batch, &tree).unwrap();
+            batch.commit(None).await;
+            publisher.flushed().await;
+            time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(publisher.id("/app/tree/a"), Some(id_a));
+            assert_eq!(a.last(), Event::Update(Value::U64(3)));
+            assert!(publisher.id("/app/tree/b").is_none());
+            assert_eq!(b.last(), Event::Unpublished(Value::U64(2)));
+            let c = subscriber.subscribe("/app/tree/c".into());
+            c.wait_subscribed().await.unwrap();
+            assert_eq!(c.last(), Event::Update(Value::U64(4)));
+            // a leaf that can't be published leaves the tree unchanged
+            let _d = publisher.publish("/app/tree/d".into(), Value::Null).unwrap();
+            let bad = value_tree(&[("c", Value::U64(5)), ("d", Value::U64(6))]);
+            let mut batch = publisher.start_batch();
+            assert!(published.update(&mut batch, &bad).is_err());
+            assert!(publisher.id("/app/tree/a").is_some());
+            drop(published);
+            assert!(publisher.id("/app/tree/a").is_none());
+            assert!(publisher.id("/app/tree/c").is_none());
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn port_range_and_advertise() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .port_range(Some((23100, 23120)))
+                .build()
+                .await
+                .unwrap();
+            let port = publisher.addr().port();
+            assert!(port >= 23100 && port <= 23120);
+            let _v = publisher.publish("/app/ports".into(), Value::U64(42)).unwrap();
+            publisher.flushed().await;
+            let subscriber =
+                Subscriber::new(client_cfg.clone(), DesiredAuth::Anonymous).unwrap();
+            let v = subscriber
+                .subscribe_nondurable_one("/app/ports".into(), None)
+                .await
+                .unwrap();
+            assert_eq!(v.last(), Event::Update(Value::U64(42)));
+            // the only port in the range is taken
+            let r = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .port_range(Some((port, port)))
+                .build()
+                .await;
+            assert!(r.is_err());
+            // an exact port outside the range
+            let r = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1:23200".parse().unwrap()))
+                .port_range(Some((23100, 23120)))
+                .build()
+                .await;
+            assert!(r.is_err());
+            let advertised = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .advertise(Some("127.0.0.2:0".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            assert_eq!(advertised.addr().ip(), "127.0.0.2".parse::<IpAddr>().unwrap());
+            let advertised = PublisherBuilder::new(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .advertise(Some("127.0.0.2:15000".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            assert_eq!(advertised.addr(), "127.0.0.2:15000".parse().unwrap());
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn update_timestamps() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .timestamps(true)
+                .build()
+                .await
+                .unwrap();
+            let vp = publisher.publish("/app/ts".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/ts".into());
+            let mut updates = dv.timestamped(UpdatesFlags::BEGIN_WITH_LAST);
+            dv.wait_subscribed().await.unwrap();
+            // the initial value wasn't committed in a batch, so it
+            // carries the arrival time
+            let (_, v) = updates.next().await.unwrap();
+            assert_eq!(v, Value::U64(0));
+            assert_eq!(dv.last_timestamped(), None);
+            let before = chrono::Utc::now();
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::U64(1));
+            batch.commit(None).await;
+            let (ts, v) = time::timeout(Duration::from_secs(10), updates.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(v, Value::U64(1));
+            assert!(ts >= before && ts <= chrono::Utc::now());
+            assert_eq!(dv.last_timestamped(), Some((ts, Value::U64(1))));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn compression() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .compression(Some(1024))
+                .build()
+                .await
+                .unwrap();
+            let big = |i: u8| Value::Bytes(Bytes::from(vec![i; 1024 * 1024]));
+            let vp = publisher.publish("/app/big".into(), big(0)).unwrap();
+            let _small = publisher.publish("/app/small".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/big".into());
+            let small = subscriber.subscribe("/app/small".into());
+            dv.wait_subscribed().await.unwrap();
+            small.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            assert_eq!(dv.last(), Event::Update(big(0)));
+            assert_eq!(small.last(), Event::Update(Value::U64(0)));
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, big(1));
+            batch.commit(None).await;
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(up.drain(..).last().unwrap().1, Event::Update(big(1)));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn conflate() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let big = |i: u8| Value::Bytes(Bytes::from(vec![i; 256 * 1024]));
+            let fast = publisher
+                .publish_with_flags(PublishFlags::CONFLATE, "/app/fast".into(), big(0))
+                .unwrap();
+            let seq = publisher.publish("/app/seq".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dfast = subscriber.subscribe("/app/fast".into());
+            let dseq = subscriber.subscribe("/app/seq".into());
+            dfast.wait_subscribed().await.unwrap();
+            dseq.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(1);
+            dfast.updates(UpdatesFlags::empty(), tx.clone());
+            dseq.updates(UpdatesFlags::empty(), tx);
+            let n = 50;
+            task::spawn(async move {
+                for i in 1..=n {
+                    let mut batch = publisher.start_batch();
+                    fast.update(&mut batch, big(i));
+                    seq.update(&mut batch, Value::U64(i as u64));
+                    batch.commit(None).await;
+                }
+                // keep the values published until the test is done
+                future::pending::<()>().await
+            });
+            let (mut fasts, mut seqs) = (vec![], vec![]);
+            while fasts.last() != Some(&big(n))
+                || seqs.last() != Some(&Value::U64(n as u64))
+            {
+                let mut up = time::timeout(Duration::from_secs(30), rx.next())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                for (id, ev) in up.drain(..) {
+                    match ev {
+                        Event::Update(v) if id == dfast.id() => fasts.push(v),
+                        Event::Update(v) => seqs.push(v),
+                        ev => panic!("unexpected event {:?}", ev),
+                    }
+                }
+                // be a slow consumer
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            // every update to the reliable value arrives in order, the
+            // conflated value may skip, but never goes backwards
+            assert_eq!(seqs, (1..=n).map(|i| Value::U64(i as u64)).collect::<Vec<_>>());
+            assert!(fasts.len() <= n as usize);
+            assert!(fasts.windows(2).all(|w| w[0] < w[1]));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn snapshot_on_subscribe() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let vp =
+                publisher.publish("/app/book".into(), Value::from("delta 0")).unwrap();
+            let book = Arc::new(Mutex::new(vec![Value::from("delta 0")]));
+            publisher.set_snapshot(vp.id(), {
+                let book = book.clone();
+                Box::new(move |_, _, _| Value::from(book.lock().clone()))
+            });
+            publisher.flushed().await;
+            let s0 = Subscriber::new(client_cfg.clone(), DesiredAuth::Anonymous).unwrap();
+            let dv0 = s0.subscribe("/app/book".into());
+            dv0.wait_subscribed().await.unwrap();
+            assert_eq!(dv0.last(), Event::Update(Value::from(vec!["delta 0"])));
+            let (tx, mut rx) = mpsc::channel(10);
+            dv0.updates(UpdatesFlags::empty(), tx);
+            book.lock().push(Value::from("delta 1"));
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::from("delta 1"));
+            batch.commit(None).await;
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(
+                up.pop().map(|(_, ev)| ev),
+                Some(Event::Update(Value::from("delta 1")))
+            );
+            // a new subscriber gets the whole book, not the last delta
+            let s1 = Subscriber::new(client_cfg.clone(), DesiredAuth::Anonymous).unwrap();
+            let dv1 = s1.subscribe("/app/book".into());
+            dv1.wait_subscribed().await.unwrap();
+            let full = Value::from(vec!["delta 0", "delta 1"]);
+            assert_eq!(dv1.last(), Event::Update(full));
+            publisher.clear_snapshot(vp.id());
+            let s2 = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv2 = s2.subscribe("/app/book".into());
+            dv2.wait_subscribed().await.unwrap();
+            assert_eq!(dv2.last(), Event::Update(Value::from("delta 1")));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn metadata() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let path = Path::from("/app/temperature");
+            let vp = publisher.publish(path.clone(), 21.5).unwrap();
+            publisher.set_metadata(
+                vp.id(),
+                [
+                    (Chars::from("units"), Value::from("celsius")),
+                    (Chars::from("precision"), Value::from(1u32)),
+                ],
+            );
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let resolver = subscriber.resolver();
+            let meta = resolver.metadata(path.clone()).await.unwrap();
+            assert_eq!(
+                &*meta,
+                &[
+                    (Chars::from("units"), Value::from("celsius")),
+                    (Chars::from("precision"), Value::from(1u32)),
+                ]
+            );
+            publisher.set_metadata(vp.id(), iter::empty());
+            publisher.flushed().await;
+            assert!(resolver.metadata(path.clone()).await.unwrap().is_empty());
+            publisher.set_metadata(vp.id(), [(Chars::from("units"), Value::from("K"))]);
+            publisher.flushed().await;
+            assert_eq!(resolver.metadata(path.clone()).await.unwrap().len(), 1);
+            drop(vp);
+            publisher.flushed().await;
+            assert!(resolver.metadata(path).await.unwrap().is_empty());
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn sync_group() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let bid = publisher.publish("/app/book/bid".into(), 99).unwrap();
+            let ask = publisher.publish("/app/book/ask".into(), 101).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv_bid = subscriber.subscribe("/app/book/bid".into());
+            let dv_ask = subscriber.subscribe("/app/book/ask".into());
+            dv_bid.wait_subscribed().await.unwrap();
+            dv_ask.wait_subscribed().await.unwrap();
+            let mut group = SyncGroup::new();
+            group.add(&dv_bid);
+            group.add(&dv_ask);
+            // the current values aren't part of a batch
+            let mut initial = 0;
+            while initial < 2 {
+                let b =
+                    time::timeout(Duration::from_secs(10), group.next()).await.unwrap();
+                assert_eq!(b.id, None);
+                initial += b.updates.len();
+            }
+            let mut last_id = None;
+            for i in 0..10 {
+                let mut batch = publisher.start_batch();
+                bid.update(&mut batch, 99 - i);
+                ask.update(&mut batch, 101 + i);
+                batch.commit(None).await;
+                let b =
+                    time::timeout(Duration::from_secs(10), group.next()).await.unwrap();
+                assert!(b.id.is_some());
+                assert!(b.id > last_id);
+                last_id = b.id;
+                let mut updates = b.updates.iter().map(|(id, ev)| (*id, ev.clone()));
+                assert_eq!(
+                    updates.next(),
+                    Some((dv_bid.id(), Event::Update(Value::from(99 - i))))
+                );
+                assert_eq!(
+                    updates.next(),
+                    Some((dv_ask.id(), Event::Update(Value::from(101 + i))))
+                );
+                assert_eq!(updates.next(), None);
+            }
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn socket_opts() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let opts = SocketOpts {
+                nodelay: false,
+                send_buffer: Some(1 << 20),
+                recv_buffer: Some(1 << 20),
+                keepalive_time: Some(30),
+                keepalive_interval: Some(5),
+                bind: Some("127.0.0.1".parse().unwrap()),
+            };
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .socket_opts(opts.clone())
+                .build()
+                .await
+                .unwrap();
+            let vp = publisher.publish("/app/v".into(), Value::U64(42)).unwrap();
+            publisher.flushed().await;
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .socket_opts(opts)
+                .build()
+                .unwrap();
+            let dv = subscriber.subscribe("/app/v".into());
+            dv.wait_subscribed().await.unwrap();
+            assert_eq!(dv.last(), Event::Update(Value::U64(42)));
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::U64(43));
+            batch.commit(None).await;
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(up.pop().map(|(_, ev)| ev), Some(Event::Update(Value::U64(43))));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn memory_budget() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let big = Value::from("x".repeat(1000));
+            let size = big.encoded_len();
+            let _vps = (0..10)
+                .map(|i| {
+                    let path = Path::from(format!("/app/{}", i));
+                    publisher.publish(path, big.clone()).unwrap()
+                })
+                .collect::<Vec<_>>();
+            publisher.flushed().await;
+            // room for 5 values, but not 6
+            let budget = size * 5 + size / 2;
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .memory_budget(Some(budget))
+                .build()
+                .unwrap();
+            let mut evicted = subscriber.evictions();
+            let mut vals = vec![];
+            for i in 0..10 {
+                let path = Path::from(format!("/app/{}", i));
+                vals.push(subscriber.subscribe_nondurable_one(path, None).await.unwrap());
+            }
+            let mut paths = vec![];
+            for _ in 0..5 {
+                let path = time::timeout(Duration::from_secs(10), evicted.next())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                paths.push(path);
+            }
+            // the least recently used are evicted first
+            let expected = (0..5).map(|i| Path::from(format!("/app/{}", i)));
+            assert_eq!(paths, expected.collect::<Vec<_>>());
+            let deadline = time::Instant::now() + Duration::from_secs(10);
+            while subscriber.memory_used() > budget
+                || vals[4].last() != Event::Unsubscribed
+            {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            assert_eq!(vals[0].last(), Event::Unsubscribed);
+            assert_eq!(vals[9].last(), Event::Update(big));
+            assert!(evicted.try_next().is_err());
+            drop(server);
+        });
+    }
+
+    async fn wait_idle(subscriber: &Subscriber) {
+        let deadline = time::Instant::now() + Duration::from_secs(10);
+        while subscriber.connection_pool_stats().idle != 1 {
+            assert!(time::Instant::now() < deadline);
+            time::sleep(Duration::from_millis(10)).await
+        }
+    }
+
+    #[test]
+    fn connection_pool() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let mut publishers = vec![];
+            let mut vps = vec![];
+            for name in ["a", "b"] {
+                let publisher = PublisherBuilder::new(client_cfg.clone())
+                    .desired_auth(DesiredAuth::Anonymous)
+                    .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                    .build()
+                    .await
+                    .unwrap();
+                let path = Path::from(format!("/app/{}", name));
+                vps.push(publisher.publish(path, Value::U64(0)).unwrap());
+                publisher.flushed().await;
+                publishers.push(publisher);
+            }
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .heartbeat(Duration::from_millis(100), Duration::from_secs(10))
+                .connection_pool(ConnectionPoolCfg {
+                    max_connections: Some(1),
+                    idle_timeout: Duration::from_secs(60),
+                })
+                .build()
+                .unwrap();
+            let a =
+                subscriber.subscribe_nondurable_one("/app/a".into(), None).await.unwrap();
+            // the only connection is in use
+            let r = subscriber.subscribe_nondurable_one("/app/b".into(), None).await;
+            assert!(r.is_err());
+            drop(a);
+            wait_idle(&subscriber).await;
+            // the idle connection is kept open and reused
+            let a =
+                subscriber.subscribe_nondurable_one("/app/a".into(), None).await.unwrap();
+            drop(a);
+            wait_idle(&subscriber).await;
+            // the idle connection is closed to make room
+            let b =
+                subscriber.subscribe_nondurable_one("/app/b".into(), None).await.unwrap();
+            assert_eq!(b.last(), Event::Update(Value::U64(0)));
+            let deadline = time::Instant::now() + Duration::from_secs(10);
+            while subscriber.connection_pool_stats().reaped != 1 {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            let stats = subscriber.connection_pool_stats();
+            assert_eq!(
+                stats,
+                ConnectionPoolStats {
+                    open: 1,
+                    idle: 0,
+                    opened: 2,
+                    reused: 1,
+                    reaped: 1,
+                    refused: 1
+                }
+            );
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn tag_stats() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let va = publisher.publish("/app/a".into(), Value::U64(0)).unwrap();
+            let vb = publisher.publish("/app/b".into(), Value::U64(0)).unwrap();
+            let vc = publisher.publish("/app/c".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            // tags on paths that aren't subscribed are ignored
+            subscriber.tag(&"/app/a".into(), "x".into());
+            let da = subscriber.subscribe_tagged("/app/a".into(), "t".into());
+            let db = subscriber.subscribe_tagged("/app/b".into(), "t".into());
+            let dc = subscriber.subscribe("/app/c".into());
+            // tagged before the subscription is established
+            subscriber.tag(&"/app/c".into(), "u".into());
+            for dv in [&da, &db, &dc] {
+                dv.wait_subscribed().await.unwrap();
+            }
+            let mut bytes = 0;
+            for i in 1..=3u64 {
+                let mut batch = publisher.start_batch();
+                for v in [&va, &vb, &vc] {
+                    v.update(&mut batch, Value::U64(i));
+                }
+                bytes += 2 * Pack::encoded_len(&Value::U64(i)) as u64;
+                batch.commit(None).await;
+            }
+            let deadline = time::Instant::now() + Duration::from_secs(10);
+            let done = Event::Update(Value::U64(3));
+            while [&da, &db, &dc].iter().any(|dv| dv.last() != done) {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            // the initial values are not counted
+            let stats = subscriber.tag_stats();
+            assert_eq!(stats.len(), 2);
+            let expected = TagStats { subscriptions: 2, updates: 6, bytes, stale: 0 };
+            assert_eq!(stats["t"], expected);
+            let u = TagStats { subscriptions: 1, updates: 3, bytes: bytes / 2, stale: 0 };
+            assert_eq!(stats["u"], u);
+            // a tagged durable subscription is stale while its publisher is gone
+            drop(vb);
+            while subscriber.tag_stats()["t"].stale != 1 {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            assert_eq!(subscriber.tag_stats()["t"], TagStats { stale: 1, ..expected });
+            // the tag is forgotten along with the last subscription
+            drop(dc);
+            while subscriber.tag_stats()["u"].subscriptions != 0 {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            let dc = subscriber.subscribe("/app/c".into());
+            dc.wait_subscribed().await.unwrap();
+            assert_eq!(subscriber.tag_stats()["u"], TagStats { subscriptions: 0, ..u });
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn watch_settings() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let _vp = publisher.publish("/app/v".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let file = std::env::temp_dir()
+                .join(format!("netidx-test-settings-{}.json", std::process::id()));
+            std::fs::write(&file, r#"{"max_clients": 1, "timestamps": true}"#).unwrap();
+            let watch = publisher.watch_settings(file.clone(), Duration::from_millis(10));
+            watch.check().await;
+            assert_eq!(publisher.settings().max_clients, 1);
+            assert!(publisher.settings().timestamps);
+            let s0 = Subscriber::new(client_cfg.clone(), DesiredAuth::Anonymous).unwrap();
+            let _v0 = s0.subscribe_nondurable_one("/app/v".into(), None).await.unwrap();
+            // the publisher is full
+            let s1 = Subscriber::new(client_cfg.clone(), DesiredAuth::Anonymous).unwrap();
+            let timeout = Some(Duration::from_secs(5));
+            assert!(s1.subscribe_nondurable_one("/app/v".into(), timeout).await.is_err());
+            std::fs::write(&file, r#"{"max_clients": 2}"#).unwrap();
+            watch.check().await;
+            assert_eq!(publisher.settings().max_clients, 2);
+            assert!(!publisher.settings().timestamps);
+            let s2 = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let v2 = s2.subscribe_nondurable_one("/app/v".into(), timeout).await.unwrap();
+            assert_eq!(v2.last(), Event::Update(Value::U64(0)));
+            // settings that can't be reloaded are rejected
+            std::fs::write(&file, r#"{"max_clients": 3, "bind": "local"}"#).unwrap();
+            watch.check().await;
+            assert_eq!(publisher.settings().max_clients, 2);
+            // changes after the watch is dropped are ignored
+            drop(watch);
+            std::fs::write(&file, r#"{"max_clients": 5}"#).unwrap();
+            time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(publisher.settings().max_clients, 2);
+            let _ = std::fs::remove_file(&file);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn publish_settings() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let _ctl = publisher.publish_settings("/app/settings".into()).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let to = Duration::from_secs(5);
+            let write = |name: &str, v: Value| {
+                let s = subscriber.subscribe(Path::from("/app/settings").append(name));
+                async move { s.write_with_timeout(v, to).await.unwrap() }
+            };
+            assert_eq!(write("max_clients", Value::U64(10)).await, Value::Ok);
+            assert_eq!(write("timestamps", Value::True).await, Value::Ok);
+            assert_eq!(write("compression", Value::U64(1024)).await, Value::Ok);
+            assert_eq!(write("conflate", Value::False).await, Value::Ok);
+            assert!(matches!(write("slack", Value::U64(0)).await, Value::Error(_)));
+            let expected = Settings {
+                max_clients: 10,
+                slack: 3,
+                timestamps: true,
+                compression: Some(1024),
+                conflate: false,
+            };
+            assert_eq!(publisher.settings(), expected);
+            assert_eq!(write("compression", Value::U64(0)).await, Value::Ok);
+            assert_eq!(publisher.settings().compression, None);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn update_strategies() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let vp = publisher.publish("/app/v".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let s = subscriber.subscribe("/app/v".into());
+            s.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            let (tx_coalesce, mut rx_coalesce) = mpsc::channel(1);
+            let (tx_unbounded, mut rx_unbounded) = mpsc::channel(1);
+            s.updates(UpdatesFlags::empty(), tx);
+            s.updates(UpdatesFlags::COALESCE, tx_coalesce);
+            s.updates(UpdatesFlags::UNBOUNDED, tx_unbounded);
+            async fn next(
+                rx: &mut mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>,
+                events: &mut Vec<Event>,
+            ) {
+                let mut batch = time::timeout(Duration::from_secs(10), rx.next())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                events.extend(batch.drain(..).map(|(_, ev)| ev));
+            }
+            // the consumers that aren't reading don't hold up this one
+            let mut events = vec![];
+            for i in 1..=100u64 {
+                let mut batch = publisher.start_batch();
+                vp.update(&mut batch, Value::U64(i));
+                batch.commit(None).await;
+                while events.len() < i as usize {
+                    next(&mut rx, &mut events).await
+                }
+            }
+            let expected =
+                (1..=100u64).map(|i| Event::Update(Value::U64(i))).collect::<Vec<_>>();
+            assert_eq!(events, expected);
+            let mut events = vec![];
+            while events.len() < 100 {
+                next(&mut rx_unbounded, &mut events).await
+            }
+            assert_eq!(events, expected);
+            let mut events = vec![];
+            while events.last() != expected.last() {
+                next(&mut rx_coalesce, &mut events).await
+            }
+            assert!(events.len() < 100);
+            assert!(events.windows(2).all(|w| w[0] < w[1]));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn path_stats() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let vp = publisher.publish("/app/v".into(), Value::U64(0)).unwrap();
+            let _ctl =
+                publisher.publish_path_stats("/stats".into(), Duration::from_millis(100));
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/v".into());
+            dv.wait_subscribed().await.unwrap();
+            for i in 1..=3u64 {
+                let mut batch = publisher.start_batch();
+                vp.update(&mut batch, Value::U64(i));
+                batch.commit(None).await;
+            }
+            let len = Value::U64(3).encoded_len() as u64;
+            let s = publisher.path_stats(&vp.id()).unwrap();
+            assert_eq!(s, PathStats { updates: 3, bytes: 3 * len });
+            let all = publisher.all_path_stats();
+            assert!(all.contains(&(Path::from("/app/v"), s)));
+            // the metrics are published, and don't include themselves
+            let updates = subscriber.subscribe("/stats/app/v/updates".into());
+            let deadline = time::Instant::now() + Duration::from_secs(10);
+            while updates.last() != Event::Update(Value::U64(3)) {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
+            }
+            let bytes = subscriber.subscribe("/stats/app/v/bytes".into());
+            bytes.wait_subscribed().await.unwrap();
+            assert_eq!(bytes.last(), Event::Update(Value::U64(3 * len)));
+            assert!(publisher.id("/stats/stats/app/v/updates").is_none());
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn auth_override() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = |cfg: ClientConfig| async move {
+                PublisherBuilder::new(cfg)
+                    .desired_auth(DesiredAuth::Anonymous)
+                    .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                    .build()
+                    .await
+                    .unwrap()
+            };
+            let p0 = publisher(client_cfg.clone()).await;
+            let _v0 = p0.publish("/app/open".into(), Value::U64(0)).unwrap();
+            let p1 = publisher(client_cfg.clone()).await;
+            let _v1 = p1.publish("/app/secure/v".into(), Value::U64(1)).unwrap();
+            p0.flushed().await;
+            p1.flushed().await;
+            // the anonymous publisher can't accept the overridden auth
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .auth_override(
+                    AuthTarget::Namespace("/app/secure".into()),
+                    DesiredAuth::Krb5 { upn: None, spn: None },
+                )
+                .build()
+                .unwrap();
+            let to = Some(Duration::from_secs(10));
+            let v = subscriber.subscribe_nondurable_one("/app/open".into(), to).await;
+            assert_eq!(v.unwrap().last(), Event::Update(Value::U64(0)));
+            let v = subscriber.subscribe_nondurable_one("/app/secure/v".into(), to).await;
+            assert!(v.is_err());
+            // a publisher override takes precedence over the namespace
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .auth_override(
+                    AuthTarget::Namespace("/app/secure".into()),
+                    DesiredAuth::Krb5 { upn: None, spn: None },
+                )
+                .auth_override(AuthTarget::Publisher(p1.addr()), DesiredAuth::Anonymous)
+                .build()
+                .unwrap();
+            let v = subscriber.subscribe_nondurable_one("/app/secure/v".into(), to).await;
+            assert_eq!(v.unwrap().last(), Event::Update(Value::U64(1)));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn heartbeat() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let (interval, timeout) =
+                (Duration::from_millis(50), Duration::from_millis(300));
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .heartbeat(interval, timeout)
+                .build()
+                .await
+                .unwrap();
+            assert!(publisher.set_heartbeat(timeout, interval).is_err());
+            let vp = publisher.publish("/app/v".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .heartbeat(interval, timeout)
+                .build()
+                .unwrap();
+            let dv = subscriber.subscribe("/app/v".into());
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            dv.wait_subscribed().await.unwrap();
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(up.drain(..).last().unwrap().1, Event::Update(Value::U64(0)));
+            // idle for many timeouts, heartbeats should keep both
+            // sides from dropping the connection
+            time::sleep(timeout * 5).await;
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::U64(1));
+            batch.commit(None).await;
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            // if either side had timed out we would see Unsubscribed first
+            let up = up.drain(..).map(|(_, ev)| ev).collect::<Vec<_>>();
+            assert_eq!(up, vec![Event::Update(Value::U64(1))]);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn update_filter() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let vp = publisher.publish("/app/v".into(), Value::F64(0.)).unwrap();
+            publisher.flushed().await;
+            let (tx_wr, mut rx_wr) = mpsc::channel(10);
+            publisher.writes(vp.id(), tx_wr);
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let path = Path::from("/app/v");
+            let filter = UpdateFilter {
+                min_interval: Some(Duration::from_millis(200)),
+                on_change: Some(0.5),
+            };
+            let dv = subscriber.subscribe(path.clone());
+            subscriber.set_filter(&path, filter);
+            // a path that isn't subscribed doesn't get a filter
+            subscriber.set_filter(&Path::from("/app/none"), filter);
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            dv.wait_subscribed().await.unwrap();
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(up.drain(..).last().unwrap().1, Event::Update(Value::F64(0.)));
+            // the publisher handles the write after the filter
+            dv.write(Value::Null);
+            time::timeout(Duration::from_secs(10), rx_wr.next()).await.unwrap().unwrap();
+            for v in [1., 1.2, 2., 3.] {
+                let mut batch = publisher.start_batch();
+                vp.update(&mut batch, Value::F64(v));
+                batch.commit(None).await;
+            }
+            let mut recvd = vec![];
+            let last = Event::Update(Value::F64(3.));
+            while recvd.last() != Some(&last) {
+                let mut up =
+                    time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+                recvd.extend(up.drain(..).map(|(_, ev)| ev));
+            }
+            // 1.2 is within epsilon of 1, 2 is superseded by 3 before
+            // the interval elapses
+            let expected = [1., 3.].map(|v| Event::Update(Value::F64(v)));
+            assert_eq!(recvd, expected);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn update_filter_pending() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            type Writes = mpsc::Receiver<Pooled<Vec<WriteRequest>>>;
+            let Ctx { server, publisher, subscriber, .. } = Ctx::new().await;
+            let vp = publisher.publish("/app/v".into(), Value::F64(0.)).unwrap();
+            publisher.flushed().await;
+            let (tx_wr, rx_wr) = mpsc::channel(10);
+            publisher.writes(vp.id(), tx_wr);
+            let path = Path::from("/app/v");
+            let (publisher, vp) = (&publisher, &vp);
+            // updates received once everything up to `last` has arrived
+            let recv = |v: crate::subscriber::Val, last: f64, mut rx_wr: Writes| async move {
+                let (tx, mut rx) = mpsc::channel(10);
+                v.updates(UpdatesFlags::empty(), tx);
+                // the publisher handles the write after the filter
+                v.write(Value::Null);
+                rx_wr.next().await.unwrap();
+                for x in [last - 2., last - 1.8, last - 1., last] {
+                    let mut batch = publisher.start_batch();
+                    vp.update(&mut batch, Value::F64(x));
+                    batch.commit(None).await;
+                }
+                let mut recvd = vec![];
+                let last = Event::Update(Value::F64(last));
+                while recvd.last() != Some(&last) {
+                    let mut up = time::timeout(Duration::from_secs(10), rx.next())
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    recvd.extend(up.drain(..).map(|(_, ev)| ev));
+                }
+                (recvd, rx_wr)
+            };
+            // set the filter while the subscription is pending
+            let mut sub =
+                Box::pin(subscriber.subscribe_nondurable_one(path.clone(), None));
+            assert!(sub.as_mut().now_or_never().is_none());
+            let filter = UpdateFilter { min_interval: None, on_change: Some(0.5) };
+            subscriber.set_filter(&path, filter);
+            subscriber.tag(&path, "t".into());
+            let v = sub.await.unwrap();
+            let (recvd, rx_wr) = recv(v, 3., rx_wr).await;
+            let expected = [1., 2., 3.].map(|v| Event::Update(Value::F64(v)));
+            assert_eq!(recvd, expected);
+            // the filter is forgotten along with the last subscription,
+            // which also forgets the tag
+            let deadline = time::Instant::now() + Duration::from_secs(10);
+            while subscriber.tag_stats().get("t").map(|s| s.subscriptions) == Some(1) {
+                assert!(time::Instant::now() < deadline);
+                time::sleep(Duration::from_millis(10)).await
             }
-            i += 1
-        }
-        assert_eq!(i, paths.len());
+            let v = subscriber.subscribe_nondurable_one(path, None).await.unwrap();
+            let (recvd, _) = recv(v, 6., rx_wr).await;
+            let expected = [4., 4.2, 5., 6.].map(|v| Event::Update(Value::F64(v)));
+            assert_eq!(recvd, expected);
+            drop(server);
+        });
     }
 
-    async fn run_publish_resolve_complex() {
-        let ctx = Ctx::new().await;
-        let waddrs =
-            vec!["127.0.0.1:5543".parse().unwrap(), "127.0.0.1:5544".parse().unwrap()];
-        let paths = [
-            "/tmp/x",
-            "/tmp/y",
-            "/tmp/z",
-            "/app/huge0/x",
-            "/app/huge0/y",
-            "/app/huge0/z",
-            "/app/huge1/x",
-            "/app/huge1/y",
-            "/app/huge1/z",
-            "/app/huge1/sub/x",
-            "/app/huge1/sub/y",
-            "/app/huge1/sub/z",
-        ]
-        .iter()
-        .map(|r| Path::from(*r))
-        .collect::<Vec<_>>();
-        let local_paths = ["/local/foo", "/local/bar"]
-            .iter()
-            .map(|r| Path::from(*r))
-            .collect::<Vec<_>>();
-        let mut ct_root = ChangeTracker::new(Path::from("/"));
-        let mut ct_app = ChangeTracker::new(Path::from("/app"));
-        let r_root = ResolverRead::new(ctx.cfg_root.clone(), DesiredAuth::Anonymous);
-        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
-        let w0 =
-            ResolverWrite::new(ctx.random_server(), DesiredAuth::Anonymous, waddrs[0])
+    #[test]
+    fn multicast() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let port = thread_rng().gen_range(20000..60000);
+            let mut mcfg =
+                MulticastCfg::new(format!("239.255.42.99:{}", port).parse().unwrap());
+            mcfg.snapshot = Duration::from_millis(100);
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .multicast(Some(mcfg))
+                .build()
+                .await
                 .unwrap();
-        let w1 =
-            ResolverWrite::new(ctx.random_server(), DesiredAuth::Anonymous, waddrs[1])
+            let vp = publisher
+                .publish_with_flags(
+                    PublishFlags::MULTICAST,
+                    "/app/mc".into(),
+                    Value::U64(0),
+                )
                 .unwrap();
-        w0.publish(paths.iter().cloned()).await.unwrap();
-        let wl =
-            ResolverWrite::new(ctx.cfg_local.clone(), DesiredAuth::Anonymous, waddrs[0])
+            publisher.flushed().await;
+            let (tx_wr, mut rx_wr) = mpsc::channel(10);
+            publisher.writes(vp.id(), tx_wr);
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/mc".into());
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            dv.wait_subscribed().await.unwrap();
+            // the group is offered along with the subscription, and the
+            // subscriber joins it, or stays on tcp if the network
+            // doesn't allow it, before handling the write. So once the
+            // publisher has the write it knows how to send updates.
+            dv.write(Value::Null);
+            time::timeout(Duration::from_secs(10), rx_wr.next()).await.unwrap().unwrap();
+            for i in 1..=10 {
+                let mut batch = publisher.start_batch();
+                vp.update(&mut batch, Value::U64(i));
+                batch.commit(None).await;
+            }
+            let deadline = time::Instant::now() + Duration::from_secs(5);
+            loop {
+                match time::timeout_at(deadline, rx.next()).await {
+                    Err(_) | Ok(None) => panic!("timed out waiting for updates"),
+                    Ok(Some(mut up)) => {
+                        if up.drain(..).any(|(_, ev)| ev == Event::Update(Value::U64(10)))
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+            assert_eq!(dv.last(), Event::Update(Value::U64(10)));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn record_replay() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
                 .unwrap();
-        wl.publish(local_paths.iter().cloned()).await.unwrap();
-        time::sleep(Duration::from_millis(1000)).await;
-        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
-        check_list(false, &r_root).await;
-        check_resolve(&ctx, &r_root, &paths, &[waddrs[0]][..]).await;
-        w1.publish(paths.iter().cloned()).await.unwrap();
-        time::sleep(Duration::from_millis(1000)).await;
-        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
-        // CR estokes: it is not strictly guaranteed that both servers
-        // in the cluster will have finished publishing all the paths
-        // when this method returns, as such this test could fail
-        // spuriously.
-        check_list(false, &r_root).await;
-        check_resolve(&ctx, &r_root, &paths, &waddrs).await;
-        let r_local = ResolverRead::new(ctx.cfg_local.clone(), DesiredAuth::Anonymous);
-        check_list(true, &r_local).await;
-        check_resolve(&ctx, &r_local, &paths, &waddrs).await;
-        check_resolve(&ctx, &r_local, &local_paths, &[waddrs[0]]).await;
-        let r_huge0 = ResolverRead::new(ctx.cfg_huge0.clone(), DesiredAuth::Anonymous);
-        check_list(false, &r_huge0).await;
-        check_resolve(&ctx, &r_huge0, &paths, &waddrs).await;
-        let r_huge1 = ResolverRead::new(ctx.cfg_huge1.clone(), DesiredAuth::Anonymous);
-        check_list(false, &r_huge1).await;
-        check_resolve(&ctx, &r_huge1, &paths, &waddrs).await;
-        let r_huge1_sub =
-            ResolverRead::new(ctx.cfg_huge1.clone(), DesiredAuth::Anonymous);
-        check_list(false, &r_huge1_sub).await;
-        check_resolve(&ctx, &r_huge1_sub, &paths, &waddrs).await;
-        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
-        w0.unpublish(paths.iter().cloned()).await.unwrap();
-        assert!(r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_root).await.unwrap());
-        assert!(r_root.check_changed(&mut ct_app).await.unwrap());
-        assert!(!r_root.check_changed(&mut ct_app).await.unwrap());
-        check_list(false, &r_root).await;
-        check_resolve(&ctx, &r_root, &paths, &[waddrs[1]][..]).await;
-        check_list(false, &r_huge1_sub).await;
-        check_resolve(&ctx, &r_huge1_sub, &paths, &[waddrs[1]][..]).await;
-        check_list(false, &r_huge1).await;
-        check_resolve(&ctx, &r_huge1, &paths, &[waddrs[1]][..]).await;
-        check_list(false, &r_huge0).await;
-        check_resolve(&ctx, &r_huge0, &paths, &[waddrs[1]][..]).await;
+            let vp = publisher.publish("/app/rec".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let file = std::env::temp_dir()
+                .join(format!("netidx-record-replay-{}", std::process::id()));
+            type Updates = mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>;
+            fn updates(dv: &Dval) -> Updates {
+                let (tx, rx) = mpsc::channel(10);
+                dv.updates(UpdatesFlags::BEGIN_WITH_LAST, tx);
+                rx
+            }
+            async fn collect(mut rx: Updates) -> Vec<Event> {
+                let mut recvd = vec![];
+                let deadline = time::Instant::now() + Duration::from_secs(5);
+                while let Ok(Some(mut up)) = time::timeout_at(deadline, rx.next()).await {
+                    recvd.extend(up.drain(..).map(|(_, ev)| ev));
+                    if recvd.last() == Some(&Event::Update(Value::U64(5))) {
+                        break;
+                    }
+                }
+                recvd
+            }
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .record(&file)
+                .build()
+                .unwrap();
+            let dv = subscriber.subscribe("/app/rec".into());
+            let recorded = task::spawn(collect(updates(&dv)));
+            dv.wait_subscribed().await.unwrap();
+            for i in 1..=5 {
+                let mut batch = publisher.start_batch();
+                vp.update(&mut batch, Value::U64(i));
+                batch.commit(None).await;
+            }
+            let recorded = recorded.await.unwrap();
+            let expected =
+                (0..=5).map(|i| Event::Update(Value::U64(i))).collect::<Vec<_>>();
+            assert_eq!(recorded, expected);
+            subscriber.flush().await;
+            drop(dv);
+            drop(subscriber);
+            drop(publisher);
+            let replay = Subscriber::replay(&file).unwrap();
+            let dv = replay.subscribe("/app/rec".into());
+            let replayed = updates(&dv);
+            dv.wait_subscribed().await.unwrap();
+            assert_eq!(collect(replayed).await, expected);
+            let to = Some(Duration::from_secs(10));
+            let missing = replay.subscribe_nondurable_one("/app/missing".into(), to).await;
+            assert!(missing.is_err());
+            let _ = std::fs::remove_file(&file);
+            drop(server);
+        });
     }
 
     #[test]
-    fn publish_resolve_complex() {
+    fn deadline_stale() {
         let _ = env_logger::try_init();
-        Runtime::new().unwrap().block_on(run_publish_resolve_complex())
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let vp = publisher.publish("/app/stale".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let subscriber = SubscriberBuilder::new()
+                .config(client_cfg)
+                .desired_auth(DesiredAuth::Anonymous)
+                .build()
+                .unwrap();
+            let dv = subscriber.subscribe("/app/stale".into());
+            dv.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            dv.set_deadline(Duration::from_millis(100));
+            async fn next<T, B: DerefMut<Target = Vec<(T, Event)>>>(
+                rx: &mut mpsc::Receiver<B>,
+            ) -> Event {
+                let to = Duration::from_secs(5);
+                let mut b = time::timeout(to, rx.next()).await.unwrap().unwrap();
+                assert_eq!(b.len(), 1);
+                b.pop().unwrap().1
+            }
+            assert_eq!(next(&mut rx).await, Event::Stale);
+            // stale is only reported once per missed deadline
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::U64(1));
+            batch.commit(None).await;
+            assert_eq!(next(&mut rx).await, Event::Update(Value::U64(1)));
+            assert_eq!(next(&mut rx).await, Event::Stale);
+            dv.clear_deadline();
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::U64(2));
+            batch.commit(None).await;
+            assert_eq!(next(&mut rx).await, Event::Update(Value::U64(2)));
+            assert!(time::timeout(Duration::from_millis(300), rx.next()).await.is_err());
+            drop(server);
+        });
     }
-}
 
-mod publisher {
-    use crate::{
-        config::Config as ClientConfig,
-        publisher::{
-            BindCfg, DesiredAuth, Event as PEvent, PublishFlags, Publisher, Val,
-        },
-        resolver_server::{config::Config as ServerConfig, Server},
-        subscriber::{Event, Subscriber, UpdatesFlags, Value},
-    };
-    use futures::{channel::mpsc, channel::oneshot, prelude::*, select_biased};
-    use parking_lot::Mutex;
-    use std::{
-        iter,
-        net::{IpAddr, SocketAddr},
-        sync::Arc,
-        time::Duration,
-    };
-    use tokio::{runtime::Runtime, task, time};
+    #[cfg(unix)]
+    #[test]
+    fn local_endpoint() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            // nothing listens on the advertised port, so the
+            // subscription can only succeed over the local endpoint
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .advertise(Some("127.0.0.1:1".parse().unwrap()))
+                .local_endpoint(true)
+                .build()
+                .await
+                .unwrap();
+            let vp = publisher.publish("/app/local".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            let resolver = ResolverRead::new(client_cfg.clone(), DesiredAuth::Anonymous);
+            let (publishers, _) = resolver.resolve(["/app/local".into()]).await.unwrap();
+            assert!(publishers.values().all(|pb| pb.addr.port() == 1));
+            let endpoint = publishers
+                .values()
+                .find_map(|pb| pb.local_endpoint.clone())
+                .expect("local endpoint advertised");
+            assert!(std::path::Path::new(&*endpoint).exists());
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/local".into());
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            dv.wait_subscribed().await.unwrap();
+            assert_eq!(dv.last(), Event::Update(Value::U64(0)));
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(up.drain(..).last().unwrap().1, Event::Update(Value::U64(0)));
+            let mut batch = publisher.start_batch();
+            vp.update(&mut batch, Value::U64(1));
+            batch.commit(None).await;
+            let mut up =
+                time::timeout(Duration::from_secs(10), rx.next()).await.unwrap().unwrap();
+            assert_eq!(up.drain(..).last().unwrap().1, Event::Update(Value::U64(1)));
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn alt_addrs() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = PublisherBuilder::new(client_cfg.clone())
+                .desired_auth(DesiredAuth::Anonymous)
+                .bind_cfg(Some("127.0.0.1/32".parse().unwrap()))
+                .alt_bind_cfg(Some("::1/128".parse().unwrap()))
+                .build()
+                .await
+                .unwrap();
+            let alt = publisher.alt_addrs();
+            assert_eq!(alt.len(), 1);
+            assert!(alt[0].is_ipv6());
+            let _vp = publisher.publish("/app/dual".into(), Value::U64(0)).unwrap();
+            publisher.flushed().await;
+            // we talk to the resolver over ipv4, so ipv4 comes first
+            let resolver = ResolverRead::new(client_cfg.clone(), DesiredAuth::Anonymous);
+            let (publishers, _) = resolver.resolve(["/app/dual".into()]).await.unwrap();
+            let pb = publishers.values().next().unwrap();
+            assert_eq!(pb.connect_addrs(), &[publisher.addr(), alt[0]][..]);
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/dual".into());
+            dv.wait_subscribed().await.unwrap();
+            assert_eq!(dv.last(), Event::Update(Value::U64(0)));
+            drop(server);
+        });
+    }
 
     #[test]
-    fn bindcfg() {
+    fn failover() {
         let _ = env_logger::try_init();
-        let addr: IpAddr = "192.168.0.0".parse().unwrap();
-        let netmask: IpAddr = "255.255.0.0".parse().unwrap();
-        assert_eq!(BindCfg::Match { addr, netmask }, "192.168.0.0/16".parse().unwrap());
-        let addr: IpAddr = "ffff:1c00:2700:3c00::".parse().unwrap();
-        let netmask: IpAddr = "ffff:ffff:ffff:ffff::".parse().unwrap();
-        let bc: BindCfg = "ffff:1c00:2700:3c00::/64".parse().unwrap();
-        assert_eq!(BindCfg::Match { addr, netmask }, bc);
-        let addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
-        assert_eq!(BindCfg::Exact(addr), "127.0.0.1:1234".parse().unwrap());
-        let addr: SocketAddr = "[ffff:1c00:2700:3c00::]:1234".parse().unwrap();
-        assert_eq!(BindCfg::Exact(addr), "[ffff:1c00:2700:3c00::]:1234".parse().unwrap());
-        assert!("192.168.0.1".parse::<BindCfg>().is_err());
-        assert!("192.168.0.1:12345/16".parse::<BindCfg>().is_err());
-        assert!("192.168.0.1/8/foo".parse::<BindCfg>().is_err());
-        assert!("ffff:1c00:2700:3c00::".parse::<BindCfg>().is_err());
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let Ctx { server, cfg: client_cfg, publisher, .. } = Ctx::new().await;
+            // a publisher that is registered but can't be reached
+            let dead: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, dead)
+                .unwrap();
+            w.publish([Path::from("/app/failover")]).await.unwrap();
+            let _vp = publisher.publish("/app/failover".into(), Value::U64(42)).unwrap();
+            publisher.flushed().await;
+            // whichever publisher is chosen first, the subscription
+            // should end up at the live one
+            for _ in 0..10 {
+                let subscriber =
+                    Subscriber::new(client_cfg.clone(), DesiredAuth::Anonymous).unwrap();
+                let timeout = Some(Duration::from_secs(10));
+                let v = subscriber
+                    .subscribe_nondurable_one("/app/failover".into(), timeout)
+                    .await
+                    .unwrap();
+                assert_eq!(v.last(), Event::Update(Value::U64(42)));
+            }
+            drop(server);
+        });
     }
 
-    async fn run_publisher(
-        cfg: ClientConfig,
-        default_destroyed: Arc<Mutex<bool>>,
-        tx: oneshot::Sender<()>,
-        auth: DesiredAuth,
-    ) {
-        let check_user = match &auth {
-            DesiredAuth::Tls { .. } => true,
-            _ => false,
-        };
-        let publisher =
-            Publisher::new(cfg, auth, "127.0.0.1/32".parse().unwrap(), 768, 3)
+    #[test]
+    fn durable_failover() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let (server, client_cfg) = start_resolver().await;
+            // a publisher that is registered but can't be reached
+            let dead: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, dead)
+                .unwrap();
+            w.publish([Path::from("/app/failover")]).await.unwrap();
+            let mut publishers = vec![];
+            for i in 1..=2 {
+                let publisher = Publisher::new(
+                    client_cfg.clone(),
+                    DesiredAuth::Anonymous,
+                    "127.0.0.1/32".parse().unwrap(),
+                    768,
+                    3,
+                )
                 .await
                 .unwrap();
-        let vp = publisher.publish("/app/v0".into(), Value::U64(0)).unwrap();
-        publisher.alias(vp.id(), "/app/v1".into()).unwrap();
-        let mut dfp: Option<Val> = None;
-        let mut _adv: Option<Val> = None;
-        let mut df = publisher.publish_default("/app/q".into()).unwrap();
-        df.advertise("/app/q/adv".into()).unwrap();
-        publisher.flushed().await;
-        tx.send(()).unwrap();
-        let (tx, mut rx) = mpsc::channel(10);
-        let (tx_ev, mut rx_ev) = mpsc::unbounded();
-        publisher.events(tx_ev);
-        publisher.writes(vp.id(), tx);
-        loop {
-            select_biased! {
-                e = rx_ev.select_next_some() => match e {
-                    PEvent::Subscribe(_, _) | PEvent::Unsubscribe(_, _) => (),
-                    PEvent::Destroyed(id) => {
-                        assert!(id == dfp.unwrap().id());
-                        dfp = None;
-                        *default_destroyed.lock() = true;
-                    }
-                },
-                (p, reply) = df.select_next_some() => {
-                    assert!(p.starts_with("/app/q"));
-                    if &*p == "/app/q/foo" {
-                        let f = PublishFlags::DESTROY_ON_IDLE;
-                        let p =
-                            publisher.publish_with_flags(f, p, Value::True).unwrap();
-                        dfp = Some(p);
-                        let _ = reply.send(());
-                    } else if &*p == "/app/q/adv" {
-                        _adv = Some(publisher.publish(p, Value::False).unwrap());
-                        let _ = reply.send(());
-                    } else {
-                        panic!("unexpected default subscription {}", p);
-                    }
-                },
-                mut batch = rx.select_next_some() => {
-                    let mut ub = publisher.start_batch();
-                    for req in batch.drain(..) {
-                        if check_user {
-                            assert!(publisher.user(&req.client).is_some())
+                let vp = publisher.publish("/app/failover".into(), Value::U64(i)).unwrap();
+                publisher.flushed().await;
+                publishers.push(Some((publisher, vp)));
+            }
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/failover".into());
+            dv.wait_subscribed().await.unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            dv.updates(UpdatesFlags::empty(), tx);
+            // stop the publisher we got, the resubscription has to
+            // skip the dead one and find the other
+            let (chosen, other) = match dv.last() {
+                Event::Update(Value::U64(1)) => (0, Value::U64(2)),
+                Event::Update(Value::U64(2)) => (1, Value::U64(1)),
+                e => panic!("unexpected event {:?}", e),
+            };
+            let (publisher, vp) = publishers[chosen].take().unwrap();
+            drop(vp);
+            publisher.shutdown().await;
+            let deadline = time::Instant::now() + Duration::from_secs(30);
+            loop {
+                match time::timeout_at(deadline, rx.next()).await {
+                    Err(_) | Ok(None) => panic!("timed out waiting to fail over"),
+                    Ok(Some(mut up)) => {
+                        if up.drain(..).any(|(_, ev)| ev == Event::Update(other.clone())) {
+                            break;
                         }
-                        vp.update(&mut ub, req.value);
                     }
-                    ub.commit(None).await;
                 }
             }
-        }
+            drop(publishers);
+            drop(server);
+        });
     }
 
-    async fn run_subscriber(
-        cfg: ClientConfig,
-        default_destroyed: Arc<Mutex<bool>>,
-        auth: DesiredAuth,
-    ) {
-        let subscriber = Subscriber::new(cfg, auth).unwrap();
-        let vs =
-            subscriber.subscribe_nondurable_one("/app/v0".into(), None).await.unwrap();
-        // we should be able to subscribe to an alias and it should
-        // behave as if we just cloned the existing
-        // subscription. E.G. no extra values in the channel.
-        let va =
-            subscriber.subscribe_nondurable_one("/app/v1".into(), None).await.unwrap();
-        let q =
-            subscriber.subscribe_nondurable_one("/app/q/foo".into(), None).await.unwrap();
-        assert_eq!(q.last(), Event::Update(Value::True));
-        let (_, res) =
-            subscriber.resolver().resolve(iter::once("/app/q/adv".into())).await.unwrap();
-        assert_eq!(res.len(), 1);
-        let a =
-            subscriber.subscribe_nondurable_one("/app/q/adv".into(), None).await.unwrap();
-        assert_eq!(a.last(), Event::Update(Value::False));
-        drop(q);
-        drop(a);
-        let mut c: u64 = 0;
-        let (tx, mut rx) = mpsc::channel(10);
-        let flags = UpdatesFlags::BEGIN_WITH_LAST | UpdatesFlags::NO_SPURIOUS;
-        vs.updates(flags, tx.clone());
-        va.updates(flags, tx);
-        loop {
-            match rx.next().await {
-                None => panic!("publisher died"),
-                Some(mut batch) => {
-                    for (_, v) in batch.drain(..) {
-                        match v {
-                            Event::Update(Value::U64(v)) => {
-                                assert_eq!(c, v);
-                                c += 1;
-                                vs.write(Value::U64(c));
-                            }
-                            v => panic!("unexpected value from publisher {:?}", v),
+    #[test]
+    fn subscribe_many() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let paths = (0..1000u64)
+                .map(|i| Path::from(format!("/app/v{}", i)))
+                .collect::<Vec<_>>();
+            let _vals = paths
+                .iter()
+                .enumerate()
+                .map(|(i, p)| publisher.publish(p.clone(), i as u64).unwrap())
+                .collect::<Vec<_>>();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dvs = subscriber.subscribe_many(paths.iter().cloned());
+            assert_eq!(dvs.len(), paths.len());
+            for (i, dv) in dvs.iter().enumerate() {
+                dv.wait_subscribed().await.unwrap();
+                assert_eq!(dv.last(), Event::Update(Value::U64(i as u64)));
+            }
+            // existing subscriptions are shared
+            let again = subscriber.subscribe_many(paths[..10].iter().cloned());
+            for (dv0, dv1) in dvs.iter().zip(again.iter()) {
+                assert_eq!(dv0.id(), dv1.id());
+            }
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn subscription_priority() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let vhi = publisher.publish("/app/hi".into(), 0u64).unwrap();
+            let vlo = publisher.publish("/app/lo".into(), 0u64).unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let shi = subscriber.subscribe("/app/hi".into());
+            let slo = subscriber.subscribe("/app/lo".into());
+            // set while the subscriptions may still be pending
+            subscriber.set_priority(&Path::from("/app/hi"), Priority::High);
+            subscriber.set_priority(&Path::from("/app/lo"), Priority::Low);
+            // a path that isn't subscribed doesn't get a priority
+            subscriber.set_priority(&Path::from("/app/none"), Priority::High);
+            shi.wait_subscribed().await.unwrap();
+            slo.wait_subscribed().await.unwrap();
+            // nothing is read from the channel until every batch is
+            // sent, so the subscriber falls behind
+            let (tx, mut rx) = mpsc::channel(1);
+            shi.updates(UpdatesFlags::empty(), tx.clone());
+            slo.updates(UpdatesFlags::empty(), tx);
+            for i in 1..=100u64 {
+                let mut batch = publisher.start_batch();
+                vlo.update(&mut batch, 2 * i - 1);
+                vlo.update(&mut batch, 2 * i);
+                vhi.update(&mut batch, i);
+                batch.commit(None).await;
+                // the publisher conflates batches that queue up, wait
+                // for it to take each one so they arrive separately
+                while publisher.congestion().iter().any(|(_, c)| c.queue_depth > 0) {
+                    time::sleep(Duration::from_millis(1)).await
+                }
+            }
+            let mut recvd = vec![];
+            let done = |recvd: &Vec<(bool, Value)>| {
+                let last_lo = recvd.iter().rev().find(|(hi, _)| !*hi);
+                last_lo == Some(&(false, Value::U64(200)))
+                    && recvd.iter().filter(|(hi, _)| *hi).count() == 100
+            };
+            while !done(&recvd) {
+                for (id, ev) in rx.next().await.unwrap().drain(..) {
+                    match ev {
+                        Event::Update(v) => recvd.push((id == shi.id(), v)),
+                        e => panic!("unexpected event {:?}", e),
+                    }
+                }
+            }
+            let pos = |hi: bool, v: u64| recvd.iter().position(|u| u == &(hi, Value::U64(v)));
+            // high priority subscriptions never lose updates
+            let hi = recvd.iter().filter(|(hi, _)| *hi).map(|(_, v)| v.clone());
+            assert_eq!(hi.collect::<Vec<_>>(), (1..=100u64).map(Value::U64).collect::<Vec<_>>());
+            // while backed up high priority updates jump ahead of the
+            // low priority updates in their batch
+            assert!((1..=100u64).any(|i| match (pos(true, i), pos(false, 2 * i)) {
+                (Some(hi), Some(lo)) => hi < lo,
+                (Some(_), None) | (None, _) => false,
+            }));
+            // and ahead of low priority updates held back from earlier
+            // batches
+            assert!(recvd.iter().enumerate().any(|(p, u)| match u {
+                (true, Value::U64(i)) => recvd[p..].iter().any(|(hi, v)| {
+                    !*hi && matches!(v, Value::U64(lo) if *lo < 2 * i - 1)
+                }),
+                _ => false,
+            }));
+            // and intermediate low priority updates are dropped
+            assert!(recvd.iter().filter(|(hi, _)| !*hi).count() < 200);
+            assert_eq!(slo.last(), Event::Update(Value::U64(200)));
+            // everything was delivered, so nothing should be queued
+            let congestion = publisher.congestion();
+            assert_eq!(congestion.len(), 1);
+            assert_eq!(congestion[0].1.queue_depth, 0);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn slow_subscriber() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let vp = publisher.publish("/app/v".into(), 0u64).unwrap();
+            publisher.flushed().await;
+            let (tx_ev, mut rx_ev) = mpsc::unbounded();
+            publisher.events(tx_ev);
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let dv = subscriber.subscribe("/app/v".into());
+            dv.wait_subscribed().await.unwrap();
+            // nobody reads this channel, so once it's full the
+            // subscriber stops reading from the socket
+            let (tx, rx) = mpsc::channel(1);
+            dv.updates(UpdatesFlags::empty(), tx);
+            let cl = publisher.congestion()[0].0;
+            let big = "x".repeat(1 << 20);
+            let (mut depth, mut buffered) = (0, 0);
+            let (mut slow, mut dropped) = (false, false);
+            let deadline = time::Instant::now() + Duration::from_secs(30);
+            for i in 0u64.. {
+                assert!(time::Instant::now() < deadline);
+                let mut batch = publisher.start_batch();
+                vp.update(&mut batch, format!("{}{}", i, big));
+                batch.commit(Some(Duration::from_secs(1))).await;
+                for (id, c) in publisher.congestion() {
+                    if id == cl {
+                        depth = depth.max(c.queue_depth);
+                        buffered = buffered.max(c.bytes_buffered);
+                    }
+                }
+                while let Ok(Some(e)) = rx_ev.try_next() {
+                    match e {
+                        PEvent::SlowSubscriber(id) => {
+                            assert_eq!(id, cl);
+                            assert!(!slow);
+                            slow = true
                         }
+                        PEvent::SubscriberDropped(id) => {
+                            assert_eq!(id, cl);
+                            dropped = true
+                        }
+                        PEvent::Subscribe(_, _)
+                        | PEvent::Unsubscribe(_, _)
+                        | PEvent::Destroyed(_) => (),
                     }
                 }
+                if dropped {
+                    break;
+                }
             }
-            if c == 100 {
-                break;
+            assert!(slow);
+            assert!(depth > 0);
+            assert!(buffered > 0);
+            assert!(publisher.congestion().iter().all(|(id, _)| *id != cl));
+            drop(rx);
+            drop(server);
+        });
+    }
+
+    #[test]
+    fn subscriber_multiple_roots() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut prod_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let mut lab_cfg = prod_cfg.clone();
+            let prod = Server::new(server_cfg.clone(), false, 0).await.expect("prod");
+            let lab = Server::new(server_cfg, false, 0).await.expect("lab");
+            prod_cfg.addrs[0].0 = *prod.local_addr();
+            lab_cfg.addrs[0].0 = *lab.local_addr();
+            let mut publishers = vec![];
+            for (cfg, path) in [(&prod_cfg, "/prod/v"), (&lab_cfg, "/lab/v")] {
+                let publisher = Publisher::new(
+                    cfg.clone(),
+                    DesiredAuth::Anonymous,
+                    "127.0.0.1/32".parse().unwrap(),
+                    768,
+                    3,
+                )
+                .await
+                .unwrap();
+                let val = publisher.publish(path.into(), path).unwrap();
+                publisher.flushed().await;
+                publishers.push((publisher, val));
             }
-        }
-        if !*default_destroyed.lock() {
-            panic!("default publisher value was not destroyed on idle")
-        }
+            let subscriber = Subscriber::new(prod_cfg, DesiredAuth::Anonymous).unwrap();
+            assert!(subscriber.add_root("lab".into(), lab_cfg.clone()).is_err());
+            subscriber.add_root("/lab".into(), lab_cfg).unwrap();
+            let prod_v = subscriber.subscribe("/prod/v".into());
+            let lab_v = subscriber.subscribe("/lab/v".into());
+            prod_v.wait_subscribed().await.unwrap();
+            lab_v.wait_subscribed().await.unwrap();
+            assert_eq!(prod_v.last(), Event::Update(Value::from("/prod/v")));
+            assert_eq!(lab_v.last(), Event::Update(Value::from("/lab/v")));
+            drop(publishers);
+            drop(prod);
+            drop(lab);
+        });
     }
 
     #[test]
-    fn publish_subscribe() {
+    fn publish_on_demand() {
         let _ = env_logger::try_init();
         let rt = Runtime::new().unwrap();
         rt.block_on(async {
@@ -540,16 +3645,52 @@ mod publisher {
                 .expect("load simple client config");
             let server = Server::new(server_cfg, false, 0).await.expect("start server");
             client_cfg.addrs[0].0 = *server.local_addr();
-            let default_destroyed = Arc::new(Mutex::new(false));
-            let (tx, ready) = oneshot::channel();
-            task::spawn(run_publisher(
+            let publisher = Publisher::new(
                 client_cfg.clone(),
-                default_destroyed.clone(),
-                tx,
                 DesiredAuth::Anonymous,
-            ));
-            time::timeout(Duration::from_secs(1), ready).await.unwrap().unwrap();
-            run_subscriber(client_cfg, default_destroyed, DesiredAuth::Anonymous).await;
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let od = publisher
+                .publish_on_demand("/app/orders".into(), |path| {
+                    match Path::basename(path) {
+                        Some("missing") => None,
+                        Some(name) => Some(Value::from(String::from(name))),
+                        None => None,
+                    }
+                })
+                .unwrap();
+            publisher.flushed().await;
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let path = Path::from("/app/orders/42");
+            let s =
+                subscriber.subscribe_nondurable_one(path.clone(), None).await.unwrap();
+            assert_eq!(s.last(), Event::Update(Value::from("42")));
+            assert_eq!(od.published(), vec![path.clone()]);
+            let missing = subscriber
+                .subscribe_nondurable_one("/app/orders/missing".into(), None)
+                .await;
+            assert!(missing.is_err());
+            let (tx, mut rx) = mpsc::channel(10);
+            s.updates(UpdatesFlags::empty(), tx);
+            let mut batch = publisher.start_batch();
+            assert!(od.update(&mut batch, &path, "filled"));
+            batch.commit(None).await;
+            let mut batch = rx.next().await.unwrap();
+            let (_, e) = batch.drain(..).next().unwrap();
+            assert_eq!(e, Event::Update(Value::from("filled")));
+            drop(s);
+            let mut tries = 0;
+            while od.id(&path).is_some() {
+                assert!(tries < 100, "idle value was not collected");
+                time::sleep(Duration::from_millis(10)).await;
+                tries += 1;
+            }
+            let mut batch = publisher.start_batch();
+            assert!(!od.update(&mut batch, &path, "gone"));
             drop(server);
         });
     }
@@ -586,4 +3727,130 @@ mod publisher {
             drop(server)
         })
     }
+
+    #[test]
+    fn tls_two_domains() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/tls/resolver/resolver.json")
+                .expect("load tls server config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            let load = |path: &str| {
+                let mut cfg = ClientConfig::load(path).expect("load tls client config");
+                cfg.addrs[0].0 = *server.local_addr();
+                cfg
+            };
+            let publisher = |cfg: ClientConfig| async move {
+                let auth = DesiredAuth::Tls { identity: None };
+                let bind = "127.0.0.1/32".parse().unwrap();
+                Publisher::new(cfg, auth, bind, 768, 3).await.unwrap()
+            };
+            let p0 = publisher(load("../cfg/tls/publisher/client.json")).await;
+            let _v0 = p0.publish("/app/com".into(), Value::U64(0)).unwrap();
+            let p1 = publisher(load("../cfg/tls/org/publisher/client.json")).await;
+            let _v1 = p1.publish("/app/org".into(), Value::U64(1)).unwrap();
+            p0.flushed().await;
+            p1.flushed().await;
+            // the desired identity is only used with the resolver, each
+            // publisher is reached with the identity matching its name
+            let com = DesiredAuth::Tls { identity: Some("example.com".into()) };
+            let subscriber =
+                Subscriber::new(load("../cfg/tls/org/client/client.json"), com.clone())
+                    .unwrap();
+            let to = Some(Duration::from_secs(10));
+            let v = subscriber.subscribe_nondurable_one("/app/com".into(), to).await;
+            assert_eq!(v.unwrap().last(), Event::Update(Value::U64(0)));
+            let v = subscriber.subscribe_nondurable_one("/app/org".into(), to).await;
+            assert_eq!(v.unwrap().last(), Event::Update(Value::U64(1)));
+            // an override's identity is used as is, and example.com
+            // doesn't trust the example.org publisher
+            let subscriber = SubscriberBuilder::new()
+                .config(load("../cfg/tls/org/client/client.json"))
+                .desired_auth(DesiredAuth::Tls { identity: None })
+                .auth_override(AuthTarget::Publisher(p1.addr()), com)
+                .build()
+                .unwrap();
+            let v = subscriber.subscribe_nondurable_one("/app/org".into(), to).await;
+            assert!(v.is_err());
+            drop(server)
+        })
+    }
+}
+
+mod channel {
+    use crate::{channel::Channel, pack::ZERO_COPY_MIN};
+    use bytes::Bytes;
+    use cross_krb5::ClientCtx;
+    use futures::future;
+    use std::time::Duration;
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        runtime::Runtime,
+        time,
+    };
+
+    // send one compressed frame claiming to decompress to `len` bytes
+    // and return what the receiving channel makes of it
+    async fn receive_compressed(decompress: bool, len: u32) -> anyhow::Result<Bytes> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (s0, s1) = future::join(TcpStream::connect(addr), listener.accept()).await;
+        let mut tx = s0.unwrap();
+        let mut rx = Channel::new::<ClientCtx, _>(None, s1.unwrap().0);
+        rx.set_decompression(decompress);
+        let data = zstd::bulk::compress(&[0u8; 1024][..], 1).unwrap();
+        let mut frame = vec![];
+        frame.extend_from_slice(&((4 + data.len() as u32) | 0x40000000).to_be_bytes());
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(&data);
+        tx.write_all(&frame).await.unwrap();
+        time::timeout(Duration::from_secs(10), rx.receive::<Bytes>())
+            .await
+            .expect("receive timed out")
+    }
+
+    #[test]
+    fn compressed_frames() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            // only accepted after zstd was negotiated
+            assert!(receive_compressed(false, 1024).await.is_err());
+            // the claimed length must match, and it can't be used to
+            // make the receiver allocate a huge buffer
+            assert!(receive_compressed(true, 1023).await.is_err());
+            assert!(receive_compressed(true, 0x3FFFFFFF).await.is_err());
+        })
+    }
+
+    #[test]
+    fn large_batch() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (s0, s1) =
+                future::join(TcpStream::connect(addr), listener.accept()).await;
+            let mut tx = Channel::new::<ClientCtx, _>(None, s0.unwrap());
+            let mut rx = Channel::new::<ClientCtx, _>(None, s1.unwrap().0);
+            let small = Bytes::from_static(b"small");
+            let big = (0..ZERO_COPY_MIN * 4).map(|i| i as u8).collect::<Vec<_>>();
+            let big = Bytes::from(big);
+            // a batch well over ZERO_COPY_MIN, read directly into its
+            // own buffer, followed by a small one
+            let mut sent = vec![small.clone(), big.clone(), small.clone(), big];
+            for m in &sent {
+                tx.queue_send(m).unwrap();
+            }
+            tx.flush().await.unwrap();
+            tx.send_one(&small).await.unwrap();
+            sent.push(small);
+            let mut received: Vec<Bytes> = vec![];
+            while received.len() < sent.len() {
+                rx.receive_batch(&mut received).await.unwrap();
+            }
+            assert_eq!(received, sent);
+        })
+    }
 }